@@ -0,0 +1,107 @@
+//! A-B looping over a section of the current track, for practising along.
+//! While a region is set a monitor thread seeks back to its start whenever
+//! playback passes its end. The region belongs to the track: starting another
+//! one or stopping clears it.
+
+use serde::Serialize;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Duration;
+use log::{debug, error};
+use tauri::{AppHandle, Manager, State};
+use crate::commands::seek_to;
+use crate::output_format::report_failure;
+use crate::relocate::restart_at;
+use crate::PlayerHandle;
+
+// Checked often so the loop point is accurate to a few tens of milliseconds
+const LOOP_POLL: Duration = Duration::from_millis(20);
+
+// Bumped whenever the region is set or cleared, so an older monitor stops
+static LOOP_ID: AtomicU64 = AtomicU64::new(0);
+
+#[derive(Debug, Serialize, Clone, Copy, PartialEq)]
+pub struct LoopRegion {
+    pub start: f32,
+    pub end: f32,
+}
+
+fn validate(start: f32, end: f32, duration: Option<Duration>) -> Result<LoopRegion, String> {
+    if !start.is_finite() || !end.is_finite() || start < 0.0 {
+        return Err("Loop points must be positions within the track".to_string());
+    }
+    if end <= start {
+        return Err(format!("Loop end ({:.2}s) must come after its start ({:.2}s)", end, start));
+    }
+    if let Some(duration) = duration {
+        if end > duration.as_secs_f32() {
+            return Err(format!("Loop end ({:.2}s) is past the end of the track ({:.2}s)", end, duration.as_secs_f32()));
+        }
+    }
+    Ok(LoopRegion { start, end })
+}
+
+/// Loops the current track between `start_secs` and `end_secs`.
+#[tauri::command]
+pub fn set_loop_region(app: AppHandle, start_secs: f32, end_secs: f32) -> Result<LoopRegion, String> {
+    let (region, path) = app.state::<PlayerHandle>().with(move |player| {
+        let path = player.current_path.clone().ok_or("Nothing is playing")?;
+        let region = validate(start_secs, end_secs, player.track_duration())?;
+        // A track already queued in the sink would take over when this one runs out
+        if let Some(preloaded) = player.preloaded.take() {
+            preloaded.withdraw();
+        }
+        player.loop_region = Some(region);
+        Ok::<_, String>((region, path))
+    })?;
+    let id = LOOP_ID.fetch_add(1, Ordering::SeqCst) + 1;
+    debug!("Looping {} between {}s and {}s", path, region.start, region.end);
+    std::thread::spawn(move || monitor(&app, id, path));
+    Ok(region)
+}
+
+#[tauri::command]
+pub fn clear_loop_region(player: State<'_, PlayerHandle>) {
+    LOOP_ID.fetch_add(1, Ordering::SeqCst);
+    player.send(|player| player.loop_region = None);
+}
+
+fn monitor(app: &AppHandle, id: u64, path: String) {
+    while LOOP_ID.load(Ordering::SeqCst) == id {
+        std::thread::sleep(LOOP_POLL);
+        let looped = path.clone();
+        let check = app.state::<PlayerHandle>().with(move |player| {
+            let region = player.loop_region.filter(|_| player.current_path.as_deref() == Some(looped.as_str()))?;
+            let ran_out = player.sink.as_ref().is_none_or(|sink| sink.empty());
+            let passed = player.track_position().as_secs_f32() >= region.end;
+            Some((player.is_playing && (passed || ran_out)).then_some((region, ran_out, player.track_offset())))
+        });
+        let Some(check) = check else { return };
+        let Some((region, ran_out, offset)) = check else { continue };
+        // A sink that already played to the end has nothing left to seek in
+        let result = if ran_out { restart_at(app, &path, offset.as_secs_f32() + region.start, true) } else { seek_to(app.state(), region.start) };
+        if let Err(e) = result {
+            error!("Failed to loop back to {}s in {}: {}", region.start, path, e);
+            report_failure(app, &e, Some(&path));
+            clear_loop_region(app.state());
+            return;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rejects_inverted_and_out_of_range_regions() {
+        let duration = Some(Duration::from_secs(200));
+        assert_eq!(validate(10.0, 20.0, duration), Ok(LoopRegion { start: 10.0, end: 20.0 }));
+        assert!(validate(20.0, 10.0, duration).unwrap_err().contains("must come after"));
+        assert!(validate(10.0, 10.0, duration).is_err());
+        assert!(validate(-1.0, 10.0, duration).is_err());
+        assert!(validate(10.0, 250.0, duration).unwrap_err().contains("past the end"));
+        assert!(validate(10.0, f32::NAN, duration).is_err());
+        // Without a known duration only the order can be checked
+        assert!(validate(10.0, 250.0, None).is_ok());
+    }
+}
@@ -0,0 +1,199 @@
+//! Local record of what background tasks did, one JSON line per finished task,
+//! for the session summary and bug reports. Nothing here leaves the machine.
+
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fs::{self, OpenOptions};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use once_cell::sync::Lazy;
+use parking_lot::Mutex;
+use log::error;
+use crate::config::get_data_dir;
+use crate::tasks::{TaskKind, TaskState};
+
+// The current log is rotated to `activity.1.jsonl` past this size, and the
+// previous rotation dropped, so the journal stays under twice this on disk
+const MAX_LOG_BYTES: u64 = 1024 * 1024;
+
+static LOG_LOCK: Lazy<Mutex<()>> = Lazy::new(|| Mutex::new(()));
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct ActivityRecord {
+    pub task_id: u64,
+    pub kind: TaskKind,
+    pub description: String,
+    pub outcome: TaskState,
+    pub started_at: u64,
+    pub finished_at: u64,
+    pub duration_ms: u64,
+    pub items_done: u64,
+    pub items_total: u64,
+    pub bytes_done: u64,
+    pub bytes_total: u64,
+    // Task-specific totals, such as "added" for a scan or "verified" for a transfer
+    #[serde(default)]
+    pub counts: BTreeMap<String, u64>,
+    pub error: Option<String>,
+    // How many item failures fell under each `error_code`
+    #[serde(default)]
+    pub error_codes: BTreeMap<String, u64>,
+}
+
+/// Buckets an error message into a short code the frontend can group by.
+pub fn error_code(message: &str) -> &'static str {
+    let message = message.to_lowercase();
+    if message.contains("permission denied") || message.contains("operation not permitted") || message.contains("access is denied") {
+        "permission_denied"
+    } else if message.contains("no space") || message.contains("disk full") {
+        "disk_full"
+    } else if message.contains("not found") || message.contains("does not exist") || message.contains("no such file") {
+        "not_found"
+    } else if message.contains("cancel") {
+        "cancelled"
+    } else if message.contains("verif") || message.contains("checksum") {
+        "verification_failed"
+    } else {
+        "other"
+    }
+}
+
+fn log_path() -> Option<PathBuf> {
+    get_data_dir().map(|dir| dir.join("activity.jsonl"))
+}
+
+fn rotated_path(path: &Path) -> PathBuf {
+    path.with_file_name("activity.1.jsonl")
+}
+
+fn append_to(path: &Path, record: &ActivityRecord) -> Result<(), String> {
+    let line = serde_json::to_string(record).map_err(|e| e.to_string())?;
+    let _guard = LOG_LOCK.lock();
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent).map_err(|e| e.to_string())?;
+    }
+    if fs::metadata(path).is_ok_and(|metadata| metadata.len() >= MAX_LOG_BYTES) {
+        fs::rename(path, rotated_path(path)).map_err(|e| e.to_string())?;
+    }
+    let mut file = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)
+        .map_err(|e| e.to_string())?;
+    writeln!(file, "{}", line).map_err(|e| e.to_string())
+}
+
+fn read_from(path: &Path, since: Option<u64>, kinds: &[TaskKind]) -> Vec<ActivityRecord> {
+    let _guard = LOG_LOCK.lock();
+    [rotated_path(path), path.to_path_buf()].iter()
+        .filter_map(|file| fs::read_to_string(file).ok())
+        .flat_map(|contents| {
+            // A line cut short by a crash is skipped rather than failing the whole log
+            contents.lines()
+                .filter_map(|line| serde_json::from_str::<ActivityRecord>(line).ok())
+                .collect::<Vec<_>>()
+        })
+        .filter(|record| since.is_none_or(|since| record.finished_at >= since))
+        .filter(|record| kinds.is_empty() || kinds.contains(&record.kind))
+        .collect()
+}
+
+fn clear_at(path: &Path) -> Result<(), String> {
+    let _guard = LOG_LOCK.lock();
+    for file in [rotated_path(path), path.to_path_buf()] {
+        match fs::remove_file(&file) {
+            Err(e) if e.kind() != std::io::ErrorKind::NotFound => return Err(e.to_string()),
+            _ => {}
+        }
+    }
+    Ok(())
+}
+
+/// Appends a finished task to the journal. Failures are only logged, since a
+/// task's outcome shouldn't depend on whether its record could be written.
+pub fn record(record: &ActivityRecord) {
+    let Some(path) = log_path() else { return };
+    if let Err(e) = append_to(&path, record) {
+        error!("Failed to write activity log: {}", e);
+    }
+}
+
+/// Finished tasks, oldest first, optionally only those that ended at or after
+/// `since` (unix seconds) and of the given kinds.
+#[tauri::command]
+pub fn get_activity_log(since: Option<u64>, kinds: Option<Vec<TaskKind>>) -> Vec<ActivityRecord> {
+    let Some(path) = log_path() else { return Vec::new() };
+    read_from(&path, since, &kinds.unwrap_or_default())
+}
+
+#[tauri::command]
+pub fn clear_activity_log() -> Result<(), String> {
+    let Some(path) = log_path() else { return Ok(()) };
+    clear_at(&path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::temp_dir;
+
+    fn sample(task_id: u64, kind: TaskKind, finished_at: u64) -> ActivityRecord {
+        ActivityRecord {
+            task_id,
+            kind,
+            description: format!("task {}", task_id),
+            outcome: TaskState::Completed,
+            started_at: finished_at.saturating_sub(5),
+            finished_at,
+            duration_ms: 5000,
+            items_done: 3,
+            items_total: 3,
+            bytes_done: 0,
+            bytes_total: 0,
+            counts: BTreeMap::from([("added".to_string(), 3)]),
+            error: None,
+            error_codes: BTreeMap::new(),
+        }
+    }
+
+    #[test]
+    fn filters_by_time_and_kind() {
+        let dir = temp_dir();
+        let path = dir.path().join("activity.jsonl");
+        append_to(&path, &sample(1, TaskKind::LibraryScan, 100)).unwrap();
+        append_to(&path, &sample(2, TaskKind::Transfer, 200)).unwrap();
+        append_to(&path, &sample(3, TaskKind::LibraryScan, 300)).unwrap();
+
+        let ids = |records: Vec<ActivityRecord>| records.iter().map(|r| r.task_id).collect::<Vec<_>>();
+        assert_eq!(ids(read_from(&path, None, &[])), [1, 2, 3]);
+        assert_eq!(ids(read_from(&path, Some(200), &[])), [2, 3]);
+        assert_eq!(ids(read_from(&path, None, &[TaskKind::LibraryScan])), [1, 3]);
+        assert_eq!(read_from(&path, None, &[])[0], sample(1, TaskKind::LibraryScan, 100));
+    }
+
+    #[test]
+    fn rotates_past_the_size_cap_and_keeps_both_generations_readable() {
+        let dir = temp_dir();
+        let path = dir.path().join("activity.jsonl");
+        fs::write(&path, format!("{}\n", "x".repeat(MAX_LOG_BYTES as usize))).unwrap();
+        append_to(&path, &sample(1, TaskKind::Batch, 100)).unwrap();
+
+        assert!(rotated_path(&path).exists());
+        assert_eq!(fs::read_to_string(&path).unwrap().lines().count(), 1);
+        // The oversized junk line in the rotated file is skipped
+        assert_eq!(read_from(&path, None, &[]).len(), 1);
+
+        clear_at(&path).unwrap();
+        assert!(!path.exists() && !rotated_path(&path).exists());
+        assert!(read_from(&path, None, &[]).is_empty());
+    }
+
+    #[test]
+    fn classifies_common_errors() {
+        assert_eq!(error_code("Permission denied (os error 13)"), "permission_denied");
+        assert_eq!(error_code("No space left on device"), "disk_full");
+        assert_eq!(error_code("Source path does not exist"), "not_found");
+        assert_eq!(error_code("Checksum mismatch for a.flac"), "verification_failed");
+        assert_eq!(error_code("Unexpected end of stream"), "other");
+    }
+}
@@ -0,0 +1,247 @@
+use serde::Serialize;
+use std::fs;
+use std::path::{Path, PathBuf};
+use tauri::State;
+use log::info;
+use crate::import::organize_destination;
+use crate::journal::Transaction;
+use crate::library::{index_file, library_root_of, track_info, LibraryTrack};
+use crate::metadata::read_audio_metadata;
+use crate::relocate::FileOpError;
+use crate::walker::{walk_audio_files, Exclusions};
+use crate::PlayerHandle;
+
+// Replaced albums set aside for undo live here, next to the album they came from
+const REPLACED_DIR: &str = ".replaced";
+const DURATION_TOLERANCE_SECS: f64 = 2.0;
+
+#[derive(Debug, Serialize)]
+pub struct TrackMatch {
+    pub old_path: String,
+    pub new_path: String,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ReplaceAlbumResult {
+    pub journal_id: u64,
+    pub destination: String,
+    // Where the old files now are: a visible sibling folder, or the hidden undo area when deleted
+    pub old_files_location: String,
+    pub matched: Vec<TrackMatch>,
+    pub unmatched_old: Vec<String>,
+    pub unmatched_new: Vec<String>,
+}
+
+type MatchRule<'a> = &'a dyn Fn(&AlbumFile, &AlbumFile) -> bool;
+
+struct AlbumFile {
+    path: PathBuf,
+    track: Option<LibraryTrack>,
+}
+
+impl AlbumFile {
+    fn track_number(&self) -> Option<u32> {
+        self.track.as_ref().and_then(|t| t.track_number)
+    }
+
+    /// Lowercased title with punctuation stripped, so "Song (Live)" matches "song live".
+    fn title_key(&self) -> Option<String> {
+        let title = self.track.as_ref().and_then(|t| t.title.as_ref())?;
+        let key: String = title.chars().filter(|c| c.is_alphanumeric()).flat_map(char::to_lowercase).collect();
+        (!key.is_empty()).then_some(key)
+    }
+
+    fn duration(&self) -> Option<f64> {
+        self.track.as_ref().and_then(|t| t.duration)
+    }
+}
+
+fn list_album(folder: &Path) -> Vec<AlbumFile> {
+    walk_audio_files(folder, true, &Exclusions::default())
+        .into_iter()
+        .map(|path| AlbumFile { track: track_info(&path), path })
+        .collect()
+}
+
+fn close_durations(a: &AlbumFile, b: &AlbumFile) -> bool {
+    matches!((a.duration(), b.duration()), (Some(x), Some(y)) if (x - y).abs() <= DURATION_TOLERANCE_SECS)
+}
+
+/// Pairs new tracks with old ones, strictest rule first: track number and
+/// title, then title and duration, then track number and duration.
+fn match_tracks(old: &[AlbumFile], new: &[AlbumFile]) -> Vec<(usize, usize)> {
+    let same_number = |a: &AlbumFile, b: &AlbumFile| a.track_number().is_some() && a.track_number() == b.track_number();
+    let same_title = |a: &AlbumFile, b: &AlbumFile| a.title_key().is_some() && a.title_key() == b.title_key();
+    let rules: [MatchRule; 3] = [
+        &|a, b| same_number(a, b) && same_title(a, b),
+        &|a, b| same_title(a, b) && close_durations(a, b),
+        &|a, b| same_number(a, b) && close_durations(a, b),
+    ];
+
+    let mut old_used = vec![false; old.len()];
+    let mut new_used = vec![false; new.len()];
+    let mut pairs = Vec::new();
+    for rule in rules {
+        for (new_index, new_file) in new.iter().enumerate() {
+            if new_used[new_index] {
+                continue;
+            }
+            let found = old.iter().enumerate().find(|(old_index, old_file)| !old_used[*old_index] && rule(old_file, new_file));
+            if let Some((old_index, _)) = found {
+                old_used[old_index] = true;
+                new_used[new_index] = true;
+                pairs.push((old_index, new_index));
+            }
+        }
+    }
+    pairs
+}
+
+fn unique_sibling(path: &Path, suffix: &str) -> PathBuf {
+    let name = path.file_name().map(|n| n.to_string_lossy().to_string()).unwrap_or_default();
+    (1..)
+        .map(|n| {
+            let candidate = if n == 1 { format!("{} ({})", name, suffix) } else { format!("{} ({} {})", name, suffix, n) };
+            path.with_file_name(candidate)
+        })
+        .find(|candidate| !candidate.exists())
+        .unwrap_or_else(|| path.to_path_buf())
+}
+
+/// Where each new file ends up: under the old album folder at the same relative
+/// path, or wherever `organize_pattern` puts it inside the old album's library root.
+fn planned_destinations(old_folder: &Path, new_folder: &Path, new: &[AlbumFile], organize_pattern: Option<&str>) -> Result<Vec<PathBuf>, String> {
+    let Some(pattern) = organize_pattern else {
+        return Ok(new.iter()
+            .map(|file| old_folder.join(file.path.strip_prefix(new_folder).unwrap_or(&file.path)))
+            .collect());
+    };
+
+    let root = library_root_of(old_folder)
+        .ok_or("The old album is not inside a library root, so there is nowhere to organize into")?;
+    new.iter().map(|file| {
+        let metadata = read_audio_metadata(&file.path, false)
+            .map_err(|e| format!("Cannot organize {}: {}", file.path.display(), e))?;
+        Ok(organize_destination(pattern, &root, &file.path, &metadata))
+    }).collect()
+}
+
+fn playing_under(player: &PlayerHandle, folders: &[&Path]) -> Option<String> {
+    player.with(|player| player.current_path.clone())
+        .filter(|current| folders.iter().any(|folder| Path::new(current).starts_with(folder)))
+}
+
+fn run_replace(
+    tx: &mut Transaction,
+    old_folder: &Path,
+    new_folder: &Path,
+    delete_old: bool,
+    organize_pattern: Option<&str>,
+) -> Result<ReplaceAlbumResult, String> {
+    let old = list_album(old_folder);
+    let new = list_album(new_folder);
+    if new.is_empty() {
+        return Err(format!("No audio files found in {}", new_folder.display()));
+    }
+    let pairs = match_tracks(&old, &new);
+    let destinations = planned_destinations(old_folder, new_folder, &new, organize_pattern)?;
+
+    // Set the old album aside first so the new one can take its place
+    let old_destination = if delete_old {
+        let replaced = old_folder.with_file_name(REPLACED_DIR);
+        fs::create_dir_all(&replaced).map_err(|e| format!("Failed to create {}: {}", replaced.display(), e))?;
+        // Keeps scans and listings out of the undo area
+        let _ = fs::write(replaced.join(".nomedia"), b"");
+        let staging = replaced.join(tx.id().to_string());
+        tx.set_staging(&staging);
+        staging.join(old_folder.file_name().unwrap_or_default())
+    } else {
+        unique_sibling(old_folder, "old")
+    };
+    tx.rename(old_folder, &old_destination)?;
+
+    if organize_pattern.is_some() {
+        for (file, destination) in new.iter().zip(&destinations) {
+            tx.rename(&file.path, destination)?;
+        }
+        // Whatever is left behind (artwork, cue sheets) stays in the new folder
+    } else {
+        tx.rename(new_folder, old_folder)?;
+    }
+
+    let mut matched = Vec::new();
+    for &(old_index, new_index) in &pairs {
+        tx.remap(&old[old_index].path, &destinations[new_index]);
+        matched.push(TrackMatch {
+            old_path: old[old_index].path.to_string_lossy().to_string(),
+            new_path: destinations[new_index].to_string_lossy().to_string(),
+        });
+    }
+
+    let unmatched_old = old.iter().enumerate()
+        .filter(|(index, _)| !pairs.iter().any(|(old_index, _)| old_index == index))
+        .map(|(_, file)| file.path.to_string_lossy().to_string())
+        .collect();
+    let unmatched_new: Vec<String> = destinations.iter().enumerate()
+        .filter(|(index, _)| !pairs.iter().any(|(_, new_index)| new_index == index))
+        .map(|(_, path)| path.to_string_lossy().to_string())
+        .collect();
+    if library_root_of(old_folder).is_some() {
+        for path in &unmatched_new {
+            let _ = index_file(Path::new(path));
+        }
+    }
+
+    Ok(ReplaceAlbumResult {
+        journal_id: tx.id(),
+        destination: destinations.first()
+            .and_then(|path| path.parent())
+            .unwrap_or(old_folder)
+            .to_string_lossy()
+            .to_string(),
+        old_files_location: old_destination.to_string_lossy().to_string(),
+        matched,
+        unmatched_old,
+        unmatched_new,
+    })
+}
+
+/// Replaces the album in `old_folder` with the one in `new_folder` (e.g. a
+/// lossless re-rip), carrying each matched track's library data over to its
+/// replacement. Journaled, so `undo_journal_entry` restores the previous state.
+#[tauri::command]
+pub async fn replace_album(
+    player: State<'_, PlayerHandle>,
+    old_folder: String,
+    new_folder: String,
+    delete_old: bool,
+    organize_pattern: Option<String>,
+) -> Result<ReplaceAlbumResult, FileOpError> {
+    let old_path = Path::new(&old_folder);
+    let new_path = Path::new(&new_folder);
+    if !old_path.is_dir() || !new_path.is_dir() {
+        return Err(FileOpError::Failed("Both album folders must exist".to_string()));
+    }
+    if old_path.starts_with(new_path) || new_path.starts_with(old_path) {
+        return Err(FileOpError::Failed("The album folders must not contain one another".to_string()));
+    }
+    if let Some(playing) = playing_under(&player, &[old_path, new_path]) {
+        return Err(FileOpError::FileInUse(format!("{} is currently playing", playing)));
+    }
+
+    let mut tx = Transaction::new("replace_album", format!("Replace {} with {}", old_folder, new_folder));
+    match run_replace(&mut tx, old_path, new_path, delete_old, organize_pattern.as_deref()) {
+        Ok(result) => {
+            tx.commit()?;
+            info!(
+                "Replaced album {} ({} matched, {} old and {} new unmatched)",
+                old_folder, result.matched.len(), result.unmatched_old.len(), result.unmatched_new.len()
+            );
+            Ok(result)
+        }
+        Err(e) => {
+            tx.rollback();
+            Err(FileOpError::Failed(e))
+        }
+    }
+}
@@ -0,0 +1,169 @@
+use serde::Serialize;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use crate::library::track_info;
+use crate::sidecar::load_sidecar;
+use crate::sorting::natural_cmp;
+use crate::text::{collate_opt, fold};
+use crate::walker::{walk_audio_files, Exclusions};
+
+#[derive(Debug, Serialize, Clone)]
+pub struct AlbumTrack {
+    pub path: String,
+    pub title: Option<String>,
+    pub artist: Option<String>,
+    pub disc_number: Option<u32>,
+    pub track_number: Option<u32>,
+    pub duration: Option<f64>,
+}
+
+#[derive(Debug, Serialize, Clone)]
+pub struct AlbumGroup {
+    pub album: Option<String>,
+    pub album_artist: Option<String>,
+    pub year: Option<u32>,
+    pub genre: Option<String>,
+    pub folder: String,
+    pub tracks: Vec<AlbumTrack>,
+}
+
+pub fn collect_audio_files(dir: &Path, recursive: bool) -> Vec<PathBuf> {
+    walk_audio_files(dir, recursive, &Exclusions::load())
+}
+
+fn file_name(path: &str) -> &str {
+    Path::new(path).file_name().and_then(|n| n.to_str()).unwrap_or(path)
+}
+
+/// Groups files into albums by (album artist, album) tag, falling back to the
+/// containing folder for untagged files. Tracks are ordered by disc, then
+/// track number, then natural filename order; an untagged disc counts as the
+/// first.
+pub fn group_albums(paths: &[PathBuf]) -> Vec<AlbumGroup> {
+    let mut groups: Vec<AlbumGroup> = Vec::new();
+    let mut keys: HashMap<String, usize> = HashMap::new();
+
+    for path in paths {
+        let info = match track_info(path) {
+            Some(info) => info,
+            None => continue,
+        };
+        let folder = path.parent()
+            .map(|p| p.to_string_lossy().to_string())
+            .unwrap_or_default();
+        let album_artist = info.album_artist.clone().or_else(|| info.artist.clone());
+
+        let key = match &info.album {
+            Some(album) => format!(
+                "{}\u{0}{}",
+                fold(album_artist.as_deref().unwrap_or("")),
+                fold(album)
+            ),
+            None => format!("folder\u{0}{}", folder),
+        };
+
+        let index = *keys.entry(key).or_insert_with(|| {
+            groups.push(AlbumGroup {
+                album: info.album.clone(),
+                album_artist: album_artist.clone(),
+                year: info.year,
+                genre: info.genre.clone(),
+                folder: folder.clone(),
+                tracks: Vec::new(),
+            });
+            groups.len() - 1
+        });
+
+        let group = &mut groups[index];
+        group.year = group.year.or(info.year);
+        group.genre = group.genre.clone().or(info.genre.clone());
+        group.tracks.push(AlbumTrack {
+            path: info.path,
+            title: info.title,
+            artist: info.artist,
+            disc_number: info.disc_number,
+            track_number: info.track_number,
+            duration: info.duration,
+        });
+    }
+
+    groups.sort_by(|a, b| {
+        collate_opt(a.album_artist.as_deref(), b.album_artist.as_deref())
+            .then_with(|| collate_opt(a.album.as_deref(), b.album.as_deref()))
+            .then_with(|| natural_cmp(&a.folder, &b.folder))
+    });
+    for group in &mut groups {
+        group.tracks.sort_by(|a, b| {
+            (a.disc_number.unwrap_or(1), a.track_number.unwrap_or(u32::MAX))
+                .cmp(&(b.disc_number.unwrap_or(1), b.track_number.unwrap_or(u32::MAX)))
+                .then_with(|| natural_cmp(file_name(&a.path), file_name(&b.path)))
+        });
+    }
+
+    groups
+}
+
+/// Fills album fields the embedded tags lack (typically year and album artist)
+/// from a sidecar file in the album's folder.
+fn merge_sidecar_data(groups: &mut [AlbumGroup]) {
+    for group in groups {
+        if group.year.is_some() && group.album_artist.is_some() && group.album.is_some() && group.genre.is_some() {
+            continue;
+        }
+        if let Some(sidecar) = load_sidecar(Path::new(&group.folder)) {
+            group.album = group.album.take().or(sidecar.album);
+            group.album_artist = group.album_artist.take().or(sidecar.album_artist);
+            group.year = group.year.or(sidecar.year);
+            group.genre = group.genre.take().or(sidecar.genre);
+        }
+    }
+}
+
+#[tauri::command]
+pub async fn get_albums(path: String, recursive: Option<bool>, merge_sidecar: Option<bool>) -> Result<Vec<AlbumGroup>, String> {
+    let root = Path::new(&path);
+    if !root.is_dir() {
+        return Err("Path must be a directory".to_string());
+    }
+
+    let files = collect_audio_files(root, recursive.unwrap_or(true));
+    let mut groups = group_albums(&files);
+    if merge_sidecar.unwrap_or(false) {
+        merge_sidecar_data(&mut groups);
+    }
+    Ok(groups)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::metadata::{write_metadata, MetadataWriteOptions};
+    use crate::test_support::{build_library, temp_dir, FixtureTags, TrackSpec};
+
+    #[test]
+    fn multi_disc_albums_play_disc_by_disc() {
+        let dir = temp_dir();
+        let tagged = |title: &str| FixtureTags { album: Some("Live".into()), artist: Some("Band".into()), ..FixtureTags::titled(title) };
+        let paths = build_library(dir.path(), &[
+            TrackSpec::new("d2t1.flac", tagged("Encore")),
+            TrackSpec::new("d1t2.flac", tagged("Second")),
+            TrackSpec::new("d2t2.flac", tagged("Finale")),
+            TrackSpec::new("d1t1.flac", tagged("Opener")),
+        ]);
+        for path in &paths {
+            let name = path.file_stem().unwrap().to_string_lossy();
+            let digit = |at: usize| name[at..at + 1].parse().ok();
+            write_metadata(&MetadataWriteOptions {
+                path: path.to_string_lossy().to_string(),
+                disc_number: digit(1),
+                track_number: digit(3),
+                ..MetadataWriteOptions::default()
+            }).unwrap();
+        }
+
+        let groups = group_albums(&paths);
+        assert_eq!(groups.len(), 1);
+        let titles: Vec<_> = groups[0].tracks.iter().map(|track| track.title.as_deref().unwrap()).collect();
+        assert_eq!(titles, ["Opener", "Second", "Encore", "Finale"]);
+    }
+}
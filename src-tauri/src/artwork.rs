@@ -0,0 +1,234 @@
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::fs::{self, OpenOptions};
+use std::io::{Cursor, Write};
+use std::path::{Path, PathBuf};
+use image::{codecs::jpeg::JpegEncoder, imageops::FilterType, DynamicImage, ImageFormat, ImageReader};
+use lofty::picture::{MimeType, Picture};
+use log::info;
+use crate::thumbnails::embedded_cover;
+use crate::walker::{walk_audio_files, Exclusions};
+
+const IMAGE_EXTENSIONS: [&str; 6] = ["jpg", "jpeg", "png", "gif", "bmp", "webp"];
+// Tried in order until the encoded cover fits the byte limit
+const EMBED_QUALITIES: [u8; 6] = [90, 85, 75, 65, 50, 35];
+// Below this the cover is scaled down further instead of losing more quality
+const MIN_EMBED_DIMENSION: u32 = 64;
+
+#[derive(Debug, Serialize)]
+pub struct ExtractedArt {
+    pub path: String,
+    pub mime_type: String,
+    pub width: u32,
+    pub height: u32,
+}
+
+/// A cover as it was embedded, after any downscaling.
+#[derive(Debug, Serialize, Clone, PartialEq)]
+pub struct EmbeddedArt {
+    pub mime_type: String,
+    pub width: u32,
+    pub height: u32,
+    pub size_bytes: usize,
+    // Whether the image was downscaled or re-encoded to fit the limits or the file format
+    pub resized: bool,
+}
+
+#[derive(Debug, Serialize, Default)]
+pub struct FolderArtReport {
+    pub written: Vec<ExtractedArt>,
+    // Album folders that already had an image file
+    pub skipped: Vec<String>,
+    // Album folders where no track has embedded art
+    pub no_art: Vec<String>,
+    pub failed: Vec<String>,
+}
+
+/// Extension and MIME type for the picture, trusting the image bytes over the
+/// tag's declared type, which taggers often leave out or get wrong.
+pub(crate) fn image_kind(picture: &Picture) -> (&'static str, String) {
+    let guessed = image::guess_format(picture.data()).ok().and_then(|format| match format {
+        ImageFormat::Jpeg => Some(("jpg", MimeType::Jpeg)),
+        ImageFormat::Png => Some(("png", MimeType::Png)),
+        ImageFormat::Gif => Some(("gif", MimeType::Gif)),
+        ImageFormat::Bmp => Some(("bmp", MimeType::Bmp)),
+        ImageFormat::Tiff => Some(("tiff", MimeType::Tiff)),
+        ImageFormat::WebP => Some(("webp", MimeType::Unknown("image/webp".to_string()))),
+        _ => None,
+    });
+
+    let (ext, mime) = guessed.unwrap_or_else(|| match picture.mime_type() {
+        Some(MimeType::Png) => ("png", MimeType::Png),
+        Some(MimeType::Gif) => ("gif", MimeType::Gif),
+        Some(MimeType::Bmp) => ("bmp", MimeType::Bmp),
+        Some(MimeType::Tiff) => ("tiff", MimeType::Tiff),
+        _ => ("jpg", MimeType::Jpeg),
+    });
+    (ext, mime.as_str().to_string())
+}
+
+/// MIME type of image data meant for embedding, from its magic numbers. Only
+/// the formats players reliably show are accepted.
+pub(crate) fn embeddable_mime(data: &[u8]) -> Result<MimeType, String> {
+    match image::guess_format(data) {
+        Ok(ImageFormat::Jpeg) => Ok(MimeType::Jpeg),
+        Ok(ImageFormat::Png) => Ok(MimeType::Png),
+        Ok(ImageFormat::Gif) => Ok(MimeType::Gif),
+        Ok(ImageFormat::WebP) => Ok(MimeType::Unknown("image/webp".to_string())),
+        _ => Err("The data is not a JPEG, PNG, WebP or GIF image".to_string()),
+    }
+}
+
+pub(crate) fn dimensions(data: &[u8]) -> Result<(u32, u32), String> {
+    ImageReader::new(Cursor::new(data))
+        .with_guessed_format()
+        .map_err(|e| e.to_string())?
+        .into_dimensions()
+        .map_err(|e| format!("Failed to read image dimensions: {}", e))
+}
+
+fn encode_jpeg(image: &DynamicImage, quality: u8) -> Result<Vec<u8>, String> {
+    let mut output = Cursor::new(Vec::new());
+    JpegEncoder::new_with_quality(&mut output, quality)
+        .encode_image(&image.to_rgb8())
+        .map_err(|e| format!("Failed to encode cover art: {}", e))?;
+    Ok(output.into_inner())
+}
+
+/// Shrinks cover art for embedding: no side longer than `max_dimension` and no
+/// more than `max_bytes`, where 0 means no limit. Images already within both
+/// are returned untouched; others become a JPEG at the best quality that fits,
+/// scaled down further if even the lowest quality is too big. With `for_mp4`,
+/// WebP and GIF always become a JPEG, since MP4's `covr` atom only holds JPEG,
+/// PNG or BMP.
+pub(crate) fn fit_for_embedding(data: Vec<u8>, max_dimension: u32, max_bytes: u64, for_mp4: bool) -> Result<(Vec<u8>, EmbeddedArt), String> {
+    let mime = embeddable_mime(&data)?;
+    let unsupported = for_mp4 && !matches!(mime, MimeType::Jpeg | MimeType::Png);
+    let mime_type = mime.as_str().to_string();
+    let (width, height) = dimensions(&data)?;
+    let too_large = max_dimension > 0 && width.max(height) > max_dimension;
+    let too_heavy = max_bytes > 0 && data.len() as u64 > max_bytes;
+    if !too_large && !too_heavy && !unsupported {
+        let size_bytes = data.len();
+        return Ok((data, EmbeddedArt { mime_type, width, height, size_bytes, resized: false }));
+    }
+
+    let mut image = image::load_from_memory(&data).map_err(|e| format!("Failed to decode cover art: {}", e))?;
+    if too_large {
+        image = image.resize(max_dimension, max_dimension, FilterType::Lanczos3);
+    }
+    loop {
+        for quality in EMBED_QUALITIES {
+            let encoded = encode_jpeg(&image, quality)?;
+            if max_bytes == 0 || encoded.len() as u64 <= max_bytes {
+                let art = EmbeddedArt {
+                    mime_type: MimeType::Jpeg.as_str().to_string(),
+                    width: image.width(),
+                    height: image.height(),
+                    size_bytes: encoded.len(),
+                    resized: true,
+                };
+                return Ok((encoded, art));
+            }
+        }
+        let longest = image.width().max(image.height()) * 3 / 4;
+        if longest < MIN_EMBED_DIMENSION {
+            return Err(format!("Cover art can't be made to fit in {} bytes", max_bytes));
+        }
+        image = image.resize(longest, longest, FilterType::Lanczos3);
+    }
+}
+
+/// Writes `picture` to `output` (or `cover.<ext>` beside `audio_path`).
+fn write_picture(picture: &Picture, audio_path: &Path, output: Option<&Path>, overwrite: bool) -> Result<ExtractedArt, String> {
+    let (ext, mime_type) = image_kind(picture);
+    let output = match output {
+        Some(output) => output.to_path_buf(),
+        None => audio_path.parent()
+            .ok_or("Audio file has no parent folder")?
+            .join(format!("cover.{}", ext)),
+    };
+    let (width, height) = dimensions(picture.data())?;
+
+    let mut options = OpenOptions::new();
+    options.write(true);
+    if overwrite {
+        options.create(true).truncate(true);
+    } else {
+        // create_new makes the existence check and the write a single step
+        options.create_new(true);
+    }
+    let mut file = options.open(&output).map_err(|e| match e.kind() {
+        std::io::ErrorKind::AlreadyExists => format!("{} already exists", output.display()),
+        _ => format!("Failed to create {}: {}", output.display(), e),
+    })?;
+    file.write_all(picture.data()).map_err(|e| format!("Failed to write {}: {}", output.display(), e))?;
+
+    Ok(ExtractedArt {
+        path: output.to_string_lossy().to_string(),
+        mime_type,
+        width,
+        height,
+    })
+}
+
+#[tauri::command]
+pub fn extract_album_art(path: String, output_path: Option<String>, overwrite: Option<bool>) -> Result<ExtractedArt, String> {
+    let audio_path = Path::new(&path);
+    let picture = embedded_cover(audio_path).ok_or("The file has no embedded artwork")?;
+    let art = write_picture(&picture, audio_path, output_path.as_deref().map(Path::new), overwrite.unwrap_or(false))?;
+    info!("Extracted cover art from {} to {}", path, art.path);
+    Ok(art)
+}
+
+fn has_image_file(folder: &Path) -> bool {
+    fs::read_dir(folder).map(|entries| {
+        entries.flatten().any(|entry| {
+            entry.path().extension()
+                .and_then(|ext| ext.to_str())
+                .is_some_and(|ext| IMAGE_EXTENSIONS.contains(&ext.to_lowercase().as_str()))
+        })
+    }).unwrap_or(false)
+}
+
+/// Writes a cover file into every album folder under `root` that has no image
+/// file yet, taken from the first track (in name order) with embedded art.
+#[tauri::command]
+pub async fn extract_album_art_for_folder(root: String, recursive: bool) -> Result<FolderArtReport, String> {
+    let root_path = Path::new(&root);
+    if !root_path.is_dir() {
+        return Err(format!("Folder does not exist: {}", root));
+    }
+
+    let mut folders: BTreeMap<PathBuf, Vec<PathBuf>> = BTreeMap::new();
+    for file in walk_audio_files(root_path, recursive, &Exclusions::load()) {
+        if let Some(parent) = file.parent() {
+            folders.entry(parent.to_path_buf()).or_default().push(file);
+        }
+    }
+
+    let mut report = FolderArtReport::default();
+    for (folder, mut files) in folders {
+        let folder_name = folder.to_string_lossy().to_string();
+        if has_image_file(&folder) {
+            report.skipped.push(folder_name);
+            continue;
+        }
+
+        files.sort();
+        let Some((file, picture)) = files.iter().find_map(|file| embedded_cover(file).map(|picture| (file, picture))) else {
+            report.no_art.push(folder_name);
+            continue;
+        };
+        match write_picture(&picture, file, None, false) {
+            Ok(art) => report.written.push(art),
+            Err(e) => report.failed.push(format!("{}: {}", folder_name, e)),
+        }
+    }
+
+    info!(
+        "Wrote {} cover files under {} ({} already had one, {} without art)",
+        report.written.len(), root, report.skipped.len(), report.no_art.len()
+    );
+    Ok(report)
+}
@@ -0,0 +1,143 @@
+//! Notices when the output device playback is using goes away, such as
+//! headphones being unplugged, and moves playback to the new default device.
+
+use serde::Serialize;
+use std::time::Duration;
+use rodio::cpal::traits::{DeviceTrait, HostTrait};
+use tauri::{AppHandle, Emitter, Manager};
+use log::{error, info, warn};
+use crate::commands::pause_audio;
+use crate::output::release;
+use crate::output_format::{PlaybackErrorKind, PlaybackFailure};
+use crate::relocate::restart_at;
+use crate::PlayerHandle;
+
+const CHECK_INTERVAL: Duration = Duration::from_secs(1);
+// A playing sink whose position hasn't moved for this many checks has lost its stream
+const STALL_CHECKS: u32 = 3;
+
+#[derive(Debug, Serialize, Clone)]
+pub struct AudioDeviceChanged {
+    pub device: String,
+    pub track: Option<String>,
+    pub position: f32,
+}
+
+#[derive(Debug, PartialEq)]
+enum DeviceAction {
+    Nothing,
+    // No output device at all
+    Pause,
+    // Reopen the stream on the current default device
+    Move,
+}
+
+/// Name of the system's default output device, if there is one.
+pub fn default_output_name() -> Option<String> {
+    rodio::cpal::default_host().default_output_device().and_then(|device| device.name().ok())
+}
+
+fn decide(default: Option<&str>, playing_on: Option<&str>, is_playing: bool, stalled_checks: u32) -> DeviceAction {
+    match default {
+        None if is_playing => DeviceAction::Pause,
+        None => DeviceAction::Nothing,
+        // The stream stays on the device it was opened on, so follow the default when it changes
+        Some(default) if playing_on.is_some_and(|device| device != default) => DeviceAction::Move,
+        Some(_) if is_playing && stalled_checks >= STALL_CHECKS => DeviceAction::Move,
+        Some(_) => DeviceAction::Nothing,
+    }
+}
+
+/// Checks the output device every second while a track is loaded. If it
+/// changed or the stream stalled, playback reopens on the default device at the
+/// same position and `audio-device-changed` is emitted; with no device left,
+/// playback pauses and `playback-error` explains why.
+pub fn start_device_watch(app: AppHandle) {
+    std::thread::spawn(move || {
+        let mut last_position = None;
+        let mut stalled_checks = 0;
+        loop {
+            std::thread::sleep(CHECK_INTERVAL);
+            let loaded = app.state::<PlayerHandle>().with(|player| match (&player.current_path, &player.sink) {
+                (Some(path), Some(sink)) => {
+                    let advancing = player.is_playing && !sink.is_paused() && !sink.empty();
+                    Some((path.clone(), player.output_device.clone(), player.is_playing, player.position(), advancing))
+                }
+                _ => None,
+            });
+            let Some((path, playing_on, is_playing, position, advancing)) = loaded else {
+                last_position = None;
+                stalled_checks = 0;
+                continue;
+            };
+
+            stalled_checks = if advancing && last_position == Some(position) { stalled_checks + 1 } else { 0 };
+            last_position = Some(position);
+
+            let default = default_output_name();
+            match decide(default.as_deref(), playing_on.as_deref(), is_playing, stalled_checks) {
+                DeviceAction::Nothing => {}
+                DeviceAction::Pause => {
+                    warn!("No audio output device; pausing {}", path);
+                    if let Err(e) = pause_audio(app.state()) {
+                        error!("Failed to pause after losing the output device: {}", e);
+                    }
+                    app.emit("playback-error", PlaybackFailure {
+                        kind: PlaybackErrorKind::DeviceError,
+                        path: Some(path),
+                        message: "No audio output device is available".to_string(),
+                    }).ok();
+                }
+                DeviceAction::Move => {
+                    let device = default.unwrap_or_default();
+                    info!("Moving playback of {} to {}", path, device);
+                    stalled_checks = 0;
+                    last_position = None;
+                    // The output stays on the device it was opened on until closed
+                    app.state::<PlayerHandle>().with(release);
+                    match restart_at(&app, &path, position.as_secs_f32(), is_playing) {
+                        Ok(()) => {
+                            app.emit("audio-device-changed", AudioDeviceChanged {
+                                device,
+                                track: Some(path),
+                                position: position.as_secs_f32(),
+                            }).ok();
+                        }
+                        Err(e) => {
+                            error!("Failed to move playback to {}: {}", device, e);
+                            // Don't keep claiming to play on a stream that's gone
+                            pause_audio(app.state()).ok();
+                            app.emit("playback-error", PlaybackFailure {
+                                kind: e.kind(),
+                                path: Some(path),
+                                message: format!("Couldn't switch to {}: {}", device, e),
+                            }).ok();
+                        }
+                    }
+                }
+            }
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn follows_the_default_device_and_pauses_without_one() {
+        assert_eq!(decide(Some("Speakers"), Some("Speakers"), true, 0), DeviceAction::Nothing);
+        assert_eq!(decide(Some("Speakers"), Some("Headphones"), true, 0), DeviceAction::Move);
+        // A paused track is moved too, so resuming plays on the right device
+        assert_eq!(decide(Some("Speakers"), Some("Headphones"), false, 0), DeviceAction::Move);
+        assert_eq!(decide(None, Some("Headphones"), true, 0), DeviceAction::Pause);
+        assert_eq!(decide(None, Some("Headphones"), false, 0), DeviceAction::Nothing);
+    }
+
+    #[test]
+    fn a_stalled_stream_is_reopened() {
+        assert_eq!(decide(Some("Speakers"), Some("Speakers"), true, STALL_CHECKS - 1), DeviceAction::Nothing);
+        assert_eq!(decide(Some("Speakers"), Some("Speakers"), true, STALL_CHECKS), DeviceAction::Move);
+        assert_eq!(decide(Some("Speakers"), None, true, STALL_CHECKS), DeviceAction::Move);
+    }
+}
@@ -0,0 +1,303 @@
+use serde::{Deserialize, Serialize};
+use std::collections::VecDeque;
+use std::fs;
+use std::path::Path;
+use std::sync::mpsc::channel;
+use std::sync::Arc;
+use std::time::Duration;
+use parking_lot::Mutex;
+use tauri::{AppHandle, Emitter};
+use log::{info, error};
+use crate::albums::{collect_audio_files, group_albums, AlbumGroup};
+use crate::cache::{self, CacheKind};
+use crate::decode::{decode_range, probe_duration, rms_db, silent_blocks, DecodedAudio};
+use crate::library::modified_secs;
+use crate::throttle;
+
+const EDGE_WINDOW: Duration = Duration::from_millis(500);
+const LEVEL_WINDOW: Duration = Duration::from_millis(50);
+const BLOCK: Duration = Duration::from_millis(10);
+const SILENCE_DB: f32 = -60.0;
+// Silence shorter than this at a boundary is treated as a sample-accurate cut
+const SILENCE_TOLERANCE_MS: u32 = 20;
+const LEVEL_JUMP_TOLERANCE_DB: f32 = 6.0;
+// Stretch either side of the join compared for waveform continuity
+const CORRELATION_WINDOW: Duration = Duration::from_millis(20);
+// How far past the join the match may start; one cycle of anything down to 50 Hz
+const CORRELATION_MAX_LAG: Duration = Duration::from_millis(20);
+// Below this the waveform doesn't carry on across the join, whatever the levels say
+const MIN_JOIN_CORRELATION: f32 = 0.3;
+const MAX_WORKERS: usize = 4;
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub enum BoundaryVerdict {
+    // Audio runs straight through the boundary at a matching level
+    Continuous,
+    // One side is sounding while the other starts/ends in silence: the classic re-rip hiccup
+    InsertedSilence,
+    // Both sides are sounding but the level jumps or the waveform breaks off, suggesting a misaligned cut
+    Discontinuous,
+    // Both sides fade to silence; a normal gap between songs
+    NaturalGap,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct BoundaryFinding {
+    pub from_path: String,
+    pub to_path: String,
+    pub trailing_silence_ms: u32,
+    pub leading_silence_ms: u32,
+    pub end_level_db: f32,
+    pub start_level_db: f32,
+    pub level_jump_db: f32,
+    // Best normalized correlation between the audio either side of the join;
+    // None when either side is silent or the sample rates differ
+    #[serde(default)]
+    pub join_correlation: Option<f32>,
+    pub verdict: BoundaryVerdict,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct AlbumBoundaryReport {
+    pub album: Option<String>,
+    pub album_artist: Option<String>,
+    pub folder: String,
+    pub content_hash: String,
+    pub boundaries: Vec<BoundaryFinding>,
+    pub errors: Vec<String>,
+    pub cached: bool,
+}
+
+#[derive(Debug, Serialize, Clone)]
+pub struct BoundaryProgress {
+    pub completed: usize,
+    pub total: usize,
+    pub from_path: String,
+}
+
+/// Milliseconds of silence at the start (or end, when `from_end`) of a signal,
+/// measured in 10ms blocks against the silence threshold.
+fn silence_ms(mono: &[f32], block_frames: usize, from_end: bool) -> u32 {
+    let silent = silent_blocks(mono, block_frames, SILENCE_DB, from_end);
+    (silent as u64 * BLOCK.as_millis() as u64) as u32
+}
+
+/// How well the waveform carries on across a join: the end of `tail` against
+/// the start of `head`, shifted by up to `max_lag` frames so periodic sound
+/// lines up with itself. 1 is a perfect continuation, near 0 unrelated audio.
+fn join_correlation(tail: &[f32], head: &[f32], window: usize, max_lag: usize) -> Option<f32> {
+    if window == 0 || tail.len() < window || head.len() < window + max_lag {
+        return None;
+    }
+    let before = &tail[tail.len() - window..];
+    let energy = |samples: &[f32]| samples.iter().map(|s| (*s as f64).powi(2)).sum::<f64>();
+    let before_energy = energy(before);
+    (0..=max_lag)
+        .filter_map(|lag| {
+            let after = &head[lag..lag + window];
+            let scale = (before_energy * energy(after)).sqrt();
+            (scale > 1e-9).then(|| before.iter().zip(after).map(|(a, b)| *a as f64 * *b as f64).sum::<f64>() / scale)
+        })
+        .max_by(f64::total_cmp)
+        .map(|correlation| correlation as f32)
+}
+
+fn decode_tail(path: &Path) -> Result<DecodedAudio, String> {
+    let duration = probe_duration(path).ok_or_else(|| format!("Unknown duration for {}", path.display()))?;
+    let start = duration.saturating_sub(EDGE_WINDOW);
+    decode_range(path, start, None)
+}
+
+fn analyze_boundary(from: &str, to: &str) -> Result<BoundaryFinding, String> {
+    let tail = decode_tail(Path::new(from))?;
+    let head = decode_range(Path::new(to), Duration::ZERO, Some(EDGE_WINDOW))?;
+
+    let tail_mono = tail.mono();
+    let head_mono = head.mono();
+
+    let trailing_silence_ms = silence_ms(&tail_mono, tail.frames_for(BLOCK), true);
+    let leading_silence_ms = silence_ms(&head_mono, head.frames_for(BLOCK), false);
+
+    let tail_window = tail.frames_for(LEVEL_WINDOW).min(tail_mono.len());
+    let head_window = head.frames_for(LEVEL_WINDOW).min(head_mono.len());
+    let end_level_db = rms_db(&tail_mono[tail_mono.len() - tail_window..]);
+    let start_level_db = rms_db(&head_mono[..head_window]);
+    let level_jump_db = start_level_db - end_level_db;
+    let join_correlation = if tail.sample_rate == head.sample_rate {
+        join_correlation(&tail_mono, &head_mono, tail.frames_for(CORRELATION_WINDOW), tail.frames_for(CORRELATION_MAX_LAG))
+    } else {
+        None
+    };
+    // Levels alone miss a cut into different material at the same loudness
+    let waveform_continues = join_correlation.is_none_or(|correlation| correlation >= MIN_JOIN_CORRELATION);
+
+    let tail_silent = trailing_silence_ms > SILENCE_TOLERANCE_MS;
+    let head_silent = leading_silence_ms > SILENCE_TOLERANCE_MS;
+    let verdict = match (tail_silent, head_silent) {
+        (true, true) => BoundaryVerdict::NaturalGap,
+        (true, false) | (false, true) => BoundaryVerdict::InsertedSilence,
+        (false, false) if level_jump_db.abs() <= LEVEL_JUMP_TOLERANCE_DB && waveform_continues => BoundaryVerdict::Continuous,
+        (false, false) => BoundaryVerdict::Discontinuous,
+    };
+
+    Ok(BoundaryFinding {
+        from_path: from.to_string(),
+        to_path: to.to_string(),
+        trailing_silence_ms,
+        leading_silence_ms,
+        end_level_db,
+        start_level_db,
+        level_jump_db,
+        join_correlation,
+        verdict,
+    })
+}
+
+/// Identifies an album's audio content so results are reused until a file changes.
+fn content_hash(group: &AlbumGroup) -> String {
+    let parts: Vec<String> = group.tracks.iter().map(|track| {
+        let (size, modified) = fs::metadata(&track.path)
+            .map(|m| (m.len(), modified_secs(&m)))
+            .unwrap_or((0, 0));
+        format!("{}:{}:{}", track.path, size, modified)
+    }).collect();
+    let refs: Vec<&str> = parts.iter().map(|s| s.as_str()).collect();
+    cache::cache_key(&refs)
+}
+
+fn cache_file_name(hash: &str) -> String {
+    format!("boundaries-{}.json", hash)
+}
+
+fn load_cached(hash: &str) -> Option<AlbumBoundaryReport> {
+    let path = cache::entry_path(CacheKind::Metadata, &cache_file_name(hash))?;
+    let contents = fs::read_to_string(&path).ok()?;
+    let mut report: AlbumBoundaryReport = serde_json::from_str(&contents).ok()?;
+    cache::mark_used(&path);
+    report.cached = true;
+    Some(report)
+}
+
+#[tauri::command]
+pub async fn analyze_track_boundaries(app: AppHandle, folder: String) -> Result<Vec<AlbumBoundaryReport>, String> {
+    let root = Path::new(&folder);
+    if !root.is_dir() {
+        return Err("Folder does not exist".to_string());
+    }
+
+    let groups = group_albums(&collect_audio_files(root, true));
+    let mut reports: Vec<AlbumBoundaryReport> = Vec::new();
+    // (report index, boundary index, from, to)
+    let mut jobs = VecDeque::new();
+
+    for group in groups.iter().filter(|g| g.tracks.len() > 1) {
+        let hash = content_hash(group);
+        if let Some(cached) = load_cached(&hash) {
+            reports.push(cached);
+            continue;
+        }
+
+        let report_index = reports.len();
+        for (boundary_index, pair) in group.tracks.windows(2).enumerate() {
+            jobs.push_back((report_index, boundary_index, pair[0].path.clone(), pair[1].path.clone()));
+        }
+        reports.push(AlbumBoundaryReport {
+            album: group.album.clone(),
+            album_artist: group.album_artist.clone(),
+            folder: group.folder.clone(),
+            content_hash: hash,
+            boundaries: Vec::new(),
+            errors: Vec::new(),
+            cached: false,
+        });
+    }
+
+    let total = jobs.len();
+    info!("Analyzing {} track boundaries under {}", total, folder);
+
+    let jobs = Arc::new(Mutex::new(jobs));
+    let (tx, rx) = channel();
+    let workers = std::thread::available_parallelism()
+        .map(|n| n.get())
+        .unwrap_or(1)
+        .clamp(1, MAX_WORKERS);
+
+    for _ in 0..workers {
+        let jobs = Arc::clone(&jobs);
+        let tx = tx.clone();
+        std::thread::spawn(move || loop {
+            let job = jobs.lock().pop_front();
+            let Some((report_index, boundary_index, from, to)) = job else { break };
+            let result = {
+                let _permit = throttle::background_io();
+                analyze_boundary(&from, &to)
+            };
+            if tx.send((report_index, boundary_index, from, result)).is_err() {
+                break;
+            }
+        });
+    }
+    drop(tx);
+
+    let mut findings: Vec<Vec<(usize, BoundaryFinding)>> = vec![Vec::new(); reports.len()];
+    for (completed, (report_index, boundary_index, from, result)) in rx.iter().enumerate() {
+        app.emit("boundary-analysis-progress", BoundaryProgress {
+            completed: completed + 1,
+            total,
+            from_path: from.clone(),
+        }).ok();
+
+        match result {
+            Ok(finding) => findings[report_index].push((boundary_index, finding)),
+            Err(e) => reports[report_index].errors.push(format!("{}: {}", from, e)),
+        }
+    }
+
+    for (report, mut album_findings) in reports.iter_mut().zip(findings) {
+        if report.cached {
+            continue;
+        }
+        album_findings.sort_by_key(|(index, _)| *index);
+        report.boundaries = album_findings.into_iter().map(|(_, finding)| finding).collect();
+
+        // Only complete analyses are cached so a transient decode error gets retried
+        if report.errors.is_empty() {
+            match serde_json::to_vec(&report) {
+                Ok(json) => {
+                    if let Err(e) = cache::write_entry(CacheKind::Metadata, &cache_file_name(&report.content_hash), &json) {
+                        error!("Failed to cache boundary analysis: {}", e);
+                    }
+                }
+                Err(e) => error!("Failed to serialize boundary analysis: {}", e),
+            }
+        }
+    }
+
+    Ok(reports)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::sine_samples;
+
+    const RATE: usize = 44_100;
+
+    #[test]
+    fn a_waveform_split_across_the_join_correlates() {
+        let window = RATE / 50;
+        let wave = |freq| -> Vec<f32> {
+            sine_samples(RATE as u32, freq, 1.0).into_iter().map(|sample| sample as f32 / i16::MAX as f32).collect()
+        };
+        let a = wave(440.0);
+        let (tail, head) = a.split_at(RATE / 2);
+        assert!(join_correlation(tail, head, window, window).unwrap() > 0.95);
+
+        // The same loudness, but a different note starting mid-cycle
+        let b = wave(1234.0);
+        let cut = &b[7..7 + RATE / 2];
+        assert!(join_correlation(tail, cut, window, window).unwrap() < MIN_JOIN_CORRELATION);
+        assert_eq!(join_correlation(tail, &vec![0.0; RATE / 2], window, window), None);
+        assert_eq!(join_correlation(tail, &head[..window], window, window), None);
+    }
+}
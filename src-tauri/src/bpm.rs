@@ -0,0 +1,170 @@
+//! Tempo estimation for files without a BPM tag. A 30 second window of the
+//! track is reduced to an onset envelope (how sharply the level rises, hop by
+//! hop), and the lag at which that envelope best lines up with itself gives
+//! the beat period.
+
+use serde::Serialize;
+use std::path::Path;
+use rodio::Source;
+use tauri::{AppHandle, Emitter};
+use log::info;
+use crate::decode::{open_decoder, probe_duration};
+use crate::metadata::{write_metadata, MetadataWriteOptions};
+use crate::tasks::{start_task, TaskHandle, TaskKind};
+
+const WINDOW_SECS: f64 = 30.0;
+// The window is centred in the track but starts no later than this, past any beatless intro
+const MAX_WINDOW_START_SECS: f64 = 30.0;
+// Onset envelope hops per second
+const ENVELOPE_RATE: f64 = 200.0;
+const MIN_BPM: f64 = 60.0;
+const MAX_BPM: f64 = 200.0;
+// When half or double the tempo lines up about as well, the one nearer this wins
+const PREFERRED_BPM: f64 = 120.0;
+// Width in octaves of that preference
+const PREFERENCE_SPREAD: f64 = 1.0;
+
+/// Sent as `bpm-analyzed` when an analysis ends.
+#[derive(Debug, Serialize, Clone)]
+pub struct BpmAnalyzed {
+    pub path: String,
+    pub bpm: Option<f32>,
+    // Whether the result was written to the file's tags
+    pub written: bool,
+    pub error: Option<String>,
+}
+
+/// How much louder each hop is than the one before, in log energy; falls are 0.
+fn onset_envelope(samples: &[f32], hop: usize) -> Vec<f64> {
+    let log_energy: Vec<f64> = samples.chunks(hop)
+        .map(|chunk| (chunk.iter().map(|sample| (*sample as f64).powi(2)).sum::<f64>() / chunk.len() as f64 + 1e-10).ln())
+        .collect();
+    log_energy.windows(2).map(|pair| (pair[1] - pair[0]).max(0.0)).collect()
+}
+
+/// Estimates the tempo of mono `samples`, to a tenth of a BPM between 60 and
+/// 200. None when there is too little audio or no beat to find.
+pub fn estimate_bpm(samples: &[f32], sample_rate: u32) -> Option<f32> {
+    let hop = ((sample_rate as f64 / ENVELOPE_RATE).round() as usize).max(1);
+    let rate = sample_rate as f64 / hop as f64;
+    let mut envelope = onset_envelope(samples, hop);
+    let mean = envelope.iter().sum::<f64>() / envelope.len().max(1) as f64;
+    envelope.iter_mut().for_each(|value| *value -= mean);
+
+    // One lag either side of the range, for interpolating at its edges
+    let min_lag = ((rate * 60.0 / MAX_BPM).floor() as usize).max(2) - 1;
+    let max_lag = (rate * 60.0 / MIN_BPM).ceil() as usize + 1;
+    if envelope.len() < max_lag * 4 {
+        return None;
+    }
+    let scores: Vec<f64> = (min_lag..=max_lag)
+        .map(|lag| envelope.iter().zip(&envelope[lag..]).map(|(a, b)| a * b).sum::<f64>() / (envelope.len() - lag) as f64)
+        .collect();
+    let preference = |lag: f64| {
+        let octaves = (60.0 * rate / lag / PREFERRED_BPM).log2() / PREFERENCE_SPREAD;
+        (-0.5 * octaves * octaves).exp()
+    };
+    let (best, _) = (1..scores.len() - 1)
+        .map(|index| (index, scores[index] * preference((min_lag + index) as f64)))
+        .max_by(|a, b| a.1.total_cmp(&b.1))?;
+    if scores[best] <= 0.0 {
+        return None;
+    }
+
+    // The true period usually falls between two lags; fit a parabola through the peak
+    let (before, peak, after) = (scores[best - 1], scores[best], scores[best + 1]);
+    let curvature = before - 2.0 * peak + after;
+    let shift = if curvature.abs() > 1e-12 { (0.5 * (before - after) / curvature).clamp(-0.5, 0.5) } else { 0.0 };
+    let bpm = 60.0 * rate / ((min_lag + best) as f64 + shift);
+    Some(((bpm * 10.0).round() / 10.0) as f32)
+}
+
+/// Decodes the analysis window of `path` and mixes it to mono.
+fn analysis_window(path: &Path, task: &TaskHandle) -> Result<(Vec<f32>, u32), String> {
+    let decoder = open_decoder(path)?;
+    let channels = decoder.channels().max(1) as usize;
+    let sample_rate = decoder.sample_rate();
+    let duration = probe_duration(path).map_or(0.0, |duration| duration.as_secs_f64());
+    let start = ((duration - WINDOW_SECS) / 2.0).clamp(0.0, MAX_WINDOW_START_SECS);
+    let frames = (WINDOW_SECS * sample_rate as f64) as usize;
+
+    let mut mono = Vec::with_capacity(frames);
+    let mut frame = 0.0;
+    let samples = decoder.skip((start * sample_rate as f64) as usize * channels).take(frames * channels);
+    for (index, sample) in samples.enumerate() {
+        frame += sample as f32 / 32768.0;
+        if (index + 1) % channels == 0 {
+            mono.push(frame / channels as f32);
+            frame = 0.0;
+        }
+        if index % (sample_rate as usize * channels) == 0 {
+            if task.is_cancelled() {
+                return Err("Cancelled".to_string());
+            }
+            task.set_progress(mono.len() as u64, frames as u64);
+        }
+    }
+    Ok((mono, sample_rate))
+}
+
+fn analyze(path: &str, write_tag: bool, task: &TaskHandle) -> Result<(f32, bool), String> {
+    let (samples, sample_rate) = analysis_window(Path::new(path), task)?;
+    let bpm = estimate_bpm(&samples, sample_rate).ok_or_else(|| format!("No steady beat found in {}", path))?;
+    if write_tag {
+        let options = MetadataWriteOptions { path: path.to_string(), bpm: Some(bpm), ..MetadataWriteOptions::default() };
+        write_metadata(&options)?;
+    }
+    Ok((bpm, write_tag))
+}
+
+/// Estimates the tempo of `path` from its audio, and with `write_tag` saves
+/// it as the BPM tag. Runs as a background task (cancel it through
+/// `cancel_background_task`) and emits `bpm-analyzed` when done.
+#[tauri::command]
+pub async fn analyze_bpm(app: AppHandle, path: String, write_tag: Option<bool>) -> Result<f32, String> {
+    let task = start_task(&app, TaskKind::Bpm, format!("Analyzing the tempo of {}", path));
+    let result = analyze(&path, write_tag.unwrap_or(false), &task);
+    app.emit("bpm-analyzed", BpmAnalyzed {
+        path: path.clone(),
+        bpm: result.as_ref().ok().map(|(bpm, _)| *bpm),
+        written: result.as_ref().is_ok_and(|(_, written)| *written),
+        error: result.as_ref().err().cloned(),
+    }).ok();
+    task.finish(result.as_ref().map(|_| ()).map_err(|e| e.clone()));
+    let (bpm, _) = result?;
+    info!("Estimated {} at {} BPM", path, bpm);
+    Ok(bpm)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::f32::consts::PI;
+
+    /// A decaying 1 kHz blip on every beat, over a quiet hum.
+    fn click_track(bpm: f32, sample_rate: u32, secs: f32) -> Vec<f32> {
+        let period = 60.0 / bpm;
+        (0..(sample_rate as f32 * secs) as usize)
+            .map(|n| {
+                let t = n as f32 / sample_rate as f32;
+                let since_beat = t % period;
+                let click = (-since_beat * 60.0).exp() * (2.0 * PI * 1000.0 * t).sin();
+                0.8 * click + 0.02 * (2.0 * PI * 110.0 * t).sin()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn finds_the_tempo_of_a_click_track() {
+        for (bpm, sample_rate) in [(128.0, 22_050), (90.0, 44_100), (174.0, 22_050), (72.5, 22_050)] {
+            let estimate = estimate_bpm(&click_track(bpm, sample_rate, 30.0), sample_rate).unwrap();
+            assert!((estimate - bpm).abs() < 1.0, "{} BPM estimated as {}", bpm, estimate);
+        }
+    }
+
+    #[test]
+    fn no_tempo_without_a_beat() {
+        assert_eq!(estimate_bpm(&vec![0.0; 22_050 * 30], 22_050), None);
+        assert_eq!(estimate_bpm(&click_track(120.0, 22_050, 1.0), 22_050), None);
+    }
+}
@@ -0,0 +1,242 @@
+use serde::{Deserialize, Serialize};
+use std::fs::{self, File};
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{channel, RecvTimeoutError, Sender};
+use std::time::{Duration, SystemTime};
+use once_cell::sync::Lazy;
+use parking_lot::Mutex;
+use sha2::{Digest, Sha256};
+use log::{info, error, debug};
+use crate::config::{get_cache_dir, load_player_config};
+
+const EVICTION_INTERVAL: Duration = Duration::from_secs(10 * 60);
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum CacheKind {
+    Metadata,
+    Artwork,
+    Waveforms,
+    Checksums,
+}
+
+pub const ALL_CACHES: [CacheKind; 4] = [
+    CacheKind::Metadata,
+    CacheKind::Artwork,
+    CacheKind::Waveforms,
+    CacheKind::Checksums,
+];
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(default)]
+pub struct CacheLimits {
+    pub metadata_mb: u64,
+    pub artwork_mb: u64,
+    pub waveforms_mb: u64,
+    pub checksums_mb: u64,
+}
+
+impl Default for CacheLimits {
+    fn default() -> Self {
+        Self {
+            metadata_mb: 200,
+            artwork_mb: 500,
+            waveforms_mb: 100,
+            checksums_mb: 50,
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Clone)]
+pub struct CacheStats {
+    pub kind: CacheKind,
+    pub entries: usize,
+    pub bytes: u64,
+    pub limit_bytes: u64,
+}
+
+impl CacheKind {
+    pub fn parse(kind: &str) -> Result<Self, String> {
+        match kind.to_lowercase().as_str() {
+            "metadata" => Ok(CacheKind::Metadata),
+            "artwork" => Ok(CacheKind::Artwork),
+            "waveforms" => Ok(CacheKind::Waveforms),
+            "checksums" => Ok(CacheKind::Checksums),
+            other => Err(format!("Unknown cache kind: {}", other)),
+        }
+    }
+
+    fn dir_name(self) -> &'static str {
+        match self {
+            CacheKind::Metadata => "metadata",
+            CacheKind::Artwork => "artwork",
+            CacheKind::Waveforms => "waveforms",
+            CacheKind::Checksums => "checksums",
+        }
+    }
+
+    fn limit_bytes(self, limits: &CacheLimits) -> u64 {
+        let mb = match self {
+            CacheKind::Metadata => limits.metadata_mb,
+            CacheKind::Artwork => limits.artwork_mb,
+            CacheKind::Waveforms => limits.waveforms_mb,
+            CacheKind::Checksums => limits.checksums_mb,
+        };
+        mb * 1024 * 1024
+    }
+}
+
+pub fn cache_dir(kind: CacheKind) -> Option<PathBuf> {
+    get_cache_dir().map(|dir| dir.join(kind.dir_name()))
+}
+
+/// Hashes the parts that identify an entry (path, mtime, size...) into a file-safe key.
+pub fn cache_key(parts: &[&str]) -> String {
+    let mut hasher = Sha256::new();
+    for part in parts {
+        hasher.update(part.as_bytes());
+        hasher.update([0]);
+    }
+    format!("{:x}", hasher.finalize())
+}
+
+/// Path for an entry, creating the cache directory on first use.
+pub fn entry_path(kind: CacheKind, file_name: &str) -> Option<PathBuf> {
+    let dir = cache_dir(kind)?;
+    fs::create_dir_all(&dir).ok()?;
+    Some(dir.join(file_name))
+}
+
+/// Bumps an entry's mtime, which is what eviction uses as its last-used time.
+/// Access times are unreliable (noatime/relatime mounts), so they aren't used.
+pub fn mark_used(path: &Path) {
+    if let Ok(file) = File::options().append(true).open(path) {
+        let _ = file.set_modified(SystemTime::now());
+    }
+}
+
+pub fn write_entry(kind: CacheKind, file_name: &str, data: &[u8]) -> Result<PathBuf, String> {
+    let path = entry_path(kind, file_name).ok_or("Could not determine cache directory")?;
+    fs::write(&path, data).map_err(|e| e.to_string())?;
+    request_eviction();
+    Ok(path)
+}
+
+fn list_entries(kind: CacheKind) -> Vec<(PathBuf, u64, SystemTime)> {
+    let dir = match cache_dir(kind) {
+        Some(dir) => dir,
+        None => return Vec::new(),
+    };
+
+    let mut entries = Vec::new();
+    if let Ok(read_dir) = fs::read_dir(dir) {
+        for entry in read_dir.flatten() {
+            if let Ok(metadata) = entry.metadata() {
+                if metadata.is_file() {
+                    let used = metadata.modified().unwrap_or(SystemTime::UNIX_EPOCH);
+                    entries.push((entry.path(), metadata.len(), used));
+                }
+            }
+        }
+    }
+    entries
+}
+
+pub fn cache_stats(kind: CacheKind, limits: &CacheLimits) -> CacheStats {
+    let entries = list_entries(kind);
+    CacheStats {
+        kind,
+        entries: entries.len(),
+        bytes: entries.iter().map(|(_, size, _)| size).sum(),
+        limit_bytes: kind.limit_bytes(limits),
+    }
+}
+
+/// Removes least-recently-used entries until the cache fits under its cap.
+fn evict(kind: CacheKind, limit: u64) {
+    let mut entries = list_entries(kind);
+    let mut total: u64 = entries.iter().map(|(_, size, _)| size).sum();
+    if total <= limit {
+        return;
+    }
+
+    entries.sort_by_key(|(_, _, used)| *used);
+    let mut removed = 0;
+    let mut freed = 0;
+    for (path, size, _) in entries {
+        if total <= limit {
+            break;
+        }
+        if fs::remove_file(&path).is_ok() {
+            debug!("Evicted cache entry {}", path.display());
+            total -= size;
+            freed += size;
+            removed += 1;
+        }
+    }
+
+    info!("Evicted {} {} cache entries ({} KB)", removed, kind.dir_name(), freed / 1024);
+}
+
+fn run_eviction_pass() {
+    let limits = load_player_config().cache_limits;
+    for kind in ALL_CACHES {
+        evict(kind, kind.limit_bytes(&limits));
+    }
+}
+
+// Eviction runs on its own thread; callers only ever send it a nudge
+static EVICTION_TRIGGER: Lazy<Mutex<Sender<()>>> = Lazy::new(|| {
+    let (tx, rx) = channel::<()>();
+    std::thread::spawn(move || {
+        // Runs on every nudge and at least once per interval until the sender is dropped
+        while !matches!(rx.recv_timeout(EVICTION_INTERVAL), Err(RecvTimeoutError::Disconnected)) {
+            // Collapse a burst of nudges into a single pass
+            while rx.try_recv().is_ok() {}
+            run_eviction_pass();
+        }
+    });
+    Mutex::new(tx)
+});
+
+pub fn request_eviction() {
+    if EVICTION_TRIGGER.lock().send(()).is_err() {
+        error!("Cache eviction thread is not running");
+    }
+}
+
+#[tauri::command]
+pub fn get_cache_stats() -> Vec<CacheStats> {
+    let limits = load_player_config().cache_limits;
+    ALL_CACHES.iter().map(|kind| cache_stats(*kind, &limits)).collect()
+}
+
+#[tauri::command]
+pub fn clear_cache(kind: String) -> Result<CacheStats, String> {
+    clear(CacheKind::parse(&kind)?)
+}
+
+/// Deletes every entry of `kind` and returns the now-empty cache's stats.
+pub fn clear(kind: CacheKind) -> Result<CacheStats, String> {
+    if let Some(dir) = cache_dir(kind) {
+        if dir.exists() {
+            fs::remove_dir_all(&dir).map_err(|e| format!("Failed to clear cache: {}", e))?;
+        }
+    }
+
+    info!("Cleared {} cache", kind.dir_name());
+    Ok(cache_stats(kind, &load_player_config().cache_limits))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn missing_limits_keep_their_defaults() {
+        let limits: CacheLimits = serde_json::from_str(r#"{"artwork_mb": 50}"#).unwrap();
+        assert_eq!((limits.metadata_mb, limits.artwork_mb, limits.waveforms_mb, limits.checksums_mb), (200, 50, 100, 50));
+        let config: crate::config::AppConfig = serde_json::from_str(r#"{"max_recent_locations": 3, "cache_limits": {"waveforms_mb": 10}}"#).unwrap();
+        assert_eq!((config.max_recent_locations, config.cache_limits.waveforms_mb), (3, 10));
+    }
+}
@@ -0,0 +1,193 @@
+//! Stereo balance and mono downmix on the main playback chain. Like the
+//! equalizer, the mixing source reads shared settings, so a change is heard
+//! on the playing track straight away.
+
+use serde::Serialize;
+use std::sync::atomic::{AtomicBool, AtomicU32, Ordering};
+use std::time::Duration;
+use once_cell::sync::Lazy;
+use rodio::source::SeekError;
+use rodio::Source;
+use crate::config::{load_player_config, update_player_config};
+
+struct MixParams {
+    // f32 bits, -1.0 (left only) to 1.0 (right only)
+    balance: AtomicU32,
+    mono: AtomicBool,
+}
+
+static PARAMS: Lazy<MixParams> = Lazy::new(|| {
+    let settings = load_player_config().playback_settings;
+    MixParams {
+        balance: AtomicU32::new(settings.effective_balance().to_bits()),
+        mono: AtomicBool::new(settings.mono),
+    }
+});
+
+fn balance() -> f32 {
+    f32::from_bits(PARAMS.balance.load(Ordering::Relaxed))
+}
+
+/// Left and right gains for `balance`: the side it leans towards stays at full
+/// level and the other is turned down.
+fn balance_gains(balance: f32) -> (f32, f32) {
+    ((1.0 - balance).min(1.0), (1.0 + balance).min(1.0))
+}
+
+/// Mixes one interleaved frame in place: averages every channel into all of
+/// them when `mono` is set, then applies the balance to the first two.
+fn mix_frame(frame: &mut [i16], balance: f32, mono: bool) {
+    if mono && frame.len() > 1 {
+        let average = frame.iter().map(|sample| *sample as i32).sum::<i32>() / frame.len() as i32;
+        frame.fill(average as i16);
+    }
+    if frame.len() >= 2 && balance != 0.0 {
+        let (left, right) = balance_gains(balance);
+        frame[0] = (frame[0] as f32 * left) as i16;
+        frame[1] = (frame[1] as f32 * right) as i16;
+    }
+}
+
+/// Applies the shared balance and mono settings to `inner`, a frame at a time.
+pub struct ChannelMix<S> {
+    inner: S,
+    frame: Vec<i16>,
+    next: usize,
+}
+
+impl<S: Source<Item = i16>> ChannelMix<S> {
+    pub fn new(inner: S) -> Self {
+        Self { inner, frame: Vec::new(), next: 0 }
+    }
+}
+
+impl<S: Source<Item = i16>> Iterator for ChannelMix<S> {
+    type Item = i16;
+
+    fn next(&mut self) -> Option<i16> {
+        if self.next >= self.frame.len() {
+            // Mono input has nothing to mix, so it skips the frame buffer
+            let channels = self.inner.channels() as usize;
+            if channels < 2 {
+                return self.inner.next();
+            }
+            self.frame.clear();
+            self.frame.extend(self.inner.by_ref().take(channels));
+            if self.frame.is_empty() {
+                return None;
+            }
+            mix_frame(&mut self.frame, balance(), PARAMS.mono.load(Ordering::Relaxed));
+            self.next = 0;
+        }
+        let sample = self.frame[self.next];
+        self.next += 1;
+        Some(sample)
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        let buffered = self.frame.len() - self.next;
+        let (low, high) = self.inner.size_hint();
+        (low + buffered, high.map(|high| high + buffered))
+    }
+}
+
+impl<S: Source<Item = i16>> Source for ChannelMix<S> {
+    fn current_frame_len(&self) -> Option<usize> {
+        self.inner.current_frame_len()
+    }
+
+    fn channels(&self) -> u16 {
+        self.inner.channels()
+    }
+
+    fn sample_rate(&self) -> u32 {
+        self.inner.sample_rate()
+    }
+
+    fn total_duration(&self) -> Option<Duration> {
+        self.inner.total_duration()
+    }
+
+    fn try_seek(&mut self, position: Duration) -> Result<(), SeekError> {
+        self.inner.try_seek(position)?;
+        self.frame.clear();
+        self.next = 0;
+        Ok(())
+    }
+}
+
+#[derive(Debug, Serialize, Clone)]
+pub struct ChannelMixSettings {
+    pub balance: f32,
+    pub mono: bool,
+}
+
+#[tauri::command]
+pub fn get_channel_mix() -> ChannelMixSettings {
+    ChannelMixSettings { balance: balance(), mono: PARAMS.mono.load(Ordering::Relaxed) }
+}
+
+/// Sets the balance from -1.0 (left only) to 1.0 (right only); 0 is centred.
+#[tauri::command]
+pub fn set_balance(value: f32) -> Result<(), String> {
+    if !value.is_finite() || !(-1.0..=1.0).contains(&value) {
+        return Err("Balance must be between -1.0 and 1.0".to_string());
+    }
+    update_player_config(|config| {
+        config.playback_settings.balance = value;
+        Ok(())
+    })?;
+    PARAMS.balance.store(value.to_bits(), Ordering::Relaxed);
+    Ok(())
+}
+
+/// Plays every channel mixed together, for listening on a single earbud.
+#[tauri::command]
+pub fn set_mono(enabled: bool) -> Result<(), String> {
+    update_player_config(|config| {
+        config.playback_settings.mono = enabled;
+        Ok(())
+    })?;
+    PARAMS.mono.store(enabled, Ordering::Relaxed);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rodio::buffer::SamplesBuffer;
+
+    #[test]
+    fn balance_turns_down_the_far_side_only() {
+        assert_eq!(balance_gains(0.0), (1.0, 1.0));
+        assert_eq!(balance_gains(-1.0), (1.0, 0.0));
+        assert_eq!(balance_gains(0.5), (0.5, 1.0));
+    }
+
+    #[test]
+    fn mono_averages_before_the_balance_is_applied() {
+        let mut frame = [1000, 3000];
+        mix_frame(&mut frame, 0.0, true);
+        assert_eq!(frame, [2000, 2000]);
+
+        let mut frame = [1000, 3000];
+        mix_frame(&mut frame, 1.0, true);
+        assert_eq!(frame, [0, 2000]);
+
+        let mut surround = [600, 0, 0, 0, 0, 0];
+        mix_frame(&mut surround, 0.0, true);
+        assert_eq!(surround, [100; 6]);
+
+        let mut single = [1234];
+        mix_frame(&mut single, -1.0, true);
+        assert_eq!(single, [1234]);
+    }
+
+    #[test]
+    fn centred_stereo_passes_through_frame_by_frame() {
+        crate::test_support::isolate_config();
+        let samples = vec![1i16, 2, 3, 4, 5, 6];
+        let source = ChannelMix::new(SamplesBuffer::new(2, 44_100, samples.clone()));
+        assert_eq!(source.collect::<Vec<_>>(), samples);
+    }
+}
@@ -1,43 +1,78 @@
-use rodio::{Decoder, OutputStream, Sink, Source};
-use std::fs::File;
+use serde::{Deserialize, Serialize};
+use rodio::{source::SeekError, Sink};
+use tauri::{Emitter, Manager, State};
 use std::path::Path;
 use std::fs;
-use std::io::BufReader;
 use std::path::PathBuf;
-use crate::{FileItem, load_config, save_config, PLAYER};
+use crate::{FileItem, PlaybackStatus, PlayedLocation, PlayerHandle, PlayerState, load_config, update_config};
 use std::sync::Arc;
 use parking_lot::Mutex;
 use lazy_static::lazy_static;
 use std::time::Duration;
-use crate::config::{load_player_config, save_player_config, AppConfig};
-use std::collections::VecDeque;
-use std::io::Read;
+use crate::config::{load_player_config, update_player_config, set_folder_view_override, view_settings_for, AppConfig, RepeatMode, ShuffleMode, ViewSettings, MAX_PLAYBACK_SPEED, MIN_PLAYBACK_SPEED};
+use crate::queue::{append_to_queue, clear_upcoming, enqueue_track, queued_track, skip_to_next, with_queue, QueuedTrack, TrackChanged};
+use crate::crossfade::cut;
+use crate::ramp::{fade_in, fade_out, volume_changed, FadeEnd};
+use crate::volume::{gain_to_db, gain_to_slider, save_playback_levels_soon, slider_to_gain};
 use lofty::{
     config::WriteOptions,
     prelude::{AudioFile, TaggedFileExt},
     probe::Probe,
     tag::{Tag, TagType, Accessor, ItemKey},
 };
-use crate::metadata::{MetadataWriteOptions, write_audio_metadata};
+use crate::metadata::{MetadataWriteOptions, write_audio_metadata, write_metadata};
+use crate::favorites::FavoriteEntry;
+use crate::paths::same_location;
+use crate::relocate::{guard_playing_track, FileOpError};
+use crate::sorting::{fill_added_times, natural_cmp, sort_entries, DirSort, SortableEntry};
+use crate::device::removable_device_name;
+use log::{error, info};
+use crate::journal::Transaction;
+use crate::library::{added_at, added_times, now_secs, prune_excluded_tracks, track_info, LibraryTrack};
+use crate::metadata::SortOption;
+use crate::text::collate;
+use crate::playlist::{resolve_playlist, ExtInf, Playlist};
+use crate::tasks::{start_task, TaskHandle, TaskKind};
+use crate::requests::{begin_request, RequestError, RequestToken};
+use crate::prefetch::take_or_open;
+use crate::resume::{remember_current_position, resume_start};
+use crate::output_format::{open_source_at, OpenedSource, PlaybackError};
+use crate::output::new_sink;
+use crate::replaygain::track_gain;
+use crate::ab_loop::LoopRegion;
+use crate::cue::{load_cue_sheet, CueRegion};
+use crate::stretch::{preserves_pitch, set_stretch};
+use crate::extensions::sniff_file;
+use crate::silence::trim_points;
+use crate::play_stats::begin_play;
+use crate::pitch;
+use crate::walker::{check_readable, emit_skipped, walk_files_reporting, Exclusions, SkippedPath};
 
 lazy_static! {
     static ref CURRENT_SINK: Mutex<Option<Arc<Sink>>> = Mutex::new(None);
+    // Tags of the track last asked about by `get_now_playing`, with its path and CUE track
+    static ref NOW_PLAYING_TAGS: Mutex<Option<(String, Option<usize>, TrackTags)>> = Mutex::new(None);
 }
 
 #[tauri::command]
-pub async fn change_file_folder_name(path: String, new_folder_name: String) -> Result<(), String> {
+pub async fn change_file_folder_name(
+    app: tauri::AppHandle,
+    path: String,
+    new_folder_name: String,
+    relocate_playing: Option<bool>,
+) -> Result<(), FileOpError> {
     println!("Changing file folder name: {} to {}", path, new_folder_name);
     let path = Path::new(&path);
     
     // Get the parent directory
     let parent_dir = path.parent()
-        .ok_or_else(|| "Could not get parent directory".to_string())?;
+        .ok_or_else(|| FileOpError::Failed("Could not get parent directory".to_string()))?;
     
     // If it's a file, preserve the extension
     let new_name = if path.is_file() {
         let extension = path.extension()
             .and_then(|ext| ext.to_str())
-            .ok_or_else(|| "Could not get file extension".to_string())?;
+            .ok_or_else(|| FileOpError::Failed("Could not get file extension".to_string()))?;
         
         // If the new name already has the correct extension, use it as is
         if new_folder_name.ends_with(&format!(".{}", extension)) {
@@ -56,33 +91,38 @@ pub async fn change_file_folder_name(path: String, new_folder_name: String) -> R
     println!("New folder path: {}", new_folder_path.to_string_lossy());
     println!("Original path: {}", path.to_string_lossy());
     
-    fs::rename(path, new_folder_path)
-        .map_err(|e| format!("Failed to rename file: {}", e))?;
-    Ok(())
+    guard_playing_track(&app, path, &new_folder_path, relocate_playing.unwrap_or(false), || {
+        fs::rename(path, &new_folder_path).map_err(|e| format!("Failed to rename file: {}", e))
+    })
 }
 
 #[tauri::command]
-pub async fn move_file(source_path: String, target_path: String) -> Result<(), String> {
-    let source = Path::new(&source_path);
-    let target = Path::new(&target_path);
+pub async fn move_file(
+    app: tauri::AppHandle,
+    source_path: String,
+    target_path: String,
+    relocate_playing: Option<bool>,
+) -> Result<(), FileOpError> {
+    move_into_folder(&app, Path::new(&source_path), Path::new(&target_path), relocate_playing.unwrap_or(false))
+}
 
+fn move_into_folder(app: &tauri::AppHandle, source: &Path, target: &Path, relocate_playing: bool) -> Result<(), FileOpError> {
     if !source.exists() {
-        return Err("Source file does not exist".to_string());
+        return Err(FileOpError::Failed("Source file does not exist".to_string()));
     }
 
     if !target.is_dir() {
-        return Err("Target must be a directory".to_string());
+        return Err(FileOpError::Failed("Target must be a directory".to_string()));
     }
 
     let file_name = source.file_name()
-        .ok_or_else(|| "Invalid source file name".to_string())?;
+        .ok_or_else(|| FileOpError::Failed("Invalid source file name".to_string()))?;
     
     let target_file = target.join(file_name);
 
-    fs::rename(source, target_file)
-        .map_err(|e| format!("Failed to move file: {}", e))?;
-
-    Ok(())
+    guard_playing_track(app, source, &target_file, relocate_playing, || {
+        fs::rename(source, &target_file).map_err(|e| format!("Failed to move file: {}", e))
+    })
 }
 
 #[tauri::command]
@@ -120,16 +160,24 @@ pub async fn combine_files(
 }
 
 #[tauri::command]
-pub async fn read_dir(path: String) -> Result<Vec<FileItem>, String> {
-    let path = PathBuf::from(path);
+pub async fn read_dir(path: String, sort: Option<String>, descending: Option<bool>, request_id: Option<String>) -> Result<Vec<FileItem>, RequestError> {
+    let request = begin_request("read_dir", &path, request_id);
+    let sort = DirSort::parse(sort.as_deref())?;
+    list_dir(Path::new(&path), sort, descending.unwrap_or(false), added_at, &request)
+}
+
+/// The entries of `path` for `read_dir`, with date-added from `added_at`.
+fn list_dir(path: &Path, sort: DirSort, descending: bool, added_at: impl Fn(&str) -> Option<u64>, request: &RequestToken) -> Result<Vec<FileItem>, RequestError> {
     let mut entries = Vec::new();
 
-    let read_dir = match std::fs::read_dir(&path) {
+    let exclusions = Exclusions::load();
+    let read_dir = match std::fs::read_dir(path) {
         Ok(dir) => dir,
-        Err(e) => return Err(e.to_string()),
+        Err(e) => return Err(SkippedPath::new(path, &e).into()),
     };
 
     for entry in read_dir {
+        request.check()?;
         if let Ok(entry) = entry {
             let path_str = entry.path().to_string_lossy().to_string();
             let metadata = entry.metadata().map_err(|e| e.to_string())?;
@@ -146,22 +194,28 @@ pub async fn read_dir(path: String) -> Result<Vec<FileItem>, String> {
                 false
             };
 
-            entries.push(FileItem {
+            // Excluded entries are still listed so the UI can show them grayed out
+            let excluded = if metadata.is_dir() {
+                exclusions.is_excluded_dir(&entry.path())
+            } else {
+                exclusions.is_excluded_file(&entry.path())
+            };
+
+            entries.push(SortableEntry::new(FileItem {
                 name,
                 path: path_str,
                 is_dir: metadata.is_dir(),
                 is_audio,
-            });
+                modified: None,
+                added_at: None,
+                excluded,
+                missing: false,
+            }, &metadata));
         }
     }
 
-    entries.sort_by(|a, b| match (a.is_dir, b.is_dir) {
-        (true, false) => std::cmp::Ordering::Less,
-        (false, true) => std::cmp::Ordering::Greater,
-        _ => a.name.to_lowercase().cmp(&b.name.to_lowercase()),
-    });
-
-    Ok(entries)
+    fill_added_times(&mut entries, added_at);
+    Ok(sort_entries(entries, sort, descending))
 }
 
 #[tauri::command]
@@ -172,33 +226,36 @@ pub fn home_dir() -> Result<String, String> {
 }
 
 #[tauri::command]
-pub fn add_favorite_location(path: String) -> Result<Vec<String>, String> {
-    let mut config = load_config();
-    if !config.favorite_locations.contains(&path) {
-        config.favorite_locations.push(path);
-        save_config(&config)?;
-    }
-    Ok(config.favorite_locations)
+pub fn add_favorite_location(path: String, label: Option<String>) -> Result<Vec<FavoriteEntry>, String> {
+    update_config(|config| {
+        if !config.favorite_locations.iter().any(|x| same_location(&x.path, &path)) {
+            config.favorite_locations.push(FavoriteEntry::for_path(path, label)?);
+        }
+        Ok(config.favorite_locations.clone())
+    })
 }
 
 #[tauri::command]
-pub fn remove_favorite_location(path: String) -> Result<Vec<String>, String> {
-    let mut config = load_config();
-    config.favorite_locations.retain(|x| x != &path);
-    save_config(&config)?;
-    Ok(config.favorite_locations)
+pub fn remove_favorite_location(path: String) -> Result<Vec<FavoriteEntry>, String> {
+    update_config(|config| {
+        config.favorite_locations.retain(|x| !same_location(&x.path, &path));
+        Ok(config.favorite_locations.clone())
+    })
 }
 
 #[tauri::command]
-pub fn get_favorite_locations() -> Result<Vec<String>, String> {
+pub fn get_favorite_locations() -> Result<Vec<FavoriteEntry>, String> {
     Ok(load_config().favorite_locations)
 }
 
 #[tauri::command]
 pub fn set_default_location(path: String) -> Result<(), String> {
-    let mut config = load_config();
-    config.default_location = Some(path);
-    save_config(&config)
+    update_config(|config| {
+        if !config.default_location.as_deref().is_some_and(|current| same_location(current, &path)) {
+            config.default_location = Some(path);
+        }
+        Ok(())
+    })
 }
 
 #[tauri::command]
@@ -208,18 +265,15 @@ pub fn get_default_location() -> Option<String> {
 
 #[tauri::command]
 pub fn add_recent_location(path: String) -> Result<Vec<String>, String> {
-    let mut config = load_config();
-    if let Some(pos) = config.recent_locations.iter().position(|x| x == &path) {
-        config.recent_locations.remove(pos);
-    }
-    config.recent_locations.insert(0, path);
+    update_config(|config| {
+        config.recent_locations.retain(|x| !same_location(x, &path));
+        config.recent_locations.insert(0, path);
 
-    if config.recent_locations.len() > config.max_recent_locations {
-        config.recent_locations.truncate(config.max_recent_locations);
-    }
-
-    save_config(&config)?;
-    Ok(config.recent_locations)
+        if config.recent_locations.len() > config.max_recent_locations {
+            config.recent_locations.truncate(config.max_recent_locations);
+        }
+        Ok(config.recent_locations.clone())
+    })
 }
 
 #[tauri::command]
@@ -227,167 +281,578 @@ pub fn get_recent_locations() -> Result<Vec<String>, String> {
     Ok(load_config().recent_locations)
 }
 
+/// Records the folder of a file that started playing. Runs off the playback
+/// path since looking up the device can touch slow mounts.
+pub fn record_played_location(file_path: &str) {
+    let folder = match Path::new(file_path).parent() {
+        Some(folder) => folder.to_path_buf(),
+        None => return,
+    };
+    let folder_str = folder.to_string_lossy().to_string();
+
+    std::thread::spawn(move || {
+        let device_name = removable_device_name(&folder);
+        let result = update_config(|config| {
+            config.recently_played.retain(|entry| !same_location(&entry.path, &folder_str));
+            config.recently_played.insert(0, PlayedLocation {
+                path: folder_str,
+                device_name,
+                played_at: now_secs(),
+            });
+            config.recently_played.truncate(config.max_recently_played);
+            Ok(())
+        });
+        if let Err(e) = result {
+            error!("Failed to record played location: {}", e);
+        }
+    });
+}
+
+#[tauri::command]
+pub fn get_recently_played_locations() -> Result<Vec<PlayedLocation>, String> {
+    Ok(load_config().recently_played)
+}
+
 #[tauri::command]
-pub fn play_audio(path: &str) -> Result<(), String> {
-    let mut player = PLAYER.lock();
+pub fn play_audio(player: State<'_, PlayerHandle>, path: &str) -> Result<(), PlaybackError> {
+    start_playback(&player, path, false)
+}
+
+/// Plays `path` starting `secs` in. The start is reached by decoding past it
+/// rather than seeking, so it works for any format, as when playing one track
+/// out of a single-file album rip.
+#[tauri::command]
+pub fn play_audio_from(player: State<'_, PlayerHandle>, path: &str, secs: f32) -> Result<(), PlaybackError> {
+    if !secs.is_finite() || secs < 0.0 {
+        return Err("Start position must be zero or more seconds".to_string().into());
+    }
+    start_playback_at(&player, path, false, Some(Duration::from_secs_f32(secs)))
+}
+
+/// Replaces whatever is playing with `path`. With `fade_in` the new sink starts
+/// silent, for the crossfade to bring up.
+pub fn start_playback(player: &PlayerHandle, path: &str, fade_in: bool) -> Result<(), PlaybackError> {
+    start_playback_at(player, path, fade_in, None)
+}
+
+/// Like `start_playback`, but from `start` when given instead of the file's
+/// bookmark.
+pub fn start_playback_at(player: &PlayerHandle, path: &str, fade_in: bool, start: Option<Duration>) -> Result<(), PlaybackError> {
+    open_playback(player, path, fade_in, start, false)
+}
+
+/// Opens `path` again in place of the current track, as when moving it to
+/// another device or following it to a new location. It isn't a new play: the
+/// play count, history, loop and CUE track are left as they are.
+pub fn reopen_playback(player: &PlayerHandle, path: &str) -> Result<(), PlaybackError> {
+    open_playback(player, path, false, None, true)
+}
+
+fn open_playback(player: &PlayerHandle, path: &str, fade_in: bool, start: Option<Duration>, reopening: bool) -> Result<(), PlaybackError> {
+    remember_current_position(player);
+    let track = open_track(path, start)?;
+    player.with(move |player| play_opened(player, track, fade_in, reopening))?;
+    record_played_location(path);
+    Ok(())
+}
+
+/// A track decoded up to where it starts, ready to hand to the playback thread.
+pub struct OpenedTrack {
+    path: String,
+    opened: OpenedSource,
+    replay_gain: f32,
+    trim_end: Option<Duration>,
+    // From the file's bookmark, when no start was asked for
+    resume: Option<Duration>,
+}
+
+/// Opens `path` from `start`, or from its bookmark without one. Done before
+/// going to the playback thread, so commands behind it aren't held up by the disk.
+pub fn open_track(path: &str, start: Option<Duration>) -> Result<OpenedTrack, PlaybackError> {
+    // The next queue track has usually been opened already
+    let (opened, replay_gain) = match start {
+        None => take_or_open(path)?,
+        Some(start) => (open_source_at(path, start)?, track_gain(path)),
+    };
+    if let Some((start, duration)) = start.zip(opened.duration).filter(|(start, duration)| start >= duration) {
+        return Err(format!("Can't start at {:.1}s, {} is only {:.1}s long", start.as_secs_f32(), path, duration.as_secs_f32()).into());
+    }
+    Ok(OpenedTrack {
+        path: path.to_string(),
+        opened,
+        replay_gain,
+        trim_end: trim_points(path).and_then(|trim| trim.end()),
+        resume: resume_start(path).filter(|_| start.is_none()),
+    })
+}
+
+/// Replaces whatever is playing with `track`, on the playback thread.
+pub fn play_opened(player: &mut PlayerState, track: OpenedTrack, fade_in: bool, reopening: bool) -> Result<(), PlaybackError> {
+    let OpenedTrack { path, opened, replay_gain, trim_end, resume } = track;
+    cut(player);
+    player.replay_gain = replay_gain;
     
-    // Create new stream and sink
-    let (stream, handle) = OutputStream::try_default()
-        .map_err(|e| e.to_string())?;
-    let sink = Sink::try_new(&handle)
-        .map_err(|e| e.to_string())?;
+    // A new sink on the output, which stays open from track to track
+    let sink = new_sink(player)?;
     
     // Set the volume to the current volume level before playing
-    sink.set_volume(player.volume);
-    
-    // Load and play the file
-    let file = BufReader::new(File::open(path).map_err(|e| e.to_string())?);
-    let source = Decoder::new(file).map_err(|e| e.to_string())?;
-    
-    // Get duration before consuming the source
-    let duration = source.total_duration();
+    sink.set_volume(if fade_in { 0.0 } else { player.output_gain() });
+    sink.set_speed(player.sink_speed());
     
+    let OpenedSource { source, duration, duration_approximate, clock } = opened;
     sink.append(source);
-    player.stream = Some((stream, Arc::new(sink)));
-    player.current_path = Some(path.to_string());
+    if let Some(resume) = resume {
+        if let Err(e) = sink.try_seek(resume.div_f32(player.sink_speed())) {
+            error!("Failed to resume {} at {:?}: {}", path, resume, e);
+        }
+    }
+    player.replace_sink(Some(Arc::new(sink)));
+    player.preloaded = None;
+    player.clock = clock;
+    if reopening {
+        // Not left behind onto the history, since it is still the track playing
+        player.current_path = None;
+    } else {
+        player.loop_region = None;
+        player.cue_track = None;
+        begin_play(&path);
+        pitch::track_changed();
+    }
+    player.trim_end = trim_end;
+    player.leave_current();
+    player.current_path = Some(path);
     player.is_playing = true;
     player.duration = duration;  // Store the duration
-    
+    player.duration_approximate = duration_approximate;
+    player.generation += 1;
     Ok(())
 }
 
 #[tauri::command]
-pub fn pause_audio() -> Result<(), String> {
-    let mut player = PLAYER.lock();
-    if let Some((_, sink)) = &player.stream {
-        sink.pause();
-    }
-    player.is_playing = false;
+pub fn pause_audio(player: State<'_, PlayerHandle>) -> Result<(), String> {
+    remember_current_position(&player);
+    player.with(|player| {
+        cut(player);
+        player.is_playing = false;
+        if let Some(sink) = player.sink.clone() {
+            fade_out(player, sink, FadeEnd::Pause);
+        }
+    });
     Ok(())
 }
 
 #[tauri::command]
-pub fn resume_audio() -> Result<(), String> {
-    let mut player = PLAYER.lock();
-    if let Some((_, sink)) = &player.stream {
-        sink.play();
-    }
-    player.is_playing = true;
+pub fn resume_audio(player: State<'_, PlayerHandle>) -> Result<(), String> {
+    player.with(|player| {
+        player.is_playing = true;
+        // A stopped track can't be resumed
+        let sink = player.sink.as_ref()
+            .filter(|_| player.current_path.is_some())
+            .cloned();
+        if let Some(sink) = sink {
+            fade_in(player, sink);
+        }
+    });
     Ok(())
 }
 
 #[tauri::command]
-pub fn stop_audio() -> Result<(), String> {
-    let mut player = PLAYER.lock();
-    if let Some((_, sink)) = &player.stream {
-        sink.stop();
-        player.current_path = None;
-    }
-    player.is_playing = false;
+pub fn stop_audio(player: State<'_, PlayerHandle>) -> Result<(), String> {
+    remember_current_position(&player);
+    player.with(|player| {
+        cut(player);
+        if player.sink.is_some() {
+            player.current_path = None;
+        }
+        player.loop_region = None;
+        player.cue_track = None;
+        player.preloaded = None;
+        player.is_playing = false;
+        player.generation += 1;
+        if let Some(sink) = player.sink.clone() {
+            fade_out(player, sink, FadeEnd::Stop);
+        }
+    });
+    // Nothing stale should linger in the OS overlay
+    #[cfg(any(target_os = "windows", target_os = "macos"))]
+    crate::media_session::clear();
     Ok(())
 }
 
-#[tauri::command]
-pub fn set_volume(volume: f32) -> Result<(), String> {
-    let mut player = PLAYER.lock();
-    player.volume = volume;
-    
-    if let Some((_, sink)) = &player.stream {
-        sink.set_volume(volume);
-    }
-    
+/// Sets the linear gain of the main output.
+pub fn apply_gain(player: &PlayerHandle, gain: f32) -> Result<(), String> {
+    save_playback_levels_soon(player);
+    player.with(move |player| {
+        player.volume = gain.clamp(0.0, 1.0);
+        volume_changed();
+        if let Some(sink) = &player.sink {
+            sink.set_volume(player.output_gain());
+        }
+    });
     Ok(())
 }
 
+/// Mutes or unmutes the main output, returning whether it is now muted. The
+/// volume is kept, so unmuting restores it exactly.
 #[tauri::command]
-pub fn get_current_track() -> Option<String> {
-    PLAYER.lock().current_path.clone()
+pub fn toggle_mute(player: State<'_, PlayerHandle>) -> bool {
+    player.with(|player| {
+        player.muted = !player.muted;
+        volume_changed();
+        if let Some(sink) = &player.sink {
+            sink.set_volume(player.output_gain());
+        }
+        if let Some(sink) = player.fading.as_ref().filter(|_| player.muted) {
+            sink.set_volume(0.0);
+        }
+        player.muted
+    })
 }
 
 #[tauri::command]
-pub fn get_track_position() -> f32 {
-    let player = PLAYER.lock();
-    // println!("Getting track position...");
-    if let Some((_, sink)) = &player.stream {
-        let position = sink.get_pos().as_secs_f32();
-        // println!("Current track position: {} seconds", position);
-        position
-    } else {
-        // println!("No active stream found, returning 0.0");
-        0.0
-    }
+pub fn is_muted(player: State<'_, PlayerHandle>) -> bool {
+    player.with(|player| player.muted)
 }
 
+/// `volume` is a 0.0-1.0 slider position, mapped to gain by the configured volume scale.
 #[tauri::command]
-pub fn get_track_duration() -> f32 {
-    let player = PLAYER.lock();
-    player.duration.map(|d| d.as_secs_f32()).unwrap_or(0.0)
+pub fn set_volume(player: State<'_, PlayerHandle>, volume: f32) -> Result<(), String> {
+    let scale = load_player_config().playback_settings.volume_scale;
+    apply_gain(&player, slider_to_gain(volume, scale))
 }
 
 #[tauri::command]
-pub fn get_playback_speed() -> f32 {
-    let player = PLAYER.lock();
-    if let Some((_, sink)) = &player.stream {
-        sink.speed()
-    } else {
-        1.0
+pub fn get_track_position(player: State<'_, PlayerHandle>) -> f32 {
+    player.with(|player| player.track_position().as_secs_f32())
+}
+
+#[tauri::command]
+pub fn get_track_duration(player: State<'_, PlayerHandle>) -> f32 {
+    player.with(|player| player.track_duration().map(|d| d.as_secs_f32()).unwrap_or(0.0))
+}
+
+#[derive(serde::Serialize, Clone)]
+pub struct PlayerStateSnapshot {
+    pub current_path: Option<String>,
+    pub is_playing: bool,
+    pub status: PlaybackStatus,
+    // Slider position in the configured scale, as accepted by `set_volume`
+    pub volume: f32,
+    pub volume_db: f32,
+    pub muted: bool,
+    // ReplayGain applied to the current track on top of the volume; 0 when none
+    pub replay_gain_db: f32,
+    pub loop_region: Option<LoopRegion>,
+    pub stop_after_current: bool,
+    // Position and duration are relative to this CUE track when set
+    pub cue_track: Option<CueRegion>,
+    pub position: f32,
+    pub duration: f32,
+    // The duration was estimated from the bitrate, so the UI can mark it "~"
+    pub duration_approximate: bool,
+    pub shuffle_mode: ShuffleMode,
+    pub repeat_mode: RepeatMode,
+    pub queue_position: usize,
+    pub queue_length: usize,
+}
+
+#[tauri::command]
+pub fn get_player_state(player: State<'_, PlayerHandle>) -> PlayerStateSnapshot {
+    player_snapshot(&player)
+}
+
+/// The queue's shuffle and repeat modes. An empty queue hasn't picked a mode
+/// yet, so it reports the saved settings.
+pub fn playback_modes() -> (ShuffleMode, RepeatMode) {
+    let modes = with_queue(|queue| (!queue.tracks.is_empty()).then_some((queue.shuffle_mode, queue.repeat_mode)));
+    modes.unwrap_or_else(|| {
+        let settings = load_player_config().playback_settings;
+        (settings.effective_shuffle_mode(), settings.repeat_mode)
+    })
+}
+
+/// What `get_player_state` returns.
+pub fn player_snapshot(player: &PlayerHandle) -> PlayerStateSnapshot {
+    let (queue_position, queue_length) = with_queue(|queue| (queue.position, queue.tracks.len()));
+    let (shuffle_mode, repeat_mode) = playback_modes();
+
+    let volume_scale = load_player_config().playback_settings.volume_scale;
+
+    player.with(move |player| PlayerStateSnapshot {
+        current_path: player.current_path.clone(),
+        is_playing: player.is_playing,
+        status: player.status(),
+        volume: gain_to_slider(player.volume, volume_scale),
+        volume_db: gain_to_db(player.volume),
+        muted: player.muted,
+        replay_gain_db: 20.0 * player.replay_gain.log10(),
+        loop_region: player.loop_region,
+        stop_after_current: player.stop_after_current,
+        cue_track: player.cue_track.clone(),
+        position: player.track_position().as_secs_f32(),
+        duration: player.track_duration().map(|d| d.as_secs_f32()).unwrap_or(0.0),
+        duration_approximate: player.duration_approximate,
+        shuffle_mode,
+        repeat_mode,
+        queue_position,
+        queue_length,
+    })
+}
+
+/// Sends the current `get_player_state` snapshot as `playback-state`, for
+/// changes the UI didn't make itself.
+pub fn emit_playback_state(app: &tauri::AppHandle) {
+    app.emit("playback-state", player_snapshot(&app.state())).ok();
+}
+
+#[derive(Debug, Serialize, Clone, Default, PartialEq)]
+pub struct TrackTags {
+    pub title: Option<String>,
+    pub artist: Option<String>,
+    pub album: Option<String>,
+}
+
+impl TrackTags {
+    /// The file's tags, or for a CUE track the names its sheet gives it.
+    fn read(path: &str, cue: Option<&CueRegion>) -> Self {
+        let track = track_info(Path::new(path));
+        let mut tags = Self {
+            title: track.as_ref().and_then(|track| track.title.clone()),
+            artist: track.as_ref().and_then(|track| track.artist.clone()),
+            album: track.and_then(|track| track.album),
+        };
+        let sheet = cue.and_then(|cue| Some((load_cue_sheet(&cue.cue_path).ok()?, cue.index)));
+        if let Some((sheet, index)) = sheet {
+            let cue_track = sheet.tracks.get(index);
+            tags.title = cue_track.and_then(|track| track.title.clone()).or(tags.title);
+            tags.artist = cue_track.and_then(|track| track.performer.clone()).or(sheet.performer).or(tags.artist);
+            tags.album = sheet.title.or(tags.album);
+        }
+        tags
     }
 }
 
+/// Tags for the track playing, read once per track rather than on every poll.
+fn now_playing_tags(path: &str, cue: Option<&CueRegion>) -> TrackTags {
+    let cue_index = cue.map(|cue| cue.index);
+    let mut cached = NOW_PLAYING_TAGS.lock();
+    match cached.as_ref() {
+        Some((cached_path, cached_index, tags)) if cached_path == path && *cached_index == cue_index => tags.clone(),
+        _ => {
+            let tags = TrackTags::read(path, cue);
+            *cached = Some((path.to_string(), cue_index, tags.clone()));
+            tags
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Clone)]
+pub struct NowPlaying {
+    pub path: String,
+    #[serde(flatten)]
+    pub tags: TrackTags,
+    // Relative to the CUE track when one is playing
+    pub position: f32,
+    pub duration: f32,
+    pub duration_approximate: bool,
+    pub is_playing: bool,
+    pub status: PlaybackStatus,
+    // Slider position in the configured scale, as accepted by `set_volume`
+    pub volume: f32,
+    pub muted: bool,
+    pub speed: f32,
+    pub shuffle_mode: ShuffleMode,
+    pub repeat_mode: RepeatMode,
+    pub queue_position: usize,
+    pub queue_length: usize,
+}
+
+/// Everything the player bar shows, in one call; None when nothing is loaded.
 #[tauri::command]
-pub fn set_playback_speed(speed: f32) -> Result<(), String> {
-    let player = PLAYER.lock();
-    if let Some((_, sink)) = &player.stream {
-        sink.set_speed(speed);
+pub fn get_now_playing(player: State<'_, PlayerHandle>) -> Option<NowPlaying> {
+    let state = player_snapshot(&player);
+    let path = state.current_path?;
+    let speed = player.with(|player| player.speed);
+    Some(NowPlaying {
+        tags: now_playing_tags(&path, state.cue_track.as_ref()),
+        path,
+        position: state.position,
+        duration: state.duration,
+        duration_approximate: state.duration_approximate,
+        is_playing: state.is_playing,
+        status: state.status,
+        volume: state.volume,
+        muted: state.muted,
+        speed,
+        shuffle_mode: state.shuffle_mode,
+        repeat_mode: state.repeat_mode,
+        queue_position: state.queue_position,
+        queue_length: state.queue_length,
+    })
+}
+
+#[tauri::command]
+pub fn get_playback_speed(player: State<'_, PlayerHandle>) -> f32 {
+    player.with(|player| player.speed)
+}
+
+/// Sets the speed for this and later tracks; it is saved for the next session.
+/// With `preserve_pitch` the audio is time-stretched so voices keep their
+/// pitch; without it the speed changes like a tape. Leaving it out keeps the
+/// current choice.
+#[tauri::command]
+pub fn set_playback_speed(player: State<'_, PlayerHandle>, speed: f32, preserve_pitch: Option<bool>) -> Result<(), String> {
+    if !speed.is_finite() || !(MIN_PLAYBACK_SPEED..=MAX_PLAYBACK_SPEED).contains(&speed) {
+        return Err(format!("Playback speed must be between {} and {}", MIN_PLAYBACK_SPEED, MAX_PLAYBACK_SPEED));
+    }
+    let preserve_pitch = preserve_pitch.unwrap_or_else(preserves_pitch);
+    if preserve_pitch != preserves_pitch() {
+        update_player_config(|config| {
+            config.playback_settings.preserve_pitch = preserve_pitch;
+            Ok(())
+        })?;
     }
+    save_playback_levels_soon(&player);
+    player.with(move |player| {
+        player.speed = speed;
+        set_stretch(speed, preserve_pitch);
+        if let Some(sink) = &player.sink {
+            sink.set_speed(player.sink_speed());
+        }
+    });
     Ok(())
 }
 
 #[tauri::command]
-pub fn skip_track() -> Result<(), String> {
-    let player = PLAYER.lock();
-    if let Some((_, sink)) = &player.stream {
-        sink.skip_one();
+pub fn get_preserve_pitch() -> bool {
+    preserves_pitch()
+}
+
+/// Moves on to the next queue track, fading into it when crossfade is on.
+#[tauri::command]
+pub fn skip_track(app: tauri::AppHandle) -> Result<(), String> {
+    // Skipping by hand means the listener wants to keep going
+    let was_stopping = app.state::<PlayerHandle>().with(|player| std::mem::take(&mut player.stop_after_current));
+    skip_to_next(&app);
+    if was_stopping {
+        emit_playback_state(&app);
     }
     Ok(())
 }
 
+/// Within this much of the start, "previous" goes back a track instead of restarting
+const PREVIOUS_TRACK_WINDOW: Duration = Duration::from_secs(3);
+
+/// Goes back to the last track played, or restarts the current one once it
+/// has been playing for a few seconds.
 #[tauri::command]
-pub fn clear_queue() -> Result<(), String> {
-    let player = PLAYER.lock();
-    if let Some((_, sink)) = &player.stream {
-        sink.clear();
+pub fn previous_track(app: tauri::AppHandle) -> Result<(), String> {
+    let player = app.state::<PlayerHandle>();
+    let previous = player.with(|player| {
+        let position = player.track_position();
+        if position >= PREVIOUS_TRACK_WINDOW || player.history.is_empty() {
+            None
+        } else {
+            Some((player.history.remove(0), player.current_path.take()))
+        }
+    });
+    let Some((previous, left)) = previous else { return seek_to(player, 0.0).map_err(String::from) };
+
+    // The track left behind isn't added to the history, so "previous" keeps going back
+    if let Err(e) = start_playback(&player, &previous, false) {
+        player.with(move |player| {
+            player.history.insert(0, previous);
+            if player.current_path.is_none() {
+                player.current_path = left;
+            }
+        });
+        return Err(e.into());
+    }
+
+    let position = with_queue(|queue| {
+        let before = queue.position.checked_sub(1);
+        if let Some(index) = before.filter(|index| queue.tracks[*index].path == previous) {
+            queue.position = index;
+        }
+        queue.position
+    });
+    if let Ok(track) = queued_track(&previous) {
+        app.emit("track-changed", TrackChanged { path: previous, position, track }).ok();
     }
     Ok(())
 }
 
+/// Paths played before the current track, most recent first.
+#[tauri::command]
+pub fn get_play_history(player: State<'_, PlayerHandle>) -> Vec<String> {
+    player.with(|player| player.history.clone())
+}
+
+/// Drops the upcoming tracks from the play queue and the play history; the
+/// current track keeps playing.
+#[tauri::command]
+pub fn clear_queue(player: State<'_, PlayerHandle>) -> Result<(), String> {
+    clear_upcoming();
+    player.with(|player| player.history.clear());
+    Ok(())
+}
+
 #[tauri::command]
 pub fn is_queue_empty() -> bool {
-    let player = PLAYER.lock();
-    if let Some((_, sink)) = &player.stream {
-        sink.empty()
-    } else {
-        true
-    }
+    queue_length() == 0
 }
 
+/// Number of tracks queued after the current one.
 #[tauri::command]
 pub fn queue_length() -> usize {
-    let player = PLAYER.lock();
-    if let Some((_, sink)) = &player.stream {
-        sink.len()
-    } else {
-        0
-    }
+    with_queue(|queue| queue.tracks.len() - queue.upcoming_start())
 }
 
+/// Seeks within the current track; for a CUE track, counting from its start.
 #[tauri::command]
-pub fn seek_to(position: f32) -> Result<(), String> {
-    let player = PLAYER.lock();
-    if let Some((_, sink)) = &player.stream {
-        let target = Duration::from_secs_f32(position);
-        sink.try_seek(target).map_err(|e| e.to_string())?;
+pub fn seek_to(player: State<'_, PlayerHandle>, position: f32) -> Result<(), PlaybackError> {
+    let offset = player.with(|player| player.track_offset());
+    seek_absolute(&player, offset.as_secs_f32() + position.max(0.0))
+}
+
+/// Seeks to `position` in the file being played.
+pub fn seek_absolute(player: &PlayerHandle, position: f32) -> Result<(), PlaybackError> {
+    player.with(move |player| {
+        if let Some(sink) = &player.sink {
+            let target = Duration::from_secs_f32(position.max(0.0));
+            // The sink scales seeks by its own speed
+            match sink.try_seek(target.div_f32(player.sink_speed())) {
+                Ok(()) => {}
+                Err(SeekError::NotSupported { underlying_source }) => {
+                    info!("{} can't seek, reopening at {:?}", underlying_source, target);
+                    reopen_at(player, target)?;
+                }
+                Err(e) => return Err(PlaybackError::SeekUnsupported(e.to_string())),
+            }
+        }
+        player.generation += 1;
+        Ok(())
+    })
+}
+
+/// Seeks by playing the current file again from `target`, for decoders that
+/// can't seek. Volume, speed and pause state carry over to the new sink.
+fn reopen_at(player: &mut PlayerState, target: Duration) -> Result<(), PlaybackError> {
+    let path = player.current_path.clone().ok_or("Nothing is playing".to_string())?;
+    let opened = open_source_at(&path, target)?;
+    let sink = new_sink(player)?;
+    sink.set_volume(player.output_gain());
+    sink.set_speed(player.sink_speed());
+    if !player.is_playing {
+        sink.pause();
     }
+    sink.append(opened.source);
+
+    cut(player);
+    // Anything queued behind the old source went with its sink
+    player.preloaded = None;
+    player.replace_sink(Some(Arc::new(sink)));
+    player.clock = opened.clock;
     Ok(())
 }
 
@@ -404,44 +869,154 @@ pub fn get_app_config() -> Result<AppConfig, String> {
 }
 
 #[tauri::command]
-pub fn update_app_config(config: AppConfig) -> Result<(), String> {
-    save_player_config(&config)
+pub fn update_app_config(app: tauri::AppHandle, config: AppConfig) -> Result<(), String> {
+    let shortcuts = config.shortcuts.clone();
+    let (exclusions_changed, watch_folders_changed, library_roots_changed) = update_player_config(|previous| {
+        let changed = (
+            previous.exclusions != config.exclusions,
+            previous.watch_folders != config.watch_folders,
+            previous.library_roots != config.library_roots,
+        );
+        *previous = config;
+        Ok(changed)
+    })?;
+    if exclusions_changed {
+        std::thread::spawn(prune_excluded_tracks);
+    }
+    if watch_folders_changed {
+        crate::import::start_watch_folders(app.clone());
+    }
+    if library_roots_changed {
+        crate::library_watch::start_library_watchers(app.clone());
+    }
+    #[cfg(desktop)]
+    crate::shortcuts::apply_shortcuts(&app, &shortcuts);
+    Ok(())
 }
 
 #[tauri::command]
-pub fn get_recursive_audio_files(path: &str) -> Result<Vec<FileItem>, String> {
-    let mut audio_files = Vec::new();
-    let mut dirs_to_process = VecDeque::new();
-    dirs_to_process.push_back(PathBuf::from(path));
-
-    while let Some(current_dir) = dirs_to_process.pop_front() {
-        if let Ok(entries) = fs::read_dir(&current_dir) {
-            for entry in entries {
-                if let Ok(entry) = entry {
-                    let path = entry.path();
-                    if path.is_dir() {
-                        dirs_to_process.push_back(path);
-                    } else if let Some(extension) = path.extension() {
-                        if let Some(ext_str) = extension.to_str() {
-                            if ["mp3", "flac", "m4a", "wav", "ogg"].contains(&ext_str.to_lowercase().as_str()) {
-                                let path_str = path.to_string_lossy().to_string();
-                                audio_files.push(FileItem {
-                                    name: path.file_name().unwrap_or_default().to_string_lossy().to_string(),
-                                    path: path_str,
-                                    is_dir: false,
-                                    is_audio: true,
-                                });
-                            }
-                        }
-                    }
-                }
-            }
+pub fn get_view_settings_for(path: String) -> Result<ViewSettings, String> {
+    let (settings, touched) = view_settings_for(&mut load_player_config(), &path);
+    // The config is only written when the override's last-used time is due for a refresh
+    if touched {
+        let result = update_player_config(|config| {
+            view_settings_for(config, &path);
+            Ok(())
+        });
+        if let Err(e) = result {
+            error!("Failed to refresh the view override for {}: {}", path, e);
         }
     }
+    Ok(settings)
+}
+
+#[tauri::command]
+pub fn set_view_settings_for(path: String, settings: ViewSettings) -> Result<(), String> {
+    if !Path::new(&path).is_dir() {
+        return Err("Folder does not exist".to_string());
+    }
+    update_player_config(|config| {
+        set_folder_view_override(config, &path, settings);
+        Ok(())
+    })
+}
 
+#[tauri::command]
+pub async fn get_recursive_audio_files(app: tauri::AppHandle, path: String, request_id: Option<String>) -> Result<Vec<FileItem>, RequestError> {
+    let request = begin_request("get_recursive_audio_files", &path, request_id);
+    let root = Path::new(&path);
+    let mut audio_files = Vec::new();
+    let mut skipped = Vec::new();
+
+    walk_playable(root, &request, &mut skipped, &mut |path| {
+        audio_files.push(FileItem {
+            name: path.file_name().unwrap_or_default().to_string_lossy().to_string(),
+            path: path.to_string_lossy().to_string(),
+            is_dir: false,
+            is_audio: true,
+            modified: None,
+            added_at: None,
+            excluded: false,
+            missing: false,
+        });
+    })?;
+    emit_skipped(&app, "get_recursive_audio_files", root, skipped);
     Ok(audio_files)
 }
 
+/// Calls `visit` with every playable file under `root`, giving up with
+/// `Cancelled` at the next folder or file once `request` is cancelled.
+pub(crate) fn walk_playable(root: &Path, request: &RequestToken, skipped: &mut Vec<SkippedPath>, visit: &mut dyn FnMut(&Path)) -> Result<(), RequestError> {
+    check_readable(root)?;
+    walk_files_reporting(root, true, &Exclusions::load(), &|| request.is_cancelled(), &mut |path| {
+        if has_playable_extension(path) {
+            visit(path);
+        }
+    }, skipped);
+    request.check()
+}
+
+fn has_playable_extension(path: &Path) -> bool {
+    path.extension()
+        .and_then(|extension| extension.to_str())
+        .is_some_and(|extension| ["mp3", "flac", "m4a", "wav", "ogg"].contains(&extension.to_lowercase().as_str()))
+}
+
+/// Orders tracks from a folder for the queue. Track numbers only mean
+/// something within a folder, so every sort keeps subfolders together.
+fn sort_for_queue(tracks: &mut [LibraryTrack], sort_by: &SortOption) {
+    let folder = |track: &LibraryTrack| Path::new(&track.path).parent().map(Path::to_path_buf).unwrap_or_default();
+    let file_name = |track: &LibraryTrack| Path::new(&track.path).file_name().unwrap_or_default().to_string_lossy().to_string();
+    tracks.sort_by(|a, b| {
+        let by_name = || natural_cmp(&file_name(a), &file_name(b));
+        let within_folder = match sort_by {
+            SortOption::FileName => by_name(),
+            SortOption::Title => collate(a.title.as_deref().unwrap_or(""), b.title.as_deref().unwrap_or("")).then_with(by_name),
+            SortOption::TrackNumber => a.track_number.unwrap_or(u32::MAX).cmp(&b.track_number.unwrap_or(u32::MAX)).then_with(by_name),
+            SortOption::DateAdded => a.added_at.cmp(&b.added_at).then_with(by_name),
+            SortOption::DateModified => a.modified.cmp(&b.modified).then_with(by_name),
+            SortOption::DiscAndTrack => (a.disc_number.unwrap_or(1), a.track_number.unwrap_or(u32::MAX))
+                .cmp(&(b.disc_number.unwrap_or(1), b.track_number.unwrap_or(u32::MAX)))
+                .then_with(by_name),
+            SortOption::Bpm => a.bpm.unwrap_or(f32::MAX).total_cmp(&b.bpm.unwrap_or(f32::MAX)).then_with(by_name),
+            SortOption::Rating => b.rating.cmp(&a.rating).then_with(by_name),
+        };
+        natural_cmp(&folder(a).to_string_lossy(), &folder(b).to_string_lossy()).then(within_folder)
+    });
+}
+
+/// Appends the audio files in `path` to the queue, ordered by `sort_by`
+/// within each folder. Files that can't be read are left out. Returns how many
+/// tracks were queued.
+#[tauri::command]
+pub async fn enqueue_directory(app: tauri::AppHandle, path: String, recursive: bool, sort_by: SortOption, request_id: Option<String>) -> Result<usize, RequestError> {
+    let request = begin_request("enqueue_directory", &path, request_id);
+    let root = Path::new(&path);
+    check_readable(root)?;
+
+    let mut tracks = Vec::new();
+    let mut skipped = Vec::new();
+    walk_files_reporting(root, recursive, &Exclusions::load(), &|| request.is_cancelled(), &mut |file| {
+        if !has_playable_extension(file) {
+            return;
+        }
+        match track_info(file) {
+            Some(track) => tracks.push(track),
+            None => error!("Skipping unreadable {} while queueing {}", file.display(), root.display()),
+        }
+    }, &mut skipped);
+    request.check()?;
+    emit_skipped(&app, "enqueue_directory", root, skipped);
+
+    // Files the index hasn't seen yet count as added when last modified
+    let added = added_times(tracks.iter().map(|track| track.path.as_str()));
+    for (track, added_at) in tracks.iter_mut().zip(added) {
+        track.added_at = added_at.unwrap_or(track.modified);
+    }
+    sort_for_queue(&mut tracks, &sort_by);
+    Ok(append_to_queue(tracks.into_iter().map(QueuedTrack::from).collect()))
+}
+
 #[tauri::command]
 pub async fn restore_file_extension(path: String) -> Result<(), String> {
     let path = Path::new(&path);
@@ -486,26 +1061,9 @@ fn restore_single_file_extension(path: &Path) -> Result<(), String> {
         return Err("Cannot restore extension for directories".to_string());
     }
 
-    // Read first few bytes to detect file type
-    let mut file = File::open(path).map_err(|e| format!("Failed to open file: {}", e))?;
-    let mut buffer = [0; 16];
-    file.read(&mut buffer).map_err(|e| format!("Failed to read file: {}", e))?;
-
-    // Detect file type based on magic numbers and get MIME type
-    let (extension, mime_type) = if buffer.starts_with(b"\x00\x00\x00\x20\x66\x74\x79\x70") || // MP4
-                      buffer.starts_with(b"\x00\x00\x00\x18\x66\x74\x79\x70") {
-        ("mp4", "video/mp4")
-    } else if buffer.starts_with(b"ID3") || buffer.starts_with(b"\xFF\xFB") { // MP3
-        ("mp3", "audio/mpeg")
-    } else if buffer.starts_with(b"fLaC") { // FLAC
-        ("flac", "audio/flac")
-    } else if buffer.starts_with(b"OggS") { // OGG
-        ("ogg", "audio/ogg")
-    } else if buffer.starts_with(b"RIFF") { // WAV
-        ("wav", "audio/wav")
-    } else {
-        return Err("Could not detect file type".to_string());
-    };
+    let extension = sniff_file(path)?
+        .ok_or_else(|| "Could not detect file type".to_string())?
+        .extension;
 
     // Get the filename without any existing extension
     let filename = path.file_name()
@@ -537,15 +1095,458 @@ fn restore_single_file_extension(path: &Path) -> Result<(), String> {
         album_artist: None,
         album_art: None,
         genre: None,
+        composer: None,
+        comment: None,
+        compilation: None,
+        bpm: None,
+        rating: None,
         year: None,
         track_number: None,
+        track_total: None,
+        disc_number: None,
+        disc_total: None,
+        lyrics: None,
     };
 
     // Try to write metadata, but don't fail if it doesn't work
-    if let Err(e) = write_audio_metadata(options) {
+    if let Err(e) = write_metadata(&options) {
         eprintln!("Warning: Failed to update metadata: {}", e);
     }
 
     Ok(())
 }
- 
\ No newline at end of file
+ 
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum BatchOp {
+    Enqueue,
+    AddToPlaylist,
+    Favorite,
+    Move,
+    Tag,
+}
+
+impl BatchOp {
+    fn parse(op: &str) -> Result<Self, String> {
+        match op {
+            "enqueue" => Ok(BatchOp::Enqueue),
+            "add_to_playlist" => Ok(BatchOp::AddToPlaylist),
+            "favorite" => Ok(BatchOp::Favorite),
+            "move" => Ok(BatchOp::Move),
+            "tag" => Ok(BatchOp::Tag),
+            other => Err(format!("Unknown batch operation: {}", other)),
+        }
+    }
+}
+
+/// Parameters for `batch_operation`; each operation reads only the fields it needs.
+#[derive(Debug, Deserialize, Default)]
+#[serde(default)]
+struct BatchParams {
+    // add_to_playlist: a playlist file or stored playlist name
+    playlist: Option<String>,
+    // move
+    target_folder: Option<String>,
+    relocate_playing: bool,
+    // tag: one of title, artist, album, album_artist, genre, composer, comment, compilation, bpm,
+    // rating, year, track_number, track_total, disc_number, disc_total, lyrics
+    field: Option<String>,
+    value: Option<serde_json::Value>,
+    // Undo everything if any item fails; only for journaled operations (move)
+    all_or_nothing: bool,
+}
+
+#[derive(Debug, Serialize, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum BatchItemStatus {
+    Done,
+    Failed,
+    // Not attempted because the batch was cancelled or stopped early
+    Skipped,
+    // Done, then undone because a later item failed in all-or-nothing mode
+    RolledBack,
+}
+
+#[derive(Debug, Serialize)]
+pub struct BatchItemResult {
+    pub path: String,
+    pub status: BatchItemStatus,
+    pub error: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct BatchResult {
+    pub op: String,
+    pub task_id: u64,
+    pub succeeded: usize,
+    pub failed: usize,
+    pub cancelled: bool,
+    pub results: Vec<BatchItemResult>,
+}
+
+fn tag_options(path: &str, field: &str, value: &serde_json::Value) -> Result<MetadataWriteOptions, String> {
+    let text = || value.as_str().map(|s| s.to_string()).ok_or_else(|| format!("{} must be a string", field));
+    let number = || value.as_u64()
+        .or_else(|| value.as_str().and_then(|s| s.trim().parse().ok()))
+        .map(|n| n as u32)
+        .ok_or_else(|| format!("{} must be a number", field));
+
+    let mut options = MetadataWriteOptions {
+        path: path.to_string(),
+        title: None,
+        artist: None,
+        album: None,
+        album_artist: None,
+        album_art: None,
+        genre: None,
+        composer: None,
+        comment: None,
+        compilation: None,
+        bpm: None,
+        rating: None,
+        year: None,
+        track_number: None,
+        track_total: None,
+        disc_number: None,
+        disc_total: None,
+        lyrics: None,
+    };
+    match field {
+        "title" => options.title = Some(text()?),
+        "artist" => options.artist = Some(text()?),
+        "album" => options.album = Some(text()?),
+        "album_artist" => options.album_artist = Some(text()?),
+        "genre" => options.genre = Some(text()?),
+        "composer" => options.composer = Some(text()?),
+        "comment" => options.comment = Some(text()?),
+        "bpm" => options.bpm = Some(value.as_f64()
+            .or_else(|| value.as_str().and_then(|s| s.trim().parse().ok()))
+            .ok_or_else(|| format!("{} must be a number", field))? as f32),
+        "rating" => options.rating = Some(number()?.min(100) as u8),
+        "compilation" => options.compilation = Some(value.as_bool().ok_or_else(|| format!("{} must be true or false", field))?),
+        "year" => options.year = Some(number()?),
+        "track_number" => options.track_number = Some(number()?),
+        "track_total" => options.track_total = Some(number()?),
+        "disc_number" => options.disc_number = Some(number()?),
+        "disc_total" => options.disc_total = Some(number()?),
+        "lyrics" => options.lyrics = Some(text()?),
+        other => return Err(format!("Unsupported tag field: {}", other)),
+    }
+    Ok(options)
+}
+
+/// Runs one item of a non-journaled batch through the matching single-item implementation.
+fn run_batch_item(app: &tauri::AppHandle, op: BatchOp, params: &BatchParams, playlist: Option<&mut Playlist>, path: &str) -> Result<(), String> {
+    match op {
+        BatchOp::Enqueue => enqueue_track(path, None).map(|_| ()),
+        BatchOp::AddToPlaylist => {
+            let playlist = playlist.ok_or("No playlist given")?;
+            let file = Path::new(path);
+            if !file.is_file() {
+                return Err(format!("{} does not exist", path));
+            }
+            let info = track_info(file).map(|track| ExtInf {
+                duration: track.duration,
+                artist: track.artist,
+                title: track.title,
+            });
+            playlist.append_entry(file, info.as_ref());
+            Ok(())
+        }
+        BatchOp::Favorite => add_favorite_location(path.to_string(), None).map(|_| ()),
+        BatchOp::Move => {
+            let target = params.target_folder.as_deref().ok_or("No target folder given")?;
+            move_into_folder(app, Path::new(path), Path::new(target), params.relocate_playing).map_err(|e| e.to_string())
+        }
+        BatchOp::Tag => {
+            let field = params.field.as_deref().ok_or("No tag field given")?;
+            let value = params.value.as_ref().ok_or("No tag value given")?;
+            let result = write_audio_metadata(app.clone(), tag_options(path, field, value)?, Some(params.relocate_playing))
+                .map_err(|e| e.to_string())?;
+            if result.success { Ok(()) } else { Err(result.message) }
+        }
+    }
+}
+
+/// Moves every path into the target folder as one journaled transaction: the
+/// first failure (or a cancellation) moves everything back.
+fn run_atomic_move(app: &tauri::AppHandle, params: &BatchParams, paths: &[String], task: &TaskHandle, results: &mut Vec<BatchItemResult>) -> Result<(), String> {
+    let target = PathBuf::from(params.target_folder.as_deref().ok_or("No target folder given")?);
+    if !target.is_dir() {
+        return Err("Target must be a directory".to_string());
+    }
+    let mut transaction = Transaction::new("batch_move", format!("Move {} items to {}", paths.len(), target.display()));
+    let mut failure = None;
+
+    for (index, path) in paths.iter().enumerate() {
+        if task.is_cancelled() {
+            failure = Some("Cancelled".to_string());
+            break;
+        }
+        task.set_progress(index as u64, paths.len() as u64);
+        task.set_message(path.clone());
+
+        let source = Path::new(path);
+        let Some(name) = source.file_name() else {
+            failure = Some(format!("{}: invalid file name", path));
+            results.push(BatchItemResult { path: path.clone(), status: BatchItemStatus::Failed, error: failure.clone() });
+            break;
+        };
+        let destination = target.join(name);
+        let moved = guard_playing_track(app, source, &destination, params.relocate_playing, || transaction.rename(source, &destination));
+        match moved {
+            Ok(()) => {
+                transaction.remap(source, &destination);
+                results.push(BatchItemResult { path: path.clone(), status: BatchItemStatus::Done, error: None });
+            }
+            Err(e) => {
+                failure = Some(e.to_string());
+                results.push(BatchItemResult { path: path.clone(), status: BatchItemStatus::Failed, error: Some(e.to_string()) });
+                break;
+            }
+        }
+    }
+
+    match failure {
+        Some(_) => {
+            transaction.rollback();
+            for result in results.iter_mut().filter(|result| result.status == BatchItemStatus::Done) {
+                result.status = BatchItemStatus::RolledBack;
+            }
+            Ok(())
+        }
+        None => transaction.commit().map(|_| ()),
+    }
+}
+
+/// Applies one selection action to many paths in a single call, in order, as a
+/// background task (cancel it through `cancel_background_task`). Items are
+/// handled by the same code as the single-item commands.
+#[tauri::command]
+pub async fn batch_operation(app: tauri::AppHandle, op: String, paths: Vec<String>, params: serde_json::Value) -> Result<BatchResult, String> {
+    let batch_op = BatchOp::parse(&op)?;
+    let params: BatchParams = if params.is_null() {
+        BatchParams::default()
+    } else {
+        serde_json::from_value(params).map_err(|e| format!("Invalid parameters for {}: {}", op, e))?
+    };
+    if params.all_or_nothing && batch_op != BatchOp::Move {
+        return Err(format!("{} can't be undone, so all_or_nothing is not supported", op));
+    }
+
+    let mut playlist = match (batch_op, &params.playlist) {
+        (BatchOp::AddToPlaylist, Some(name)) => Some(Playlist::load(&resolve_playlist(name)?)?),
+        (BatchOp::AddToPlaylist, None) => return Err("No playlist given".to_string()),
+        _ => None,
+    };
+
+    let task = start_task(&app, TaskKind::Batch, format!("{} ({} items)", op, paths.len()));
+    let mut results = Vec::with_capacity(paths.len());
+
+    let outcome = if params.all_or_nothing {
+        run_atomic_move(&app, &params, &paths, &task, &mut results)
+    } else {
+        for (index, path) in paths.iter().enumerate() {
+            if task.is_cancelled() {
+                break;
+            }
+            task.set_progress(index as u64, paths.len() as u64);
+            task.set_message(path.clone());
+            let result = run_batch_item(&app, batch_op, &params, playlist.as_mut(), path);
+            results.push(BatchItemResult {
+                path: path.clone(),
+                status: if result.is_ok() { BatchItemStatus::Done } else { BatchItemStatus::Failed },
+                error: result.err(),
+            });
+        }
+        match &playlist {
+            Some(playlist) => playlist.save(),
+            None => Ok(()),
+        }
+    };
+
+    for path in paths.iter().skip(results.len()) {
+        results.push(BatchItemResult { path: path.clone(), status: BatchItemStatus::Skipped, error: None });
+    }
+    task.set_progress(paths.len() as u64, paths.len() as u64);
+    for result in &results {
+        if let Some(error) = &result.error {
+            task.count_error(error);
+        }
+    }
+    task.set_count("succeeded", results.iter().filter(|result| result.status == BatchItemStatus::Done).count() as u64);
+    task.set_count("failed", results.iter().filter(|result| result.status == BatchItemStatus::Failed).count() as u64);
+    let cancelled = task.is_cancelled();
+    task.finish(outcome.clone());
+    outcome?;
+
+    Ok(BatchResult {
+        op,
+        task_id: task.id(),
+        succeeded: results.iter().filter(|result| result.status == BatchItemStatus::Done).count(),
+        failed: results.iter().filter(|result| result.status == BatchItemStatus::Failed).count(),
+        cancelled,
+        results,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::{Duration, SystemTime};
+    use crate::test_support::{build_dated_library, build_library, set_modified, temp_dir, FixtureTags, TrackSpec};
+
+    fn names(items: &[FileItem]) -> Vec<&str> {
+        items.iter().map(|item| item.name.as_str()).collect()
+    }
+
+    fn list(path: &Path, sort: Option<&str>, descending: bool) -> Vec<FileItem> {
+        tauri::async_runtime::block_on(read_dir(
+            path.to_string_lossy().to_string(),
+            sort.map(str::to_string),
+            Some(descending),
+            None,
+        )).unwrap()
+    }
+
+    #[test]
+    fn read_dir_lists_folders_first_in_natural_order() {
+        let dir = temp_dir();
+        let epoch = SystemTime::UNIX_EPOCH + Duration::from_secs(1_600_000_000);
+        build_library(dir.path(), &[
+            TrackSpec::new("10 - Outro.mp3", FixtureTags::default()).modified(epoch),
+            TrackSpec::new("2 - Song.flac", FixtureTags::default()).modified(epoch + Duration::from_secs(20)),
+            TrackSpec::new("1 - Intro.wav", FixtureTags::default()).modified(epoch + Duration::from_secs(10)),
+        ]);
+        fs::create_dir(dir.path().join("Disc 10")).unwrap();
+        fs::create_dir(dir.path().join("Disc 2")).unwrap();
+        fs::write(dir.path().join("notes.txt"), "liner notes").unwrap();
+        set_modified(&dir.path().join("notes.txt"), epoch - Duration::from_secs(10));
+
+        let natural = list(dir.path(), None, false);
+        assert_eq!(names(&natural), ["Disc 2", "Disc 10", "1 - Intro.wav", "2 - Song.flac", "10 - Outro.mp3", "notes.txt"]);
+
+        let by_modified = list(dir.path(), Some("modified"), true);
+        assert_eq!(&names(&by_modified)[2..], ["2 - Song.flac", "1 - Intro.wav", "10 - Outro.mp3", "notes.txt"]);
+        assert!(by_modified[..2].iter().all(|item| item.is_dir));
+    }
+
+    #[test]
+    fn read_dir_sorts_by_date_added_and_modified() {
+        let dir = temp_dir();
+        let added = build_dated_library(dir.path());
+        let list = |sort: &str, descending| {
+            let sort = DirSort::parse(Some(sort)).unwrap();
+            list_dir(dir.path(), sort, descending, |path| added.get(path).copied(), &begin_request("test", "", None)).unwrap()
+        };
+
+        assert_eq!(names(&list("added", false)), ["d.mp3", "c.wav", "a.mp3", "b.flac"]);
+        assert_eq!(names(&list("added", true)), ["b.flac", "a.mp3", "c.wav", "d.mp3"]);
+        assert_eq!(names(&list("modified", false)), ["d.mp3", "b.flac", "c.wav", "a.mp3"]);
+        assert_eq!(names(&list("modified", true)), ["a.mp3", "c.wav", "b.flac", "d.mp3"]);
+
+        let listed = list("name", false);
+        let added: Vec<_> = listed.iter().map(|item| item.added_at).collect();
+        assert_eq!(added, [None, Some(1_600_000_040), None, Some(1_600_000_001)]);
+        assert_eq!(listed[0].modified, Some(1_600_000_030));
+    }
+
+    #[test]
+    fn read_dir_flags_audio_by_extension() {
+        let dir = temp_dir();
+        build_library(dir.path(), &[TrackSpec::new("song.flac", FixtureTags::default())]);
+        fs::create_dir(dir.path().join("album.mp3")).unwrap();
+        fs::write(dir.path().join("cover.jpg"), b"not audio").unwrap();
+
+        for item in list(dir.path(), Some("name"), false) {
+            assert_eq!(item.is_audio, item.name == "song.flac", "{}", item.name);
+        }
+    }
+
+    #[test]
+    fn read_dir_rejects_unknown_sort() {
+        let dir = temp_dir();
+        let result = tauri::async_runtime::block_on(read_dir(
+            dir.path().to_string_lossy().to_string(),
+            Some("colour".to_string()),
+            None,
+            None,
+        ));
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn restore_extension_detects_format_from_magic_numbers() {
+        let dir = temp_dir();
+        let sources = build_library(dir.path(), &[
+            TrackSpec::new("tagged.mp3", FixtureTags::titled("Tagged")),
+            TrackSpec::new("bare.mp3", FixtureTags::default()),
+            TrackSpec::new("lossless.flac", FixtureTags::default()),
+            TrackSpec::new("clip.wav", FixtureTags::default()),
+        ]);
+        let renames = [("tagged", "tagged.mp3"), ("bare.bin", "bare.mp3"), ("lossless.wav", "lossless.flac"), ("clip", "clip.wav")];
+        for (source, (misnamed, expected)) in sources.iter().zip(renames) {
+            let misnamed = dir.path().join(misnamed);
+            fs::rename(source, &misnamed).unwrap();
+            restore_single_file_extension(&misnamed).unwrap();
+            assert!(dir.path().join(expected).is_file(), "expected {}", expected);
+            assert!(!misnamed.exists());
+        }
+    }
+
+    #[test]
+    fn restore_extension_rejects_unknown_content() {
+        let dir = temp_dir();
+        let path = dir.path().join("mystery");
+        fs::write(&path, b"plain text, not audio").unwrap();
+        assert!(restore_single_file_extension(&path).is_err());
+        assert!(path.exists());
+        assert!(restore_single_file_extension(dir.path()).is_err());
+    }
+
+    #[test]
+    fn folder_queue_keeps_subfolders_together_in_track_order() {
+        let dir = temp_dir();
+        let track = |n: &str| FixtureTags { track: Some(n.to_string()), ..FixtureTags::default() };
+        build_library(dir.path(), &[
+            TrackSpec::new("B/b2.flac", track("2")),
+            TrackSpec::new("A/a10.flac", FixtureTags::default()),
+            TrackSpec::new("B/b1.flac", track("1")),
+            TrackSpec::new("A/a2.flac", FixtureTags::default()),
+        ]);
+        let mut tracks: Vec<LibraryTrack> = ["A/a10.flac", "A/a2.flac", "B/b2.flac", "B/b1.flac"].iter()
+            .map(|name| track_info(&dir.path().join(name)).unwrap())
+            .collect();
+
+        sort_for_queue(&mut tracks, &SortOption::TrackNumber);
+        let order: Vec<String> = tracks.iter()
+            .map(|track| Path::new(&track.path).file_name().unwrap().to_string_lossy().to_string())
+            .collect();
+        // Untagged files fall back to natural filename order
+        assert_eq!(order, ["a2.flac", "a10.flac", "b1.flac", "b2.flac"]);
+    }
+
+    #[test]
+    fn box_sets_sort_by_disc_then_track() {
+        let dir = temp_dir();
+        let track = |n: &str| FixtureTags { track: Some(n.to_string()), ..FixtureTags::default() };
+        build_library(dir.path(), &[
+            TrackSpec::new("Box/d2t1.flac", track("1")),
+            TrackSpec::new("Box/d1t2.flac", track("2")),
+            TrackSpec::new("Box/d2t2.flac", track("2")),
+            TrackSpec::new("Box/d1t1.flac", track("1")),
+        ]);
+        let mut tracks: Vec<LibraryTrack> = ["d2t1.flac", "d1t2.flac", "d2t2.flac", "d1t1.flac"].iter()
+            .map(|name| {
+                let mut track = track_info(&dir.path().join("Box").join(name)).unwrap();
+                track.disc_number = Some(if name.starts_with("d2") { 2 } else { 1 });
+                track
+            })
+            .collect();
+
+        sort_for_queue(&mut tracks, &SortOption::DiscAndTrack);
+        let order: Vec<String> = tracks.iter()
+            .map(|track| Path::new(&track.path).file_name().unwrap().to_string_lossy().to_string())
+            .collect();
+        assert_eq!(order, ["d1t1.flac", "d1t2.flac", "d2t1.flac", "d2t2.flac"]);
+    }
+}
@@ -1,28 +1,131 @@
 use serde::{Deserialize, Serialize};
-use std::fs;
-use std::path::PathBuf;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
 use directories::ProjectDirs;
+use log::error;
+use crate::cache::CacheLimits;
+use crate::library::now_secs;
+use crate::paths::canonical_key;
+use crate::config_file::{read_json, update_json};
+use crate::equalizer::{BAND_COUNT, MAX_BAND_DB};
+
+pub const CONFIG_SCHEMA_VERSION: u32 = 1;
+const MAX_FOLDER_VIEW_OVERRIDES: usize = 200;
+// Reads only persist their LRU timestamp once it is this stale, so browsing doesn't rewrite the config
+const VIEW_OVERRIDE_TOUCH_SECS: u64 = 60 * 60;
 
 #[derive(Debug, Serialize, Deserialize)]
+#[serde(default)]
 pub struct AppConfig {
+    pub schema_version: u32,
     pub default_location: Option<String>,
     pub recent_locations: Vec<String>,
     pub favorite_locations: Vec<String>,
     pub max_recent_locations: usize,
     pub playback_settings: PlaybackSettings,
     pub view_settings: ViewSettings,
+    pub library_roots: Vec<LibraryRoot>,
+    pub cache_limits: CacheLimits,
+    pub thumbnail_workers: usize,
+    pub shortcuts: ShortcutSettings,
+    // Keyed by canonical folder path
+    pub folder_view_overrides: HashMap<String, ViewSettings>,
+    // Last use of each override, for LRU eviction
+    pub folder_view_last_used: HashMap<String, u64>,
+    // Glob patterns for files and folders to hide from scans and listings
+    pub exclusions: Vec<String>,
+    pub watch_folders: Vec<WatchFolderConfig>,
+    pub preview: PreviewSettings,
+    pub embedded_art: EmbeddedArtSettings,
+    // Offer playback to media keys and desktop widgets over MPRIS (Linux only)
+    pub mpris_enabled: bool,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct LibraryRoot {
+    pub path: String,
+    #[serde(default = "default_true")]
+    pub scan_on_startup: bool,
+    // Rescan automatically when files under the root change
+    #[serde(default)]
+    pub watch: bool,
+}
+
+fn default_true() -> bool {
+    true
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum WatchAction {
+    Move,
+    Copy,
+}
+
+/// A folder (such as a browser's downloads) whose new audio files are imported
+/// into `target_root`, laid out by `organize_pattern`.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct WatchFolderConfig {
+    pub path: String,
+    pub action: WatchAction,
+    pub target_root: String,
+    // Placeholders: {artist} {album_artist} {album} {title} {track} {year} {genre} {filename}
+    #[serde(default = "default_organize_pattern")]
+    pub organize_pattern: String,
+    // Only meaningful for copies; moves always remove the original
+    #[serde(default)]
+    pub delete_after_import: bool,
+}
+
+fn default_organize_pattern() -> String {
+    "{album_artist}/{album}/{track} - {title}".to_string()
 }
 
 #[derive(Debug, Serialize, Deserialize)]
+#[serde(default)]
 pub struct PlaybackSettings {
+    // Interpreted in `volume_saved_scale`, which older configs lack and so read as linear
     pub volume: f32,
+    pub volume_saved_scale: VolumeScale,
+    // How `set_volume` maps 0.0-1.0 onto gain
+    pub volume_scale: VolumeScale,
     pub repeat_mode: RepeatMode,
+    // Kept in sync with `shuffle_mode` for configs written before it existed
     pub shuffle: bool,
+    pub shuffle_mode: ShuffleMode,
     pub crossfade: bool,
     pub crossfade_duration: f32,
+    // Files at least this long remember where playback stopped
+    pub resume_min_duration_secs: f32,
+    // Convert files above the output device's highest rate instead of refusing them
+    pub resample_unsupported_rates: bool,
+    // Fade applied around pause, resume and stop; 0 switches it off
+    pub pause_fade_ms: u32,
+    // Older configs lack it and so start at normal speed
+    pub playback_speed: f32,
+    // Change speed by time-stretching rather than resampling, so voices keep their pitch
+    pub preserve_pitch: bool,
+    pub replaygain_mode: ReplayGainMode,
+    pub eq_enabled: bool,
+    // Gain in dB for each of the equalizer's bands, lowest first
+    pub eq_bands: Vec<f32>,
+    // -1.0 is left only, 1.0 right only
+    pub balance: f32,
+    pub mono: bool,
+    // Save the queue and position while playing and offer them back on the next launch
+    pub restore_session: bool,
+    // Start tracks after their leading silence and move on at their trailing silence
+    pub skip_silence: bool,
+    // Quieter than this counts as silence
+    pub silence_threshold_db: f32,
+    // Shorter stretches of silence are played as they are
+    pub silence_min_ms: u32,
+    // Close the audio device after this long with nothing loaded; 0 keeps it open
+    pub output_idle_timeout_secs: u32,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Serialize, Deserialize, Clone)]
+#[serde(default)]
 pub struct ViewSettings {
     pub show_artwork: bool,
     pub dark_mode: bool,
@@ -30,14 +133,102 @@ pub struct ViewSettings {
     pub group_by: GroupBy,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+/// Global playback shortcuts as accelerator strings such as `"CmdOrControl+Alt+P"`; `None` leaves the action unbound.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+#[serde(default)]
+pub struct ShortcutSettings {
+    pub enabled: bool,
+    pub play_pause: Option<String>,
+    pub next: Option<String>,
+    pub previous: Option<String>,
+    pub volume_up: Option<String>,
+    pub volume_down: Option<String>,
+}
+
+/// Hover previews: their own volume, and how much to lower main playback while one plays.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+#[serde(default)]
+pub struct PreviewSettings {
+    pub volume: f32,
+    // Fraction of the main volume removed during a preview; 0 leaves it untouched
+    pub duck_amount: f32,
+}
+
+impl Default for PreviewSettings {
+    fn default() -> Self {
+        Self {
+            volume: 0.3,
+            duck_amount: 0.7,
+        }
+    }
+}
+
+/// Limits for cover art embedded by `set_album_art`; larger images are
+/// downscaled and re-encoded as JPEG. 0 turns a limit off.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+#[serde(default)]
+pub struct EmbeddedArtSettings {
+    // Longest side, in pixels
+    pub max_dimension: u32,
+    pub max_bytes: u64,
+}
+
+impl Default for EmbeddedArtSettings {
+    fn default() -> Self {
+        Self {
+            max_dimension: 1500,
+            max_bytes: 1024 * 1024,
+        }
+    }
+}
+
+impl Default for ShortcutSettings {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            play_pause: Some("CmdOrControl+Alt+P".to_string()),
+            next: Some("CmdOrControl+Alt+Right".to_string()),
+            previous: Some("CmdOrControl+Alt+Left".to_string()),
+            volume_up: Some("CmdOrControl+Alt+Up".to_string()),
+            volume_down: Some("CmdOrControl+Alt+Down".to_string()),
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq)]
 pub enum RepeatMode {
     Off,
     Single,
     All,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum VolumeScale {
+    Linear,
+    // Slider position maps evenly onto decibels, so each step sounds about as loud as the last
+    Logarithmic,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum ShuffleMode {
+    #[default]
+    Off,
+    Tracks,
+    // Shuffle album order but play each album's tracks in order
+    Albums,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Default)]
+pub enum ReplayGainMode {
+    #[default]
+    Off,
+    Track,
+    Album,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq)]
 pub enum SortBy {
     Name,
     Artist,
@@ -46,7 +237,7 @@ pub enum SortBy {
     DateModified,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq)]
 pub enum GroupBy {
     None,
     Artist,
@@ -58,12 +249,24 @@ pub enum GroupBy {
 impl Default for AppConfig {
     fn default() -> Self {
         Self {
+            schema_version: CONFIG_SCHEMA_VERSION,
             default_location: None,
             recent_locations: Vec::new(),
             favorite_locations: Vec::new(),
             max_recent_locations: 10,
             playback_settings: PlaybackSettings::default(),
             view_settings: ViewSettings::default(),
+            library_roots: Vec::new(),
+            cache_limits: CacheLimits::default(),
+            thumbnail_workers: 2,
+            shortcuts: ShortcutSettings::default(),
+            folder_view_overrides: HashMap::new(),
+            folder_view_last_used: HashMap::new(),
+            exclusions: Vec::new(),
+            watch_folders: Vec::new(),
+            preview: PreviewSettings::default(),
+            embedded_art: EmbeddedArtSettings::default(),
+            mpris_enabled: true,
         }
     }
 }
@@ -72,10 +275,60 @@ impl Default for PlaybackSettings {
     fn default() -> Self {
         Self {
             volume: 0.5,
+            volume_saved_scale: VolumeScale::Linear,
+            volume_scale: VolumeScale::Linear,
             repeat_mode: RepeatMode::Off,
             shuffle: false,
+            shuffle_mode: ShuffleMode::Off,
             crossfade: false,
             crossfade_duration: 2.0,
+            resume_min_duration_secs: 20.0 * 60.0,
+            resample_unsupported_rates: false,
+            pause_fade_ms: 200,
+            playback_speed: 1.0,
+            preserve_pitch: false,
+            replaygain_mode: ReplayGainMode::Off,
+            eq_enabled: false,
+            eq_bands: vec![0.0; BAND_COUNT],
+            balance: 0.0,
+            mono: false,
+            restore_session: true,
+            skip_silence: false,
+            silence_threshold_db: -60.0,
+            silence_min_ms: 500,
+            output_idle_timeout_secs: 60,
+        }
+    }
+}
+
+pub const MIN_PLAYBACK_SPEED: f32 = 0.25;
+pub const MAX_PLAYBACK_SPEED: f32 = 4.0;
+
+impl PlaybackSettings {
+    /// The saved speed, or normal speed if a hand-edited config holds nonsense.
+    pub fn effective_playback_speed(&self) -> f32 {
+        if self.playback_speed.is_finite() && self.playback_speed > 0.0 {
+            self.playback_speed.clamp(MIN_PLAYBACK_SPEED, MAX_PLAYBACK_SPEED)
+        } else {
+            1.0
+        }
+    }
+
+    /// The saved band gains, or flat if a hand-edited config has the wrong number of them.
+    pub fn effective_eq_bands(&self) -> [f32; BAND_COUNT] {
+        let bands: [f32; BAND_COUNT] = self.eq_bands.as_slice().try_into().unwrap_or([0.0; BAND_COUNT]);
+        bands.map(|gain| if gain.is_finite() { gain.clamp(-MAX_BAND_DB, MAX_BAND_DB) } else { 0.0 })
+    }
+
+    /// The saved balance, or centred if a hand-edited config holds nonsense.
+    pub fn effective_balance(&self) -> f32 {
+        if self.balance.is_finite() { self.balance.clamp(-1.0, 1.0) } else { 0.0 }
+    }
+
+    pub fn effective_shuffle_mode(&self) -> ShuffleMode {
+        match self.shuffle_mode {
+            ShuffleMode::Off if self.shuffle => ShuffleMode::Tracks,
+            mode => mode,
         }
     }
 }
@@ -96,32 +349,100 @@ pub fn get_config_dir() -> Option<PathBuf> {
         .map(|proj_dirs| proj_dirs.config_dir().to_path_buf())
 }
 
+pub fn get_config_file_path() -> Option<PathBuf> {
+    get_config_dir().map(|dir| dir.join("config.json"))
+}
+
+pub fn get_cache_dir() -> Option<PathBuf> {
+    ProjectDirs::from("com", "your-org", "music-manager")
+        .map(|proj_dirs| proj_dirs.cache_dir().to_path_buf())
+}
+
+pub fn get_data_dir() -> Option<PathBuf> {
+    ProjectDirs::from("com", "your-org", "music-manager")
+        .map(|proj_dirs| proj_dirs.data_dir().to_path_buf())
+}
+
 pub fn load_player_config() -> AppConfig {
-    let config_dir = match get_config_dir() {
-        Some(dir) => dir,
-        None => return AppConfig::default(),
-    };
+    let Some(config_path) = get_config_file_path() else { return AppConfig::default() };
+    read_json(&config_path)
+}
 
-    let config_path = config_dir.join("config.json");
+/// Applies `change` to the settings as currently on disk and saves them under
+/// the config lock, so another instance's changes aren't overwritten.
+pub fn update_player_config<T>(change: impl FnOnce(&mut AppConfig) -> Result<T, String>) -> Result<T, String> {
+    let config_path = get_config_file_path().ok_or("Could not determine config directory")?;
+    update_json(&config_path, change)
+}
 
-    if !config_dir.exists() {
-        let _ = fs::create_dir_all(&config_dir);
-    }
+/// Drops overrides for folders that were deleted. A folder whose parent is
+/// also gone is kept, since that is usually an unmounted drive.
+fn prune_folder_view_overrides(config: &mut AppConfig) -> bool {
+    let before = config.folder_view_overrides.len();
+    config.folder_view_overrides.retain(|key, _| {
+        let path = Path::new(key);
+        path.exists() || path.parent().is_some_and(|parent| !parent.exists())
+    });
 
-    match fs::read_to_string(&config_path) {
-        Ok(contents) => serde_json::from_str(&contents).unwrap_or_default(),
-        Err(_) => AppConfig::default(),
+    let overrides = &config.folder_view_overrides;
+    let usage_before = config.folder_view_last_used.len();
+    config.folder_view_last_used.retain(|key, _| overrides.contains_key(key));
+
+    before != config.folder_view_overrides.len() || usage_before != config.folder_view_last_used.len()
+}
+
+/// Prunes overrides for deleted folders once, off the startup path. Writing an
+/// override prunes again, so they don't pile up while the app runs.
+pub fn start_view_override_pruning() {
+    std::thread::spawn(|| {
+        if !prune_folder_view_overrides(&mut load_player_config()) {
+            return;
+        }
+        let result = update_player_config(|config| {
+            prune_folder_view_overrides(config);
+            Ok(())
+        });
+        if let Err(e) = result {
+            error!("Failed to prune folder view overrides: {}", e);
+        }
+    });
+}
+
+/// View settings for `path`: its own override, else the nearest ancestor's,
+/// else the global settings. Refreshes the matching override's LRU timestamp.
+pub fn view_settings_for(config: &mut AppConfig, path: &str) -> (ViewSettings, bool) {
+    let key = canonical_key(path);
+    let found = Path::new(&key).ancestors()
+        .map(|ancestor| ancestor.to_string_lossy().to_string())
+        .find(|ancestor| config.folder_view_overrides.contains_key(ancestor));
+
+    match found {
+        Some(ancestor) => {
+            let now = now_secs();
+            let last_used = config.folder_view_last_used.insert(ancestor.clone(), now).unwrap_or(0);
+            let touched = now.saturating_sub(last_used) >= VIEW_OVERRIDE_TOUCH_SECS;
+            (config.folder_view_overrides[&ancestor].clone(), touched)
+        }
+        None => (config.view_settings.clone(), false),
     }
 }
 
-pub fn save_player_config(config: &AppConfig) -> Result<(), String> {
-    let config_dir = get_config_dir().ok_or("Could not determine config directory")?;
-    let config_path = config_dir.join("config.json");
+pub fn set_folder_view_override(config: &mut AppConfig, path: &str, settings: ViewSettings) {
+    prune_folder_view_overrides(config);
+    let key = canonical_key(path);
+    config.folder_view_overrides.insert(key.clone(), settings);
+    config.folder_view_last_used.insert(key, now_secs());
 
-    fs::create_dir_all(&config_dir).map_err(|e| e.to_string())?;
-    
-    let json = serde_json::to_string_pretty(config).map_err(|e| e.to_string())?;
-    fs::write(config_path, json).map_err(|e| e.to_string())?;
-    
-    Ok(())
-} 
\ No newline at end of file
+    while config.folder_view_overrides.len() > MAX_FOLDER_VIEW_OVERRIDES {
+        let oldest = config.folder_view_overrides.keys()
+            .min_by_key(|key| config.folder_view_last_used.get(*key).copied().unwrap_or(0))
+            .cloned();
+        match oldest {
+            Some(oldest) => {
+                config.folder_view_overrides.remove(&oldest);
+                config.folder_view_last_used.remove(&oldest);
+            }
+            None => break,
+        }
+    }
+}
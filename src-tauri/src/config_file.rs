@@ -0,0 +1,213 @@
+//! Locked read-modify-write for the JSON config files. Several app instances
+//! (or a CLI next to the GUI) may share them, so every change re-reads the file
+//! under an advisory lock, and edits made elsewhere are announced with
+//! `config-reloaded`.
+
+use serde::{de::DeserializeOwned, Serialize};
+use std::collections::HashMap;
+use std::fs::{self, File, OpenOptions};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime};
+use fs2::FileExt;
+use once_cell::sync::Lazy;
+use parking_lot::Mutex;
+use tauri::{AppHandle, Emitter};
+use log::{error, info};
+
+const POLL_INTERVAL: Duration = Duration::from_secs(2);
+
+// Modification time of each config file as this instance last wrote or saw it
+static KNOWN_MODIFIED: Lazy<Mutex<HashMap<PathBuf, Option<SystemTime>>>> = Lazy::new(|| Mutex::new(HashMap::new()));
+
+#[derive(Debug, Serialize, Clone)]
+pub struct ConfigReloaded {
+    // "locations" for favorites and recents, "player" for settings
+    pub config: String,
+    pub path: String,
+}
+
+/// Held while a config file is being read, changed and written; released on drop.
+pub struct ConfigLock(File);
+
+impl Drop for ConfigLock {
+    fn drop(&mut self) {
+        let _ = FileExt::unlock(&self.0);
+    }
+}
+
+fn lock_path(path: &Path) -> PathBuf {
+    let mut name = path.file_name().unwrap_or_default().to_os_string();
+    name.push(".lock");
+    path.with_file_name(name)
+}
+
+/// Blocks until no other process or thread holds the lock for `path`.
+pub fn lock_config(path: &Path) -> Result<ConfigLock, String> {
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent).map_err(|e| e.to_string())?;
+    }
+    let file = OpenOptions::new()
+        .create(true)
+        .truncate(false)
+        .write(true)
+        .open(lock_path(path))
+        .map_err(|e| format!("Failed to open config lock: {}", e))?;
+    file.lock_exclusive().map_err(|e| format!("Failed to lock config: {}", e))?;
+    Ok(ConfigLock(file))
+}
+
+fn modified(path: &Path) -> Option<SystemTime> {
+    fs::metadata(path).and_then(|metadata| metadata.modified()).ok()
+}
+
+/// Reads a config, falling back to the default when it is missing or unreadable.
+pub fn read_json<C: DeserializeOwned + Default>(path: &Path) -> C {
+    fs::read_to_string(path).ok()
+        .and_then(|contents| serde_json::from_str(&contents).ok())
+        .unwrap_or_default()
+}
+
+/// Writes through a temporary file and a rename, so a reader never sees half a config.
+pub fn write_json<C: Serialize>(path: &Path, config: &C) -> Result<(), String> {
+    let json = serde_json::to_string_pretty(config).map_err(|e| e.to_string())?;
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent).map_err(|e| e.to_string())?;
+    }
+    let mut temp_name = path.file_name().unwrap_or_default().to_os_string();
+    temp_name.push(".tmp");
+    let temp_path = path.with_file_name(temp_name);
+
+    // Held across the rename so the poller can't mistake our own write for someone else's
+    let mut known = KNOWN_MODIFIED.lock();
+    fs::write(&temp_path, json).map_err(|e| e.to_string())?;
+    fs::rename(&temp_path, path).map_err(|e| e.to_string())?;
+    known.insert(path.to_path_buf(), modified(path));
+    Ok(())
+}
+
+/// Re-reads the config under the lock, applies `change` and saves the result
+/// if it differs, so concurrent writers never drop each other's updates.
+pub fn update_json<C, T>(path: &Path, change: impl FnOnce(&mut C) -> Result<T, String>) -> Result<T, String>
+where
+    C: Serialize + DeserializeOwned + Default,
+{
+    let _lock = lock_config(path)?;
+    let mut config: C = read_json(path);
+    let before = serde_json::to_value(&config).map_err(|e| e.to_string())?;
+    let result = change(&mut config)?;
+    if serde_json::to_value(&config).map_err(|e| e.to_string())? != before || !path.exists() {
+        write_json(path, &config)?;
+    }
+    Ok(result)
+}
+
+/// Files among `watched` whose modification time changed since this instance
+/// last wrote or checked them. The first check of a file only records it.
+fn changed_files<'a>(watched: &'a [(&'static str, PathBuf)]) -> Vec<&'a (&'static str, PathBuf)> {
+    let mut known = KNOWN_MODIFIED.lock();
+    watched.iter()
+        .filter(|(_, path)| {
+            let current = modified(path);
+            match known.insert(path.clone(), current) {
+                Some(previous) => previous != current,
+                None => false,
+            }
+        })
+        .collect()
+}
+
+/// Polls the config files and emits `config-reloaded` when another instance changes one.
+pub fn watch_config_files(app: AppHandle, watched: Vec<(&'static str, PathBuf)>) {
+    std::thread::spawn(move || {
+        changed_files(&watched);
+        loop {
+            std::thread::sleep(POLL_INTERVAL);
+            for (config, path) in changed_files(&watched) {
+                info!("{} was changed by another instance", path.display());
+                let payload = ConfigReloaded {
+                    config: config.to_string(),
+                    path: path.to_string_lossy().to_string(),
+                };
+                if let Err(e) = app.emit("config-reloaded", payload) {
+                    error!("Failed to announce config reload: {}", e);
+                }
+            }
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde::Deserialize;
+    use crate::test_support::{set_modified, temp_dir};
+
+    #[derive(Debug, Default, Serialize, Deserialize)]
+    struct Favorites {
+        paths: Vec<String>,
+    }
+
+    #[test]
+    fn interleaved_writers_keep_every_update() {
+        let dir = temp_dir();
+        let path = dir.path().join("config.json");
+
+        let writers: Vec<_> = (0..2).map(|writer| {
+            let path = path.clone();
+            std::thread::spawn(move || {
+                for i in 0..25 {
+                    update_json(&path, |config: &mut Favorites| {
+                        config.paths.push(format!("/music/{}/{}", writer, i));
+                        // Widen the window in which an unlocked writer would lose the other's change
+                        std::thread::yield_now();
+                        Ok(())
+                    }).unwrap();
+                }
+            })
+        }).collect();
+        for writer in writers {
+            writer.join().unwrap();
+        }
+
+        let config: Favorites = read_json(&path);
+        assert_eq!(config.paths.len(), 50);
+        for writer in 0..2 {
+            let mine: Vec<_> = config.paths.iter().filter(|p| p.starts_with(&format!("/music/{}/", writer))).collect();
+            assert_eq!(mine.len(), 25);
+        }
+    }
+
+    #[test]
+    fn unchanged_config_is_not_rewritten() {
+        let dir = temp_dir();
+        let path = dir.path().join("config.json");
+        update_json(&path, |config: &mut Favorites| {
+            config.paths.push("/music".to_string());
+            Ok(())
+        }).unwrap();
+
+        set_modified(&path, SystemTime::UNIX_EPOCH + Duration::from_secs(1_000_000));
+        update_json(&path, |_: &mut Favorites| Ok(())).unwrap();
+        assert_eq!(modified(&path), Some(SystemTime::UNIX_EPOCH + Duration::from_secs(1_000_000)));
+    }
+
+    #[test]
+    fn only_external_changes_are_reported() {
+        let dir = temp_dir();
+        let path = dir.path().join("config.json");
+        let watched = vec![("locations", path.clone())];
+        write_json(&path, &Favorites::default()).unwrap();
+        assert!(changed_files(&watched).is_empty());
+
+        // Our own write is already known
+        write_json(&path, &Favorites { paths: vec!["/a".to_string()] }).unwrap();
+        assert!(changed_files(&watched).is_empty());
+
+        // Another instance's write moves the mtime without going through `write_json`
+        fs::write(&path, r#"{"paths":["/b"]}"#).unwrap();
+        set_modified(&path, SystemTime::UNIX_EPOCH + Duration::from_secs(2_000_000));
+        assert_eq!(changed_files(&watched).len(), 1);
+        assert!(changed_files(&watched).is_empty());
+        assert_eq!(read_json::<Favorites>(&path).paths, ["/b"]);
+    }
+}
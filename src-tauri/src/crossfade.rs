@@ -0,0 +1,170 @@
+//! Crossfading between tracks. The outgoing track keeps its own sink
+//! while it fades out, and the incoming one fades in over the same time. Only
+//! automatic advances and `skip_track` fade; stop, pause and picking another
+//! track cut straight over.
+
+use std::f32::consts::FRAC_PI_2;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+use once_cell::sync::Lazy;
+use parking_lot::Mutex;
+use rodio::Sink;
+use log::info;
+use crate::commands::{open_track, play_opened, record_played_location, start_playback};
+use crate::resume::remember_current_position;
+use crate::config::{load_player_config, update_player_config};
+use crate::output_format::PlaybackError;
+use crate::{PlayerHandle, PlayerState};
+
+const FADE_STEP: Duration = Duration::from_millis(50);
+const MAX_CROSSFADE_SECS: f32 = 12.0;
+
+// Fade length while crossfade is on, cached so the watcher needn't read the config every poll
+static CROSSFADE: Lazy<Mutex<Option<Duration>>> = Lazy::new(|| {
+    let settings = load_player_config().playback_settings;
+    Mutex::new(fade_length(settings.crossfade, settings.crossfade_duration))
+});
+
+// Bumped by every fade and every cut, so a superseded fade stops adjusting volumes
+static FADE_ID: AtomicU64 = AtomicU64::new(0);
+
+fn fade_length(enabled: bool, seconds: f32) -> Option<Duration> {
+    (enabled && seconds > 0.0).then(|| Duration::from_secs_f32(seconds.min(MAX_CROSSFADE_SECS)))
+}
+
+/// The fade length if crossfade is on.
+pub fn crossfade_length() -> Option<Duration> {
+    *CROSSFADE.lock()
+}
+
+/// Whether the current track is close enough to its end to start fading into
+/// the next. Tracks of unknown length just run out and follow without a fade.
+pub fn due(player: &PlayerState) -> bool {
+    let Some(fade) = crossfade_length() else { return false };
+    // A track already queued gaplessly behind this one follows without a fade
+    if player.preloaded.is_some() {
+        return false;
+    }
+    match (&player.sink, player.trim_end.or(player.duration)) {
+        (Some(sink), Some(duration)) => !sink.empty() && duration.saturating_sub(player.position()) <= fade,
+        _ => false,
+    }
+}
+
+/// Equal-power gains for the outgoing and incoming track at `progress` (0 to 1)
+/// through a fade, so the overall loudness doesn't dip in the middle.
+fn fade_gains(progress: f32) -> (f32, f32) {
+    let angle = progress.clamp(0.0, 1.0) * FRAC_PI_2;
+    (angle.cos(), angle.sin())
+}
+
+/// Ends any fade in progress: the outgoing track stops at once.
+pub fn cut(player: &mut PlayerState) {
+    FADE_ID.fetch_add(1, Ordering::SeqCst);
+    if let Some(sink) = player.fading.take() {
+        sink.stop();
+    }
+}
+
+/// Starts `path`, fading out whatever is playing when crossfade is on.
+pub fn start_next(player: &PlayerHandle, path: &str) -> Result<(), PlaybackError> {
+    let Some(fade) = crossfade_length() else { return start_playback(player, path, false) };
+    remember_current_position(player);
+    let opened = open_track(path, None);
+    let result = player.with(move |player| {
+        let outgoing = player.sink.take();
+        // Even if the next track won't start, the current one still fades out
+        let result = opened.and_then(|track| play_opened(player, track, true, false));
+
+        let fade_id = FADE_ID.fetch_add(1, Ordering::SeqCst) + 1;
+        player.fading = outgoing;
+        let incoming = result.is_ok().then(|| player.sink.clone()).flatten();
+        if let Some(outgoing) = player.fading.clone() {
+            run_fade(player, fade_id, fade, outgoing, incoming);
+        }
+        result
+    });
+    if result.is_ok() {
+        info!("Crossfading into {} over {:?}", path, fade);
+        record_played_location(path);
+    }
+    result
+}
+
+/// Steps the volumes of both tracks on the playback thread until the fade is
+/// done or superseded.
+fn run_fade(player: &mut PlayerState, fade_id: u64, fade: Duration, outgoing: Arc<Sink>, incoming: Option<Arc<Sink>>) {
+    let start_gain = outgoing.volume();
+    let steps = (fade.as_millis() / FADE_STEP.as_millis()).max(1) as u32;
+    let mut step = 0;
+    player.every(FADE_STEP, move |player| {
+        step += 1;
+        if FADE_ID.load(Ordering::SeqCst) == fade_id && step <= steps {
+            let (out_gain, in_gain) = fade_gains(step as f32 / steps as f32);
+            outgoing.set_volume(if player.muted { 0.0 } else { start_gain * out_gain });
+            if let Some(sink) = &incoming {
+                sink.set_volume(player.output_gain() * in_gain);
+            }
+            if step < steps {
+                return true;
+            }
+        }
+
+        // A cut mid-fade leaves the incoming track at full volume, unless it has
+        // since become the outgoing side of a newer fade or is fading for a pause
+        let still_current = |sink: &&Arc<Sink>| {
+            player.is_playing && player.sink.as_ref().is_some_and(|current| Arc::ptr_eq(current, sink))
+        };
+        if let Some(sink) = incoming.as_ref().filter(still_current) {
+            sink.set_volume(player.output_gain());
+        }
+        if FADE_ID.load(Ordering::SeqCst) == fade_id {
+            // Stopping the sink releases the outgoing file
+            if let Some(sink) = player.fading.take() {
+                sink.stop();
+            }
+        }
+        false
+    });
+}
+
+/// Turns crossfade on or off and sets its length in seconds, for this session
+/// and in the saved settings.
+#[tauri::command]
+pub fn set_crossfade(enabled: bool, duration: f32) -> Result<(), String> {
+    if !duration.is_finite() || duration <= 0.0 || duration > MAX_CROSSFADE_SECS {
+        return Err(format!("Crossfade duration must be between 0 and {} seconds", MAX_CROSSFADE_SECS));
+    }
+    update_player_config(|config| {
+        config.playback_settings.crossfade = enabled;
+        config.playback_settings.crossfade_duration = duration;
+        Ok(())
+    })?;
+    *CROSSFADE.lock() = fade_length(enabled, duration);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn gains_move_from_outgoing_to_incoming_at_constant_power() {
+        assert_eq!(fade_gains(0.0), (1.0, 0.0));
+        let (out_gain, in_gain) = fade_gains(1.0);
+        assert!(out_gain.abs() < 1e-6 && (in_gain - 1.0).abs() < 1e-6);
+        for progress in [0.25, 0.5, 0.75] {
+            let (out_gain, in_gain) = fade_gains(progress);
+            assert!((out_gain * out_gain + in_gain * in_gain - 1.0).abs() < 1e-5);
+        }
+    }
+
+    #[test]
+    fn disabled_or_empty_fades_have_no_length() {
+        assert_eq!(fade_length(false, 4.0), None);
+        assert_eq!(fade_length(true, 0.0), None);
+        assert_eq!(fade_length(true, 4.0), Some(Duration::from_secs(4)));
+        assert_eq!(fade_length(true, 60.0), Some(Duration::from_secs_f32(MAX_CROSSFADE_SECS)));
+    }
+}
@@ -0,0 +1,315 @@
+//! CUE sheets: a single-file rip plus a `.cue` describing where each track
+//! starts. Tracks play as regions of the file; position and duration are then
+//! reported relative to the track, and a monitor moves on (or stops) when
+//! playback reaches the next track's start.
+
+use serde::Serialize;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Duration;
+use tauri::{AppHandle, Emitter, Manager};
+use log::{error, info};
+use crate::commands::{start_playback_at, stop_audio};
+use crate::library::is_audio_file;
+use crate::output_format::{report_failure, PlaybackError};
+use crate::queue::PlaybackEnded;
+use crate::PlayerHandle;
+
+// CUE positions count frames of 1/75 s, as on a CD
+const FRAMES_PER_SECOND: f64 = 75.0;
+const CUE_POLL: Duration = Duration::from_millis(50);
+
+// Bumped whenever a CUE track is started, so an older monitor stops
+static CUE_ID: AtomicU64 = AtomicU64::new(0);
+
+#[derive(Debug, Serialize, Clone, PartialEq)]
+pub struct CueTrack {
+    pub number: u32,
+    pub title: Option<String>,
+    pub performer: Option<String>,
+    // The audio file holding the track
+    pub file: String,
+    // Seconds into `file` of INDEX 01
+    pub start: f64,
+    // Where the next track in the same file starts; None runs to the end of the file
+    pub end: Option<f64>,
+}
+
+#[derive(Debug, Serialize, Clone)]
+pub struct CueSheet {
+    pub path: String,
+    pub title: Option<String>,
+    pub performer: Option<String>,
+    pub tracks: Vec<CueTrack>,
+}
+
+/// The CUE track being played, as stored in the player state.
+#[derive(Debug, Serialize, Clone, PartialEq)]
+pub struct CueRegion {
+    pub cue_path: String,
+    pub index: usize,
+    pub start: f64,
+    pub end: Option<f64>,
+}
+
+impl CueRegion {
+    fn new(cue_path: &str, index: usize, track: &CueTrack) -> Self {
+        Self { cue_path: cue_path.to_string(), index, start: track.start, end: track.end }
+    }
+
+    pub fn start(&self) -> Duration {
+        Duration::from_secs_f64(self.start)
+    }
+
+    pub fn end(&self) -> Option<Duration> {
+        self.end.map(Duration::from_secs_f64)
+    }
+}
+
+#[derive(Debug, Serialize, Clone)]
+pub struct CueTrackChanged {
+    pub cue_path: String,
+    pub index: usize,
+    pub track: CueTrack,
+}
+
+/// `mm:ss:ff` to seconds. Minutes may go past 59.
+fn parse_time(text: &str) -> Option<f64> {
+    let mut parts = text.split(':').map(|part| part.parse::<u32>().ok());
+    let (Some(Some(minutes)), Some(Some(seconds)), Some(Some(frames)), None) = (parts.next(), parts.next(), parts.next(), parts.next()) else {
+        return None;
+    };
+    Some(minutes as f64 * 60.0 + seconds as f64 + frames as f64 / FRAMES_PER_SECOND)
+}
+
+/// Splits a possibly quoted value off the front of `rest`.
+fn take_value(rest: &str) -> (String, &str) {
+    let rest = rest.trim_start();
+    if let Some(quoted) = rest.strip_prefix('"') {
+        return match quoted.find('"') {
+            Some(end) => (quoted[..end].to_string(), &quoted[end + 1..]),
+            None => (quoted.to_string(), ""),
+        };
+    }
+    let end = rest.find(char::is_whitespace).unwrap_or(rest.len());
+    (rest[..end].to_string(), &rest[end..])
+}
+
+// Title, performer and tracks of a sheet
+type ParsedSheet = (Option<String>, Option<String>, Vec<CueTrack>);
+
+/// Parses the text of a sheet. Files are left as written; `end` is filled in
+/// from the following track in the same file.
+fn parse(text: &str) -> Result<ParsedSheet, String> {
+    let (mut title, mut performer) = (None, None);
+    let mut file: Option<String> = None;
+    let mut tracks: Vec<CueTrack> = Vec::new();
+    // Set between a TRACK line and its INDEX 01
+    let mut pending: Option<CueTrack> = None;
+
+    for (line_number, line) in text.lines().enumerate() {
+        let line = line.trim().trim_start_matches('\u{feff}');
+        let (command, rest) = line.split_once(char::is_whitespace).unwrap_or((line, ""));
+        match command.to_ascii_uppercase().as_str() {
+            "FILE" => file = Some(take_value(rest).0),
+            "TRACK" => {
+                if let Some(track) = pending.take() {
+                    return Err(format!("Track {} has no INDEX 01", track.number));
+                }
+                let number = take_value(rest).0.parse()
+                    .map_err(|_| format!("Line {}: bad track number", line_number + 1))?;
+                let file = file.clone().ok_or_else(|| format!("Line {}: TRACK before any FILE", line_number + 1))?;
+                pending = Some(CueTrack { number, title: None, performer: None, file, start: 0.0, end: None });
+            }
+            "TITLE" => match pending.as_mut() {
+                Some(track) => track.title = Some(take_value(rest).0),
+                None => title = Some(take_value(rest).0),
+            },
+            "PERFORMER" => match pending.as_mut() {
+                Some(track) => track.performer = Some(take_value(rest).0),
+                None => performer = Some(take_value(rest).0),
+            },
+            "INDEX" => {
+                let (index, rest) = take_value(rest);
+                if index.parse::<u32>() != Ok(1) {
+                    continue;
+                }
+                let Some(mut track) = pending.take() else { continue };
+                track.start = parse_time(take_value(rest).0.as_str())
+                    .ok_or_else(|| format!("Line {}: bad index time", line_number + 1))?;
+                tracks.push(track);
+            }
+            _ => {}
+        }
+    }
+    if let Some(track) = pending {
+        return Err(format!("Track {} has no INDEX 01", track.number));
+    }
+    if tracks.is_empty() {
+        return Err("The CUE sheet lists no tracks".to_string());
+    }
+
+    for index in 1..tracks.len() {
+        if tracks[index].file == tracks[index - 1].file {
+            tracks[index - 1].end = Some(tracks[index].start);
+        }
+    }
+    Ok((title, performer, tracks))
+}
+
+/// The audio file a sheet refers to. Rips are often re-encoded without the
+/// sheet being updated, so a missing file falls back to one with the same
+/// name and another audio extension.
+fn resolve_file(dir: &Path, name: &str) -> PathBuf {
+    let path = dir.join(name.replace('\\', "/"));
+    if path.is_file() {
+        return path;
+    }
+    let stem = path.file_stem().map(|stem| stem.to_os_string());
+    fs::read_dir(dir).ok()
+        .and_then(|entries| {
+            entries.flatten()
+                .map(|entry| entry.path())
+                .find(|candidate| candidate.file_stem().map(|stem| stem.to_os_string()) == stem && is_audio_file(candidate))
+        })
+        .unwrap_or(path)
+}
+
+pub fn load_cue_sheet(path: &str) -> Result<CueSheet, String> {
+    let bytes = fs::read(path).map_err(|e| format!("Failed to read {}: {}", path, e))?;
+    // Older sheets are usually Latin-1 rather than UTF-8
+    let text = String::from_utf8(bytes)
+        .unwrap_or_else(|e| e.into_bytes().iter().map(|byte| *byte as char).collect());
+    let (title, performer, mut tracks) = parse(&text).map_err(|e| format!("{}: {}", path, e))?;
+    let dir = Path::new(path).parent().unwrap_or(Path::new(""));
+    for track in tracks.iter_mut() {
+        track.file = resolve_file(dir, &track.file).to_string_lossy().to_string();
+    }
+    Ok(CueSheet { path: path.to_string(), title, performer, tracks })
+}
+
+/// The tracks of a CUE sheet, for listing them as if they were files.
+#[tauri::command]
+pub fn get_cue_sheet(path: String) -> Result<CueSheet, String> {
+    load_cue_sheet(&path)
+}
+
+/// Plays track `track_index` (counting from 0) of the sheet at `cue_path`.
+/// When it ends playback carries on into the next track of the sheet, or
+/// stops there if `continue_sheet` is false.
+#[tauri::command]
+pub fn play_cue_track(app: AppHandle, cue_path: String, track_index: usize, continue_sheet: Option<bool>) -> Result<CueTrack, PlaybackError> {
+    let sheet = load_cue_sheet(&cue_path)?;
+    let track = sheet.tracks.get(track_index).cloned()
+        .ok_or_else(|| format!("{} has no track {}", cue_path, track_index + 1))?;
+    start_track(&app.state(), &sheet, track_index)?;
+    let id = CUE_ID.fetch_add(1, Ordering::SeqCst) + 1;
+    info!("Playing track {} of {}", track.number, cue_path);
+    std::thread::spawn(move || monitor(&app, id, sheet, continue_sheet.unwrap_or(true)));
+    Ok(track)
+}
+
+fn start_track(player: &PlayerHandle, sheet: &CueSheet, index: usize) -> Result<(), PlaybackError> {
+    let track = &sheet.tracks[index];
+    start_playback_at(player, &track.file, false, Some(Duration::from_secs_f64(track.start)))?;
+    let cue = CueRegion::new(&sheet.path, index, track);
+    player.send(move |player| player.cue_track = Some(cue));
+    Ok(())
+}
+
+/// Moves on when playback reaches the end of the current CUE track. The next
+/// track in the same file just carries on; one in another file is started.
+fn monitor(app: &AppHandle, id: u64, sheet: CueSheet, continue_sheet: bool) {
+    let handle = app.state::<PlayerHandle>();
+    while CUE_ID.load(Ordering::SeqCst) == id {
+        std::thread::sleep(CUE_POLL);
+        let (cue_path, files) = (sheet.path.clone(), sheet.tracks.iter().map(|track| track.file.clone()).collect::<Vec<_>>());
+        let ended = handle.with(move |player| {
+            let cue = player.cue_track.as_ref().filter(|cue| cue.cue_path == cue_path)?;
+            if player.current_path.as_deref() != Some(files[cue.index].as_str()) {
+                return None;
+            }
+            Some(cue.end().filter(|end| player.is_playing && player.position() >= *end).map(|_| cue.index))
+        });
+        let Some(ended) = ended else { return };
+        let Some(index) = ended else { continue };
+
+        let next = index + 1;
+        if !continue_sheet || next >= sheet.tracks.len() {
+            if let Err(e) = stop_audio(app.state()) {
+                error!("Failed to stop at the end of a CUE track: {}", e);
+            }
+            app.emit("playback-ended", PlaybackEnded { last_path: Some(sheet.tracks[index].file.clone()), error: None }).ok();
+            return;
+        }
+        let track = sheet.tracks[next].clone();
+        if track.file == sheet.tracks[index].file {
+            let cue = CueRegion::new(&sheet.path, next, &track);
+            handle.send(move |player| player.cue_track = Some(cue));
+        } else if let Err(e) = start_track(&handle, &sheet, next) {
+            error!("Failed to start track {} of {}: {}", track.number, sheet.path, e);
+            report_failure(app, &e, Some(&track.file));
+            return;
+        }
+        app.emit("cue-track-changed", CueTrackChanged { cue_path: sheet.path.clone(), index: next, track }).ok();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SHEET: &str = "\u{feff}REM GENRE Jazz
+PERFORMER \"The Band\"
+TITLE \"Live Set\"
+FILE \"Live Set.flac\" WAVE
+  TRACK 01 AUDIO
+    TITLE \"Opening\"
+    INDEX 01 00:00:00
+  TRACK 02 AUDIO
+    TITLE \"Second Tune\"
+    PERFORMER \"Guest\"
+    INDEX 00 04:58:00
+    INDEX 01 05:01:37
+FILE encore.flac WAVE
+  TRACK 03 AUDIO
+    TITLE Encore
+    INDEX 01 00:00:00
+";
+
+    #[test]
+    fn parses_tracks_and_ends_them_at_the_next_start_in_the_same_file() {
+        let (title, performer, tracks) = parse(SHEET).unwrap();
+        assert_eq!(title.as_deref(), Some("Live Set"));
+        assert_eq!(performer.as_deref(), Some("The Band"));
+        assert_eq!(tracks.len(), 3);
+
+        assert_eq!(tracks[0].title.as_deref(), Some("Opening"));
+        assert_eq!(tracks[0].file, "Live Set.flac");
+        assert_eq!(tracks[0].end, Some(tracks[1].start));
+        assert!((tracks[1].start - (301.0 + 37.0 / 75.0)).abs() < 1e-9);
+        assert_eq!(tracks[1].performer.as_deref(), Some("Guest"));
+        // The last track of a file runs to its end
+        assert_eq!(tracks[1].end, None);
+        assert_eq!(tracks[2].file, "encore.flac");
+        assert_eq!(tracks[2].title.as_deref(), Some("Encore"));
+    }
+
+    #[test]
+    fn rejects_sheets_without_usable_tracks() {
+        assert!(parse("FILE a.flac WAVE\nTRACK 01 AUDIO\nTITLE x\n").unwrap_err().contains("no INDEX 01"));
+        assert!(parse("TRACK 01 AUDIO\nINDEX 01 00:00:00\n").unwrap_err().contains("before any FILE"));
+        assert!(parse("REM nothing here\n").is_err());
+        assert_eq!(parse_time("75:00:74"), Some(4500.0 + 74.0 / 75.0));
+        assert_eq!(parse_time("1:2"), None);
+    }
+
+    #[test]
+    fn falls_back_to_a_re_encoded_file_with_the_same_name() {
+        let dir = crate::test_support::temp_dir();
+        fs::write(dir.path().join("album.flac"), b"").unwrap();
+        assert_eq!(resolve_file(dir.path(), "album.wav"), dir.path().join("album.flac"));
+        assert_eq!(resolve_file(dir.path(), "other.wav"), dir.path().join("other.wav"));
+    }
+}
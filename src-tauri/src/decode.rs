@@ -0,0 +1,180 @@
+use rodio::{Decoder, Source};
+use std::fs::{self, File};
+use std::io::{BufReader, Read};
+use std::path::Path;
+use std::time::Duration;
+use lofty::{prelude::AudioFile, probe::Probe};
+
+/// Interleaved samples decoded from part of a file, normalized to -1.0..1.0.
+pub struct DecodedAudio {
+    pub samples: Vec<f32>,
+    pub channels: u16,
+    pub sample_rate: u32,
+}
+
+impl DecodedAudio {
+    pub fn frames(&self) -> usize {
+        self.samples.len() / self.channels.max(1) as usize
+    }
+
+    /// Averages all channels into a single mono signal.
+    pub fn mono(&self) -> Vec<f32> {
+        let channels = self.channels.max(1) as usize;
+        self.samples
+            .chunks(channels)
+            .map(|frame| frame.iter().sum::<f32>() / frame.len() as f32)
+            .collect()
+    }
+
+    pub fn frames_for(&self, duration: Duration) -> usize {
+        (duration.as_secs_f64() * self.sample_rate as f64) as usize
+    }
+}
+
+pub fn open_decoder(path: &Path) -> Result<Decoder<BufReader<File>>, String> {
+    let file = File::open(path).map_err(|e| format!("Failed to open {}: {}", path.display(), e))?;
+    Decoder::new(BufReader::new(file)).map_err(|e| format!("Failed to decode {}: {}", path.display(), e))
+}
+
+/// Duration from the container headers, which is cheap and works for formats
+/// where the decoder can't report a total duration.
+pub fn probe_duration(path: &Path) -> Option<Duration> {
+    let tagged_file = Probe::open(path).ok()?.read().ok()?;
+    let duration = tagged_file.properties().duration();
+    if duration.is_zero() { None } else { Some(duration) }
+}
+
+// How much of the file to search for an MPEG frame header
+const HEADER_SEARCH_BYTES: u64 = 64 * 1024;
+
+// Layer III bitrates in kbps by the header's bitrate index
+const MPEG1_BITRATES: [u32; 15] = [0, 32, 40, 48, 56, 64, 80, 96, 112, 128, 160, 192, 224, 256, 320];
+const MPEG2_BITRATES: [u32; 15] = [0, 8, 16, 24, 32, 40, 48, 56, 64, 80, 96, 112, 128, 144, 160];
+
+/// Bitrate in kbps from a Layer III frame header, the format that most often
+/// lacks a duration.
+fn frame_bitrate(header: &[u8]) -> Option<u32> {
+    let [sync, flags, rates, ..] = *header else { return None };
+    if sync != 0xFF || flags & 0xE0 != 0xE0 {
+        return None;
+    }
+    let version = (flags >> 3) & 0b11;
+    let layer = (flags >> 1) & 0b11;
+    let bitrate_index = (rates >> 4) as usize;
+    let rate_index = (rates >> 2) & 0b11;
+    if version == 1 || layer != 1 || bitrate_index == 0 || bitrate_index == 15 || rate_index == 3 {
+        return None;
+    }
+    let table = if version == 3 { &MPEG1_BITRATES } else { &MPEG2_BITRATES };
+    Some(table[bitrate_index])
+}
+
+/// Length of the ID3v2 tag at the start of `data`, 0 when there is none.
+fn id3v2_size(data: &[u8]) -> usize {
+    match data {
+        [b'I', b'D', b'3', _, _, _, size @ ..] if size.len() >= 4 => {
+            10 + size[..4].iter().fold(0, |total, byte| (total << 7) | (*byte & 0x7F) as usize)
+        }
+        _ => 0,
+    }
+}
+
+/// Bitrate of the first MPEG frame in `data`, after any ID3v2 tag.
+fn stream_bitrate(data: &[u8]) -> Option<u32> {
+    data.get(id3v2_size(data)..)?.windows(4).find_map(frame_bitrate)
+}
+
+fn header_bitrate(path: &Path) -> Option<u32> {
+    let mut file = File::open(path).ok()?;
+    let mut head = [0u8; 10];
+    file.read_exact(&mut head).ok()?;
+    // An ID3v2 tag with large artwork can push the first frame a long way in
+    let mut data = head.to_vec();
+    file.take(id3v2_size(&head) as u64 + HEADER_SEARCH_BYTES).read_to_end(&mut data).ok()?;
+    stream_bitrate(&data)
+}
+
+/// A last-resort duration from the file size and its bitrate, for files whose
+/// decoder and headers both can't say. Exact only for constant bitrates.
+pub fn estimate_duration(path: &Path) -> Option<Duration> {
+    let size = fs::metadata(path).ok()?.len();
+    let tagged_bitrate = Probe::open(path).ok()
+        .and_then(|probe| probe.read().ok())
+        .and_then(|tagged_file| tagged_file.properties().overall_bitrate().or(tagged_file.properties().audio_bitrate()));
+    let kbps = tagged_bitrate.filter(|kbps| *kbps > 0).or_else(|| header_bitrate(path))?;
+    Some(Duration::from_secs_f64(size as f64 * 8.0 / (kbps as f64 * 1000.0)))
+}
+
+/// Decodes `length` of audio starting at `start` (or to the end of the file when
+/// `length` is None). Seeks when the decoder supports it and otherwise decodes
+/// and discards up to the start position.
+pub fn decode_range(path: &Path, start: Duration, length: Option<Duration>) -> Result<DecodedAudio, String> {
+    let mut decoder = open_decoder(path)?;
+    let channels = decoder.channels();
+    let sample_rate = decoder.sample_rate();
+
+    if !start.is_zero() && decoder.try_seek(start).is_err() {
+        let skip = (start.as_secs_f64() * sample_rate as f64) as usize * channels as usize;
+        decoder.by_ref().take(skip).for_each(drop);
+    }
+
+    let limit = length
+        .map(|length| (length.as_secs_f64() * sample_rate as f64) as usize * channels as usize)
+        .unwrap_or(usize::MAX);
+
+    let samples = decoder
+        .take(limit)
+        .map(|sample| sample as f32 / i16::MAX as f32)
+        .collect();
+
+    Ok(DecodedAudio { samples, channels, sample_rate })
+}
+
+pub fn rms(samples: &[f32]) -> f32 {
+    if samples.is_empty() {
+        return 0.0;
+    }
+    (samples.iter().map(|s| s * s).sum::<f32>() / samples.len() as f32).sqrt()
+}
+
+/// RMS level in dBFS, floored at -120 dB for digital silence.
+pub fn rms_db(samples: &[f32]) -> f32 {
+    let level = rms(samples);
+    if level <= 1e-6 { -120.0 } else { 20.0 * level.log10() }
+}
+
+/// How many blocks of `block_frames` at the start (or end, when `from_end`) of
+/// a mono signal are quieter than `threshold_db`.
+pub fn silent_blocks(mono: &[f32], block_frames: usize, threshold_db: f32, from_end: bool) -> usize {
+    if block_frames == 0 {
+        return 0;
+    }
+    let silent = |block: &&[f32]| rms_db(block) < threshold_db;
+    if from_end {
+        mono.chunks(block_frames).rev().take_while(silent).count()
+    } else {
+        mono.chunks(block_frames).take_while(silent).count()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reads_the_bitrate_from_the_first_frame_after_the_tag() {
+        // MPEG-1 Layer III, 128 kbps, 44.1 kHz
+        assert_eq!(frame_bitrate(&[0xFF, 0xFB, 0x90, 0x64]), Some(128));
+        // MPEG-2 Layer III, 64 kbps
+        assert_eq!(frame_bitrate(&[0xFF, 0xF3, 0x80, 0x00]), Some(64));
+        assert_eq!(frame_bitrate(&[0xFF, 0xFB, 0xF0, 0x00]), None);
+        assert_eq!(frame_bitrate(&[0xFF, 0xFD, 0x90, 0x00]), None);
+
+        let mut data = b"ID3\x04\x00\x00\x00\x00\x00\x04".to_vec();
+        // Tag contents that look like a frame header but aren't audio
+        data.extend_from_slice(&[0xFF, 0xFB, 0x50, 0x00]);
+        data.extend_from_slice(&[0x00, 0xFF, 0xFB, 0xB0, 0x00]);
+        assert_eq!(id3v2_size(&data), 14);
+        assert_eq!(stream_bitrate(&data), Some(192));
+    }
+}
@@ -1,11 +1,15 @@
 use serde::{Deserialize, Serialize};
-use std::path::Path;
+use std::path::{Path, PathBuf};
 use tauri::{AppHandle, Emitter, Manager};
-use notify::{Watcher, RecursiveMode, Event};
-use std::sync::mpsc::channel;
+use notify::{Watcher, RecommendedWatcher, RecursiveMode, Event};
+use once_cell::sync::Lazy;
+use parking_lot::Mutex;
+use std::sync::mpsc::{channel, RecvTimeoutError};
 use std::time::Duration;
 use log::{info, error, debug};
 use crate::FileItem;
+use crate::sorting::{fill_added_times, sort_entries, DirSort, SortableEntry};
+use crate::library::added_at;
 
 #[cfg(target_os = "windows")]
 use windows::Win32::Storage::FileSystem::{GetLogicalDrives, GetDriveTypeW};
@@ -16,11 +20,25 @@ use std::process::Command;
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct Device {
-    name: String,
-    path: String,
+    pub name: String,
+    pub path: String,
     #[serde(rename = "deviceType")]
-    device_type: String,
-    removable: bool,
+    pub device_type: String,
+    pub removable: bool,
+}
+
+/// Name and mount point of the removable device `path` lives on, if any.
+/// The innermost mount wins.
+pub fn removable_device_root(path: &Path) -> Option<(String, PathBuf)> {
+    let devices = tauri::async_runtime::block_on(get_connected_devices()).ok()?;
+    devices.into_iter()
+        .filter(|device| device.removable && path.starts_with(&device.path))
+        .max_by_key(|device| device.path.len())
+        .map(|device| (device.name, PathBuf::from(device.path)))
+}
+
+pub fn removable_device_name(path: &Path) -> Option<String> {
+    removable_device_root(path).map(|(name, _)| name)
 }
 
 #[tauri::command]
@@ -190,9 +208,19 @@ async fn get_macos_devices() -> Result<Vec<Device>, String> {
     Ok(devices)
 }
 
-#[tauri::command]
-pub async fn watch_devices(app: AppHandle) -> Result<(), String> {
-    info!("Starting device watcher");
+// Kept alive here; dropping the watcher stops its notifications
+static DEVICE_WATCHER: Lazy<Mutex<Option<RecommendedWatcher>>> = Lazy::new(|| Mutex::new(None));
+
+fn emit_devices(app: &AppHandle, rt: &tokio::runtime::Runtime) {
+    if let Ok(devices) = rt.block_on(get_connected_devices()) {
+        debug!("Emitting devices-changed event with devices: {:?}", devices);
+        if let Err(e) = app.emit("devices-changed", devices) {
+            error!("Failed to emit devices-changed event: {}", e);
+        }
+    }
+}
+
+fn start_device_watcher(app: AppHandle) -> Result<(), String> {
     let (tx, rx) = channel();
     
     let mut watcher = notify::recommended_watcher(move |res: Result<Event, notify::Error>| {
@@ -214,19 +242,21 @@ pub async fn watch_devices(app: AppHandle) -> Result<(), String> {
                 format!("Failed to watch volumes: {}", e)
             })?;
     }
+
+    // Replacing the previous watcher disconnects its channel, which ends its thread
+    *DEVICE_WATCHER.lock() = Some(watcher);
     
     // Spawn a thread to handle device changes
     std::thread::spawn(move || {
         let rt = tokio::runtime::Runtime::new().unwrap();
         loop {
-            if rx.recv_timeout(Duration::from_secs(1)).is_ok() {
-                info!("Device change detected, updating device list");
-                if let Ok(devices) = rt.block_on(get_connected_devices()) {
-                    debug!("Emitting devices-changed event with devices: {:?}", devices);
-                    if let Err(e) = app.emit("devices-changed", devices) {
-                        error!("Failed to emit devices-changed event: {}", e);
-                    }
+            match rx.recv_timeout(Duration::from_secs(1)) {
+                Ok(()) => {
+                    info!("Device change detected, updating device list");
+                    emit_devices(&app, &rt);
                 }
+                Err(RecvTimeoutError::Timeout) => {}
+                Err(RecvTimeoutError::Disconnected) => break,
             }
         }
     });
@@ -234,8 +264,32 @@ pub async fn watch_devices(app: AppHandle) -> Result<(), String> {
     Ok(())
 }
 
+/// Re-creates the device watcher after a sleep, if one was running, and
+/// reports the current devices since mounts may have changed meanwhile.
+pub fn restart_device_watcher(app: &AppHandle) -> Result<(), String> {
+    if DEVICE_WATCHER.lock().is_none() {
+        return Ok(());
+    }
+    start_device_watcher(app.clone())?;
+    let rt = tokio::runtime::Runtime::new().map_err(|e| e.to_string())?;
+    emit_devices(app, &rt);
+    Ok(())
+}
+
 #[tauri::command]
-pub async fn read_device_dir(device_path: String, relative_path: Option<String>) -> Result<Vec<FileItem>, String> {
+pub async fn watch_devices(app: AppHandle) -> Result<(), String> {
+    info!("Starting device watcher");
+    start_device_watcher(app)
+}
+
+#[tauri::command]
+pub async fn read_device_dir(
+    device_path: String,
+    relative_path: Option<String>,
+    sort: Option<String>,
+    descending: Option<bool>,
+) -> Result<Vec<FileItem>, String> {
+    let sort = DirSort::parse(sort.as_deref())?;
     let base_path = Path::new(&device_path);
     
     // If relative_path is provided, append it to the base device path
@@ -280,12 +334,16 @@ pub async fn read_device_dir(device_path: String, relative_path: Option<String>)
                         false
                     };
 
-                    entries.push(FileItem {
+                    entries.push(SortableEntry::new(FileItem {
                         name,
                         path: path_str,
                         is_dir: metadata.is_dir(),
                         is_audio,
-                    });
+                        modified: None,
+                        added_at: None,
+                        excluded: false,
+                        missing: false,
+                    }, &metadata));
                 }
                 Err(e) => {
                     error!("Failed to read metadata for {}: {}", path_str, e);
@@ -295,13 +353,9 @@ pub async fn read_device_dir(device_path: String, relative_path: Option<String>)
         }
     }
 
-    // Sort directories first, then files alphabetically
-    entries.sort_by(|a, b| match (a.is_dir, b.is_dir) {
-        (true, false) => std::cmp::Ordering::Less,
-        (false, true) => std::cmp::Ordering::Greater,
-        _ => a.name.to_lowercase().cmp(&b.name.to_lowercase()),
-    });
-
     debug!("Found {} entries in device directory", entries.len());
-    Ok(entries)
+
+    // Sort directories first, then files by the requested key
+    fill_added_times(&mut entries, added_at);
+    Ok(sort_entries(entries, sort, descending.unwrap_or(false)))
 }
@@ -0,0 +1,263 @@
+use serde::Serialize;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use tauri::AppHandle;
+use log::info;
+use crate::file_count::{list_counted, CountFilter};
+use crate::library::track_info;
+use crate::tasks::{start_task, TaskHandle, TaskKind};
+use crate::transfer::{cached_file_checksum, TransferOptions};
+use crate::text::fold;
+
+// Encoders and taggers can shift the reported length slightly between copies of one song
+const DURATION_TOLERANCE_SECS: f64 = 2.0;
+
+#[derive(Debug, Serialize, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum MatchBy {
+    RelativePath,
+    Metadata,
+}
+
+impl MatchBy {
+    pub fn parse(value: &str) -> Result<Self, String> {
+        match value.to_lowercase().as_str() {
+            "relative_path" | "path" => Ok(MatchBy::RelativePath),
+            "metadata" => Ok(MatchBy::Metadata),
+            other => Err(format!("Unknown match mode: {}", other)),
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum DiffMismatch {
+    Size,
+    Checksum,
+}
+
+#[derive(Debug, Serialize, Clone)]
+pub struct DiffEntry {
+    // Relative to the library root when the file is in the library, otherwise to the device folder
+    pub relative_path: String,
+    pub library_path: Option<String>,
+    pub device_path: Option<String>,
+    pub library_size: Option<u64>,
+    pub device_size: Option<u64>,
+    pub mismatch: Option<DiffMismatch>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct DeviceDiff {
+    pub library_root: String,
+    pub device_music_path: String,
+    pub match_by: MatchBy,
+    pub only_in_library: Vec<DiffEntry>,
+    pub only_on_device: Vec<DiffEntry>,
+    pub different: Vec<DiffEntry>,
+    // Ready to pass to `transfer_files` to copy the differences onto the device
+    pub sync_options: TransferOptions,
+}
+
+// Indexes into the library and device listings; one side is empty for unmatched files
+type Pairing = (Option<usize>, Option<usize>);
+
+struct SideFile {
+    path: PathBuf,
+    relative_path: String,
+    size: u64,
+}
+
+fn relative_string(path: &Path, root: &Path) -> String {
+    path.strip_prefix(root)
+        .unwrap_or(path)
+        .components()
+        .map(|c| c.as_os_str().to_string_lossy())
+        .collect::<Vec<_>>()
+        .join("/")
+}
+
+/// Lists the audio files under `root`, reporting the running count since
+/// enumerating a slow device can take a while before any comparing starts.
+fn list_side(root: &Path, label: &str, filter: &CountFilter, task: &TaskHandle) -> Result<Vec<SideFile>, String> {
+    let (files, _, _) = list_counted(root, filter, &|| task.is_cancelled(), &mut |running| {
+        task.set_message(format!("Listing {}: {} files", label, running.file_count));
+    }).ok_or_else(|| "Comparison cancelled".to_string())?;
+    Ok(files.into_iter()
+        .map(|file| SideFile { relative_path: relative_string(&file.path, root), path: file.path, size: file.size })
+        .collect())
+}
+
+fn entry(relative_path: &str, library: Option<&SideFile>, device: Option<&SideFile>, mismatch: Option<DiffMismatch>) -> DiffEntry {
+    DiffEntry {
+        relative_path: relative_path.to_string(),
+        library_path: library.map(|file| file.path.to_string_lossy().to_string()),
+        device_path: device.map(|file| file.path.to_string_lossy().to_string()),
+        library_size: library.map(|file| file.size),
+        device_size: device.map(|file| file.size),
+        mismatch,
+    }
+}
+
+fn compare_contents(library: &SideFile, device: &SideFile) -> Result<Option<DiffMismatch>, String> {
+    if library.size != device.size {
+        return Ok(Some(DiffMismatch::Size));
+    }
+    let checksum = |file: &SideFile| cached_file_checksum(&file.path)
+        .map_err(|e| format!("Failed to checksum {}: {}", file.path.display(), e));
+    Ok((checksum(library)? != checksum(device)?).then_some(DiffMismatch::Checksum))
+}
+
+/// Key for metadata matching; untagged files can only be matched by name.
+fn metadata_key(file: &SideFile) -> (String, Option<f64>) {
+    let track = track_info(&file.path);
+    let artist = track.as_ref().and_then(|t| t.artist.clone().or_else(|| t.album_artist.clone()));
+    let title = track.as_ref().and_then(|t| t.title.clone());
+    let duration = track.as_ref().and_then(|t| t.duration);
+
+    match (artist, title) {
+        (Some(artist), Some(title)) => (
+            format!("{}\u{0}{}", fold(&artist), fold(&title)),
+            duration,
+        ),
+        _ => (format!("path\u{0}{}", file.relative_path.to_lowercase()), None),
+    }
+}
+
+/// Pairs each library file with at most one device file.
+fn pair_files(library: &[SideFile], device: &[SideFile], match_by: MatchBy, task: &TaskHandle) -> Result<Vec<Pairing>, String> {
+    let total = (library.len() + device.len()) as u64;
+    let mut device_used = vec![false; device.len()];
+    let mut pairs = Vec::new();
+
+    match match_by {
+        MatchBy::RelativePath => {
+            // Device filesystems are usually FAT/exFAT, which don't preserve case reliably
+            let by_path: HashMap<String, usize> = device.iter()
+                .enumerate()
+                .map(|(index, file)| (file.relative_path.to_lowercase(), index))
+                .collect();
+            for (index, file) in library.iter().enumerate() {
+                let matched = by_path.get(&file.relative_path.to_lowercase()).copied();
+                if let Some(device_index) = matched {
+                    device_used[device_index] = true;
+                }
+                pairs.push((Some(index), matched));
+            }
+        }
+        MatchBy::Metadata => {
+            let mut by_key: HashMap<String, Vec<(usize, Option<f64>)>> = HashMap::new();
+            for (index, file) in device.iter().enumerate() {
+                if task.is_cancelled() {
+                    return Err("Comparison cancelled".to_string());
+                }
+                let (key, duration) = metadata_key(file);
+                by_key.entry(key).or_default().push((index, duration));
+                task.set_progress(index as u64 + 1, total);
+            }
+
+            for (index, file) in library.iter().enumerate() {
+                if task.is_cancelled() {
+                    return Err("Comparison cancelled".to_string());
+                }
+                let (key, duration) = metadata_key(file);
+                let matched = by_key.get(&key).and_then(|candidates| {
+                    candidates.iter()
+                        .find(|(candidate, candidate_duration)| !device_used[*candidate] && match (duration, candidate_duration) {
+                            (Some(a), Some(b)) => (a - b).abs() <= DURATION_TOLERANCE_SECS,
+                            _ => true,
+                        })
+                        .map(|(candidate, _)| *candidate)
+                });
+                if let Some(device_index) = matched {
+                    device_used[device_index] = true;
+                }
+                pairs.push((Some(index), matched));
+                task.set_progress((device.len() + index) as u64 + 1, total);
+            }
+        }
+    }
+
+    pairs.extend(device_used.iter().enumerate().filter(|(_, used)| !**used).map(|(index, _)| (None, Some(index))));
+    Ok(pairs)
+}
+
+fn run_compare(library_root: &Path, device_root: &Path, match_by: MatchBy, task: &TaskHandle) -> Result<DeviceDiff, String> {
+    let library = list_side(library_root, "library", &CountFilter::default(), task)?;
+    // Exclusion rules describe the library, so the device side is listed in full
+    let device = list_side(device_root, "device", &CountFilter { include_excluded: true, ..Default::default() }, task)?;
+
+    task.set_message("Comparing files...");
+    let pairs = pair_files(&library, &device, match_by, task)?;
+
+    let mut only_in_library = Vec::new();
+    let mut only_on_device = Vec::new();
+    let mut different = Vec::new();
+    let matched_total = pairs.iter().filter(|(l, d)| l.is_some() && d.is_some()).count() as u64;
+    let mut matched_done = 0;
+    task.set_message("Checking matched files...");
+
+    for (library_index, device_index) in pairs {
+        match (library_index.map(|i| &library[i]), device_index.map(|i| &device[i])) {
+            (Some(lib), None) => only_in_library.push(entry(&lib.relative_path, Some(lib), None, None)),
+            (None, Some(dev)) => only_on_device.push(entry(&dev.relative_path, None, Some(dev), None)),
+            (Some(lib), Some(dev)) => {
+                if task.is_cancelled() {
+                    return Err("Comparison cancelled".to_string());
+                }
+                if let Some(mismatch) = compare_contents(lib, dev)? {
+                    different.push(entry(&lib.relative_path, Some(lib), Some(dev), Some(mismatch)));
+                }
+                matched_done += 1;
+                task.set_progress(matched_done, matched_total);
+            }
+            (None, None) => {}
+        }
+    }
+
+    // Metadata matches can live at a different path on the device, so copying the
+    // library file would add a second copy rather than replace it; only new files sync
+    let mut files: Vec<String> = only_in_library.iter().map(|e| e.relative_path.clone()).collect();
+    if match_by == MatchBy::RelativePath {
+        files.extend(different.iter().map(|e| e.relative_path.clone()));
+    }
+
+    info!(
+        "Device diff {} vs {}: {} only in library, {} only on device, {} different",
+        library_root.display(), device_root.display(), only_in_library.len(), only_on_device.len(), different.len()
+    );
+
+    Ok(DeviceDiff {
+        library_root: library_root.to_string_lossy().to_string(),
+        device_music_path: device_root.to_string_lossy().to_string(),
+        match_by,
+        only_in_library,
+        only_on_device,
+        different,
+        sync_options: TransferOptions {
+            source_path: library_root.to_string_lossy().to_string(),
+            target_path: device_root.to_string_lossy().to_string(),
+            create_archive: false,
+            verify_transfer: true,
+            files: Some(files),
+        },
+    })
+}
+
+#[tauri::command]
+pub async fn compare_with_device(app: AppHandle, library_root: String, device_music_path: String, match_by: String) -> Result<DeviceDiff, String> {
+    let match_by = MatchBy::parse(&match_by)?;
+    let library_root = PathBuf::from(library_root);
+    let device_root = PathBuf::from(device_music_path);
+    if !library_root.is_dir() {
+        return Err(format!("Library folder does not exist: {}", library_root.display()));
+    }
+    if !device_root.is_dir() {
+        return Err(format!("Device folder does not exist: {}", device_root.display()));
+    }
+
+    let task = start_task(&app, TaskKind::DeviceDiff, format!("Comparing {} with {}", library_root.display(), device_root.display()));
+    let result = run_compare(&library_root, &device_root, match_by, &task);
+    task.finish(result.as_ref().map(|_| ()).map_err(|e| e.clone()));
+    result
+}
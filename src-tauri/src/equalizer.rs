@@ -0,0 +1,327 @@
+//! Ten-band graphic equalizer on the main playback chain. Every source from
+//! `open_source` is wrapped in an `Equalizer`, which reads the band gains from
+//! a shared parameter block, so changes are heard straight away without
+//! reopening the track.
+
+use serde::Serialize;
+use std::f32::consts::PI;
+use std::sync::atomic::{AtomicBool, AtomicU32, AtomicU64, Ordering};
+use std::time::Duration;
+use once_cell::sync::Lazy;
+use rodio::source::SeekError;
+use rodio::Source;
+use crate::config::{load_player_config, update_player_config};
+
+pub const BAND_COUNT: usize = 10;
+pub const BAND_FREQUENCIES: [f32; BAND_COUNT] = [31.0, 62.0, 125.0, 250.0, 500.0, 1000.0, 2000.0, 4000.0, 8000.0, 16000.0];
+pub const MAX_BAND_DB: f32 = 12.0;
+// Roughly an octave wide, so neighbouring bands overlap without big ripples
+const BAND_Q: f32 = 1.41;
+
+/// Gains and on/off state shared between the commands and every playing source.
+struct EqParams {
+    enabled: AtomicBool,
+    // f32 bits of each band's gain in dB
+    gains: [AtomicU32; BAND_COUNT],
+    // Bumped on every change, so sources know to recompute their filters
+    version: AtomicU64,
+}
+
+static PARAMS: Lazy<EqParams> = Lazy::new(|| {
+    let settings = load_player_config().playback_settings;
+    let bands = settings.effective_eq_bands();
+    EqParams {
+        enabled: AtomicBool::new(settings.eq_enabled),
+        gains: std::array::from_fn(|index| AtomicU32::new(bands[index].to_bits())),
+        version: AtomicU64::new(0),
+    }
+});
+
+fn current_gains() -> [f32; BAND_COUNT] {
+    std::array::from_fn(|index| f32::from_bits(PARAMS.gains[index].load(Ordering::Relaxed)))
+}
+
+fn publish(enabled: bool, bands: &[f32; BAND_COUNT]) {
+    for (gain, value) in PARAMS.gains.iter().zip(bands) {
+        gain.store(value.to_bits(), Ordering::Relaxed);
+    }
+    PARAMS.enabled.store(enabled, Ordering::Relaxed);
+    PARAMS.version.fetch_add(1, Ordering::Release);
+}
+
+/// Normalized biquad coefficients: `b0 b1 b2 a1 a2`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct Coeffs([f32; 5]);
+
+/// A peaking filter around `frequency`, from the RBJ audio EQ cookbook.
+fn peaking(frequency: f32, gain_db: f32, sample_rate: u32) -> Coeffs {
+    let a = 10f32.powf(gain_db / 40.0);
+    let w0 = 2.0 * PI * frequency / sample_rate as f32;
+    let alpha = w0.sin() / (2.0 * BAND_Q);
+    let cos = w0.cos();
+    let a0 = 1.0 + alpha / a;
+    Coeffs([
+        (1.0 + alpha * a) / a0,
+        -2.0 * cos / a0,
+        (1.0 - alpha * a) / a0,
+        -2.0 * cos / a0,
+        (1.0 - alpha / a) / a0,
+    ])
+}
+
+/// Filters for the bands that do something at `sample_rate`. Flat bands and
+/// those too close to the Nyquist frequency are left out.
+fn band_filters(gains: &[f32; BAND_COUNT], sample_rate: u32) -> Vec<Coeffs> {
+    BAND_FREQUENCIES.iter().zip(gains)
+        .filter(|(frequency, gain)| gain.abs() > 0.01 && **frequency < sample_rate as f32 * 0.45)
+        .map(|(frequency, gain)| peaking(*frequency, *gain, sample_rate))
+        .collect()
+}
+
+/// Gain applied before the filters so the loudest boost can't clip.
+fn headroom(gains: &[f32; BAND_COUNT]) -> f32 {
+    let boost = gains.iter().copied().fold(0.0, f32::max);
+    10f32.powf(-boost / 20.0)
+}
+
+/// Filter memory for one band on one channel, in transposed direct form II.
+#[derive(Debug, Clone, Copy, Default)]
+struct State {
+    z1: f32,
+    z2: f32,
+}
+
+impl State {
+    fn process(&mut self, coeffs: &Coeffs, input: f32) -> f32 {
+        let [b0, b1, b2, a1, a2] = coeffs.0;
+        let output = b0 * input + self.z1;
+        self.z1 = b1 * input - a1 * output + self.z2;
+        self.z2 = b2 * input - a2 * output;
+        output
+    }
+}
+
+/// Applies the shared equalizer settings to `inner`.
+pub struct Equalizer<S> {
+    inner: S,
+    version: Option<u64>,
+    channels: u16,
+    sample_rate: u32,
+    filters: Vec<Coeffs>,
+    headroom: f32,
+    // One set of band states per channel
+    states: Vec<Vec<State>>,
+    channel: usize,
+}
+
+impl<S: Source<Item = i16>> Equalizer<S> {
+    pub fn new(inner: S) -> Self {
+        Self {
+            channels: inner.channels(),
+            sample_rate: inner.sample_rate(),
+            inner,
+            version: None,
+            filters: Vec::new(),
+            headroom: 1.0,
+            states: Vec::new(),
+            channel: 0,
+        }
+    }
+
+    /// Picks up new settings or a format change. Called at frame boundaries so
+    /// every channel of a frame goes through the same filters.
+    fn refresh(&mut self) {
+        let version = PARAMS.version.load(Ordering::Acquire);
+        let (channels, sample_rate) = (self.inner.channels(), self.inner.sample_rate());
+        if self.version == Some(version) && channels == self.channels && sample_rate == self.sample_rate {
+            return;
+        }
+        let gains = current_gains();
+        let enabled = PARAMS.enabled.load(Ordering::Relaxed);
+        self.filters = if enabled { band_filters(&gains, sample_rate) } else { Vec::new() };
+        self.headroom = if self.filters.is_empty() { 1.0 } else { headroom(&gains) };
+        // Keeping the memory across a gain change avoids a click; a new band layout or format starts clean
+        let layout_changed = self.states.first().is_none_or(|states| states.len() != self.filters.len())
+            || channels != self.channels
+            || sample_rate != self.sample_rate;
+        if layout_changed {
+            self.states = vec![vec![State::default(); self.filters.len()]; channels.max(1) as usize];
+        }
+        self.version = Some(version);
+        self.channels = channels;
+        self.sample_rate = sample_rate;
+    }
+}
+
+impl<S: Source<Item = i16>> Iterator for Equalizer<S> {
+    type Item = i16;
+
+    fn next(&mut self) -> Option<i16> {
+        let sample = self.inner.next()?;
+        if self.channel == 0 {
+            self.refresh();
+        }
+        let channel = self.channel;
+        self.channel = (self.channel + 1) % self.channels.max(1) as usize;
+        if self.filters.is_empty() {
+            return Some(sample);
+        }
+
+        let mut value = sample as f32 / 32768.0 * self.headroom;
+        let states = &mut self.states[channel];
+        for (state, coeffs) in states.iter_mut().zip(&self.filters) {
+            value = state.process(coeffs, value);
+        }
+        Some((value * 32768.0).clamp(i16::MIN as f32, i16::MAX as f32) as i16)
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.inner.size_hint()
+    }
+}
+
+impl<S: Source<Item = i16>> Source for Equalizer<S> {
+    fn current_frame_len(&self) -> Option<usize> {
+        self.inner.current_frame_len()
+    }
+
+    fn channels(&self) -> u16 {
+        self.inner.channels()
+    }
+
+    fn sample_rate(&self) -> u32 {
+        self.inner.sample_rate()
+    }
+
+    fn total_duration(&self) -> Option<Duration> {
+        self.inner.total_duration()
+    }
+
+    fn try_seek(&mut self, position: Duration) -> Result<(), SeekError> {
+        self.inner.try_seek(position)?;
+        // Ringing from before the seek would otherwise bleed into the new position
+        self.states.iter_mut().flatten().for_each(|state| *state = State::default());
+        self.channel = 0;
+        Ok(())
+    }
+}
+
+#[derive(Debug, Serialize, Clone)]
+pub struct EqualizerSettings {
+    pub enabled: bool,
+    pub bands: Vec<f32>,
+    pub frequencies: Vec<f32>,
+}
+
+#[derive(Debug, Serialize, Clone)]
+pub struct EqualizerPreset {
+    pub name: &'static str,
+    pub bands: [f32; BAND_COUNT],
+}
+
+pub const PRESETS: [EqualizerPreset; 4] = [
+    EqualizerPreset { name: "Flat", bands: [0.0; BAND_COUNT] },
+    EqualizerPreset { name: "Bass boost", bands: [6.0, 5.0, 4.0, 2.5, 1.0, 0.0, 0.0, 0.0, 0.0, 0.0] },
+    EqualizerPreset { name: "Vocal", bands: [-2.0, -2.0, -1.0, 0.0, 2.0, 3.5, 3.5, 2.0, 0.0, -1.0] },
+    EqualizerPreset { name: "Treble boost", bands: [0.0, 0.0, 0.0, 0.0, 0.0, 0.0, 1.5, 3.0, 4.5, 5.5] },
+];
+
+fn validate_bands(bands: &[f32]) -> Result<[f32; BAND_COUNT], String> {
+    let bands: [f32; BAND_COUNT] = bands.try_into()
+        .map_err(|_| format!("The equalizer has {} bands, got {}", BAND_COUNT, bands.len()))?;
+    if bands.iter().any(|gain| !gain.is_finite() || gain.abs() > MAX_BAND_DB) {
+        return Err(format!("Band gains must be between -{0} and {0} dB", MAX_BAND_DB));
+    }
+    Ok(bands)
+}
+
+#[tauri::command]
+pub fn get_equalizer() -> EqualizerSettings {
+    EqualizerSettings {
+        enabled: PARAMS.enabled.load(Ordering::Relaxed),
+        bands: current_gains().to_vec(),
+        frequencies: BAND_FREQUENCIES.to_vec(),
+    }
+}
+
+#[tauri::command]
+pub fn get_equalizer_presets() -> Vec<EqualizerPreset> {
+    PRESETS.to_vec()
+}
+
+/// Sets the gain of every band in dB, lowest band first. Applies to the
+/// playing track at once and is saved for the next session.
+#[tauri::command]
+pub fn set_equalizer(bands: Vec<f32>) -> Result<(), String> {
+    let bands = validate_bands(&bands)?;
+    update_player_config(|config| {
+        config.playback_settings.eq_bands = bands.to_vec();
+        Ok(())
+    })?;
+    publish(PARAMS.enabled.load(Ordering::Relaxed), &bands);
+    Ok(())
+}
+
+#[tauri::command]
+pub fn set_equalizer_enabled(enabled: bool) -> Result<(), String> {
+    update_player_config(|config| {
+        config.playback_settings.eq_enabled = enabled;
+        Ok(())
+    })?;
+    publish(enabled, &current_gains());
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rodio::buffer::SamplesBuffer;
+
+    /// Magnitude response of `coeffs` at `frequency`, in dB.
+    fn response_db(coeffs: &Coeffs, frequency: f32, sample_rate: u32) -> f32 {
+        let [b0, b1, b2, a1, a2] = coeffs.0;
+        let w = 2.0 * PI * frequency / sample_rate as f32;
+        let (c1, s1, c2, s2) = (w.cos(), w.sin(), (2.0 * w).cos(), (2.0 * w).sin());
+        let numerator = ((b0 + b1 * c1 + b2 * c2).powi(2) + (b1 * s1 + b2 * s2).powi(2)).sqrt();
+        let denominator = ((1.0 + a1 * c1 + a2 * c2).powi(2) + (a1 * s1 + a2 * s2).powi(2)).sqrt();
+        20.0 * (numerator / denominator).log10()
+    }
+
+    #[test]
+    fn bands_hit_their_gain_at_the_centre_and_leave_far_frequencies_alone() {
+        let boost = peaking(1000.0, 6.0, 44_100);
+        assert!((response_db(&boost, 1000.0, 44_100) - 6.0).abs() < 0.01);
+        assert!(response_db(&boost, 50.0, 44_100).abs() < 0.2);
+        let cut = peaking(125.0, -9.0, 48_000);
+        assert!((response_db(&cut, 125.0, 48_000) + 9.0).abs() < 0.01);
+    }
+
+    #[test]
+    fn flat_and_unreachable_bands_add_no_filters() {
+        assert!(band_filters(&[0.0; BAND_COUNT], 44_100).is_empty());
+        let mut gains = [0.0; BAND_COUNT];
+        gains[9] = 6.0;
+        // 16 kHz is beyond what an 8 kHz file can hold
+        assert!(band_filters(&gains, 8_000).is_empty());
+        assert_eq!(band_filters(&gains, 44_100).len(), 1);
+        assert!((headroom(&gains) - 10f32.powf(-0.3)).abs() < 1e-6);
+        assert_eq!(headroom(&[-3.0; BAND_COUNT]), 1.0);
+    }
+
+    #[test]
+    fn disabled_equalizer_passes_samples_through() {
+        crate::test_support::isolate_config();
+        let samples = vec![0i16, 1000, -1000, i16::MAX, i16::MIN, 42];
+        let source = Equalizer::new(SamplesBuffer::new(2, 44_100, samples.clone()));
+        assert_eq!((source.channels(), source.sample_rate()), (2, 44_100));
+        assert_eq!(source.collect::<Vec<_>>(), samples);
+    }
+
+    #[test]
+    fn rejects_the_wrong_number_of_bands_or_extreme_gains() {
+        assert!(validate_bands(&[0.0; 9]).is_err());
+        assert!(validate_bands(&[20.0; BAND_COUNT]).is_err());
+        assert!(validate_bands(&[f32::NAN; BAND_COUNT]).is_err());
+        assert_eq!(validate_bands(&PRESETS[1].bands), Ok(PRESETS[1].bands));
+    }
+}
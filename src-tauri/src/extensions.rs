@@ -0,0 +1,407 @@
+use serde::Serialize;
+use std::fs::File;
+use std::io::{Read, Seek, SeekFrom};
+use std::path::{Path, PathBuf};
+use tauri::AppHandle;
+use log::info;
+use crate::inspect::parse_frame_header;
+use crate::journal::Transaction;
+use crate::relocate::guard_playing_track;
+use crate::walker::{walk_audio_files, Exclusions};
+
+// Enough to see the first two MPEG or ADTS frames at common bitrates
+const SNIFF_LEN: u64 = 4096;
+
+#[derive(Debug, Serialize, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum Confidence {
+    // A container signature, or two consecutive frames
+    High,
+    // A single frame sync, which random data can imitate
+    Medium,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct DetectedFormat {
+    pub extension: &'static str,
+    pub mime_type: &'static str,
+    // Other spellings of the same format
+    pub aliases: &'static [&'static str],
+    // Extensions that are also plausible for this content, e.g. .mp4 for M4A audio
+    pub ambiguous_with: &'static [&'static str],
+    pub confidence: Confidence,
+}
+
+const fn format(extension: &'static str, mime_type: &'static str) -> DetectedFormat {
+    DetectedFormat { extension, mime_type, aliases: &[], ambiguous_with: &[], confidence: Confidence::High }
+}
+
+impl DetectedFormat {
+    const fn aliases(mut self, aliases: &'static [&'static str]) -> Self {
+        self.aliases = aliases;
+        self
+    }
+
+    const fn ambiguous_with(mut self, extensions: &'static [&'static str]) -> Self {
+        self.ambiguous_with = extensions;
+        self
+    }
+
+    const fn confidence(mut self, confidence: Confidence) -> Self {
+        self.confidence = confidence;
+        self
+    }
+}
+
+#[derive(Debug, Serialize, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum ExtensionStatus {
+    Matches,
+    Mismatch,
+    Ambiguous,
+}
+
+#[derive(Debug, Serialize, Clone)]
+pub struct ExtensionFinding {
+    pub path: String,
+    pub current_extension: Option<String>,
+    pub detected_extension: String,
+    pub mime_type: String,
+    pub confidence: Confidence,
+    pub status: ExtensionStatus,
+    // Where `fix_extensions` would rename the file
+    pub suggested_path: String,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ExtensionAudit {
+    pub root: String,
+    pub scanned: usize,
+    pub mismatches: Vec<ExtensionFinding>,
+    // Extension and content are both plausible; left for the user to decide
+    pub ambiguous: Vec<ExtensionFinding>,
+    // Content no signature matched
+    pub undetected: Vec<String>,
+}
+
+#[derive(Debug, Serialize, Clone)]
+pub struct ExtensionRename {
+    pub from: String,
+    pub to: String,
+}
+
+#[derive(Debug, Serialize, Clone)]
+pub struct SkippedFix {
+    pub path: String,
+    pub reason: String,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ExtensionFixReport {
+    pub dry_run: bool,
+    pub renamed: Vec<ExtensionRename>,
+    pub skipped: Vec<SkippedFix>,
+    // Undo entry for the renames; None for dry runs or when nothing was renamed
+    pub journal_id: Option<u64>,
+}
+
+/// MPEG audio, confirmed by a second frame right where the first one ends.
+fn detect_mpeg(bytes: &[u8]) -> Option<DetectedFormat> {
+    let first = parse_frame_header(bytes, 0)?;
+    let detected = match first.layer {
+        3 => format("mp3", "audio/mpeg"),
+        2 => format("mp2", "audio/mpeg").aliases(&["mpa"]).ambiguous_with(&["mp3"]),
+        _ => format("mp1", "audio/mpeg").aliases(&["mpa"]),
+    };
+    let next = bytes.get(first.length as usize..).and_then(|rest| parse_frame_header(rest, first.length as u64));
+    Some(match next {
+        Some(next) if next.layer == first.layer && next.sample_rate == first.sample_rate => detected,
+        Some(_) => return None,
+        None if bytes.len() > first.length as usize + 4 => return None,
+        None => detected.confidence(Confidence::Medium),
+    })
+}
+
+/// AAC in ADTS frames, confirmed the same way.
+fn detect_adts(bytes: &[u8]) -> Option<DetectedFormat> {
+    let is_sync = |b: &[u8]| b.len() >= 7 && b[0] == 0xff && b[1] & 0xf6 == 0xf0;
+    if !is_sync(bytes) {
+        return None;
+    }
+    let length = ((bytes[3] as usize & 3) << 11) | ((bytes[4] as usize) << 3) | (bytes[5] as usize >> 5);
+    let detected = format("aac", "audio/aac").aliases(&["adts"]);
+    match bytes.get(length..) {
+        Some(rest) if length >= 7 && is_sync(rest) => Some(detected),
+        _ if length >= 7 && bytes.len() < length + 7 => Some(detected.confidence(Confidence::Medium)),
+        _ => None,
+    }
+}
+
+fn contains(bytes: &[u8], needle: &[u8]) -> bool {
+    bytes.windows(needle.len()).any(|window| window == needle)
+}
+
+/// Identifies audio content from its first bytes (after any ID3v2 tag).
+pub fn detect_format(bytes: &[u8]) -> Option<DetectedFormat> {
+    let at = |offset: usize, magic: &[u8]| bytes.get(offset..offset + magic.len()) == Some(magic);
+
+    if at(0, b"fLaC") {
+        return Some(format("flac", "audio/flac"));
+    }
+    if at(0, b"RIFF") && at(8, b"WAVE") {
+        return Some(format("wav", "audio/wav").aliases(&["wave"]));
+    }
+    if at(0, b"FORM") && (at(8, b"AIFF") || at(8, b"AIFC")) {
+        return Some(format("aiff", "audio/aiff").aliases(&["aif", "aifc"]));
+    }
+    if at(0, b"OggS") {
+        let page = &bytes[..bytes.len().min(128)];
+        return Some(if contains(page, b"OpusHead") {
+            format("opus", "audio/opus").ambiguous_with(&["ogg", "oga"])
+        } else if contains(page, b"\x7fFLAC") {
+            format("oga", "audio/ogg").ambiguous_with(&["ogg"])
+        } else if contains(page, b"\x01vorbis") {
+            format("ogg", "audio/ogg").aliases(&["oga"])
+        } else {
+            format("ogg", "audio/ogg").aliases(&["oga"]).confidence(Confidence::Medium)
+        });
+    }
+    if at(4, b"ftyp") {
+        let brand = bytes.get(8..12).unwrap_or_default();
+        return Some(match brand {
+            b"M4A " => format("m4a", "audio/mp4").ambiguous_with(&["mp4", "m4b"]),
+            b"M4B " => format("m4b", "audio/mp4").ambiguous_with(&["m4a", "mp4"]),
+            b"M4P " => format("m4p", "audio/mp4").ambiguous_with(&["m4a"]),
+            b"qt  " => format("mov", "video/quicktime"),
+            // Generic ISO brands are used for audio-only files as often as for video
+            _ => format("mp4", "video/mp4").ambiguous_with(&["m4a", "m4b"]),
+        });
+    }
+    if at(0, b"\x30\x26\xb2\x75\x8e\x66\xcf\x11") {
+        return Some(format("wma", "audio/x-ms-wma").ambiguous_with(&["asf", "wmv"]));
+    }
+    if at(0, b"MAC ") {
+        return Some(format("ape", "audio/ape"));
+    }
+    if at(0, b"wvpk") {
+        return Some(format("wv", "audio/wavpack"));
+    }
+    if at(0, b"caff") {
+        return Some(format("caf", "audio/x-caf"));
+    }
+    if at(0, b"DSD ") {
+        return Some(format("dsf", "audio/dsf"));
+    }
+    if at(0, b"FRM8") {
+        return Some(format("dff", "audio/dff"));
+    }
+    if at(0, b"#!AMR") {
+        return Some(format("amr", "audio/amr"));
+    }
+    detect_adts(bytes).or_else(|| detect_mpeg(bytes))
+}
+
+fn read_at(file: &mut File, offset: u64) -> std::io::Result<Vec<u8>> {
+    file.seek(SeekFrom::Start(offset))?;
+    let mut buffer = Vec::new();
+    file.take(SNIFF_LEN).read_to_end(&mut buffer)?;
+    Ok(buffer)
+}
+
+/// Detects the format of the file at `path`, looking past a leading ID3v2 tag.
+pub fn sniff_file(path: &Path) -> Result<Option<DetectedFormat>, String> {
+    let mut file = File::open(path).map_err(|e| format!("Failed to open file: {}", e))?;
+    let head = read_at(&mut file, 0).map_err(|e| format!("Failed to read file: {}", e))?;
+    if !head.starts_with(b"ID3") || head.len() < 10 {
+        return Ok(detect_format(&head));
+    }
+
+    let size = head[6..10].iter().fold(0u64, |size, byte| (size << 7) | (*byte & 0x7f) as u64);
+    let footer = if head[5] & 0x10 != 0 { 10 } else { 0 };
+    let audio = read_at(&mut file, 10 + size + footer).map_err(|e| format!("Failed to read file: {}", e))?;
+    Ok(Some(match detect_format(&audio) {
+        // A tag in front of a single frame is good evidence on its own
+        Some(detected) if detected.extension == "mp3" || detected.extension == "aac" => detected.confidence(Confidence::High),
+        Some(detected) => detected,
+        // ID3v2 is used almost only by MP3 files
+        None => format("mp3", "audio/mpeg").confidence(Confidence::Medium),
+    }))
+}
+
+fn current_extension(path: &Path) -> Option<String> {
+    path.extension().map(|ext| ext.to_string_lossy().to_lowercase())
+}
+
+pub fn classify(current: Option<&str>, detected: &DetectedFormat) -> ExtensionStatus {
+    match current {
+        Some(ext) if ext == detected.extension || detected.aliases.contains(&ext) => ExtensionStatus::Matches,
+        Some(ext) if detected.ambiguous_with.contains(&ext) => ExtensionStatus::Ambiguous,
+        _ => ExtensionStatus::Mismatch,
+    }
+}
+
+fn finding(path: &Path, detected: &DetectedFormat) -> ExtensionFinding {
+    let current = current_extension(path);
+    ExtensionFinding {
+        path: path.to_string_lossy().to_string(),
+        status: classify(current.as_deref(), detected),
+        current_extension: current,
+        detected_extension: detected.extension.to_string(),
+        mime_type: detected.mime_type.to_string(),
+        confidence: detected.confidence,
+        suggested_path: path.with_extension(detected.extension).to_string_lossy().to_string(),
+    }
+}
+
+/// Compares each audio file's content with its extension.
+#[tauri::command]
+pub async fn audit_extensions(root: String, recursive: bool) -> Result<ExtensionAudit, String> {
+    let root_path = Path::new(&root);
+    if !root_path.is_dir() {
+        return Err(format!("{} is not a folder", root));
+    }
+
+    let files = walk_audio_files(root_path, recursive, &Exclusions::load());
+    let mut audit = ExtensionAudit {
+        root: root.clone(),
+        scanned: files.len(),
+        mismatches: Vec::new(),
+        ambiguous: Vec::new(),
+        undetected: Vec::new(),
+    };
+    for path in files {
+        match sniff_file(&path) {
+            Ok(Some(detected)) => {
+                let finding = finding(&path, &detected);
+                match finding.status {
+                    ExtensionStatus::Matches => {}
+                    ExtensionStatus::Mismatch => audit.mismatches.push(finding),
+                    ExtensionStatus::Ambiguous => audit.ambiguous.push(finding),
+                }
+            }
+            Ok(None) | Err(_) => audit.undetected.push(path.to_string_lossy().to_string()),
+        }
+    }
+    info!(
+        "Extension audit of {}: {} files, {} mismatched, {} ambiguous",
+        root, audit.scanned, audit.mismatches.len(), audit.ambiguous.len()
+    );
+    Ok(audit)
+}
+
+/// Why `path` shouldn't be renamed, or where it should go.
+fn plan_fix(path: &Path) -> Result<PathBuf, String> {
+    let detected = sniff_file(path)?.ok_or("Content not recognized")?;
+    let finding = finding(path, &detected);
+    match finding.status {
+        ExtensionStatus::Matches => return Err("Extension already matches the content".to_string()),
+        ExtensionStatus::Ambiguous => return Err(format!("Both .{} and .{} fit the content", finding.current_extension.unwrap_or_default(), detected.extension)),
+        ExtensionStatus::Mismatch => {}
+    }
+    if detected.confidence != Confidence::High {
+        return Err(format!("Only weak evidence that this is .{}", detected.extension));
+    }
+    let target = PathBuf::from(finding.suggested_path);
+    if target.exists() {
+        return Err(format!("{} already exists", target.display()));
+    }
+    Ok(target)
+}
+
+/// Renames confirmed mismatches to the extension their content calls for. Each
+/// file is re-checked first; ambiguous and weakly detected files are skipped.
+/// The renames are journaled as one undoable operation.
+#[tauri::command]
+pub async fn fix_extensions(app: AppHandle, paths: Vec<String>, dry_run: bool) -> Result<ExtensionFixReport, String> {
+    let mut report = ExtensionFixReport { dry_run, renamed: Vec::new(), skipped: Vec::new(), journal_id: None };
+    let mut transaction = Transaction::new("fix_extensions", format!("Fix extensions of {} files", paths.len()));
+
+    for path in paths {
+        let source = Path::new(&path);
+        let target = match plan_fix(source) {
+            Ok(target) => target,
+            Err(reason) => {
+                report.skipped.push(SkippedFix { path, reason });
+                continue;
+            }
+        };
+        if !dry_run {
+            if let Err(e) = guard_playing_track(&app, source, &target, false, || transaction.rename(source, &target)) {
+                report.skipped.push(SkippedFix { path, reason: e.to_string() });
+                continue;
+            }
+            transaction.remap(source, &target);
+        }
+        report.renamed.push(ExtensionRename { from: path, to: target.to_string_lossy().to_string() });
+    }
+
+    if !dry_run && !report.renamed.is_empty() {
+        report.journal_id = Some(transaction.commit()?);
+        info!("Fixed the extensions of {} files", report.renamed.len());
+    }
+    Ok(report)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use crate::test_support::{build_library, flac_bytes, mp3_frames, sine_samples, temp_dir, wav_bytes, FixtureTags, TrackSpec, SAMPLE_RATE};
+
+    #[test]
+    fn detects_containers_from_their_signatures() {
+        assert_eq!(detect_format(&flac_bytes(&sine_samples(SAMPLE_RATE, 440.0, 1.0))).unwrap().extension, "flac");
+        assert_eq!(detect_format(&wav_bytes(&sine_samples(SAMPLE_RATE, 440.0, 1.0))).unwrap().extension, "wav");
+        let mp3 = detect_format(&mp3_frames(1)).unwrap();
+        assert_eq!((mp3.extension, mp3.confidence), ("mp3", Confidence::High));
+        assert_eq!(detect_format(b"\x00\x00\x00\x20ftypM4A \x00\x00\x00\x00").unwrap().extension, "m4a");
+        assert_eq!(detect_format(b"\x00\x00\x00\x1cftypisom\x00\x00\x02\x00").unwrap().extension, "mp4");
+        assert!(detect_format(b"plain text, not audio").is_none());
+    }
+
+    #[test]
+    fn a_lone_frame_sync_is_weak_evidence() {
+        let single = &mp3_frames(1)[..420];
+        assert_eq!(detect_format(single).unwrap().confidence, Confidence::Medium);
+        // A second header that doesn't match the first means it wasn't a frame at all
+        let mut broken = mp3_frames(1);
+        broken[417] = 0;
+        assert!(detect_format(&broken).is_none());
+    }
+
+    #[test]
+    fn audio_only_mp4_is_ambiguous_rather_than_wrong() {
+        let m4a = detect_format(b"\x00\x00\x00\x20ftypM4A \x00\x00\x00\x00").unwrap();
+        assert_eq!(classify(Some("mp4"), &m4a), ExtensionStatus::Ambiguous);
+        let generic = detect_format(b"\x00\x00\x00\x1cftypisom\x00\x00\x02\x00").unwrap();
+        assert_eq!(classify(Some("m4a"), &generic), ExtensionStatus::Ambiguous);
+        assert_eq!(classify(Some("mp3"), &generic), ExtensionStatus::Mismatch);
+        let wav = detect_format(&wav_bytes(&sine_samples(SAMPLE_RATE, 440.0, 1.0))).unwrap();
+        assert_eq!(classify(Some("wave"), &wav), ExtensionStatus::Matches);
+    }
+
+    #[test]
+    fn audit_reports_and_fix_renames_mismatches() {
+        let dir = temp_dir();
+        let files = build_library(dir.path(), &[
+            TrackSpec::new("good.mp3", FixtureTags::titled("Good")),
+            TrackSpec::new("lossless.flac", FixtureTags::default()),
+            TrackSpec::new("clip.wav", FixtureTags::default()),
+        ]);
+        let wrong_flac = dir.path().join("lossless.mp3");
+        let wrong_wav = dir.path().join("clip.flac");
+        fs::rename(&files[1], &wrong_flac).unwrap();
+        fs::rename(&files[2], &wrong_wav).unwrap();
+
+        let audit = tauri::async_runtime::block_on(audit_extensions(dir.path().to_string_lossy().to_string(), true)).unwrap();
+        assert_eq!(audit.scanned, 3);
+        let mut mismatched: Vec<(&str, &str)> = audit.mismatches.iter()
+            .map(|finding| (Path::new(&finding.path).file_name().unwrap().to_str().unwrap(), finding.detected_extension.as_str()))
+            .collect();
+        mismatched.sort();
+        assert_eq!(mismatched, [("clip.flac", "wav"), ("lossless.mp3", "flac")]);
+
+        assert_eq!(plan_fix(&wrong_flac).unwrap(), dir.path().join("lossless.flac"));
+        assert!(plan_fix(&files[0]).is_err());
+    }
+}
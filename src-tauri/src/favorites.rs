@@ -0,0 +1,110 @@
+use serde::{Deserialize, Deserializer, Serialize};
+use std::path::Path;
+use std::time::Duration;
+use tauri::State;
+use crate::commands::start_playback;
+use crate::library::{is_audio_file, track_info, LibraryTrack};
+use crate::paths::{probe_paths, same_location};
+use crate::{load_config, update_config, PlayerHandle};
+
+const AVAILABILITY_TIMEOUT: Duration = Duration::from_secs(2);
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum FavoriteKind {
+    Folder,
+    File,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct FavoriteEntry {
+    pub path: String,
+    pub kind: FavoriteKind,
+    #[serde(default)]
+    pub label: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct FavoriteTrack {
+    pub path: String,
+    pub label: Option<String>,
+    pub available: bool,
+    pub metadata: Option<LibraryTrack>,
+}
+
+impl FavoriteEntry {
+    /// Builds an entry for `path`, checking that files are audio.
+    pub fn for_path(path: String, label: Option<String>) -> Result<Self, String> {
+        let as_path = Path::new(&path);
+        let kind = if as_path.is_file() {
+            if !is_audio_file(as_path) {
+                return Err("Only audio files can be added to favorites".to_string());
+            }
+            FavoriteKind::File
+        } else {
+            FavoriteKind::Folder
+        };
+        Ok(Self { path, kind, label })
+    }
+}
+
+/// Reads favorites written before entries carried a kind, when they were bare path strings.
+pub fn deserialize_favorites<'de, D>(deserializer: D) -> Result<Vec<FavoriteEntry>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum Stored {
+        Path(String),
+        Entry(FavoriteEntry),
+    }
+
+    let stored = Vec::<Stored>::deserialize(deserializer)?;
+    Ok(stored.into_iter().map(|entry| match entry {
+        Stored::Path(path) => FavoriteEntry { path, kind: FavoriteKind::Folder, label: None },
+        Stored::Entry(entry) => entry,
+    }).collect())
+}
+
+#[tauri::command]
+pub fn get_favorite_tracks_metadata() -> Vec<FavoriteTrack> {
+    let tracks: Vec<FavoriteEntry> = load_config().favorite_locations.into_iter()
+        .filter(|entry| entry.kind == FavoriteKind::File)
+        .collect();
+    let paths: Vec<String> = tracks.iter().map(|entry| entry.path.clone()).collect();
+    let availability = probe_paths(&paths, AVAILABILITY_TIMEOUT);
+
+    tracks.into_iter().zip(availability).map(|(entry, available)| {
+        let available = available == Some(true);
+        FavoriteTrack {
+            metadata: if available { track_info(Path::new(&entry.path)) } else { None },
+            path: entry.path,
+            label: entry.label,
+            available,
+        }
+    }).collect()
+}
+
+#[tauri::command]
+pub fn play_favorite_track(player: State<'_, PlayerHandle>, path: String) -> Result<(), String> {
+    let entry = load_config().favorite_locations.into_iter()
+        .find(|entry| entry.kind == FavoriteKind::File && same_location(&entry.path, &path))
+        .ok_or_else(|| "Track is not in favorites".to_string())?;
+
+    if probe_paths(std::slice::from_ref(&entry.path), AVAILABILITY_TIMEOUT)[0] != Some(true) {
+        return Err(format!("Favorite is not available: {}", entry.path));
+    }
+    Ok(start_playback(&player, &entry.path, false)?)
+}
+
+#[tauri::command]
+pub fn set_favorite_label(path: String, label: Option<String>) -> Result<Vec<FavoriteEntry>, String> {
+    update_config(|config| {
+        let entry = config.favorite_locations.iter_mut()
+            .find(|entry| same_location(&entry.path, &path))
+            .ok_or_else(|| "Location is not in favorites".to_string())?;
+        entry.label = label.filter(|label| !label.trim().is_empty());
+        Ok(config.favorite_locations.clone())
+    })
+}
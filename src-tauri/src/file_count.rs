@@ -0,0 +1,215 @@
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant, SystemTime};
+use once_cell::sync::Lazy;
+use parking_lot::Mutex;
+use tauri::{AppHandle, Emitter};
+use crate::library::is_audio_file;
+use crate::requests::{begin_request, RequestError};
+use crate::walker::{check_readable, emit_skipped, walk_files_reporting, Exclusions, SkippedPath};
+
+// Counts are reused for this long while the root folder itself is unchanged
+const CACHE_TTL: Duration = Duration::from_secs(30);
+const PROGRESS_INTERVAL: Duration = Duration::from_millis(250);
+
+#[derive(Debug, Deserialize, Clone, Default, PartialEq, Eq, Hash)]
+#[serde(default)]
+pub struct CountFilter {
+    // Lowercase extensions to count; empty counts every audio format
+    pub extensions: Vec<String>,
+    // Count files the library's exclusion rules would skip
+    pub include_excluded: bool,
+    // Count every file, not just audio, e.g. the artwork a transfer carries along
+    pub all_files: bool,
+}
+
+impl CountFilter {
+    fn matches(&self, path: &Path) -> bool {
+        if self.all_files {
+            return true;
+        }
+        if self.extensions.is_empty() {
+            return is_audio_file(path);
+        }
+        path.extension()
+            .map(|ext| ext.to_string_lossy().to_lowercase())
+            .is_some_and(|ext| self.extensions.contains(&ext))
+    }
+}
+
+#[derive(Debug, Serialize, Clone, Default, PartialEq)]
+pub struct ExtensionCount {
+    pub files: usize,
+    pub bytes: u64,
+}
+
+#[derive(Debug, Serialize, Clone, Default, PartialEq)]
+pub struct AudioFileCount {
+    pub root: String,
+    pub file_count: usize,
+    pub total_bytes: u64,
+    // Keyed by lowercase extension
+    pub by_extension: BTreeMap<String, ExtensionCount>,
+    // Folders that couldn't be read and so aren't counted
+    pub skipped_folders: usize,
+}
+
+/// A file `list_counted` found, with its size.
+pub struct CountedFile {
+    pub path: PathBuf,
+    pub size: u64,
+}
+
+struct CachedCount {
+    root_modified: Option<SystemTime>,
+    counted_at: Instant,
+    count: AudioFileCount,
+}
+
+static CACHE: Lazy<Mutex<HashMap<(String, CountFilter), CachedCount>>> = Lazy::new(|| Mutex::new(HashMap::new()));
+
+fn root_modified(root: &Path) -> Option<SystemTime> {
+    fs::metadata(root).and_then(|metadata| metadata.modified()).ok()
+}
+
+fn cached(root: &Path, filter: &CountFilter) -> Option<AudioFileCount> {
+    let key = (root.to_string_lossy().to_string(), filter.clone());
+    let cache = CACHE.lock();
+    cache.get(&key)
+        .filter(|entry| entry.counted_at.elapsed() < CACHE_TTL && entry.root_modified == root_modified(root))
+        .map(|entry| entry.count.clone())
+}
+
+/// Counts the matching files under `root` with their sizes. `progress` sees the
+/// running totals every so often on large trees. Returns None if `stop` fired.
+pub fn count_audio(
+    root: &Path,
+    filter: &CountFilter,
+    stop: &dyn Fn() -> bool,
+    progress: &mut dyn FnMut(&AudioFileCount),
+) -> Option<(AudioFileCount, Vec<SkippedPath>)> {
+    if let Some(count) = cached(root, filter) {
+        return Some((count, Vec::new()));
+    }
+    walk_counting(root, filter, stop, progress, &mut |_, _| {})
+}
+
+/// `count_audio` for callers that go on to work through the files: it always
+/// walks, and also returns the matching files and their sizes in the order it
+/// found them.
+pub fn list_counted(
+    root: &Path,
+    filter: &CountFilter,
+    stop: &dyn Fn() -> bool,
+    progress: &mut dyn FnMut(&AudioFileCount),
+) -> Option<(Vec<CountedFile>, AudioFileCount, Vec<SkippedPath>)> {
+    let mut files = Vec::new();
+    let (count, skipped) = walk_counting(root, filter, stop, progress, &mut |path, size| files.push(CountedFile { path: path.to_path_buf(), size }))?;
+    Some((files, count, skipped))
+}
+
+/// Walks `root`, handing each counted file and its size to `visit`, and caches the result
+/// of a complete walk.
+fn walk_counting(
+    root: &Path,
+    filter: &CountFilter,
+    stop: &dyn Fn() -> bool,
+    progress: &mut dyn FnMut(&AudioFileCount),
+    visit: &mut dyn FnMut(&Path, u64),
+) -> Option<(AudioFileCount, Vec<SkippedPath>)> {
+    let exclusions = if filter.include_excluded { Exclusions::default() } else { Exclusions::load() };
+    let root_modified = root_modified(root);
+    let mut count = AudioFileCount { root: root.to_string_lossy().to_string(), ..Default::default() };
+    let mut skipped = Vec::new();
+    let mut last_progress = Instant::now();
+
+    let finished = walk_files_reporting(root, true, &exclusions, stop, &mut |path| {
+        if !filter.matches(path) {
+            return;
+        }
+        let Ok(metadata) = fs::metadata(path) else { return };
+        let extension = path.extension().map(|ext| ext.to_string_lossy().to_lowercase()).unwrap_or_default();
+        let entry = count.by_extension.entry(extension).or_default();
+        entry.files += 1;
+        entry.bytes += metadata.len();
+        count.file_count += 1;
+        count.total_bytes += metadata.len();
+        visit(path, metadata.len());
+        if last_progress.elapsed() >= PROGRESS_INTERVAL {
+            progress(&count);
+            last_progress = Instant::now();
+        }
+    }, &mut skipped);
+    if !finished {
+        return None;
+    }
+
+    count.skipped_folders = skipped.len();
+    CACHE.lock().insert((count.root.clone(), filter.clone()), CachedCount {
+        root_modified,
+        counted_at: Instant::now(),
+        count: count.clone(),
+    });
+    Some((count, skipped))
+}
+
+/// How many audio files, and how many bytes of them, are under `root`. Large
+/// trees report `count-progress` along the way.
+#[tauri::command]
+pub async fn count_audio_files(app: AppHandle, root: String, filter: Option<CountFilter>, request_id: Option<String>) -> Result<AudioFileCount, RequestError> {
+    let request = begin_request("count_audio_files", &root, request_id);
+    let root_path = Path::new(&root);
+    check_readable(root_path)?;
+
+    let filter = filter.unwrap_or_default();
+    let (count, skipped) = count_audio(root_path, &filter, &|| request.is_cancelled(), &mut |running| {
+        app.emit("count-progress", running).ok();
+    }).ok_or(RequestError::Cancelled)?;
+    emit_skipped(&app, "count_audio_files", root_path, skipped);
+    Ok(count)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::{build_library, temp_dir, FixtureTags, TrackSpec};
+
+    #[test]
+    fn counts_files_and_bytes_per_extension() {
+        let dir = temp_dir();
+        let files = build_library(dir.path(), &[
+            TrackSpec::new("A/one.mp3", FixtureTags::default()),
+            TrackSpec::new("A/two.mp3", FixtureTags::default()),
+            TrackSpec::new("B/deep/three.flac", FixtureTags::default()),
+        ]);
+        fs::write(dir.path().join("A/cover.jpg"), b"not audio").unwrap();
+        let size = |index: usize| fs::metadata(&files[index]).unwrap().len();
+
+        let (count, _) = count_audio(dir.path(), &CountFilter::default(), &|| false, &mut |_| {}).unwrap();
+        assert_eq!(count.file_count, 3);
+        assert_eq!(count.total_bytes, size(0) + size(1) + size(2));
+        assert_eq!(count.by_extension["mp3"], ExtensionCount { files: 2, bytes: size(0) + size(1) });
+        assert_eq!(count.by_extension["flac"].files, 1);
+
+        let flac_only = CountFilter { extensions: vec!["flac".to_string()], ..Default::default() };
+        let (count, _) = count_audio(dir.path(), &flac_only, &|| false, &mut |_| {}).unwrap();
+        assert_eq!(count.file_count, 1);
+
+        let everything = CountFilter { all_files: true, ..Default::default() };
+        let (files, count, _) = list_counted(dir.path(), &everything, &|| false, &mut |_| {}).unwrap();
+        assert_eq!(count.file_count, 4);
+        assert_eq!(files.len(), 4);
+        let cover = files.iter().find(|file| file.path == dir.path().join("A/cover.jpg")).unwrap();
+        assert_eq!(cover.size, 9);
+    }
+
+    #[test]
+    fn cancelled_counts_are_not_cached() {
+        let dir = temp_dir();
+        build_library(dir.path(), &[TrackSpec::new("one.mp3", FixtureTags::default())]);
+        assert!(count_audio(dir.path(), &CountFilter::default(), &|| true, &mut |_| {}).is_none());
+        assert!(cached(dir.path(), &CountFilter::default()).is_none());
+    }
+}
@@ -0,0 +1,176 @@
+use serde::Serialize;
+use std::collections::{BTreeMap, HashSet};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use tauri::{AppHandle, Emitter};
+use crate::library::{modified_secs, track_info, LibraryTrack, LIBRARY};
+use crate::walker::{walk_audio_files, Exclusions};
+use crate::text::fold;
+
+// Bumped by every summary request; a background probe stops once a newer folder is selected
+static GENERATION: AtomicU64 = AtomicU64::new(0);
+
+#[derive(Debug, Serialize, Clone, PartialEq)]
+pub struct SummaryFile {
+    pub path: String,
+    pub size: u64,
+    // Added-at time from the index, or the modification time for unscanned files
+    pub added_at: u64,
+}
+
+#[derive(Debug, Serialize, Clone, PartialEq)]
+pub struct FolderSummary {
+    pub path: String,
+    pub recursive: bool,
+    pub file_count: usize,
+    // Files not (or no longer) matching the index; counted, but without tag data
+    pub unscanned_count: usize,
+    pub total_size: u64,
+    pub total_duration: f64,
+    // Lowercase extension -> file count
+    pub formats: BTreeMap<String, usize>,
+    // Estimated from file size and duration, in kbps
+    pub average_bitrate: Option<u32>,
+    pub album_count: usize,
+    pub artist_count: usize,
+    // Scanned files missing a title, artist or album
+    pub untagged_count: usize,
+    pub all_tagged: bool,
+    pub largest_file: Option<SummaryFile>,
+    pub most_recent: Option<SummaryFile>,
+}
+
+struct SummaryEntry {
+    path: PathBuf,
+    size: u64,
+    modified: u64,
+    track: Option<LibraryTrack>,
+}
+
+/// Pairs each file with its index entry when the entry is still current.
+fn collect_entries(root: &Path, recursive: bool) -> Vec<SummaryEntry> {
+    let files = walk_audio_files(root, recursive, &Exclusions::load());
+    let stats: Vec<(PathBuf, u64, u64)> = files.into_iter()
+        .filter_map(|path| {
+            let metadata = fs::metadata(&path).ok()?;
+            Some((path, metadata.len(), modified_secs(&metadata)))
+        })
+        .collect();
+
+    let index = LIBRARY.lock();
+    stats.into_iter().map(|(path, size, modified)| {
+        let track = index.tracks.get(path.to_string_lossy().as_ref())
+            .filter(|track| track.size == size && track.modified == modified)
+            .cloned();
+        SummaryEntry { path, size, modified, track }
+    }).collect()
+}
+
+fn summarize(root: &str, recursive: bool, entries: &[SummaryEntry]) -> FolderSummary {
+    let mut formats = BTreeMap::new();
+    let mut albums = HashSet::new();
+    let mut artists = HashSet::new();
+    let mut total_duration = 0.0;
+    let mut timed_bytes = 0u64;
+    let mut timed_duration = 0.0;
+    let mut unscanned_count = 0;
+    let mut untagged_count = 0;
+    let mut largest_file: Option<SummaryFile> = None;
+    let mut most_recent: Option<SummaryFile> = None;
+
+    for entry in entries {
+        let extension = entry.path.extension()
+            .map(|ext| ext.to_string_lossy().to_lowercase())
+            .unwrap_or_default();
+        *formats.entry(extension).or_insert(0) += 1;
+
+        let added_at = entry.track.as_ref().map(|track| track.added_at).unwrap_or(entry.modified);
+        let file = SummaryFile {
+            path: entry.path.to_string_lossy().to_string(),
+            size: entry.size,
+            added_at,
+        };
+        if largest_file.as_ref().is_none_or(|largest| file.size > largest.size) {
+            largest_file = Some(file.clone());
+        }
+        if most_recent.as_ref().is_none_or(|recent| file.added_at > recent.added_at) {
+            most_recent = Some(file);
+        }
+
+        let Some(track) = &entry.track else {
+            unscanned_count += 1;
+            continue;
+        };
+        if let Some(duration) = track.duration.filter(|d| *d > 0.0) {
+            total_duration += duration;
+            timed_bytes += entry.size;
+            timed_duration += duration;
+        }
+        let artist = track.album_artist.as_ref().or(track.artist.as_ref());
+        if let Some(artist) = &track.artist {
+            artists.insert(fold(artist));
+        }
+        if let Some(album) = &track.album {
+            albums.insert(format!("{}\u{0}{}", artist.map(|a| fold(a)).unwrap_or_default(), fold(album)));
+        }
+        if track.title.is_none() || track.artist.is_none() || track.album.is_none() {
+            untagged_count += 1;
+        }
+    }
+
+    let average_bitrate = (timed_duration > 0.0).then(|| (timed_bytes as f64 * 8.0 / timed_duration / 1000.0).round() as u32);
+
+    FolderSummary {
+        path: root.to_string(),
+        recursive,
+        file_count: entries.len(),
+        unscanned_count,
+        total_size: entries.iter().map(|entry| entry.size).sum(),
+        total_duration,
+        formats,
+        average_bitrate,
+        album_count: albums.len(),
+        artist_count: artists.len(),
+        untagged_count,
+        all_tagged: untagged_count == 0 && unscanned_count == 0,
+        largest_file,
+        most_recent,
+    }
+}
+
+/// Probes the files the index didn't cover and emits `folder-summary-refined`
+/// if that changes the numbers. Probed tags are not stored in the index.
+fn refine_in_background(app: AppHandle, generation: u64, summary: FolderSummary, mut entries: Vec<SummaryEntry>) {
+    std::thread::spawn(move || {
+        for entry in entries.iter_mut().filter(|entry| entry.track.is_none()) {
+            if GENERATION.load(Ordering::SeqCst) != generation {
+                return;
+            }
+            entry.track = track_info(&entry.path);
+        }
+
+        let refined = summarize(&summary.path, summary.recursive, &entries);
+        if refined != summary && GENERATION.load(Ordering::SeqCst) == generation {
+            app.emit("folder-summary-refined", refined).ok();
+        }
+    });
+}
+
+/// Quick statistics for a folder, built from the library index. Files the index
+/// doesn't know are counted as unscanned and probed afterwards in the background.
+#[tauri::command]
+pub fn get_folder_summary(app: AppHandle, path: String, recursive: bool) -> Result<FolderSummary, String> {
+    let root = Path::new(&path);
+    if !root.is_dir() {
+        return Err(format!("{} is not a folder", path));
+    }
+
+    let generation = GENERATION.fetch_add(1, Ordering::SeqCst) + 1;
+    let entries = collect_entries(root, recursive);
+    let summary = summarize(&path, recursive, &entries);
+    if summary.unscanned_count > 0 {
+        refine_in_background(app, generation, summary.clone(), entries);
+    }
+    Ok(summary)
+}
@@ -0,0 +1,265 @@
+//! Gapless playback. Shortly before a track ends, the next one in the queue is
+//! decoded and appended to the same sink, so the output never runs dry between
+//! them. The sink's mixer converts every source to the device rate, so tracks
+//! with different sample rates can follow each other directly.
+
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+use once_cell::sync::Lazy;
+use parking_lot::Mutex;
+use rodio::source::SeekError;
+use rodio::Source;
+use tauri::{AppHandle, Emitter, Manager};
+use log::{debug, info};
+use crate::commands::record_played_location;
+use crate::crossfade::crossfade_length;
+use crate::output_format::OpenedSource;
+use crate::prefetch::{self, take_or_open};
+use crate::stretch::TrackClock;
+use crate::resume::forget_resume_point;
+use crate::{pitch, play_stats, silence};
+use crate::queue::{next_index, with_queue, PlayQueue, QueuedTrack, TrackChanged};
+use crate::{PlayerHandle, PlayerState};
+
+// How long before the end of a track the next one is appended
+const PRELOAD_AHEAD: Duration = Duration::from_secs(10);
+
+// Generation and path of the last track that couldn't be preloaded, so it is
+// left for the normal advance to report instead of being retried every poll
+static FAILED_PRELOAD: Lazy<Mutex<Option<(u64, String)>>> = Lazy::new(|| Mutex::new(None));
+
+/// The next queue track, sitting in the sink behind the current one.
+pub struct Preloaded {
+    pub path: String,
+    pub position: usize,
+    pub track: QueuedTrack,
+    pub duration: Option<Duration>,
+    pub duration_approximate: bool,
+    pub replay_gain: f32,
+    pub clock: Arc<TrackClock>,
+    withdrawn: Arc<AtomicBool>,
+}
+
+impl Preloaded {
+    /// Makes the appended source end as soon as it is reached. Sinks can't drop
+    /// a queued source, so this is how a preload goes stale after a queue edit.
+    pub fn withdraw(&self) {
+        self.withdrawn.store(true, Ordering::Relaxed);
+    }
+}
+
+/// A source that stops producing samples once withdrawn.
+struct Withdrawable<S> {
+    inner: S,
+    withdrawn: Arc<AtomicBool>,
+}
+
+impl<S: Source> Iterator for Withdrawable<S>
+where
+    S::Item: rodio::Sample,
+{
+    type Item = S::Item;
+
+    fn next(&mut self) -> Option<S::Item> {
+        if self.withdrawn.load(Ordering::Relaxed) {
+            return None;
+        }
+        self.inner.next()
+    }
+}
+
+impl<S: Source> Source for Withdrawable<S>
+where
+    S::Item: rodio::Sample,
+{
+    fn current_frame_len(&self) -> Option<usize> {
+        self.inner.current_frame_len()
+    }
+
+    fn channels(&self) -> u16 {
+        self.inner.channels()
+    }
+
+    fn sample_rate(&self) -> u32 {
+        self.inner.sample_rate()
+    }
+
+    fn total_duration(&self) -> Option<Duration> {
+        self.inner.total_duration()
+    }
+
+    fn try_seek(&mut self, position: Duration) -> Result<(), SeekError> {
+        self.inner.try_seek(position)
+    }
+}
+
+/// Whether a track with `remaining` left should have its successor appended.
+/// Without a known duration the successor is appended straight away.
+fn due(remaining: Option<Duration>) -> bool {
+    remaining.is_none_or(|remaining| remaining <= PRELOAD_AHEAD)
+}
+
+/// The queue entry that should follow what is playing now, if any.
+fn expected_next(queue: &PlayQueue, player: &PlayerState) -> Option<(usize, QueuedTrack)> {
+    next_index(queue, player.current_path.as_deref()).map(|index| (index, queue.tracks[index].clone()))
+}
+
+/// Moves the player onto the preloaded track once the sink has started it.
+fn promote(queue: &mut PlayQueue, player: &mut PlayerState) -> Option<TrackChanged> {
+    let started = player.sink.as_ref().is_some_and(|sink| sink.len() == 1);
+    if !started {
+        return None;
+    }
+    let preloaded = player.preloaded.take()?;
+    // The sink only moves on once the track before has played to its end
+    if let Some(finished) = &player.current_path {
+        forget_resume_point(finished);
+    }
+    player.leave_current();
+    player.current_path = Some(preloaded.path.clone());
+    player.duration = preloaded.duration;
+    player.duration_approximate = preloaded.duration_approximate;
+    player.replay_gain = preloaded.replay_gain;
+    player.loop_region = None;
+    player.cue_track = None;
+    player.trim_end = silence::cached_trim_end(&preloaded.path);
+    play_stats::begin_play(&preloaded.path);
+    pitch::track_changed();
+    if player.is_playing {
+        if let Some(sink) = &player.sink {
+            sink.set_volume(player.output_gain());
+        }
+    }
+    player.clock = preloaded.clock;
+    player.generation += 1;
+    // Its index moves if the queue was edited since it was loaded
+    if queue.tracks.get(preloaded.position).is_none_or(|track| track.path != preloaded.path) {
+        if let Some(index) = queue.tracks.iter().position(|track| track.path == preloaded.path) {
+            queue.position = index;
+        }
+    } else {
+        queue.position = preloaded.position;
+    }
+    queue.reshuffle_for_wrap();
+    Some(TrackChanged { path: preloaded.path, position: queue.position, track: preloaded.track })
+}
+
+/// Withdraws the preload if the queue no longer has it coming next.
+fn withdraw_if_stale(queue: &PlayQueue, player: &mut PlayerState) {
+    let Some(preloaded) = &player.preloaded else { return };
+    let still_next = expected_next(queue, player)
+        .is_some_and(|(index, track)| index == preloaded.position && track.path == preloaded.path);
+    if !still_next {
+        debug!("Withdrawing preloaded {} after a queue change", preloaded.path);
+        preloaded.withdraw();
+        player.preloaded = None;
+    }
+}
+
+/// Called by the auto-advance watcher on every poll: reports a hand-over to the
+/// preloaded track, drops a preload the queue no longer wants, and appends the
+/// next track when the current one is close to its end.
+pub fn poll(app: &AppHandle) {
+    let handle = app.state::<PlayerHandle>();
+    let (changed, plan, upcoming) = handle.with_queue(|player, queue| {
+        let changed = promote(queue, player);
+        withdraw_if_stale(queue, player);
+
+        // Crossfading overlaps the tracks instead, so nothing is queued in the sink
+        let remaining = match &player.sink {
+            // A looped section never reaches the next track, nor does one set to stop after it
+            // or cut short at its trailing silence
+            Some(_) if player.is_playing && player.preloaded.is_none() && player.loop_region.is_none() && !player.stop_after_current && player.trim_end.is_none() && crossfade_length().is_none() => {
+                Some(player.duration.map(|duration| duration.saturating_sub(player.position())))
+            }
+            _ => None,
+        };
+        let plan = remaining
+            .filter(|remaining| due(*remaining))
+            .and_then(|_| expected_next(queue, player))
+            .map(|(index, track)| (player.generation, index, track))
+            .filter(|(generation, _, track)| *FAILED_PRELOAD.lock() != Some((*generation, track.path.clone())));
+        // Opened ahead for whichever way playback moves on to it
+        let upcoming = expected_next(queue, player)
+            .filter(|_| player.current_path.is_some() && player.preloaded.is_none())
+            .map(|(_, track)| track.path);
+        (changed, plan, upcoming)
+    });
+    prefetch::prepare(upcoming.as_deref());
+
+    if let Some(changed) = changed {
+        info!("Continued gaplessly into {}", changed.path);
+        record_played_location(&changed.path);
+        app.emit("track-changed", changed).ok();
+    }
+    if let Some((generation, position, track)) = plan {
+        preload(&handle, generation, position, track);
+    }
+}
+
+/// Decodes `track` off the playback thread, then has it appended if nothing
+/// moved on meanwhile.
+fn preload(player: &PlayerHandle, generation: u64, position: usize, track: QueuedTrack) {
+    let (OpenedSource { source, duration, duration_approximate, clock }, replay_gain) = match take_or_open(&track.path) {
+        Ok(opened) => opened,
+        Err(e) => {
+            // Left for the normal advance, which reports the failure when the current track ends
+            debug!("Could not preload {}: {}", track.path, e);
+            *FAILED_PRELOAD.lock() = Some((generation, track.path));
+            return;
+        }
+    };
+
+    player.send(move |player| with_queue(|queue| {
+        let unchanged = player.generation == generation
+            && player.preloaded.is_none()
+            && expected_next(queue, player).is_some_and(|(index, next)| index == position && next.path == track.path);
+        if !unchanged {
+            return;
+        }
+        let Some(sink) = &player.sink else { return };
+        let withdrawn = Arc::new(AtomicBool::new(false));
+        sink.append(Withdrawable { inner: source, withdrawn: Arc::clone(&withdrawn) });
+        debug!("Preloaded {} behind {:?}", track.path, player.current_path);
+        player.preloaded = Some(Preloaded {
+            path: track.path.clone(),
+            position,
+            track,
+            duration,
+            duration_approximate,
+            replay_gain,
+            clock,
+            withdrawn,
+        });
+    }));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rodio::buffer::SamplesBuffer;
+
+    #[test]
+    fn preloads_only_near_the_end_or_without_a_duration() {
+        assert!(!due(Some(Duration::from_secs(60))));
+        assert!(due(Some(PRELOAD_AHEAD)));
+        assert!(due(Some(Duration::ZERO)));
+        assert!(due(None));
+    }
+
+    #[test]
+    fn withdrawn_source_ends_immediately() {
+        let withdrawn = Arc::new(AtomicBool::new(false));
+        let mut source = Withdrawable {
+            inner: SamplesBuffer::new(2, 48_000, vec![1i16, 2, 3, 4]),
+            withdrawn: Arc::clone(&withdrawn),
+        };
+        assert_eq!(source.sample_rate(), 48_000);
+        assert_eq!(source.channels(), 2);
+        assert_eq!(source.next(), Some(1));
+
+        withdrawn.store(true, Ordering::Relaxed);
+        assert_eq!(source.next(), None);
+    }
+}
@@ -0,0 +1,182 @@
+use serde::Serialize;
+use std::fs::{self, OpenOptions};
+use std::sync::mpsc;
+use std::time::{Duration, Instant};
+use rodio::OutputStream;
+use log::info;
+use crate::cache::{cache_stats, CacheLimits, ALL_CACHES};
+use crate::config::{get_config_dir, get_config_file_path, load_player_config, CONFIG_SCHEMA_VERSION};
+use crate::paths::probe_paths;
+use crate::tasks::{get_background_tasks, TaskInfo, TaskKind, TaskState};
+use crate::load_config;
+
+// Upper bound for any single probe so an unresponsive mount can't hold up the report
+const PROBE_TIMEOUT: Duration = Duration::from_millis(1500);
+const LOW_DISK_SPACE: u64 = 500 * 1024 * 1024;
+
+#[derive(Debug, Serialize, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum CheckStatus {
+    Pass,
+    Warn,
+    Fail,
+}
+
+#[derive(Debug, Serialize)]
+pub struct HealthCheck {
+    pub name: String,
+    pub status: CheckStatus,
+    pub message: String,
+}
+
+#[derive(Debug, Serialize)]
+pub struct HealthReport {
+    pub overall: CheckStatus,
+    pub config_path: Option<String>,
+    pub checks: Vec<HealthCheck>,
+    pub duration_ms: u64,
+}
+
+fn check(name: &str, status: CheckStatus, message: impl Into<String>) -> HealthCheck {
+    HealthCheck {
+        name: name.to_string(),
+        status,
+        message: message.into(),
+    }
+}
+
+fn check_config_file() -> HealthCheck {
+    let path = match get_config_file_path() {
+        Some(path) => path,
+        None => return check("config_file", CheckStatus::Fail, "Could not determine config directory"),
+    };
+
+    if !path.exists() {
+        return check("config_file", CheckStatus::Warn, format!("{} does not exist yet; defaults are in use", path.display()));
+    }
+
+    if let Err(e) = fs::read_to_string(&path) {
+        return check("config_file", CheckStatus::Fail, format!("{} is not readable: {}", path.display(), e));
+    }
+
+    // Opening for append checks write permission without touching the contents
+    match OpenOptions::new().append(true).open(&path) {
+        Ok(_) => check("config_file", CheckStatus::Pass, format!("{} is readable and writable", path.display())),
+        Err(e) => check("config_file", CheckStatus::Fail, format!("{} is not writable: {}", path.display(), e)),
+    }
+}
+
+fn check_schema_version() -> HealthCheck {
+    let contents = get_config_file_path().and_then(|path| fs::read_to_string(path).ok());
+    let value = match contents {
+        Some(contents) => serde_json::from_str::<serde_json::Value>(&contents),
+        None => return check("config_schema", CheckStatus::Pass, format!("Using defaults (schema {})", CONFIG_SCHEMA_VERSION)),
+    };
+
+    match value {
+        Ok(value) => match value.get("schema_version").and_then(|v| v.as_u64()) {
+            Some(version) if version == CONFIG_SCHEMA_VERSION as u64 => {
+                check("config_schema", CheckStatus::Pass, format!("Schema version {}", version))
+            }
+            Some(version) => check("config_schema", CheckStatus::Warn, format!(
+                "Schema version {} differs from the supported version {}", version, CONFIG_SCHEMA_VERSION
+            )),
+            None => check("config_schema", CheckStatus::Warn, "Config predates schema versioning; it will be upgraded on next save"),
+        },
+        Err(e) => check("config_schema", CheckStatus::Fail, format!("Config is not valid JSON and will be ignored: {}", e)),
+    }
+}
+
+fn check_audio_output() -> HealthCheck {
+    // OutputStream is not Send, so it is opened and dropped entirely on the probe thread
+    let (tx, rx) = mpsc::channel();
+    std::thread::spawn(move || {
+        let result = OutputStream::try_default().map(|_| ()).map_err(|e| e.to_string());
+        let _ = tx.send(result);
+    });
+
+    match rx.recv_timeout(PROBE_TIMEOUT) {
+        Ok(Ok(())) => check("audio_output", CheckStatus::Pass, "Default output device opened successfully"),
+        Ok(Err(e)) => check("audio_output", CheckStatus::Fail, format!("Could not open the default output device: {}", e)),
+        Err(_) => check("audio_output", CheckStatus::Warn, "Timed out opening the default output device"),
+    }
+}
+
+fn check_locations(name: &str, label: &str, locations: Vec<String>) -> Vec<HealthCheck> {
+    let results = probe_paths(&locations, PROBE_TIMEOUT);
+    locations.iter().zip(results).map(|(location, result)| match result {
+        Some(true) => check(name, CheckStatus::Pass, format!("{} {} is reachable", label, location)),
+        Some(false) => check(name, CheckStatus::Fail, format!("{} {} does not exist or is not mounted", label, location)),
+        None => check(name, CheckStatus::Warn, format!("{} {} did not respond within {}ms", label, location, PROBE_TIMEOUT.as_millis())),
+    }).collect()
+}
+
+fn check_free_space() -> HealthCheck {
+    let dir = match get_config_dir() {
+        Some(dir) => dir,
+        None => return check("free_space", CheckStatus::Warn, "Could not determine the app data directory"),
+    };
+    let _ = fs::create_dir_all(&dir);
+
+    match fs2::available_space(&dir) {
+        Ok(bytes) if bytes < LOW_DISK_SPACE => check("free_space", CheckStatus::Warn, format!(
+            "Only {} MB free on the app data volume", bytes / (1024 * 1024)
+        )),
+        Ok(bytes) => check("free_space", CheckStatus::Pass, format!("{} MB free on the app data volume", bytes / (1024 * 1024))),
+        Err(e) => check("free_space", CheckStatus::Warn, format!("Could not determine free space: {}", e)),
+    }
+}
+
+fn check_cache_sizes(limits: &CacheLimits) -> Vec<HealthCheck> {
+    ALL_CACHES.iter().map(|kind| {
+        let stats = cache_stats(*kind, limits);
+        let status = if stats.bytes > stats.limit_bytes { CheckStatus::Warn } else { CheckStatus::Pass };
+        check("cache_size", status, format!(
+            "{:?}: {} entries, {} KB of {} KB", stats.kind, stats.entries, stats.bytes / 1024, stats.limit_bytes / 1024
+        ))
+    }).collect()
+}
+
+fn check_incomplete_transfers() -> HealthCheck {
+    // Failed and cancelled transfers can leave partial copies on the target
+    let transfers: Vec<TaskInfo> = get_background_tasks().into_iter().filter(|task| task.kind == TaskKind::Transfer).collect();
+    let running = transfers.iter().filter(|task| task.state == TaskState::Running).count();
+    let unfinished = transfers.iter().filter(|task| matches!(task.state, TaskState::Failed | TaskState::Cancelled)).count();
+    let message = format!("{} incomplete transfers ({} running, {} failed or cancelled)", running + unfinished, running, unfinished);
+    check("incomplete_transfers", if unfinished > 0 { CheckStatus::Warn } else { CheckStatus::Pass }, message)
+}
+
+#[tauri::command]
+pub async fn run_health_check() -> Result<HealthReport, String> {
+    let started = Instant::now();
+    let config = load_player_config();
+
+    let mut checks = vec![check_config_file(), check_schema_version(), check_audio_output()];
+
+    let roots: Vec<String> = config.library_roots.iter().map(|root| root.path.clone()).collect();
+    if roots.is_empty() {
+        checks.push(check("library_root", CheckStatus::Warn, "No library roots configured"));
+    }
+    checks.extend(check_locations("library_root", "Library root", roots));
+    checks.extend(check_locations("favorite_location", "Favorite", load_config().favorite_locations.into_iter().map(|entry| entry.path).collect()));
+    checks.push(check_free_space());
+    checks.extend(check_cache_sizes(&config.cache_limits));
+    checks.push(check_incomplete_transfers());
+
+    let overall = if checks.iter().any(|c| c.status == CheckStatus::Fail) {
+        CheckStatus::Fail
+    } else if checks.iter().any(|c| c.status == CheckStatus::Warn) {
+        CheckStatus::Warn
+    } else {
+        CheckStatus::Pass
+    };
+
+    let report = HealthReport {
+        overall,
+        config_path: get_config_file_path().map(|path| path.to_string_lossy().to_string()),
+        checks,
+        duration_ms: started.elapsed().as_millis() as u64,
+    };
+    info!("Health check finished in {}ms: {:?}", report.duration_ms, report.overall);
+    Ok(report)
+}
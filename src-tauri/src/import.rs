@@ -0,0 +1,341 @@
+use serde::Serialize;
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{channel, RecvTimeoutError, Sender};
+use std::time::{Duration, Instant};
+use notify::{Event, RecommendedWatcher, RecursiveMode, Watcher};
+use once_cell::sync::Lazy;
+use parking_lot::Mutex;
+use tauri::{AppHandle, Emitter};
+use log::{info, error, debug};
+use crate::config::{load_player_config, WatchAction, WatchFolderConfig};
+use crate::library::{index_file, is_audio_file};
+use crate::metadata::{read_audio_metadata, AudioMetadata};
+
+pub const NEEDS_REVIEW_DIR: &str = "_needs_review";
+
+// A download counts as finished once its size has held steady this long
+const STABLE_FOR: Duration = Duration::from_secs(3);
+const POLL_INTERVAL: Duration = Duration::from_secs(1);
+
+#[derive(Debug, Serialize, Clone)]
+pub struct ImportedFile {
+    pub source: String,
+    pub destination: String,
+}
+
+#[derive(Debug, Serialize, Clone)]
+pub struct ImportFailure {
+    pub source: String,
+    pub error: String,
+}
+
+#[derive(Debug, Serialize, Clone, Default)]
+pub struct AutoImportCompleted {
+    pub watch_folder: String,
+    pub imported: Vec<ImportedFile>,
+    // Files whose tags couldn't be read, parked under `_needs_review` in the watch folder
+    pub needs_review: Vec<ImportedFile>,
+    pub failed: Vec<ImportFailure>,
+}
+
+struct PendingFile {
+    size: u64,
+    stable_since: Instant,
+}
+
+// Files seen by a watcher but not imported yet; the library scan leaves these alone
+static PENDING: Lazy<Mutex<HashMap<PathBuf, PendingFile>>> = Lazy::new(|| Mutex::new(HashMap::new()));
+static WATCHER: Lazy<Mutex<Option<RecommendedWatcher>>> = Lazy::new(|| Mutex::new(None));
+static EVENTS: Lazy<Mutex<Option<Sender<PathBuf>>>> = Lazy::new(|| Mutex::new(None));
+
+pub fn is_import_pending(path: &Path) -> bool {
+    PENDING.lock().contains_key(path)
+}
+
+/// Replaces characters that are invalid in file names on common filesystems.
+fn sanitize_component(value: &str) -> String {
+    let cleaned: String = value.chars()
+        .map(|c| if matches!(c, '/' | '\\' | ':' | '*' | '?' | '"' | '<' | '>' | '|') || c.is_control() { '_' } else { c })
+        .collect();
+    // Windows rejects names ending in a dot or space
+    let cleaned = cleaned.trim().trim_end_matches('.').trim();
+    if cleaned.is_empty() { "_".to_string() } else { cleaned.to_string() }
+}
+
+/// Expands `pattern` for a file with tags `metadata`. Each `/`-separated segment
+/// becomes one folder (or the file name), so tag values can't add levels.
+pub fn organize_destination(pattern: &str, target_root: &Path, source: &Path, metadata: &AudioMetadata) -> PathBuf {
+    let stem = source.file_stem().map(|s| s.to_string_lossy().to_string()).unwrap_or_default();
+    let artist = metadata.artist.clone().unwrap_or_else(|| "Unknown Artist".to_string());
+    let values = [
+        ("{artist}", artist.clone()),
+        ("{album_artist}", metadata.album_artist.clone().unwrap_or(artist)),
+        ("{album}", metadata.album.clone().unwrap_or_else(|| "Unknown Album".to_string())),
+        ("{title}", metadata.title.clone().unwrap_or_else(|| stem.clone())),
+        ("{track}", metadata.track_number.map(|n| format!("{:02}", n)).unwrap_or_default()),
+        ("{year}", metadata.year.map(|y| y.to_string()).unwrap_or_default()),
+        ("{genre}", metadata.genre.clone().unwrap_or_default()),
+        ("{filename}", stem),
+    ];
+
+    let segments: Vec<String> = pattern.split(['/', '\\'])
+        .filter(|segment| !segment.trim().is_empty())
+        .map(|segment| {
+            let expanded = values.iter().fold(segment.to_string(), |acc, (key, value)| acc.replace(key, value));
+            // A missing track number leaves "- Title"; drop the dangling separator
+            sanitize_component(expanded.trim_start_matches([' ', '-']))
+        })
+        .collect();
+
+    let mut destination = target_root.to_path_buf();
+    for segment in &segments {
+        destination.push(segment);
+    }
+    if let Some(ext) = source.extension() {
+        let name = format!("{}.{}", segments.last().map(String::as_str).unwrap_or("_"), ext.to_string_lossy());
+        destination.set_file_name(name);
+    }
+    destination
+}
+
+/// `path`, or `name (2).ext`, `name (3).ext`... if something already lives there.
+fn unique_destination(path: PathBuf) -> PathBuf {
+    if !path.exists() {
+        return path;
+    }
+    let stem = path.file_stem().map(|s| s.to_string_lossy().to_string()).unwrap_or_default();
+    let ext = path.extension().map(|e| format!(".{}", e.to_string_lossy())).unwrap_or_default();
+    (2..)
+        .map(|n| path.with_file_name(format!("{} ({}){}", stem, n, ext)))
+        .find(|candidate| !candidate.exists())
+        .unwrap_or(path)
+}
+
+/// Moves `source` to `destination`, copying across filesystems when a rename can't.
+fn move_file(source: &Path, destination: &Path) -> Result<(), String> {
+    if fs::rename(source, destination).is_ok() {
+        return Ok(());
+    }
+    copy_file(source, destination)?;
+    fs::remove_file(source).map_err(|e| format!("Copied but could not remove {}: {}", source.display(), e))
+}
+
+fn copy_file(source: &Path, destination: &Path) -> Result<(), String> {
+    let copied = fs::copy(source, destination).map_err(|e| format!("Failed to copy {}: {}", source.display(), e))?;
+    let expected = fs::metadata(source).map(|m| m.len()).unwrap_or(copied);
+    if copied != expected {
+        let _ = fs::remove_file(destination);
+        return Err(format!("Copy of {} is incomplete", source.display()));
+    }
+    Ok(())
+}
+
+fn is_in_library(path: &Path) -> bool {
+    load_player_config().library_roots.iter().any(|root| path.starts_with(&root.path))
+}
+
+enum ImportOutcome {
+    Imported(ImportedFile),
+    NeedsReview(ImportedFile),
+}
+
+/// Runs one file through the import pipeline: probe tags, place it under the
+/// watch folder's target root and index it if that is part of the library.
+fn import_file(source: &Path, folder: &WatchFolderConfig) -> Result<ImportOutcome, String> {
+    if folder.target_root.trim().is_empty() {
+        return Err("Watch folder has no target root".to_string());
+    }
+    let metadata = match read_audio_metadata(source, false) {
+        Ok(metadata) => metadata,
+        Err(e) => {
+            // Unreadable tags won't fix themselves, so park the file instead of retrying it
+            debug!("Tag probe failed for {}: {}", source.display(), e);
+            let review_dir = Path::new(&folder.path).join(NEEDS_REVIEW_DIR);
+            fs::create_dir_all(&review_dir).map_err(|e| format!("Failed to create {}: {}", review_dir.display(), e))?;
+            let destination = unique_destination(review_dir.join(source.file_name().unwrap_or_default()));
+            move_file(source, &destination)?;
+            return Ok(ImportOutcome::NeedsReview(ImportedFile {
+                source: source.to_string_lossy().to_string(),
+                destination: destination.to_string_lossy().to_string(),
+            }));
+        }
+    };
+
+    let destination = unique_destination(organize_destination(
+        &folder.organize_pattern,
+        Path::new(&folder.target_root),
+        source,
+        &metadata,
+    ));
+    if let Some(parent) = destination.parent() {
+        fs::create_dir_all(parent).map_err(|e| format!("Failed to create {}: {}", parent.display(), e))?;
+    }
+
+    match folder.action {
+        WatchAction::Move => move_file(source, &destination)?,
+        WatchAction::Copy => {
+            copy_file(source, &destination)?;
+            if folder.delete_after_import {
+                fs::remove_file(source).map_err(|e| format!("Imported but could not remove {}: {}", source.display(), e))?;
+            }
+        }
+    }
+
+    if is_in_library(&destination) {
+        if let Err(e) = index_file(&destination) {
+            error!("Failed to index imported file {}: {}", destination.display(), e);
+        }
+    }
+
+    Ok(ImportOutcome::Imported(ImportedFile {
+        source: source.to_string_lossy().to_string(),
+        destination: destination.to_string_lossy().to_string(),
+    }))
+}
+
+/// The watch folder responsible for `path`, unless the path is one of the
+/// importer's own outputs (a nested target root or `_needs_review`).
+fn folder_for<'a>(path: &Path, folders: &'a [WatchFolderConfig]) -> Option<&'a WatchFolderConfig> {
+    if folders.iter().any(|folder| !folder.target_root.is_empty() && path.starts_with(&folder.target_root)) {
+        return None;
+    }
+    folders.iter()
+        .filter(|folder| path.starts_with(&folder.path))
+        .max_by_key(|folder| folder.path.len())
+        .filter(|folder| !path.starts_with(Path::new(&folder.path).join(NEEDS_REVIEW_DIR)))
+}
+
+/// Imports every pending file whose size has stopped changing.
+fn process_stable_files(app: &AppHandle) {
+    let now = Instant::now();
+    let ready: Vec<PathBuf> = {
+        let mut pending = PENDING.lock();
+        pending.retain(|path, _| path.is_file());
+        pending.iter_mut()
+            .filter_map(|(path, file)| {
+                let size = fs::metadata(path).map(|m| m.len()).unwrap_or(0);
+                if size != file.size || size == 0 {
+                    file.size = size;
+                    file.stable_since = now;
+                    return None;
+                }
+                (now.duration_since(file.stable_since) >= STABLE_FOR).then(|| path.clone())
+            })
+            .collect()
+    };
+    if ready.is_empty() {
+        return;
+    }
+
+    let folders = load_player_config().watch_folders;
+    let mut reports: HashMap<String, AutoImportCompleted> = HashMap::new();
+    for path in ready {
+        let Some(folder) = folder_for(&path, &folders) else {
+            PENDING.lock().remove(&path);
+            continue;
+        };
+        let report = reports.entry(folder.path.clone()).or_insert_with(|| AutoImportCompleted {
+            watch_folder: folder.path.clone(),
+            ..Default::default()
+        });
+
+        match import_file(&path, folder) {
+            Ok(ImportOutcome::Imported(file)) => {
+                info!("Imported {} -> {}", file.source, file.destination);
+                report.imported.push(file);
+            }
+            Ok(ImportOutcome::NeedsReview(file)) => {
+                info!("Moved {} to {} for review", file.source, NEEDS_REVIEW_DIR);
+                report.needs_review.push(file);
+            }
+            Err(e) => {
+                error!("Failed to import {}: {}", path.display(), e);
+                report.failed.push(ImportFailure {
+                    source: path.to_string_lossy().to_string(),
+                    error: e,
+                });
+            }
+        }
+        // Failures are reported once; the file is picked up again only if it changes
+        PENDING.lock().remove(&path);
+    }
+
+    for report in reports.into_values() {
+        app.emit("auto-import-completed", report).ok();
+    }
+}
+
+fn track_pending(path: PathBuf) {
+    if !is_audio_file(&path) {
+        return;
+    }
+    let mut pending = PENDING.lock();
+    pending.entry(path).or_insert_with(|| PendingFile {
+        size: 0,
+        stable_since: Instant::now(),
+    });
+}
+
+/// (Re)starts watching the configured watch folders. The worker thread is
+/// started once and survives config changes; only the watcher is replaced.
+pub fn start_watch_folders(app: AppHandle) {
+    let sender = {
+        let mut events = EVENTS.lock();
+        if events.is_none() {
+            let (tx, rx) = channel::<PathBuf>();
+            std::thread::spawn(move || loop {
+                match rx.recv_timeout(POLL_INTERVAL) {
+                    Ok(path) => track_pending(path),
+                    Err(RecvTimeoutError::Timeout) => {}
+                    Err(RecvTimeoutError::Disconnected) => break,
+                }
+                while let Ok(path) = rx.try_recv() {
+                    track_pending(path);
+                }
+                process_stable_files(&app);
+            });
+            *events = Some(tx);
+        }
+        events.as_ref().cloned()
+    };
+    let Some(sender) = sender else { return };
+
+    let folders = load_player_config().watch_folders;
+    let mut current = WATCHER.lock();
+    // Dropping the old watcher stops its notifications before the new one starts
+    *current = None;
+    if folders.is_empty() {
+        return;
+    }
+
+    let watcher = notify::recommended_watcher(move |res: Result<Event, notify::Error>| match res {
+        Ok(event) if event.kind.is_create() || event.kind.is_modify() => {
+            for path in event.paths {
+                let _ = sender.send(path);
+            }
+        }
+        Ok(_) => {}
+        Err(e) => error!("Watch folder error: {}", e),
+    });
+    let mut watcher = match watcher {
+        Ok(watcher) => watcher,
+        Err(e) => {
+            error!("Failed to create watch folder watcher: {}", e);
+            return;
+        }
+    };
+
+    let mut watched = HashSet::new();
+    for folder in &folders {
+        if !watched.insert(folder.path.clone()) {
+            continue;
+        }
+        match watcher.watch(Path::new(&folder.path), RecursiveMode::Recursive) {
+            Ok(()) => info!("Watching {} for new audio files", folder.path),
+            Err(e) => error!("Failed to watch {}: {}", folder.path, e),
+        }
+    }
+    *current = Some(watcher);
+}
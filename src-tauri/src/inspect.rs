@@ -0,0 +1,550 @@
+use serde::Serialize;
+use std::fs::File;
+use std::io::{BufReader, Read, Seek, SeekFrom};
+use std::path::Path;
+use lofty::{
+    config::ParseOptions, file::FileType, iff::wav::WavFile, mp4::Mp4File, prelude::{AudioFile, ItemKey, TaggedFileExt}, probe::Probe, tag::{Accessor, Tag},
+};
+use rodio::Source;
+use crate::decode::open_decoder;
+use crate::output_format::output_caps;
+
+// Header and decoded durations further apart than this are reported
+const DURATION_MISMATCH: f64 = 0.05;
+// How much of the file is searched for the first MPEG frame after the ID3v2 tag
+const FIRST_FRAME_SEARCH: u64 = 128 * 1024;
+// How much of the end of the audio is searched for the last frame
+const LAST_FRAME_SEARCH: u64 = 16 * 1024;
+
+#[derive(Debug, Serialize, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum BitrateMode {
+    Constant,
+    Variable,
+}
+
+#[derive(Debug, Serialize)]
+pub struct TagSummary {
+    pub tag_type: String,
+    pub item_count: u32,
+    pub picture_count: u32,
+}
+
+#[derive(Debug, Serialize)]
+pub struct PictureSummary {
+    pub tag_type: String,
+    pub picture_type: String,
+    pub mime_type: Option<String>,
+    pub size: usize,
+}
+
+#[derive(Debug, Serialize)]
+pub struct Id3v2Info {
+    // e.g. "2.4.0"
+    pub version: String,
+    // Whole tag including the header
+    pub size: u64,
+    // None when the tag is unsynchronised and its frames can't be walked
+    pub padding: Option<u64>,
+}
+
+#[derive(Debug, Serialize, Clone)]
+pub struct MpegFrameHeader {
+    pub offset: u64,
+    pub version: String,
+    pub layer: u8,
+    pub bitrate_kbps: u32,
+    pub sample_rate: u32,
+    pub channel_mode: String,
+    pub padding: bool,
+    pub length: u32,
+}
+
+#[derive(Debug, Serialize)]
+pub struct MpegInfo {
+    pub first_frame: MpegFrameHeader,
+    pub last_frame: Option<MpegFrameHeader>,
+    // "Xing", "Info" or "VBRI"
+    pub vbr_header: Option<String>,
+    pub frame_count: Option<u32>,
+    pub lame_tag: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct AudioFileInspection {
+    pub path: String,
+    pub file_size: u64,
+    pub container: Option<String>,
+    pub codec: Option<String>,
+    pub encoder: Option<String>,
+    pub bitrate_mode: Option<BitrateMode>,
+    pub duration: Option<f64>,
+    // From the MPEG frame headers, or what the decoder reports for other formats
+    pub estimated_duration: Option<f64>,
+    pub audio_bitrate: Option<u32>,
+    pub overall_bitrate: Option<u32>,
+    pub sample_rate: Option<u32>,
+    pub bit_depth: Option<u32>,
+    pub channels: Option<u32>,
+    pub tags: Vec<TagSummary>,
+    pub pictures: Vec<PictureSummary>,
+    pub id3v2: Option<Id3v2Info>,
+    pub id3v1: bool,
+    pub ape: bool,
+    pub mpeg: Option<MpegInfo>,
+    pub decoder_error: Option<String>,
+    // Set when the sample rate is above what the current output device accepts
+    pub device_max_sample_rate: Option<u32>,
+    pub warnings: Vec<String>,
+}
+
+/// Reads up to `len` bytes at `offset`; short reads at the end of the file are fine.
+fn read_at(file: &mut File, offset: u64, len: u64) -> std::io::Result<Vec<u8>> {
+    file.seek(SeekFrom::Start(offset))?;
+    let mut buffer = Vec::new();
+    file.take(len).read_to_end(&mut buffer)?;
+    Ok(buffer)
+}
+
+fn syncsafe(bytes: &[u8]) -> u64 {
+    bytes.iter().fold(0, |acc, b| (acc << 7) | (*b & 0x7f) as u64)
+}
+
+fn big_endian(bytes: &[u8]) -> u64 {
+    bytes.iter().fold(0, |acc, b| (acc << 8) | *b as u64)
+}
+
+/// Parses the ID3v2 tag at the start of the file, walking its frames to find the padding.
+fn read_id3v2(file: &mut File, warnings: &mut Vec<String>) -> Option<Id3v2Info> {
+    let header = read_at(file, 0, 10).ok()?;
+    if header.len() < 10 || &header[..3] != b"ID3" {
+        return None;
+    }
+    let (major, revision, flags) = (header[3], header[4], header[5]);
+    let body_size = syncsafe(&header[6..10]);
+    let footer = if major >= 4 && flags & 0x10 != 0 { 10 } else { 0 };
+    let size = 10 + body_size + footer;
+    let version = format!("2.{}.{}", major, revision);
+
+    let body = read_at(file, 10, body_size).ok()?;
+    if (body.len() as u64) < body_size {
+        warnings.push(format!("ID3v{} tag claims {} bytes but the file ends first", version, size));
+        return Some(Id3v2Info { version, size, padding: None });
+    }
+    if flags & 0x80 != 0 && major < 4 {
+        return Some(Id3v2Info { version, size, padding: None });
+    }
+
+    let mut position = 0usize;
+    if flags & 0x40 != 0 && body.len() >= 4 {
+        // Extended header: v2.3 gives its size excluding the size field, v2.4 including it
+        position = match major {
+            3 => 4 + big_endian(&body[..4]) as usize,
+            _ => syncsafe(&body[..4]) as usize,
+        };
+    }
+
+    let (header_len, id_len) = if major == 2 { (6, 3) } else { (10, 4) };
+    while position + header_len <= body.len() && body[position] != 0 {
+        let size_bytes = &body[position + id_len..position + id_len + (header_len - id_len).min(4)];
+        let frame_size = match major {
+            2 => big_endian(&size_bytes[..3]),
+            3 => big_endian(size_bytes),
+            _ => syncsafe(size_bytes),
+        } as usize;
+        position += header_len + frame_size;
+    }
+    if position > body.len() {
+        warnings.push(format!("ID3v{} frames run past the end of the tag", version));
+        return Some(Id3v2Info { version, size, padding: None });
+    }
+    Some(Id3v2Info { version, size, padding: Some((body.len() - position) as u64) })
+}
+
+/// Size of an APE tag ending at `end`, including its header when present.
+fn ape_tag_size(file: &mut File, end: u64) -> Option<u64> {
+    let footer = read_at(file, end.checked_sub(32)?, 32).ok()?;
+    if footer.len() < 32 || &footer[..8] != b"APETAGEX" {
+        return None;
+    }
+    let size = u32::from_le_bytes(footer[12..16].try_into().ok()?) as u64;
+    let flags = u32::from_le_bytes(footer[20..24].try_into().ok()?);
+    let header = if flags & 0x8000_0000 != 0 { 32 } else { 0 };
+    Some(size + header)
+}
+
+const BITRATES_V1: [[u32; 15]; 3] = [
+    [0, 32, 64, 96, 128, 160, 192, 224, 256, 288, 320, 352, 384, 416, 448],
+    [0, 32, 48, 56, 64, 80, 96, 112, 128, 160, 192, 224, 256, 320, 384],
+    [0, 32, 40, 48, 56, 64, 80, 96, 112, 128, 160, 192, 224, 256, 320],
+];
+const BITRATES_V2: [[u32; 15]; 2] = [
+    [0, 32, 48, 56, 64, 80, 96, 112, 128, 144, 160, 176, 192, 224, 256],
+    [0, 8, 16, 24, 32, 40, 48, 56, 64, 80, 96, 112, 128, 144, 160],
+];
+
+/// Parses a 4-byte MPEG audio frame header. Free-format frames are rejected
+/// since their length can't be computed from the header.
+pub fn parse_frame_header(bytes: &[u8], offset: u64) -> Option<MpegFrameHeader> {
+    if bytes.len() < 4 || bytes[0] != 0xff || bytes[1] & 0xe0 != 0xe0 {
+        return None;
+    }
+    let version_bits = (bytes[1] >> 3) & 3;
+    let layer = match (bytes[1] >> 1) & 3 {
+        1 => 3,
+        2 => 2,
+        3 => 1,
+        _ => return None,
+    };
+    let bitrate_index = (bytes[2] >> 4) as usize;
+    let rate_index = ((bytes[2] >> 2) & 3) as usize;
+    if version_bits == 1 || bitrate_index == 0 || bitrate_index == 15 || rate_index == 3 {
+        return None;
+    }
+
+    let (version, rates) = match version_bits {
+        3 => ("MPEG-1", [44100, 48000, 32000]),
+        2 => ("MPEG-2", [22050, 24000, 16000]),
+        _ => ("MPEG-2.5", [11025, 12000, 8000]),
+    };
+    let bitrate_kbps = if version_bits == 3 {
+        BITRATES_V1[layer as usize - 1][bitrate_index]
+    } else {
+        BITRATES_V2[if layer == 1 { 0 } else { 1 }][bitrate_index]
+    };
+    let sample_rate = rates[rate_index];
+    let padding = (bytes[2] >> 1) & 1 == 1;
+    let length = match layer {
+        1 => (12 * bitrate_kbps * 1000 / sample_rate + padding as u32) * 4,
+        _ => samples_per_frame(version_bits == 3, layer) / 8 * bitrate_kbps * 1000 / sample_rate + padding as u32,
+    };
+    let channel_mode = match bytes[3] >> 6 {
+        0 => "stereo",
+        1 => "joint stereo",
+        2 => "dual channel",
+        _ => "mono",
+    };
+
+    Some(MpegFrameHeader {
+        offset,
+        version: version.to_string(),
+        layer,
+        bitrate_kbps,
+        sample_rate,
+        channel_mode: channel_mode.to_string(),
+        padding,
+        length,
+    })
+}
+
+fn samples_per_frame(mpeg1: bool, layer: u8) -> u32 {
+    match layer {
+        1 => 384,
+        2 => 1152,
+        _ if mpeg1 => 1152,
+        _ => 576,
+    }
+}
+
+fn same_stream(a: &MpegFrameHeader, b: &MpegFrameHeader) -> bool {
+    a.version == b.version && a.layer == b.layer && a.sample_rate == b.sample_rate
+}
+
+/// Finds the first frame at or after `start` that is followed by another frame,
+/// so stray sync bytes in leftover data aren't mistaken for audio.
+fn find_first_frame(buffer: &[u8], base: u64) -> Option<MpegFrameHeader> {
+    (0..buffer.len().saturating_sub(4)).find_map(|i| {
+        let frame = parse_frame_header(&buffer[i..], base + i as u64)?;
+        let next = i + frame.length as usize;
+        match buffer.get(next..) {
+            Some(rest) if rest.len() >= 4 => parse_frame_header(rest, 0).filter(|n| same_stream(&frame, n)).map(|_| frame),
+            // A single frame that fills the rest of the buffer
+            _ => Some(frame),
+        }
+    })
+}
+
+/// Xing/Info or VBRI header found in the first frame.
+struct VbrHeader {
+    name: String,
+    frames: Option<u32>,
+    bytes: Option<u32>,
+    lame: Option<String>,
+}
+
+fn read_vbr_header(frame: &[u8], header: &MpegFrameHeader) -> Option<VbrHeader> {
+    let mono = header.channel_mode == "mono";
+    let side_info = match (header.version == "MPEG-1", mono) {
+        (true, false) => 32,
+        (true, true) | (false, false) => 17,
+        (false, true) => 9,
+    };
+    let read_u32 = |at: usize| frame.get(at..at + 4).map(|b| big_endian(b) as u32);
+
+    let xing = 4 + side_info;
+    if let Some(name @ (b"Xing" | b"Info")) = frame.get(xing..xing + 4) {
+        let flags = read_u32(xing + 4)?;
+        let mut position = xing + 8;
+        let mut frames = None;
+        let mut bytes = None;
+        if flags & 1 != 0 {
+            frames = read_u32(position);
+            position += 4;
+        }
+        if flags & 2 != 0 {
+            bytes = read_u32(position);
+            position += 4;
+        }
+        if flags & 4 != 0 {
+            position += 100;
+        }
+        if flags & 8 != 0 {
+            position += 4;
+        }
+        let lame = frame.get(position..position + 9)
+            .filter(|tag| tag.iter().all(|b| b.is_ascii_graphic() || *b == b' ') && tag[0].is_ascii_alphabetic())
+            .map(|tag| String::from_utf8_lossy(tag).trim().to_string());
+        return Some(VbrHeader { name: String::from_utf8_lossy(name).to_string(), frames, bytes, lame });
+    }
+
+    if frame.get(36..40) == Some(b"VBRI") {
+        return Some(VbrHeader { name: "VBRI".to_string(), frames: read_u32(50), bytes: read_u32(46), lame: None });
+    }
+    None
+}
+
+/// Follows the frame chain through the end of the audio to find the last frame.
+fn find_last_frame(file: &mut File, first: &MpegFrameHeader, audio_end: u64, warnings: &mut Vec<String>) -> Option<MpegFrameHeader> {
+    let start = audio_end.saturating_sub(LAST_FRAME_SEARCH).max(first.offset);
+    let tail = read_at(file, start, audio_end - start).ok()?;
+    let mut frame = find_first_frame(&tail, start).filter(|frame| same_stream(first, frame))?;
+
+    loop {
+        let next = (frame.offset - start) as usize + frame.length as usize;
+        match tail.get(next..).and_then(|rest| parse_frame_header(rest, start + next as u64)) {
+            Some(next_frame) if same_stream(first, &next_frame) => frame = next_frame,
+            _ => break,
+        }
+    }
+
+    let frame_end = frame.offset + frame.length as u64;
+    if frame_end > audio_end {
+        warnings.push(format!("Last frame is truncated by {} bytes", frame_end - audio_end));
+    } else if frame_end < audio_end {
+        warnings.push(format!("{} bytes of unrecognized data after the last frame", audio_end - frame_end));
+    }
+    Some(frame)
+}
+
+fn inspect_mpeg(file: &mut File, audio_start: u64, audio_end: u64, warnings: &mut Vec<String>) -> Option<(MpegInfo, Option<f64>)> {
+    let head = read_at(file, audio_start, FIRST_FRAME_SEARCH.min(audio_end.saturating_sub(audio_start))).ok()?;
+    let Some(first) = find_first_frame(&head, audio_start) else {
+        warnings.push("No MPEG audio frames found".to_string());
+        return None;
+    };
+    if first.offset > audio_start {
+        warnings.push(format!("{} bytes of unrecognized data before the first frame", first.offset - audio_start));
+    }
+
+    let frame_bytes = &head[(first.offset - audio_start) as usize..];
+    let vbr = read_vbr_header(frame_bytes, &first);
+    let audio_bytes = audio_end.saturating_sub(first.offset);
+    let mpeg1 = first.version == "MPEG-1";
+
+    let estimated = match vbr.as_ref().and_then(|vbr| vbr.frames) {
+        Some(frames) => frames as f64 * samples_per_frame(mpeg1, first.layer) as f64 / first.sample_rate as f64,
+        None => audio_bytes as f64 * 8.0 / (first.bitrate_kbps as f64 * 1000.0),
+    };
+    if let Some(VbrHeader { name, bytes: Some(bytes), .. }) = &vbr {
+        if (*bytes as u64) > audio_bytes + audio_bytes / 20 {
+            warnings.push(format!("{} header expects {} bytes of audio but only {} are present; the file looks truncated", name, bytes, audio_bytes));
+        }
+    }
+
+    let last_frame = find_last_frame(file, &first, audio_end, warnings);
+    let (vbr_header, frame_count, lame_tag) = match vbr {
+        Some(vbr) => (Some(vbr.name), vbr.frames, vbr.lame),
+        None => (None, None, None),
+    };
+    Some((MpegInfo { first_frame: first, last_frame, vbr_header, frame_count, lame_tag }, Some(estimated)))
+}
+
+fn codec_for(path: &Path, file_type: FileType, mpeg: Option<&MpegInfo>) -> Option<String> {
+    let open = || File::open(path).ok().map(BufReader::new);
+    match file_type {
+        FileType::Mpeg => mpeg.map(|info| format!("{} Layer {}", info.first_frame.version, "I".repeat(info.first_frame.layer as usize))),
+        FileType::Mp4 => open()
+            .and_then(|mut reader| Mp4File::read_from(&mut reader, ParseOptions::new()).ok())
+            .map(|file| format!("{:?}", file.properties().codec())),
+        FileType::Wav => open()
+            .and_then(|mut reader| WavFile::read_from(&mut reader, ParseOptions::new()).ok())
+            .map(|file| format!("{:?}", file.properties().format())),
+        FileType::Aac => Some("AAC (ADTS)".to_string()),
+        FileType::Aiff => Some("PCM".to_string()),
+        FileType::Ape => Some("Monkey's Audio".to_string()),
+        FileType::Flac => Some("FLAC".to_string()),
+        FileType::Mpc => Some("Musepack".to_string()),
+        FileType::Opus => Some("Opus".to_string()),
+        FileType::Vorbis => Some("Vorbis".to_string()),
+        FileType::Speex => Some("Speex".to_string()),
+        FileType::WavPack => Some("WavPack".to_string()),
+        FileType::Custom(name) => Some(name.to_string()),
+        _ => None,
+    }
+}
+
+type TagField = fn(&Tag) -> Option<String>;
+
+/// Warns when tag types disagree on a field, such as an old ID3v1 title next to an updated ID3v2 one.
+fn check_tag_conflicts(tags: &[Tag], warnings: &mut Vec<String>) {
+    let fields: [(&str, TagField); 3] = [
+        ("title", |tag| tag.title().map(|s| s.trim().to_string())),
+        ("artist", |tag| tag.artist().map(|s| s.trim().to_string())),
+        ("album", |tag| tag.album().map(|s| s.trim().to_string())),
+    ];
+    for (field, get) in fields {
+        let values: Vec<(String, String)> = tags.iter()
+            .filter_map(|tag| get(tag).filter(|v| !v.is_empty()).map(|v| (format!("{:?}", tag.tag_type()), v)))
+            .collect();
+        // ID3v1 truncates to 30 characters, so a prefix of a longer value is not a conflict
+        let conflicting = values.iter().any(|(_, a)| values.iter().any(|(_, b)| !b.starts_with(a.as_str()) && !a.starts_with(b.as_str())));
+        if conflicting {
+            let listed: Vec<String> = values.iter().map(|(kind, value)| format!("{}: {:?}", kind, value)).collect();
+            warnings.push(format!("Tags disagree on {} ({})", field, listed.join(", ")));
+        }
+    }
+}
+
+/// Technical details for diagnosing files that won't play or report odd
+/// durations. Only reads the file; truncated or damaged files produce warnings
+/// rather than an error.
+#[tauri::command]
+pub fn inspect_audio_file(path: String) -> Result<AudioFileInspection, String> {
+    let file_path = Path::new(&path);
+    let mut file = File::open(file_path).map_err(|e| format!("Failed to open {}: {}", path, e))?;
+    let file_size = file.metadata().map_err(|e| e.to_string())?.len();
+    let mut warnings = Vec::new();
+
+    let mut inspection = AudioFileInspection {
+        path: path.clone(),
+        file_size,
+        container: None,
+        codec: None,
+        encoder: None,
+        bitrate_mode: None,
+        duration: None,
+        estimated_duration: None,
+        audio_bitrate: None,
+        overall_bitrate: None,
+        sample_rate: None,
+        bit_depth: None,
+        channels: None,
+        tags: Vec::new(),
+        pictures: Vec::new(),
+        id3v2: None,
+        id3v1: false,
+        ape: false,
+        mpeg: None,
+        decoder_error: None,
+        device_max_sample_rate: None,
+        warnings: Vec::new(),
+    };
+
+    let file_type = Probe::open(file_path).ok()
+        .and_then(|probe| probe.guess_file_type().ok())
+        .and_then(|probe| probe.file_type());
+    inspection.container = file_type.map(|kind| format!("{:?}", kind));
+
+    match Probe::open(file_path).and_then(|probe| probe.read()) {
+        Ok(tagged_file) => {
+            let properties = tagged_file.properties();
+            let duration = properties.duration().as_secs_f64();
+            inspection.duration = (duration > 0.0).then_some(duration);
+            inspection.audio_bitrate = properties.audio_bitrate();
+            inspection.overall_bitrate = properties.overall_bitrate();
+            inspection.sample_rate = properties.sample_rate();
+            inspection.bit_depth = properties.bit_depth().map(|b| b as u32);
+            inspection.channels = properties.channels().map(|c| c as u32);
+
+            for tag in tagged_file.tags() {
+                let tag_type = format!("{:?}", tag.tag_type());
+                inspection.tags.push(TagSummary {
+                    tag_type: tag_type.clone(),
+                    item_count: tag.item_count(),
+                    picture_count: tag.picture_count(),
+                });
+                for picture in tag.pictures() {
+                    inspection.pictures.push(PictureSummary {
+                        tag_type: tag_type.clone(),
+                        picture_type: format!("{:?}", picture.pic_type()),
+                        mime_type: picture.mime_type().map(|mime| mime.as_str().to_string()),
+                        size: picture.data().len(),
+                    });
+                }
+                inspection.encoder = inspection.encoder.take()
+                    .or_else(|| tag.get_string(&ItemKey::EncoderSoftware).map(|s| s.to_string()));
+            }
+            check_tag_conflicts(tagged_file.tags(), &mut warnings);
+        }
+        Err(e) => warnings.push(format!("Tags and properties could not be read: {}", e)),
+    }
+
+    inspection.id3v2 = read_id3v2(&mut file, &mut warnings);
+    let audio_start = inspection.id3v2.as_ref().map(|tag| tag.size).unwrap_or(0);
+    let mut audio_end = file_size;
+    if let Some(trailer) = file_size.checked_sub(128).and_then(|at| read_at(&mut file, at, 3).ok()) {
+        if trailer == b"TAG" {
+            inspection.id3v1 = true;
+            audio_end -= 128;
+        }
+    }
+    if let Some(size) = ape_tag_size(&mut file, audio_end) {
+        inspection.ape = true;
+        audio_end = audio_end.saturating_sub(size);
+    }
+
+    if file_type == Some(FileType::Mpeg) {
+        if let Some((info, estimated)) = inspect_mpeg(&mut file, audio_start, audio_end.max(audio_start), &mut warnings) {
+            inspection.bitrate_mode = Some(match info.vbr_header.as_deref() {
+                Some("Xing" | "VBRI") => BitrateMode::Variable,
+                _ => BitrateMode::Constant,
+            });
+            if let Some(lame) = &info.lame_tag {
+                inspection.encoder = Some(lame.clone());
+            }
+            inspection.estimated_duration = estimated;
+            inspection.mpeg = Some(info);
+        }
+    }
+    if let Some(file_type) = file_type {
+        inspection.codec = codec_for(file_path, file_type, inspection.mpeg.as_ref());
+    }
+
+    match open_decoder(file_path) {
+        Ok(decoder) => {
+            if inspection.estimated_duration.is_none() {
+                inspection.estimated_duration = decoder.total_duration().map(|d| d.as_secs_f64());
+            }
+        }
+        Err(e) => {
+            warnings.push(format!("The decoder can't open this file: {}", e));
+            inspection.decoder_error = Some(e);
+        }
+    }
+
+    if let (Some(rate), Some(caps)) = (inspection.sample_rate, output_caps()) {
+        if rate > caps.max_rate {
+            warnings.push(format!("{} Hz is above the output device's maximum of {} Hz", rate, caps.max_rate));
+            inspection.device_max_sample_rate = Some(caps.max_rate);
+        }
+    }
+
+    if let (Some(header), Some(estimate)) = (inspection.duration, inspection.estimated_duration) {
+        if (header - estimate).abs() > header.max(estimate) * DURATION_MISMATCH {
+            warnings.push(format!("Header duration {:.1}s differs from the estimate of {:.1}s", header, estimate));
+        }
+    }
+
+    inspection.warnings = warnings;
+    Ok(inspection)
+}
@@ -0,0 +1,192 @@
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::{Path, PathBuf};
+use once_cell::sync::Lazy;
+use parking_lot::Mutex;
+use log::{info, error};
+use crate::config::get_config_dir;
+use crate::library::now_secs;
+use crate::relocate::remap_track_data;
+
+// Undo history is kept for this many operations; older staged files are deleted
+const MAX_JOURNAL_ENTRIES: usize = 50;
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum JournalStep {
+    Moved { from: String, to: String },
+    // Per-track data (index entry, favorites, queue) carried from one file to another
+    Remapped { from: String, to: String },
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct JournalEntry {
+    pub id: u64,
+    pub operation: String,
+    pub description: String,
+    pub created_at: u64,
+    pub steps: Vec<JournalStep>,
+    pub undone: bool,
+    // Folder holding files set aside by the operation, deleted when the entry ages out
+    pub staging: Option<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Default)]
+struct Journal {
+    entries: Vec<JournalEntry>,
+}
+
+static JOURNAL_LOCK: Lazy<Mutex<()>> = Lazy::new(|| Mutex::new(()));
+
+fn journal_path() -> Option<PathBuf> {
+    get_config_dir().map(|dir| dir.join("journal.json"))
+}
+
+fn load_journal() -> Journal {
+    journal_path()
+        .and_then(|path| fs::read_to_string(path).ok())
+        .and_then(|contents| serde_json::from_str(&contents).ok())
+        .unwrap_or_default()
+}
+
+fn save_journal(journal: &Journal) -> Result<(), String> {
+    let path = journal_path().ok_or("Could not determine config directory")?;
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent).map_err(|e| e.to_string())?;
+    }
+    let temp_path = path.with_extension("json.tmp");
+    let json = serde_json::to_string_pretty(journal).map_err(|e| e.to_string())?;
+    fs::write(&temp_path, json).map_err(|e| e.to_string())?;
+    fs::rename(&temp_path, &path).map_err(|e| e.to_string())
+}
+
+fn rename(from: &Path, to: &Path) -> Result<(), String> {
+    if to.exists() {
+        return Err(format!("{} already exists", to.display()));
+    }
+    if let Some(parent) = to.parent() {
+        fs::create_dir_all(parent).map_err(|e| format!("Failed to create {}: {}", parent.display(), e))?;
+    }
+    fs::rename(from, to).map_err(|e| format!("Failed to move {} to {}: {}", from.display(), to.display(), e))
+}
+
+/// Reverts steps newest first. File moves are reverted before data remaps so
+/// that restored index entries are rebuilt from the files back in place.
+fn revert(steps: &[JournalStep]) -> Result<(), String> {
+    for step in steps.iter().rev() {
+        if let JournalStep::Moved { from, to } = step {
+            rename(Path::new(to), Path::new(from))?;
+        }
+    }
+    for step in steps.iter().rev() {
+        if let JournalStep::Remapped { from, to } = step {
+            remap_track_data(Path::new(to), Path::new(from));
+        }
+    }
+    Ok(())
+}
+
+/// A journaled file operation. Steps are applied as they are added; if the
+/// operation fails, `rollback` reverts everything applied so far, and `commit`
+/// stores the steps so the whole operation can be undone later.
+pub struct Transaction {
+    entry: JournalEntry,
+}
+
+impl Transaction {
+    pub fn new(operation: &str, description: impl Into<String>) -> Self {
+        let created_at = now_secs();
+        Self {
+            entry: JournalEntry {
+                // Millisecond ids stay unique without reading the journal up front
+                id: std::time::SystemTime::now()
+                    .duration_since(std::time::UNIX_EPOCH)
+                    .map(|d| d.as_millis() as u64)
+                    .unwrap_or(created_at),
+                operation: operation.to_string(),
+                description: description.into(),
+                created_at,
+                steps: Vec::new(),
+                undone: false,
+                staging: None,
+            },
+        }
+    }
+
+    pub fn id(&self) -> u64 {
+        self.entry.id
+    }
+
+    pub fn set_staging(&mut self, dir: &Path) {
+        self.entry.staging = Some(dir.to_string_lossy().to_string());
+    }
+
+    pub fn rename(&mut self, from: &Path, to: &Path) -> Result<(), String> {
+        rename(from, to)?;
+        self.entry.steps.push(JournalStep::Moved {
+            from: from.to_string_lossy().to_string(),
+            to: to.to_string_lossy().to_string(),
+        });
+        Ok(())
+    }
+
+    pub fn remap(&mut self, from: &Path, to: &Path) {
+        remap_track_data(from, to);
+        self.entry.steps.push(JournalStep::Remapped {
+            from: from.to_string_lossy().to_string(),
+            to: to.to_string_lossy().to_string(),
+        });
+    }
+
+    pub fn rollback(self) {
+        if let Err(e) = revert(&self.entry.steps) {
+            error!("Failed to roll back {}: {}", self.entry.operation, e);
+        }
+    }
+
+    pub fn commit(self) -> Result<u64, String> {
+        let _guard = JOURNAL_LOCK.lock();
+        let mut journal = load_journal();
+        let id = self.entry.id;
+        journal.entries.push(self.entry);
+
+        let excess = journal.entries.len().saturating_sub(MAX_JOURNAL_ENTRIES);
+        for expired in journal.entries.drain(..excess) {
+            if let Some(staging) = expired.staging.filter(|_| !expired.undone) {
+                if let Err(e) = fs::remove_dir_all(&staging) {
+                    error!("Failed to remove staged files in {}: {}", staging, e);
+                }
+            }
+        }
+        save_journal(&journal)?;
+        Ok(id)
+    }
+}
+
+#[tauri::command]
+pub fn get_journal() -> Vec<JournalEntry> {
+    load_journal().entries
+}
+
+#[tauri::command]
+pub fn undo_journal_entry(id: u64) -> Result<JournalEntry, String> {
+    let _guard = JOURNAL_LOCK.lock();
+    let mut journal = load_journal();
+    let entry = journal.entries.iter_mut()
+        .find(|entry| entry.id == id)
+        .ok_or_else(|| format!("No journal entry with id {}", id))?;
+    if entry.undone {
+        return Err("This operation has already been undone".to_string());
+    }
+
+    revert(&entry.steps)?;
+    entry.undone = true;
+    if let Some(staging) = &entry.staging {
+        // The staged files were moved back; only the empty folders remain
+        let _ = fs::remove_dir_all(staging);
+    }
+    let entry = entry.clone();
+    save_journal(&journal)?;
+    info!("Undid {} ({})", entry.operation, entry.description);
+    Ok(entry)
+}
@@ -1,11 +1,9 @@
 use serde::{Deserialize, Serialize};
 use std::fs;
 use std::path::PathBuf;
-use parking_lot::Mutex;
-use once_cell::sync::Lazy;
 use std::sync::Arc;
 use rodio::Sink;
-use rodio::OutputStream;
+use tauri::Manager;
 use std::time::Duration;
 
 pub mod commands;
@@ -13,6 +11,81 @@ pub mod metadata;
 pub mod config;
 pub mod transfer;
 pub mod device;
+pub mod library;
+pub mod paths;
+pub mod health;
+pub mod cache;
+pub mod decode;
+pub mod albums;
+pub mod boundaries;
+pub mod sidecar;
+pub mod throttle;
+pub mod thumbnails;
+pub mod queue;
+pub mod tasks;
+pub mod walker;
+pub mod favorites;
+pub mod relocate;
+pub mod reveal;
+pub mod sorting;
+pub mod device_diff;
+pub mod import;
+pub mod journal;
+pub mod album_replace;
+pub mod power;
+pub mod artwork;
+pub mod preview;
+pub mod volume;
+pub mod playlist;
+pub mod playlist_repair;
+pub mod folder_summary;
+pub mod trash;
+pub mod requests;
+pub mod library_watch;
+pub mod onboarding;
+pub mod inspect;
+pub mod resume;
+pub mod startup;
+pub mod text;
+pub mod sync_history;
+pub mod output_format;
+pub mod config_file;
+pub mod sync_sets;
+pub mod extensions;
+pub mod file_count;
+pub mod activity;
+pub mod gapless;
+pub mod crossfade;
+pub mod ramp;
+pub mod audio_device;
+pub mod output;
+pub mod replaygain;
+pub mod loudness;
+pub mod bpm;
+pub mod equalizer;
+pub mod channel_mix;
+pub mod stretch;
+pub mod pitch;
+pub mod ab_loop;
+pub mod session;
+pub mod silence;
+pub mod play_stats;
+pub mod scrobble;
+pub mod queue_store;
+#[cfg(target_os = "linux")]
+pub mod mpris;
+#[cfg(any(target_os = "windows", target_os = "macos"))]
+pub mod media_session;
+pub mod prefetch;
+pub mod playback;
+pub mod cue;
+pub mod lyrics;
+#[cfg(test)]
+mod test_support;
+#[cfg(desktop)]
+pub mod shortcuts;
+
+pub use playback::PlayerHandle;
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct FileItem {
@@ -20,31 +93,73 @@ pub struct FileItem {
     pub path: String,
     pub is_dir: bool,
     pub is_audio: bool,
+    #[serde(default)]
+    pub modified: Option<u64>,
+    // When the library index first saw the file; None for unindexed files
+    #[serde(default)]
+    pub added_at: Option<u64>,
+    // Matches an exclusion rule or holds a .nomedia marker
+    #[serde(default)]
+    pub excluded: bool,
+    // A playlist entry whose file isn't there
+    #[serde(default)]
+    pub missing: bool,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct AppConfig {
-    pub favorite_locations: Vec<String>,
+    #[serde(deserialize_with = "favorites::deserialize_favorites")]
+    pub favorite_locations: Vec<favorites::FavoriteEntry>,
     pub recent_locations: Vec<String>,
     pub default_location: Option<String>,
     pub max_recent_locations: usize,
+    // Folders music was actually played from, most recent first
+    #[serde(default)]
+    pub recently_played: Vec<PlayedLocation>,
+    #[serde(default = "default_max_recently_played")]
+    pub max_recently_played: usize,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct PlayedLocation {
+    pub path: String,
+    // Name of the removable device the folder was on, so the entry still reads
+    // sensibly once the device is unplugged
+    pub device_name: Option<String>,
+    pub played_at: u64,
+}
+
+fn default_max_recently_played() -> usize {
+    20
+}
+
+/// Merges duplicate favorites and recents. Returns whether anything changed.
+fn dedupe_config(config: &mut AppConfig) -> bool {
+    // Older versions compared raw strings, so the same folder could be saved several times
+    let recents_changed = paths::dedupe_locations(&mut config.recent_locations, false, |path| path.as_str());
+    let favorites_changed = paths::dedupe_locations(&mut config.favorite_locations, true, |entry| entry.path.as_str());
+    recents_changed || favorites_changed
 }
 
 pub fn load_config() -> AppConfig {
     let config_path = get_config_path();
-    match fs::read_to_string(&config_path) {
-        Ok(contents) => serde_json::from_str(&contents).unwrap_or_default(),
-        Err(_) => AppConfig::default(),
+    let mut config: AppConfig = config_file::read_json(&config_path);
+    if dedupe_config(&mut config) {
+        let _ = update_config(|_| Ok(()));
     }
+    config
 }
 
-pub fn save_config(config: &AppConfig) -> Result<(), String> {
-    let config_path = get_config_path();
-    let json = serde_json::to_string_pretty(config).map_err(|e| e.to_string())?;
-    fs::write(config_path, json).map_err(|e| e.to_string())
+/// Applies `change` to the config as currently on disk and saves it, holding
+/// the config lock throughout so other instances' changes aren't overwritten.
+pub fn update_config<T>(change: impl FnOnce(&mut AppConfig) -> Result<T, String>) -> Result<T, String> {
+    config_file::update_json(&get_config_path(), |config| {
+        dedupe_config(config);
+        change(config)
+    })
 }
 
-fn get_config_path() -> PathBuf {
+pub fn get_config_path() -> PathBuf {
     let mut path = dirs::config_dir().unwrap_or_else(|| PathBuf::from("."));
     path.push("your_app_name");
     fs::create_dir_all(&path).unwrap_or_default();
@@ -59,6 +174,8 @@ impl Default for AppConfig {
             recent_locations: Vec::new(),
             default_location: None,
             max_recent_locations: 10,
+            recently_played: Vec::new(),
+            max_recently_played: default_max_recently_played(),
         }
     }
 }
@@ -69,44 +186,231 @@ fn greet(name: &str) -> String {
     format!("Hello, {}! You've been greeted from Rust!", name)
 }
 
+/// What the player is doing, as far as the listener can hear.
+#[derive(Debug, Serialize, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum PlaybackStatus {
+    Playing,
+    Paused,
+    // Nothing loaded, or the track has run out
+    Stopped,
+}
+
 // Add this struct for minimal player state
 pub struct PlayerState {
     pub current_path: Option<String>,
     pub is_playing: bool,
-    pub stream: Option<(OutputStream, Arc<Sink>)>,
+    // Open while anything may play; shared by the sinks of every track
+    pub output: Option<output::Output>,
+    pub sink: Option<Arc<Sink>>,
     pub duration: Option<Duration>,
+    // `duration` was estimated from the file size and bitrate
+    pub duration_approximate: bool,
     pub volume: f32,
+    // Carries over from track to track, applied by the sink or, while pitch is
+    // preserved, by the time stretch
+    pub speed: f32,
+    // Position within the current track, kept by the source itself
+    pub clock: Arc<stretch::TrackClock>,
+    // Bumped on every play, stop and seek, so the auto-advance watcher can tell
+    // that what it saw has been superseded
+    pub generation: u64,
+    // The next queue track, already appended to the sink behind the current one
+    pub preloaded: Option<gapless::Preloaded>,
+    // The previous track while it crossfades out
+    pub fading: Option<Arc<Sink>>,
+    // Tracks played before the current one, most recent first
+    pub history: Vec<String>,
+    // Silences output without touching `volume`; not saved between sessions
+    pub muted: bool,
+    // Name of the device the output was opened on
+    pub output_device: Option<String>,
+    // Linear ReplayGain adjustment for the current track, 1.0 when off or untagged
+    pub replay_gain: f32,
+    // Section of the current track being repeated
+    pub loop_region: Option<ab_loop::LoopRegion>,
+    // Stop when the current track finishes instead of moving on through the queue
+    pub stop_after_current: bool,
+    // Set while playing one track of a CUE sheet out of its file
+    pub cue_track: Option<cue::CueRegion>,
+    // Where the current track's trailing silence starts, while skipping silence
+    pub trim_end: Option<Duration>,
+    // Work the playback thread carries on with between commands, such as fades
+    repeating: Vec<playback::Repeating>,
 }
 
-// Implement Send and Sync explicitly
-unsafe impl Send for PlayerState {}
-unsafe impl Sync for PlayerState {}
-
-pub static PLAYER: Lazy<Mutex<PlayerState>> = Lazy::new(|| {
-    Mutex::new(PlayerState {
-        current_path: None,
-        is_playing: false,
-        stream: None,
-        duration: None,
-        volume: 1.0,
-    })
-});
+const MAX_PLAY_HISTORY: usize = 100;
+
+impl PlayerState {
+    /// A stopped player with the saved volume and speed.
+    pub(crate) fn new() -> Self {
+        let settings = config::load_player_config().playback_settings;
+        Self {
+            current_path: None,
+            is_playing: false,
+            output: None,
+            sink: None,
+            duration: None,
+            duration_approximate: false,
+            volume: volume::persisted_gain(&settings),
+            speed: settings.effective_playback_speed(),
+            clock: Arc::default(),
+            generation: 0,
+            preloaded: None,
+            fading: None,
+            history: Vec::new(),
+            muted: false,
+            output_device: None,
+            replay_gain: 1.0,
+            loop_region: None,
+            stop_after_current: false,
+            cue_track: None,
+            trim_end: None,
+            repeating: Vec::new(),
+        }
+    }
+
+    /// Runs `step` on the playback thread every `interval` until it returns false.
+    pub fn every(&mut self, interval: Duration, step: impl FnMut(&mut PlayerState) -> bool + 'static) {
+        self.repeating.push(playback::Repeating::new(interval, step));
+    }
+
+    /// How far into the current track playback is.
+    pub fn position(&self) -> Duration {
+        match (&self.sink, &self.current_path) {
+            (Some(_), Some(_)) => self.clock.get(),
+            _ => Duration::ZERO,
+        }
+    }
+
+    /// Playing or paused while the sink still has the track to play; stopped
+    /// once it has run dry, whatever `is_playing` says.
+    pub fn status(&self) -> PlaybackStatus {
+        match &self.sink {
+            Some(sink) if self.current_path.is_some() && !sink.empty() => {
+                if self.is_playing { PlaybackStatus::Playing } else { PlaybackStatus::Paused }
+            }
+            _ => PlaybackStatus::Stopped,
+        }
+    }
+
+    /// Where the current track starts in its file: zero, except for a CUE track.
+    pub fn track_offset(&self) -> Duration {
+        self.cue_track.as_ref().map_or(Duration::ZERO, |cue| cue.start())
+    }
+
+    /// Position within the current track, which for a CUE track counts from its start.
+    pub fn track_position(&self) -> Duration {
+        self.position().saturating_sub(self.track_offset())
+    }
+
+    /// Length of the current track, which for a CUE track is its region of the file.
+    pub fn track_duration(&self) -> Option<Duration> {
+        match &self.cue_track {
+            Some(cue) => cue.end().or(self.duration).map(|end| end.saturating_sub(cue.start())),
+            None => self.duration,
+        }
+    }
+
+    /// Whether the current track has reached its trailing silence, which counts as its end.
+    pub fn past_trim_end(&self) -> bool {
+        self.trim_end.is_some_and(|end| self.position() >= end)
+    }
+
+    /// Speed for the sink itself; while pitch is preserved the source does the stretching.
+    pub fn sink_speed(&self) -> f32 {
+        if stretch::preserves_pitch() { 1.0 } else { self.speed }
+    }
+
+    /// Gain for the main sink: the volume with the track's ReplayGain applied,
+    /// lowered while a preview plays and silent while muted.
+    pub fn output_gain(&self) -> f32 {
+        if self.muted {
+            return 0.0;
+        }
+        self.volume * self.replay_gain * preview::main_volume_factor()
+    }
+
+    /// Puts `sink` in place of the current sink, which is stopped at once
+    /// rather than whenever the last copy of it is dropped.
+    pub fn replace_sink(&mut self, sink: Option<Arc<Sink>>) {
+        if let Some(old) = std::mem::replace(&mut self.sink, sink) {
+            old.stop();
+        }
+    }
+
+    /// Moves the current track onto the history as another one replaces it.
+    pub fn leave_current(&mut self) {
+        let Some(path) = self.current_path.take() else { return };
+        if self.history.first() != Some(&path) {
+            self.history.insert(0, path);
+            self.history.truncate(MAX_PLAY_HISTORY);
+        }
+    }
+}
 
 #[cfg_attr(mobile, tauri::mobile_entry_point)]
 pub fn run() {
     tauri::Builder::default()
         .plugin(tauri_plugin_opener::init())
+        .manage(PlayerHandle::new())
+        .setup(|app| {
+            startup::announce_startup_location(app.handle().clone());
+            queue::start_auto_advance(app.handle().clone());
+            let mut config_files = vec![("locations", get_config_path())];
+            config_files.extend(config::get_config_file_path().map(|path| ("player", path)));
+            config_file::watch_config_files(app.handle().clone(), config_files);
+            library::start_startup_scan(app.handle().clone());
+            config::start_view_override_pruning();
+            import::start_watch_folders(app.handle().clone());
+            library_watch::start_library_watchers(app.handle().clone());
+            power::register_resume_hook("watch_folders", |app| {
+                import::start_watch_folders(app.clone());
+                Ok(())
+            });
+            power::register_resume_hook("library_roots", |app| {
+                library_watch::start_library_watchers(app.clone());
+                Ok(())
+            });
+            power::register_resume_hook("devices", device::restart_device_watcher);
+            power::start_sleep_detector(app.handle().clone());
+            audio_device::start_device_watch(app.handle().clone());
+            let player = app.state::<PlayerHandle>().inner().clone();
+            session::start_session_saver(player.clone());
+            queue_store::start_queue_saver();
+            output::start_idle_release(&player);
+            #[cfg(target_os = "linux")]
+            mpris::start(app.handle().clone());
+            #[cfg(any(target_os = "windows", target_os = "macos"))]
+            media_session::start(app.handle().clone());
+            cache::request_eviction();
+
+            #[cfg(desktop)]
+            {
+                app.handle().plugin(tauri_plugin_global_shortcut::Builder::new().build())?;
+                shortcuts::apply_shortcuts(app.handle(), &config::load_player_config().shortcuts);
+            }
+
+            Ok(())
+        })
         .invoke_handler(tauri::generate_handler![
             commands::read_dir,
             commands::home_dir,
             commands::add_favorite_location,
             commands::remove_favorite_location,
             commands::get_favorite_locations,
+            favorites::get_favorite_tracks_metadata,
+            favorites::play_favorite_track,
+            favorites::set_favorite_label,
             commands::set_default_location,
             commands::get_default_location,
             commands::add_recent_location,
             commands::get_recent_locations,
+            commands::get_recently_played_locations,
             commands::play_audio,
+            commands::play_audio_from,
+            cue::get_cue_sheet,
+            cue::play_cue_track,
             commands::pause_audio,
             commands::resume_audio,
             commands::stop_audio,
@@ -114,8 +418,17 @@ pub fn run() {
             commands::get_track_position,
             commands::get_track_duration,
             commands::get_playback_speed,
+            pitch::set_pitch_semitones,
+            pitch::get_pitch_semitones,
             commands::set_playback_speed,
+            commands::get_preserve_pitch,
+            ab_loop::set_loop_region,
+            ab_loop::clear_loop_region,
             commands::skip_track,
+            commands::previous_track,
+            commands::toggle_mute,
+            commands::is_muted,
+            commands::get_play_history,
             commands::clear_queue,
             commands::is_queue_empty,
             commands::queue_length,
@@ -125,22 +438,139 @@ pub fn run() {
             metadata::combine_folders,
             metadata::get_artists_in_directory,
             metadata::get_album_art,
+            metadata::set_album_art,
+            metadata::get_pictures,
+            metadata::remove_picture,
+            metadata::remove_all_pictures,
+            metadata::get_lyrics,
+            metadata::set_rating,
+            lyrics::get_synced_lyrics,
+            lyrics::write_lrc,
             commands::get_app_config,
             commands::update_app_config,
+            commands::get_view_settings_for,
+            commands::set_view_settings_for,
             metadata::get_metadata_for_directory,
             commands::get_recursive_audio_files,
             commands::move_file,
             commands::combine_files,
             commands::change_file_folder_name,
             commands::restore_file_extension,
+            extensions::audit_extensions,
+            extensions::fix_extensions,
+            file_count::count_audio_files,
+            activity::get_activity_log,
+            activity::clear_activity_log,
             device::get_connected_devices,
             device::watch_devices,
             device::read_device_dir,
             transfer::verify_transfer,
             transfer::calculate_directory_checksum,
             transfer::transfer_files,
+            device_diff::compare_with_device,
+            album_replace::replace_album,
+            journal::get_journal,
+            journal::undo_journal_entry,
+            artwork::extract_album_art,
+            artwork::extract_album_art_for_folder,
+            preview::play_preview,
+            preview::stop_preview,
+            volume::set_volume_db,
+            volume::get_volume_db,
+            playlist_repair::repair_playlist,
+            folder_summary::get_folder_summary,
+            trash::stage_for_removal,
+            trash::list_staged_removals,
+            trash::restore_staged,
+            trash::purge_staged,
+            requests::cancel_request,
+            onboarding::get_onboarding_state,
+            onboarding::complete_onboarding,
+            inspect::inspect_audio_file,
+            commands::batch_operation,
+            resume::get_bookmark,
+            resume::set_bookmark,
+            resume::clear_bookmark,
+            startup::get_startup_location,
+            sync_history::get_sync_status,
+            sync_history::forget_device_sync_history,
+            sync_sets::get_device_id,
+            sync_sets::get_sync_set,
+            sync_sets::add_to_sync_set,
+            sync_sets::remove_from_sync_set,
+            sync_sets::set_device_sync_options,
+            sync_sets::run_sync_set,
+            library::scan_library,
+            library::get_scan_status,
+            reveal::reveal_in_file_manager,
+            health::run_health_check,
+            cache::get_cache_stats,
+            cache::clear_cache,
+            albums::get_albums,
+            boundaries::analyze_track_boundaries,
+            sidecar::read_album_sidecar,
+            sidecar::write_album_sidecar,
+            thumbnails::pregenerate_thumbnails,
+            thumbnails::get_album_art_thumbnail,
+            thumbnails::clear_art_cache,
+            thumbnails::pause_thumbnail_pregen,
+            thumbnails::resume_thumbnail_pregen,
+            thumbnails::cancel_thumbnail_pregen,
+            queue::play_artist,
+            queue::play_album,
+            queue::set_shuffle_mode,
+            commands::enqueue_directory,
+            queue::play_next,
+            queue::play_next_tracks,
+            queue::set_repeat_mode,
+            queue::set_stop_after_current,
+            session::restore_session,
+            queue::set_shuffle,
+            queue::get_shuffle,
+            queue::get_shuffle_mode,
+            queue::get_repeat_mode,
+            crossfade::set_crossfade,
+            silence::set_skip_silence,
+            play_stats::get_track_stats,
+            play_stats::get_most_played,
+            play_stats::get_recently_played,
+            scrobble::get_pending_scrobbles,
+            scrobble::export_scrobble_log,
+            queue_store::load_saved_queue,
+            replaygain::set_replaygain_mode,
+            loudness::analyze_loudness,
+            bpm::analyze_bpm,
+            equalizer::get_equalizer,
+            equalizer::get_equalizer_presets,
+            equalizer::set_equalizer,
+            equalizer::set_equalizer_enabled,
+            channel_mix::get_channel_mix,
+            channel_mix::set_balance,
+            channel_mix::set_mono,
+            queue::enqueue_track,
+            queue::enqueue_tracks,
+            queue::dedupe_queue,
+            queue::remove_from_queue,
+            queue::move_queue_item,
+            queue::get_queue,
+            queue::save_queue_as_playlist,
+            queue::enqueue_playlist,
+            playlist::read_playlist,
+            commands::get_player_state,
+            commands::get_now_playing,
+            tasks::get_background_tasks,
+            tasks::cancel_background_task,
+            #[cfg(desktop)]
+            shortcuts::set_global_shortcuts_suspended,
         ])
-        .run(tauri::generate_context!())
-        .expect("error while running tauri application");
+        .build(tauri::generate_context!())
+        .expect("error while building tauri application")
+        .run(|app, event| {
+            if let tauri::RunEvent::Exit = event {
+                session::save_session(&app.state());
+                #[cfg(target_os = "linux")]
+                mpris::stop();
+            }
+        });
 }
 
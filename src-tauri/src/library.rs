@@ -0,0 +1,593 @@
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use once_cell::sync::Lazy;
+use parking_lot::Mutex;
+use tauri::{AppHandle, Emitter};
+use log::{info, error, debug};
+use crate::config::{get_config_dir, load_player_config};
+use crate::import::is_import_pending;
+use crate::metadata::read_audio_metadata;
+use crate::sync_history::{DeviceSync, SyncDevice};
+use crate::tasks::{start_task, TaskHandle, TaskKind};
+use crate::throttle;
+use crate::walker::{Exclusions, SkippedPath};
+
+pub const AUDIO_EXTENSIONS: [&str; 7] = ["mp3", "flac", "m4a", "wav", "ogg", "aac", "aiff"];
+
+// Number of changed tracks buffered before the index is flushed to disk
+const WRITE_BATCH_SIZE: usize = 500;
+// Pause between directories so a startup scan never competes with the UI or playback
+const DIRECTORY_PAUSE: Duration = Duration::from_millis(2);
+const PROGRESS_INTERVAL: Duration = Duration::from_millis(250);
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct LibraryTrack {
+    pub path: String,
+    pub size: u64,
+    pub modified: u64,
+    pub added_at: u64,
+    pub title: Option<String>,
+    pub artist: Option<String>,
+    pub album: Option<String>,
+    pub album_artist: Option<String>,
+    pub year: Option<u32>,
+    pub track_number: Option<u32>,
+    pub disc_number: Option<u32>,
+    pub bpm: Option<f32>,
+    pub rating: Option<u8>,
+    pub genre: Option<String>,
+    pub duration: Option<f64>,
+    // Moved to a library trash folder pending removal; the entry is kept so a restore loses nothing
+    #[serde(default)]
+    pub staged: bool,
+}
+
+#[derive(Debug, Serialize, Deserialize, Default)]
+pub struct LibraryIndex {
+    pub tracks: HashMap<String, LibraryTrack>,
+    pub last_full_scan: Option<u64>,
+    // Verified copies on removable devices, keyed by local track path
+    #[serde(default)]
+    pub synced: HashMap<String, Vec<DeviceSync>>,
+    #[serde(default)]
+    pub devices: HashMap<String, SyncDevice>,
+}
+
+#[derive(Debug, Serialize, Clone, Default)]
+pub struct ScanStatus {
+    pub running: bool,
+    pub current_root: Option<String>,
+    pub queued_roots: Vec<String>,
+    // Task for the batch being scanned, for progress through the task list
+    pub task_id: Option<u64>,
+    pub processed_dirs: usize,
+    pub total_dirs: usize,
+    pub processed_files: usize,
+    pub last_full_scan: Option<u64>,
+}
+
+#[derive(Debug, Serialize, Clone)]
+pub struct ScanProgress {
+    pub root: String,
+    pub current_dir: String,
+    pub processed_dirs: usize,
+    pub total_dirs: usize,
+    pub processed_files: usize,
+}
+
+#[derive(Debug, Serialize, Clone, Default)]
+pub struct ScanReport {
+    pub roots: Vec<String>,
+    pub added: usize,
+    pub updated: usize,
+    pub removed: usize,
+    pub missing: usize,
+    pub duration_ms: u64,
+    // Folders that couldn't be read; their tracks are kept as they were
+    pub skipped: Vec<SkippedPath>,
+}
+
+pub static LIBRARY: Lazy<Mutex<LibraryIndex>> = Lazy::new(|| Mutex::new(load_index()));
+
+static SCAN_STATUS: Lazy<Mutex<ScanStatus>> = Lazy::new(|| {
+    Mutex::new(ScanStatus {
+        last_full_scan: LIBRARY.lock().last_full_scan,
+        ..ScanStatus::default()
+    })
+});
+
+static PENDING_ROOTS: Lazy<Mutex<VecDeque<String>>> = Lazy::new(|| Mutex::new(VecDeque::new()));
+
+pub fn is_audio_file(path: &Path) -> bool {
+    path.extension()
+        .and_then(|ext| ext.to_str())
+        .map(|ext| AUDIO_EXTENSIONS.contains(&ext.to_lowercase().as_str()))
+        .unwrap_or(false)
+}
+
+pub fn now_secs() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0)
+}
+
+pub fn modified_secs(metadata: &fs::Metadata) -> u64 {
+    metadata.modified()
+        .ok()
+        .and_then(|time| time.duration_since(UNIX_EPOCH).ok())
+        .map(|d| d.as_secs())
+        .unwrap_or(0)
+}
+
+pub fn index_exists() -> bool {
+    index_path().is_some_and(|path| path.is_file())
+}
+
+fn index_path() -> Option<PathBuf> {
+    get_config_dir().map(|dir| dir.join("library.json"))
+}
+
+fn load_index() -> LibraryIndex {
+    match index_path().and_then(|path| fs::read_to_string(path).ok()) {
+        Some(contents) => serde_json::from_str(&contents).unwrap_or_default(),
+        None => LibraryIndex::default(),
+    }
+}
+
+pub fn save_index(index: &LibraryIndex) -> Result<(), String> {
+    let path = index_path().ok_or("Could not determine config directory")?;
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent).map_err(|e| e.to_string())?;
+    }
+
+    // Write to a temporary file first so a crash mid-write never truncates the index
+    let temp_path = path.with_extension("json.tmp");
+    let json = serde_json::to_string(index).map_err(|e| e.to_string())?;
+    fs::write(&temp_path, json).map_err(|e| e.to_string())?;
+    fs::rename(&temp_path, &path).map_err(|e| e.to_string())
+}
+
+fn build_track(path: &Path, size: u64, modified: u64, added_at: u64) -> LibraryTrack {
+    let metadata = read_audio_metadata(path, false).ok();
+
+    LibraryTrack {
+        path: path.to_string_lossy().to_string(),
+        size,
+        modified,
+        added_at,
+        title: metadata.as_ref().and_then(|m| m.title.clone()),
+        artist: metadata.as_ref().and_then(|m| m.artist.clone()),
+        album: metadata.as_ref().and_then(|m| m.album.clone()),
+        album_artist: metadata.as_ref().and_then(|m| m.album_artist.clone()),
+        year: metadata.as_ref().and_then(|m| m.year),
+        track_number: metadata.as_ref().and_then(|m| m.track_number),
+        disc_number: metadata.as_ref().and_then(|m| m.disc_number),
+        bpm: metadata.as_ref().and_then(|m| m.bpm),
+        rating: metadata.as_ref().and_then(|m| m.rating),
+        genre: metadata.as_ref().and_then(|m| m.genre.clone()),
+        duration: metadata.as_ref().and_then(|m| m.duration),
+        staged: false,
+    }
+}
+
+/// When `path` was first indexed, if the library has it. Listings take this as
+/// a parameter, so tests can give them their own dates instead.
+pub fn added_at(path: &str) -> Option<u64> {
+    LIBRARY.lock().tracks.get(path).map(|track| track.added_at)
+}
+
+/// Added-at timestamps from the index, one per path, under a single lock.
+pub fn added_times<'a>(paths: impl Iterator<Item = &'a str>) -> Vec<Option<u64>> {
+    let index = LIBRARY.lock();
+    paths.map(|path| index.tracks.get(path).map(|track| track.added_at)).collect()
+}
+
+/// Returns index data for `path` when the indexed copy is still current, and
+/// otherwise probes the file directly. Probed results are not stored, since the
+/// file may live outside any library root.
+pub fn track_info(path: &Path) -> Option<LibraryTrack> {
+    let metadata = fs::metadata(path).ok()?;
+    let size = metadata.len();
+    let modified = modified_secs(&metadata);
+
+    let path_str = path.to_string_lossy().to_string();
+    if let Some(track) = LIBRARY.lock().tracks.get(&path_str) {
+        if track.size == size && track.modified == modified {
+            return Some(track.clone());
+        }
+    }
+
+    Some(build_track(path, size, modified, now_secs()))
+}
+
+/// Indexes a single file right away, e.g. after an import placed it in a library root.
+pub fn index_file(path: &Path) -> Result<LibraryTrack, String> {
+    let metadata = fs::metadata(path).map_err(|e| e.to_string())?;
+    let track = build_track(path, metadata.len(), modified_secs(&metadata), now_secs());
+    flush_tracks(&mut vec![track.clone()]);
+    Ok(track)
+}
+
+/// The innermost configured library root containing `path`.
+pub fn library_root_of(path: &Path) -> Option<PathBuf> {
+    load_player_config().library_roots.into_iter()
+        .map(|root| PathBuf::from(root.path))
+        .filter(|root| path.starts_with(root))
+        .max_by_key(|root| root.as_os_str().len())
+}
+
+/// Flags index entries as staged for removal (or clears the flag after a restore).
+pub fn set_staged(paths: &[String], staged: bool) {
+    let mut index = LIBRARY.lock();
+    for path in paths {
+        if let Some(track) = index.tracks.get_mut(path) {
+            track.staged = staged;
+        }
+    }
+    if let Err(e) = save_index(&index) {
+        error!("Failed to save library index: {}", e);
+    }
+}
+
+pub fn remove_tracks(paths: &[String]) {
+    let mut index = LIBRARY.lock();
+    for path in paths {
+        index.tracks.remove(path);
+        index.synced.remove(path);
+    }
+    if let Err(e) = save_index(&index) {
+        error!("Failed to save library index: {}", e);
+    }
+}
+
+/// Moves the index entry for `from` to `to`, keeping its added-at time and sync history.
+/// Tags are re-read from `to` when it exists.
+pub fn remap_track(from: &Path, to: &Path) {
+    let from_str = from.to_string_lossy().to_string();
+    let old = {
+        let mut index = LIBRARY.lock();
+        if let Some(synced) = index.synced.remove(&from_str) {
+            index.synced.insert(to.to_string_lossy().to_string(), synced);
+        }
+        index.tracks.remove(&from_str)
+    };
+    let Some(mut old) = old else { return };
+
+    let track = match fs::metadata(to) {
+        Ok(metadata) => build_track(to, metadata.len(), modified_secs(&metadata), old.added_at),
+        Err(_) => {
+            old.path = to.to_string_lossy().to_string();
+            old
+        }
+    };
+    flush_tracks(&mut vec![track]);
+}
+
+fn flush_tracks(pending: &mut Vec<LibraryTrack>) {
+    if pending.is_empty() {
+        return;
+    }
+
+    let mut index = LIBRARY.lock();
+    for track in pending.drain(..) {
+        index.tracks.insert(track.path.clone(), track);
+    }
+    if let Err(e) = save_index(&index) {
+        error!("Failed to save library index: {}", e);
+    }
+}
+
+/// Collects every directory under `root` together with its modification time,
+/// most recently modified first, so new downloads are indexed before old archives.
+fn collect_directories(root: &Path, exclusions: &Exclusions, skipped: &mut Vec<SkippedPath>) -> Vec<PathBuf> {
+    let mut directories = Vec::new();
+    let mut to_visit = VecDeque::new();
+    if !exclusions.is_excluded_dir(root) {
+        to_visit.push_back(root.to_path_buf());
+    }
+
+    while let Some(dir) = to_visit.pop_front() {
+        let modified = fs::metadata(&dir).map(|m| modified_secs(&m)).unwrap_or(0);
+        match fs::read_dir(&dir) {
+            Ok(entries) => {
+                for entry in entries.flatten() {
+                    if entry.file_type().map(|t| t.is_dir()).unwrap_or(false) && !exclusions.is_excluded_dir(&entry.path()) {
+                        to_visit.push_back(entry.path());
+                    }
+                }
+                directories.push((dir, modified));
+            }
+            Err(e) => {
+                debug!("Skipping unreadable directory {}: {}", dir.display(), e);
+                skipped.push(SkippedPath::new(&dir, &e));
+            }
+        }
+    }
+
+    directories.sort_by_key(|(_, modified)| std::cmp::Reverse(*modified));
+    directories.into_iter().map(|(dir, _)| dir).collect()
+}
+
+fn scan_root(app: &AppHandle, task: &TaskHandle, root: &str, report: &mut ScanReport) {
+    let root_path = Path::new(root);
+
+    // An unreachable root (unmounted NAS, unplugged drive) keeps its tracks but reports them missing
+    if !root_path.is_dir() {
+        let index = LIBRARY.lock();
+        report.missing += index.tracks.keys()
+            .filter(|path| Path::new(path).starts_with(root_path))
+            .count();
+        info!("Library root {} is unreachable, skipping scan", root);
+        return;
+    }
+
+    let exclusions = Exclusions::load();
+    let mut skipped = Vec::new();
+    let directories = collect_directories(root_path, &exclusions, &mut skipped);
+    {
+        let mut status = SCAN_STATUS.lock();
+        status.current_root = Some(root.to_string());
+        status.total_dirs = directories.len();
+        status.processed_dirs = 0;
+        status.processed_files = 0;
+    }
+
+    let mut seen = HashSet::new();
+    let mut pending = Vec::new();
+    let mut last_progress: Option<Instant> = None;
+
+    task.set_message(root);
+
+    for (dir_index, dir) in directories.iter().enumerate() {
+        if task.is_cancelled() {
+            info!("Library scan of {} cancelled", root);
+            break;
+        }
+        let _permit = throttle::background_io();
+        let entries = match fs::read_dir(dir) {
+            Ok(entries) => entries,
+            Err(e) => {
+                debug!("Skipping unreadable directory {}: {}", dir.display(), e);
+                skipped.push(SkippedPath::new(dir, &e));
+                continue;
+            }
+        };
+
+        for entry in entries.flatten() {
+            let path = entry.path();
+            // Files a watch folder is still waiting on are indexed by the importer once they land
+            if !is_audio_file(&path) || exclusions.is_excluded_file(&path) || is_import_pending(&path) {
+                continue;
+            }
+            let metadata = match entry.metadata() {
+                Ok(metadata) if metadata.is_file() => metadata,
+                _ => continue,
+            };
+
+            let path_str = path.to_string_lossy().to_string();
+            let size = metadata.len();
+            let modified = modified_secs(&metadata);
+            seen.insert(path_str.clone());
+
+            let existing = LIBRARY.lock().tracks.get(&path_str)
+                .map(|track| (track.size, track.modified, track.added_at));
+
+            match existing {
+                Some((old_size, old_modified, _)) if old_size == size && old_modified == modified => {}
+                Some((_, _, added_at)) => {
+                    pending.push(build_track(&path, size, modified, added_at));
+                    report.updated += 1;
+                }
+                None => {
+                    pending.push(build_track(&path, size, modified, now_secs()));
+                    report.added += 1;
+                }
+            }
+
+            if pending.len() >= WRITE_BATCH_SIZE {
+                flush_tracks(&mut pending);
+            }
+            std::thread::yield_now();
+        }
+
+        let processed_files = seen.len();
+        task.set_progress(dir_index as u64 + 1, directories.len() as u64);
+        {
+            let mut status = SCAN_STATUS.lock();
+            status.processed_dirs = dir_index + 1;
+            status.processed_files = processed_files;
+        }
+
+        let due = last_progress.is_none_or(|at| at.elapsed() >= PROGRESS_INTERVAL);
+        if due || dir_index + 1 == directories.len() {
+            last_progress = Some(Instant::now());
+            app.emit("library-scan-progress", ScanProgress {
+                root: root.to_string(),
+                current_dir: dir.to_string_lossy().to_string(),
+                processed_dirs: dir_index + 1,
+                total_dirs: directories.len(),
+                processed_files,
+            }).ok();
+        }
+
+        std::thread::sleep(DIRECTORY_PAUSE);
+    }
+
+    flush_tracks(&mut pending);
+
+    // Drop tracks whose files are gone or now excluded; anything else not visited
+    // (e.g. inside a folder that became unreadable) and staged removals are left alone.
+    // Files in an unreadable folder look missing too, so those are never pruned.
+    let unreadable: Vec<PathBuf> = skipped.iter().map(|s| PathBuf::from(&s.path)).collect();
+    let mut index = LIBRARY.lock();
+    let stale: Vec<String> = index.tracks.values()
+        .filter(|track| !track.staged)
+        .map(|track| &track.path)
+        .filter(|path| Path::new(path).starts_with(root_path) && !seen.contains(*path))
+        .filter(|path| !unreadable.iter().any(|dir| Path::new(path).starts_with(dir)))
+        .filter(|path| !Path::new(path).exists() || exclusions.is_path_excluded(Path::new(path)))
+        .cloned()
+        .collect();
+    report.removed += stale.len();
+    for path in stale {
+        index.tracks.remove(&path);
+    }
+    if !skipped.is_empty() {
+        info!("Library scan of {} skipped {} unreadable folders", root, skipped.len());
+    }
+    report.skipped.extend(skipped);
+    if let Err(e) = save_index(&index) {
+        error!("Failed to save library index: {}", e);
+    }
+}
+
+/// Scans queued roots in batches until none are left. `first_task` is the task
+/// already announced for the first batch, so its id could be returned up front.
+fn run_scan_worker(app: AppHandle, mut first_task: Option<TaskHandle>) {
+    loop {
+        // Drain everything queued so far into a single batch
+        let roots: Vec<String> = {
+            let mut pending = PENDING_ROOTS.lock();
+            let mut status = SCAN_STATUS.lock();
+            if pending.is_empty() {
+                status.running = false;
+                status.current_root = None;
+                status.task_id = None;
+                status.queued_roots.clear();
+                if let Some(task) = first_task.take() {
+                    task.finish(Ok(()));
+                }
+                return;
+            }
+            status.queued_roots.clear();
+            pending.drain(..).collect()
+        };
+
+        info!("Starting library scan of {:?}", roots);
+        let started = Instant::now();
+        let mut report = ScanReport {
+            roots: roots.clone(),
+            ..ScanReport::default()
+        };
+
+        let task = first_task.take()
+            .unwrap_or_else(|| start_task(&app, TaskKind::LibraryScan, format!("Scanning {}", roots.join(", "))));
+        SCAN_STATUS.lock().task_id = Some(task.id());
+        for root in &roots {
+            if task.is_cancelled() {
+                break;
+            }
+            scan_root(&app, &task, root, &mut report);
+        }
+        report.duration_ms = started.elapsed().as_millis() as u64;
+        let cancelled = task.is_cancelled();
+        task.set_count("added", report.added as u64);
+        task.set_count("updated", report.updated as u64);
+        task.set_count("removed", report.removed as u64);
+        task.set_count("skipped_folders", report.skipped.len() as u64);
+        task.finish(Ok(()));
+
+        let configured: Vec<String> = load_player_config().library_roots
+            .into_iter()
+            .map(|root| root.path)
+            .collect();
+        if !cancelled && !configured.is_empty() && configured.iter().all(|root| roots.contains(root)) {
+            let completed = now_secs();
+            {
+                let mut index = LIBRARY.lock();
+                index.last_full_scan = Some(completed);
+                if let Err(e) = save_index(&index) {
+                    error!("Failed to save library index: {}", e);
+                }
+            }
+            SCAN_STATUS.lock().last_full_scan = Some(completed);
+        }
+
+        info!("Library scan finished: {:?}", report);
+        app.emit("library-scan-complete", report).ok();
+    }
+}
+
+/// Removes indexed tracks that the current exclusion rules hide, without a full rescan.
+pub fn prune_excluded_tracks() {
+    let exclusions = Exclusions::load();
+    let paths: Vec<String> = LIBRARY.lock().tracks.keys().cloned().collect();
+    let excluded: Vec<String> = paths.into_iter()
+        .filter(|path| exclusions.is_path_excluded(Path::new(path)))
+        .collect();
+    if excluded.is_empty() {
+        return;
+    }
+
+    let mut index = LIBRARY.lock();
+    for path in &excluded {
+        index.tracks.remove(path);
+    }
+    if let Err(e) = save_index(&index) {
+        error!("Failed to save library index: {}", e);
+    }
+    info!("Removed {} newly excluded tracks from the library", excluded.len());
+}
+
+/// Queues `roots` for scanning. If a scan is already running the roots are picked
+/// up once it finishes instead of starting a second scan alongside it. Returns
+/// the id of the scan task that is running, or about to run.
+pub fn request_scan(app: AppHandle, roots: Vec<String>) -> Option<u64> {
+    let mut pending = PENDING_ROOTS.lock();
+    for root in roots {
+        if !pending.contains(&root) {
+            pending.push_back(root);
+        }
+    }
+
+    let mut status = SCAN_STATUS.lock();
+    status.queued_roots = pending.iter().cloned().collect();
+    if status.running {
+        return status.task_id;
+    }
+    let task = start_task(&app, TaskKind::LibraryScan, format!("Scanning {}", status.queued_roots.join(", ")));
+    status.running = true;
+    status.task_id = Some(task.id());
+    drop(status);
+    drop(pending);
+
+    let task_id = task.id();
+    std::thread::spawn(move || run_scan_worker(app, Some(task)));
+    Some(task_id)
+}
+
+pub fn start_startup_scan(app: AppHandle) {
+    let roots: Vec<String> = load_player_config().library_roots
+        .into_iter()
+        .filter(|root| root.scan_on_startup)
+        .map(|root| root.path)
+        .collect();
+
+    if !roots.is_empty() {
+        request_scan(app, roots);
+    }
+}
+
+#[tauri::command]
+pub fn scan_library(app: AppHandle, roots: Option<Vec<String>>) -> Result<ScanStatus, String> {
+    let roots = match roots {
+        Some(roots) => roots,
+        None => load_player_config().library_roots.into_iter().map(|root| root.path).collect(),
+    };
+
+    if roots.is_empty() {
+        return Err("No library roots configured".to_string());
+    }
+
+    request_scan(app, roots);
+    Ok(get_scan_status())
+}
+
+#[tauri::command]
+pub fn get_scan_status() -> ScanStatus {
+    SCAN_STATUS.lock().clone()
+}
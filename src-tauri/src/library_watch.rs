@@ -0,0 +1,88 @@
+use std::collections::HashSet;
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{channel, RecvTimeoutError, Sender};
+use std::time::{Duration, Instant};
+use notify::{Event, RecommendedWatcher, RecursiveMode, Watcher};
+use once_cell::sync::Lazy;
+use parking_lot::Mutex;
+use tauri::AppHandle;
+use log::{info, error};
+use crate::config::load_player_config;
+use crate::library::request_scan;
+
+// Changes are batched until the root has been quiet this long, so a large copy triggers one rescan
+const QUIET_PERIOD: Duration = Duration::from_secs(5);
+const POLL_INTERVAL: Duration = Duration::from_secs(1);
+
+static WATCHER: Lazy<Mutex<Option<RecommendedWatcher>>> = Lazy::new(|| Mutex::new(None));
+static EVENTS: Lazy<Mutex<Option<Sender<PathBuf>>>> = Lazy::new(|| Mutex::new(None));
+
+fn spawn_rescan_worker(app: AppHandle) -> Sender<PathBuf> {
+    let (tx, rx) = channel::<PathBuf>();
+    std::thread::spawn(move || {
+        let mut changed: HashSet<String> = HashSet::new();
+        let mut last_change = Instant::now();
+        loop {
+            match rx.recv_timeout(POLL_INTERVAL) {
+                Ok(path) => {
+                    let roots = load_player_config().library_roots;
+                    // Nested roots: the innermost one covers the change
+                    if let Some(root) = roots.iter()
+                        .filter(|root| root.watch && path.starts_with(&root.path))
+                        .max_by_key(|root| root.path.len())
+                    {
+                        changed.insert(root.path.clone());
+                        last_change = Instant::now();
+                    }
+                }
+                Err(RecvTimeoutError::Timeout) => {}
+                Err(RecvTimeoutError::Disconnected) => break,
+            }
+            if !changed.is_empty() && last_change.elapsed() >= QUIET_PERIOD {
+                request_scan(app.clone(), changed.drain().collect());
+            }
+        }
+    });
+    tx
+}
+
+/// Watches library roots that have `watch` set and queues an incremental scan
+/// of a root once changes under it settle. Call again after the roots change.
+pub fn start_library_watchers(app: AppHandle) {
+    let sender = EVENTS.lock().get_or_insert_with(|| spawn_rescan_worker(app)).clone();
+
+    let roots: Vec<String> = load_player_config().library_roots.into_iter()
+        .filter(|root| root.watch)
+        .map(|root| root.path)
+        .collect();
+    let mut current = WATCHER.lock();
+    *current = None;
+    if roots.is_empty() {
+        return;
+    }
+
+    let watcher = notify::recommended_watcher(move |res: Result<Event, notify::Error>| match res {
+        Ok(event) if !event.kind.is_access() => {
+            for path in event.paths {
+                let _ = sender.send(path);
+            }
+        }
+        Ok(_) => {}
+        Err(e) => error!("Library watcher error: {}", e),
+    });
+    let mut watcher = match watcher {
+        Ok(watcher) => watcher,
+        Err(e) => {
+            error!("Failed to create library watcher: {}", e);
+            return;
+        }
+    };
+
+    for root in &roots {
+        match watcher.watch(Path::new(root), RecursiveMode::Recursive) {
+            Ok(()) => info!("Watching library root {} for changes", root),
+            Err(e) => error!("Failed to watch {}: {}", root, e),
+        }
+    }
+    *current = Some(watcher);
+}
@@ -0,0 +1,401 @@
+//! Loudness analysis per EBU R128 / ITU-R BS.1770, written out as ReplayGain
+//! 2.0 tags so `replaygain` can level playback. Files are decoded in full, so
+//! this runs as a background task with progress per file.
+
+use serde::Serialize;
+use std::collections::HashSet;
+use std::path::{Path, PathBuf};
+use rodio::Source;
+use tauri::{AppHandle, Emitter};
+use log::{info, warn};
+use crate::albums::group_albums;
+use crate::decode::open_decoder;
+use crate::metadata::write_replaygain_tags;
+use crate::replaygain::{read_tags, ReplayGainTags};
+use crate::tasks::{start_task, TaskHandle, TaskKind};
+
+// ReplayGain 2.0 plays everything as if it measured this loudness
+const REFERENCE_LUFS: f64 = -18.0;
+const ABSOLUTE_GATE_LUFS: f64 = -70.0;
+const RELATIVE_GATE_LU: f64 = -10.0;
+// Gating blocks are 400ms long and start every 100ms
+const SUB_BLOCK_SECS: f64 = 0.1;
+const SUB_BLOCKS_PER_BLOCK: usize = 4;
+
+/// One stage of the K-weighting filter, in direct form I.
+#[derive(Debug, Clone)]
+struct Biquad {
+    b: [f64; 3],
+    a: [f64; 2],
+    x: [f64; 2],
+    y: [f64; 2],
+}
+
+impl Biquad {
+    fn new(b: [f64; 3], a: [f64; 2]) -> Self {
+        Self { b, a, x: [0.0; 2], y: [0.0; 2] }
+    }
+
+    /// The high shelf modelling the head's effect, per BS.1770 but derived for
+    /// any sample rate rather than only 48 kHz.
+    fn shelf(sample_rate: u32) -> Self {
+        let (f0, gain_db, q) = (1681.974450955533, 3.999843853973347, 0.7071752369554196);
+        let k = (std::f64::consts::PI * f0 / sample_rate as f64).tan();
+        let vh = 10f64.powf(gain_db / 20.0);
+        let vb = vh.powf(0.4996667741545416);
+        let a0 = 1.0 + k / q + k * k;
+        Self::new(
+            [(vh + vb * k / q + k * k) / a0, 2.0 * (k * k - vh) / a0, (vh - vb * k / q + k * k) / a0],
+            [2.0 * (k * k - 1.0) / a0, (1.0 - k / q + k * k) / a0],
+        )
+    }
+
+    /// The high pass that stops bass from dominating the measurement.
+    fn high_pass(sample_rate: u32) -> Self {
+        let (f0, q) = (38.13547087602444, 0.5003270373238773);
+        let k = (std::f64::consts::PI * f0 / sample_rate as f64).tan();
+        let a0 = 1.0 + k / q + k * k;
+        Self::new([1.0, -2.0, 1.0], [2.0 * (k * k - 1.0) / a0, (1.0 - k / q + k * k) / a0])
+    }
+
+    fn process(&mut self, input: f64) -> f64 {
+        let output = self.b[0] * input + self.b[1] * self.x[0] + self.b[2] * self.x[1]
+            - self.a[0] * self.y[0] - self.a[1] * self.y[1];
+        self.x = [input, self.x[0]];
+        self.y = [output, self.y[0]];
+        output
+    }
+}
+
+/// Channel weights from BS.1770: surround channels count a little more and
+/// the LFE not at all. Assumes the usual L R C LFE Ls Rs order for 5.1.
+fn channel_weight(channels: u16, index: usize) -> f64 {
+    match (channels, index) {
+        (6, 3) => 0.0,
+        (6, 4) | (6, 5) => 1.41,
+        _ => 1.0,
+    }
+}
+
+/// Accumulates the gating blocks and sample peak of one track.
+struct Meter {
+    channels: usize,
+    weights: Vec<f64>,
+    filters: Vec<(Biquad, Biquad)>,
+    sub_block_frames: usize,
+    frames: usize,
+    // Weighted sum of squares over the sub-block in progress
+    sum: f64,
+    sub_blocks: Vec<f64>,
+    peak: f64,
+}
+
+impl Meter {
+    fn new(channels: u16, sample_rate: u32) -> Self {
+        Self {
+            channels: channels.max(1) as usize,
+            weights: (0..channels.max(1) as usize).map(|index| channel_weight(channels, index)).collect(),
+            filters: (0..channels.max(1)).map(|_| (Biquad::shelf(sample_rate), Biquad::high_pass(sample_rate))).collect(),
+            sub_block_frames: ((sample_rate as f64 * SUB_BLOCK_SECS).round() as usize).max(1),
+            frames: 0,
+            sum: 0.0,
+            sub_blocks: Vec::new(),
+            peak: 0.0,
+        }
+    }
+
+    /// Adds one frame of samples, one per channel, in -1.0..1.0.
+    fn push_frame(&mut self, frame: &[f64]) {
+        for (index, sample) in frame.iter().enumerate().take(self.channels) {
+            self.peak = self.peak.max(sample.abs());
+            let (shelf, high_pass) = &mut self.filters[index];
+            let filtered = high_pass.process(shelf.process(*sample));
+            self.sum += self.weights[index] * filtered * filtered;
+        }
+        self.frames += 1;
+        if self.frames == self.sub_block_frames {
+            self.sub_blocks.push(self.sum / self.frames as f64);
+            self.frames = 0;
+            self.sum = 0.0;
+        }
+    }
+
+    /// Mean power of every 400ms gating block. A trailing partial block is dropped.
+    fn blocks(&self) -> Vec<f64> {
+        self.sub_blocks
+            .windows(SUB_BLOCKS_PER_BLOCK)
+            .map(|window| window.iter().sum::<f64>() / SUB_BLOCKS_PER_BLOCK as f64)
+            .collect()
+    }
+}
+
+fn lufs(power: f64) -> f64 {
+    -0.691 + 10.0 * power.log10()
+}
+
+/// Gated integrated loudness of `blocks`, or None if everything is below the
+/// absolute gate (silence, or a track shorter than one block).
+fn integrated_loudness(blocks: &[f64]) -> Option<f64> {
+    let mean = |blocks: &[f64]| (!blocks.is_empty()).then(|| blocks.iter().sum::<f64>() / blocks.len() as f64);
+    let audible: Vec<f64> = blocks.iter().copied().filter(|power| lufs(*power) > ABSOLUTE_GATE_LUFS).collect();
+    let threshold = lufs(mean(&audible)?) + RELATIVE_GATE_LU;
+    let gated: Vec<f64> = audible.into_iter().filter(|power| lufs(*power) > threshold).collect();
+    mean(&gated).map(lufs)
+}
+
+/// What one track measured: its gating blocks, kept for the album
+/// measurement, and its sample peak.
+struct Measurement {
+    blocks: Vec<f64>,
+    peak: f64,
+}
+
+fn measure(path: &Path, cancelled: &dyn Fn() -> bool) -> Result<Measurement, String> {
+    let decoder = open_decoder(path)?;
+    let channels = decoder.channels();
+    let mut meter = Meter::new(channels, decoder.sample_rate());
+    let mut frame = Vec::with_capacity(channels as usize);
+    for (index, sample) in decoder.enumerate() {
+        frame.push(sample as f64 / 32768.0);
+        if frame.len() == channels as usize {
+            meter.push_frame(&frame);
+            frame.clear();
+        }
+        if index % 1_000_000 == 0 && cancelled() {
+            return Err("Cancelled".to_string());
+        }
+    }
+    Ok(Measurement { blocks: meter.blocks(), peak: meter.peak })
+}
+
+fn gain_for(loudness: f64) -> f64 {
+    REFERENCE_LUFS - loudness
+}
+
+#[derive(Debug, Serialize, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum LoudnessStatus {
+    Analyzed,
+    Skipped,
+    Failed,
+}
+
+/// Sent as `loudness-progress` after each file.
+#[derive(Debug, Serialize, Clone)]
+pub struct LoudnessProgress {
+    pub path: String,
+    pub current: usize,
+    pub total: usize,
+    pub status: LoudnessStatus,
+    pub loudness: Option<f64>,
+    pub track_gain: Option<f64>,
+    pub error: Option<String>,
+}
+
+#[derive(Debug, Serialize, Clone)]
+pub struct LoudnessFailure {
+    pub path: String,
+    pub error: String,
+}
+
+#[derive(Debug, Serialize, Clone)]
+pub struct LoudnessSummary {
+    pub task_id: u64,
+    pub analyzed: usize,
+    pub skipped: usize,
+    pub failed: usize,
+    pub failures: Vec<LoudnessFailure>,
+    pub cancelled: bool,
+}
+
+/// Splits `paths` into the groups that share an album gain: albums by tag (or
+/// folder) in album mode, otherwise every file on its own.
+fn analysis_groups(paths: &[String], album_mode: bool) -> Vec<Vec<String>> {
+    if !album_mode {
+        return paths.iter().map(|path| vec![path.clone()]).collect();
+    }
+    let files: Vec<PathBuf> = paths.iter().map(PathBuf::from).collect();
+    let mut groups: Vec<Vec<String>> = group_albums(&files)
+        .into_iter()
+        .map(|album| album.tracks.into_iter().map(|track| track.path).collect())
+        .collect();
+    // Files whose tags couldn't be read still get a track gain, and then fail or not on their own
+    let grouped: HashSet<String> = groups.iter().flatten().cloned().collect();
+    groups.extend(paths.iter().filter(|path| !grouped.contains(*path)).map(|path| vec![path.clone()]));
+    groups
+}
+
+/// Whether a file already has the tags this run would write.
+fn already_tagged(tags: &ReplayGainTags, album_mode: bool) -> bool {
+    tags.track_gain.is_some() && (!album_mode || tags.album_gain.is_some())
+}
+
+struct Run<'a> {
+    app: &'a AppHandle,
+    task: &'a TaskHandle,
+    total: usize,
+    done: usize,
+    summary: LoudnessSummary,
+}
+
+impl Run<'_> {
+    fn report(&mut self, path: &str, status: LoudnessStatus, loudness: Option<f64>, error: Option<String>) {
+        self.done += 1;
+        match status {
+            LoudnessStatus::Analyzed => self.summary.analyzed += 1,
+            LoudnessStatus::Skipped => self.summary.skipped += 1,
+            LoudnessStatus::Failed => {
+                self.summary.failed += 1;
+                let error = error.clone().unwrap_or_default();
+                self.task.count_error(&error);
+                self.summary.failures.push(LoudnessFailure { path: path.to_string(), error });
+            }
+        }
+        self.task.set_progress(self.done as u64, self.total as u64);
+        self.app.emit("loudness-progress", LoudnessProgress {
+            path: path.to_string(),
+            current: self.done,
+            total: self.total,
+            status,
+            loudness,
+            track_gain: loudness.map(gain_for),
+            error,
+        }).ok();
+    }
+
+    /// Measures and tags one group. The album gain is only written when every
+    /// track in it could be measured, since a partial album would skew it.
+    fn analyze_group(&mut self, group: &[String], album_mode: bool, force: bool) {
+        if !force && group.iter().all(|path| already_tagged(&read_tags(Path::new(path)), album_mode)) {
+            for path in group {
+                self.report(path, LoudnessStatus::Skipped, None, None);
+            }
+            return;
+        }
+
+        let task = self.task;
+        let mut measured = Vec::new();
+        for path in group {
+            if task.is_cancelled() {
+                return;
+            }
+            task.set_message(path.clone());
+            match measure(Path::new(path), &|| task.is_cancelled()) {
+                Ok(measurement) => measured.push((path, measurement)),
+                Err(_) if task.is_cancelled() => return,
+                Err(e) => self.report(path, LoudnessStatus::Failed, None, Some(e)),
+            }
+        }
+
+        let album = (album_mode && measured.len() == group.len()).then(|| {
+            let blocks: Vec<f64> = measured.iter().flat_map(|(_, measurement)| measurement.blocks.iter().copied()).collect();
+            let peak = measured.iter().map(|(_, measurement)| measurement.peak).fold(0.0, f64::max);
+            (integrated_loudness(&blocks).map(gain_for), peak)
+        });
+        for (path, measurement) in measured {
+            let loudness = integrated_loudness(&measurement.blocks);
+            let values = ReplayGainTags {
+                // Silence has no loudness to correct, so it plays unchanged
+                track_gain: Some(loudness.map_or(0.0, gain_for) as f32),
+                track_peak: Some(measurement.peak as f32),
+                album_gain: album.map(|(gain, _)| gain.unwrap_or(0.0) as f32),
+                album_peak: album.map(|(_, peak)| peak as f32),
+            };
+            match write_replaygain_tags(Path::new(path), &values) {
+                Ok(()) => self.report(path, LoudnessStatus::Analyzed, loudness, None),
+                Err(e) => {
+                    warn!("Failed to write ReplayGain tags to {}: {}", path, e);
+                    self.report(path, LoudnessStatus::Failed, loudness, Some(e));
+                }
+            }
+        }
+    }
+}
+
+/// Measures the loudness of `paths` and writes ReplayGain track gain and peak
+/// tags, plus album gain and peak when `album_mode` is set. Files that already
+/// have those tags are left alone unless `force` is set. Runs as a background
+/// task (cancel it through `cancel_background_task`).
+#[tauri::command]
+pub async fn analyze_loudness(app: AppHandle, paths: Vec<String>, album_mode: bool, force: Option<bool>) -> Result<LoudnessSummary, String> {
+    let task = start_task(&app, TaskKind::Loudness, format!("Analyzing loudness of {} files", paths.len()));
+    let mut run = Run {
+        app: &app,
+        task: &task,
+        total: paths.len(),
+        done: 0,
+        summary: LoudnessSummary {
+            task_id: task.id(),
+            analyzed: 0,
+            skipped: 0,
+            failed: 0,
+            failures: Vec::new(),
+            cancelled: false,
+        },
+    };
+    task.set_progress(0, paths.len() as u64);
+    for group in analysis_groups(&paths, album_mode) {
+        if task.is_cancelled() {
+            break;
+        }
+        run.analyze_group(&group, album_mode, force.unwrap_or(false));
+    }
+
+    let mut summary = run.summary;
+    summary.cancelled = task.is_cancelled();
+    task.set_count("analyzed", summary.analyzed as u64);
+    task.set_count("skipped", summary.skipped as u64);
+    task.set_count("failed", summary.failed as u64);
+    task.finish(Ok(()));
+    info!("Loudness analysis: {} analyzed, {} skipped, {} failed", summary.analyzed, summary.skipped, summary.failed);
+    Ok(summary)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::f64::consts::PI;
+
+    fn stereo_sine(amplitude: f64, sample_rate: u32, secs: f64) -> Meter {
+        let mut meter = Meter::new(2, sample_rate);
+        for n in 0..(sample_rate as f64 * secs) as usize {
+            let sample = amplitude * (2.0 * PI * 1000.0 * n as f64 / sample_rate as f64).sin();
+            meter.push_frame(&[sample, sample]);
+        }
+        meter
+    }
+
+    #[test]
+    fn a_minus_23_dbfs_sine_measures_minus_23_lufs() {
+        // EBU Tech 3341 case 1, at the reference rate and a common CD rate
+        let amplitude = 10f64.powf(-23.0 / 20.0);
+        for sample_rate in [48_000, 44_100] {
+            let loudness = integrated_loudness(&stereo_sine(amplitude, sample_rate, 20.0).blocks()).unwrap();
+            assert!((loudness + 23.0).abs() < 0.1, "{} Hz measured {}", sample_rate, loudness);
+        }
+        assert!((gain_for(-23.0) - 5.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn silence_is_gated_out() {
+        let amplitude = 10f64.powf(-23.0 / 20.0);
+        let mut meter = stereo_sine(amplitude, 48_000, 10.0);
+        for _ in 0..48_000 * 10 {
+            meter.push_frame(&[0.0, 0.0]);
+        }
+        let loudness = integrated_loudness(&meter.blocks()).unwrap();
+        assert!((loudness + 23.0).abs() < 0.1, "measured {}", loudness);
+        assert!((meter.peak - amplitude).abs() < 1e-3);
+
+        assert_eq!(integrated_loudness(&stereo_sine(0.0, 48_000, 2.0).blocks()), None);
+        assert_eq!(integrated_loudness(&[]), None);
+    }
+
+    #[test]
+    fn existing_tags_are_only_enough_for_the_mode_they_cover() {
+        let track_only = ReplayGainTags { track_gain: Some(-3.0), ..ReplayGainTags::default() };
+        assert!(already_tagged(&track_only, false));
+        assert!(!already_tagged(&track_only, true));
+        assert!(!already_tagged(&ReplayGainTags::default(), false));
+    }
+}
@@ -0,0 +1,257 @@
+//! Time-synced lyrics for a karaoke-style display: an embedded SYLT frame in
+//! the file's ID3v2 tag, or else a `.lrc` file next to it with the same stem.
+
+use serde::{Deserialize, Serialize};
+use std::fs::{self, File};
+use std::io::BufReader;
+use std::path::{Path, PathBuf};
+use lofty::{
+    config::ParseOptions, file::{AudioFile, FileType}, id3::v2::{Frame, SyncTextContentType, SynchronizedTextFrame, TimestampFormat},
+    mpeg::MpegFile, probe::Probe,
+};
+use log::warn;
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct LyricLine {
+    // Milliseconds from the start of the track
+    pub time_ms: u64,
+    pub text: String,
+}
+
+/// An LRC file as parsed: its timed lines, in order, and the 1-based numbers
+/// of lines that couldn't be read.
+#[derive(Debug, Default, PartialEq)]
+struct ParsedLrc {
+    lines: Vec<LyricLine>,
+    skipped: Vec<usize>,
+}
+
+/// `mm:ss`, `mm:ss.xx`, `mm:ss.xxx` or `mm:ss:xx` to milliseconds. Minutes
+/// may go past 59.
+fn parse_timestamp(text: &str) -> Option<u64> {
+    let (minutes, rest) = text.split_once(':')?;
+    let (seconds, fraction) = match rest.split_once(['.', ':']) {
+        Some((seconds, fraction)) => (seconds, Some(fraction)),
+        None => (rest, None),
+    };
+    let digits = |part: &str| !part.is_empty() && part.bytes().all(|b| b.is_ascii_digit());
+    if !digits(minutes) || !digits(seconds) || fraction.is_some_and(|fraction| !digits(fraction)) {
+        return None;
+    }
+    let seconds: u64 = seconds.parse().ok()?;
+    if seconds >= 60 {
+        return None;
+    }
+    // Tenths, hundredths or thousandths, by how many digits there are
+    let millis = match fraction {
+        Some(fraction) if fraction.len() <= 3 => fraction.parse::<u64>().ok()? * 10u64.pow(3 - fraction.len() as u32),
+        Some(_) => return None,
+        None => 0,
+    };
+    Some(minutes.parse::<u64>().ok()? * 60_000 + seconds * 1000 + millis)
+}
+
+/// Parses LRC text. A line may carry several timestamps (`[00:12.00][01:30.00]Chorus`),
+/// each giving its own entry; `[offset:+250]` moves every line that many
+/// milliseconds earlier. Other `[key:value]` tags are ignored, and lines that
+/// are neither tags nor timed lyrics are skipped.
+fn parse_lrc(contents: &str) -> ParsedLrc {
+    let mut parsed = ParsedLrc::default();
+    let mut offset_ms: i64 = 0;
+
+    for (line_number, line) in contents.trim_start_matches('\u{feff}').lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() {
+            continue;
+        }
+        let mut rest = line;
+        let mut times = Vec::new();
+        let mut tagged = false;
+        let mut malformed = false;
+        while let Some(inner) = rest.strip_prefix('[') {
+            let Some(end) = inner.find(']') else {
+                malformed = true;
+                break;
+            };
+            let tag = &inner[..end];
+            rest = &inner[end + 1..];
+            if let Some(time) = parse_timestamp(tag) {
+                times.push(time);
+                continue;
+            }
+            match tag.split_once(':') {
+                Some((key, value)) if !key.is_empty() && key.chars().all(|c| c.is_ascii_alphabetic() || c == '#') => {
+                    tagged = true;
+                    if key.eq_ignore_ascii_case("offset") {
+                        match value.trim().parse::<i64>() {
+                            Ok(offset) => offset_ms = offset,
+                            Err(_) => malformed = true,
+                        }
+                    }
+                }
+                _ => malformed = true,
+            }
+            if malformed {
+                break;
+            }
+        }
+        if malformed || (times.is_empty() && !tagged) || (tagged && !times.is_empty()) {
+            parsed.skipped.push(line_number + 1);
+            continue;
+        }
+        let text = rest.trim();
+        parsed.lines.extend(times.into_iter().map(|time_ms| LyricLine { time_ms, text: text.to_string() }));
+    }
+
+    // The offset tag can come anywhere in the file, so it is applied last
+    for line in &mut parsed.lines {
+        line.time_ms = (line.time_ms as i64 - offset_ms).max(0) as u64;
+    }
+    parsed.lines.sort_by_key(|line| line.time_ms);
+    parsed
+}
+
+/// `[mm:ss.xx]` for a time in milliseconds.
+fn format_timestamp(time_ms: u64) -> String {
+    format!("[{:02}:{:02}.{:02}]", time_ms / 60_000, time_ms / 1000 % 60, time_ms % 1000 / 10)
+}
+
+fn render_lrc(lines: &[LyricLine]) -> String {
+    let mut sorted: Vec<&LyricLine> = lines.iter().collect();
+    sorted.sort_by_key(|line| line.time_ms);
+    sorted.iter()
+        .map(|line| format!("{}{}\n", format_timestamp(line.time_ms), line.text.replace(['\r', '\n'], " ")))
+        .collect()
+}
+
+/// The sidecar for `path`: an existing `.lrc` or `.LRC` with the same stem,
+/// else where a new `.lrc` would go.
+fn lrc_path(path: &Path) -> PathBuf {
+    ["lrc", "LRC"].iter()
+        .map(|extension| path.with_extension(extension))
+        .find(|sidecar| sidecar.is_file())
+        .unwrap_or_else(|| path.with_extension("lrc"))
+}
+
+/// Lyrics from the first SYLT frame timed in milliseconds, preferring frames
+/// marked as lyrics. Only MP3s are looked at.
+fn embedded_synced_lyrics(path: &Path) -> Option<Vec<LyricLine>> {
+    let file_type = Probe::open(path).ok()?.guess_file_type().ok()?.file_type();
+    if file_type != Some(FileType::Mpeg) {
+        return None;
+    }
+    let mut reader = BufReader::new(File::open(path).ok()?);
+    let file = MpegFile::read_from(&mut reader, ParseOptions::new()).ok()?;
+    let frames: Vec<SynchronizedTextFrame> = file.id3v2()?.into_iter()
+        .filter(|frame| frame.id_str() == "SYLT")
+        .filter_map(|frame| match frame {
+            Frame::Binary(binary) => SynchronizedTextFrame::parse(&binary.data, frame.flags()).ok(),
+            _ => None,
+        })
+        // Timestamps in MPEG frames would need the frame length to convert
+        .filter(|sylt| sylt.timestamp_format == TimestampFormat::MS)
+        .collect();
+    let sylt = frames.iter()
+        .find(|sylt| sylt.content_type == SyncTextContentType::Lyrics)
+        .or_else(|| frames.first())?;
+    let mut lines: Vec<LyricLine> = sylt.content.iter()
+        .map(|(time, text)| LyricLine { time_ms: *time as u64, text: text.trim_matches(['\r', '\n']).to_string() })
+        .collect();
+    lines.sort_by_key(|line| line.time_ms);
+    (!lines.is_empty()).then_some(lines)
+}
+
+/// Synced lyrics for `path`, from an embedded SYLT frame or a sidecar `.lrc`.
+/// None when there are neither, or the `.lrc` has no timed lines.
+#[tauri::command]
+pub fn get_synced_lyrics(path: &str) -> Option<Vec<LyricLine>> {
+    let path = Path::new(path);
+    if let Some(lines) = embedded_synced_lyrics(path) {
+        return Some(lines);
+    }
+    let sidecar = lrc_path(path);
+    // Lossy decoding: LRC files are often Latin-1 or some other legacy encoding
+    let bytes = fs::read(&sidecar).ok()?;
+    let parsed = parse_lrc(&String::from_utf8_lossy(&bytes));
+    if !parsed.skipped.is_empty() {
+        warn!("Skipped unreadable lines in {}: {:?}", sidecar.display(), parsed.skipped);
+    }
+    (!parsed.lines.is_empty()).then_some(parsed.lines)
+}
+
+/// Saves `lines` as the `.lrc` next to `path`, replacing any there, and
+/// returns the sidecar's path.
+#[tauri::command]
+pub fn write_lrc(path: &str, lines: Vec<LyricLine>) -> Result<String, String> {
+    let sidecar = lrc_path(Path::new(path));
+    fs::write(&sidecar, render_lrc(&lines))
+        .map_err(|e| format!("Failed to write {}: {}", sidecar.display(), e))?;
+    Ok(sidecar.to_string_lossy().to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use lofty::TextEncoding;
+    use crate::test_support::{id3v2_tag_with, mp3_frames, temp_dir, FixtureTags, FIXTURE_SECS};
+
+    fn line(time_ms: u64, text: &str) -> LyricLine {
+        LyricLine { time_ms, text: text.to_string() }
+    }
+
+    #[test]
+    fn parses_repeated_timestamps_offsets_and_bad_lines() {
+        let lrc = "\u{feff}[ti:Song]\n[ar:Someone]\n[offset:+500]\n\
+            [00:12.00]First\n\
+            [00:20.50][01:05.25]Chorus\n\
+            this line has no time\n\
+            [00:30.1]Tenths\n\
+            [00:7x.00]Broken\n\
+            \n\
+            [00:00.20]Early\n";
+        let parsed = parse_lrc(lrc);
+        assert_eq!(parsed.lines, [
+            line(0, "Early"),
+            line(11_500, "First"),
+            line(20_000, "Chorus"),
+            line(29_600, "Tenths"),
+            line(64_750, "Chorus"),
+        ]);
+        assert_eq!(parsed.skipped, [6, 8]);
+    }
+
+    #[test]
+    fn written_lrc_reads_back() {
+        let dir = temp_dir();
+        let track = dir.path().join("song.flac");
+        let lines = vec![line(61_230, "Second"), line(5_000, "First"), line(90_000, "")];
+        let sidecar = write_lrc(track.to_str().unwrap(), lines).unwrap();
+        assert_eq!(sidecar, dir.path().join("song.lrc").to_string_lossy());
+        assert_eq!(fs::read_to_string(&sidecar).unwrap(), "[00:05.00]First\n[01:01.23]Second\n[01:30.00]\n");
+        assert_eq!(get_synced_lyrics(track.to_str().unwrap()).unwrap(), [
+            line(5_000, "First"),
+            line(61_230, "Second"),
+            line(90_000, ""),
+        ]);
+    }
+
+    #[test]
+    fn embedded_sylt_wins_over_the_sidecar() {
+        let dir = temp_dir();
+        let track = dir.path().join("sung.mp3");
+        let sylt = SynchronizedTextFrame::new(
+            TextEncoding::UTF8,
+            *b"eng",
+            TimestampFormat::MS,
+            SyncTextContentType::Lyrics,
+            None,
+            vec![(1_000, "Hello".to_string()), (2_500, "\nWorld".to_string())],
+        );
+        let mut bytes = id3v2_tag_with(&FixtureTags::titled("Sung"), &[(b"SYLT", sylt.as_bytes().unwrap())]);
+        bytes.extend(mp3_frames(FIXTURE_SECS));
+        fs::write(&track, bytes).unwrap();
+        fs::write(dir.path().join("sung.lrc"), "[00:09.00]Sidecar\n").unwrap();
+
+        assert_eq!(get_synced_lyrics(track.to_str().unwrap()).unwrap(), [line(1_000, "Hello"), line(2_500, "World")]);
+    }
+}
@@ -0,0 +1,351 @@
+//! The OS media overlay on Windows (System Media Transport Controls) and macOS
+//! (Now Playing), so the current track shows there and hardware media keys
+//! reach the player. The session is registered when playback first starts, a
+//! watcher thread keeps it in step with the player, and `stop_audio` clears it.
+
+use std::path::Path;
+use std::time::Duration;
+use once_cell::sync::Lazy;
+use parking_lot::Mutex;
+use tauri::{AppHandle, Manager};
+use base64::{Engine as _, engine::general_purpose::STANDARD as BASE64};
+use log::error;
+use crate::commands::{emit_playback_state, pause_audio, previous_track, resume_audio, skip_track, stop_audio};
+use crate::cue::{load_cue_sheet, CueRegion};
+use crate::library::track_info;
+use crate::metadata::get_album_art;
+use crate::PlayerHandle;
+
+const WATCH_INTERVAL: Duration = Duration::from_millis(500);
+
+/// A button pressed in the overlay or on the keyboard.
+#[derive(Debug, Clone, Copy)]
+pub enum Control {
+    Play,
+    Pause,
+    Toggle,
+    Stop,
+    Next,
+    Previous,
+}
+
+/// What the overlay shows for the current track.
+pub struct NowPlaying {
+    pub title: String,
+    pub artist: Option<String>,
+    pub album: Option<String>,
+    pub duration: Option<Duration>,
+    // Image file contents, as embedded in the track
+    pub artwork: Option<Vec<u8>>,
+}
+
+impl NowPlaying {
+    fn load(path: &str, cue: Option<&CueRegion>, duration: Option<Duration>) -> Self {
+        let track = track_info(Path::new(path));
+        let artwork = get_album_art(path).ok().flatten()
+            .and_then(|encoded| BASE64.decode(encoded).ok());
+        let mut now = Self {
+            title: track.as_ref().and_then(|track| track.title.clone())
+                .unwrap_or_else(|| Path::new(path).file_stem().unwrap_or_default().to_string_lossy().to_string()),
+            artist: track.as_ref().and_then(|track| track.artist.clone()),
+            album: track.and_then(|track| track.album),
+            duration,
+            artwork,
+        };
+        // A CUE track shares its file with the rest of the sheet, which names it
+        let sheet = cue.and_then(|cue| Some((load_cue_sheet(&cue.cue_path).ok()?, cue.index)));
+        if let Some((sheet, index)) = sheet {
+            let cue_track = sheet.tracks.get(index);
+            if let Some(title) = cue_track.and_then(|track| track.title.clone()) {
+                now.title = title;
+            }
+            now.artist = cue_track.and_then(|track| track.performer.clone()).or(sheet.performer).or(now.artist);
+            now.album = sheet.title.or(now.album);
+        }
+        now
+    }
+}
+
+// None until something has been played
+static SESSION: Lazy<Mutex<Option<platform::Session>>> = Lazy::new(|| Mutex::new(None));
+
+fn handle(app: &AppHandle, control: Control) {
+    let result = match control {
+        Control::Play => resume_audio(app.state()),
+        Control::Pause => pause_audio(app.state()),
+        Control::Toggle => {
+            let player = app.state::<PlayerHandle>();
+            let is_playing = player.with(|player| player.is_playing);
+            if is_playing { pause_audio(player) } else { resume_audio(player) }
+        }
+        Control::Stop => stop_audio(app.state()),
+        Control::Next => skip_track(app.clone()),
+        Control::Previous => previous_track(app.clone()),
+    };
+    match result {
+        // The UI didn't make this change, so it is told like it is for shortcuts
+        Ok(()) => emit_playback_state(app),
+        Err(e) => error!("Media key {:?} failed: {}", control, e),
+    }
+}
+
+/// Shows `now` in the overlay, registering the session first if needed.
+fn show(app: &AppHandle, now: &NowPlaying, playing: bool) {
+    let mut session = SESSION.lock();
+    if session.is_none() {
+        let app = app.clone();
+        match platform::Session::new(move |control| handle(&app, control)) {
+            Ok(created) => *session = Some(created),
+            Err(e) => {
+                error!("Could not register the media session: {}", e);
+                return;
+            }
+        }
+    }
+    let Some(session) = session.as_ref() else { return };
+    if let Err(e) = session.show(now).and_then(|()| session.set_playing(playing)) {
+        error!("Failed to update the media session: {}", e);
+    }
+}
+
+/// Takes the current track out of the overlay.
+pub fn clear() {
+    if let Some(session) = SESSION.lock().as_ref() {
+        if let Err(e) = session.clear() {
+            error!("Failed to clear the media session: {}", e);
+        }
+    }
+}
+
+/// Keeps the overlay in step with the player, whichever way it changes.
+pub fn start(app: AppHandle) {
+    std::thread::spawn(move || {
+        let mut shown: Option<(String, Option<CueRegion>)> = None;
+        let mut was_playing = false;
+        loop {
+            std::thread::sleep(WATCH_INTERVAL);
+            let (track, playing, duration) = app.state::<PlayerHandle>().with(|player| {
+                let track = player.current_path.clone().map(|path| (path, player.cue_track.clone()));
+                (track, player.is_playing, player.track_duration())
+            });
+            match &track {
+                None if shown.is_some() => clear(),
+                Some((path, cue)) if track != shown => show(&app, &NowPlaying::load(path, cue.as_ref(), duration), playing),
+                Some(_) if playing != was_playing => {
+                    if let Some(session) = SESSION.lock().as_ref() {
+                        session.set_playing(playing).ok();
+                    }
+                }
+                _ => {}
+            }
+            shown = track;
+            was_playing = playing;
+        }
+    });
+}
+
+#[cfg(target_os = "windows")]
+mod platform {
+    use windows::core::HSTRING;
+    use windows::Foundation::TypedEventHandler;
+    use windows::Media::Playback::MediaPlayer;
+    use windows::Media::{
+        MediaPlaybackStatus, MediaPlaybackType, SystemMediaTransportControls, SystemMediaTransportControlsButton,
+        SystemMediaTransportControlsButtonPressedEventArgs,
+    };
+    use windows::Storage::Streams::{DataWriter, InMemoryRandomAccessStream, RandomAccessStreamReference};
+    use super::{Control, NowPlaying};
+
+    pub struct Session {
+        // Owns the transport controls; it never plays anything itself
+        _player: MediaPlayer,
+        controls: SystemMediaTransportControls,
+    }
+
+    fn artwork_stream(bytes: &[u8]) -> windows::core::Result<RandomAccessStreamReference> {
+        let stream = InMemoryRandomAccessStream::new()?;
+        let writer = DataWriter::CreateDataWriter(&stream)?;
+        writer.WriteBytes(bytes)?;
+        writer.StoreAsync()?.get()?;
+        writer.DetachStream()?;
+        stream.Seek(0)?;
+        RandomAccessStreamReference::CreateFromStream(&stream)
+    }
+
+    impl Session {
+        pub fn new(handler: impl Fn(Control) + Send + 'static) -> Result<Self, String> {
+            let register = || -> windows::core::Result<Self> {
+                let player = MediaPlayer::new()?;
+                // Buttons come to us instead of to the idle player
+                player.CommandManager()?.SetIsEnabled(false)?;
+                let controls = player.SystemMediaTransportControls()?;
+                controls.SetIsPlayEnabled(true)?;
+                controls.SetIsPauseEnabled(true)?;
+                controls.SetIsStopEnabled(true)?;
+                controls.SetIsNextEnabled(true)?;
+                controls.SetIsPreviousEnabled(true)?;
+                controls.ButtonPressed(&TypedEventHandler::new(
+                    move |_, args: &Option<SystemMediaTransportControlsButtonPressedEventArgs>| {
+                        let Some(args) = args else { return Ok(()) };
+                        let control = match args.Button()? {
+                            SystemMediaTransportControlsButton::Play => Control::Play,
+                            SystemMediaTransportControlsButton::Pause => Control::Pause,
+                            SystemMediaTransportControlsButton::Stop => Control::Stop,
+                            SystemMediaTransportControlsButton::Next => Control::Next,
+                            SystemMediaTransportControlsButton::Previous => Control::Previous,
+                            _ => return Ok(()),
+                        };
+                        handler(control);
+                        Ok(())
+                    },
+                ))?;
+                Ok(Self { _player: player, controls })
+            };
+            register().map_err(|e| e.to_string())
+        }
+
+        pub fn show(&self, now: &NowPlaying) -> Result<(), String> {
+            let update = || -> windows::core::Result<()> {
+                self.controls.SetIsEnabled(true)?;
+                let updater = self.controls.DisplayUpdater()?;
+                updater.ClearAll()?;
+                updater.SetType(MediaPlaybackType::Music)?;
+                let music = updater.MusicProperties()?;
+                music.SetTitle(&HSTRING::from(now.title.as_str()))?;
+                music.SetArtist(&HSTRING::from(now.artist.as_deref().unwrap_or_default()))?;
+                music.SetAlbumTitle(&HSTRING::from(now.album.as_deref().unwrap_or_default()))?;
+                if let Some(artwork) = &now.artwork {
+                    updater.SetThumbnail(&artwork_stream(artwork)?)?;
+                }
+                updater.Update()
+            };
+            update().map_err(|e| e.to_string())
+        }
+
+        pub fn set_playing(&self, playing: bool) -> Result<(), String> {
+            let status = if playing { MediaPlaybackStatus::Playing } else { MediaPlaybackStatus::Paused };
+            self.controls.SetPlaybackStatus(status).map_err(|e| e.to_string())
+        }
+
+        pub fn clear(&self) -> Result<(), String> {
+            let clear = || -> windows::core::Result<()> {
+                let updater = self.controls.DisplayUpdater()?;
+                updater.ClearAll()?;
+                updater.Update()?;
+                self.controls.SetPlaybackStatus(MediaPlaybackStatus::Closed)?;
+                // Hides the overlay until the next track
+                self.controls.SetIsEnabled(false)
+            };
+            clear().map_err(|e| e.to_string())
+        }
+    }
+}
+
+#[cfg(target_os = "macos")]
+mod platform {
+    use std::sync::Arc;
+    use block2::RcBlock;
+    use objc2::rc::Retained;
+    use objc2::runtime::AnyObject;
+    use objc2::{class, msg_send, msg_send_id};
+    use objc2_foundation::{NSData, NSNumber, NSString};
+    use super::{Control, NowPlaying};
+
+    #[link(name = "MediaPlayer", kind = "framework")]
+    extern "C" {
+        static MPMediaItemPropertyTitle: &'static NSString;
+        static MPMediaItemPropertyArtist: &'static NSString;
+        static MPMediaItemPropertyAlbumTitle: &'static NSString;
+        static MPMediaItemPropertyArtwork: &'static NSString;
+        static MPMediaItemPropertyPlaybackDuration: &'static NSString;
+    }
+
+    // MPNowPlayingPlaybackState
+    const STATE_PLAYING: usize = 1;
+    const STATE_PAUSED: usize = 2;
+    const STATE_STOPPED: usize = 3;
+    // MPRemoteCommandHandlerStatusSuccess
+    const HANDLED: isize = 0;
+
+    // The info center is a process-wide singleton, so there is nothing to hold on to
+    pub struct Session;
+
+    fn info_center() -> Retained<AnyObject> {
+        unsafe { msg_send_id![class!(MPNowPlayingInfoCenter), defaultCenter] }
+    }
+
+    fn artwork(bytes: &[u8]) -> Option<Retained<AnyObject>> {
+        let data = NSData::with_bytes(bytes);
+        unsafe {
+            let image: Option<Retained<AnyObject>> = msg_send_id![msg_send_id![class!(NSImage), alloc], initWithData: &*data];
+            msg_send_id![msg_send_id![class!(MPMediaItemArtwork), alloc], initWithImage: &*image?]
+        }
+    }
+
+    impl Session {
+        pub fn new(handler: impl Fn(Control) + Send + 'static) -> Result<Self, String> {
+            let handler = Arc::new(handler);
+            unsafe {
+                let center: Retained<AnyObject> = msg_send_id![class!(MPRemoteCommandCenter), sharedCommandCenter];
+                let commands: [(Retained<AnyObject>, Control); 6] = [
+                    (msg_send_id![&*center, playCommand], Control::Play),
+                    (msg_send_id![&*center, pauseCommand], Control::Pause),
+                    (msg_send_id![&*center, togglePlayPauseCommand], Control::Toggle),
+                    (msg_send_id![&*center, stopCommand], Control::Stop),
+                    (msg_send_id![&*center, nextTrackCommand], Control::Next),
+                    (msg_send_id![&*center, previousTrackCommand], Control::Previous),
+                ];
+                for (command, control) in commands {
+                    let handler = Arc::clone(&handler);
+                    let block = RcBlock::new(move |_event: *mut AnyObject| -> isize {
+                        handler(control);
+                        HANDLED
+                    });
+                    let _: Retained<AnyObject> = msg_send_id![&*command, addTargetWithHandler: &*block];
+                }
+            }
+            Ok(Session)
+        }
+
+        pub fn show(&self, now: &NowPlaying) -> Result<(), String> {
+            unsafe {
+                let info: Retained<AnyObject> = msg_send_id![class!(NSMutableDictionary), new];
+                let set = |key: &NSString, value: &AnyObject| {
+                    let _: () = msg_send![&*info, setObject: value, forKey: key];
+                };
+                set(MPMediaItemPropertyTitle, &NSString::from_str(&now.title));
+                if let Some(artist) = &now.artist {
+                    set(MPMediaItemPropertyArtist, &NSString::from_str(artist));
+                }
+                if let Some(album) = &now.album {
+                    set(MPMediaItemPropertyAlbumTitle, &NSString::from_str(album));
+                }
+                if let Some(duration) = now.duration {
+                    set(MPMediaItemPropertyPlaybackDuration, &NSNumber::numberWithDouble(duration.as_secs_f64()));
+                }
+                if let Some(artwork) = now.artwork.as_deref().and_then(artwork) {
+                    set(MPMediaItemPropertyArtwork, &artwork);
+                }
+                let _: () = msg_send![&*info_center(), setNowPlayingInfo: &*info];
+            }
+            Ok(())
+        }
+
+        pub fn set_playing(&self, playing: bool) -> Result<(), String> {
+            let state = if playing { STATE_PLAYING } else { STATE_PAUSED };
+            unsafe {
+                let _: () = msg_send![&*info_center(), setPlaybackState: state];
+            }
+            Ok(())
+        }
+
+        pub fn clear(&self) -> Result<(), String> {
+            unsafe {
+                let center = info_center();
+                let _: () = msg_send![&*center, setNowPlayingInfo: std::ptr::null::<AnyObject>()];
+                let _: () = msg_send![&*center, setPlaybackState: STATE_STOPPED];
+            }
+            Ok(())
+        }
+    }
+}
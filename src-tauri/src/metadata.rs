@@ -1,13 +1,28 @@
 use lofty::{
-    config::WriteOptions, prelude::{AudioFile, ItemKey, TaggedFileExt}, probe::Probe, tag::{Accessor, Tag, TagType}, picture::PictureType, picture::MimeType, picture::Picture
+    config::{ParseOptions, WriteOptions}, file::{FileType, TaggedFile}, prelude::{AudioFile, ItemKey, TaggedFileExt}, probe::Probe, tag::{Accessor, Tag, TagType}, picture::PictureType, picture::MimeType, picture::Picture,
+    mp4::{Atom, AtomData, AtomIdent, Mp4File}, mpeg::MpegFile, id3::v2::{Frame, FrameId, Id3v2Tag, PopularimeterFrame},
 };
+use std::borrow::Cow;
 use serde::Serialize;
 use serde::Deserialize;
 use std::path::Path;
 use base64::{Engine as _, engine::general_purpose::STANDARD as BASE64};
 use std::collections::HashSet;
-use std::fs;
+use std::fs::{self, File};
+use std::io::BufReader;
 use std::path::PathBuf;
+use log::warn;
+use crate::config::load_player_config;
+use crate::thumbnails::{thumbnail_base64, LISTING_THUMBNAIL_PX};
+use crate::artwork::{dimensions, fit_for_embedding, image_kind, EmbeddedArt};
+use crate::library::{added_at, modified_secs};
+use crate::requests::{begin_request, RequestError, RequestToken};
+use crate::resume::resume_positions;
+use crate::text::{collate, fold};
+use crate::walker::{check_readable, emit_skipped, walk_files_reporting, Exclusions, SkippedPath};
+use crate::relocate::{guard_playing_track, FileOpError};
+use crate::replaygain::ReplayGainTags;
+use tauri::AppHandle;
 
 #[derive(Debug, Serialize)]
 pub struct AudioMetadata {
@@ -17,7 +32,21 @@ pub struct AudioMetadata {
     pub album_artist: Option<String>,
     pub year: Option<u32>,
     pub track_number: Option<u32>,
+    pub track_total: Option<u32>,
+    pub disc_number: Option<u32>,
+    pub disc_total: Option<u32>,
+    // Date exactly as tagged, e.g. "1999-04-12" or "℗ 2003"
+    pub raw_date: Option<String>,
     pub genre: Option<String>,
+    pub composer: Option<String>,
+    pub comment: Option<String>,
+    // Part of a compilation, as iTunes marks various-artists albums
+    pub compilation: Option<bool>,
+    pub bpm: Option<f32>,
+    // 0 to 100, 20 per star; None when unrated
+    pub rating: Option<u8>,
+    // Unsynchronized lyrics, line breaks kept
+    pub lyrics: Option<String>,
     pub album_art: Option<String>, // Base64 encoded image
     pub duration: Option<f64>,
     pub audio_bitrate: Option<u32>,
@@ -25,6 +54,11 @@ pub struct AudioMetadata {
     pub sample_rate: Option<u32>,
     pub bit_depth: Option<u32>,
     pub channels: Option<u32>,
+    pub modified: Option<u64>,
+    // Filled in by directory listings from the library index
+    pub added_at: Option<u64>,
+    // Filled in by directory listings for long files with a stored resume point
+    pub resume_position: Option<f32>,
 }
 
 #[derive(Debug, Serialize)]
@@ -33,9 +67,146 @@ pub struct MetadataWriteResult {
     pub message: String,
 }
 
+fn tag_date(tag: &Tag) -> Option<&str> {
+    [ItemKey::RecordingDate, ItemKey::Year]
+        .iter()
+        .find_map(|key| tag.get_string(key))
+        .map(str::trim)
+        .filter(|date| !date.is_empty())
+}
+
+/// Year from a date as it appears in tags: "1999", "1999-04-12", "12/04/1999",
+/// "℗ 2003" and the like. Takes the first run of exactly four digits.
+pub fn parse_year(raw: &str) -> Option<u32> {
+    raw.split(|c: char| !c.is_ascii_digit())
+        .find(|run| run.len() == 4)
+        .and_then(|run| run.parse().ok())
+        .filter(|year| (1000..=2999).contains(year))
+}
+
+/// Track number and total from "3", "03", "3/12" or "3 of 12".
+pub fn parse_track(raw: &str) -> (Option<u32>, Option<u32>) {
+    let number = |part: &str| part.trim().parse::<u32>().ok().filter(|n| *n > 0);
+    let lower = raw.to_lowercase();
+    match lower.split_once('/').or_else(|| lower.split_once(" of ")) {
+        Some((track, total)) => (number(track), number(total)),
+        None => (number(&lower), None),
+    }
+}
+
+/// A flag as tagged: "1" in TCMP and COMPILATION, a boolean in MP4's cpil.
+fn parse_flag(value: &str) -> Option<bool> {
+    match value.trim().to_lowercase().as_str() {
+        "1" | "true" | "yes" => Some(true),
+        "0" | "false" | "no" | "" => Some(false),
+        _ => None,
+    }
+}
+
+/// Tempo as tagged: a decimal "BPM" field in Vorbis comments and iTunes
+/// freeform atoms, a whole number in ID3 TBPM and MP4 tmpo.
+fn tag_bpm(tag: &Tag) -> Option<f32> {
+    [ItemKey::Bpm, ItemKey::IntegerBpm].iter()
+        .filter_map(|key| tag.get_string(key))
+        .find_map(|bpm| bpm.trim().parse::<f32>().ok())
+        .filter(|bpm| *bpm > 0.0)
+}
+
+/// The POPM owner Windows Media Player and Explorer read ratings from.
+const POPM_EMAIL: &str = "Windows Media Player 9 Series";
+
+/// Stars for a POPM rating byte, as Windows Media Player maps them.
+fn popm_stars(byte: u8) -> u8 {
+    match byte {
+        0 => 0,
+        1..=31 => 1,
+        32..=95 => 2,
+        96..=159 => 3,
+        160..=223 => 4,
+        _ => 5,
+    }
+}
+
+/// The POPM byte other players write for `stars`.
+fn popm_byte(stars: u8) -> u8 {
+    [0, 1, 64, 128, 196, 255][stars.min(5) as usize]
+}
+
+/// Stars for a 0–100 rating; any rating at all is worth one.
+fn rating_stars(rating: u8) -> u8 {
+    if rating == 0 { 0 } else { ((rating as f32 / 20.0).round() as u8).clamp(1, 5) }
+}
+
+/// A 0–100 rating from a text field: 0–100 as most players write it, whole
+/// stars up to 5, or FMPS-style fractions of 1.
+fn parse_rating(value: &str) -> Option<u8> {
+    let value: f32 = value.trim().parse().ok()?;
+    let rating = if value.fract() != 0.0 && value < 1.0 {
+        value * 100.0
+    } else if value <= 5.0 {
+        value * 20.0
+    } else {
+        value
+    };
+    Some(rating.round().clamp(0.0, 100.0) as u8).filter(|rating| *rating > 0)
+}
+
+fn read_mpeg(path: &Path) -> Result<MpegFile, String> {
+    let mut reader = BufReader::new(File::open(path).map_err(|e| format!("Failed to open file: {}", e))?);
+    MpegFile::read_from(&mut reader, ParseOptions::new().read_properties(false))
+        .map_err(|e| format!("Failed to read file: {}", e))
+}
+
+/// Rating as tagged. lofty leaves POPM frames out of the generic tag, so
+/// MP3s are read again for them; elsewhere it's RATING (Vorbis), rate (MP4)
+/// or IRTD (RIFF INFO).
+fn tag_rating(path: &Path, file_type: FileType, tag: &Tag) -> Option<u8> {
+    if file_type == FileType::Mpeg {
+        let file = read_mpeg(path).ok()?;
+        return file.id3v2()?.into_iter().find_map(|frame| match frame {
+            Frame::Popularimeter(popm) if popm.rating > 0 => Some(popm_stars(popm.rating) * 20),
+            _ => None,
+        });
+    }
+    tag.get_string(&ItemKey::Popularimeter).and_then(parse_rating)
+}
+
+/// Replaces the POPM frames of an MP3 with one for `rating`, keeping the play
+/// counter; a rating of 0 just removes them.
+fn write_popm(path: &Path, rating: u8) -> Result<(), String> {
+    let mut file = read_mpeg(path)?;
+    if file.id3v2().is_none() {
+        file.set_id3v2(Id3v2Tag::new());
+    }
+    let tag = file.id3v2_mut().expect("set above");
+    let counter = tag.remove(&FrameId::Valid(Cow::Borrowed("POPM")))
+        .find_map(|frame| match frame {
+            Frame::Popularimeter(popm) => Some(popm.counter),
+            _ => None,
+        })
+        .unwrap_or(0);
+    if rating > 0 {
+        tag.insert(Frame::Popularimeter(PopularimeterFrame::new(POPM_EMAIL.to_string(), popm_byte(rating_stars(rating)), counter)));
+    }
+    file.save_to_path(path, WriteOptions::default())
+        .map_err(|e| format!("Failed to save metadata: {}", e))
+}
+
+/// Lyrics as tagged (USLT for ID3, LYRICS in Vorbis comments, ©lyr for MP4).
+fn tag_lyrics(tag: &Tag) -> Option<String> {
+    tag.get_string(&ItemKey::Lyrics)
+        .filter(|lyrics| !lyrics.trim().is_empty())
+        .map(|lyrics| lyrics.to_string())
+}
+
 #[tauri::command]
 pub fn get_audio_metadata(path: &str) -> Result<AudioMetadata, String> {
-    let path = Path::new(path);
+    read_audio_metadata(Path::new(path), true)
+}
+
+/// Reads tags and properties for a single file. Skipping the album art keeps
+/// bulk callers (library scans, listings) from base64-encoding every cover.
+pub fn read_audio_metadata(path: &Path, include_art: bool) -> Result<AudioMetadata, String> {
     let tagged_file = Probe::open(path)
         .map_err(|e| e.to_string())?
         .read()
@@ -48,21 +219,41 @@ pub fn get_audio_metadata(path: &str) -> Result<AudioMetadata, String> {
     };
 
     // Get the first picture (usually album art)
-    let album_art = tag.pictures().first().map(|picture| {
-        BASE64.encode(&picture.data())
-    });
+    let album_art = if include_art {
+        tag.pictures().first().map(|picture| BASE64.encode(picture.data()))
+    } else {
+        None
+    };
 
     let properties = tagged_file.properties();
     let duration = properties.duration().as_secs_f64();
 
+    let raw_date = tag_date(tag).map(|date| date.to_string());
+    let (parsed_track, parsed_total) = tag.get_string(&ItemKey::TrackNumber)
+        .map(parse_track)
+        .unwrap_or((None, None));
+    let (parsed_disc, parsed_disc_total) = tag.get_string(&ItemKey::DiscNumber)
+        .map(parse_track)
+        .unwrap_or((None, None));
+
     Ok(AudioMetadata {
         title: tag.title().map(|s| s.to_string()),
         artist: tag.artist().map(|s| s.to_string()),
         album: tag.album().map(|s| s.to_string()),
         album_artist: tag.get_string(&ItemKey::AlbumArtist).map(|s| s.to_string()),
-        year: tag.year(),
-        track_number: tag.track(),
+        year: raw_date.as_deref().and_then(parse_year).or_else(|| tag.year()),
+        track_number: parsed_track.or_else(|| tag.track()),
+        track_total: parsed_total.or_else(|| tag.track_total()),
+        disc_number: parsed_disc.or_else(|| tag.disk()),
+        disc_total: parsed_disc_total.or_else(|| tag.disk_total()),
+        raw_date,
         genre: tag.genre().map(|s| s.to_string()),
+        composer: tag.get_string(&ItemKey::Composer).map(|s| s.to_string()),
+        comment: tag.comment().map(|s| s.to_string()),
+        compilation: tag.get_string(&ItemKey::FlagCompilation).and_then(parse_flag),
+        bpm: tag_bpm(tag),
+        rating: tag_rating(path, tagged_file.file_type(), tag),
+        lyrics: tag_lyrics(tag),
         album_art,
         duration: Some(duration),
         audio_bitrate: properties.audio_bitrate(),
@@ -70,10 +261,13 @@ pub fn get_audio_metadata(path: &str) -> Result<AudioMetadata, String> {
         sample_rate: properties.sample_rate(),
         bit_depth: properties.bit_depth().map(|b| b as u32),
         channels: properties.channels().map(|c| c as u32),
+        modified: fs::metadata(path).ok().map(|m| modified_secs(&m)),
+        added_at: None,
+        resume_position: None,
     })
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Serialize, Deserialize, Default)]
 pub struct MetadataWriteOptions {
     pub path: String,
     pub title: Option<String>,
@@ -82,23 +276,34 @@ pub struct MetadataWriteOptions {
     pub album_artist: Option<String>,
     pub album_art: Option<String>,
     pub genre: Option<String>,
+    pub composer: Option<String>,
+    pub comment: Option<String>,
+    pub compilation: Option<bool>,
+    // Zero removes the tempo
+    pub bpm: Option<f32>,
+    // 0 to 100; zero removes the rating
+    pub rating: Option<u8>,
     pub year: Option<u32>,
     pub track_number: Option<u32>,
+    pub track_total: Option<u32>,
+    pub disc_number: Option<u32>,
+    pub disc_total: Option<u32>,
+    // An empty string removes the lyrics
+    pub lyrics: Option<String>,
 }
 
-fn process_directory_metadata(dir_path: &Path, options: &MetadataWriteOptions) -> Result<(u32, u32), String> {
+/// Applies `options` to every audio file under `dir_path`. Returns the number of
+/// files updated and failed, and how many folders couldn't be read.
+fn process_directory_metadata(dir_path: &Path, options: &MetadataWriteOptions) -> Result<(u32, u32, usize), String> {
     let mut success_count = 0;
     let mut error_count = 0;
+    check_readable(dir_path).map_err(|skipped| format!("{}: {}", skipped.path, skipped.reason))?;
 
-    for entry in fs::read_dir(dir_path).map_err(|e| e.to_string())? {
-        let entry = entry.map_err(|e| e.to_string())?;
-        let path = entry.path();
-
-        if path.is_dir() {
-            let (sub_success, sub_error) = process_directory_metadata(&path, options)?;
-            success_count += sub_success;
-            error_count += sub_error;
-        } else if let Some(extension) = path.extension() {
+    let mut files = Vec::new();
+    let mut skipped = Vec::new();
+    walk_files_reporting(dir_path, true, &Exclusions::default(), &|| false, &mut |file| files.push(file.to_path_buf()), &mut skipped);
+    for path in files {
+        if let Some(extension) = path.extension() {
             if let Some(ext_str) = extension.to_str() {
                 if ["mp3", "flac", "m4a"].contains(&ext_str.to_lowercase().as_str()) {
                     // Create new options for each file with the same metadata
@@ -110,8 +315,17 @@ fn process_directory_metadata(dir_path: &Path, options: &MetadataWriteOptions) -
                         album_artist: options.album_artist.clone(),
                         album_art: options.album_art.clone(),
                         genre: options.genre.clone(),
+                        composer: options.composer.clone(),
+                        comment: options.comment.clone(),
+                        compilation: options.compilation,
+                        bpm: None, // Tempo belongs to each track
+                        rating: None,
                         year: options.year,
                         track_number: None, // Don't change track numbers for batch operations
+                        track_total: options.track_total,
+                        disc_number: options.disc_number,
+                        disc_total: options.disc_total,
+                        lyrics: None,
                     };
 
                     match write_single_file_metadata(&file_options) {
@@ -126,34 +340,148 @@ fn process_directory_metadata(dir_path: &Path, options: &MetadataWriteOptions) -
         }
     }
 
-    Ok((success_count, error_count))
+    for folder in &skipped {
+        warn!("Skipped unreadable folder {}: {}", folder.path, folder.reason);
+    }
+    Ok((success_count, error_count, skipped.len()))
 }
 
 #[tauri::command]
-pub fn write_audio_metadata(options: MetadataWriteOptions) -> Result<MetadataWriteResult, String> {
+pub fn write_audio_metadata(
+    app: AppHandle,
+    options: MetadataWriteOptions,
+    relocate_playing: Option<bool>,
+) -> Result<MetadataWriteResult, FileOpError> {
+    let path = Path::new(&options.path);
+
+    // Embedding artwork rewrites the whole file, which the open decoder can't survive
+    if options.album_art.is_some() {
+        return guard_playing_track(&app, path, path, relocate_playing.unwrap_or(false), || write_metadata(&options));
+    }
+    Ok(write_metadata(&options)?)
+}
+
+pub fn write_metadata(options: &MetadataWriteOptions) -> Result<MetadataWriteResult, String> {
     let path = Path::new(&options.path);
     
     // If it's a directory, recursively process all audio files
     if path.is_dir() {
-        let (success_count, error_count) = process_directory_metadata(path, &options)?;
+        let (success_count, error_count, skipped_count) = process_directory_metadata(path, options)?;
+        let skipped_note = if skipped_count > 0 {
+            format!(" {} unreadable folders were skipped.", skipped_count)
+        } else {
+            String::new()
+        };
 
         if error_count == 0 {
             Ok(MetadataWriteResult {
                 success: true,
-                message: format!("Successfully updated metadata for {} files", success_count),
+                message: format!("Successfully updated metadata for {} files.{}", success_count, skipped_note),
             })
         } else {
             Ok(MetadataWriteResult {
                 success: false,
                 message: format!(
-                    "Updated {} files, failed to update {} files. Check logs for details.",
-                    success_count, error_count
+                    "Updated {} files, failed to update {} files. Check logs for details.{}",
+                    success_count, error_count, skipped_note
                 ),
             })
         }
     } else {
         // Single file case
-        write_single_file_metadata(&options)
+        write_single_file_metadata(options)
+    }
+}
+
+/// The primary tag, or the first one, or a new primary tag if the file has none.
+fn writable_tag(tagged_file: &mut TaggedFile) -> Result<&mut Tag, String> {
+    if tagged_file.primary_tag().is_none() && tagged_file.first_tag().is_none() {
+        let tag_type = tagged_file.primary_tag_type();
+        tagged_file.insert_tag(Tag::new(tag_type));
+    }
+    let tag = if tagged_file.primary_tag().is_some() {
+        tagged_file.primary_tag_mut()
+    } else {
+        tagged_file.first_tag_mut()
+    };
+    tag.ok_or_else(|| "Failed to create new tag".to_string())
+}
+
+/// Saves the tags of `tagged_file` back to `path`.
+fn save_tags(tagged_file: &TaggedFile, path: &Path) -> Result<(), String> {
+    let compilation = tagged_file.primary_tag()
+        .and_then(|tag| tag.get_string(&ItemKey::FlagCompilation))
+        .and_then(parse_flag);
+    tagged_file.save_to_path(path, WriteOptions::default())
+        .map_err(|e| format!("Failed to save metadata: {}", e))?;
+    if tagged_file.file_type() == FileType::Mp4 {
+        if let Some(compilation) = compilation {
+            rewrite_cpil(path, compilation)?;
+        }
+    }
+    Ok(())
+}
+
+/// lofty writes the cpil flag as a four-byte integer but reads only its first
+/// byte, so a compilation would read back as not one; write it as the single
+/// byte iTunes uses.
+fn rewrite_cpil(path: &Path, compilation: bool) -> Result<(), String> {
+    let mut reader = BufReader::new(File::open(path).map_err(|e| format!("Failed to open file: {}", e))?);
+    let mut file = Mp4File::read_from(&mut reader, ParseOptions::new().read_properties(false))
+        .map_err(|e| format!("Failed to read file: {}", e))?;
+    drop(reader);
+    let Some(ilst) = file.ilst_mut() else { return Ok(()) };
+    // 21 is the data type for integers
+    ilst.replace_atom(Atom::new(AtomIdent::Fourcc(*b"cpil"), AtomData::Unknown { code: 21, data: vec![compilation as u8] }));
+    file.save_to_path(path, WriteOptions::default())
+        .map_err(|e| format!("Failed to save metadata: {}", e))
+}
+
+/// Writes ReplayGain values as text tags, replacing any already there. Values
+/// left as `None` are not touched.
+pub fn write_replaygain_tags(path: &Path, values: &ReplayGainTags) -> Result<(), String> {
+    let mut tagged_file = Probe::open(path)
+        .map_err(|e| format!("Failed to open file: {}", e))?
+        .read()
+        .map_err(|e| format!("Failed to read file: {}", e))?;
+    let tag = writable_tag(&mut tagged_file)?;
+    let fields = [
+        (ItemKey::ReplayGainTrackGain, values.track_gain.map(|gain| format!("{:.2} dB", gain))),
+        (ItemKey::ReplayGainTrackPeak, values.track_peak.map(|peak| format!("{:.6}", peak))),
+        (ItemKey::ReplayGainAlbumGain, values.album_gain.map(|gain| format!("{:.2} dB", gain))),
+        (ItemKey::ReplayGainAlbumPeak, values.album_peak.map(|peak| format!("{:.6}", peak))),
+    ];
+    for (key, value) in fields {
+        if let Some(value) = value {
+            tag.insert_text(key, value);
+        }
+    }
+    save_tags(&tagged_file, path)
+}
+
+/// Sets a track or disc number and its total, keeping whichever half isn't
+/// given. Vorbis comments often hold both as "3/12" in the number field; that
+/// is split first, so changing one half doesn't lose or contradict the other.
+/// lofty joins them again for formats that store a pair (ID3 TRCK/TPOS, MP4).
+fn set_number_and_total(tag: &mut Tag, number_key: ItemKey, total_key: ItemKey, number: Option<u32>, total: Option<u32>) {
+    if number.is_none() && total.is_none() {
+        return;
+    }
+    let (tagged_number, tagged_total) = tag.get_string(&number_key)
+        .map(parse_track)
+        .unwrap_or((None, None));
+    let total = total
+        .or(tagged_total)
+        .or_else(|| tag.get_string(&total_key).and_then(|total| total.trim().parse().ok()));
+    let number = number.or(tagged_number);
+
+    tag.remove_key(&number_key);
+    tag.remove_key(&total_key);
+    if let Some(number) = number {
+        tag.insert_text(number_key, number.to_string());
+    }
+    if let Some(total) = total {
+        tag.insert_text(total_key, total.to_string());
     }
 }
 
@@ -166,20 +494,8 @@ fn write_single_file_metadata(options: &MetadataWriteOptions) -> Result<Metadata
         .read()
         .map_err(|e| format!("Failed to read file: {}", e))?;
 
-    // Get the primary tag or create one if it doesn't exist
-    let tag = match tagged_file.primary_tag_mut() {
-        Some(primary_tag) => primary_tag,
-        None => {
-            if let Some(first_tag) = tagged_file.first_tag_mut() {
-                first_tag
-            } else {
-                let tag_type = tagged_file.primary_tag_type();
-                tagged_file.insert_tag(Tag::new(tag_type));
-                tagged_file.primary_tag_mut()
-                    .ok_or_else(|| "Failed to create new tag".to_string())?
-            }
-        },
-    };
+    let is_mpeg = tagged_file.file_type() == FileType::Mpeg;
+    let tag = writable_tag(&mut tagged_file)?;
 
     // Only update fields that were provided in the options
     if let Some(artist) = &options.artist {
@@ -194,19 +510,64 @@ fn write_single_file_metadata(options: &MetadataWriteOptions) -> Result<Metadata
     if let Some(genre) = &options.genre {
         tag.set_genre(genre.to_string());
     }
+    if let Some(composer) = &options.composer {
+        tag.insert_text(ItemKey::Composer, composer.to_string());
+    }
+    if let Some(comment) = &options.comment {
+        tag.set_comment(comment.to_string());
+    }
+    if let Some(compilation) = options.compilation {
+        tag.insert_text(ItemKey::FlagCompilation, if compilation { "1" } else { "0" }.to_string());
+    }
+    if let Some(bpm) = options.bpm {
+        tag.remove_key(&ItemKey::Bpm);
+        tag.remove_key(&ItemKey::IntegerBpm);
+        if bpm > 0.0 {
+            // Each format keeps whichever of the two it has a field for
+            tag.insert_text(ItemKey::Bpm, format!("{}", (bpm * 10.0).round() / 10.0));
+            tag.insert_text(ItemKey::IntegerBpm, format!("{}", bpm.round() as u32));
+        }
+    }
+    let rating = options.rating.map(|rating| rating.min(100));
+    if let Some(rating) = rating.filter(|_| !is_mpeg) {
+        tag.remove_key(&ItemKey::Popularimeter);
+        if rating > 0 {
+            tag.insert_text(ItemKey::Popularimeter, rating.to_string());
+        }
+    }
     if let Some(year) = options.year {
-        tag.set_year(year);
+        // A full date with the same year already says more than the year alone
+        let date = tag_date(tag).map(|date| date.to_string());
+        if date.as_deref().and_then(parse_year) != Some(year) {
+            // lofty swaps the leading year of a date and keeps the rest; any
+            // other shape ("℗ 2003") would come out mangled, so replace it
+            let leading_year = date.as_deref()
+                .and_then(|date| date.get(..4))
+                .is_some_and(|prefix| prefix.bytes().all(|b| b.is_ascii_digit()));
+            if !leading_year {
+                tag.remove_key(&ItemKey::RecordingDate);
+            }
+            tag.set_year(year);
+        }
     }
     if let Some(title) = &options.title {
         tag.set_title(title.to_string());
     }
-    if let Some(track) = options.track_number {
-        tag.set_track(track);
+    set_number_and_total(tag, ItemKey::TrackNumber, ItemKey::TrackTotal, options.track_number, options.track_total);
+    set_number_and_total(tag, ItemKey::DiscNumber, ItemKey::DiscTotal, options.disc_number, options.disc_total);
+    if let Some(lyrics) = &options.lyrics {
+        // Dropped rather than left behind as an empty USLT frame or LYRICS field
+        tag.remove_key(&ItemKey::Lyrics);
+        if !lyrics.is_empty() {
+            tag.insert_text(ItemKey::Lyrics, lyrics.to_string());
+        }
     }
 
     // Save the changes
-    tagged_file.save_to_path(path, WriteOptions::default())
-        .map_err(|e| format!("Failed to save metadata: {}", e))?;
+    save_tags(&tagged_file, path)?;
+    if let Some(rating) = rating.filter(|_| is_mpeg) {
+        write_popm(path, rating)?;
+    }
 
     Ok(MetadataWriteResult {
         success: true,
@@ -214,50 +575,93 @@ fn write_single_file_metadata(options: &MetadataWriteOptions) -> Result<Metadata
     })
 }
 
+/// Rates one file from 0 to 5 stars, 0 clearing its rating, and returns the
+/// rating as `AudioMetadata` reports it.
 #[tauri::command]
-pub async fn set_album_art(path: &str, album_art: &str) -> Result<(), String> {
-    let path = Path::new(path);
-    let mut tagged_file = Probe::open(path)
+pub fn set_rating(path: &str, stars: u8) -> Result<u8, String> {
+    if stars > 5 {
+        return Err("A rating is 0 to 5 stars".to_string());
+    }
+    if !Path::new(path).is_file() {
+        return Err(format!("{} is not a file", path));
+    }
+    let rating = stars * 20;
+    write_single_file_metadata(&MetadataWriteOptions { path: path.to_string(), rating: Some(rating), ..MetadataWriteOptions::default() })?;
+    Ok(rating)
+}
+
+/// An embedded picture as `get_pictures` lists it.
+#[derive(Debug, Serialize, Clone)]
+pub struct PictureInfo {
+    // ID3/FLAC picture type: 3 is the front cover, 4 the back, 8 an artist photo
+    pub pic_type: u8,
+    pub mime: String,
+    // None when the image header can't be read
+    pub width: Option<u32>,
+    pub height: Option<u32>,
+    pub size_bytes: usize,
+    pub data_base64: String,
+}
+
+fn read_tagged_file(path: &Path) -> Result<TaggedFile, String> {
+    Probe::open(path)
         .map_err(|e| e.to_string())?
         .read()
-        .map_err(|e| e.to_string())?;
-
-    // Get the primary tag or create one if it doesn't exist
-    let tag = match tagged_file.primary_tag_mut() {
-        Some(primary_tag) => primary_tag,
-        None => {
-            if let Some(first_tag) = tagged_file.first_tag_mut() {
-                first_tag
-            } else {
-                let tag_type = tagged_file.primary_tag_type();
-                tagged_file.insert_tag(Tag::new(tag_type));
-                tagged_file.primary_tag_mut()
-                    .ok_or_else(|| "Failed to create new tag".to_string())?
-            }
-        },
-    };
+        .map_err(|e| e.to_string())
+}
 
+/// Sets `album_art` as the picture of `pic_type` (the front cover when not
+/// given), replacing any other picture of that type. MP4 stores no picture
+/// types, so there a front cover replaces the first picture and anything
+/// else is added after it. The image must be a JPEG, PNG, WebP or GIF; one
+/// over `max_dimension` pixels or `max_bytes` (defaulting to the config's
+/// `embedded_art` limits, 0 for none) is downscaled and re-encoded as JPEG.
+/// MP4 can't hold WebP or GIF, so those are re-encoded as JPEG there too.
+#[tauri::command]
+pub async fn set_album_art(
+    path: &str,
+    album_art: &str,
+    pic_type: Option<u8>,
+    max_dimension: Option<u32>,
+    max_bytes: Option<u64>,
+) -> Result<EmbeddedArt, String> {
     // Decode base64 album art
     let image_data = BASE64.decode(album_art)
         .map_err(|e| format!("Failed to decode base64 image: {}", e))?;
+    let path = Path::new(path);
+    let mut tagged_file = read_tagged_file(path)?;
+    let is_mp4 = tagged_file.file_type() == FileType::Mp4;
+
+    let limits = load_player_config().embedded_art;
+    let (image_data, art) = fit_for_embedding(
+        image_data,
+        max_dimension.unwrap_or(limits.max_dimension),
+        max_bytes.unwrap_or(limits.max_bytes),
+        is_mp4,
+    )?;
+    let pic_type = pic_type.map_or(PictureType::CoverFront, PictureType::from_u8);
+
+    let tag = writable_tag(&mut tagged_file)?;
 
     // Create a new picture with the image data
     let picture = Picture::new_unchecked(
-        PictureType::CoverFront,
-        Some(MimeType::Jpeg),
+        pic_type,
+        Some(MimeType::from_str(&art.mime_type)),
         None,
         image_data,
     );
 
-    // Remove existing pictures and add the new one
-    // tag.remove_picture();
-    tag.push_picture(picture);
+    if is_mp4 && pic_type == PictureType::CoverFront && !tag.pictures().is_empty() {
+        tag.set_picture(0, picture);
+    } else {
+        tag.remove_picture_type(pic_type);
+        tag.push_picture(picture);
+    }
 
     // Save the changes
-    tagged_file.save_to_path(path, WriteOptions::default())
-        .map_err(|e| format!("Failed to save metadata: {}", e))?;
+    save_tags(&tagged_file, path)?;
 
-    Ok(())
+    Ok(art)
 }
 
 #[tauri::command]
@@ -279,47 +683,182 @@ pub fn get_album_art(path: &str) -> Result<Option<String>, String> {
     }))
 }
 
+/// Every picture embedded in `path`, in tag order.
+#[tauri::command]
+pub fn get_pictures(path: &str) -> Result<Vec<PictureInfo>, String> {
+    let tagged_file = read_tagged_file(Path::new(path))?;
+    let Some(tag) = tagged_file.primary_tag().or_else(|| tagged_file.first_tag()) else {
+        return Ok(Vec::new());
+    };
+    Ok(tag.pictures().iter().map(|picture| {
+        let size = dimensions(picture.data()).ok();
+        PictureInfo {
+            pic_type: picture.pic_type().as_u8(),
+            mime: image_kind(picture).1,
+            width: size.map(|(width, _)| width),
+            height: size.map(|(_, height)| height),
+            size_bytes: picture.data().len(),
+            data_base64: BASE64.encode(picture.data()),
+        }
+    }).collect())
+}
+
+/// Removes the pictures of `pic_type` (every picture in an MP4 reads back as
+/// type 0, "other") and returns how many there were.
+#[tauri::command]
+pub fn remove_picture(path: &str, pic_type: u8) -> Result<usize, String> {
+    remove_pictures(Path::new(path), Some(PictureType::from_u8(pic_type)))
+}
+
+/// Removes every embedded picture and returns how many there were.
+#[tauri::command]
+pub fn remove_all_pictures(path: &str) -> Result<usize, String> {
+    remove_pictures(Path::new(path), None)
+}
+
+fn remove_pictures(path: &Path, pic_type: Option<PictureType>) -> Result<usize, String> {
+    let mut tagged_file = read_tagged_file(path)?;
+    if tagged_file.primary_tag().is_none() && tagged_file.first_tag().is_none() {
+        return Ok(0);
+    }
+    let tag = writable_tag(&mut tagged_file)?;
+    let before = tag.pictures().len();
+    match pic_type {
+        Some(pic_type) => tag.remove_picture_type(pic_type),
+        None => (0..before).rev().for_each(|index| { tag.remove_picture(index); }),
+    }
+    let removed = before - tag.pictures().len();
+    if removed > 0 {
+        save_tags(&tagged_file, path)?;
+    }
+    Ok(removed)
+}
+
+/// Just the lyrics of a file, without the rest of its metadata or its cover.
+#[tauri::command]
+pub fn get_lyrics(path: &str) -> Result<Option<String>, String> {
+    let tagged_file = Probe::open(path)
+        .map_err(|e| e.to_string())?
+        .read()
+        .map_err(|e| e.to_string())?;
+    Ok(tagged_file.primary_tag()
+        .or_else(|| tagged_file.first_tag())
+        .and_then(tag_lyrics))
+}
+
 #[derive(Debug, Serialize)]
 pub struct ArtistInfo {
     pub name: String,
     pub track_count: u32,
 }
 
+/// How much cover art `get_metadata_for_directory` sends with each track.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Default)]
+pub enum ArtMode {
+    None,
+    // A small cached JPEG, falling back to a cover image in the folder
+    #[default]
+    Thumbnail,
+    // The embedded picture as stored
+    Full,
+}
+
+/// Metadata for a directory listing, with art as `art_mode` asks.
+fn listing_metadata(path: &Path, art_mode: ArtMode) -> Result<AudioMetadata, String> {
+    let mut metadata = read_audio_metadata(path, art_mode == ArtMode::Full)?;
+    if art_mode == ArtMode::Thumbnail {
+        metadata.album_art = thumbnail_base64(path, LISTING_THUMBNAIL_PX).unwrap_or_else(|e| {
+            warn!("Error making a thumbnail for {:?}: {}", path, e);
+            None
+        });
+    }
+    Ok(metadata)
+}
+
 #[derive(Debug, Serialize, Deserialize)]
 pub enum SortOption {
     FileName,
     Title,
     TrackNumber,
+    DateAdded,
+    DateModified,
+    // Disc, then track within the disc
+    DiscAndTrack,
+    // Slowest first; untagged tracks last
+    Bpm,
+    // Best rated first; unrated tracks last
+    Rating,
 }
 
 #[tauri::command]
-pub fn get_metadata_for_directory(path: &str, sort_by: Option<SortOption>) -> Result<Vec<AudioMetadata>, String> {
-    let path = Path::new(path);
+pub async fn get_metadata_for_directory(
+    app: AppHandle,
+    path: String,
+    sort_by: Option<SortOption>,
+    recursive: Option<bool>,
+    request_id: Option<String>,
+    include_art: Option<ArtMode>,
+    descending: Option<bool>,
+) -> Result<Vec<AudioMetadata>, RequestError> {
+    let request = begin_request("get_metadata_for_directory", &path, request_id);
+    let listing = DirectoryListing {
+        sort_by,
+        descending: descending.unwrap_or(false),
+        recursive: recursive.unwrap_or(false),
+        art_mode: include_art.unwrap_or_default(),
+    };
+    let mut skipped = Vec::new();
+    let result = directory_metadata(Path::new(&path), &listing, added_at, &request, &mut skipped);
+    emit_skipped(&app, "get_metadata_for_directory", Path::new(&path), skipped);
+    result
+}
+
+/// Options for `directory_metadata`, as `get_metadata_for_directory` takes them.
+struct DirectoryListing {
+    sort_by: Option<SortOption>,
+    descending: bool,
+    recursive: bool,
+    art_mode: ArtMode,
+}
+
+/// Metadata for `path` (or every audio file in it), sorted as `listing` asks,
+/// with date-added from `added_at`. Unreadable entries met along the way are
+/// added to `skipped`.
+fn directory_metadata(
+    path: &Path,
+    listing: &DirectoryListing,
+    added_at: impl Fn(&str) -> Option<u64>,
+    request: &RequestToken,
+    skipped: &mut Vec<SkippedPath>,
+) -> Result<Vec<AudioMetadata>, RequestError> {
+    let art_mode = listing.art_mode;
     let mut metadata_list = Vec::new();
     
     // If it's a single file, just get its metadata
     if path.is_file() {
-        if let Ok(metadata) = get_audio_metadata(path.to_str().unwrap_or_default()) {
+        if let Ok(metadata) = listing_metadata(path, art_mode) {
             metadata_list.push(metadata);
         }
         return Ok(metadata_list);
     }
     
     // Otherwise process directory
-    let files = fs::read_dir(path).map_err(|e| e.to_string())?;
+    check_readable(path)?;
+    let mut files = Vec::new();
+    walk_files_reporting(path, listing.recursive, &Exclusions::load(), &|| request.is_cancelled(), &mut |file| files.push(file.to_path_buf()), skipped);
+    let mut listed_paths = Vec::new();
     
-    for entry in files {
-        let entry = entry.map_err(|e| e.to_string())?;
-        let path = entry.path();
-        
+    for path in files {
+        request.check()?;
         // Check if the file has an audio extension
         if let Some(extension) = path.extension() {
             if let Some(ext_str) = extension.to_str() {
                 if ["mp3", "flac", "m4a", "wav", "ogg"].contains(&ext_str.to_lowercase().as_str()) {
                     // Try to get metadata for the audio file
-                    match get_audio_metadata(path.to_str().unwrap_or_default()) {
+                    match listing_metadata(&path, art_mode) {
                         Ok(metadata) => {
                             metadata_list.push(metadata);
+                            listed_paths.push(path.to_string_lossy().to_string());
                         },
                         Err(e) => {
                             eprintln!("Error getting metadata for {:?}: {}", path, e);
@@ -330,10 +869,16 @@ pub fn get_metadata_for_directory(path: &str, sort_by: Option<SortOption>) -> Re
         }
     }
 
+    let resume = resume_positions(listed_paths.iter().map(|p| p.as_str()));
+    for ((metadata, path), resume_position) in metadata_list.iter_mut().zip(&listed_paths).zip(resume) {
+        metadata.added_at = added_at(path);
+        metadata.resume_position = resume_position;
+    }
+
     // Sort the metadata list based on the sort option
-    if let Some(sort_option) = sort_by {
+    if let Some(sort_option) = &listing.sort_by {
         metadata_list.sort_by(|a, b| {
-            match sort_option {
+            let ordering = match sort_option {
                 SortOption::FileName => {
                     Path::new(&a.title.as_ref().unwrap_or(&String::new()))
                         .file_name()
@@ -352,7 +897,25 @@ pub fn get_metadata_for_directory(path: &str, sort_by: Option<SortOption>) -> Re
                     a.track_number.unwrap_or(u32::MAX)
                         .cmp(&b.track_number.unwrap_or(u32::MAX))
                 },
-            }
+                // Files the index hasn't seen yet count as added when last modified
+                SortOption::DateAdded => {
+                    a.added_at.or(a.modified).unwrap_or(0)
+                        .cmp(&b.added_at.or(b.modified).unwrap_or(0))
+                },
+                SortOption::DateModified => {
+                    a.modified.unwrap_or(0).cmp(&b.modified.unwrap_or(0))
+                },
+                // Untagged discs count as the first, as on most single-disc albums
+                SortOption::DiscAndTrack => {
+                    (a.disc_number.unwrap_or(1), a.track_number.unwrap_or(u32::MAX))
+                        .cmp(&(b.disc_number.unwrap_or(1), b.track_number.unwrap_or(u32::MAX)))
+                },
+                SortOption::Bpm => {
+                    a.bpm.unwrap_or(f32::MAX).total_cmp(&b.bpm.unwrap_or(f32::MAX))
+                },
+                SortOption::Rating => b.rating.cmp(&a.rating),
+            };
+            if listing.descending { ordering.reverse() } else { ordering }
         });
     }
     
@@ -361,40 +924,37 @@ pub fn get_metadata_for_directory(path: &str, sort_by: Option<SortOption>) -> Re
 }
 
 #[tauri::command]
-pub fn get_artists_in_directory(path: &str) -> Result<Vec<ArtistInfo>, String> {
-    let mut artist_counts: std::collections::HashMap<String, u32> = std::collections::HashMap::new();
-    
-    fn process_directory(dir_path: &Path, artist_counts: &mut std::collections::HashMap<String, u32>) -> Result<(), String> {
-        for entry in fs::read_dir(dir_path).map_err(|e| e.to_string())? {
-            let entry = entry.map_err(|e| e.to_string())?;
-            let path = entry.path();
-            
-            if path.is_dir() {
-                process_directory(&path, artist_counts)?;
-            } else if let Some(extension) = path.extension() {
-                if let Some(ext_str) = extension.to_str() {
-                    if ["mp3", "flac", "m4a", "wav", "ogg"].contains(&ext_str.to_lowercase().as_str()) {
-                        if let Ok(tagged_file) = Probe::open(&path).and_then(|p| p.read()) {
-                            if let Some(tag) = tagged_file.primary_tag().or_else(|| tagged_file.first_tag()) {
-                                if let Some(artist) = tag.artist() {
-                                    let count = artist_counts.entry(artist.to_string()).or_insert(0);
-                                    *count += 1;
-                                }
+pub fn get_artists_in_directory(app: AppHandle, path: &str) -> Result<Vec<ArtistInfo>, RequestError> {
+    let root = Path::new(path);
+    check_readable(root)?;
+
+    // Keyed by folded name, so "Björk" and "BJÖRK" count as one artist under the first spelling seen
+    let mut artist_counts: std::collections::HashMap<String, (String, u32)> = std::collections::HashMap::new();
+    let mut skipped = Vec::new();
+    walk_files_reporting(root, true, &Exclusions::load(), &|| false, &mut |path| {
+        if let Some(extension) = path.extension() {
+            if let Some(ext_str) = extension.to_str() {
+                if ["mp3", "flac", "m4a", "wav", "ogg"].contains(&ext_str.to_lowercase().as_str()) {
+                    if let Ok(tagged_file) = Probe::open(path).and_then(|p| p.read()) {
+                        if let Some(tag) = tagged_file.primary_tag().or_else(|| tagged_file.first_tag()) {
+                            if let Some(artist) = tag.artist() {
+                                let entry = artist_counts.entry(fold(&artist))
+                                    .or_insert_with(|| (artist.to_string(), 0));
+                                entry.1 += 1;
                             }
                         }
                     }
                 }
             }
         }
-        Ok(())
-    }
+    }, &mut skipped);
+    emit_skipped(&app, "get_artists_in_directory", root, skipped);
     
-    process_directory(Path::new(path), &mut artist_counts)?;
-    
-    let artists: Vec<ArtistInfo> = artist_counts
-        .into_iter()
+    let mut artists: Vec<ArtistInfo> = artist_counts
+        .into_values()
         .map(|(name, track_count)| ArtistInfo { name, track_count })
         .collect();
+    artists.sort_by(|a, b| collate(&a.name, &b.name));
     
     Ok(artists)
 }
@@ -420,4 +980,424 @@ pub fn combine_folders(paths: Vec<String>, new_folder_name: String, parent_path:
     }
 
     Ok(())
-} 
\ No newline at end of file
+} 
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::{build_dated_library, id3v2_tag_with, jpeg_bytes, mp3_frames, temp_dir, write_fixture, Format, FixtureTags, FIXTURE_SECS};
+
+    fn write_options(path: &Path) -> MetadataWriteOptions {
+        MetadataWriteOptions {
+            path: path.to_string_lossy().to_string(),
+            title: None,
+            artist: None,
+            album: None,
+            album_artist: None,
+            album_art: None,
+            genre: None,
+            composer: None,
+            comment: None,
+            compilation: None,
+            bpm: None,
+            rating: None,
+            year: None,
+            track_number: None,
+            track_total: None,
+            disc_number: None,
+            disc_total: None,
+            lyrics: None,
+        }
+    }
+
+    fn flac_dated(dir: &Path, date: &str) -> PathBuf {
+        let path = dir.join(format!("{}.flac", date.len()));
+        write_fixture(&path, Format::Flac, &FixtureTags {
+            date: Some(date.to_string()),
+            ..FixtureTags::titled("Dated")
+        });
+        path
+    }
+
+    #[test]
+    fn parses_messy_years() {
+        assert_eq!(parse_year("1999"), Some(1999));
+        assert_eq!(parse_year("1999-04-12"), Some(1999));
+        assert_eq!(parse_year("1999-04-12T10:00:00"), Some(1999));
+        assert_eq!(parse_year("12/04/1999"), Some(1999));
+        assert_eq!(parse_year("℗ 2003"), Some(2003));
+        assert_eq!(parse_year("(P) 2003 Label"), Some(2003));
+        assert_eq!(parse_year("04/99"), None);
+        assert_eq!(parse_year("unknown"), None);
+    }
+
+    #[test]
+    fn parses_track_and_total() {
+        assert_eq!(parse_track("3"), (Some(3), None));
+        assert_eq!(parse_track("03"), (Some(3), None));
+        assert_eq!(parse_track("3/12"), (Some(3), Some(12)));
+        assert_eq!(parse_track(" 3 / 12 "), (Some(3), Some(12)));
+        assert_eq!(parse_track("3 of 12"), (Some(3), Some(12)));
+        assert_eq!(parse_track("/12"), (None, Some(12)));
+        assert_eq!(parse_track("A1"), (None, None));
+    }
+
+    #[test]
+    fn reads_messy_fixture_tags() {
+        let dir = temp_dir();
+        let cases = [("1999-04-12", 1999), ("℗ 2003", 2003), ("2010", 2010)];
+        for (date, year) in cases {
+            let metadata = read_audio_metadata(&flac_dated(dir.path(), date), false).unwrap();
+            assert_eq!(metadata.year, Some(year), "{}", date);
+            assert_eq!(metadata.raw_date.as_deref(), Some(date));
+        }
+
+        for format in [Format::Flac, Format::Mp3] {
+            let path = dir.path().join(format!("numbered.{}", format.extension()));
+            write_fixture(&path, format, &FixtureTags {
+                track: Some("3/12".into()),
+                ..FixtureTags::titled("Numbered")
+            });
+            let metadata = read_audio_metadata(&path, false).unwrap();
+            assert_eq!(metadata.track_number, Some(3), "{:?}", format);
+            assert_eq!(metadata.track_total, Some(12), "{:?}", format);
+        }
+    }
+
+    #[test]
+    fn writing_the_same_year_keeps_the_full_date() {
+        let dir = temp_dir();
+        let path = flac_dated(dir.path(), "1999-04-12");
+        write_metadata(&MetadataWriteOptions { year: Some(1999), ..write_options(&path) }).unwrap();
+        assert_eq!(read_audio_metadata(&path, false).unwrap().raw_date.as_deref(), Some("1999-04-12"));
+
+        write_metadata(&MetadataWriteOptions { year: Some(2001), ..write_options(&path) }).unwrap();
+        let metadata = read_audio_metadata(&path, false).unwrap();
+        assert_eq!(metadata.year, Some(2001));
+        assert_eq!(metadata.raw_date.as_deref(), Some("2001-04-12"));
+
+        let path = flac_dated(dir.path(), "℗ 2003");
+        write_metadata(&MetadataWriteOptions { year: Some(2005), ..write_options(&path) }).unwrap();
+        assert_eq!(read_audio_metadata(&path, false).unwrap().year, Some(2005));
+    }
+
+    #[test]
+    fn metadata_round_trips_per_format() {
+        let dir = temp_dir();
+        for format in [Format::Wav, Format::Flac, Format::Mp3] {
+            let path = dir.path().join(format!("round_trip.{}", format.extension()));
+            write_fixture(&path, format, &FixtureTags { with_art: true, ..FixtureTags::titled("Before") });
+
+            write_metadata(&MetadataWriteOptions {
+                title: Some("After".into()),
+                artist: Some("Artist".into()),
+                album: Some("Album".into()),
+                album_artist: Some("Album Artist".into()),
+                genre: Some("Ambient".into()),
+                year: Some(2004),
+                track_number: Some(7),
+                track_total: Some(9),
+                disc_number: Some(2),
+                disc_total: Some(3),
+                ..write_options(&path)
+            }).unwrap();
+
+            let metadata = read_audio_metadata(&path, true).unwrap();
+            assert_eq!(metadata.title.as_deref(), Some("After"), "{:?}", format);
+            assert_eq!(metadata.artist.as_deref(), Some("Artist"), "{:?}", format);
+            assert_eq!(metadata.album.as_deref(), Some("Album"), "{:?}", format);
+            assert_eq!(metadata.album_artist.as_deref(), Some("Album Artist"), "{:?}", format);
+            assert_eq!(metadata.genre.as_deref(), Some("Ambient"), "{:?}", format);
+            assert_eq!(metadata.year, Some(2004), "{:?}", format);
+            assert_eq!(metadata.track_number, Some(7), "{:?}", format);
+            assert_eq!(metadata.track_total, Some(9), "{:?}", format);
+            assert_eq!((metadata.disc_number, metadata.disc_total), (Some(2), Some(3)), "{:?}", format);
+            assert!(metadata.album_art.is_some(), "{:?} lost its cover", format);
+            assert!((metadata.duration.unwrap() - 2.0).abs() < 0.1, "{:?}", format);
+        }
+    }
+
+    #[test]
+    fn composer_comment_and_compilation_round_trip() {
+        let dir = temp_dir();
+        for format in [Format::Mp3, Format::Flac, Format::M4a] {
+            let path = dir.path().join(format!("various.{}", format.extension()));
+            write_fixture(&path, format, &FixtureTags::titled("Various"));
+            assert_eq!(read_audio_metadata(&path, false).unwrap().compilation, None, "{:?}", format);
+
+            write_metadata(&MetadataWriteOptions {
+                composer: Some("Arvo Pärt".into()),
+                comment: Some("Cue at 1:30".into()),
+                compilation: Some(true),
+                ..write_options(&path)
+            }).unwrap();
+            let metadata = read_audio_metadata(&path, false).unwrap();
+            assert_eq!(metadata.composer.as_deref(), Some("Arvo Pärt"), "{:?}", format);
+            assert_eq!(metadata.comment.as_deref(), Some("Cue at 1:30"), "{:?}", format);
+            assert_eq!(metadata.compilation, Some(true), "{:?}", format);
+
+            // Saving other fields keeps the flag
+            write_metadata(&MetadataWriteOptions { genre: Some("Choral".into()), ..write_options(&path) }).unwrap();
+            assert_eq!(read_audio_metadata(&path, false).unwrap().compilation, Some(true), "{:?}", format);
+
+            write_metadata(&MetadataWriteOptions { compilation: Some(false), ..write_options(&path) }).unwrap();
+            let metadata = read_audio_metadata(&path, false).unwrap();
+            assert_eq!(metadata.compilation, Some(false), "{:?}", format);
+            assert_eq!(metadata.composer.as_deref(), Some("Arvo Pärt"), "{:?}", format);
+        }
+    }
+
+    #[test]
+    fn directory_writes_carry_composer_comment_and_compilation() {
+        let dir = temp_dir();
+        for format in [Format::Mp3, Format::Flac, Format::M4a] {
+            write_fixture(&dir.path().join(format!("track.{}", format.extension())), format, &FixtureTags::titled("Track"));
+        }
+        let result = write_metadata(&MetadataWriteOptions {
+            composer: Some("Composer".into()),
+            comment: Some("Comment".into()),
+            compilation: Some(true),
+            ..write_options(dir.path())
+        }).unwrap();
+        assert!(result.success, "{}", result.message);
+        for format in [Format::Mp3, Format::Flac, Format::M4a] {
+            let metadata = read_audio_metadata(&dir.path().join(format!("track.{}", format.extension())), false).unwrap();
+            assert_eq!(metadata.title.as_deref(), Some("Track"));
+            assert_eq!(metadata.composer.as_deref(), Some("Composer"), "{:?}", format);
+            assert_eq!(metadata.comment.as_deref(), Some("Comment"), "{:?}", format);
+            assert_eq!(metadata.compilation, Some(true), "{:?}", format);
+        }
+    }
+
+    #[test]
+    fn bpm_round_trips_and_clears() {
+        let dir = temp_dir();
+        // ID3 only has a whole-number field; MP4 keeps the exact value in a freeform atom too
+        for (format, expected) in [(Format::Flac, 127.5), (Format::Mp3, 128.0), (Format::M4a, 127.5)] {
+            let path = dir.path().join(format!("tempo.{}", format.extension()));
+            write_fixture(&path, format, &FixtureTags::titled("Tempo"));
+            write_metadata(&MetadataWriteOptions { bpm: Some(127.5), ..write_options(&path) }).unwrap();
+            assert_eq!(read_audio_metadata(&path, false).unwrap().bpm, Some(expected), "{:?}", format);
+
+            write_metadata(&MetadataWriteOptions { bpm: Some(0.0), ..write_options(&path) }).unwrap();
+            assert_eq!(read_audio_metadata(&path, false).unwrap().bpm, None, "{:?}", format);
+        }
+    }
+
+    #[test]
+    fn ratings_read_from_any_scale() {
+        assert_eq!(parse_rating("80"), Some(80));
+        assert_eq!(parse_rating("4"), Some(80));
+        assert_eq!(parse_rating("0.6"), Some(60));
+        assert_eq!(parse_rating("0"), None);
+        assert_eq!(parse_rating("five"), None);
+        assert_eq!([1, 64, 128, 196, 255].map(popm_stars), [1, 2, 3, 4, 5]);
+    }
+
+    #[test]
+    fn popm_ratings_from_other_players_read_back() {
+        let dir = temp_dir();
+        let path = dir.path().join("rated.mp3");
+        let popm = PopularimeterFrame::new("someone@example.com".to_string(), 196, 12);
+        let mut bytes = id3v2_tag_with(&FixtureTags::titled("Rated"), &[(b"POPM", popm.as_bytes().unwrap())]);
+        bytes.extend(mp3_frames(FIXTURE_SECS));
+        std::fs::write(&path, bytes).unwrap();
+        assert_eq!(read_audio_metadata(&path, false).unwrap().rating, Some(80));
+
+        // The play count survives a new rating
+        set_rating(path.to_str().unwrap(), 2).unwrap();
+        let file = read_mpeg(&path).unwrap();
+        let frames: Vec<_> = file.id3v2().unwrap().into_iter()
+            .filter_map(|frame| match frame {
+                Frame::Popularimeter(popm) => Some((popm.email.clone(), popm.rating, popm.counter)),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(frames, [(POPM_EMAIL.to_string(), 64, 12)]);
+    }
+
+    #[test]
+    fn ratings_round_trip_and_clear() {
+        let dir = temp_dir();
+        for format in [Format::Flac, Format::Mp3, Format::M4a] {
+            let path = dir.path().join(format!("rated.{}", format.extension()));
+            write_fixture(&path, format, &FixtureTags::titled("Rated"));
+            let path = path.to_str().unwrap();
+            assert_eq!(set_rating(path, 4).unwrap(), 80);
+            assert_eq!(read_audio_metadata(Path::new(path), false).unwrap().rating, Some(80), "{:?}", format);
+
+            set_rating(path, 0).unwrap();
+            assert_eq!(read_audio_metadata(Path::new(path), false).unwrap().rating, None, "{:?}", format);
+        }
+        assert!(set_rating(dir.path().join("rated.flac").to_str().unwrap(), 6).is_err());
+    }
+
+    #[test]
+    fn back_covers_sit_beside_the_front() {
+        let dir = temp_dir();
+        for format in [Format::Flac, Format::Mp3] {
+            let path = dir.path().join(format!("covers.{}", format.extension()));
+            write_fixture(&path, format, &FixtureTags { with_art: true, ..FixtureTags::titled("Covers") });
+            let path = path.to_str().unwrap();
+            let art = BASE64.encode(jpeg_bytes());
+            tauri::async_runtime::block_on(set_album_art(path, &art, Some(PictureType::CoverBack.as_u8()), None, None)).unwrap();
+            tauri::async_runtime::block_on(set_album_art(path, &art, Some(PictureType::CoverBack.as_u8()), None, None)).unwrap();
+
+            let pictures = get_pictures(path).unwrap();
+            let kinds: Vec<_> = pictures.iter().map(|picture| (picture.pic_type, picture.mime.as_str(), picture.width, picture.height)).collect();
+            assert_eq!(kinds, [(3, "image/jpeg", Some(8), Some(8)), (4, "image/jpeg", Some(8), Some(8))], "{:?}", format);
+            assert_eq!(pictures[1].size_bytes, jpeg_bytes().len());
+
+            assert_eq!(remove_picture(path, PictureType::CoverFront.as_u8()).unwrap(), 1);
+            assert_eq!(get_pictures(path).unwrap().iter().map(|picture| picture.pic_type).collect::<Vec<_>>(), [4]);
+            assert_eq!(remove_all_pictures(path).unwrap(), 1);
+            assert!(get_pictures(path).unwrap().is_empty());
+            assert_eq!(remove_all_pictures(path).unwrap(), 0);
+        }
+    }
+
+    fn png_bytes() -> Vec<u8> {
+        let mut out = std::io::Cursor::new(Vec::new());
+        image::RgbImage::from_pixel(4, 6, image::Rgb([20, 90, 200])).write_to(&mut out, image::ImageFormat::Png).unwrap();
+        out.into_inner()
+    }
+
+    #[test]
+    fn setting_a_cover_again_replaces_it() {
+        let dir = temp_dir();
+        let art = BASE64.encode(png_bytes());
+        for format in [Format::Flac, Format::Mp3, Format::M4a] {
+            let path = dir.path().join(format!("cover.{}", format.extension()));
+            write_fixture(&path, format, &FixtureTags { with_art: true, ..FixtureTags::titled("Cover") });
+            let path = path.to_str().unwrap();
+            tauri::async_runtime::block_on(set_album_art(path, &art, None, None, None)).unwrap();
+            let size = std::fs::metadata(path).unwrap().len();
+            for _ in 0..3 {
+                tauri::async_runtime::block_on(set_album_art(path, &art, None, None, None)).unwrap();
+            }
+            assert_eq!(std::fs::metadata(path).unwrap().len(), size, "{:?}", format);
+
+            let pictures = get_pictures(path).unwrap();
+            assert_eq!(pictures.len(), 1, "{:?}", format);
+            assert_eq!((pictures[0].mime.as_str(), pictures[0].width, pictures[0].height), ("image/png", Some(4), Some(6)));
+        }
+    }
+
+    #[test]
+    fn oversized_covers_are_shrunk_to_fit() {
+        let dir = temp_dir();
+        let path = dir.path().join("scan.mp3");
+        write_fixture(&path, Format::Mp3, &FixtureTags::titled("Scan"));
+        let path = path.to_str().unwrap();
+        // Busy enough that the PNG is far over the byte limit
+        let scan = image::RgbImage::from_fn(1200, 600, |x, y| image::Rgb([(x * 7 + y * 13) as u8, (x ^ y) as u8, (x * y) as u8]));
+        let mut png = std::io::Cursor::new(Vec::new());
+        scan.write_to(&mut png, image::ImageFormat::Png).unwrap();
+        let png = png.into_inner();
+
+        let art = tauri::async_runtime::block_on(set_album_art(path, &BASE64.encode(&png), None, Some(400), Some(80_000))).unwrap();
+        assert_eq!((art.mime_type.as_str(), art.width, art.height, art.resized), ("image/jpeg", 400, 200, true));
+        assert!(art.size_bytes <= 80_000, "{} bytes", art.size_bytes);
+        let pictures = get_pictures(path).unwrap();
+        assert_eq!((pictures[0].mime.as_str(), pictures[0].width, pictures[0].height), ("image/jpeg", Some(400), Some(200)));
+        assert_eq!(pictures[0].size_bytes, art.size_bytes);
+
+        // Within the limits the image goes in as it came
+        let art = tauri::async_runtime::block_on(set_album_art(path, &BASE64.encode(png_bytes()), None, Some(400), Some(80_000))).unwrap();
+        assert_eq!((art.mime_type.as_str(), art.width, art.height, art.resized), ("image/png", 4, 6, false));
+        assert_eq!(BASE64.decode(&get_pictures(path).unwrap()[0].data_base64).unwrap(), png_bytes());
+    }
+
+    #[test]
+    fn cover_art_must_be_an_image() {
+        let dir = temp_dir();
+        let path = dir.path().join("cover.flac");
+        write_fixture(&path, Format::Flac, &FixtureTags { with_art: true, ..FixtureTags::titled("Cover") });
+        let error = tauri::async_runtime::block_on(set_album_art(path.to_str().unwrap(), &BASE64.encode(b"not a picture"), None, None, None)).unwrap_err();
+        assert!(error.contains("not a JPEG"), "{}", error);
+        assert_eq!(get_pictures(path.to_str().unwrap()).unwrap()[0].mime, "image/jpeg");
+    }
+
+    #[test]
+    fn mp4_covers_are_converted_from_webp_and_gif() {
+        let dir = temp_dir();
+        let m4a = dir.path().join("cover.m4a");
+        let flac = dir.path().join("cover.flac");
+        write_fixture(&m4a, Format::M4a, &FixtureTags::titled("Cover"));
+        write_fixture(&flac, Format::Flac, &FixtureTags::titled("Cover"));
+        for format in [image::ImageFormat::WebP, image::ImageFormat::Gif] {
+            let mut out = std::io::Cursor::new(Vec::new());
+            image::RgbImage::from_pixel(4, 6, image::Rgb([20, 90, 200])).write_to(&mut out, format).unwrap();
+            let cover = BASE64.encode(out.into_inner());
+
+            let art = tauri::async_runtime::block_on(set_album_art(m4a.to_str().unwrap(), &cover, None, None, None)).unwrap();
+            assert_eq!((art.mime_type.as_str(), art.width, art.height, art.resized), ("image/jpeg", 4, 6, true), "{:?}", format);
+            assert_eq!(get_pictures(m4a.to_str().unwrap()).unwrap()[0].mime, "image/jpeg");
+
+            // Other containers take them as they come
+            let art = tauri::async_runtime::block_on(set_album_art(flac.to_str().unwrap(), &cover, None, None, None)).unwrap();
+            assert!(!art.resized, "{:?} was re-encoded for FLAC", format);
+        }
+    }
+
+    #[test]
+    fn directory_metadata_sorts_by_date_added_and_modified() {
+        let dir = temp_dir();
+        let added = build_dated_library(dir.path());
+        let titles = |sort_by: SortOption, descending: bool| -> Vec<String> {
+            let listing = DirectoryListing { sort_by: Some(sort_by), descending, recursive: false, art_mode: ArtMode::None };
+            directory_metadata(dir.path(), &listing, |path| added.get(path).copied(), &begin_request("test", "", None), &mut Vec::new())
+                .unwrap()
+                .into_iter()
+                .map(|metadata| metadata.title.unwrap())
+                .collect()
+        };
+
+        assert_eq!(titles(SortOption::DateAdded, false), ["d", "c", "a", "b"]);
+        assert_eq!(titles(SortOption::DateAdded, true), ["b", "a", "c", "d"]);
+        assert_eq!(titles(SortOption::DateModified, false), ["d", "b", "c", "a"]);
+        assert_eq!(titles(SortOption::DateModified, true), ["a", "c", "b", "d"]);
+    }
+
+    #[test]
+    fn combined_track_numbers_keep_their_other_half() {
+        let dir = temp_dir();
+        for format in [Format::Flac, Format::Mp3] {
+            let path = dir.path().join(format!("combined.{}", format.extension()));
+            write_fixture(&path, format, &FixtureTags {
+                track: Some("3/12".into()),
+                ..FixtureTags::titled("Combined")
+            });
+
+            write_metadata(&MetadataWriteOptions { track_number: Some(4), ..write_options(&path) }).unwrap();
+            let metadata = read_audio_metadata(&path, false).unwrap();
+            assert_eq!((metadata.track_number, metadata.track_total), (Some(4), Some(12)), "{:?}", format);
+
+            write_metadata(&MetadataWriteOptions { track_total: Some(14), disc_number: Some(2), ..write_options(&path) }).unwrap();
+            write_metadata(&MetadataWriteOptions { disc_total: Some(2), ..write_options(&path) }).unwrap();
+            let metadata = read_audio_metadata(&path, false).unwrap();
+            assert_eq!((metadata.track_number, metadata.track_total), (Some(4), Some(14)), "{:?}", format);
+            assert_eq!((metadata.disc_number, metadata.disc_total), (Some(2), Some(2)), "{:?}", format);
+        }
+    }
+
+    #[test]
+    fn lyrics_round_trip_and_clear() {
+        let dir = temp_dir();
+        let lyrics = "First line\nSecond line\n\nAfter a gap";
+        for format in [Format::Flac, Format::Mp3] {
+            let path = dir.path().join(format!("lyrics.{}", format.extension()));
+            write_fixture(&path, format, &FixtureTags::titled("Sung"));
+            assert_eq!(get_lyrics(path.to_str().unwrap()).unwrap(), None);
+
+            write_metadata(&MetadataWriteOptions { lyrics: Some(lyrics.into()), ..write_options(&path) }).unwrap();
+            assert_eq!(get_lyrics(path.to_str().unwrap()).unwrap().as_deref(), Some(lyrics), "{:?}", format);
+            assert_eq!(read_audio_metadata(&path, false).unwrap().lyrics.as_deref(), Some(lyrics));
+
+            write_metadata(&MetadataWriteOptions { lyrics: Some(String::new()), ..write_options(&path) }).unwrap();
+            let tagged_file = Probe::open(&path).unwrap().read().unwrap();
+            let tag = tagged_file.primary_tag().unwrap();
+            assert!(tag.get(&ItemKey::Lyrics).is_none(), "{:?} kept an empty lyrics field", format);
+            assert_eq!(tag.title().as_deref(), Some("Sung"));
+        }
+    }
+}
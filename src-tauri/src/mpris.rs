@@ -0,0 +1,473 @@
+//! The player over MPRIS (`org.mpris.MediaPlayer2`), which Linux media keys,
+//! desktop widgets and `playerctl` use. Calls go to the same functions as the
+//! app's own commands; a watcher thread reports changes made in the app back
+//! over D-Bus. Turned off with `mpris_enabled` in the player config.
+
+use std::collections::HashMap;
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+use once_cell::sync::Lazy;
+use parking_lot::Mutex;
+use tauri::{AppHandle, Manager, State};
+use log::{error, info};
+use zbus::blocking::{connection, Connection};
+use zbus::object_server::SignalContext;
+use zbus::zvariant::{ObjectPath, OwnedValue, Value};
+use zbus::{fdo, interface};
+use crate::commands::{
+    emit_playback_state, get_track_duration, get_track_position, pause_audio, previous_track, resume_audio,
+    seek_to, set_playback_speed, set_volume, skip_track, stop_audio,
+};
+use crate::config::{load_player_config, MAX_PLAYBACK_SPEED, MIN_PLAYBACK_SPEED};
+use crate::library::track_info;
+use crate::thumbnails::{ensure_thumbnail, ThumbnailOutcome};
+use crate::volume::gain_to_slider;
+use crate::PlayerHandle;
+
+const BUS_NAME: &str = "org.mpris.MediaPlayer2.musicmanager";
+const OBJECT_PATH: &str = "/org/mpris/MediaPlayer2";
+const WATCH_INTERVAL: Duration = Duration::from_millis(500);
+const ART_PX: u32 = 512;
+// The spec's id for "no track"
+const NO_TRACK: &str = "/org/mpris/MediaPlayer2/TrackList/NoTrack";
+
+struct Service {
+    connection: Connection,
+    stopped: Arc<AtomicBool>,
+}
+
+static SERVICE: Lazy<Mutex<Option<Service>>> = Lazy::new(|| Mutex::new(None));
+
+fn micros(secs: f32) -> i64 {
+    (secs as f64 * 1_000_000.0) as i64
+}
+
+fn failed(error: impl ToString) -> fdo::Error {
+    fdo::Error::Failed(error.to_string())
+}
+
+/// `org.mpris.MediaPlayer2`: the application itself.
+struct Root {
+    app: AppHandle,
+}
+
+#[interface(name = "org.mpris.MediaPlayer2")]
+impl Root {
+    fn raise(&self) {
+        if let Some(window) = self.app.get_webview_window("main") {
+            window.show().ok();
+            window.set_focus().ok();
+        }
+    }
+
+    fn quit(&self) {
+        self.app.exit(0);
+    }
+
+    #[zbus(property)]
+    fn can_quit(&self) -> bool {
+        true
+    }
+
+    #[zbus(property)]
+    fn can_raise(&self) -> bool {
+        true
+    }
+
+    #[zbus(property)]
+    fn has_track_list(&self) -> bool {
+        false
+    }
+
+    #[zbus(property)]
+    fn identity(&self) -> String {
+        "musicManager".to_string()
+    }
+
+    #[zbus(property)]
+    fn supported_uri_schemes(&self) -> Vec<String> {
+        vec!["file".to_string()]
+    }
+
+    #[zbus(property)]
+    fn supported_mime_types(&self) -> Vec<String> {
+        ["audio/mpeg", "audio/flac", "audio/wav", "audio/mp4", "audio/aac", "audio/ogg", "audio/aiff"]
+            .iter().map(|mime| mime.to_string()).collect()
+    }
+}
+
+/// What the `Metadata` property describes, worked out when the track changes.
+#[derive(Default)]
+struct TrackMetadata {
+    track_id: String,
+    path: Option<String>,
+    title: Option<String>,
+    artists: Vec<String>,
+    album: Option<String>,
+    length: Option<i64>,
+    art_url: Option<String>,
+}
+
+impl TrackMetadata {
+    fn load(track_id: String, path: &str, length: Option<i64>) -> Self {
+        let track = track_info(Path::new(path));
+        let art_url = match ensure_thumbnail(Path::new(path), ART_PX) {
+            Ok(ThumbnailOutcome::Cached(art) | ThumbnailOutcome::Generated(art)) => Some(format!("file://{}", art.display())),
+            _ => None,
+        };
+        let title = track.as_ref().and_then(|track| track.title.clone())
+            .or_else(|| Path::new(path).file_stem().map(|stem| stem.to_string_lossy().to_string()));
+        Self {
+            track_id,
+            path: Some(path.to_string()),
+            title,
+            artists: track.as_ref().and_then(|track| track.artist.clone()).into_iter().collect(),
+            album: track.and_then(|track| track.album),
+            length,
+            art_url,
+        }
+    }
+
+    fn to_map(&self) -> HashMap<String, OwnedValue> {
+        let mut map = HashMap::new();
+        let mut insert = |key: &str, value: Value| {
+            if let Ok(value) = OwnedValue::try_from(value) {
+                map.insert(key.to_string(), value);
+            }
+        };
+        let track_id = ObjectPath::try_from(self.track_id.as_str()).unwrap_or_else(|_| ObjectPath::from_static_str_unchecked(NO_TRACK));
+        insert("mpris:trackid", Value::from(track_id));
+        if let Some(path) = &self.path {
+            insert("xesam:url", Value::from(format!("file://{}", path)));
+        }
+        if let Some(title) = &self.title {
+            insert("xesam:title", Value::from(title.clone()));
+        }
+        if !self.artists.is_empty() {
+            insert("xesam:artist", Value::from(self.artists.clone()));
+        }
+        if let Some(album) = &self.album {
+            insert("xesam:album", Value::from(album.clone()));
+        }
+        if let Some(length) = self.length {
+            insert("mpris:length", Value::from(length));
+        }
+        if let Some(art_url) = &self.art_url {
+            insert("mpris:artUrl", Value::from(art_url.clone()));
+        }
+        map
+    }
+}
+
+/// `org.mpris.MediaPlayer2.Player`: playback of the current track.
+struct Player {
+    app: AppHandle,
+    metadata: TrackMetadata,
+}
+
+impl Player {
+    fn player(&self) -> &PlayerHandle {
+        self.app.state::<PlayerHandle>().inner()
+    }
+
+    /// Runs a player command and tells the UI, which didn't make the change itself.
+    fn control<E: ToString>(&self, command: impl FnOnce(State<'_, PlayerHandle>) -> Result<(), E>) -> fdo::Result<()> {
+        command(self.app.state()).map_err(failed)?;
+        emit_playback_state(&self.app);
+        Ok(())
+    }
+}
+
+#[interface(name = "org.mpris.MediaPlayer2.Player")]
+impl Player {
+    fn next(&self) -> fdo::Result<()> {
+        skip_track(self.app.clone()).map_err(failed)
+    }
+
+    fn previous(&self) -> fdo::Result<()> {
+        previous_track(self.app.clone()).map_err(failed)
+    }
+
+    fn pause(&self) -> fdo::Result<()> {
+        self.control(pause_audio)
+    }
+
+    fn play_pause(&self) -> fdo::Result<()> {
+        if self.player().with(|player| player.is_playing) {
+            self.control(pause_audio)
+        } else {
+            self.control(resume_audio)
+        }
+    }
+
+    fn stop(&self) -> fdo::Result<()> {
+        self.control(stop_audio)
+    }
+
+    fn play(&self) -> fdo::Result<()> {
+        self.control(resume_audio)
+    }
+
+    /// Moves by `offset` microseconds; past the end moves on to the next track.
+    fn seek(&self, offset: i64) -> fdo::Result<()> {
+        let target = get_track_position(self.app.state()) as f64 + offset as f64 / 1_000_000.0;
+        let duration = get_track_duration(self.app.state()) as f64;
+        if duration > 0.0 && target >= duration {
+            return self.next();
+        }
+        self.control(|player| seek_to(player, target.max(0.0) as f32))
+    }
+
+    fn set_position(&self, track_id: ObjectPath<'_>, position: i64) -> fdo::Result<()> {
+        // A request for a track that has since changed is ignored, as the spec asks
+        if track_id.as_str() != self.metadata.track_id || position < 0 {
+            return Ok(());
+        }
+        self.control(|player| seek_to(player, position as f32 / 1_000_000.0))
+    }
+
+    fn open_uri(&self, uri: String) -> fdo::Result<()> {
+        let path = uri.strip_prefix("file://").ok_or_else(|| fdo::Error::NotSupported(format!("Only file URIs can be opened, not {}", uri)))?;
+        self.control(|player| crate::commands::play_audio(player, path))
+    }
+
+    #[zbus(property)]
+    fn playback_status(&self) -> String {
+        self.player().with(|player| match (&player.current_path, player.is_playing) {
+            (None, _) => "Stopped",
+            (Some(_), true) => "Playing",
+            (Some(_), false) => "Paused",
+        }).to_string()
+    }
+
+    #[zbus(property)]
+    fn rate(&self) -> f64 {
+        self.player().with(|player| player.speed) as f64
+    }
+
+    #[zbus(property)]
+    fn set_rate(&mut self, rate: f64) {
+        // Zero would mean pause, which MPRIS clients do through Pause instead
+        if rate > 0.0 {
+            let rate = (rate as f32).clamp(MIN_PLAYBACK_SPEED, MAX_PLAYBACK_SPEED);
+            self.control(|player| set_playback_speed(player, rate, None)).ok();
+        }
+    }
+
+    #[zbus(property)]
+    fn minimum_rate(&self) -> f64 {
+        MIN_PLAYBACK_SPEED as f64
+    }
+
+    #[zbus(property)]
+    fn maximum_rate(&self) -> f64 {
+        MAX_PLAYBACK_SPEED as f64
+    }
+
+    #[zbus(property)]
+    fn metadata(&self) -> HashMap<String, OwnedValue> {
+        self.metadata.to_map()
+    }
+
+    #[zbus(property)]
+    fn volume(&self) -> f64 {
+        let scale = load_player_config().playback_settings.volume_scale;
+        gain_to_slider(self.player().with(|player| player.volume), scale) as f64
+    }
+
+    #[zbus(property)]
+    fn set_volume(&mut self, volume: f64) {
+        self.control(|player| set_volume(player, volume.clamp(0.0, 1.0) as f32)).ok();
+    }
+
+    // Clients read it when they need it; changes come as `Seeked` instead
+    #[zbus(property(emits_changed_signal = "false"))]
+    fn position(&self) -> i64 {
+        micros(get_track_position(self.app.state()))
+    }
+
+    #[zbus(property)]
+    fn can_go_next(&self) -> bool {
+        true
+    }
+
+    #[zbus(property)]
+    fn can_go_previous(&self) -> bool {
+        true
+    }
+
+    #[zbus(property)]
+    fn can_play(&self) -> bool {
+        self.player().with(|player| player.current_path.is_some())
+    }
+
+    #[zbus(property)]
+    fn can_pause(&self) -> bool {
+        self.player().with(|player| player.current_path.is_some())
+    }
+
+    #[zbus(property)]
+    fn can_seek(&self) -> bool {
+        self.player().with(|player| player.current_path.is_some())
+    }
+
+    #[zbus(property(emits_changed_signal = "const"))]
+    fn can_control(&self) -> bool {
+        true
+    }
+
+    #[zbus(signal)]
+    async fn seeked(ctxt: &SignalContext<'_>, position: i64) -> zbus::Result<()>;
+}
+
+/// The parts of the player state MPRIS clients are told about when they change.
+#[derive(Clone, PartialEq)]
+struct Observed {
+    path: Option<String>,
+    cue_index: Option<usize>,
+    is_playing: bool,
+    generation: u64,
+    position: f32,
+    duration: Option<f32>,
+    volume: f32,
+    speed: f32,
+}
+
+fn observe(player: &PlayerHandle) -> Observed {
+    player.with(|player| Observed {
+        path: player.current_path.clone(),
+        cue_index: player.cue_track.as_ref().map(|cue| cue.index),
+        is_playing: player.is_playing,
+        generation: player.generation,
+        position: player.track_position().as_secs_f32(),
+        duration: player.track_duration().map(|duration| duration.as_secs_f32()),
+        volume: player.volume,
+        speed: player.speed,
+    })
+}
+
+/// Sends the signals for whatever changed between `before` and `now`.
+fn announce(connection: &Connection, before: &Observed, now: &Observed, tracks_seen: &mut u64) -> zbus::Result<()> {
+    let player = connection.object_server().interface::<_, Player>(OBJECT_PATH)?;
+    let ctxt = player.signal_context();
+    let track_changed = now.path != before.path || now.cue_index != before.cue_index;
+    if track_changed {
+        let metadata = match &now.path {
+            Some(path) => {
+                *tracks_seen += 1;
+                let track_id = format!("/org/musicmanager/track/{}", tracks_seen);
+                TrackMetadata::load(track_id, path, now.duration.map(micros))
+            }
+            None => TrackMetadata { track_id: NO_TRACK.to_string(), ..Default::default() },
+        };
+        player.get_mut().metadata = metadata;
+        let iface = player.get();
+        zbus::block_on(iface.metadata_changed(ctxt))?;
+        zbus::block_on(iface.can_play_changed(ctxt))?;
+        zbus::block_on(iface.can_pause_changed(ctxt))?;
+        zbus::block_on(iface.can_seek_changed(ctxt))?;
+    } else if now.duration != before.duration && now.path.is_some() {
+        // Estimated durations can firm up once the track is open
+        player.get_mut().metadata.length = now.duration.map(micros);
+        zbus::block_on(player.get().metadata_changed(ctxt))?;
+    }
+    let iface = player.get();
+    if track_changed || now.is_playing != before.is_playing {
+        zbus::block_on(iface.playback_status_changed(ctxt))?;
+    }
+    // Play and stop bump the generation too, but only a seek keeps the track
+    if !track_changed && now.generation != before.generation {
+        zbus::block_on(Player::seeked(ctxt, micros(now.position)))?;
+    }
+    if now.volume != before.volume {
+        zbus::block_on(iface.volume_changed(ctxt))?;
+    }
+    if now.speed != before.speed {
+        zbus::block_on(iface.rate_changed(ctxt))?;
+    }
+    Ok(())
+}
+
+fn watch(connection: Connection, stopped: Arc<AtomicBool>, player: PlayerHandle) {
+    let mut before = Observed {
+        path: None,
+        cue_index: None,
+        is_playing: false,
+        generation: 0,
+        position: 0.0,
+        duration: None,
+        volume: -1.0,
+        speed: -1.0,
+    };
+    let mut tracks_seen = 0;
+    while !stopped.load(Ordering::Relaxed) {
+        let now = observe(&player);
+        if now != before {
+            if let Err(e) = announce(&connection, &before, &now, &mut tracks_seen) {
+                error!("Failed to update MPRIS clients: {}", e);
+            }
+            before = now;
+        }
+        std::thread::sleep(WATCH_INTERVAL);
+    }
+}
+
+/// Registers the player on the session bus, unless turned off in the config.
+pub fn start(app: AppHandle) {
+    if !load_player_config().mpris_enabled {
+        return;
+    }
+    let metadata = TrackMetadata { track_id: NO_TRACK.to_string(), ..Default::default() };
+    let player = app.state::<PlayerHandle>().inner().clone();
+    let built = connection::Builder::session()
+        .and_then(|builder| builder.name(BUS_NAME))
+        .and_then(|builder| builder.serve_at(OBJECT_PATH, Root { app: app.clone() }))
+        .and_then(|builder| builder.serve_at(OBJECT_PATH, Player { app, metadata }))
+        .and_then(|builder| builder.build());
+    let connection = match built {
+        Ok(connection) => connection,
+        Err(e) => {
+            error!("Could not register MPRIS service: {}", e);
+            return;
+        }
+    };
+    info!("Registered MPRIS service {}", BUS_NAME);
+    let stopped = Arc::new(AtomicBool::new(false));
+    *SERVICE.lock() = Some(Service { connection: connection.clone(), stopped: Arc::clone(&stopped) });
+    std::thread::spawn(move || watch(connection, stopped, player));
+}
+
+/// Gives up the bus name and closes the connection. Called from the exit hook.
+pub fn stop() {
+    let Some(service) = SERVICE.lock().take() else { return };
+    service.stopped.store(true, Ordering::Relaxed);
+    if let Err(e) = service.connection.release_name(BUS_NAME) {
+        error!("Failed to release MPRIS name: {}", e);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn metadata_carries_the_fields_clients_show() {
+        let metadata = TrackMetadata {
+            track_id: "/org/musicmanager/track/3".to_string(),
+            path: Some("/music/a b.flac".to_string()),
+            title: Some("Title".to_string()),
+            artists: vec!["Artist".to_string()],
+            album: None,
+            length: Some(micros(90.5)),
+            art_url: None,
+        };
+        let map = metadata.to_map();
+        assert_eq!(map["mpris:length"], OwnedValue::from(90_500_000i64));
+        assert_eq!(Value::try_from(&map["xesam:title"]).unwrap(), Value::from("Title"));
+        assert!(map.contains_key("mpris:trackid"));
+        assert!(!map.contains_key("xesam:album"));
+        assert!(!map.contains_key("mpris:artUrl"));
+    }
+}
@@ -0,0 +1,101 @@
+use serde::Serialize;
+use std::fs;
+use std::path::Path;
+use tauri::AppHandle;
+use log::info;
+use crate::config::{get_config_file_path, load_player_config, update_player_config, LibraryRoot};
+use crate::library::{index_exists, request_scan, LIBRARY};
+use crate::library_watch::start_library_watchers;
+use crate::paths::same_location;
+use crate::{load_config, update_config};
+
+#[derive(Debug, Serialize)]
+pub struct OnboardingState {
+    pub config_exists: bool,
+    pub has_library_root: bool,
+    pub index_exists: bool,
+    pub indexed_tracks: usize,
+    pub library_roots: Vec<String>,
+    pub default_location: Option<String>,
+    // True until a library root has been chosen
+    pub needs_onboarding: bool,
+}
+
+#[derive(Debug, Serialize)]
+pub struct OnboardingResult {
+    pub library_root: String,
+    // Set when a scan was requested; follow it through `task-progress`
+    pub scan_task_id: Option<u64>,
+    pub state: OnboardingState,
+}
+
+#[tauri::command]
+pub fn get_onboarding_state() -> OnboardingState {
+    let config = load_player_config();
+    let library_roots: Vec<String> = config.library_roots.into_iter().map(|root| root.path).collect();
+    OnboardingState {
+        config_exists: get_config_file_path().is_some_and(|path| path.is_file()),
+        has_library_root: !library_roots.is_empty(),
+        index_exists: index_exists(),
+        indexed_tracks: LIBRARY.lock().tracks.len(),
+        needs_onboarding: library_roots.is_empty(),
+        library_roots,
+        default_location: load_config().default_location,
+    }
+}
+
+/// Makes `library_root` the default location and first library root. Safe to
+/// run again on a configured install: other roots and settings are kept, and a
+/// root that is already configured is moved to the front rather than duplicated.
+#[tauri::command]
+pub fn complete_onboarding(app: AppHandle, library_root: String, scan_now: bool, watch: bool) -> Result<OnboardingResult, String> {
+    let root = Path::new(&library_root);
+    if !root.is_dir() {
+        return Err(format!("{} is not a folder", library_root));
+    }
+    fs::read_dir(root).map_err(|e| format!("{} is not readable: {}", library_root, e))?;
+    let library_root = fs::canonicalize(root)
+        .map(|path| path.to_string_lossy().to_string())
+        .unwrap_or(library_root);
+
+    let library_root = update_player_config(|config| {
+        let existing = config.library_roots.iter().position(|root| same_location(&root.path, &library_root));
+        let entry = match existing {
+            Some(index) => {
+                let existing = config.library_roots.remove(index);
+                // Re-running onboarding can turn watching on but never silently turns it off
+                LibraryRoot {
+                    watch: watch || existing.watch,
+                    ..existing
+                }
+            }
+            None => LibraryRoot {
+                path: library_root.clone(),
+                scan_on_startup: true,
+                watch,
+            },
+        };
+        let library_root = entry.path.clone();
+        config.library_roots.insert(0, entry);
+        Ok(library_root)
+    })?;
+
+    update_config(|locations| {
+        locations.default_location = Some(library_root.clone());
+        Ok(())
+    })?;
+
+    start_library_watchers(app.clone());
+    let scan_task_id = if scan_now {
+        request_scan(app, vec![library_root.clone()])
+    } else {
+        None
+    };
+    info!("Onboarding completed with library root {}", library_root);
+
+    Ok(OnboardingResult {
+        library_root,
+        scan_task_id,
+        state: get_onboarding_state(),
+    })
+}
@@ -0,0 +1,88 @@
+//! The output stream, opened on the default device the first time something
+//! plays and shared by every track's sink after that, so moving from track to
+//! track doesn't reopen the device. It is closed again once nothing has been
+//! loaded for `output_idle_timeout_secs`, and when playback moves to another
+//! device.
+//!
+//! `OutputStream` can't leave the thread that opened it; it is only ever
+//! opened, used and dropped on the playback thread, which owns the player state.
+
+use std::time::{Duration, Instant};
+use rodio::{OutputStream, OutputStreamHandle, Sink};
+use log::info;
+use crate::audio_device::default_output_name;
+use crate::config::load_player_config;
+use crate::crossfade::cut;
+use crate::output_format::PlaybackError;
+use crate::{PlayerHandle, PlayerState};
+
+const IDLE_CHECK_INTERVAL: Duration = Duration::from_secs(1);
+
+/// The open output stream; dropping this closes it, and every sink on it
+/// goes quiet.
+pub struct Output {
+    _stream: OutputStream,
+    handle: OutputStreamHandle,
+}
+
+impl Output {
+    fn open() -> Result<Self, String> {
+        let (stream, handle) = OutputStream::try_default().map_err(|e| e.to_string())?;
+        Ok(Self { _stream: stream, handle })
+    }
+}
+
+/// A new sink on the output, opening the output first if it isn't open.
+pub fn new_sink(player: &mut PlayerState) -> Result<Sink, PlaybackError> {
+    if player.output.is_none() {
+        player.output = Some(Output::open().map_err(PlaybackError::DeviceError)?);
+        player.output_device = default_output_name();
+        info!("Opened audio output on {}", player.output_device.as_deref().unwrap_or("the default device"));
+    }
+    let handle = &player.output.as_ref().expect("opened above").handle;
+    Sink::try_new(handle).map_err(|e| PlaybackError::DeviceError(e.to_string()))
+}
+
+/// Stops whatever is playing and closes the output, which lets go of the
+/// device and of every open file. The track stays current, so it can be
+/// started again.
+pub fn release(player: &mut PlayerState) {
+    cut(player);
+    player.replace_sink(None);
+    player.preloaded = None;
+    player.is_playing = false;
+    if player.output.take().is_some() {
+        info!("Closed audio output");
+    }
+}
+
+/// Whether the output is open with nothing on it worth keeping it for.
+fn idle(player: &PlayerState) -> bool {
+    player.output.is_some()
+        && !player.is_playing
+        && player.fading.is_none()
+        && (player.current_path.is_none() || player.sink.as_ref().is_none_or(|sink| sink.empty()))
+}
+
+/// Closes the output once it has been idle for the configured time.
+pub fn start_idle_release(player: &PlayerHandle) {
+    player.send(|player| {
+        let mut idle_since: Option<(Instant, Option<Duration>)> = None;
+        player.every(IDLE_CHECK_INTERVAL, move |player| {
+            if !idle(player) {
+                idle_since = None;
+                return true;
+            }
+            // Read once per idle spell rather than every second
+            let (since, timeout) = *idle_since.get_or_insert_with(|| {
+                let secs = load_player_config().playback_settings.output_idle_timeout_secs;
+                (Instant::now(), (secs > 0).then(|| Duration::from_secs(secs.into())))
+            });
+            if timeout.is_some_and(|timeout| since.elapsed() >= timeout) {
+                release(player);
+                idle_since = None;
+            }
+            true
+        });
+    });
+}
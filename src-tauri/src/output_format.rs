@@ -0,0 +1,280 @@
+use serde::Serialize;
+use std::fmt;
+use std::fs::File;
+use std::io::{self, BufReader, Read, Seek};
+use std::path::Path;
+use std::sync::Arc;
+use std::time::Duration;
+use lofty::{prelude::AudioFile, probe::Probe};
+use rodio::cpal::traits::{DeviceTrait, HostTrait};
+use rodio::{source::UniformSourceIterator, Decoder, Source};
+use tauri::{AppHandle, Emitter};
+use crate::config::load_player_config;
+use crate::channel_mix::ChannelMix;
+use crate::decode::{estimate_duration, probe_duration};
+use crate::equalizer::Equalizer;
+use crate::pitch::PitchShift;
+use crate::stretch::{TimeStretch, TrackClock};
+
+/// Why `play_audio` couldn't start a file.
+#[derive(Debug, Serialize, Clone, PartialEq)]
+#[serde(tag = "kind", content = "message")]
+pub enum PlaybackError {
+    FileNotFound(String),
+    // The file is there but isn't audio the decoder can read
+    DecodeError(String),
+    // No output device, or it refused the stream
+    DeviceError(String),
+    SeekUnsupported(String),
+    // The file's sample rate is above anything the output device accepts;
+    // `resample_unsupported_rates` converts it instead
+    UnsupportedSampleFormat {
+        source_rate: u32,
+        bit_depth: Option<u8>,
+        device_max: u32,
+    },
+    Failed(String),
+}
+
+#[derive(Debug, Serialize, Clone, Copy, PartialEq)]
+pub enum PlaybackErrorKind {
+    FileNotFound,
+    DecodeError,
+    DeviceError,
+    SeekUnsupported,
+    UnsupportedSampleFormat,
+    Other,
+}
+
+impl PlaybackError {
+    pub fn kind(&self) -> PlaybackErrorKind {
+        match self {
+            PlaybackError::FileNotFound(_) => PlaybackErrorKind::FileNotFound,
+            PlaybackError::DecodeError(_) => PlaybackErrorKind::DecodeError,
+            PlaybackError::DeviceError(_) => PlaybackErrorKind::DeviceError,
+            PlaybackError::SeekUnsupported(_) => PlaybackErrorKind::SeekUnsupported,
+            PlaybackError::UnsupportedSampleFormat { .. } => PlaybackErrorKind::UnsupportedSampleFormat,
+            PlaybackError::Failed(_) => PlaybackErrorKind::Other,
+        }
+    }
+
+    /// For a file that couldn't be opened.
+    pub fn opening(path: &str, error: io::Error) -> Self {
+        if error.kind() == io::ErrorKind::NotFound {
+            PlaybackError::FileNotFound(format!("{} does not exist", path))
+        } else {
+            PlaybackError::Failed(format!("Failed to open {}: {}", path, error))
+        }
+    }
+}
+
+/// Payload of `playback-error`, for failures nobody asked about directly,
+/// such as a queue track that won't start during auto-advance.
+#[derive(Debug, Serialize, Clone)]
+pub struct PlaybackFailure {
+    pub kind: PlaybackErrorKind,
+    pub path: Option<String>,
+    pub message: String,
+}
+
+/// Emits `error` as `playback-error`.
+pub fn report_failure(app: &AppHandle, error: &PlaybackError, path: Option<&str>) {
+    app.emit("playback-error", PlaybackFailure {
+        kind: error.kind(),
+        path: path.map(str::to_string),
+        message: error.to_string(),
+    }).ok();
+}
+
+impl fmt::Display for PlaybackError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            PlaybackError::UnsupportedSampleFormat { source_rate, bit_depth: Some(bits), device_max } => write!(
+                f, "{} Hz / {}-bit audio is not supported by the output device (max {} Hz)", source_rate, bits, device_max
+            ),
+            PlaybackError::UnsupportedSampleFormat { source_rate, device_max, .. } => write!(
+                f, "{} Hz audio is not supported by the output device (max {} Hz)", source_rate, device_max
+            ),
+            PlaybackError::FileNotFound(message)
+            | PlaybackError::DecodeError(message)
+            | PlaybackError::DeviceError(message)
+            | PlaybackError::SeekUnsupported(message)
+            | PlaybackError::Failed(message) => f.write_str(message),
+        }
+    }
+}
+
+impl From<String> for PlaybackError {
+    fn from(message: String) -> Self {
+        PlaybackError::Failed(message)
+    }
+}
+
+impl From<PlaybackError> for String {
+    fn from(error: PlaybackError) -> Self {
+        error.to_string()
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct OutputCaps {
+    pub max_rate: u32,
+    // What the device runs at by default; the target when resampling
+    pub default_rate: u32,
+}
+
+/// Sample rates the default output device accepts, or None when there is no
+/// device or it won't list its configurations.
+pub fn output_caps() -> Option<OutputCaps> {
+    let device = rodio::cpal::default_host().default_output_device()?;
+    let max_rate = device.supported_output_configs().ok()?
+        .map(|config| config.max_sample_rate().0)
+        .max()?;
+    let default_rate = device.default_output_config().ok()
+        .map(|config| config.sample_rate().0)
+        .unwrap_or(max_rate);
+    Some(OutputCaps { max_rate, default_rate: default_rate.min(max_rate) })
+}
+
+/// Sample rate and bit depth from the container headers; works even when the
+/// decoder can't open the file.
+pub fn source_format(path: &Path) -> Option<(u32, Option<u8>)> {
+    let tagged_file = Probe::open(path).ok()?.read().ok()?;
+    let properties = tagged_file.properties();
+    properties.sample_rate().map(|rate| (rate, properties.bit_depth()))
+}
+
+#[derive(Debug, PartialEq)]
+pub enum RatePlan {
+    Direct,
+    Resample(u32),
+}
+
+/// How to play a source at `source_rate`: as is, converted to the device's
+/// default rate, or not at all. Unknown capabilities are assumed to be fine.
+pub fn plan_rate(source_rate: u32, bit_depth: Option<u8>, caps: Option<OutputCaps>, resample: bool) -> Result<RatePlan, PlaybackError> {
+    match caps {
+        Some(caps) if source_rate > caps.max_rate => {
+            if resample {
+                Ok(RatePlan::Resample(caps.default_rate))
+            } else {
+                Err(PlaybackError::UnsupportedSampleFormat { source_rate, bit_depth, device_max: caps.max_rate })
+            }
+        }
+        _ => Ok(RatePlan::Direct),
+    }
+}
+
+pub type PlayableSource = Box<dyn Source<Item = i16> + Send>;
+
+pub struct OpenedSource {
+    pub source: PlayableSource,
+    // From the decoder, else the container headers, else estimated from the bitrate
+    pub duration: Option<Duration>,
+    // Set when `duration` is only an estimate
+    pub duration_approximate: bool,
+    // Where in the track the source has got to
+    pub clock: Arc<TrackClock>,
+}
+
+/// Opens `path` for the main sink, converted to the device's rate when it is
+/// above what the device takes and resampling is enabled, and run through the
+/// equalizer, channel mix, time stretch and pitch shift.
+pub fn open_source(path: &str) -> Result<OpenedSource, PlaybackError> {
+    open_source_at(path, Duration::ZERO)
+}
+
+/// Like `open_source`, but starting `start` into the track by decoding and
+/// discarding up to it, for decoders that can't seek.
+pub fn open_source_at(path: &str, start: Duration) -> Result<OpenedSource, PlaybackError> {
+    let file = BufReader::new(File::open(path).map_err(|e| PlaybackError::opening(path, e))?);
+    open_reader_at(path, file, start)
+}
+
+/// Like `open_source_at`, decoding from `reader`, which holds the contents of `path`.
+pub fn open_reader_at<R>(path: &str, reader: R, start: Duration) -> Result<OpenedSource, PlaybackError>
+where
+    R: Read + Seek + Send + Sync + 'static,
+{
+    let caps = output_caps();
+    let source = match Decoder::new(reader) {
+        Ok(source) => source,
+        Err(e) => {
+            // A rate the device can't take is a clearer reason than the decoder's
+            if let Some((rate, bit_depth)) = source_format(Path::new(path)) {
+                plan_rate(rate, bit_depth, caps, false)?;
+            }
+            return Err(PlaybackError::DecodeError(format!("Can't decode {}: {}", path, e)));
+        }
+    };
+    // Many MP3s don't tell the decoder how long they are
+    let (duration, duration_approximate) = match source.total_duration().or_else(|| probe_duration(Path::new(path))) {
+        Some(duration) => (Some(duration), false),
+        None => {
+            let estimate = estimate_duration(Path::new(path));
+            (estimate, estimate.is_some())
+        }
+    };
+
+    let resample = load_player_config().playback_settings.resample_unsupported_rates;
+    let rate = source.sample_rate();
+    // Only worth probing the headers when the rate is already a problem
+    let bit_depth = caps.filter(|caps| rate > caps.max_rate)
+        .and_then(|_| source_format(Path::new(path)))
+        .and_then(|(_, bits)| bits);
+    let source: PlayableSource = match plan_rate(rate, bit_depth, caps, resample)? {
+        RatePlan::Direct => Box::new(source.skip_duration(start)),
+        RatePlan::Resample(rate) => {
+            let channels = source.channels();
+            Box::new(UniformSourceIterator::<_, i16>::new(source.skip_duration(start), channels, rate))
+        }
+    };
+    let stretch = TimeStretch::new(ChannelMix::new(Equalizer::new(source)), start);
+    let clock = stretch.clock();
+    Ok(OpenedSource { source: Box::new(PitchShift::new(stretch)), duration, duration_approximate, clock })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CAPS: OutputCaps = OutputCaps { max_rate: 96_000, default_rate: 48_000 };
+
+    #[test]
+    fn rates_within_the_device_play_directly() {
+        assert_eq!(plan_rate(44_100, Some(16), Some(CAPS), false), Ok(RatePlan::Direct));
+        assert_eq!(plan_rate(96_000, Some(24), Some(CAPS), false), Ok(RatePlan::Direct));
+        assert_eq!(plan_rate(384_000, Some(32), None, false), Ok(RatePlan::Direct));
+    }
+
+    #[test]
+    fn rates_above_the_device_fail_or_resample() {
+        assert_eq!(
+            plan_rate(192_000, Some(24), Some(CAPS), false),
+            Err(PlaybackError::UnsupportedSampleFormat { source_rate: 192_000, bit_depth: Some(24), device_max: 96_000 })
+        );
+        assert_eq!(plan_rate(192_000, Some(24), Some(CAPS), true), Ok(RatePlan::Resample(48_000)));
+    }
+
+    #[test]
+    fn typed_error_serializes_with_its_fields() {
+        let error = PlaybackError::UnsupportedSampleFormat { source_rate: 192_000, bit_depth: None, device_max: 48_000 };
+        let json = serde_json::to_value(&error).unwrap();
+        assert_eq!(json["kind"], "UnsupportedSampleFormat");
+        assert_eq!(json["message"]["device_max"], 48_000);
+        assert!(String::from(error).contains("192000 Hz"));
+    }
+
+    #[test]
+    fn open_failures_are_told_apart() {
+        let missing = PlaybackError::opening("/music/gone.flac", io::Error::from(io::ErrorKind::NotFound));
+        assert_eq!(missing.kind(), PlaybackErrorKind::FileNotFound);
+        assert!(missing.to_string().contains("gone.flac"));
+        let denied = PlaybackError::opening("/music/locked.flac", io::Error::from(io::ErrorKind::PermissionDenied));
+        assert_eq!(denied.kind(), PlaybackErrorKind::Other);
+
+        let json = serde_json::to_value(PlaybackError::DeviceError("No output device".to_string())).unwrap();
+        assert_eq!(json["kind"], "DeviceError");
+        assert_eq!(json["message"], "No output device");
+    }
+}
@@ -0,0 +1,145 @@
+use std::fs;
+use std::path::{Path, MAIN_SEPARATOR};
+use std::sync::mpsc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// Returns the key used to decide whether two user-supplied paths point at the
+/// same location. Symlinks and trailing separators are resolved, and the result
+/// is case-folded on platforms whose default filesystems are case-insensitive.
+/// The key is only for comparison; callers keep the original string for display.
+pub fn canonical_key(path: &str) -> String {
+    let resolved = match fs::canonicalize(path) {
+        Ok(canonical) => strip_verbatim_prefix(&canonical.to_string_lossy()),
+        Err(_) => trim_trailing_separators(path),
+    };
+
+    if cfg!(any(target_os = "windows", target_os = "macos")) {
+        resolved.to_lowercase()
+    } else {
+        resolved
+    }
+}
+
+pub fn same_location(a: &str, b: &str) -> bool {
+    a == b || canonical_key(a) == canonical_key(b)
+}
+
+fn trim_trailing_separators(path: &str) -> String {
+    let trimmed = path.trim_end_matches(['/', MAIN_SEPARATOR]);
+    if trimmed.is_empty() || trimmed.ends_with(':') {
+        // Keep filesystem roots ("/", "C:\") intact
+        path.to_string()
+    } else {
+        trimmed.to_string()
+    }
+}
+
+// fs::canonicalize on Windows yields `\\?\C:\...`, which never matches what the user typed
+fn strip_verbatim_prefix(path: &str) -> String {
+    path.strip_prefix(r"\\?\").unwrap_or(path).to_string()
+}
+
+/// Collapses entries that resolve to the same location. With `keep_last` the
+/// later duplicate wins (lists that append), otherwise the earlier one does
+/// (lists ordered most-recent first).
+pub fn dedupe_locations<T>(locations: &mut Vec<T>, keep_last: bool, path_of: impl Fn(&T) -> &str) -> bool {
+    let original_len = locations.len();
+    let mut seen = std::collections::HashSet::new();
+
+    if keep_last {
+        locations.reverse();
+    }
+    locations.retain(|location| seen.insert(canonical_key(path_of(location))));
+    if keep_last {
+        locations.reverse();
+    }
+
+    locations.len() != original_len
+}
+
+/// Checks whether each path exists, probing on separate threads so a hung
+/// network mount can't stall the caller. Paths that don't answer within
+/// `timeout` come back as `None`.
+pub fn probe_paths(paths: &[String], timeout: Duration) -> Vec<Option<bool>> {
+    let (tx, rx) = mpsc::channel();
+    for (index, path) in paths.iter().enumerate() {
+        let tx = tx.clone();
+        let path = path.clone();
+        thread::spawn(move || {
+            let _ = tx.send((index, Path::new(&path).exists()));
+        });
+    }
+    drop(tx);
+
+    let deadline = Instant::now() + timeout;
+    let mut results = vec![None; paths.len()];
+    while let Some(remaining) = deadline.checked_duration_since(Instant::now()) {
+        match rx.recv_timeout(remaining) {
+            Ok((index, exists)) => results[index] = Some(exists),
+            Err(_) => break,
+        }
+    }
+    results
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::temp_dir;
+
+    #[test]
+    fn trailing_separators_share_a_key() {
+        let dir = temp_dir();
+        let music = dir.path().join("Music");
+        fs::create_dir(&music).unwrap();
+        let plain = music.to_string_lossy().to_string();
+        let slashed = format!("{}/", plain);
+        assert_eq!(canonical_key(&plain), canonical_key(&slashed));
+        assert!(same_location(&slashed, &plain));
+        // Paths that don't exist are compared as typed, less the separator
+        assert_eq!(canonical_key("/nowhere/Music//"), "/nowhere/Music");
+        assert_eq!(canonical_key("/"), "/");
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn symlinks_resolve_to_their_target() {
+        let dir = temp_dir();
+        let music = dir.path().join("Music");
+        fs::create_dir(&music).unwrap();
+        let link = dir.path().join("Linked");
+        std::os::unix::fs::symlink(&music, &link).unwrap();
+        let plain = music.to_string_lossy().to_string();
+        let linked = link.to_string_lossy().to_string();
+        assert_eq!(canonical_key(&linked), canonical_key(&plain));
+        assert!(same_location(&format!("{}/", linked), &plain));
+        assert!(!same_location(&linked, &dir.path().to_string_lossy()));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn dedupe_keeps_the_most_recent_spelling() {
+        let dir = temp_dir();
+        let music = dir.path().join("Music");
+        fs::create_dir(&music).unwrap();
+        let link = dir.path().join("Linked");
+        std::os::unix::fs::symlink(&music, &link).unwrap();
+        let other = dir.path().join("Other").to_string_lossy().to_string();
+        let plain = music.to_string_lossy().to_string();
+        let slashed = format!("{}/", link.to_string_lossy());
+
+        // Most recent first, like recent_locations
+        let mut recent = vec![slashed.clone(), other.clone(), plain.clone()];
+        assert!(dedupe_locations(&mut recent, false, |path| path.as_str()));
+        assert_eq!(recent, [slashed.clone(), other.clone()]);
+
+        // Appended in order, so the last one is the most recent
+        let mut appended = vec![plain.clone(), other.clone(), slashed.clone()];
+        assert!(dedupe_locations(&mut appended, true, |path| path.as_str()));
+        assert_eq!(appended, [other.clone(), slashed.clone()]);
+
+        let mut distinct = vec![plain, other];
+        assert!(!dedupe_locations(&mut distinct, false, |path| path.as_str()));
+    }
+}
@@ -0,0 +1,217 @@
+//! Transposing by semitones without changing the tempo. `PitchShift` follows
+//! the time stretch and resamples its output, raising or lowering the pitch
+//! and speeding it up or slowing it down by the same ratio; the stretch makes
+//! up for the speed by that ratio, so only the pitch is left changed.
+
+use std::sync::atomic::{AtomicBool, AtomicU32, Ordering};
+use std::time::Duration;
+use rodio::source::SeekError;
+use rodio::Source;
+
+const MAX_SEMITONES: f32 = 12.0;
+
+// f32 bits of the transposition in semitones; not saved between sessions
+static SEMITONES: AtomicU32 = AtomicU32::new(0);
+// Keep the transposition when the track changes
+static LOCKED: AtomicBool = AtomicBool::new(false);
+
+fn semitones() -> f32 {
+    f32::from_bits(SEMITONES.load(Ordering::Relaxed))
+}
+
+/// Frequency ratio for the current transposition, 1.0 when there is none.
+pub fn ratio() -> f64 {
+    2f64.powf(semitones() as f64 / 12.0)
+}
+
+/// Drops the transposition for a new track, unless it is locked.
+pub fn track_changed() {
+    if !LOCKED.load(Ordering::Relaxed) {
+        SEMITONES.store(0f32.to_bits(), Ordering::Relaxed);
+    }
+}
+
+/// Transposes playing and later tracks by `semitones`, clamped to an octave
+/// either way, and returns what was applied. With `lock_pitch` the
+/// transposition carries over to the next track; leaving it out keeps the
+/// current choice.
+#[tauri::command]
+pub fn set_pitch_semitones(semitones: f32, lock_pitch: Option<bool>) -> Result<f32, String> {
+    if !semitones.is_finite() {
+        return Err("Pitch must be a number of semitones".to_string());
+    }
+    let semitones = semitones.clamp(-MAX_SEMITONES, MAX_SEMITONES);
+    SEMITONES.store(semitones.to_bits(), Ordering::Relaxed);
+    if let Some(locked) = lock_pitch {
+        LOCKED.store(locked, Ordering::Relaxed);
+    }
+    Ok(semitones)
+}
+
+#[tauri::command]
+pub fn get_pitch_semitones() -> f32 {
+    semitones()
+}
+
+/// Resamples `inner` by the pitch ratio with linear interpolation. At normal
+/// pitch its samples pass through unchanged.
+pub struct PitchShift<S> {
+    inner: S,
+    channels: usize,
+    // The frames either side of the read position, `frac` of the way from `prev` to `next`
+    prev: Vec<f32>,
+    next: Vec<f32>,
+    frac: f64,
+    // `prev` holds the last frame of `inner`
+    on_last: bool,
+    finished: bool,
+    output: Vec<i16>,
+    output_index: usize,
+}
+
+impl<S: Source<Item = i16>> PitchShift<S> {
+    pub fn new(inner: S) -> Self {
+        Self {
+            channels: inner.channels().max(1) as usize,
+            inner,
+            prev: Vec::new(),
+            next: Vec::new(),
+            frac: 0.0,
+            on_last: false,
+            finished: false,
+            output: Vec::new(),
+            output_index: 0,
+        }
+    }
+
+    fn read_frame(&mut self) -> Option<Vec<f32>> {
+        (0..self.channels).map(|_| self.inner.next().map(f32::from)).collect()
+    }
+
+    /// Produces the next output frame at `ratio`, or None at the end.
+    fn step(&mut self, ratio: f64) -> Option<()> {
+        if self.finished {
+            return None;
+        }
+        if self.prev.is_empty() {
+            self.prev = self.read_frame()?;
+            match self.read_frame() {
+                Some(frame) => self.next = frame,
+                None => self.on_last = true,
+            }
+        }
+        let frac = self.frac as f32;
+        self.output = if self.on_last {
+            self.prev.iter().map(|sample| *sample as i16).collect()
+        } else {
+            self.prev.iter().zip(&self.next)
+                .map(|(prev, next)| (prev + (next - prev) * frac).round() as i16)
+                .collect()
+        };
+        self.output_index = 0;
+        if self.on_last {
+            self.finished = true;
+            return Some(());
+        }
+
+        // Snapping back at normal pitch lets samples through untouched again
+        let mut position = if ratio == 1.0 { 1.0 } else { self.frac + ratio };
+        while position >= 1.0 {
+            position -= 1.0;
+            match self.read_frame() {
+                Some(frame) => self.prev = std::mem::replace(&mut self.next, frame),
+                None => {
+                    // The last frame is handed on as it is, then the source ends
+                    self.prev = std::mem::take(&mut self.next);
+                    self.on_last = true;
+                    position = 0.0;
+                    break;
+                }
+            }
+        }
+        self.frac = position;
+        Some(())
+    }
+}
+
+impl<S: Source<Item = i16>> Iterator for PitchShift<S> {
+    type Item = i16;
+
+    fn next(&mut self) -> Option<i16> {
+        if self.output_index >= self.output.len() {
+            self.step(ratio())?;
+        }
+        let sample = self.output[self.output_index];
+        self.output_index += 1;
+        Some(sample)
+    }
+}
+
+impl<S: Source<Item = i16>> Source for PitchShift<S> {
+    fn current_frame_len(&self) -> Option<usize> {
+        // Frames are read ahead, so the inner frame boundaries don't line up
+        None
+    }
+
+    fn channels(&self) -> u16 {
+        self.channels as u16
+    }
+
+    fn sample_rate(&self) -> u32 {
+        self.inner.sample_rate()
+    }
+
+    fn total_duration(&self) -> Option<Duration> {
+        self.inner.total_duration()
+    }
+
+    fn try_seek(&mut self, position: Duration) -> Result<(), SeekError> {
+        self.inner.try_seek(position)?;
+        self.prev.clear();
+        self.next.clear();
+        self.frac = 0.0;
+        self.on_last = false;
+        self.finished = false;
+        self.output.clear();
+        self.output_index = 0;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rodio::buffer::SamplesBuffer;
+    use crate::test_support::sine_samples;
+
+    fn crossings(samples: &[i16]) -> usize {
+        samples.windows(2).filter(|pair| (pair[0] < 0) != (pair[1] < 0)).count()
+    }
+
+    fn shifted(samples: &[i16], ratio: f64) -> Vec<i16> {
+        let mut source = PitchShift::new(SamplesBuffer::new(1, 8_000, samples.to_vec()));
+        let mut played = Vec::new();
+        while source.step(ratio).is_some() {
+            played.append(&mut source.output);
+        }
+        played
+    }
+
+    #[test]
+    fn normal_pitch_passes_samples_through() {
+        let samples = sine_samples(8_000, 220.0, 1.0);
+        assert_eq!(shifted(&samples, 1.0), samples);
+        let stereo = PitchShift::new(SamplesBuffer::new(2, 8_000, vec![1i16, 2, 3, 4, 5, 6]));
+        assert_eq!(stereo.collect::<Vec<_>>(), [1, 2, 3, 4, 5, 6]);
+    }
+
+    #[test]
+    fn an_octave_up_doubles_the_frequency_in_half_the_time() {
+        let samples = sine_samples(8_000, 220.0, 2.0);
+        let up = shifted(&samples, 2.0);
+        assert!((up.len() as f32 / samples.len() as f32 - 0.5).abs() < 0.01);
+        // As many cycles in half the samples
+        let (before, after) = (crossings(&samples) as f32, crossings(&up) as f32);
+        assert!((after / before - 1.0).abs() < 0.02, "{} crossings became {}", before, after);
+    }
+}
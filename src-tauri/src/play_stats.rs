@@ -0,0 +1,203 @@
+//! Play counts and last-played times, saved to `stats.json` next to the player
+//! config. A play counts once the track has been listened to for half its
+//! length or four minutes, whichever comes first; skipping ahead doesn't count
+//! towards it.
+
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use once_cell::sync::Lazy;
+use parking_lot::Mutex;
+use tauri::{AppHandle, Manager};
+use log::error;
+use crate::config::get_config_dir;
+use crate::config_file::{read_json, write_json};
+use crate::library::now_secs;
+use crate::paths::canonical_key;
+use crate::scrobble;
+use crate::PlayerHandle;
+
+const MAX_PLAY_THRESHOLD: Duration = Duration::from_secs(4 * 60);
+// A bigger step between two looks at the position is a seek, not listening
+const MAX_LISTEN_STEP: Duration = Duration::from_secs(2);
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct TrackStats {
+    // As last played, for display; the store itself is keyed by `canonical_key`
+    pub path: String,
+    pub play_count: u32,
+    pub last_played: u64,
+}
+
+static STATS: Lazy<Mutex<HashMap<String, TrackStats>>> = Lazy::new(|| {
+    Mutex::new(stats_path().map(|path| read_json(&path)).unwrap_or_default())
+});
+
+/// How much of the track playing now has been heard.
+struct Listening {
+    path: String,
+    listened: Duration,
+    last_position: Duration,
+    counted: bool,
+    // For the scrobble log, which times a play by when it began
+    started_at: u64,
+}
+
+static LISTENING: Lazy<Mutex<Option<Listening>>> = Lazy::new(|| Mutex::new(None));
+
+fn stats_path() -> Option<PathBuf> {
+    get_config_dir().map(|dir| dir.join("stats.json"))
+}
+
+fn save_stats(stats: &HashMap<String, TrackStats>) {
+    let Some(path) = stats_path() else { return };
+    if let Err(e) = write_json(&path, stats) {
+        error!("Failed to save play counts: {}", e);
+    }
+}
+
+/// Listening time after which a track of `duration` counts as played.
+fn play_threshold(duration: Option<Duration>) -> Duration {
+    duration.map_or(MAX_PLAY_THRESHOLD, |duration| (duration / 2).min(MAX_PLAY_THRESHOLD))
+}
+
+/// Starts counting a new play of `path`. Called whenever a track starts,
+/// including the same one again.
+pub fn begin_play(path: &str) {
+    *LISTENING.lock() = Some(Listening {
+        path: path.to_string(),
+        listened: Duration::ZERO,
+        last_position: Duration::ZERO,
+        counted: false,
+        started_at: now_secs(),
+    });
+}
+
+/// Adds what was heard since the last call to the current play, recording it
+/// once it has gone on long enough, and logging it as a scrobble. Called from
+/// the auto-advance watcher.
+pub fn poll(app: &AppHandle) {
+    let playing = app.state::<PlayerHandle>().with(|player| match (&player.current_path, &player.sink) {
+        (Some(path), Some(_)) if player.is_playing => Some((path.clone(), player.track_position(), player.track_duration())),
+        _ => None,
+    });
+    let Some((path, position, duration)) = playing else { return };
+    let (path, started_at) = {
+        let mut listening = LISTENING.lock();
+        let Some(listening) = listening.as_mut().filter(|listening| listening.path == path) else { return };
+        let step = position.saturating_sub(listening.last_position);
+        if position > listening.last_position && step <= MAX_LISTEN_STEP {
+            listening.listened += step;
+        }
+        listening.last_position = position;
+        if listening.counted || listening.listened < play_threshold(duration) {
+            return;
+        }
+        listening.counted = true;
+        (path, listening.started_at)
+    };
+    record_play(&path);
+    scrobble::record(app, &path, started_at, duration);
+}
+
+fn record_play(path: &str) {
+    let mut stats = STATS.lock();
+    let entry = stats.entry(canonical_key(path)).or_insert_with(|| TrackStats {
+        path: path.to_string(),
+        play_count: 0,
+        last_played: 0,
+    });
+    entry.path = path.to_string();
+    entry.play_count += 1;
+    entry.last_played = now_secs();
+    save_stats(&stats);
+}
+
+/// Moves the entries for `old_key` (a file, or a folder and everything under
+/// it) to their places under `new_base`.
+fn rebase_stats(stats: &mut HashMap<String, TrackStats>, old_key: &str, new_base: &Path) -> bool {
+    let moved: Vec<String> = stats.keys()
+        .filter(|key| Path::new(key).starts_with(old_key))
+        .cloned()
+        .collect();
+    for key in &moved {
+        let Some(mut entry) = stats.remove(key) else { continue };
+        let rest = Path::new(key).strip_prefix(old_key).unwrap_or(Path::new(""));
+        let new_path = if rest.as_os_str().is_empty() { new_base.to_path_buf() } else { new_base.join(rest) };
+        entry.path = new_path.to_string_lossy().to_string();
+        stats.insert(canonical_key(&entry.path), entry);
+    }
+    !moved.is_empty()
+}
+
+/// Carries the stats of a moved or renamed file, or of the files in a moved
+/// folder, over to the new location. `old_key` is the `canonical_key` of the
+/// old location, taken before the move.
+pub fn remap_stats(old_key: &str, new_base: &Path) {
+    let mut stats = STATS.lock();
+    if rebase_stats(&mut stats, old_key, new_base) {
+        save_stats(&stats);
+    }
+}
+
+/// Tracks that still exist, best first by `better`, at most `limit` of them.
+fn ranked(better: impl Fn(&TrackStats, &TrackStats) -> std::cmp::Ordering, limit: usize) -> Vec<TrackStats> {
+    let mut tracks: Vec<TrackStats> = STATS.lock().values()
+        .filter(|entry| Path::new(&entry.path).is_file())
+        .cloned()
+        .collect();
+    tracks.sort_by(better);
+    tracks.truncate(limit);
+    tracks
+}
+
+#[tauri::command]
+pub fn get_track_stats(path: String) -> Option<TrackStats> {
+    STATS.lock().get(&canonical_key(&path)).cloned()
+}
+
+/// The most played tracks; ties go to the one played most recently.
+#[tauri::command]
+pub fn get_most_played(limit: usize) -> Vec<TrackStats> {
+    ranked(|a, b| b.play_count.cmp(&a.play_count).then(b.last_played.cmp(&a.last_played)), limit)
+}
+
+#[tauri::command]
+pub fn get_recently_played(limit: usize) -> Vec<TrackStats> {
+    ranked(|a, b| b.last_played.cmp(&a.last_played), limit)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn plays_count_at_half_the_track_or_four_minutes() {
+        assert_eq!(play_threshold(Some(Duration::from_secs(180))), Duration::from_secs(90));
+        assert_eq!(play_threshold(Some(Duration::from_secs(3600))), MAX_PLAY_THRESHOLD);
+        assert_eq!(play_threshold(None), MAX_PLAY_THRESHOLD);
+    }
+
+    #[test]
+    fn moving_a_folder_carries_the_stats_of_its_files() {
+        let dir = crate::test_support::temp_dir();
+        let old_key = canonical_key(&dir.path().join("Old").to_string_lossy());
+        let new_base = dir.path().join("New");
+        let stats = |path: &str| TrackStats { path: path.to_string(), play_count: 3, last_played: 10 };
+        let inside = format!("{}/a.flac", old_key);
+        let beside = format!("{}er/b.flac", old_key);
+        let mut all = HashMap::from([
+            (inside.clone(), stats(&inside)),
+            (beside.clone(), stats(&beside)),
+        ]);
+
+        assert!(rebase_stats(&mut all, &old_key, &new_base));
+        let moved = new_base.join("a.flac").to_string_lossy().to_string();
+        assert_eq!(all[&canonical_key(&moved)].path, moved);
+        assert_eq!(all[&canonical_key(&moved)].play_count, 3);
+        // A sibling whose name merely starts the same stays put
+        assert!(all.contains_key(&beside));
+        assert!(!all.contains_key(&inside));
+    }
+}
@@ -0,0 +1,185 @@
+//! The playback thread. It owns the player state, and with it the output
+//! stream and every sink on it; `OutputStream` can't leave the thread that
+//! opened it, so nothing else touches them. Commands and watchers reach the
+//! player through a `PlayerHandle`, which sends closures over a channel for the
+//! thread to run one at a time. Work that has to carry on between commands,
+//! such as a volume ramp, is scheduled on the thread with `PlayerState::every`.
+
+use std::cell::Cell;
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError, Sender};
+use std::time::{Duration, Instant};
+use log::error;
+use crate::queue::{holding_queue, with_queue, PlayQueue};
+use crate::PlayerState;
+
+type Command = Box<dyn FnOnce(&mut PlayerState) + Send>;
+
+thread_local! {
+    static ON_PLAYBACK_THREAD: Cell<bool> = const { Cell::new(false) };
+}
+
+/// Work repeated on the playback thread until it returns false.
+pub struct Repeating {
+    interval: Duration,
+    due: Instant,
+    step: Box<dyn FnMut(&mut PlayerState) -> bool>,
+}
+
+impl Repeating {
+    pub fn new(interval: Duration, step: impl FnMut(&mut PlayerState) -> bool + 'static) -> Self {
+        Self { interval, due: Instant::now() + interval, step: Box::new(step) }
+    }
+}
+
+/// The way to the playback thread, registered with Tauri's managed state:
+/// commands take it as `State<'_, PlayerHandle>` and background threads get it
+/// from the app handle or a clone made when they start.
+#[derive(Clone)]
+pub struct PlayerHandle(Sender<Command>);
+
+impl PlayerHandle {
+    /// Starts a playback thread with a stopped player at the saved volume and speed.
+    pub fn new() -> Self {
+        let (sender, commands) = mpsc::channel();
+        std::thread::Builder::new()
+            .name("playback".to_string())
+            .spawn(move || run(commands))
+            .expect("Failed to start the playback thread");
+        Self(sender)
+    }
+
+    /// Runs `command` on the playback thread and waits for what it returns.
+    /// Must not be called from the playback thread itself, which would wait on
+    /// itself forever, nor with the queue locked, which the thread may be
+    /// waiting for.
+    pub fn with<T: Send + 'static>(&self, command: impl FnOnce(&mut PlayerState) -> T + Send + 'static) -> T {
+        assert!(!ON_PLAYBACK_THREAD.with(Cell::get), "A player command was sent from the playback thread");
+        assert!(!holding_queue(), "A player command was sent with the queue locked");
+        let (reply, result) = mpsc::sync_channel(1);
+        self.send(move |player| {
+            reply.send(command(player)).ok();
+        });
+        // The reply is dropped unsent when the command panics
+        result.recv().expect("A player command failed")
+    }
+
+    /// Runs `command` with both the player and the queue, locking the queue on
+    /// the playback thread so the two are always taken in the same order.
+    pub fn with_queue<T: Send + 'static>(&self, command: impl FnOnce(&mut PlayerState, &mut PlayQueue) -> T + Send + 'static) -> T {
+        self.with(move |player| with_queue(|queue| command(player, queue)))
+    }
+
+    /// Queues `command` for the playback thread without waiting for it.
+    pub fn send(&self, command: impl FnOnce(&mut PlayerState) + Send + 'static) {
+        if self.0.send(Box::new(command)).is_err() {
+            error!("The playback thread has stopped");
+        }
+    }
+}
+
+impl Default for PlayerHandle {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+fn run(commands: Receiver<Command>) {
+    ON_PLAYBACK_THREAD.with(|on| on.set(true));
+    let mut player = PlayerState::new();
+    loop {
+        let next_due = player.repeating.iter().map(|repeating| repeating.due).min();
+        let command = match next_due {
+            Some(due) => match commands.recv_timeout(due.saturating_duration_since(Instant::now())) {
+                Ok(command) => Some(command),
+                Err(RecvTimeoutError::Timeout) => None,
+                Err(RecvTimeoutError::Disconnected) => return,
+            },
+            None => match commands.recv() {
+                Ok(command) => Some(command),
+                Err(_) => return,
+            },
+        };
+        if let Some(command) = command {
+            // A failed command is reported to its sender; the player carries on
+            if panic::catch_unwind(AssertUnwindSafe(|| command(&mut player))).is_err() {
+                error!("A player command panicked");
+            }
+        }
+        run_due(&mut player);
+    }
+}
+
+/// Runs the repeating work that is due, dropping whatever has finished.
+fn run_due(player: &mut PlayerState) {
+    let now = Instant::now();
+    if !player.repeating.iter().any(|repeating| repeating.due <= now) {
+        return;
+    }
+    // Taken out while it runs, since each step gets the whole player
+    let mut repeating = std::mem::take(&mut player.repeating);
+    repeating.retain_mut(|repeating| {
+        if repeating.due > now {
+            return true;
+        }
+        repeating.due = now + repeating.interval;
+        panic::catch_unwind(AssertUnwindSafe(|| (repeating.step)(player))).unwrap_or_else(|_| {
+            error!("Repeating player work panicked");
+            false
+        })
+    });
+    // Steps may have scheduled more work meanwhile
+    repeating.append(&mut player.repeating);
+    player.repeating = repeating;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::temp_dir;
+    use std::sync::atomic::{AtomicU32, Ordering};
+    use std::sync::Arc;
+
+    #[test]
+    fn commands_run_in_order_and_repeating_work_stops_when_done() {
+        let _dir = temp_dir();
+        let player = PlayerHandle::new();
+        player.send(|player| player.history.push("a".to_string()));
+        player.send(|player| player.history.push("b".to_string()));
+        assert_eq!(player.with(|player| player.history.clone()), ["a", "b"]);
+
+        let steps = Arc::new(AtomicU32::new(0));
+        let counted = Arc::clone(&steps);
+        player.send(move |player| {
+            player.every(Duration::from_millis(5), move |_| counted.fetch_add(1, Ordering::SeqCst) + 1 < 3);
+        });
+        std::thread::sleep(Duration::from_millis(100));
+        assert_eq!(steps.load(Ordering::SeqCst), 3);
+        assert!(player.with(|player| player.repeating.is_empty()));
+    }
+
+    #[test]
+    fn a_panicking_command_leaves_the_player_running() {
+        let _dir = temp_dir();
+        let player = PlayerHandle::new();
+        let failed = std::thread::spawn({
+            let player = player.clone();
+            move || player.with(|_| -> () { panic!("bad command") })
+        }).join();
+        assert!(failed.is_err());
+        assert_eq!(player.with(|player| player.generation), 0);
+    }
+
+    #[test]
+    fn waiting_on_the_player_with_the_queue_locked_is_refused() {
+        let _dir = temp_dir();
+        let player = PlayerHandle::new();
+        let refused = std::thread::spawn({
+            let player = player.clone();
+            move || with_queue(|_| player.with(|_| ()))
+        }).join();
+        assert!(refused.is_err());
+        // The queue was let go of when the assertion unwound
+        player.with_queue(|_, _| ());
+    }
+}
@@ -0,0 +1,263 @@
+use serde::Serialize;
+use std::collections::HashSet;
+use std::fs;
+use std::path::{Component, Path, PathBuf};
+use crate::config::get_config_dir;
+use crate::library::is_audio_file;
+use crate::FileItem;
+
+/// `#EXTINF:<seconds>,<artist> - <title>` as written by most players.
+#[derive(Debug, Serialize, Clone, Default)]
+pub struct ExtInf {
+    pub duration: Option<f64>,
+    pub artist: Option<String>,
+    pub title: Option<String>,
+}
+
+#[derive(Debug, Clone)]
+pub enum PlaylistLine {
+    // Comments, directives and blank lines, kept verbatim so a rewrite changes only paths
+    Other(String),
+    Info(String, ExtInf),
+    Entry(String),
+}
+
+/// An M3U/M3U8 playlist kept line by line, so rewriting it preserves anything
+/// it doesn't understand.
+#[derive(Debug, Clone)]
+pub struct Playlist {
+    pub path: PathBuf,
+    pub lines: Vec<PlaylistLine>,
+}
+
+#[derive(Debug, Serialize, Clone)]
+pub struct PlaylistEntry {
+    // Index into `Playlist::lines`
+    pub line: usize,
+    // As written in the file, possibly relative to the playlist
+    pub raw: String,
+    pub path: PathBuf,
+    pub info: Option<ExtInf>,
+}
+
+pub fn playlists_dir() -> Option<PathBuf> {
+    get_config_dir().map(|dir| dir.join("playlists"))
+}
+
+/// A playlist file path, or the name of a playlist stored in the app's playlist folder.
+pub fn resolve_playlist(path_or_name: &str) -> Result<PathBuf, String> {
+    let as_path = Path::new(path_or_name);
+    if as_path.is_file() {
+        return Ok(as_path.to_path_buf());
+    }
+
+    let dir = playlists_dir().ok_or("Could not determine config directory")?;
+    ["m3u8", "m3u"].iter()
+        .map(|ext| dir.join(format!("{}.{}", path_or_name, ext)))
+        .find(|candidate| candidate.is_file())
+        .ok_or_else(|| format!("No playlist file or stored playlist named {}", path_or_name))
+}
+
+#[derive(Debug, Serialize, Clone)]
+pub struct PlaylistReference {
+    pub playlist: String,
+    pub entries: Vec<String>,
+}
+
+/// Stored playlists with entries pointing at any of `paths`.
+pub fn playlists_referencing(paths: &HashSet<String>) -> Vec<PlaylistReference> {
+    let Some(entries) = playlists_dir().and_then(|dir| fs::read_dir(dir).ok()) else { return Vec::new() };
+    entries.flatten()
+        .map(|entry| entry.path())
+        .filter(|path| path.extension().is_some_and(|ext| ext.eq_ignore_ascii_case("m3u") || ext.eq_ignore_ascii_case("m3u8")))
+        .filter_map(|path| Playlist::load(&path).ok())
+        .filter_map(|playlist| {
+            let matching: Vec<String> = playlist.entries().into_iter()
+                .map(|entry| entry.path.to_string_lossy().to_string())
+                .filter(|path| paths.contains(path))
+                .collect();
+            (!matching.is_empty()).then(|| PlaylistReference {
+                playlist: playlist.path.to_string_lossy().to_string(),
+                entries: matching,
+            })
+        })
+        .collect()
+}
+
+/// `target` as seen from the folder `base`, climbing out with `..` where
+/// needed. None when the two share no root, e.g. on different drives.
+pub fn relative_path(target: &Path, base: &Path) -> Option<PathBuf> {
+    let target: Vec<Component> = target.components().collect();
+    let base: Vec<Component> = base.components().collect();
+    let common = target.iter().zip(&base).take_while(|(a, b)| a == b).count();
+    let rooted = |parts: &[Component]| parts.iter().any(|part| matches!(part, Component::Prefix(_) | Component::RootDir));
+    if common == 0 || rooted(&target[common..]) || rooted(&base[common..]) {
+        return None;
+    }
+    let mut relative: PathBuf = base[common..].iter().map(|_| Component::ParentDir).collect();
+    relative.extend(&target[common..]);
+    Some(relative)
+}
+
+/// An entry as written, as a path on this platform. Playlists made on Windows
+/// separate folders with backslashes, which elsewhere would read as part of a name.
+fn entry_path(raw: &str) -> PathBuf {
+    let raw = raw.strip_prefix("file://").unwrap_or(raw);
+    if cfg!(windows) {
+        PathBuf::from(raw)
+    } else {
+        PathBuf::from(raw.replace('\\', "/"))
+    }
+}
+
+fn parse_extinf(value: &str) -> ExtInf {
+    let (duration, display) = value.split_once(',').unwrap_or((value, ""));
+    // Attributes like tvg-id="..." may follow the duration; only the number matters here
+    let duration = duration.split_whitespace().next()
+        .and_then(|d| d.parse::<f64>().ok())
+        .filter(|d| *d > 0.0);
+    let display = display.trim();
+    let (artist, title) = match display.split_once(" - ") {
+        Some((artist, title)) => (Some(artist.trim().to_string()), Some(title.trim().to_string())),
+        None if !display.is_empty() => (None, Some(display.to_string())),
+        None => (None, None),
+    };
+    ExtInf { duration, artist, title }
+}
+
+impl Playlist {
+    /// An empty extended M3U playlist, not yet written to `path`.
+    pub fn new(path: &Path) -> Self {
+        Self { path: path.to_path_buf(), lines: vec![PlaylistLine::Other("#EXTM3U".to_string())] }
+    }
+
+    pub fn load(path: &Path) -> Result<Self, String> {
+        let contents = fs::read_to_string(path).map_err(|e| format!("Failed to read {}: {}", path.display(), e))?;
+        let lines = contents.lines().map(|line| {
+            let trimmed = line.trim_start_matches('\u{feff}').trim();
+            if let Some(value) = trimmed.strip_prefix("#EXTINF:") {
+                PlaylistLine::Info(line.to_string(), parse_extinf(value))
+            } else if trimmed.is_empty() || trimmed.starts_with('#') {
+                PlaylistLine::Other(line.to_string())
+            } else {
+                PlaylistLine::Entry(trimmed.to_string())
+            }
+        }).collect();
+        Ok(Self { path: path.to_path_buf(), lines })
+    }
+
+    fn base_dir(&self) -> &Path {
+        self.path.parent().unwrap_or(Path::new(""))
+    }
+
+    /// Entry paths resolved against the playlist's folder, each with the
+    /// `#EXTINF` line directly above it, if any.
+    pub fn entries(&self) -> Vec<PlaylistEntry> {
+        let mut entries = Vec::new();
+        let mut info = None;
+        for (index, line) in self.lines.iter().enumerate() {
+            match line {
+                PlaylistLine::Info(_, extinf) => info = Some(extinf.clone()),
+                PlaylistLine::Entry(raw) => {
+                    entries.push(PlaylistEntry {
+                        line: index,
+                        raw: raw.clone(),
+                        path: self.base_dir().join(entry_path(raw)),
+                        info: info.take(),
+                    });
+                }
+                PlaylistLine::Other(_) => {}
+            }
+        }
+        entries
+    }
+
+    /// Points the entry on `line` at `target`, written relative to the playlist
+    /// when the original entry was relative and the target is under the same folder.
+    pub fn replace_entry(&mut self, line: usize, target: &Path) {
+        let Some(PlaylistLine::Entry(raw)) = self.lines.get(line) else { return };
+        let relative = Path::new(raw).is_relative()
+            .then(|| target.strip_prefix(self.base_dir()).ok())
+            .flatten();
+        let written = relative.unwrap_or(target).to_string_lossy().to_string();
+        self.lines[line] = PlaylistLine::Entry(written);
+    }
+
+    /// Adds `target` at the end, with an `#EXTINF` line when its tags are known.
+    pub fn append_entry(&mut self, target: &Path, info: Option<&ExtInf>) {
+        if let Some(info) = info {
+            let display = match (&info.artist, &info.title) {
+                (Some(artist), Some(title)) => format!("{} - {}", artist, title),
+                (None, Some(title)) => title.clone(),
+                _ => String::new(),
+            };
+            let duration = info.duration.map(|d| d.round() as i64).unwrap_or(-1);
+            self.lines.push(PlaylistLine::Info(format!("#EXTINF:{},{}", duration, display), info.clone()));
+        }
+        self.lines.push(PlaylistLine::Entry(target.to_string_lossy().to_string()));
+    }
+
+    /// Writes via a temporary file and a rename, so a failed write never leaves a truncated playlist.
+    pub fn save(&self) -> Result<(), String> {
+        let mut contents = String::new();
+        for line in &self.lines {
+            match line {
+                PlaylistLine::Other(text) | PlaylistLine::Info(text, _) | PlaylistLine::Entry(text) => contents.push_str(text),
+            }
+            contents.push('\n');
+        }
+
+        let temp_path = self.path.with_extension("m3u8.tmp");
+        fs::write(&temp_path, contents).map_err(|e| format!("Failed to write {}: {}", temp_path.display(), e))?;
+        fs::rename(&temp_path, &self.path).map_err(|e| format!("Failed to replace {}: {}", self.path.display(), e))
+    }
+}
+
+/// The entries of a playlist file (or stored playlist name) in order, with
+/// relative entries resolved against the playlist's folder. Entries whose
+/// files are gone are kept and marked `missing`.
+#[tauri::command]
+pub fn read_playlist(path: String) -> Result<Vec<FileItem>, String> {
+    let playlist = Playlist::load(&resolve_playlist(&path)?)?;
+    Ok(playlist.entries().into_iter().map(|entry| FileItem {
+        name: entry.path.file_name().map(|name| name.to_string_lossy().to_string()).unwrap_or_else(|| entry.raw.clone()),
+        path: entry.path.to_string_lossy().to_string(),
+        is_dir: false,
+        is_audio: is_audio_file(&entry.path),
+        modified: None,
+        added_at: None,
+        excluded: false,
+        missing: !entry.path.is_file(),
+    }).collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn windows_playlists_parse_everywhere() {
+        let dir = crate::test_support::temp_dir();
+        let artist = dir.path().join("Artist");
+        fs::create_dir_all(&artist).unwrap();
+        fs::write(artist.join("a.mp3"), b"").unwrap();
+        let absolute = dir.path().join("b.flac");
+        let path = dir.path().join("mix.m3u");
+        let text = format!(
+            "#EXTM3U\r\n\r\n# made on Windows\r\n#EXTINF:201,Artist - A\r\nArtist\\a.mp3\r\n{}\r\n",
+            absolute.display(),
+        );
+        fs::write(&path, text).unwrap();
+
+        let entries = Playlist::load(&path).unwrap().entries();
+        assert_eq!(entries.len(), 2);
+        assert_eq!(entries[0].path, artist.join("a.mp3"));
+        assert_eq!(entries[0].info.as_ref().unwrap().duration, Some(201.0));
+        assert_eq!(entries[1].path, absolute);
+        assert!(entries[1].info.is_none());
+
+        let items = read_playlist(path.to_string_lossy().to_string()).unwrap();
+        assert_eq!(items.iter().map(|item| item.missing).collect::<Vec<_>>(), [false, true]);
+        assert_eq!(items[0].name, "a.mp3");
+    }
+}
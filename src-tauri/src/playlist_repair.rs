@@ -0,0 +1,222 @@
+use serde::Serialize;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use log::info;
+use crate::library::{track_info, LibraryTrack, LIBRARY};
+use crate::playlist::{resolve_playlist, ExtInf, Playlist, PlaylistEntry};
+use crate::trash::staged_paths;
+use crate::walker::{walk_audio_files, Exclusions};
+use crate::text::fold;
+
+const DURATION_TOLERANCE_SECS: f64 = 2.0;
+
+#[derive(Debug, Serialize, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum MatchConfidence {
+    // A single file with the same name
+    High,
+    // A single file with the same artist and title (and duration, when known)
+    Medium,
+    // Several equally good candidates; never applied automatically
+    Ambiguous,
+    Unresolved,
+}
+
+#[derive(Debug, Serialize)]
+pub struct RepairProposal {
+    pub line: usize,
+    pub original: String,
+    pub replacement: Option<String>,
+    pub candidates: Vec<String>,
+    pub confidence: MatchConfidence,
+    pub applied: bool,
+}
+
+#[derive(Debug, Serialize)]
+pub struct PlaylistRepairReport {
+    pub playlist: String,
+    pub total_entries: usize,
+    pub missing_entries: usize,
+    pub proposals: Vec<RepairProposal>,
+    pub applied: usize,
+    pub unresolved: Vec<String>,
+    // Entries whose files are staged for removal; restoring them fixes the playlist instead
+    pub staged: Vec<String>,
+    pub dry_run: bool,
+}
+
+struct Candidate {
+    path: String,
+    track: Option<LibraryTrack>,
+}
+
+/// What the missing file was: its `#EXTINF` details, or what the library index
+/// remembered about it before it moved.
+fn expected_tags(entry: &PlaylistEntry) -> ExtInf {
+    let mut expected = entry.info.clone().unwrap_or_default();
+    if expected.artist.is_none() || expected.title.is_none() || expected.duration.is_none() {
+        if let Some(track) = LIBRARY.lock().tracks.get(&entry.path.to_string_lossy().to_string()) {
+            expected.artist = expected.artist.or_else(|| track.artist.clone());
+            expected.title = expected.title.or_else(|| track.title.clone());
+            expected.duration = expected.duration.or(track.duration);
+        }
+    }
+    expected
+}
+
+fn normalized(value: &str) -> String {
+    fold(value).chars().filter(|c| c.is_alphanumeric()).collect()
+}
+
+fn tags_match(expected: &ExtInf, track: &LibraryTrack) -> bool {
+    let same = |a: &Option<String>, b: &Option<String>| match (a, b) {
+        (Some(a), Some(b)) => normalized(a) == normalized(b),
+        _ => false,
+    };
+    let duration_ok = match (expected.duration, track.duration) {
+        (Some(a), Some(b)) => (a - b).abs() <= DURATION_TOLERANCE_SECS,
+        _ => true,
+    };
+    same(&expected.title, &track.title) && duration_ok
+        && (expected.artist.is_none() || same(&expected.artist, &track.artist) || same(&expected.artist, &track.album_artist))
+}
+
+fn file_name_key(path: &Path) -> Option<String> {
+    path.file_name().map(|name| name.to_string_lossy().to_lowercase())
+}
+
+/// Every file that could stand in for a missing entry: the library index plus
+/// whatever is under the extra search roots.
+fn gather_candidates(search_roots: &[String]) -> Vec<Candidate> {
+    let mut candidates: Vec<Candidate> = LIBRARY.lock().tracks.values()
+        .filter(|track| Path::new(&track.path).is_file())
+        .map(|track| Candidate { path: track.path.clone(), track: Some(track.clone()) })
+        .collect();
+
+    let exclusions = Exclusions::load();
+    let known: std::collections::HashSet<String> = candidates.iter().map(|c| c.path.clone()).collect();
+    for root in search_roots {
+        for path in walk_audio_files(Path::new(root), true, &exclusions) {
+            let path_str = path.to_string_lossy().to_string();
+            if !known.contains(&path_str) {
+                // Tags are read lazily, only if a metadata match needs them
+                candidates.push(Candidate { path: path_str, track: None });
+            }
+        }
+    }
+    candidates
+}
+
+fn propose(entry: &PlaylistEntry, candidates: &mut [Candidate], by_name: &HashMap<String, Vec<usize>>) -> RepairProposal {
+    let expected = expected_tags(entry);
+    let mut proposal = RepairProposal {
+        line: entry.line,
+        original: entry.raw.clone(),
+        replacement: None,
+        candidates: Vec::new(),
+        confidence: MatchConfidence::Unresolved,
+        applied: false,
+    };
+
+    let load_tags = |candidate: &mut Candidate| {
+        if candidate.track.is_none() {
+            candidate.track = track_info(Path::new(&candidate.path));
+        }
+    };
+
+    // Filename first; when several files share the name, the tags pick between them
+    let same_name = file_name_key(&entry.path).and_then(|key| by_name.get(&key)).cloned().unwrap_or_default();
+    let mut matches: Vec<usize> = same_name.clone();
+    if matches.len() > 1 {
+        for &index in &same_name {
+            load_tags(&mut candidates[index]);
+        }
+        let narrowed: Vec<usize> = same_name.iter().copied()
+            .filter(|&index| candidates[index].track.as_ref().is_some_and(|track| tags_match(&expected, track)))
+            .collect();
+        if !narrowed.is_empty() {
+            matches = narrowed;
+        }
+    }
+
+    let mut confidence = MatchConfidence::High;
+    if matches.is_empty() && expected.title.is_some() {
+        confidence = MatchConfidence::Medium;
+        for (index, candidate) in candidates.iter_mut().enumerate() {
+            load_tags(candidate);
+            if candidate.track.as_ref().is_some_and(|track| tags_match(&expected, track)) {
+                matches.push(index);
+            }
+        }
+    }
+
+    proposal.candidates = matches.iter().map(|&index| candidates[index].path.clone()).collect();
+    proposal.confidence = match matches.len() {
+        0 => MatchConfidence::Unresolved,
+        1 => {
+            proposal.replacement = Some(candidates[matches[0]].path.clone());
+            confidence
+        }
+        _ => MatchConfidence::Ambiguous,
+    };
+    proposal
+}
+
+/// Finds replacements for playlist entries whose files no longer exist. With
+/// `dry_run` the playlist is left alone; otherwise unambiguous matches are
+/// written back and everything else is reported as unresolved.
+#[tauri::command]
+pub async fn repair_playlist(path_or_name: String, search_roots: Vec<String>, dry_run: bool) -> Result<PlaylistRepairReport, String> {
+    let playlist_path: PathBuf = resolve_playlist(&path_or_name)?;
+    let mut playlist = Playlist::load(&playlist_path)?;
+    let entries = playlist.entries();
+    let staged_files = staged_paths();
+    let (staged, missing): (Vec<&PlaylistEntry>, Vec<&PlaylistEntry>) = entries.iter()
+        .filter(|entry| !entry.path.exists())
+        .partition(|entry| staged_files.contains(entry.path.to_string_lossy().as_ref()));
+
+    let mut proposals = Vec::new();
+    if !missing.is_empty() {
+        let mut candidates = gather_candidates(&search_roots);
+        let mut by_name: HashMap<String, Vec<usize>> = HashMap::new();
+        for (index, candidate) in candidates.iter().enumerate() {
+            if let Some(key) = file_name_key(Path::new(&candidate.path)) {
+                by_name.entry(key).or_default().push(index);
+            }
+        }
+        for entry in &missing {
+            proposals.push(propose(entry, &mut candidates, &by_name));
+        }
+    }
+
+    let mut applied = 0;
+    if !dry_run {
+        for proposal in proposals.iter_mut() {
+            if let Some(replacement) = &proposal.replacement {
+                playlist.replace_entry(proposal.line, Path::new(replacement));
+                proposal.applied = true;
+                applied += 1;
+            }
+        }
+        if applied > 0 {
+            playlist.save()?;
+        }
+        info!("Repaired {} of {} missing entries in {}", applied, missing.len(), playlist_path.display());
+    }
+
+    let unresolved = proposals.iter()
+        .filter(|proposal| proposal.replacement.is_none())
+        .map(|proposal| proposal.original.clone())
+        .collect();
+
+    Ok(PlaylistRepairReport {
+        playlist: playlist_path.to_string_lossy().to_string(),
+        total_entries: entries.len(),
+        missing_entries: missing.len(),
+        proposals,
+        applied,
+        unresolved,
+        staged: staged.iter().map(|entry| entry.raw.clone()).collect(),
+        dry_run,
+    })
+}
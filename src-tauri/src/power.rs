@@ -0,0 +1,129 @@
+use serde::Serialize;
+use std::path::Path;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, Instant, SystemTime};
+use once_cell::sync::Lazy;
+use parking_lot::Mutex;
+use tauri::{AppHandle, Emitter, Manager};
+use log::{info, error};
+use crate::commands::{pause_audio, reopen_playback, seek_absolute};
+use crate::output::release;
+use crate::tasks::TaskHandle;
+use crate::PlayerHandle;
+
+const TICK: Duration = Duration::from_secs(2);
+// A tick that arrives this much later than scheduled means the machine was asleep
+const SLEEP_THRESHOLD: Duration = Duration::from_secs(10);
+// Removable targets can take a while to remount after wake
+const TARGET_POLL: Duration = Duration::from_millis(500);
+
+type ResumeHook = Box<dyn Fn(&AppHandle) -> Result<(), String> + Send>;
+
+static RESUME_HOOKS: Lazy<Mutex<Vec<(&'static str, ResumeHook)>>> = Lazy::new(|| Mutex::new(Vec::new()));
+static RESUME_EPOCH: AtomicU64 = AtomicU64::new(0);
+
+#[derive(Debug, Serialize, Clone)]
+pub struct ResumedFromSleep {
+    pub slept_secs: u64,
+    pub track: Option<String>,
+    pub position: Option<f32>,
+    pub playback_restored: bool,
+    pub watchers_restarted: Vec<String>,
+    pub errors: Vec<String>,
+}
+
+/// Registers a watcher to restart after the machine wakes, since OS file and
+/// device notifications are often lost across a suspend.
+pub fn register_resume_hook(name: &'static str, hook: impl Fn(&AppHandle) -> Result<(), String> + Send + 'static) {
+    RESUME_HOOKS.lock().push((name, Box::new(hook)));
+}
+
+/// Counts detected wake-ups; long operations compare it to spot a sleep in between.
+pub fn resume_epoch() -> u64 {
+    RESUME_EPOCH.load(Ordering::SeqCst)
+}
+
+/// Blocks until `path` exists again or the task is cancelled. Returns whether it came back.
+pub fn wait_for_path(path: &Path, task: &TaskHandle) -> bool {
+    while !path.exists() {
+        if task.is_cancelled() {
+            return false;
+        }
+        std::thread::sleep(TARGET_POLL);
+    }
+    true
+}
+
+/// Reopens the output stream for the track that was loaded before sleeping and
+/// parks it, paused, at the last position read before the machine went down.
+fn restore_playback(app: &AppHandle, track: &str, position: f32) -> Result<(), String> {
+    let player = app.state::<PlayerHandle>();
+    // The stream from before sleeping may be dead
+    player.with(release);
+    reopen_playback(&player, track)?;
+    pause_audio(app.state())?;
+    seek_absolute(&player, position).map_err(String::from)
+}
+
+fn handle_resume(app: &AppHandle, slept: Duration, last_good: Option<(String, f32)>) {
+    RESUME_EPOCH.fetch_add(1, Ordering::SeqCst);
+    info!("Resumed after roughly {}s asleep", slept.as_secs());
+
+    let mut errors = Vec::new();
+    let mut playback_restored = false;
+    if let Some((track, position)) = &last_good {
+        match restore_playback(app, track, *position) {
+            Ok(()) => playback_restored = true,
+            Err(e) => errors.push(format!("Playback: {}", e)),
+        }
+    }
+
+    let mut watchers_restarted = Vec::new();
+    for (name, hook) in RESUME_HOOKS.lock().iter() {
+        match hook(app) {
+            Ok(()) => watchers_restarted.push(name.to_string()),
+            Err(e) => {
+                error!("Failed to restart {} after sleep: {}", name, e);
+                errors.push(format!("{}: {}", name, e));
+            }
+        }
+    }
+
+    app.emit("resumed-from-sleep", ResumedFromSleep {
+        slept_secs: slept.as_secs(),
+        track: last_good.as_ref().map(|(track, _)| track.clone()),
+        position: last_good.map(|(_, position)| position),
+        playback_restored,
+        watchers_restarted,
+        errors,
+    }).ok();
+}
+
+/// Watches for suspend by ticking on a timer: a tick that lands far later than
+/// scheduled, by either the wall clock or the monotonic clock (platforms differ
+/// in whether the latter counts sleep), means the machine was suspended.
+pub fn start_sleep_detector(app: AppHandle) {
+    std::thread::spawn(move || {
+        let mut last_instant = Instant::now();
+        let mut last_wall = SystemTime::now();
+        let mut last_good: Option<(String, f32)> = None;
+
+        loop {
+            std::thread::sleep(TICK);
+            let monotonic = last_instant.elapsed();
+            let wall = SystemTime::now().duration_since(last_wall).unwrap_or_default();
+            let slept = monotonic.max(wall);
+
+            if slept > TICK + SLEEP_THRESHOLD {
+                handle_resume(&app, slept, last_good.take());
+            }
+
+            last_good = app.state::<PlayerHandle>().with(|player| match (&player.current_path, &player.sink) {
+                (Some(path), Some(_)) => Some((path.clone(), player.position().as_secs_f32())),
+                _ => None,
+            });
+            last_instant = Instant::now();
+            last_wall = SystemTime::now();
+        }
+    });
+}
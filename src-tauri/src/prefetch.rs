@@ -0,0 +1,220 @@
+//! Opens the next queued track ahead of time. On slow drives opening and
+//! probing a file is a noticeable stall when a track starts, so the decoder is
+//! set up in the background with the start of the file already in memory,
+//! ready for the sink however playback moves on to it.
+
+use std::fs::{self, File};
+use std::io::{self, BufReader, Read, Seek, SeekFrom};
+use std::time::SystemTime;
+use once_cell::sync::Lazy;
+use parking_lot::Mutex;
+use log::debug;
+use crate::output_format::{open_reader_at, open_source_at, OpenedSource, PlaybackError};
+use crate::replaygain::track_gain;
+use crate::silence::playback_start;
+
+// Several seconds of audio even at lossless bitrates
+const HEAD_BYTES: usize = 2 * 1024 * 1024;
+
+/// Serves the first bytes of `inner` from memory, read ahead of time, and the
+/// rest from `inner` itself.
+pub struct HeadBuffered<R> {
+    head: Vec<u8>,
+    inner: R,
+    pos: u64,
+    inner_pos: u64,
+}
+
+impl<R: Read + Seek> HeadBuffered<R> {
+    /// Reads up to `limit` bytes from the start of `inner` straight away.
+    pub fn prefill(mut inner: R, limit: usize) -> io::Result<Self> {
+        let mut head = Vec::with_capacity(limit);
+        inner.by_ref().take(limit as u64).read_to_end(&mut head)?;
+        let inner_pos = head.len() as u64;
+        Ok(Self { head, inner, pos: 0, inner_pos })
+    }
+}
+
+impl<R: Read + Seek> Read for HeadBuffered<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.pos < self.head.len() as u64 {
+            let start = self.pos as usize;
+            let count = (self.head.len() - start).min(buf.len());
+            buf[..count].copy_from_slice(&self.head[start..start + count]);
+            self.pos += count as u64;
+            return Ok(count);
+        }
+        if self.inner_pos != self.pos {
+            self.inner_pos = self.inner.seek(SeekFrom::Start(self.pos))?;
+        }
+        let count = self.inner.read(buf)?;
+        self.pos += count as u64;
+        self.inner_pos = self.pos;
+        Ok(count)
+    }
+}
+
+impl<R: Read + Seek> Seek for HeadBuffered<R> {
+    fn seek(&mut self, from: SeekFrom) -> io::Result<u64> {
+        self.pos = match from {
+            SeekFrom::Start(pos) => pos,
+            SeekFrom::Current(offset) => self.pos.checked_add_signed(offset)
+                .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "Seek before the start of the file"))?,
+            // Only the file knows where it ends
+            SeekFrom::End(_) => {
+                self.inner_pos = self.inner.seek(from)?;
+                self.inner_pos
+            }
+        };
+        Ok(self.pos)
+    }
+}
+
+// Modification time and length, to notice a file rewritten since it was opened
+type FileStamp = (Option<SystemTime>, u64);
+
+fn stamp(path: &str) -> Option<FileStamp> {
+    fs::metadata(path).ok().map(|metadata| (metadata.modified().ok(), metadata.len()))
+}
+
+struct Prefetch {
+    path: String,
+    stamp: Option<FileStamp>,
+    // None while loading, or when it failed and is left to the normal open
+    ready: Option<(OpenedSource, f32)>,
+}
+
+static PREFETCH: Lazy<Mutex<Option<Prefetch>>> = Lazy::new(|| Mutex::new(None));
+
+fn load(path: &str) -> Result<(OpenedSource, f32), PlaybackError> {
+    let file = BufReader::new(File::open(path).map_err(|e| PlaybackError::opening(path, e))?);
+    let reader = HeadBuffered::prefill(file, HEAD_BYTES).map_err(|e| e.to_string())?;
+    let opened = open_reader_at(path, reader, playback_start(path))?;
+    Ok((opened, track_gain(path)))
+}
+
+/// Makes `path` the track to have ready, starting to open it in the background
+/// if it isn't already. `None` drops whatever was prefetched.
+pub fn prepare(path: Option<&str>) {
+    let mut slot = PREFETCH.lock();
+    let Some(path) = path else {
+        *slot = None;
+        return;
+    };
+    if slot.as_ref().is_some_and(|prefetch| prefetch.path == path && prefetch.stamp == stamp(path)) {
+        return;
+    }
+    let file_stamp = stamp(path);
+    *slot = Some(Prefetch { path: path.to_string(), stamp: file_stamp, ready: None });
+    drop(slot);
+
+    let path = path.to_string();
+    std::thread::spawn(move || {
+        let loaded = match load(&path) {
+            Ok(loaded) => loaded,
+            Err(e) => {
+                debug!("Could not prefetch {}: {}", path, e);
+                return;
+            }
+        };
+        let mut slot = PREFETCH.lock();
+        // The queue may have moved on while it loaded
+        if let Some(prefetch) = slot.as_mut().filter(|prefetch| prefetch.path == path && prefetch.stamp == file_stamp) {
+            debug!("Prefetched {}", path);
+            prefetch.ready = Some(loaded);
+        }
+    });
+}
+
+/// The prefetched source for `path`, if it is ready and the file hasn't
+/// changed since; each one is handed out once.
+pub fn take(path: &str) -> Option<(OpenedSource, f32)> {
+    let mut slot = PREFETCH.lock();
+    if slot.as_ref().is_none_or(|prefetch| prefetch.path != path || prefetch.ready.is_none()) {
+        return None;
+    }
+    let prefetch = slot.take()?;
+    if prefetch.stamp != stamp(path) {
+        debug!("{} changed since it was prefetched", path);
+        return None;
+    }
+    prefetch.ready
+}
+
+/// The prefetched source for `path` and its ReplayGain, or both read now.
+pub fn take_or_open(path: &str) -> Result<(OpenedSource, f32), PlaybackError> {
+    match take(path) {
+        Some(prefetched) => Ok(prefetched),
+        None => Ok((open_source_at(path, playback_start(path))?, track_gain(path))),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+    use rodio::Decoder;
+    use crate::test_support::{sine_samples, wav_bytes, SAMPLE_RATE};
+
+    /// A drive where every read is slow, so the number of reads is what counts.
+    struct CountingReader {
+        inner: Cursor<Vec<u8>>,
+        reads: Arc<AtomicUsize>,
+    }
+
+    impl Read for CountingReader {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            self.reads.fetch_add(1, Ordering::SeqCst);
+            self.inner.read(buf)
+        }
+    }
+
+    impl Seek for CountingReader {
+        fn seek(&mut self, from: SeekFrom) -> io::Result<u64> {
+            self.inner.seek(from)
+        }
+    }
+
+    fn counted_wav() -> (CountingReader, Arc<AtomicUsize>) {
+        let reads = Arc::new(AtomicUsize::new(0));
+        (CountingReader { inner: Cursor::new(wav_bytes(&sine_samples(SAMPLE_RATE, 440.0, 10.0))), reads: Arc::clone(&reads) }, reads)
+    }
+
+    /// The first seconds of the track, as decoded when it starts.
+    fn opening<R: Read + Seek + Send + Sync + 'static>(reader: R) -> Vec<i16> {
+        Decoder::new(reader).unwrap().take(8_000 * 5).collect()
+    }
+
+    #[test]
+    fn prefilled_reader_starts_without_touching_the_drive() {
+        let (direct, direct_reads) = counted_wav();
+        let expected = opening(BufReader::new(direct));
+        assert!(direct_reads.load(Ordering::SeqCst) > 0);
+
+        // Prefilling happens in the background while the previous track plays
+        let (reader, reads) = counted_wav();
+        let prefilled = HeadBuffered::prefill(BufReader::new(reader), HEAD_BYTES).unwrap();
+        let prefill_reads = reads.load(Ordering::SeqCst);
+        assert_eq!(opening(prefilled), expected);
+        assert_eq!(reads.load(Ordering::SeqCst), prefill_reads);
+    }
+
+    #[test]
+    fn reads_past_the_head_come_from_the_inner_reader() {
+        let data: Vec<u8> = (0..=255).collect();
+        let mut reader = HeadBuffered::prefill(Cursor::new(data.clone()), 16).unwrap();
+        let mut all = Vec::new();
+        reader.read_to_end(&mut all).unwrap();
+        assert_eq!(all, data);
+
+        reader.seek(SeekFrom::Start(10)).unwrap();
+        let mut straddling = [0u8; 10];
+        reader.read_exact(&mut straddling).unwrap();
+        assert_eq!(straddling, [10, 11, 12, 13, 14, 15, 16, 17, 18, 19]);
+        assert_eq!(reader.seek(SeekFrom::End(-1)).unwrap(), 255);
+        assert_eq!(reader.seek(SeekFrom::Current(-5)).unwrap(), 250);
+        assert!(reader.seek(SeekFrom::Current(-300)).is_err());
+    }
+}
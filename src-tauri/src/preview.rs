@@ -0,0 +1,115 @@
+use std::fs::File;
+use std::io::BufReader;
+use std::path::Path;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::mpsc;
+use std::time::Duration;
+use parking_lot::Mutex;
+use rodio::{Decoder, OutputStream, Sink, Source};
+use tauri::State;
+use log::debug;
+use crate::config::load_player_config;
+use crate::library::track_info;
+use crate::PlayerHandle;
+
+const POLL_INTERVAL: Duration = Duration::from_millis(50);
+const PREVIEW_FADE: Duration = Duration::from_millis(150);
+const MAX_PREVIEW_SECS: f32 = 60.0;
+
+// Bumped by every play/stop call; a preview thread exits once it is no longer current
+static GENERATION: AtomicU64 = AtomicU64::new(0);
+// Multiplier applied to the main sink while a preview plays
+static DUCK_FACTOR: Mutex<Option<f32>> = Mutex::new(None);
+
+/// Factor to apply to the main playback volume; below 1.0 while a preview is ducking it.
+pub fn main_volume_factor() -> f32 {
+    DUCK_FACTOR.lock().unwrap_or(1.0)
+}
+
+fn apply_duck(player: &PlayerHandle, factor: Option<f32>) {
+    *DUCK_FACTOR.lock() = factor;
+    player.send(|player| {
+        if let Some(sink) = &player.sink {
+            sink.set_volume(player.output_gain());
+        }
+    });
+}
+
+/// Restores main playback unless a newer preview has taken over, which keeps
+/// the duck in place rather than letting the volume bounce between previews.
+fn end_preview(player: &PlayerHandle, generation: u64) {
+    if GENERATION.load(Ordering::SeqCst) == generation {
+        apply_duck(player, None);
+    }
+}
+
+fn open_preview(path: &Path, start_fraction: f32, duration: Duration) -> Result<impl Source<Item = i16> + Send, String> {
+    let file = BufReader::new(File::open(path).map_err(|e| e.to_string())?);
+    let source = Decoder::new(file).map_err(|e| e.to_string())?;
+    // Decoders can't always tell the length of VBR files, so fall back to the tags
+    let total = source.total_duration()
+        .or_else(|| track_info(path).and_then(|track| track.duration).map(Duration::from_secs_f64))
+        .unwrap_or_default();
+    let start = total.mul_f32(start_fraction.clamp(0.0, 1.0));
+    // Keep a full snippet when the requested start is too close to the end
+    let start = start.min(total.saturating_sub(duration));
+
+    Ok(source
+        .skip_duration(start)
+        .take_duration(duration)
+        .fade_in(PREVIEW_FADE))
+}
+
+/// Plays a short snippet of `path` on its own output, separate from the main
+/// player: it never touches the current track, the queue or play tracking.
+/// Starting a preview cancels the previous one.
+#[tauri::command]
+pub fn play_preview(player: State<'_, PlayerHandle>, path: String, start_fraction: f32, duration_secs: f32) -> Result<(), String> {
+    let generation = GENERATION.fetch_add(1, Ordering::SeqCst) + 1;
+    let duration = Duration::from_secs_f32(duration_secs.clamp(0.5, MAX_PREVIEW_SECS));
+    let settings = load_player_config().preview;
+    let source = open_preview(Path::new(&path), start_fraction, duration).inspect_err(|_| end_preview(&player, generation))?;
+
+    // OutputStream is not Send, so the preview's stream lives and dies on its own thread
+    let (ready_tx, ready_rx) = mpsc::channel();
+    let player = player.inner().clone();
+    std::thread::spawn(move || {
+        let opened = OutputStream::try_default()
+            .map_err(|e| e.to_string())
+            .and_then(|(stream, handle)| Sink::try_new(&handle).map(|sink| (stream, sink)).map_err(|e| e.to_string()));
+        let (_stream, sink) = match opened {
+            Ok(opened) => opened,
+            Err(e) => {
+                end_preview(&player, generation);
+                let _ = ready_tx.send(Err(e));
+                return;
+            }
+        };
+        // A newer preview may have started while this one was opening
+        if GENERATION.load(Ordering::SeqCst) != generation {
+            let _ = ready_tx.send(Ok(()));
+            return;
+        }
+
+        sink.set_volume(settings.volume.clamp(0.0, 1.0));
+        sink.append(source);
+        apply_duck(&player, Some(1.0 - settings.duck_amount.clamp(0.0, 1.0)));
+        let _ = ready_tx.send(Ok(()));
+
+        while GENERATION.load(Ordering::SeqCst) == generation && !sink.empty() {
+            std::thread::sleep(POLL_INTERVAL);
+        }
+        sink.stop();
+
+        end_preview(&player, generation);
+        debug!("Preview {} finished", generation);
+    });
+
+    ready_rx.recv().map_err(|_| "Preview thread exited unexpectedly".to_string())?
+}
+
+#[tauri::command]
+pub fn stop_preview(player: State<'_, PlayerHandle>) {
+    GENERATION.fetch_add(1, Ordering::SeqCst);
+    apply_duck(&player, None);
+}
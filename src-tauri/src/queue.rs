@@ -0,0 +1,1077 @@
+use serde::Serialize;
+use std::cell::Cell;
+use std::cmp::Ordering;
+use std::collections::{HashMap, HashSet};
+use std::path::Path;
+use std::time::Duration;
+use once_cell::sync::Lazy;
+use parking_lot::Mutex;
+use rand::seq::SliceRandom;
+use tauri::{AppHandle, Emitter, Manager, State};
+use log::{error, info};
+use crate::commands::{emit_playback_state, playback_modes, start_playback};
+use crate::crossfade;
+use crate::playlist::{relative_path, resolve_playlist, ExtInf, Playlist};
+use crate::output_format::{report_failure, PlaybackErrorKind};
+use crate::config::{load_player_config, update_player_config, RepeatMode, ShuffleMode};
+use crate::library::{track_info, LibraryTrack, LIBRARY};
+use crate::paths::canonical_key;
+use crate::sorting::natural_cmp;
+use crate::text::{collate, fold, same_text};
+use crate::{gapless, play_stats, FileItem, PlaybackStatus, PlayerHandle, PlayerState};
+
+#[derive(Debug, Serialize, Clone)]
+pub struct QueuedTrack {
+    pub path: String,
+    pub title: Option<String>,
+    pub artist: Option<String>,
+    pub album: Option<String>,
+    pub album_artist: Option<String>,
+    pub track_number: Option<u32>,
+    pub duration: Option<f64>,
+}
+
+pub struct PlayQueue {
+    pub tracks: Vec<QueuedTrack>,
+    pub position: usize,
+    pub repeat_mode: RepeatMode,
+    pub shuffle_mode: ShuffleMode,
+    // The order the queue was built in, restored when shuffle is turned off
+    pub original_order: Vec<String>,
+}
+
+// How often the auto-advance watcher checks whether the current track has finished
+const ADVANCE_POLL: Duration = Duration::from_millis(250);
+
+#[derive(Debug, Serialize, Clone)]
+pub struct TrackChanged {
+    pub path: String,
+    pub position: usize,
+    pub track: QueuedTrack,
+}
+
+#[derive(Debug, Serialize, Clone)]
+pub struct PlaybackEnded {
+    pub last_path: Option<String>,
+    // Set when the next track couldn't be started
+    pub error: Option<String>,
+}
+
+/// Payload of `playback-state-changed`.
+#[derive(Debug, Serialize, Clone)]
+pub struct PlaybackStatusChanged {
+    pub status: PlaybackStatus,
+    pub path: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct QueueBuildResult {
+    pub tracks: Vec<QueuedTrack>,
+    pub position: usize,
+    pub repeat_mode: RepeatMode,
+    pub shuffle_mode: ShuffleMode,
+    // Indexed files that no longer exist on disk; rescanning the library drops them
+    pub missing: Vec<String>,
+}
+
+// Lock order: the player, then the queue. Whoever holds the queue must not
+// wait on the playback thread, which may itself be waiting for the queue, so
+// nothing locks it directly: `with_queue` runs code against it and
+// `PlayerHandle::with_queue` takes both in order.
+static PLAY_QUEUE: Lazy<Mutex<PlayQueue>> = Lazy::new(|| {
+    let settings = load_player_config().playback_settings;
+    Mutex::new(PlayQueue {
+        tracks: Vec::new(),
+        position: 0,
+        repeat_mode: settings.repeat_mode,
+        shuffle_mode: settings.effective_shuffle_mode(),
+        original_order: Vec::new(),
+    })
+});
+
+thread_local! {
+    static HOLDING_QUEUE: Cell<bool> = const { Cell::new(false) };
+}
+
+/// Runs `f` with the queue locked. `f` must not wait on the player; use
+/// `PlayerHandle::with_queue` to work on both.
+pub fn with_queue<T>(f: impl FnOnce(&mut PlayQueue) -> T) -> T {
+    struct Held;
+    impl Drop for Held {
+        fn drop(&mut self) {
+            HOLDING_QUEUE.with(|held| held.set(false));
+        }
+    }
+    let mut queue = PLAY_QUEUE.lock();
+    HOLDING_QUEUE.with(|held| held.set(true));
+    let _held = Held;
+    f(&mut queue)
+}
+
+/// Whether this thread is inside `with_queue`, where waiting on the player could deadlock.
+pub fn holding_queue() -> bool {
+    HOLDING_QUEUE.with(Cell::get)
+}
+
+impl From<LibraryTrack> for QueuedTrack {
+    fn from(track: LibraryTrack) -> Self {
+        Self {
+            path: track.path,
+            title: track.title,
+            artist: track.artist,
+            album: track.album,
+            album_artist: track.album_artist,
+            track_number: track.track_number,
+            duration: track.duration,
+        }
+    }
+}
+
+fn same_name(value: Option<&String>, name: &str) -> bool {
+    value.is_some_and(|value| same_text(value, name))
+}
+
+fn file_name(path: &str) -> &str {
+    Path::new(path).file_name().and_then(|n| n.to_str()).unwrap_or(path)
+}
+
+fn album_order(a: &LibraryTrack, b: &LibraryTrack) -> Ordering {
+    a.track_number.unwrap_or(u32::MAX)
+        .cmp(&b.track_number.unwrap_or(u32::MAX))
+        .then_with(|| natural_cmp(file_name(&a.path), file_name(&b.path)))
+}
+
+/// Albums in release order, each kept together in track order.
+fn discography_order(a: &LibraryTrack, b: &LibraryTrack) -> Ordering {
+    a.year.unwrap_or(u32::MAX)
+        .cmp(&b.year.unwrap_or(u32::MAX))
+        .then_with(|| collate(a.album.as_deref().unwrap_or(""), b.album.as_deref().unwrap_or("")))
+        .then_with(|| album_order(a, b))
+}
+
+fn library_tracks(filter: impl Fn(&LibraryTrack) -> bool) -> Vec<LibraryTrack> {
+    LIBRARY.lock().tracks.values()
+        .filter(|track| filter(track))
+        .cloned()
+        .collect()
+}
+
+/// Album identity for album shuffle; untagged tracks group by folder.
+fn album_key(track: &QueuedTrack) -> String {
+    let (album, artist) = if track.album.is_some() {
+        (track.album.clone(), track.album_artist.clone().or_else(|| track.artist.clone()))
+    } else {
+        // Tracks queued from outside the library may only have tags in the metadata cache
+        track_info(Path::new(&track.path))
+            .map(|info| (info.album, info.album_artist.or(info.artist)))
+            .unwrap_or((None, None))
+    };
+
+    match album {
+        Some(album) => format!("{}\u{0}{}", fold(&artist.unwrap_or_default()), fold(&album)),
+        None => format!(
+            "folder\u{0}{}",
+            Path::new(&track.path).parent().map(|p| p.to_string_lossy().to_string()).unwrap_or_default()
+        ),
+    }
+}
+
+fn queued_album_order(a: &QueuedTrack, b: &QueuedTrack) -> Ordering {
+    a.track_number.unwrap_or(u32::MAX)
+        .cmp(&b.track_number.unwrap_or(u32::MAX))
+        .then_with(|| natural_cmp(file_name(&a.path), file_name(&b.path)))
+}
+
+/// Orders `tracks` for `mode`. `original_order` is the build order, used when shuffle is off.
+fn order_tracks(tracks: &mut Vec<QueuedTrack>, mode: ShuffleMode, original_order: &[String]) {
+    match mode {
+        ShuffleMode::Off => {
+            let positions: HashMap<&str, usize> = original_order.iter()
+                .enumerate()
+                .map(|(index, path)| (path.as_str(), index))
+                .collect();
+            tracks.sort_by_key(|track| positions.get(track.path.as_str()).copied().unwrap_or(usize::MAX));
+        }
+        ShuffleMode::Tracks => tracks.shuffle(&mut rand::thread_rng()),
+        ShuffleMode::Albums => {
+            let mut keys: HashMap<String, usize> = HashMap::new();
+            let mut albums: Vec<Vec<QueuedTrack>> = Vec::new();
+            for track in tracks.drain(..) {
+                let index = *keys.entry(album_key(&track)).or_insert_with(|| {
+                    albums.push(Vec::new());
+                    albums.len() - 1
+                });
+                albums[index].push(track);
+            }
+
+            albums.shuffle(&mut rand::thread_rng());
+            for mut album in albums {
+                album.sort_by(queued_album_order);
+                tracks.extend(album);
+            }
+        }
+    }
+}
+
+/// Swaps a different track to the front if `tracks` would open with `previous`,
+/// so a shuffle never plays the same file twice in a row.
+fn avoid_immediate_repeat(tracks: &mut [QueuedTrack], previous: &str) {
+    if tracks.first().is_some_and(|track| track.path == previous) {
+        if let Some(other) = tracks.iter().position(|track| track.path != previous) {
+            tracks.swap(0, other);
+        }
+    }
+}
+
+impl PlayQueue {
+    /// With shuffle and repeat-all on, reshuffles everything before the last
+    /// track once that becomes current, so the next pass through the queue
+    /// plays in a new order that doesn't open with the track just heard.
+    pub fn reshuffle_for_wrap(&mut self) {
+        let on_last = self.position + 1 == self.tracks.len();
+        if self.repeat_mode != RepeatMode::All || self.shuffle_mode == ShuffleMode::Off || !on_last {
+            return;
+        }
+        let Some(current) = self.tracks.pop() else { return };
+        order_tracks(&mut self.tracks, self.shuffle_mode, &self.original_order);
+        avoid_immediate_repeat(&mut self.tracks, &current.path);
+        self.tracks.push(current);
+    }
+
+    /// Index of the first track after the current one.
+    pub fn upcoming_start(&self) -> usize {
+        (self.position + 1).min(self.tracks.len())
+    }
+
+    fn push(&mut self, track: QueuedTrack) {
+        self.original_order.push(track.path.clone());
+        self.tracks.push(track);
+    }
+
+    /// Keeps the unshuffled order in step with manual edits, so turning shuffle
+    /// off later doesn't undo them.
+    fn sync_original_order(&mut self) {
+        if self.shuffle_mode == ShuffleMode::Off {
+            self.original_order = self.tracks.iter().map(|track| track.path.clone()).collect();
+        }
+    }
+
+    fn remove(&mut self, index: usize) -> Result<QueuedTrack, String> {
+        if index >= self.tracks.len() {
+            return Err(format!("No track at queue position {}", index));
+        }
+        if index == self.position {
+            return Err("The current track can't be removed from the queue".to_string());
+        }
+        let track = self.tracks.remove(index);
+        if index < self.position {
+            self.position -= 1;
+        }
+        if let Some(original) = self.original_order.iter().position(|path| *path == track.path) {
+            self.original_order.remove(original);
+        }
+        Ok(track)
+    }
+
+    /// Moves the track at `from` to `to`; the current track stays current wherever it ends up.
+    fn move_item(&mut self, from: usize, to: usize) -> Result<(), String> {
+        let len = self.tracks.len();
+        if from >= len || to >= len {
+            return Err(format!("Queue positions {} and {} must be below {}", from, to, len));
+        }
+        let track = self.tracks.remove(from);
+        self.tracks.insert(to, track);
+        self.position = if self.position == from {
+            to
+        } else if from < self.position && to >= self.position {
+            self.position - 1
+        } else if from > self.position && to <= self.position {
+            self.position + 1
+        } else {
+            self.position
+        };
+        self.sync_original_order();
+        Ok(())
+    }
+
+    /// Puts `tracks` straight after the current one, ahead of the rest of the
+    /// upcoming tracks whatever the shuffle order. With shuffle off they also
+    /// follow the current track in the order restored later.
+    fn insert_next(&mut self, tracks: Vec<QueuedTrack>) {
+        let at = self.upcoming_start();
+        let original_at = self.tracks.get(self.position)
+            .and_then(|current| self.original_order.iter().position(|path| *path == current.path))
+            .map_or(self.original_order.len(), |index| index + 1);
+        self.original_order.splice(original_at..original_at, tracks.iter().map(|track| track.path.clone()));
+        self.tracks.splice(at..at, tracks);
+    }
+
+    /// Removes entries for files already earlier in the queue, as told apart by
+    /// `key`, and returns how many went. The current entry always stays.
+    fn dedupe(&mut self, key: impl Fn(&str) -> String) -> usize {
+        let mut seen = HashSet::new();
+        let keep: Vec<bool> = self.tracks.iter().enumerate()
+            .map(|(index, track)| seen.insert(key(&track.path)) || index == self.position)
+            .collect();
+        let removed: Vec<String> = self.tracks.iter().zip(&keep)
+            .filter(|(_, keep)| !**keep)
+            .map(|(track, _)| track.path.clone())
+            .collect();
+        self.position -= keep.iter().take(self.position).filter(|keep| !**keep).count();
+        let mut keep = keep.into_iter();
+        self.tracks.retain(|_| keep.next().unwrap_or(true));
+        // The later copies go from the unshuffled order too
+        for path in &removed {
+            if let Some(index) = self.original_order.iter().rposition(|original| original == path) {
+                self.original_order.remove(index);
+            }
+        }
+        removed.len()
+    }
+
+    /// `tracks` less those already queued, and repeats among them.
+    fn not_yet_queued(&self, tracks: Vec<QueuedTrack>) -> Vec<QueuedTrack> {
+        let mut seen: HashSet<String> = self.tracks.iter().map(|track| canonical_key(&track.path)).collect();
+        tracks.into_iter().filter(|track| seen.insert(canonical_key(&track.path))).collect()
+    }
+
+    /// Drops every track after the current one.
+    fn clear_upcoming(&mut self) {
+        let start = self.upcoming_start();
+        let removed: Vec<QueuedTrack> = self.tracks.split_off(start);
+        self.original_order.retain(|path| !removed.iter().any(|track| track.path == *path));
+    }
+}
+
+/// What plays once `current` finishes. A track played from outside the queue
+/// is followed by the queue's current entry; `position` past the end means the
+/// queue has been played through.
+pub fn next_index(queue: &PlayQueue, current: Option<&str>) -> Option<usize> {
+    following(queue, current, queue.repeat_mode)
+}
+
+/// What a manual skip moves to: repeating a single track doesn't hold it back.
+fn skip_index(queue: &PlayQueue, current: Option<&str>) -> Option<usize> {
+    let repeat_mode = match queue.repeat_mode {
+        RepeatMode::Single => RepeatMode::Off,
+        mode => mode,
+    };
+    following(queue, current, repeat_mode)
+}
+
+fn following(queue: &PlayQueue, current: Option<&str>, repeat_mode: RepeatMode) -> Option<usize> {
+    let from_queue = queue.tracks.get(queue.position).is_some_and(|track| Some(track.path.as_str()) == current);
+    if !from_queue {
+        return (queue.position < queue.tracks.len()).then_some(queue.position);
+    }
+    match repeat_mode {
+        RepeatMode::Single => Some(queue.position),
+        _ if queue.position + 1 < queue.tracks.len() => Some(queue.position + 1),
+        RepeatMode::All => Some(0),
+        RepeatMode::Off => None,
+    }
+}
+
+/// Whether the current track has run out, or is close enough to the end to
+/// start crossfading into the next.
+fn track_ending(player: &PlayerState) -> bool {
+    // The loop monitor restarts a looped track that runs out
+    if player.loop_region.is_some() {
+        return false;
+    }
+    player.sink.as_ref().is_some_and(|sink| sink.empty()) || player.past_trim_end() || crossfade::due(player)
+}
+
+/// Starts the next track after the one seen finishing at `generation`. Does
+/// nothing if playback was stopped, seeked or restarted since. `skipping`
+/// moves on even though the current track is still playing.
+fn advance(app: &AppHandle, generation: u64, finished: Option<String>, skipping: bool) {
+    let handle = app.state::<PlayerHandle>();
+    let claim = handle.with_queue(move |player, queue| {
+        let mut finished = finished;
+        let active = player.is_playing || (skipping && player.sink.is_some());
+        if player.generation != generation || !active || !(skipping || track_ending(player)) {
+            return None;
+        }
+        // With nothing to fade into, the last track plays out to its real end
+        let stopping = !skipping && player.stop_after_current;
+        let sounding = player.sink.as_ref().is_some_and(|sink| !sink.empty()) && !player.past_trim_end();
+        if sounding && !skipping && (stopping || next_index(queue, finished.as_deref()).is_none()) {
+            return None;
+        }
+        // Claim this ending so a second look can't advance again
+        player.is_playing = false;
+        player.generation += 1;
+
+        // A preload the sink ran through before the watcher saw it start counts
+        // as played, so the queue doesn't repeat it. One that hasn't started
+        // yet goes with the sink when the next track replaces it.
+        let ran_out = player.sink.as_ref().is_some_and(|sink| sink.empty());
+        if let Some(preloaded) = player.preloaded.take().filter(|_| ran_out) {
+            queue.position = preloaded.position.min(queue.tracks.len());
+            finished = Some(preloaded.path);
+        }
+
+        // Stopping after this track leaves the queue where it is, to carry on from later
+        let next = if stopping {
+            player.stop_after_current = false;
+            None
+        } else {
+            let next = if skipping { skip_index(queue, finished.as_deref()) } else { next_index(queue, finished.as_deref()) };
+            queue.position = next.unwrap_or(queue.tracks.len());
+            queue.reshuffle_for_wrap();
+            next
+        };
+        if next.is_none() {
+            if let Some(sink) = &player.sink {
+                sink.stop();
+            }
+        }
+        Some((next.map(|index| (index, queue.tracks[index].clone())), stopping, player.generation, finished))
+    });
+    let Some((next, stopping, claimed, finished)) = claim else { return };
+
+    let Some((mut position, mut track)) = next else {
+        app.emit("playback-ended", PlaybackEnded { last_path: finished, error: None }).ok();
+        if stopping {
+            emit_playback_state(app);
+        }
+        return;
+    };
+    // A track that won't start is reported and skipped, at most once round the queue
+    let mut attempts = with_queue(|queue| queue.tracks.len());
+    loop {
+        let error = match crossfade::start_next(&handle, &track.path) {
+            Ok(()) => {
+                app.emit("track-changed", TrackChanged { path: track.path.clone(), position, track }).ok();
+                return;
+            }
+            Err(e) => e,
+        };
+        error!("Failed to start next track {}: {}", track.path, error);
+        report_failure(app, &error, Some(&track.path));
+        attempts = attempts.saturating_sub(1);
+
+        // Without a device every other track would fail the same way
+        let following = if error.kind() == PlaybackErrorKind::DeviceError || attempts == 0 {
+            None
+        } else {
+            let failed = track.path.clone();
+            let following = handle.with_queue(move |player, queue| {
+                if player.generation != claimed {
+                    // Something else was started meanwhile
+                    return None;
+                }
+                let next = skip_index(queue, Some(&failed));
+                queue.position = next.unwrap_or(queue.tracks.len());
+                queue.reshuffle_for_wrap();
+                Some(next.map(|index| (index, queue.tracks[index].clone())))
+            });
+            let Some(following) = following else { return };
+            following
+        };
+        let Some(following) = following else {
+            app.emit("playback-ended", PlaybackEnded { last_path: finished, error: Some(error.to_string()) }).ok();
+            return;
+        };
+        (position, track) = following;
+    }
+}
+
+/// Notices the status changing, and clears `is_playing` once the sink has
+/// been dry for two looks in a row with nothing started in its place, as when
+/// a decoder gives up mid-track.
+struct StatusWatch {
+    last: PlaybackStatus,
+    dry: bool,
+}
+
+impl StatusWatch {
+    /// The new status, if it changed since the last look.
+    fn check(&mut self, player: &mut PlayerState) -> Option<PlaybackStatus> {
+        // A looped track that runs out is restarted by the loop monitor
+        let dry = player.is_playing && player.loop_region.is_none() && player.sink.as_ref().is_none_or(|sink| sink.empty());
+        if dry && self.dry {
+            player.is_playing = false;
+        }
+        self.dry = dry;
+        let status = player.status();
+        (status != self.last).then(|| {
+            self.last = status;
+            status
+        })
+    }
+}
+
+/// Watches for the current track running out and moves on through the queue,
+/// emitting `track-changed`, or `playback-ended` once nothing is left. Tracks
+/// that won't start are reported with `playback-error` and skipped. Whenever
+/// playback starts, pauses or stops, `playback-state-changed` says so.
+pub fn start_auto_advance(app: AppHandle) {
+    let mut watch = StatusWatch { last: PlaybackStatus::Stopped, dry: false };
+    std::thread::spawn(move || loop {
+        std::thread::sleep(ADVANCE_POLL);
+        gapless::poll(&app);
+        play_stats::poll(&app);
+        let handle = app.state::<PlayerHandle>();
+        let finished = handle.with(|player| match &player.sink {
+            Some(_) if player.is_playing && track_ending(player) => Some((player.generation, player.current_path.clone())),
+            _ => None,
+        });
+        if let Some((generation, path)) = finished {
+            advance(&app, generation, path, false);
+        }
+        let (checked, changed) = handle.with(move |player| {
+            let changed = watch.check(player).map(|status| PlaybackStatusChanged { status, path: player.current_path.clone() });
+            (watch, changed)
+        });
+        watch = checked;
+        if let Some(changed) = changed {
+            app.emit("playback-state-changed", changed).ok();
+        }
+    });
+}
+
+/// Moves straight on to the next queue entry, as if the current track had
+/// ended, even when repeating a single track. A paused player starts playing.
+pub fn skip_to_next(app: &AppHandle) {
+    let (generation, current) = app.state::<PlayerHandle>().with(|player| (player.generation, player.current_path.clone()));
+    advance(app, generation, current, true);
+}
+
+pub fn queued_track(path: &str) -> Result<QueuedTrack, String> {
+    let file = Path::new(path);
+    if !file.is_file() {
+        return Err(format!("{} does not exist", path));
+    }
+    Ok(QueuedTrack::from(track_info(file).ok_or_else(|| format!("Failed to read {}", path))?))
+}
+
+/// Appends `path` to the end of the queue without interrupting playback.
+/// With `allow_duplicates` false, a file already queued is refused.
+#[tauri::command]
+pub fn enqueue_track(path: &str, allow_duplicates: Option<bool>) -> Result<QueuedTrack, String> {
+    let track = queued_track(path)?;
+    with_queue(|queue| {
+        if !allow_duplicates.unwrap_or(true) && queue.not_yet_queued(vec![track.clone()]).is_empty() {
+            return Err(format!("{} is already in the queue", path));
+        }
+        queue.push(track.clone());
+        Ok(track)
+    })
+}
+
+/// Appends `paths` in order and returns what was queued. Nothing is queued if
+/// any of them can't be read. With `allow_duplicates` false, files already
+/// queued, or repeated in `paths`, are left out.
+#[tauri::command]
+pub fn enqueue_tracks(paths: Vec<String>, allow_duplicates: Option<bool>) -> Result<Vec<QueuedTrack>, String> {
+    let tracks = paths.iter().map(|path| queued_track(path)).collect::<Result<Vec<_>, _>>()?;
+    with_queue(|queue| {
+        let tracks = if allow_duplicates.unwrap_or(true) { tracks } else { queue.not_yet_queued(tracks) };
+        for track in &tracks {
+            queue.push(track.clone());
+        }
+        Ok(tracks)
+    })
+}
+
+/// Removes later copies of files queued more than once, keeping the first and
+/// never the current entry. Returns how many were removed.
+#[tauri::command]
+pub fn dedupe_queue() -> usize {
+    with_queue(|queue| queue.dedupe(canonical_key))
+}
+
+#[derive(Debug, Serialize)]
+pub struct PlaylistQueued {
+    pub tracks: Vec<QueuedTrack>,
+    // Entries left out because their files are gone or unreadable
+    pub skipped: Vec<String>,
+}
+
+/// Appends the entries of a playlist file (or stored playlist name) in order,
+/// leaving out those that can't be played.
+#[tauri::command]
+pub fn enqueue_playlist(path: String) -> Result<PlaylistQueued, String> {
+    let playlist = Playlist::load(&resolve_playlist(&path)?)?;
+    let mut tracks = Vec::new();
+    let mut skipped = Vec::new();
+    for entry in playlist.entries() {
+        let path = entry.path.to_string_lossy().to_string();
+        match queued_track(&path) {
+            Ok(track) => tracks.push(track),
+            Err(_) => skipped.push(path),
+        }
+    }
+    if tracks.is_empty() {
+        return Err(format!("None of the {} entries in the playlist could be found", skipped.len()));
+    }
+    append_to_queue(tracks.clone());
+    Ok(PlaylistQueued { tracks, skipped })
+}
+
+/// Appends `tracks` under a single lock, so nothing lands between them.
+pub fn append_to_queue(tracks: Vec<QueuedTrack>) -> usize {
+    let count = tracks.len();
+    with_queue(|queue| {
+        for track in tracks {
+            queue.push(track);
+        }
+    });
+    count
+}
+
+/// Queues `path` to play right after the current track. Returns the updated queue.
+#[tauri::command]
+pub fn play_next(path: String) -> Result<Vec<FileItem>, String> {
+    play_next_tracks(vec![path])
+}
+
+/// Queues `paths`, in order, to play right after the current track, as for an
+/// album. Nothing is queued if any of them can't be read.
+#[tauri::command]
+pub fn play_next_tracks(paths: Vec<String>) -> Result<Vec<FileItem>, String> {
+    let tracks = paths.iter().map(|path| queued_track(path)).collect::<Result<Vec<_>, _>>()?;
+    with_queue(|queue| queue.insert_next(tracks));
+    Ok(get_queue())
+}
+
+#[tauri::command]
+pub fn remove_from_queue(index: usize) -> Result<QueuedTrack, String> {
+    with_queue(|queue| queue.remove(index))
+}
+
+#[tauri::command]
+pub fn move_queue_item(from: usize, to: usize) -> Result<(), String> {
+    with_queue(|queue| queue.move_item(from, to))
+}
+
+/// Removes the upcoming tracks; the current one keeps playing.
+pub fn clear_upcoming() {
+    with_queue(PlayQueue::clear_upcoming);
+}
+
+/// The whole queue in play order; `get_player_state` reports which entry is current.
+#[tauri::command]
+pub fn get_queue() -> Vec<FileItem> {
+    with_queue(|queue| queue.tracks.iter()
+        .map(|track| FileItem {
+            name: file_name(&track.path).to_string(),
+            path: track.path.clone(),
+            is_dir: false,
+            is_audio: true,
+            modified: None,
+            added_at: None,
+            excluded: false,
+            missing: false,
+        })
+        .collect())
+}
+
+/// The queue as an M3U8 playlist at `path`, its entries written relative to
+/// the playlist's folder when `relative` is set and the two share a root.
+fn queue_playlist(tracks: &[QueuedTrack], path: &Path, relative: bool) -> Playlist {
+    let mut playlist = Playlist::new(path);
+    let base = path.parent().unwrap_or(Path::new(""));
+    for track in tracks {
+        let target = Path::new(&track.path);
+        let written = relative.then(|| relative_path(target, base)).flatten();
+        let info = ExtInf { duration: track.duration, artist: track.artist.clone(), title: track.title.clone() };
+        let known = info.duration.is_some() || info.title.is_some();
+        playlist.append_entry(written.as_deref().unwrap_or(target), known.then_some(&info));
+    }
+    playlist
+}
+
+/// Writes the queue to an `.m3u8` playlist and returns how many entries it
+/// has. An existing file is only replaced when `overwrite` is set.
+#[tauri::command]
+pub fn save_queue_as_playlist(path: String, relative: bool, overwrite: Option<bool>) -> Result<usize, String> {
+    let path = Path::new(&path);
+    if path.exists() && !overwrite.unwrap_or(false) {
+        return Err(format!("{} already exists", path.display()));
+    }
+    let tracks = with_queue(|queue| queue.tracks.clone());
+    if tracks.is_empty() {
+        return Err("The queue is empty".to_string());
+    }
+    queue_playlist(&tracks, path, relative).save()?;
+    Ok(tracks.len())
+}
+
+/// Replaces the queue with `tracks` (already in play order) and starts the first one.
+fn replace_queue_and_play(player: &PlayerHandle, tracks: Vec<LibraryTrack>, shuffle_mode: ShuffleMode) -> Result<QueueBuildResult, String> {
+    let (tracks, missing): (Vec<LibraryTrack>, Vec<LibraryTrack>) = tracks.into_iter()
+        .partition(|track| Path::new(&track.path).is_file());
+    let missing: Vec<String> = missing.into_iter().map(|track| track.path).collect();
+
+    if tracks.is_empty() {
+        return Err(if missing.is_empty() {
+            "No matching tracks in the library".to_string()
+        } else {
+            format!("All {} matching tracks are missing from disk", missing.len())
+        });
+    }
+
+    let mut tracks: Vec<QueuedTrack> = tracks.into_iter().map(QueuedTrack::from).collect();
+    let original_order: Vec<String> = tracks.iter().map(|track| track.path.clone()).collect();
+    order_tracks(&mut tracks, shuffle_mode, &original_order);
+    let repeat_mode = load_player_config().playback_settings.repeat_mode;
+
+    start_playback(player, &tracks[0].path, false)?;
+
+    with_queue(|queue| {
+        queue.tracks = tracks.clone();
+        queue.position = 0;
+        queue.repeat_mode = repeat_mode;
+        queue.shuffle_mode = shuffle_mode;
+        queue.original_order = original_order;
+    });
+    info!("Queued {} tracks ({} missing)", tracks.len(), missing.len());
+
+    Ok(QueueBuildResult {
+        tracks,
+        position: 0,
+        repeat_mode,
+        shuffle_mode,
+        missing,
+    })
+}
+
+#[tauri::command]
+pub fn play_artist(player: State<'_, PlayerHandle>, name: String, shuffle: bool) -> Result<QueueBuildResult, String> {
+    let mut tracks = library_tracks(|track| {
+        same_name(track.album_artist.as_ref(), &name) || same_name(track.artist.as_ref(), &name)
+    });
+    tracks.sort_by(discography_order);
+
+    let mode = match load_player_config().playback_settings.effective_shuffle_mode() {
+        ShuffleMode::Off if shuffle => ShuffleMode::Tracks,
+        mode => mode,
+    };
+    replace_queue_and_play(&player, tracks, mode)
+}
+
+#[tauri::command]
+pub fn play_album(player: State<'_, PlayerHandle>, album: String, album_artist: Option<String>) -> Result<QueueBuildResult, String> {
+    let mut tracks = library_tracks(|track| {
+        same_name(track.album.as_ref(), &album)
+            && album_artist.as_ref().is_none_or(|artist| {
+                same_name(track.album_artist.as_ref().or(track.artist.as_ref()), artist)
+            })
+    });
+    tracks.sort_by(album_order);
+
+    let mode = load_player_config().playback_settings.effective_shuffle_mode();
+    replace_queue_and_play(&player, tracks, mode)
+}
+
+/// Saves the repeat mode and applies it to the queue, announcing it with
+/// `playback-state`.
+#[tauri::command]
+pub fn set_repeat_mode(app: AppHandle, mode: RepeatMode) -> Result<(), String> {
+    update_player_config(|config| {
+        config.playback_settings.repeat_mode = mode;
+        Ok(())
+    })?;
+    with_queue(|queue| {
+        queue.repeat_mode = mode;
+        queue.reshuffle_for_wrap();
+    });
+    emit_playback_state(&app);
+    Ok(())
+}
+
+/// Stops once the current track finishes instead of moving on, leaving the
+/// queue as it is. The flag clears when it takes effect or on a manual skip.
+/// Announced with `playback-state`.
+#[tauri::command]
+pub fn set_stop_after_current(app: AppHandle, enabled: bool) {
+    app.state::<PlayerHandle>().with(move |player| {
+        player.stop_after_current = enabled;
+        // A track already queued in the sink would carry on into it
+        if enabled {
+            if let Some(preloaded) = player.preloaded.take() {
+                preloaded.withdraw();
+            }
+        }
+    });
+    emit_playback_state(&app);
+}
+
+#[tauri::command]
+pub fn get_repeat_mode() -> RepeatMode {
+    playback_modes().1
+}
+
+/// Saves the shuffle mode and reorders the not-yet-played part of the queue.
+/// The current track and everything before it stay where they are.
+#[tauri::command]
+pub fn set_shuffle_mode(mode: ShuffleMode) -> Result<(), String> {
+    update_player_config(|config| {
+        config.playback_settings.shuffle_mode = mode;
+        config.playback_settings.shuffle = mode != ShuffleMode::Off;
+        Ok(())
+    })?;
+
+    with_queue(|queue| {
+        queue.shuffle_mode = mode;
+        if queue.tracks.is_empty() {
+            return;
+        }
+
+        let split_at = (queue.position + 1).min(queue.tracks.len());
+        let mut remaining = queue.tracks.split_off(split_at);
+        order_tracks(&mut remaining, mode, &queue.original_order);
+        if let Some(current) = queue.tracks.get(queue.position) {
+            avoid_immediate_repeat(&mut remaining, &current.path);
+        }
+        queue.tracks.extend(remaining);
+        queue.reshuffle_for_wrap();
+    });
+    Ok(())
+}
+
+/// Turns track shuffle on or off; see `set_shuffle_mode`. Album shuffle
+/// counts as on and is kept when shuffle is switched on again.
+#[tauri::command]
+pub fn set_shuffle(enabled: bool) -> Result<(), String> {
+    let (current, _) = playback_modes();
+    match (enabled, current) {
+        (true, ShuffleMode::Off) => set_shuffle_mode(ShuffleMode::Tracks),
+        (true, _) => Ok(()),
+        (false, _) => set_shuffle_mode(ShuffleMode::Off),
+    }
+}
+
+#[tauri::command]
+pub fn get_shuffle() -> bool {
+    playback_modes().0 != ShuffleMode::Off
+}
+
+#[tauri::command]
+pub fn get_shuffle_mode() -> ShuffleMode {
+    playback_modes().0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn queue_of(paths: &[&str], position: usize) -> PlayQueue {
+        let tracks: Vec<QueuedTrack> = paths.iter().map(|path| QueuedTrack {
+            path: path.to_string(),
+            title: None,
+            artist: None,
+            album: None,
+            album_artist: None,
+            track_number: None,
+            duration: None,
+        }).collect();
+        PlayQueue {
+            original_order: paths.iter().map(|path| path.to_string()).collect(),
+            tracks,
+            position,
+            repeat_mode: RepeatMode::Off,
+            shuffle_mode: ShuffleMode::Off,
+        }
+    }
+
+    fn paths(queue: &PlayQueue) -> Vec<&str> {
+        queue.tracks.iter().map(|track| track.path.as_str()).collect()
+    }
+
+    #[test]
+    fn removing_keeps_the_current_track_current() {
+        let mut queue = queue_of(&["a", "b", "c", "d"], 2);
+        assert_eq!(queue.remove(0).unwrap().path, "a");
+        assert_eq!(queue.position, 1);
+        assert_eq!(queue.tracks[queue.position].path, "c");
+        assert!(queue.remove(1).is_err());
+        assert!(queue.remove(9).is_err());
+        queue.remove(2).unwrap();
+        assert_eq!(paths(&queue), ["b", "c"]);
+        assert_eq!(queue.original_order, ["b", "c"]);
+    }
+
+    #[test]
+    fn moving_follows_the_current_track() {
+        let mut queue = queue_of(&["a", "b", "c", "d"], 1);
+        queue.move_item(3, 0).unwrap();
+        assert_eq!(paths(&queue), ["d", "a", "b", "c"]);
+        assert_eq!(queue.tracks[queue.position].path, "b");
+
+        queue.move_item(2, 3).unwrap();
+        assert_eq!(paths(&queue), ["d", "a", "c", "b"]);
+        assert_eq!(queue.position, 3);
+
+        queue.move_item(0, 1).unwrap();
+        assert_eq!(queue.tracks[queue.position].path, "b");
+        assert_eq!(queue.original_order, ["a", "d", "c", "b"]);
+        assert!(queue.move_item(0, 4).is_err());
+    }
+
+    #[test]
+    fn next_track_follows_the_repeat_mode() {
+        let mut queue = queue_of(&["a", "b", "c"], 1);
+        assert_eq!(next_index(&queue, Some("b")), Some(2));
+        queue.position = 2;
+        assert_eq!(next_index(&queue, Some("c")), None);
+        queue.repeat_mode = RepeatMode::All;
+        assert_eq!(next_index(&queue, Some("c")), Some(0));
+        queue.repeat_mode = RepeatMode::Single;
+        assert_eq!(next_index(&queue, Some("c")), Some(2));
+    }
+
+    #[test]
+    fn shuffles_never_open_with_the_track_just_played() {
+        let mut tracks = queue_of(&["a", "a", "b"], 0).tracks;
+        avoid_immediate_repeat(&mut tracks, "a");
+        assert_eq!(tracks[0].path, "b");
+
+        let mut only_repeats = queue_of(&["a", "a"], 0).tracks;
+        avoid_immediate_repeat(&mut only_repeats, "a");
+        assert_eq!(only_repeats[0].path, "a");
+    }
+
+    #[test]
+    fn reaching_the_last_track_reshuffles_the_next_pass() {
+        let mut queue = queue_of(&["a", "b", "c", "d", "e", "d"], 5);
+        queue.shuffle_mode = ShuffleMode::Tracks;
+        queue.reshuffle_for_wrap();
+        // Off repeat doesn't wrap, so nothing moves
+        assert_eq!(paths(&queue), ["a", "b", "c", "d", "e", "d"]);
+
+        queue.repeat_mode = RepeatMode::All;
+        for _ in 0..20 {
+            queue.reshuffle_for_wrap();
+            assert_eq!(queue.tracks.len(), 6);
+            assert_eq!(queue.tracks[5].path, "d");
+            assert_ne!(queue.tracks[0].path, "d");
+        }
+    }
+
+    #[test]
+    fn skipping_moves_on_even_when_repeating_one_track() {
+        let mut queue = queue_of(&["a", "b", "c"], 1);
+        queue.repeat_mode = RepeatMode::Single;
+        assert_eq!(next_index(&queue, Some("b")), Some(1));
+        assert_eq!(skip_index(&queue, Some("b")), Some(2));
+        queue.position = 2;
+        assert_eq!(skip_index(&queue, Some("c")), None);
+        queue.repeat_mode = RepeatMode::All;
+        assert_eq!(skip_index(&queue, Some("c")), Some(0));
+    }
+
+    #[test]
+    fn a_track_from_outside_the_queue_is_followed_by_the_queue() {
+        let mut queue = queue_of(&["a", "b"], 0);
+        assert_eq!(next_index(&queue, Some("elsewhere")), Some(0));
+        // Played through: only tracks queued afterwards follow
+        queue.position = 2;
+        assert_eq!(next_index(&queue, Some("elsewhere")), None);
+        queue.tracks.push(queue_of(&["c"], 0).tracks.remove(0));
+        assert_eq!(next_index(&queue, Some("elsewhere")), Some(2));
+    }
+
+    #[test]
+    fn play_next_goes_straight_after_the_current_track() {
+        let mut queue = queue_of(&["a", "b", "c", "d"], 1);
+        queue.shuffle_mode = ShuffleMode::Tracks;
+        queue.original_order = vec!["d".into(), "b".into(), "a".into(), "c".into()];
+        queue.insert_next(queue_of(&["x", "y"], 0).tracks);
+        assert_eq!(paths(&queue), ["a", "b", "x", "y", "c", "d"]);
+        assert_eq!(queue.position, 1);
+        assert_eq!(next_index(&queue, Some("b")), Some(2));
+        // Turning shuffle off keeps them after the current track
+        assert_eq!(queue.original_order, ["d", "b", "x", "y", "a", "c"]);
+
+        // Once the queue has played through they are what plays next
+        queue.position = queue.tracks.len();
+        queue.insert_next(queue_of(&["z"], 0).tracks);
+        assert_eq!(queue.tracks.last().unwrap().path, "z");
+        assert_eq!(next_index(&queue, Some("elsewhere")), Some(6));
+    }
+
+    #[test]
+    fn clearing_keeps_history_and_the_current_track() {
+        let mut queue = queue_of(&["a", "b", "c", "d"], 1);
+        queue.clear_upcoming();
+        assert_eq!(paths(&queue), ["a", "b"]);
+        assert_eq!(queue.original_order, ["a", "b"]);
+        assert_eq!(queue.upcoming_start(), 2);
+    }
+
+    #[test]
+    fn saved_playlists_round_trip_unicode_and_relative_paths() {
+        let dir = crate::test_support::temp_dir();
+        let music = dir.path().join("Música").join("Sigur Rós");
+        let mut queue = queue_of(&[
+            &music.join("01 Svefn-g-englar.flac").to_string_lossy(),
+            &dir.path().join("日本語").join("曲.mp3").to_string_lossy(),
+        ], 0);
+        queue.tracks[0].artist = Some("Sigur Rós".to_string());
+        queue.tracks[0].title = Some("Svefn-g-englar".to_string());
+        queue.tracks[0].duration = Some(596.4);
+        let path = dir.path().join("Playlists").join("Ágætis.m3u8");
+        std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+
+        queue_playlist(&queue.tracks, &path, true).save().unwrap();
+        let text = std::fs::read_to_string(&path).unwrap();
+        let lines: Vec<&str> = text.lines().collect();
+        assert_eq!(lines[..2], ["#EXTM3U", "#EXTINF:596,Sigur Rós - Svefn-g-englar"]);
+        assert_eq!(Path::new(lines[2]), Path::new("../Música/Sigur Rós/01 Svefn-g-englar.flac"));
+        // Untagged tracks get no #EXTINF line
+        assert_eq!(Path::new(lines[3]), Path::new("../日本語/曲.mp3"));
+
+        let entries = Playlist::load(&path).unwrap().entries();
+        assert_eq!(entries.len(), 2);
+        assert_eq!(entries[0].info.as_ref().unwrap().title.as_deref(), Some("Svefn-g-englar"));
+        assert_eq!(entries[1].path, path.parent().unwrap().join("../日本語/曲.mp3"));
+
+        queue_playlist(&queue.tracks, &path, false).save().unwrap();
+        let entries = Playlist::load(&path).unwrap().entries();
+        assert_eq!(entries[0].raw, queue.tracks[0].path);
+    }
+
+    #[test]
+    fn album_shuffle_keeps_albums_together_in_track_order() {
+        let mut queue = queue_of(&["/a/2.flac", "/b/1.flac", "/a/1.flac", "/loose/x.flac", "/b/2.flac", "/loose/y.flac"], 0);
+        for track in queue.tracks.iter_mut() {
+            let folder = Path::new(&track.path).parent().unwrap().to_string_lossy().to_string();
+            if folder != "/loose" {
+                track.album = Some(format!("Album {}", folder));
+                track.artist = Some("Artist".to_string());
+            }
+            track.track_number = Path::new(&track.path).file_stem().unwrap().to_str().unwrap().parse().ok();
+        }
+        let original = queue.original_order.clone();
+        order_tracks(&mut queue.tracks, ShuffleMode::Albums, &original);
+
+        let order = paths(&queue);
+        let runs: Vec<&[&str]> = order.chunk_by(|a, b| Path::new(a).parent() == Path::new(b).parent()).collect();
+        // Each album, and the untagged folder, plays as one run in track order
+        assert_eq!(runs.len(), 3);
+        for run in runs {
+            let expected: &[&str] = match Path::new(run[0]).parent().unwrap().to_str().unwrap() {
+                "/a" => &["/a/1.flac", "/a/2.flac"],
+                "/b" => &["/b/1.flac", "/b/2.flac"],
+                _ => &["/loose/x.flac", "/loose/y.flac"],
+            };
+            assert_eq!(run, expected);
+        }
+    }
+
+    #[test]
+    fn dedupe_keeps_first_copies_and_the_current_entry() {
+        let mut queue = queue_of(&["/m/A.flac", "/m/b.flac", "/m/a.flac", "/m/c.flac", "/m/b.flac", "/m/C.flac"], 4);
+        // Case-folded, as `canonical_key` does on Windows
+        assert_eq!(queue.dedupe(|path| path.to_lowercase()), 2);
+        // The current entry stays even though it repeats an earlier one
+        assert_eq!(paths(&queue), ["/m/A.flac", "/m/b.flac", "/m/c.flac", "/m/b.flac"]);
+        assert_eq!(queue.position, 3);
+        assert_eq!(queue.original_order, ["/m/A.flac", "/m/b.flac", "/m/c.flac", "/m/b.flac"]);
+        assert_eq!(queue.dedupe(|path| path.to_lowercase()), 0);
+    }
+}
@@ -0,0 +1,170 @@
+//! The play queue on disk, in `queue.json` next to the player config. It is
+//! written shortly after every change rather than on exit, so a crash or a
+//! killed process still leaves the last queue behind for `load_saved_queue`.
+
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use log::error;
+use crate::config::get_config_dir;
+use crate::config_file::write_json;
+use crate::queue::with_queue;
+
+// A queue is saved once it has looked the same for this long
+const SAVE_INTERVAL: Duration = Duration::from_secs(1);
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Default)]
+pub struct SavedQueue {
+    pub tracks: Vec<String>,
+    pub position: usize,
+    // The order the queue was built in, for turning shuffle off later
+    pub original_order: Vec<String>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct SavedQueueEntry {
+    pub path: String,
+    // False for files deleted or moved away since the queue was saved
+    pub exists: bool,
+}
+
+#[derive(Debug, Serialize)]
+pub struct LoadedQueue {
+    pub tracks: Vec<SavedQueueEntry>,
+    pub position: usize,
+    pub original_order: Vec<String>,
+}
+
+fn queue_path() -> Option<PathBuf> {
+    get_config_dir().map(|dir| dir.join("queue.json"))
+}
+
+fn current_queue() -> SavedQueue {
+    with_queue(|queue| SavedQueue {
+        tracks: queue.tracks.iter().map(|track| track.path.clone()).collect(),
+        position: queue.position,
+        original_order: queue.original_order.clone(),
+    })
+}
+
+/// Writes a queue once it has stopped changing, and only if it differs from
+/// what is on disk.
+struct QueueSaver {
+    path: PathBuf,
+    saved: Option<SavedQueue>,
+    // Seen last time but not yet written, while it may still be changing
+    pending: Option<SavedQueue>,
+}
+
+impl QueueSaver {
+    /// Starts from `baseline`, which counts as saved already.
+    fn new(path: PathBuf, baseline: SavedQueue) -> Self {
+        Self { path, saved: Some(baseline), pending: None }
+    }
+
+    /// Looks at the queue as it is now; returns whether it was written.
+    fn observe(&mut self, current: SavedQueue) -> Result<bool, String> {
+        if self.saved.as_ref() == Some(&current) {
+            self.pending = None;
+            return Ok(false);
+        }
+        if self.pending.as_ref() != Some(&current) {
+            self.pending = Some(current);
+            return Ok(false);
+        }
+        write_json(&self.path, &current)?;
+        self.saved = Some(current);
+        self.pending = None;
+        Ok(true)
+    }
+}
+
+/// Keeps `queue.json` up to date. The queue at launch counts as saved, so
+/// starting with an empty queue doesn't wipe the last one.
+pub fn start_queue_saver() {
+    let Some(path) = queue_path() else { return };
+    let mut saver = QueueSaver::new(path, current_queue());
+    std::thread::spawn(move || loop {
+        std::thread::sleep(SAVE_INTERVAL);
+        if let Err(e) = saver.observe(current_queue()) {
+            error!("Failed to save the play queue: {}", e);
+        }
+    });
+}
+
+/// The saved queue, or None when there is none or it can't be read.
+fn read_saved_queue(path: &Path) -> Option<SavedQueue> {
+    let contents = fs::read_to_string(path).ok()?;
+    match serde_json::from_str::<SavedQueue>(&contents) {
+        Ok(queue) => Some(queue),
+        Err(e) => {
+            error!("Ignoring unreadable queue {}: {}", path.display(), e);
+            None
+        }
+    }
+}
+
+/// The queue last saved, each entry marked with whether its file is still
+/// there. `None` when nothing usable was saved.
+#[tauri::command]
+pub fn load_saved_queue() -> Option<LoadedQueue> {
+    let saved = read_saved_queue(&queue_path()?)?;
+    Some(LoadedQueue {
+        position: saved.position.min(saved.tracks.len()),
+        tracks: saved.tracks.into_iter()
+            .map(|path| SavedQueueEntry { exists: Path::new(&path).is_file(), path })
+            .collect(),
+        original_order: saved.original_order,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn queue(tracks: &[&str], position: usize) -> SavedQueue {
+        let tracks: Vec<String> = tracks.iter().map(|track| track.to_string()).collect();
+        SavedQueue { original_order: tracks.clone(), tracks, position }
+    }
+
+    #[test]
+    fn queue_survives_a_crash_without_the_exit_hook() {
+        let dir = crate::test_support::temp_dir();
+        let path = dir.path().join("queue.json");
+        let mut saver = QueueSaver::new(path.clone(), SavedQueue::default());
+
+        let edited = queue(&["/music/a.flac", "/music/b.flac"], 1);
+        // Written once the queue has stopped changing, not on every edit
+        assert!(!saver.observe(edited.clone()).unwrap());
+        assert!(saver.observe(edited.clone()).unwrap());
+        assert!(!saver.observe(edited.clone()).unwrap());
+        // The process dies here: no exit hook, no final save
+        drop(saver);
+
+        assert_eq!(read_saved_queue(&path), Some(edited));
+    }
+
+    #[test]
+    fn an_unchanged_launch_queue_keeps_the_saved_one() {
+        let dir = crate::test_support::temp_dir();
+        let path = dir.path().join("queue.json");
+        let before = queue(&["/music/a.flac"], 0);
+        write_json(&path, &before).unwrap();
+
+        let mut saver = QueueSaver::new(path.clone(), SavedQueue::default());
+        saver.observe(SavedQueue::default()).unwrap();
+        saver.observe(SavedQueue::default()).unwrap();
+        assert_eq!(read_saved_queue(&path), Some(before));
+    }
+
+    #[test]
+    fn corrupt_or_missing_queue_files_are_ignored() {
+        let dir = crate::test_support::temp_dir();
+        let path = dir.path().join("queue.json");
+        assert_eq!(read_saved_queue(&path), None);
+        // A write cut short leaves a half-written file at worst
+        fs::write(&path, br#"{"tracks": ["/music/a.fl"#).unwrap();
+        assert_eq!(read_saved_queue(&path), None);
+    }
+}
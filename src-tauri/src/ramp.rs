@@ -0,0 +1,119 @@
+//! Short volume ramps around pause, resume and stop, so they don't click. The
+//! ramps run as repeating work on the playback thread, so the commands return
+//! straight away.
+
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+use rodio::Sink;
+use crate::config::load_player_config;
+use crate::PlayerState;
+
+const RAMP_STEP: Duration = Duration::from_millis(10);
+
+// Bumped by every ramp, so one started later takes over from an earlier one
+static RAMP_ID: AtomicU64 = AtomicU64::new(0);
+// Bumped when the volume is set directly; a ramp then leaves the volume alone
+static VOLUME_EPOCH: AtomicU64 = AtomicU64::new(0);
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum FadeEnd {
+    Pause,
+    Stop,
+}
+
+/// Tells running ramps that the volume was set on purpose and shouldn't be overridden.
+pub fn volume_changed() {
+    VOLUME_EPOCH.fetch_add(1, Ordering::SeqCst);
+}
+
+fn ramp_length() -> Duration {
+    Duration::from_millis(load_player_config().playback_settings.pause_fade_ms as u64)
+}
+
+fn steps(length: Duration) -> u32 {
+    (length.as_millis() / RAMP_STEP.as_millis()).max(1) as u32
+}
+
+/// Gain `step` of `steps` of the way from `from` to `to`.
+fn ramp_gain(from: f32, to: f32, step: u32, steps: u32) -> f32 {
+    from + (to - from) * (step as f32 / steps as f32).min(1.0)
+}
+
+/// Fades `sink` out, then pauses or stops it. A pause leaves the sink at the
+/// main volume again, ready for a resume without a fade.
+pub fn fade_out(player: &mut PlayerState, sink: Arc<Sink>, end: FadeEnd) {
+    let id = RAMP_ID.fetch_add(1, Ordering::SeqCst) + 1;
+    let length = ramp_length();
+    let finish = move |player: &PlayerState, sink: &Sink| {
+        match end {
+            FadeEnd::Pause => sink.pause(),
+            FadeEnd::Stop => sink.stop(),
+        }
+        sink.set_volume(player.output_gain());
+    };
+    if length.is_zero() {
+        finish(player, &sink);
+        return;
+    }
+
+    let epoch = VOLUME_EPOCH.load(Ordering::SeqCst);
+    let from = sink.volume();
+    let steps = steps(length);
+    let mut step = 0;
+    player.every(RAMP_STEP, move |player| {
+        if RAMP_ID.load(Ordering::SeqCst) != id {
+            // Resumed before the fade finished; the newer ramp takes it from here
+            return false;
+        }
+        step += 1;
+        if VOLUME_EPOCH.load(Ordering::SeqCst) == epoch && step < steps {
+            sink.set_volume(ramp_gain(from, 0.0, step, steps));
+            return true;
+        }
+        finish(player, &sink);
+        false
+    });
+}
+
+/// Starts `sink` playing and brings it up to the main volume. A sink still
+/// fading out for a pause is brought back from where it got to.
+pub fn fade_in(player: &mut PlayerState, sink: Arc<Sink>) {
+    let id = RAMP_ID.fetch_add(1, Ordering::SeqCst) + 1;
+    let length = ramp_length();
+    if length.is_zero() {
+        sink.set_volume(player.output_gain());
+        sink.play();
+        return;
+    }
+
+    let from = if sink.is_paused() { 0.0 } else { sink.volume() };
+    sink.set_volume(from);
+    sink.play();
+    let epoch = VOLUME_EPOCH.load(Ordering::SeqCst);
+    let steps = steps(length);
+    let mut step = 0;
+    player.every(RAMP_STEP, move |player| {
+        if RAMP_ID.load(Ordering::SeqCst) != id || VOLUME_EPOCH.load(Ordering::SeqCst) != epoch {
+            return false;
+        }
+        step += 1;
+        // Read each step so a preview ducking the output is followed
+        sink.set_volume(ramp_gain(from, player.output_gain(), step, steps));
+        step < steps
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn ramps_end_exactly_on_the_target() {
+        assert_eq!(ramp_gain(0.8, 0.0, 0, 20), 0.8);
+        assert_eq!(ramp_gain(0.8, 0.0, 20, 20), 0.0);
+        assert_eq!(ramp_gain(0.0, 0.5, 10, 20), 0.25);
+        assert_eq!(steps(Duration::from_millis(200)), 20);
+        assert_eq!(steps(Duration::from_millis(1)), 1);
+    }
+}
@@ -0,0 +1,160 @@
+use serde::Serialize;
+use std::path::{Path, PathBuf};
+use tauri::{AppHandle, Emitter, Manager};
+use log::{info, error};
+use crate::commands::{pause_audio, reopen_playback, seek_absolute};
+use crate::favorites::FavoriteKind;
+use crate::output::release;
+use crate::output_format::PlaybackError;
+use crate::library::remap_track;
+use crate::paths::{canonical_key, same_location};
+use crate::play_stats::remap_stats;
+use crate::queue::with_queue;
+use crate::resume::remap_resume_point;
+use crate::{update_config, PlayerHandle};
+
+#[derive(Debug, Serialize)]
+#[serde(tag = "kind", content = "message")]
+pub enum FileOpError {
+    // The operation touches the track that is playing and relocation wasn't requested
+    FileInUse(String),
+    Failed(String),
+}
+
+impl std::fmt::Display for FileOpError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            FileOpError::FileInUse(message) | FileOpError::Failed(message) => f.write_str(message),
+        }
+    }
+}
+
+impl From<String> for FileOpError {
+    fn from(message: String) -> Self {
+        FileOpError::Failed(message)
+    }
+}
+
+#[derive(Debug, Serialize, Clone)]
+pub struct TrackRelocated {
+    pub old_path: String,
+    pub new_path: String,
+    pub position: f32,
+}
+
+/// Maps `path` from under `old_base` to under `new_base`; `None` if it isn't under `old_base`.
+fn rebase(path: &Path, old_base: &Path, new_base: &Path) -> Option<PathBuf> {
+    path.strip_prefix(old_base).ok().map(|rest| {
+        if rest.as_os_str().is_empty() { new_base.to_path_buf() } else { new_base.join(rest) }
+    })
+}
+
+fn playing_track_under(player: &PlayerHandle, affected: &Path) -> Option<String> {
+    player.with(|player| player.current_path.clone())
+        .filter(|current| Path::new(current).starts_with(affected))
+}
+
+fn rewrite_queue(old_base: &Path, new_base: &Path) {
+    with_queue(|queue| {
+        for track in queue.tracks.iter_mut() {
+            if let Some(new_path) = rebase(Path::new(&track.path), old_base, new_base) {
+                track.path = new_path.to_string_lossy().to_string();
+            }
+        }
+    });
+}
+
+/// Carries per-track data from `from` to `to` after a file has been replaced by
+/// another one: its library index entry, favorites, play counts and queue entries.
+pub fn remap_track_data(from: &Path, to: &Path) {
+    remap_track(from, to);
+    remap_resume_point(&from.to_string_lossy(), &to.to_string_lossy());
+    remap_stats(&canonical_key(&from.to_string_lossy()), to);
+    rewrite_queue(from, to);
+
+    let result = update_config(|config| {
+        for entry in config.favorite_locations.iter_mut() {
+            if entry.kind == FavoriteKind::File && same_location(&entry.path, &from.to_string_lossy()) {
+                entry.path = to.to_string_lossy().to_string();
+            }
+        }
+        Ok(())
+    });
+    if let Err(e) = result {
+        error!("Failed to update favorites for {}: {}", to.display(), e);
+    }
+}
+
+/// Releases the playing file and restarts it at `path` from `position`.
+pub fn restart_at(app: &AppHandle, path: &str, position: f32, was_playing: bool) -> Result<(), PlaybackError> {
+    let player = app.state::<PlayerHandle>();
+    reopen_playback(&player, path)?;
+    seek_absolute(&player, position)?;
+    if !was_playing {
+        pause_audio(player)?;
+    }
+    Ok(())
+}
+
+/// Runs a file operation that moves `old_base` to `new_base` (equal for in-place
+/// rewrites such as retagging). If the playing track is at or under `old_base`
+/// the operation is refused with `FileInUse`, unless `relocate` is set: then
+/// playback is stopped so the decoder lets go of the file, the operation runs,
+/// and playback resumes at the same position from the track's new path.
+pub fn guard_playing_track<T>(
+    app: &AppHandle,
+    old_base: &Path,
+    new_base: &Path,
+    relocate: bool,
+    operation: impl FnOnce() -> Result<T, String>,
+) -> Result<T, FileOpError> {
+    // Taken while the old location still resolves
+    let old_key = canonical_key(&old_base.to_string_lossy());
+    let player = app.state::<PlayerHandle>();
+    let Some(playing) = playing_track_under(&player, old_base) else {
+        let result = operation()?;
+        rewrite_queue(old_base, new_base);
+        remap_stats(&old_key, new_base);
+        return Ok(result);
+    };
+
+    if !relocate {
+        return Err(FileOpError::FileInUse(format!("{} is currently playing", playing)));
+    }
+
+    let (position, was_playing) = player.with(|player| {
+        let position = player.position().as_secs_f32();
+        let was_playing = player.is_playing;
+        // Closing the output drops the decoder and closes the file at once
+        release(player);
+        (position, was_playing)
+    });
+
+    let result = match operation() {
+        Ok(result) => result,
+        Err(e) => {
+            if let Err(restore_error) = restart_at(app, &playing, position, was_playing) {
+                error!("Failed to resume {} after a failed operation: {}", playing, restore_error);
+            }
+            return Err(FileOpError::Failed(e));
+        }
+    };
+
+    let new_path = rebase(Path::new(&playing), old_base, new_base)
+        .map(|path| path.to_string_lossy().to_string())
+        .unwrap_or_else(|| playing.clone());
+    rewrite_queue(old_base, new_base);
+    remap_stats(&old_key, new_base);
+
+    restart_at(app, &new_path, position, was_playing)
+        .map_err(|e| FileOpError::Failed(format!("Operation succeeded but playback could not resume: {}", e)))?;
+
+    info!("Relocated playing track {} -> {}", playing, new_path);
+    app.emit("track-relocated", TrackRelocated {
+        old_path: playing,
+        new_path,
+        position,
+    }).ok();
+
+    Ok(result)
+}
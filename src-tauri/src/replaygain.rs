@@ -0,0 +1,157 @@
+//! ReplayGain: evens out loudness between tracks using the gain and peak tags
+//! written by taggers such as foobar2000 or `metaflac --add-replay-gain`.
+
+use std::path::Path;
+use lofty::prelude::{ItemKey, TaggedFileExt};
+use lofty::probe::Probe;
+use lofty::tag::Tag;
+use tauri::State;
+use log::debug;
+use crate::config::{load_player_config, update_player_config, ReplayGainMode};
+use crate::ramp::volume_changed;
+use crate::PlayerHandle;
+
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub struct ReplayGainTags {
+    pub track_gain: Option<f32>,
+    pub track_peak: Option<f32>,
+    pub album_gain: Option<f32>,
+    pub album_peak: Option<f32>,
+}
+
+/// Reads a value such as "-6.48 dB" or "0.988", ignoring the unit.
+fn parse_value(text: &str) -> Option<f32> {
+    let text = text.trim();
+    let number = text.strip_suffix("dB").or_else(|| text.strip_suffix("db")).unwrap_or(text);
+    number.trim().parse::<f32>().ok().filter(|value| value.is_finite())
+}
+
+fn tag_value(tags: &[&Tag], key: &ItemKey) -> Option<f32> {
+    tags.iter().find_map(|tag| tag.get_string(key).and_then(parse_value))
+}
+
+/// The ReplayGain tags of the file at `path`; all empty if it has none or can't be read.
+pub fn read_tags(path: &Path) -> ReplayGainTags {
+    let tagged_file = match Probe::open(path).and_then(|probe| probe.read()) {
+        Ok(tagged_file) => tagged_file,
+        Err(e) => {
+            debug!("No ReplayGain tags read from {}: {}", path.display(), e);
+            return ReplayGainTags::default();
+        }
+    };
+    // The primary tag first, then any others, since MP3s sometimes keep them in APE
+    let mut tags: Vec<&Tag> = tagged_file.primary_tag().into_iter().collect();
+    tags.extend(tagged_file.tags().iter().filter(|tag| tag.tag_type() != tagged_file.primary_tag_type()));
+    ReplayGainTags {
+        track_gain: tag_value(&tags, &ItemKey::ReplayGainTrackGain),
+        track_peak: tag_value(&tags, &ItemKey::ReplayGainTrackPeak),
+        album_gain: tag_value(&tags, &ItemKey::ReplayGainAlbumGain),
+        album_peak: tag_value(&tags, &ItemKey::ReplayGainAlbumPeak),
+    }
+}
+
+/// Linear gain for `tags` in `mode`, lowered if needed so the peak doesn't clip.
+/// Either mode falls back to the other's values when its own are missing.
+pub fn gain_for(tags: &ReplayGainTags, mode: ReplayGainMode) -> f32 {
+    let track = tags.track_gain.map(|gain| (gain, tags.track_peak));
+    let album = tags.album_gain.map(|gain| (gain, tags.album_peak));
+    let chosen = match mode {
+        ReplayGainMode::Off => None,
+        ReplayGainMode::Track => track.or(album),
+        ReplayGainMode::Album => album.or(track),
+    };
+    let Some((db, peak)) = chosen else { return 1.0 };
+    let gain = 10f32.powf(db / 20.0);
+    match peak.filter(|peak| *peak > 0.0) {
+        Some(peak) => gain.min(1.0 / peak),
+        None => gain,
+    }
+}
+
+/// The gain to play `path` at under the saved mode.
+pub fn track_gain(path: &str) -> f32 {
+    let mode = load_player_config().playback_settings.replaygain_mode;
+    if mode == ReplayGainMode::Off {
+        return 1.0;
+    }
+    gain_for(&read_tags(Path::new(path)), mode)
+}
+
+/// Sets the ReplayGain mode and applies it to the playing track straight away.
+#[tauri::command]
+pub fn set_replaygain_mode(player: State<'_, PlayerHandle>, mode: ReplayGainMode) -> Result<(), String> {
+    update_player_config(|config| {
+        config.playback_settings.replaygain_mode = mode;
+        Ok(())
+    })?;
+    let Some(path) = player.with(|player| player.current_path.clone()) else { return Ok(()) };
+    let gain = gain_for(&read_tags(Path::new(&path)), mode);
+
+    volume_changed();
+    player.send(move |player| {
+        // Another track started while the tags were read; it picked up the new mode itself
+        if player.current_path.as_deref() != Some(path.as_str()) {
+            return;
+        }
+        player.replay_gain = gain;
+        if let Some(sink) = &player.sink {
+            if player.is_playing {
+                sink.set_volume(player.output_gain());
+            }
+        }
+    });
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use lofty::config::WriteOptions;
+    use lofty::prelude::AudioFile;
+    use crate::test_support::{temp_dir, write_fixture, FixtureTags, Format};
+
+    fn tags(track_gain: Option<f32>, track_peak: Option<f32>, album_gain: Option<f32>) -> ReplayGainTags {
+        ReplayGainTags { track_gain, track_peak, album_gain, album_peak: None }
+    }
+
+    #[test]
+    fn parses_values_with_or_without_a_unit() {
+        assert_eq!(parse_value("-6.50 dB"), Some(-6.5));
+        assert_eq!(parse_value(" +2.1 db "), Some(2.1));
+        assert_eq!(parse_value("0.988"), Some(0.988));
+        assert_eq!(parse_value("loud"), None);
+    }
+
+    #[test]
+    fn applies_the_chosen_gain_and_stops_at_the_peak() {
+        let quiet = tags(Some(-20.0), Some(0.5), Some(-6.0));
+        assert_eq!(gain_for(&quiet, ReplayGainMode::Off), 1.0);
+        assert!((gain_for(&quiet, ReplayGainMode::Track) - 0.1).abs() < 1e-6);
+        assert!((gain_for(&quiet, ReplayGainMode::Album) - 10f32.powf(-0.3)).abs() < 1e-6);
+
+        // +12 dB would push a 0.5 peak past full scale, so it stops at 2x
+        let boosted = tags(Some(12.0), Some(0.5), None);
+        assert_eq!(gain_for(&boosted, ReplayGainMode::Track), 2.0);
+        // Album mode falls back to the track values
+        assert_eq!(gain_for(&boosted, ReplayGainMode::Album), 2.0);
+        assert_eq!(gain_for(&ReplayGainTags::default(), ReplayGainMode::Track), 1.0);
+    }
+
+    #[test]
+    fn reads_tags_from_a_flac() {
+        let dir = temp_dir();
+        let path = dir.path().join("gain.flac");
+        write_fixture(&path, Format::Flac, &FixtureTags::titled("Gain"));
+        let mut tagged_file = Probe::open(&path).unwrap().read().unwrap();
+        let tag = tagged_file.primary_tag_mut().unwrap();
+        tag.insert_text(ItemKey::ReplayGainTrackGain, "-7.25 dB".to_string());
+        tag.insert_text(ItemKey::ReplayGainTrackPeak, "0.912".to_string());
+        tagged_file.save_to_path(&path, WriteOptions::default()).unwrap();
+
+        let read = read_tags(&path);
+        assert_eq!(read.track_gain, Some(-7.25));
+        assert_eq!(read.track_peak, Some(0.912));
+        assert_eq!(read.album_gain, None);
+        assert_eq!(read_tags(&dir.path().join("missing.flac")), ReplayGainTags::default());
+    }
+}
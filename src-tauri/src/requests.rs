@@ -0,0 +1,194 @@
+use serde::ser::{Serialize, SerializeMap, Serializer};
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::Arc;
+use once_cell::sync::Lazy;
+use parking_lot::Mutex;
+use log::debug;
+use crate::paths::canonical_key;
+use crate::walker::{AccessErrorKind, SkippedPath};
+
+/// Sent to the frontend as `{kind, message}`, except `Failed`, which stays the
+/// plain message string commands returned before they could be cancelled.
+#[derive(Debug)]
+pub enum RequestError {
+    // Cancelled by `cancel_request` or superseded by a newer request for the same path
+    Cancelled,
+    // The folder itself can't be read; the message is the path
+    PermissionDenied(String),
+    FullDiskAccessRequired(String),
+    Failed(String),
+}
+
+impl Serialize for RequestError {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let (kind, message) = match self {
+            RequestError::Failed(message) => return serializer.serialize_str(message),
+            RequestError::Cancelled => ("Cancelled", None),
+            RequestError::PermissionDenied(path) => ("PermissionDenied", Some(path)),
+            RequestError::FullDiskAccessRequired(path) => ("FullDiskAccessRequired", Some(path)),
+        };
+        let mut map = serializer.serialize_map(Some(1 + message.is_some() as usize))?;
+        map.serialize_entry("kind", kind)?;
+        if let Some(message) = message {
+            map.serialize_entry("message", message)?;
+        }
+        map.end()
+    }
+}
+
+impl From<String> for RequestError {
+    fn from(message: String) -> Self {
+        RequestError::Failed(message)
+    }
+}
+
+impl From<SkippedPath> for RequestError {
+    fn from(skipped: SkippedPath) -> Self {
+        match skipped.kind {
+            AccessErrorKind::PermissionDenied => RequestError::PermissionDenied(skipped.path),
+            AccessErrorKind::FullDiskAccessRequired => RequestError::FullDiskAccessRequired(skipped.path),
+            AccessErrorKind::Unreadable => RequestError::Failed(format!("{}: {}", skipped.path, skipped.reason)),
+        }
+    }
+}
+
+struct InFlight {
+    serial: u64,
+    scope: String,
+    cancelled: Arc<AtomicBool>,
+}
+
+static NEXT_SERIAL: AtomicU64 = AtomicU64::new(0);
+// Keyed by the frontend's request id
+static IN_FLIGHT: Lazy<Mutex<HashMap<String, InFlight>>> = Lazy::new(|| Mutex::new(HashMap::new()));
+// Latest request id per command and path, so a newer one can supersede it
+static LATEST: Lazy<Mutex<HashMap<String, String>>> = Lazy::new(|| Mutex::new(HashMap::new()));
+
+/// Cancellation handle for one command invocation. Requests without an id can't
+/// be cancelled and never supersede anything. Dropping it unregisters the request.
+pub struct RequestToken {
+    id: Option<String>,
+    serial: u64,
+    cancelled: Arc<AtomicBool>,
+}
+
+impl RequestToken {
+    pub fn is_cancelled(&self) -> bool {
+        self.cancelled.load(Ordering::Relaxed)
+    }
+
+    /// `Err(Cancelled)` once cancelled, for use with `?` at iteration boundaries.
+    pub fn check(&self) -> Result<(), RequestError> {
+        if self.is_cancelled() {
+            Err(RequestError::Cancelled)
+        } else {
+            Ok(())
+        }
+    }
+}
+
+impl Drop for RequestToken {
+    fn drop(&mut self) {
+        let Some(id) = &self.id else { return };
+        let mut in_flight = IN_FLIGHT.lock();
+        // The id may have been reused by a newer request; only remove our own registration
+        if in_flight.get(id).is_some_and(|request| request.serial == self.serial) {
+            let request = in_flight.remove(id).expect("checked above");
+            let mut latest = LATEST.lock();
+            if latest.get(&request.scope) == Some(id) {
+                latest.remove(&request.scope);
+            }
+        }
+    }
+}
+
+/// Registers a request for `command` on `path`, cancelling any earlier request
+/// still running for the same command and path.
+pub fn begin_request(command: &str, path: &str, request_id: Option<String>) -> RequestToken {
+    let serial = NEXT_SERIAL.fetch_add(1, Ordering::Relaxed);
+    let cancelled = Arc::new(AtomicBool::new(false));
+    let Some(id) = request_id else {
+        return RequestToken { id: None, serial, cancelled };
+    };
+
+    let scope = format!("{}\u{0}{}", command, canonical_key(path));
+    let mut in_flight = IN_FLIGHT.lock();
+    if let Some(previous) = LATEST.lock().insert(scope.clone(), id.clone()) {
+        if let Some(request) = in_flight.get(&previous) {
+            debug!("Request {} superseded by {}", previous, id);
+            request.cancelled.store(true, Ordering::Relaxed);
+        }
+    }
+    in_flight.insert(id.clone(), InFlight { serial, scope, cancelled: cancelled.clone() });
+    RequestToken { id: Some(id), serial, cancelled }
+}
+
+/// Aborts an in-flight request at its next iteration boundary. Returns false if
+/// no request with that id is running, e.g. because it already finished.
+#[tauri::command]
+pub fn cancel_request(request_id: String) -> bool {
+    match IN_FLIGHT.lock().get(&request_id) {
+        Some(request) => {
+            request.cancelled.store(true, Ordering::Relaxed);
+            true
+        }
+        None => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use std::path::Path;
+    use std::sync::atomic::AtomicUsize;
+    use std::time::Duration;
+    use crate::commands::walk_playable;
+    use crate::test_support::temp_dir;
+
+    #[test]
+    fn a_superseded_walk_stops_reading_folders() {
+        const DEPTH: usize = 400;
+        let dir = temp_dir();
+        // One track per folder, so tracks seen is folders read
+        let mut folder = dir.path().to_path_buf();
+        for level in 0..DEPTH {
+            folder = folder.join(format!("level {}", level));
+            fs::create_dir(&folder).unwrap();
+            fs::write(folder.join("track.mp3"), b"").unwrap();
+        }
+        let root = dir.path().to_string_lossy().to_string();
+
+        let visited = Arc::new(AtomicUsize::new(0));
+        let first = {
+            let (root, visited) = (root.clone(), Arc::clone(&visited));
+            std::thread::spawn(move || {
+                let request = begin_request("get_recursive_audio_files", &root, Some("supersede-first".to_string()));
+                walk_playable(Path::new(&root), &request, &mut Vec::new(), &mut |_| {
+                    visited.fetch_add(1, Ordering::SeqCst);
+                    std::thread::sleep(Duration::from_millis(2));
+                })
+            })
+        };
+        while visited.load(Ordering::SeqCst) < 10 {
+            std::thread::sleep(Duration::from_millis(1));
+        }
+
+        // A newer listing of the same folder, spelled with a trailing slash
+        let _second = begin_request("get_recursive_audio_files", &format!("{}/", root), Some("supersede-second".to_string()));
+        assert!(matches!(first.join().unwrap(), Err(RequestError::Cancelled)));
+        let stopped_at = visited.load(Ordering::SeqCst);
+        assert!(stopped_at < DEPTH / 2, "read {} of {} folders", stopped_at, DEPTH);
+        std::thread::sleep(Duration::from_millis(50));
+        assert_eq!(visited.load(Ordering::SeqCst), stopped_at);
+    }
+
+    #[test]
+    fn failures_stay_plain_strings_for_the_frontend() {
+        let json = |error: RequestError| serde_json::to_string(&error).unwrap();
+        assert_eq!(json(RequestError::Failed("Disk on fire".to_string())), r#""Disk on fire""#);
+        assert_eq!(json(RequestError::Cancelled), r#"{"kind":"Cancelled"}"#);
+        assert_eq!(json(RequestError::PermissionDenied("/root".to_string())), r#"{"kind":"PermissionDenied","message":"/root"}"#);
+    }
+}
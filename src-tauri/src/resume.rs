@@ -0,0 +1,177 @@
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use once_cell::sync::Lazy;
+use parking_lot::Mutex;
+use log::error;
+use crate::config::{get_config_dir, load_player_config};
+use crate::decode::probe_duration;
+use crate::library::now_secs;
+use crate::paths::canonical_key;
+use crate::PlayerHandle;
+
+// Playback resumes this far before the stored position, to pick the thread back up
+const RESUME_REWIND_SECS: f32 = 5.0;
+// Stopping this close to the end counts as finished and forgets the position
+const FINISHED_WITHIN_SECS: f32 = 30.0;
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct ResumePoint {
+    pub position: f32,
+    pub duration: f32,
+    pub updated_at: u64,
+}
+
+// Keyed by `canonical_key`, so a file reached through another path or a symlink shares its point
+static RESUME_POINTS: Lazy<Mutex<HashMap<String, ResumePoint>>> = Lazy::new(|| Mutex::new(load_resume_points()));
+
+fn resume_points_path() -> Option<PathBuf> {
+    get_config_dir().map(|dir| dir.join("resume_points.json"))
+}
+
+/// Reads the stored points, dropping those for files that no longer exist.
+fn load_resume_points() -> HashMap<String, ResumePoint> {
+    let stored: HashMap<String, ResumePoint> = resume_points_path()
+        .and_then(|path| fs::read_to_string(path).ok())
+        .and_then(|contents| serde_json::from_str(&contents).ok())
+        .unwrap_or_default();
+    let points = prune_missing(stored.clone());
+    if points.len() != stored.len() || points.keys().any(|key| !stored.contains_key(key)) {
+        save_resume_points(&points);
+    }
+    points
+}
+
+/// Keeps the points whose files still exist, re-keyed canonically.
+fn prune_missing(points: HashMap<String, ResumePoint>) -> HashMap<String, ResumePoint> {
+    points.into_iter()
+        .filter(|(path, _)| Path::new(path).is_file())
+        .map(|(path, point)| (canonical_key(&path), point))
+        .collect()
+}
+
+fn save_resume_points(points: &HashMap<String, ResumePoint>) {
+    let Some(path) = resume_points_path() else { return };
+    let result = serde_json::to_string(points)
+        .map_err(|e| e.to_string())
+        .and_then(|json| {
+            if let Some(parent) = path.parent() {
+                fs::create_dir_all(parent).map_err(|e| e.to_string())?;
+            }
+            let temp_path = path.with_extension("json.tmp");
+            fs::write(&temp_path, json).map_err(|e| e.to_string())?;
+            fs::rename(&temp_path, &path).map_err(|e| e.to_string())
+        });
+    if let Err(e) = result {
+        error!("Failed to save resume points: {}", e);
+    }
+}
+
+fn is_long_enough(duration: f32) -> bool {
+    duration >= load_player_config().playback_settings.resume_min_duration_secs
+}
+
+/// Stores (or, near the end, clears) the resume point of the track that is
+/// loaded right now. Called before pausing, stopping or switching tracks.
+pub fn remember_current_position(player: &PlayerHandle) {
+    let playing = player.with(|player| {
+        let (Some(path), Some(sink)) = (&player.current_path, &player.sink) else { return None };
+        Some((path.clone(), player.position().as_secs_f32(), player.duration, sink.empty()))
+    });
+    let Some((path, position, duration, finished)) = playing else { return };
+    let duration = duration
+        .or_else(|| probe_duration(Path::new(&path)))
+        .map(|d| d.as_secs_f32())
+        .unwrap_or(0.0);
+    if !is_long_enough(duration) {
+        return;
+    }
+
+    if finished || position >= duration - FINISHED_WITHIN_SECS {
+        forget_resume_point(&path);
+    } else {
+        let mut points = RESUME_POINTS.lock();
+        points.insert(canonical_key(&path), ResumePoint { position, duration, updated_at: now_secs() });
+        save_resume_points(&points);
+    }
+}
+
+/// Drops the point for a track that was played to its end.
+pub fn forget_resume_point(path: &str) {
+    let mut points = RESUME_POINTS.lock();
+    if points.remove(&canonical_key(path)).is_some() {
+        save_resume_points(&points);
+    }
+}
+
+/// Where `play_audio` should start `path`, if it has a stored position.
+pub fn resume_start(path: &str) -> Option<Duration> {
+    let point = RESUME_POINTS.lock().get(&canonical_key(path)).cloned()?;
+    is_long_enough(point.duration)
+        .then(|| Duration::from_secs_f32((point.position - RESUME_REWIND_SECS).max(0.0)))
+}
+
+/// Stored positions for `paths`, one per path, under a single lock.
+pub fn resume_positions<'a>(paths: impl Iterator<Item = &'a str>) -> Vec<Option<f32>> {
+    let points = RESUME_POINTS.lock();
+    paths.map(|path| points.get(&canonical_key(path)).map(|point| point.position)).collect()
+}
+
+/// Carries a resume point over when a file is moved or renamed.
+pub fn remap_resume_point(from: &str, to: &str) {
+    let mut points = RESUME_POINTS.lock();
+    if let Some(point) = points.remove(&canonical_key(from)) {
+        points.insert(canonical_key(to), point);
+        save_resume_points(&points);
+    }
+}
+
+#[tauri::command]
+pub fn get_bookmark(path: String) -> Option<ResumePoint> {
+    RESUME_POINTS.lock().get(&canonical_key(&path)).cloned()
+}
+
+/// Bookmarks `path` at `secs`, for `play_audio` to resume from. Like the
+/// points saved automatically, it is only used for files long enough to resume.
+#[tauri::command]
+pub fn set_bookmark(path: String, secs: f32) -> Result<ResumePoint, String> {
+    if !Path::new(&path).is_file() {
+        return Err(format!("{} does not exist", path));
+    }
+    let duration = probe_duration(Path::new(&path)).map(|d| d.as_secs_f32()).unwrap_or(0.0);
+    if !secs.is_finite() || secs < 0.0 || (duration > 0.0 && secs > duration) {
+        return Err(format!("{:.1}s is not a position within {}", secs, path));
+    }
+    let point = ResumePoint { position: secs, duration, updated_at: now_secs() };
+    let mut points = RESUME_POINTS.lock();
+    points.insert(canonical_key(&path), point.clone());
+    save_resume_points(&points);
+    Ok(point)
+}
+
+#[tauri::command]
+pub fn clear_bookmark(path: String) {
+    forget_resume_point(&path);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn pruning_drops_missing_files_and_canonicalizes_keys() {
+        let dir = crate::test_support::temp_dir();
+        let kept = dir.path().join("book.m4b");
+        fs::write(&kept, b"").unwrap();
+        let point = ResumePoint { position: 60.0, duration: 3600.0, updated_at: 0 };
+        let stored = HashMap::from([
+            (format!("{}/./book.m4b", dir.path().display()), point.clone()),
+            (dir.path().join("gone.mp3").to_string_lossy().to_string(), point),
+        ]);
+        let points = prune_missing(stored);
+        assert_eq!(points.len(), 1);
+        assert!(points.contains_key(&canonical_key(&kept.to_string_lossy())));
+    }
+}
@@ -0,0 +1,116 @@
+use serde::Serialize;
+use std::path::Path;
+use std::process::Command;
+use log::{info, error};
+
+#[derive(Debug, Serialize)]
+#[serde(tag = "kind", content = "message")]
+pub enum RevealError {
+    NotFound(String),
+    LaunchFailed(String),
+}
+
+#[tauri::command]
+pub fn reveal_in_file_manager(path: String) -> Result<(), RevealError> {
+    let path = Path::new(&path);
+    if !path.exists() {
+        return Err(RevealError::NotFound(format!("Path does not exist: {}", path.display())));
+    }
+
+    info!("Revealing {} in file manager", path.display());
+
+    // Directories open as themselves; files are selected inside their parent
+    let result = if path.is_dir() {
+        open_directory(path)
+    } else {
+        select_file(path)
+    };
+
+    result.map_err(|e| {
+        error!("Failed to reveal {}: {}", path.display(), e);
+        RevealError::LaunchFailed(e)
+    })
+}
+
+fn spawn(command: &mut Command) -> Result<(), String> {
+    command.spawn()
+        .map(|_| ())
+        .map_err(|e| format!("Failed to launch file manager: {}", e))
+}
+
+#[cfg(target_os = "macos")]
+fn open_directory(path: &Path) -> Result<(), String> {
+    spawn(Command::new("open").arg(path))
+}
+
+#[cfg(target_os = "macos")]
+fn select_file(path: &Path) -> Result<(), String> {
+    spawn(Command::new("open").arg("-R").arg(path))
+}
+
+#[cfg(target_os = "windows")]
+fn open_directory(path: &Path) -> Result<(), String> {
+    spawn(Command::new("explorer").arg(path))
+}
+
+#[cfg(target_os = "windows")]
+fn select_file(path: &Path) -> Result<(), String> {
+    use std::os::windows::process::CommandExt;
+
+    // explorer does its own argument parsing, so the path has to be quoted by hand
+    // rather than through Command's escaping. Its exit code is meaningless, so only
+    // a failure to spawn is treated as an error.
+    spawn(Command::new("explorer").raw_arg(format!("/select,\"{}\"", path.display())))
+}
+
+#[cfg(target_os = "linux")]
+fn open_directory(path: &Path) -> Result<(), String> {
+    spawn(Command::new("xdg-open").arg(path))
+}
+
+#[cfg(target_os = "linux")]
+fn select_file(path: &Path) -> Result<(), String> {
+    let uri = file_uri(path);
+
+    // Ask the desktop's file manager to highlight the item; not every desktop implements it
+    let status = Command::new("dbus-send")
+        .args([
+            "--session",
+            "--print-reply",
+            "--dest=org.freedesktop.FileManager1",
+            "--type=method_call",
+            "/org/freedesktop/FileManager1",
+            "org.freedesktop.FileManager1.ShowItems",
+        ])
+        .arg(format!("array:string:{}", uri))
+        .arg("string:")
+        .output();
+
+    match status {
+        Ok(output) if output.status.success() => Ok(()),
+        _ => {
+            info!("FileManager1 unavailable, falling back to xdg-open");
+            let parent = path.parent().unwrap_or(path);
+            open_directory(parent)
+        }
+    }
+}
+
+/// Builds a `file://` URI, percent-encoding everything outside the unreserved set
+/// so spaces, commas and non-ASCII names survive D-Bus argument parsing.
+#[cfg(target_os = "linux")]
+fn file_uri(path: &Path) -> String {
+    use std::os::unix::ffi::OsStrExt;
+
+    let absolute = std::fs::canonicalize(path).unwrap_or_else(|_| path.to_path_buf());
+    let mut uri = String::from("file://");
+    for byte in absolute.as_os_str().as_bytes() {
+        match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' | b'/' => {
+                uri.push(*byte as char)
+            }
+            _ => uri.push_str(&format!("%{:02X}", byte)),
+        }
+    }
+    uri
+}
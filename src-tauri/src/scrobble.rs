@@ -0,0 +1,189 @@
+//! An offline scrobble journal in the Rockbox `.scrobbler.log` format
+//! (Audioscrobbler 1.1), which third-party uploaders submit to Last.fm. Plays
+//! are appended as they qualify, the same way play counts are recorded.
+
+use serde::Serialize;
+use std::fs::{self, File, OpenOptions};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use once_cell::sync::Lazy;
+use parking_lot::Mutex;
+use tauri::{AppHandle, Emitter};
+use log::{error, warn};
+use crate::config::get_config_dir;
+use crate::library::track_info;
+
+// Timestamps are written as Unix time, so the zone is UTC
+const LOG_HEADER: &str = concat!("#AUDIOSCROBBLER/1.1\n#TZ/UTC\n#CLIENT/musicManager ", env!("CARGO_PKG_VERSION"), "\n");
+
+// Appends and exports would otherwise interleave
+static LOG_LOCK: Lazy<Mutex<()>> = Lazy::new(|| Mutex::new(()));
+
+#[derive(Debug, Serialize, Clone, PartialEq)]
+pub struct Scrobble {
+    pub artist: String,
+    pub album: Option<String>,
+    pub title: String,
+    pub track_number: Option<u32>,
+    // Whole seconds
+    pub duration: u64,
+    // When the track started playing
+    pub timestamp: u64,
+}
+
+/// Payload of `scrobble-skipped`, for a qualifying play that can't be logged.
+#[derive(Debug, Serialize, Clone)]
+pub struct ScrobbleSkipped {
+    pub path: String,
+    pub reason: String,
+}
+
+fn log_path() -> Option<PathBuf> {
+    get_config_dir().map(|dir| dir.join(".scrobbler.log"))
+}
+
+/// Fields are tab separated, one entry a line, so neither may appear inside one.
+fn clean_field(value: &str) -> String {
+    value.chars().map(|c| if matches!(c, '\t' | '\n' | '\r') { ' ' } else { c }).collect::<String>().trim().to_string()
+}
+
+impl Scrobble {
+    /// The entry as a log line: artist, album, title, track number, length,
+    /// rating (L for listened), timestamp and an empty MusicBrainz track id.
+    fn to_line(&self) -> String {
+        format!(
+            "{}\t{}\t{}\t{}\t{}\tL\t{}\t\n",
+            clean_field(&self.artist),
+            self.album.as_deref().map(clean_field).unwrap_or_default(),
+            clean_field(&self.title),
+            self.track_number.map(|n| n.to_string()).unwrap_or_default(),
+            self.duration,
+            self.timestamp,
+        )
+    }
+
+    /// Reads back a listened entry; headers, skips and malformed lines are None.
+    fn from_line(line: &str) -> Option<Self> {
+        if line.starts_with('#') {
+            return None;
+        }
+        let fields: Vec<&str> = line.trim_end_matches(['\r', '\n']).split('\t').collect();
+        if fields.len() < 7 || fields[5] != "L" {
+            return None;
+        }
+        Some(Self {
+            artist: fields[0].to_string(),
+            album: Some(fields[1].to_string()).filter(|album| !album.is_empty()),
+            title: fields[2].to_string(),
+            track_number: fields[3].parse().ok(),
+            duration: fields[4].parse().ok()?,
+            timestamp: fields[6].parse().ok()?,
+        })
+    }
+}
+
+fn append(path: &Path, scrobble: &Scrobble) -> Result<(), String> {
+    let _lock = LOG_LOCK.lock();
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent).map_err(|e| e.to_string())?;
+    }
+    let mut file = OpenOptions::new().create(true).append(true).open(path).map_err(|e| e.to_string())?;
+    let new = file.metadata().map(|metadata| metadata.len() == 0).unwrap_or(true);
+    let mut text = if new { LOG_HEADER.to_string() } else { String::new() };
+    text.push_str(&scrobble.to_line());
+    file.write_all(text.as_bytes()).map_err(|e| e.to_string())
+}
+
+/// Logs a qualifying play of `path` that began at `started_at`, taking the
+/// artist and title from its tags. Plays without them are reported with
+/// `scrobble-skipped` instead, since uploaders reject them.
+pub fn record(app: &AppHandle, path: &str, started_at: u64, duration: Option<Duration>) {
+    let track = track_info(Path::new(path));
+    let tag = |value: Option<&String>| value.map(|value| clean_field(value)).filter(|value| !value.is_empty());
+    let named = track.and_then(|track| Some((tag(track.artist.as_ref())?, tag(track.title.as_ref())?, track)));
+    let Some((artist, title, track)) = named else {
+        warn!("Not scrobbling {}: it has no artist or title", path);
+        app.emit("scrobble-skipped", ScrobbleSkipped {
+            path: path.to_string(),
+            reason: "The track has no artist or title tag".to_string(),
+        }).ok();
+        return;
+    };
+    let scrobble = Scrobble {
+        artist,
+        album: tag(track.album.as_ref()),
+        title,
+        track_number: track.track_number,
+        duration: track.duration.map(|secs| secs.round() as u64)
+            .or(duration.map(|duration| duration.as_secs_f64().round() as u64))
+            .unwrap_or(0),
+        timestamp: started_at,
+    };
+    let Some(log) = log_path() else { return };
+    if let Err(e) = append(&log, &scrobble) {
+        error!("Failed to log a scrobble for {}: {}", path, e);
+    }
+}
+
+fn read_log(path: &Path) -> Vec<Scrobble> {
+    fs::read_to_string(path).map(|text| text.lines().filter_map(Scrobble::from_line).collect()).unwrap_or_default()
+}
+
+/// Plays logged and not yet exported, oldest first.
+#[tauri::command]
+pub fn get_pending_scrobbles() -> Vec<Scrobble> {
+    log_path().map(|path| read_log(&path)).unwrap_or_default()
+}
+
+/// Copies the log to `path` for an uploader, then starts the journal afresh so
+/// the same plays aren't handed over twice. Returns how many were exported.
+#[tauri::command]
+pub fn export_scrobble_log(path: String) -> Result<usize, String> {
+    let log = log_path().ok_or("Could not determine config directory")?;
+    let _lock = LOG_LOCK.lock();
+    let pending = read_log(&log).len();
+    if pending == 0 {
+        return Err("There are no scrobbles to export".to_string());
+    }
+    fs::copy(&log, &path).map_err(|e| format!("Failed to export the scrobble log: {}", e))?;
+    File::create(&log).map_err(|e| format!("Exported, but failed to clear the scrobble log: {}", e))?;
+    Ok(pending)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn scrobble() -> Scrobble {
+        Scrobble {
+            artist: "Boards of Canada".to_string(),
+            album: Some("Geogaddi".to_string()),
+            title: "Music Is Math".to_string(),
+            track_number: Some(4),
+            duration: 321,
+            timestamp: 1_700_000_000,
+        }
+    }
+
+    #[test]
+    fn lines_follow_the_audioscrobbler_format() {
+        assert_eq!(scrobble().to_line(), "Boards of Canada\tGeogaddi\tMusic Is Math\t4\t321\tL\t1700000000\t\n");
+        let bare = Scrobble { album: None, track_number: None, title: "Tab\there".to_string(), ..scrobble() };
+        assert_eq!(bare.to_line(), "Boards of Canada\t\tTab here\t\t321\tL\t1700000000\t\n");
+        assert_eq!(Scrobble::from_line(&bare.to_line()), Some(Scrobble { title: "Tab here".to_string(), ..bare }));
+    }
+
+    #[test]
+    fn log_gets_one_header_and_reads_back() {
+        let dir = crate::test_support::temp_dir();
+        let log = dir.path().join(".scrobbler.log");
+        append(&log, &scrobble()).unwrap();
+        append(&log, &scrobble()).unwrap();
+        let text = fs::read_to_string(&log).unwrap();
+        assert!(text.starts_with("#AUDIOSCROBBLER/1.1\n#TZ/UTC\n#CLIENT/"));
+        assert_eq!(text.matches("#AUDIOSCROBBLER").count(), 1);
+        assert_eq!(read_log(&log), vec![scrobble(), scrobble()]);
+        assert_eq!(Scrobble::from_line("a\tb\tc\t1\t200\tS\t1700000000\t"), None);
+    }
+}
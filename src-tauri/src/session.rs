@@ -0,0 +1,200 @@
+//! The playback session (queue, current track and position, volume and modes),
+//! saved to `session.json` next to the player config while the app runs and on
+//! exit, so the next launch can pick up where this one left off.
+
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use once_cell::sync::Lazy;
+use parking_lot::Mutex;
+use tauri::{AppHandle, Manager};
+use log::{error, info};
+use crate::commands::{emit_playback_state, pause_audio, start_playback_at};
+use crate::config::{get_config_dir, load_player_config, RepeatMode, ShuffleMode};
+use crate::config_file::write_json;
+use crate::queue::{queued_track, with_queue, QueuedTrack};
+use crate::PlayerHandle;
+
+const SAVE_INTERVAL: Duration = Duration::from_secs(30);
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct SessionState {
+    pub queue: Vec<String>,
+    pub queue_position: usize,
+    // The order the queue was built in, for turning shuffle off later
+    pub original_order: Vec<String>,
+    pub current_path: Option<String>,
+    pub position: f32,
+    pub volume: f32,
+    pub shuffle_mode: ShuffleMode,
+    pub repeat_mode: RepeatMode,
+}
+
+#[derive(Debug, Serialize)]
+pub struct RestoredSession {
+    pub queue: Vec<QueuedTrack>,
+    pub queue_position: usize,
+    pub current_path: Option<String>,
+    pub position: f32,
+    // Saved tracks that no longer exist, left out of the queue
+    pub dropped: Vec<String>,
+}
+
+// What was last written, or found at launch; unchanged sessions aren't rewritten
+static LAST_SAVED: Lazy<Mutex<Option<SessionState>>> = Lazy::new(|| Mutex::new(None));
+
+fn session_path() -> Option<PathBuf> {
+    get_config_dir().map(|dir| dir.join("session.json"))
+}
+
+fn enabled() -> bool {
+    load_player_config().playback_settings.restore_session
+}
+
+fn current_session(player: &PlayerHandle) -> SessionState {
+    player.with_queue(|player, queue| SessionState {
+        queue: queue.tracks.iter().map(|track| track.path.clone()).collect(),
+        queue_position: queue.position,
+        original_order: queue.original_order.clone(),
+        current_path: player.current_path.clone(),
+        position: player.position().as_secs_f32(),
+        volume: player.volume,
+        shuffle_mode: queue.shuffle_mode,
+        repeat_mode: queue.repeat_mode,
+    })
+}
+
+/// Writes the session if it changed since the last save. Called periodically
+/// and from the exit hook.
+pub fn save_session(player: &PlayerHandle) {
+    if !enabled() {
+        return;
+    }
+    let Some(path) = session_path() else { return };
+    let session = current_session(player);
+    let mut last_saved = LAST_SAVED.lock();
+    if last_saved.as_ref() == Some(&session) {
+        return;
+    }
+    match write_json(&path, &session) {
+        Ok(()) => *last_saved = Some(session),
+        Err(e) => error!("Failed to save the playback session: {}", e),
+    }
+}
+
+/// Saves the session every so often. The state at launch counts as saved, so
+/// an app closed before anything was played keeps the previous session.
+pub fn start_session_saver(player: PlayerHandle) {
+    *LAST_SAVED.lock() = Some(current_session(&player));
+    std::thread::spawn(move || loop {
+        std::thread::sleep(SAVE_INTERVAL);
+        save_session(&player);
+    });
+}
+
+fn load_session(path: &Path) -> Option<SessionState> {
+    let contents = fs::read_to_string(path).ok()?;
+    match serde_json::from_str(&contents) {
+        Ok(session) => Some(session),
+        Err(e) => {
+            error!("Ignoring unreadable session {}: {}", path.display(), e);
+            None
+        }
+    }
+}
+
+/// Where `position` ends up once the entries not `kept` are removed: on the
+/// same entry, or the next surviving one if it was removed itself.
+fn surviving_position(kept: &[bool], position: usize) -> usize {
+    kept.iter().take(position).filter(|kept| **kept).count()
+}
+
+/// Reloads the last saved session into the queue. With `load_track` the saved
+/// track is also opened, paused at its saved position. `None` when session
+/// restore is turned off or nothing was saved.
+#[tauri::command]
+pub fn restore_session(app: AppHandle, load_track: bool) -> Result<Option<RestoredSession>, String> {
+    if !enabled() {
+        return Ok(None);
+    }
+    let Some(session) = session_path().and_then(|path| load_session(&path)) else {
+        return Ok(None);
+    };
+
+    let mut dropped = Vec::new();
+    let mut tracks = Vec::new();
+    let mut kept = Vec::with_capacity(session.queue.len());
+    for path in &session.queue {
+        let keep = match queued_track(path) {
+            Ok(track) => {
+                tracks.push(track);
+                true
+            }
+            Err(_) => {
+                if !dropped.contains(path) {
+                    dropped.push(path.clone());
+                }
+                false
+            }
+        };
+        kept.push(keep);
+    }
+    let queue_position = surviving_position(&kept, session.queue_position).min(tracks.len());
+    let missing: HashSet<&String> = dropped.iter().collect();
+    let original_order: Vec<String> = session.original_order.iter()
+        .filter(|path| !missing.contains(path) && Path::new(path).is_file())
+        .cloned()
+        .collect();
+    let current_path = match session.current_path {
+        Some(path) if Path::new(&path).is_file() => Some(path),
+        Some(path) => {
+            if !missing.contains(&path) {
+                dropped.push(path);
+            }
+            None
+        }
+        None => None,
+    };
+
+    with_queue(|queue| {
+        queue.tracks = tracks.clone();
+        queue.position = queue_position;
+        queue.original_order = original_order;
+        queue.shuffle_mode = session.shuffle_mode;
+        queue.repeat_mode = session.repeat_mode;
+    });
+    if session.volume.is_finite() {
+        let volume = session.volume.clamp(0.0, 1.0);
+        app.state::<PlayerHandle>().send(move |player| player.volume = volume);
+    }
+
+    let position = if current_path.is_some() { session.position.max(0.0) } else { 0.0 };
+    if let (true, Some(path)) = (load_track, &current_path) {
+        // Opened silent and paused straight away, so nothing is heard until resume
+        let player = app.state::<PlayerHandle>();
+        start_playback_at(&player, path, true, Some(Duration::from_secs_f32(position))).map_err(|e| e.to_string())?;
+        pause_audio(player)?;
+    }
+    info!("Restored a session of {} tracks ({} dropped)", tracks.len(), dropped.len());
+    emit_playback_state(&app);
+
+    Ok(Some(RestoredSession { queue: tracks, queue_position, current_path, position, dropped }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn position_follows_its_track_past_removed_entries() {
+        let kept = [true, false, true, false, true];
+        assert_eq!(surviving_position(&kept, 0), 0);
+        assert_eq!(surviving_position(&kept, 2), 1);
+        // A removed current entry moves on to the one after it
+        assert_eq!(surviving_position(&kept, 3), 2);
+        assert_eq!(surviving_position(&kept, 4), 2);
+        assert_eq!(surviving_position(&kept, 5), 3);
+    }
+}
@@ -0,0 +1,115 @@
+use serde::{Deserialize, Serialize};
+use std::sync::atomic::{AtomicBool, Ordering};
+use tauri::{AppHandle, Emitter, Manager};
+use tauri_plugin_global_shortcut::{GlobalShortcutExt, ShortcutState};
+use log::{info, error};
+use crate::commands::{pause_audio, previous_track, resume_audio, set_volume, skip_track};
+use crate::config::{load_player_config, ShortcutSettings};
+use crate::volume::current_slider_volume;
+use crate::PlayerHandle;
+
+const VOLUME_STEP: f32 = 0.05;
+
+static SUSPENDED: AtomicBool = AtomicBool::new(false);
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum ShortcutAction {
+    PlayPause,
+    Next,
+    Previous,
+    VolumeUp,
+    VolumeDown,
+}
+
+fn bindings(settings: &ShortcutSettings) -> Vec<(ShortcutAction, &str)> {
+    [
+        (ShortcutAction::PlayPause, &settings.play_pause),
+        (ShortcutAction::Next, &settings.next),
+        (ShortcutAction::Previous, &settings.previous),
+        (ShortcutAction::VolumeUp, &settings.volume_up),
+        (ShortcutAction::VolumeDown, &settings.volume_down),
+    ]
+    .into_iter()
+    .filter_map(|(action, accelerator)| {
+        accelerator.as_deref()
+            .map(str::trim)
+            .filter(|accelerator| !accelerator.is_empty())
+            .map(|accelerator| (action, accelerator))
+    })
+    .collect()
+}
+
+#[derive(Debug, Serialize, Clone)]
+pub struct ShortcutRegistrationFailed {
+    pub action: ShortcutAction,
+    pub accelerator: String,
+    pub error: String,
+}
+
+fn trigger(app: &AppHandle, action: ShortcutAction) {
+    let result = match action {
+        ShortcutAction::PlayPause => {
+            let player = app.state::<PlayerHandle>();
+            let is_playing = player.with(|player| player.is_playing);
+            if is_playing { pause_audio(player) } else { resume_audio(player) }
+        }
+        ShortcutAction::Next => skip_track(app.clone()),
+        ShortcutAction::Previous => previous_track(app.clone()),
+        ShortcutAction::VolumeUp | ShortcutAction::VolumeDown => {
+            let step = if action == ShortcutAction::VolumeUp { VOLUME_STEP } else { -VOLUME_STEP };
+            let player = app.state::<PlayerHandle>();
+            let volume = (current_slider_volume(&player) + step).clamp(0.0, 1.0);
+            set_volume(player, volume)
+        }
+    };
+
+    match result {
+        Ok(()) => {
+            app.emit("shortcut-triggered", action).ok();
+        }
+        Err(e) => error!("Global shortcut {:?} failed: {}", action, e),
+    }
+}
+
+/// Replaces every registered global shortcut with the ones in `settings`.
+/// Bad accelerators are reported through `shortcut-registration-failed` and
+/// skipped, so one conflict never takes the other shortcuts down with it.
+pub fn apply_shortcuts(app: &AppHandle, settings: &ShortcutSettings) {
+    let manager = app.global_shortcut();
+    if let Err(e) = manager.unregister_all() {
+        error!("Failed to unregister global shortcuts: {}", e);
+    }
+    if !settings.enabled || SUSPENDED.load(Ordering::SeqCst) {
+        return;
+    }
+
+    for (action, accelerator) in bindings(settings) {
+        let registered = manager.on_shortcut(accelerator, move |app, _shortcut, event| {
+            if event.state() == ShortcutState::Pressed {
+                trigger(app, action);
+            }
+        });
+
+        if let Err(e) = registered {
+            error!("Failed to register global shortcut {} for {:?}: {}", accelerator, action, e);
+            app.emit("shortcut-registration-failed", ShortcutRegistrationFailed {
+                action,
+                accelerator: accelerator.to_string(),
+                error: e.to_string(),
+            }).ok();
+        }
+    }
+    info!("Registered global shortcuts");
+}
+
+#[tauri::command]
+pub fn set_global_shortcuts_suspended(app: AppHandle, suspended: bool) -> Result<(), String> {
+    SUSPENDED.store(suspended, Ordering::SeqCst);
+    if suspended {
+        app.global_shortcut().unregister_all().map_err(|e| e.to_string())
+    } else {
+        apply_shortcuts(&app, &load_player_config().shortcuts);
+        Ok(())
+    }
+}
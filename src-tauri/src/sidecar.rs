@@ -0,0 +1,368 @@
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::{Path, PathBuf};
+use log::debug;
+
+// Checked in order; the first one present wins
+const SIDECAR_FILES: [&str; 4] = ["album.nfo", "folder.nfo", "folder.txt", "album.txt"];
+
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+pub struct AlbumSidecar {
+    pub album: Option<String>,
+    pub album_artist: Option<String>,
+    pub year: Option<u32>,
+    pub genre: Option<String>,
+    pub label: Option<String>,
+    pub comment: Option<String>,
+    pub source_file: Option<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum SidecarFormat {
+    Nfo,
+    Txt,
+}
+
+impl AlbumSidecar {
+    fn is_empty(&self) -> bool {
+        self.album.is_none()
+            && self.album_artist.is_none()
+            && self.year.is_none()
+            && self.genre.is_none()
+            && self.label.is_none()
+            && self.comment.is_none()
+    }
+
+    /// Fills any field that is still missing from `other`.
+    fn merge_missing(&mut self, other: AlbumSidecar) {
+        self.album = self.album.take().or(other.album);
+        self.album_artist = self.album_artist.take().or(other.album_artist);
+        self.year = self.year.or(other.year);
+        self.genre = self.genre.take().or(other.genre);
+        self.label = self.label.take().or(other.label);
+        self.comment = self.comment.take().or(other.comment);
+    }
+}
+
+fn unescape_xml(value: &str) -> String {
+    value
+        .replace("&lt;", "<")
+        .replace("&gt;", ">")
+        .replace("&quot;", "\"")
+        .replace("&apos;", "'")
+        .replace("&amp;", "&")
+}
+
+fn escape_xml(value: &str) -> String {
+    value
+        .replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+/// Finds the text of the first `<tag>...</tag>` element, ignoring case and
+/// attributes. This is deliberately not a real XML parser: it keeps working on
+/// truncated or hand-edited files a strict parser would reject.
+fn extract_xml_field(contents: &str, tag: &str) -> Option<String> {
+    // ASCII-only lowercasing keeps byte offsets valid for slicing `contents`
+    let lower = contents.to_ascii_lowercase();
+    let open = format!("<{}", tag);
+    let close = format!("</{}>", tag);
+
+    let mut search_from = 0;
+    while let Some(found) = lower[search_from..].find(&open) {
+        let tag_start = search_from + found;
+        let after_name = tag_start + open.len();
+        // Make sure we matched `<year>` and not `<yearly>`
+        match lower[after_name..].chars().next() {
+            Some('>') | Some(' ') | Some('\t') | Some('\n') | Some('\r') => {}
+            _ => {
+                search_from = after_name;
+                continue;
+            }
+        }
+
+        let content_start = after_name + lower[after_name..].find('>')? + 1;
+        let content_end = lower[content_start..]
+            .find(&close)
+            .map(|end| content_start + end)
+            .or_else(|| lower[content_start..].find('<').map(|end| content_start + end))
+            .unwrap_or(contents.len());
+
+        let value = unescape_xml(contents[content_start..content_end].trim());
+        return if value.is_empty() { None } else { Some(value) };
+    }
+    None
+}
+
+fn parse_year(value: &str) -> Option<u32> {
+    value
+        .split(|c: char| !c.is_ascii_digit())
+        .find(|part| part.len() == 4)
+        .and_then(|part| part.parse().ok())
+}
+
+fn parse_nfo(contents: &str) -> AlbumSidecar {
+    let year = extract_xml_field(contents, "year")
+        .or_else(|| extract_xml_field(contents, "releasedate"))
+        .and_then(|value| parse_year(&value));
+
+    let parsed = AlbumSidecar {
+        album: extract_xml_field(contents, "title"),
+        album_artist: extract_xml_field(contents, "albumartist")
+            .or_else(|| extract_xml_field(contents, "artistdesc"))
+            .or_else(|| extract_xml_field(contents, "artist")),
+        year,
+        genre: extract_xml_field(contents, "genre"),
+        label: extract_xml_field(contents, "label"),
+        comment: extract_xml_field(contents, "review")
+            .or_else(|| extract_xml_field(contents, "comment")),
+        source_file: None,
+    };
+
+    // Scene-style NFOs are free text rather than XML; fall back to key/value lines
+    if parsed.is_empty() { parse_key_values(contents) } else { parsed }
+}
+
+/// Parses `Key: Value` or `Key = Value` lines, as found in folder.txt files.
+fn parse_key_values(contents: &str) -> AlbumSidecar {
+    let mut sidecar = AlbumSidecar::default();
+
+    for line in contents.lines() {
+        let Some(split_at) = line.find([':', '=']) else { continue };
+        // Scene NFOs pad keys with dot leaders: `Artist......: Name`
+        let key = line[..split_at].trim().to_lowercase().replace([' ', '_', '.'], "");
+        let value = line[split_at + 1..].trim().trim_matches('.').trim();
+        if value.is_empty() {
+            continue;
+        }
+        let value = value.to_string();
+
+        match key.as_str() {
+            "album" | "title" | "albumtitle" => sidecar.album = sidecar.album.or(Some(value)),
+            "albumartist" | "artist" | "performer" => {
+                sidecar.album_artist = sidecar.album_artist.or(Some(value))
+            }
+            "year" | "date" | "released" | "releasedate" => sidecar.year = sidecar.year.or(parse_year(&value)),
+            "genre" | "style" => sidecar.genre = sidecar.genre.or(Some(value)),
+            "label" | "publisher" => sidecar.label = sidecar.label.or(Some(value)),
+            "comment" | "notes" => sidecar.comment = sidecar.comment.or(Some(value)),
+            _ => {}
+        }
+    }
+
+    sidecar
+}
+
+fn parse_sidecar_file(path: &Path) -> Option<AlbumSidecar> {
+    // Lossy decoding: old NFOs are frequently CP437/Latin-1 rather than UTF-8
+    let bytes = fs::read(path).ok()?;
+    let contents = String::from_utf8_lossy(&bytes);
+
+    let is_nfo = path.extension().and_then(|e| e.to_str()).is_some_and(|e| e.eq_ignore_ascii_case("nfo"));
+    let mut sidecar = if is_nfo { parse_nfo(&contents) } else { parse_key_values(&contents) };
+    sidecar.source_file = Some(path.to_string_lossy().to_string());
+    debug!("Parsed sidecar {}: {:?}", path.display(), sidecar);
+    Some(sidecar)
+}
+
+fn find_sidecar(folder: &Path) -> Option<PathBuf> {
+    SIDECAR_FILES.iter()
+        .map(|name| folder.join(name))
+        .find(|path| path.is_file())
+}
+
+/// Reads the folder's sidecar, if any. Parsing never fails; at worst the
+/// returned struct has no fields set.
+pub fn load_sidecar(folder: &Path) -> Option<AlbumSidecar> {
+    find_sidecar(folder).and_then(|path| parse_sidecar_file(&path))
+}
+
+fn render_nfo(sidecar: &AlbumSidecar) -> String {
+    let mut xml = String::from("<?xml version=\"1.0\" encoding=\"UTF-8\" standalone=\"yes\"?>\n<album>\n");
+    let fields = [
+        ("title", sidecar.album.clone()),
+        ("albumartist", sidecar.album_artist.clone()),
+        ("year", sidecar.year.map(|y| y.to_string())),
+        ("genre", sidecar.genre.clone()),
+        ("label", sidecar.label.clone()),
+        ("review", sidecar.comment.clone()),
+    ];
+    for (tag, value) in fields {
+        if let Some(value) = value {
+            xml.push_str(&format!("    <{}>{}</{}>\n", tag, escape_xml(&value), tag));
+        }
+    }
+    xml.push_str("</album>\n");
+    xml
+}
+
+fn render_txt(sidecar: &AlbumSidecar) -> String {
+    let fields = [
+        ("Album", sidecar.album.clone()),
+        ("Album Artist", sidecar.album_artist.clone()),
+        ("Year", sidecar.year.map(|y| y.to_string())),
+        ("Genre", sidecar.genre.clone()),
+        ("Label", sidecar.label.clone()),
+        ("Comment", sidecar.comment.clone()),
+    ];
+    fields.iter()
+        .filter_map(|(key, value)| value.as_ref().map(|value| format!("{}: {}\n", key, value)))
+        .collect()
+}
+
+#[tauri::command]
+pub fn read_album_sidecar(folder: String) -> Result<Option<AlbumSidecar>, String> {
+    let folder = Path::new(&folder);
+    if !folder.is_dir() {
+        return Err("Folder does not exist".to_string());
+    }
+    Ok(load_sidecar(folder))
+}
+
+#[tauri::command]
+pub fn write_album_sidecar(folder: String, data: AlbumSidecar, format: SidecarFormat) -> Result<String, String> {
+    let folder = Path::new(&folder);
+    if !folder.is_dir() {
+        return Err("Folder does not exist".to_string());
+    }
+
+    let file_name = match format {
+        SidecarFormat::Nfo => "album.nfo",
+        SidecarFormat::Txt => "folder.txt",
+    };
+    let path = folder.join(file_name);
+
+    // Updating keeps fields the caller didn't supply
+    let mut sidecar = data;
+    if let Some(existing) = parse_sidecar_file(&path) {
+        sidecar.merge_missing(existing);
+    }
+
+    let contents = match format {
+        SidecarFormat::Nfo => render_nfo(&sidecar),
+        SidecarFormat::Txt => render_txt(&sidecar),
+    };
+    fs::write(&path, contents).map_err(|e| format!("Failed to write sidecar: {}", e))?;
+
+    Ok(path.to_string_lossy().to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::temp_dir;
+
+    #[test]
+    fn extracts_fields_from_messy_xml() {
+        let xml = "<Album><TITLE lang=\"en\">Tom &amp; Jerry</TITLE><yearly>1980</yearly><year>1999</year></Album>";
+        assert_eq!(extract_xml_field(xml, "title").as_deref(), Some("Tom & Jerry"));
+        assert_eq!(extract_xml_field(xml, "year").as_deref(), Some("1999"));
+        assert_eq!(extract_xml_field("<title>  </title>", "title"), None);
+        assert_eq!(extract_xml_field("<album><genre>Rock</genre></album>", "title"), None);
+    }
+
+    #[test]
+    fn extracts_fields_from_malformed_and_truncated_xml() {
+        // Misspelt closing tag: the value ends at the next tag
+        assert_eq!(extract_xml_field("<title>Foo</titel><year>1999</year>", "title").as_deref(), Some("Foo"));
+        // Never closed
+        assert_eq!(extract_xml_field("<album><title>Foo<artist>Bar", "title").as_deref(), Some("Foo"));
+        assert_eq!(extract_xml_field("<album><title>Foo", "title").as_deref(), Some("Foo"));
+        // Cut off inside the opening tag
+        assert_eq!(extract_xml_field("<album><title", "title"), None);
+        assert_eq!(extract_xml_field("<album><title lang=", "title"), None);
+
+        let sidecar = parse_nfo("<?xml version=\"1.0\"?>\n<album>\n  <title>Foo</title>\n  <artist>Bar</artist>\n  <year>2001");
+        assert_eq!(sidecar.album.as_deref(), Some("Foo"));
+        assert_eq!(sidecar.album_artist.as_deref(), Some("Bar"));
+        assert_eq!(sidecar.year, Some(2001));
+    }
+
+    #[test]
+    fn prefers_album_artist_and_release_date_fallbacks() {
+        let sidecar = parse_nfo("<album><artist>Track Artist</artist><albumartist>Album Artist</albumartist><releasedate>2004-05-06</releasedate></album>");
+        assert_eq!(sidecar.album_artist.as_deref(), Some("Album Artist"));
+        assert_eq!(sidecar.year, Some(2004));
+    }
+
+    #[test]
+    fn falls_back_to_key_values_for_scene_nfos() {
+        let nfo = "\
+      ___  ___  ___
+     /  / /  / /  /   RELEASE GROUP
+  http://example.invalid
+
+  Artist........: Some Band
+  Album.........: First Light
+  Label.........: Small Records
+  Genre.........: Rock
+  Release Date..: 2001-03-05
+  Playtime......: 45:12
+";
+        let sidecar = parse_nfo(nfo);
+        assert_eq!(sidecar.album_artist.as_deref(), Some("Some Band"));
+        assert_eq!(sidecar.album.as_deref(), Some("First Light"));
+        assert_eq!(sidecar.label.as_deref(), Some("Small Records"));
+        assert_eq!(sidecar.genre.as_deref(), Some("Rock"));
+        assert_eq!(sidecar.year, Some(2001));
+        assert_eq!(sidecar.comment, None);
+    }
+
+    #[test]
+    fn parses_folder_txt_key_values() {
+        let txt = "Album Artist = Foo\nalbum_artist: Ignored\nALBUM: Bar: Live\nYear = c. 1975\nGenre =\nstray line\nNotes: Remastered.\n";
+        let sidecar = parse_key_values(txt);
+        assert_eq!(sidecar.album_artist.as_deref(), Some("Foo"));
+        assert_eq!(sidecar.album.as_deref(), Some("Bar: Live"));
+        assert_eq!(sidecar.year, Some(1975));
+        assert_eq!(sidecar.genre, None);
+        assert_eq!(sidecar.comment.as_deref(), Some("Remastered"));
+    }
+
+    #[test]
+    fn parses_odd_year_strings() {
+        assert_eq!(parse_year("1999"), Some(1999));
+        assert_eq!(parse_year("1999-04-12"), Some(1999));
+        assert_eq!(parse_year("12/04/1999"), Some(1999));
+        assert_eq!(parse_year("℗ 2003"), Some(2003));
+        assert_eq!(parse_year("1990s"), Some(1990));
+        assert_eq!(parse_year("'99"), None);
+        assert_eq!(parse_year("19995"), None);
+        assert_eq!(parse_year("MCMXCIX"), None);
+        assert_eq!(parse_year(""), None);
+    }
+
+    #[test]
+    fn reads_latin1_sidecars_in_priority_order() {
+        let dir = temp_dir();
+        fs::write(dir.path().join("album.txt"), "Album: Ignored\n").unwrap();
+        // "Café" in Latin-1
+        fs::write(dir.path().join("folder.txt"), b"Album: Caf\xe9\nYear: 1999\n").unwrap();
+
+        let sidecar = load_sidecar(dir.path()).unwrap();
+        assert_eq!(sidecar.album.as_deref(), Some("Caf\u{FFFD}"));
+        assert_eq!(sidecar.year, Some(1999));
+        assert!(sidecar.source_file.unwrap().ends_with("folder.txt"));
+        assert!(load_sidecar(&dir.path().join("missing")).is_none());
+    }
+
+    #[test]
+    fn writing_keeps_fields_the_caller_left_out() {
+        let dir = temp_dir();
+        let folder = dir.path().to_string_lossy().to_string();
+        for format in [SidecarFormat::Nfo, SidecarFormat::Txt] {
+            let first = AlbumSidecar { album: Some("A & B".into()), year: Some(1999), ..Default::default() };
+            write_album_sidecar(folder.clone(), first, format).unwrap();
+            let second = AlbumSidecar { genre: Some("Jazz".into()), ..Default::default() };
+            let path = write_album_sidecar(folder.clone(), second, format).unwrap();
+
+            let sidecar = parse_sidecar_file(Path::new(&path)).unwrap();
+            assert_eq!(sidecar.album.as_deref(), Some("A & B"));
+            assert_eq!(sidecar.year, Some(1999));
+            assert_eq!(sidecar.genre.as_deref(), Some("Jazz"));
+        }
+    }
+}
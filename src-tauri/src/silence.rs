@@ -0,0 +1,193 @@
+//! Skipping the silence ripped tracks often have at each end. When enabled,
+//! the first and last seconds of a file are decoded once to find where the
+//! sound starts and stops; playback then starts after the leading silence and
+//! the auto-advance watcher moves on when the trailing silence is reached. The
+//! trim points are cached per file.
+
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fs;
+use std::path::Path;
+use std::time::Duration;
+use once_cell::sync::Lazy;
+use parking_lot::Mutex;
+use log::debug;
+use crate::cache::{self, CacheKind};
+use crate::config::{load_player_config, update_player_config, PlaybackSettings};
+use crate::decode::{decode_range, probe_duration, silent_blocks};
+use crate::library::modified_secs;
+
+// How much of each end of a file is scanned
+const SCAN_WINDOW: Duration = Duration::from_secs(10);
+const BLOCK: Duration = Duration::from_millis(10);
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq)]
+pub struct TrimPoints {
+    // Seconds of leading silence to skip
+    pub start: f32,
+    // Where the trailing silence begins, if there is enough of it
+    pub end: Option<f32>,
+}
+
+impl TrimPoints {
+    pub fn start(&self) -> Duration {
+        Duration::from_secs_f32(self.start)
+    }
+
+    pub fn end(&self) -> Option<Duration> {
+        self.end.map(Duration::from_secs_f32)
+    }
+}
+
+// Trim points by cache key, so a file isn't even read from the disk cache twice
+static TRIMS: Lazy<Mutex<HashMap<String, TrimPoints>>> = Lazy::new(|| Mutex::new(HashMap::new()));
+
+struct Thresholds {
+    db: f32,
+    min: Duration,
+}
+
+impl Thresholds {
+    fn from_settings(settings: &PlaybackSettings) -> Option<Self> {
+        settings.skip_silence.then(|| Self {
+            db: if settings.silence_threshold_db.is_finite() { settings.silence_threshold_db.clamp(-90.0, -20.0) } else { -60.0 },
+            min: Duration::from_millis(settings.silence_min_ms as u64),
+        })
+    }
+}
+
+/// Identifies `path` as it is now, under the current thresholds.
+fn cache_key(path: &str, thresholds: &Thresholds) -> Option<String> {
+    let metadata = fs::metadata(path).ok()?;
+    let parts = [
+        path.to_string(),
+        metadata.len().to_string(),
+        modified_secs(&metadata).to_string(),
+        thresholds.db.to_string(),
+        thresholds.min.as_millis().to_string(),
+    ];
+    let refs: Vec<&str> = parts.iter().map(|part| part.as_str()).collect();
+    Some(cache::cache_key(&refs))
+}
+
+fn cache_file_name(key: &str) -> String {
+    format!("silence-{}.json", key)
+}
+
+/// Seconds of silence at one end of a decoded window, once it is long enough to trim.
+fn silent_secs(mono: &[f32], block_frames: usize, thresholds: &Thresholds, from_end: bool) -> f32 {
+    let silent = silent_blocks(mono, block_frames, thresholds.db, from_end) as f32 * BLOCK.as_secs_f32();
+    if silent >= thresholds.min.as_secs_f32() { silent } else { 0.0 }
+}
+
+fn scan(path: &Path, thresholds: &Thresholds) -> Result<TrimPoints, String> {
+    let head = decode_range(path, Duration::ZERO, Some(SCAN_WINDOW))?;
+    let start = silent_secs(&head.mono(), head.frames_for(BLOCK), thresholds, false);
+    let Some(duration) = probe_duration(path) else {
+        return Ok(TrimPoints { start, end: None });
+    };
+    let tail = decode_range(path, duration.saturating_sub(SCAN_WINDOW), None)?;
+    let trailing = silent_secs(&tail.mono(), tail.frames_for(BLOCK), thresholds, true);
+    let end = (trailing > 0.0).then_some(duration.as_secs_f32() - trailing);
+    // A file that is silent throughout is left alone
+    if end.is_some_and(|end| end <= start) {
+        return Ok(TrimPoints { start: 0.0, end: None });
+    }
+    Ok(TrimPoints { start, end })
+}
+
+/// Where the sound in `path` starts and stops, or None while skipping
+/// silence is off. Scans the file the first time it is asked about.
+pub fn trim_points(path: &str) -> Option<TrimPoints> {
+    let thresholds = Thresholds::from_settings(&load_player_config().playback_settings)?;
+    let key = cache_key(path, &thresholds)?;
+    if let Some(trim) = TRIMS.lock().get(&key) {
+        return Some(*trim);
+    }
+
+    let cached = cache::entry_path(CacheKind::Metadata, &cache_file_name(&key)).and_then(|entry| {
+        let trim = serde_json::from_str(&fs::read_to_string(&entry).ok()?).ok()?;
+        cache::mark_used(&entry);
+        Some(trim)
+    });
+    let trim = match cached {
+        Some(trim) => trim,
+        None => {
+            let trim = match scan(Path::new(path), &thresholds) {
+                Ok(trim) => trim,
+                Err(e) => {
+                    debug!("Could not scan {} for silence: {}", path, e);
+                    return None;
+                }
+            };
+            if let Ok(json) = serde_json::to_vec(&trim) {
+                cache::write_entry(CacheKind::Metadata, &cache_file_name(&key), &json).ok();
+            }
+            trim
+        }
+    };
+    TRIMS.lock().insert(key, trim);
+    Some(trim)
+}
+
+/// Where playback of `path` should start: after its leading silence when
+/// skipping silence, otherwise the beginning.
+pub fn playback_start(path: &str) -> Duration {
+    trim_points(path).map_or(Duration::ZERO, |trim| trim.start())
+}
+
+/// The end of `path`'s sound if it has already been scanned; never reads the
+/// file, so it is safe to call from the playback thread.
+pub fn cached_trim_end(path: &str) -> Option<Duration> {
+    let thresholds = Thresholds::from_settings(&load_player_config().playback_settings)?;
+    let key = cache_key(path, &thresholds)?;
+    TRIMS.lock().get(&key).and_then(TrimPoints::end)
+}
+
+/// Turns skipping silence on or off, optionally changing what counts as
+/// silence. Applies from the next track started.
+#[tauri::command]
+pub fn set_skip_silence(enabled: bool, threshold_db: Option<f32>, min_ms: Option<u32>) -> Result<(), String> {
+    if let Some(db) = threshold_db.filter(|db| !db.is_finite() || !(-90.0..=-20.0).contains(db)) {
+        return Err(format!("Silence threshold must be between -90 and -20 dB, got {}", db));
+    }
+    update_player_config(|config| {
+        let settings = &mut config.playback_settings;
+        settings.skip_silence = enabled;
+        if let Some(db) = threshold_db {
+            settings.silence_threshold_db = db;
+        }
+        if let Some(min_ms) = min_ms {
+            settings.silence_min_ms = min_ms;
+        }
+        Ok(())
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn trims_only_silence_long_enough_to_matter() {
+        let thresholds = Thresholds { db: -60.0, min: Duration::from_millis(500) };
+        // 8 kHz: 80 frames per 10ms block
+        let mut signal = vec![0.0f32; 8_000];
+        signal.extend((0..8_000).map(|n| (n as f32 * 0.05).sin() * 0.5));
+        signal.extend(vec![0.0f32; 2_000]);
+        assert!((silent_secs(&signal, 80, &thresholds, false) - 1.0).abs() < 0.011);
+        // A quarter of a second at the end is shorter than the minimum
+        assert_eq!(silent_secs(&signal, 80, &thresholds, true), 0.0);
+    }
+
+    #[test]
+    fn thresholds_follow_the_settings() {
+        let mut settings = PlaybackSettings::default();
+        assert!(Thresholds::from_settings(&settings).is_none());
+        settings.skip_silence = true;
+        settings.silence_threshold_db = f32::NAN;
+        let thresholds = Thresholds::from_settings(&settings).unwrap();
+        assert_eq!(thresholds.db, -60.0);
+        assert_eq!(thresholds.min, Duration::from_millis(settings.silence_min_ms as u64));
+    }
+}
@@ -0,0 +1,134 @@
+use std::cmp::Ordering;
+use std::iter::Peekable;
+use std::str::Chars;
+use std::fs;
+use crate::FileItem;
+use crate::library::modified_secs;
+use crate::text::{collate, fold};
+
+/// A directory listing entry together with the filesystem facts used for sorting.
+pub struct SortableEntry {
+    pub item: FileItem,
+    pub modified: u64,
+    // Falls back to `modified` for files the library index doesn't know
+    pub added_at: u64,
+    pub size: u64,
+}
+
+impl SortableEntry {
+    pub fn new(mut item: FileItem, metadata: &fs::Metadata) -> Self {
+        let modified = modified_secs(metadata);
+        item.modified = Some(modified);
+        Self {
+            item,
+            modified,
+            added_at: modified,
+            size: metadata.len(),
+        }
+    }
+}
+
+/// Fills in added-at times for the entries `added_at` knows about.
+pub fn fill_added_times(entries: &mut [SortableEntry], added_at: impl Fn(&str) -> Option<u64>) {
+    for entry in entries.iter_mut() {
+        if let Some(added_at) = added_at(&entry.item.path) {
+            entry.item.added_at = Some(added_at);
+            entry.added_at = added_at;
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum DirSort {
+    Name,
+    Natural,
+    Modified,
+    Added,
+    Size,
+}
+
+impl DirSort {
+    pub fn parse(sort: Option<&str>) -> Result<Self, String> {
+        match sort.map(|s| s.to_lowercase()).as_deref() {
+            None | Some("natural") => Ok(DirSort::Natural),
+            Some("name") => Ok(DirSort::Name),
+            Some("modified") | Some("date_modified") => Ok(DirSort::Modified),
+            Some("added") | Some("date_added") => Ok(DirSort::Added),
+            Some("size") => Ok(DirSort::Size),
+            Some(other) => Err(format!("Unknown sort option: {}", other)),
+        }
+    }
+}
+
+fn take_digits(chars: &mut Peekable<Chars>) -> String {
+    let mut digits = String::new();
+    while let Some(c) = chars.peek() {
+        if !c.is_ascii_digit() {
+            break;
+        }
+        digits.push(*c);
+        chars.next();
+    }
+    digits
+}
+
+/// Compares names treating runs of digits as numbers, so "2 - Song" sorts before
+/// "10 - Outro". Letters compare folded (see `text::fold`); names that differ
+/// only by case or accents fall back to a plain comparison so the order is deterministic.
+pub fn natural_cmp(a: &str, b: &str) -> Ordering {
+    let (a_folded, b_folded) = (fold(a), fold(b));
+    let mut a_chars = a_folded.chars().peekable();
+    let mut b_chars = b_folded.chars().peekable();
+
+    loop {
+        let ordering = match (a_chars.peek().copied(), b_chars.peek().copied()) {
+            (None, None) => return a.cmp(b),
+            (None, Some(_)) => return Ordering::Less,
+            (Some(_), None) => return Ordering::Greater,
+            (Some(ca), Some(cb)) if ca.is_ascii_digit() && cb.is_ascii_digit() => {
+                let a_digits = take_digits(&mut a_chars);
+                let b_digits = take_digits(&mut b_chars);
+                // Compare by magnitude without parsing, so long digit runs can't overflow
+                let a_trimmed = a_digits.trim_start_matches('0');
+                let b_trimmed = b_digits.trim_start_matches('0');
+                a_trimmed.len().cmp(&b_trimmed.len())
+                    .then_with(|| a_trimmed.cmp(b_trimmed))
+                    .then_with(|| a_digits.len().cmp(&b_digits.len()))
+            }
+            (Some(ca), Some(cb)) => {
+                a_chars.next();
+                b_chars.next();
+                ca.cmp(&cb)
+            }
+        };
+
+        if ordering != Ordering::Equal {
+            return ordering;
+        }
+    }
+}
+
+/// Sorts a listing with directories first, then by the requested key.
+pub fn sort_entries(mut entries: Vec<SortableEntry>, sort: DirSort, descending: bool) -> Vec<FileItem> {
+    entries.sort_by(|a, b| {
+        let dirs_first = b.item.is_dir.cmp(&a.item.is_dir);
+        if dirs_first != Ordering::Equal {
+            return dirs_first;
+        }
+
+        let ordering = match sort {
+            DirSort::Name => collate(&a.item.name, &b.item.name),
+            DirSort::Natural => natural_cmp(&a.item.name, &b.item.name),
+            DirSort::Modified => a.modified.cmp(&b.modified)
+                .then_with(|| natural_cmp(&a.item.name, &b.item.name)),
+            DirSort::Added => a.added_at.cmp(&b.added_at)
+                .then_with(|| natural_cmp(&a.item.name, &b.item.name)),
+            DirSort::Size => a.size.cmp(&b.size)
+                .then_with(|| natural_cmp(&a.item.name, &b.item.name)),
+        };
+
+        if descending { ordering.reverse() } else { ordering }
+    });
+
+    entries.into_iter().map(|entry| entry.item).collect()
+}
@@ -0,0 +1,73 @@
+use serde::Serialize;
+use std::time::Duration;
+use tauri::{AppHandle, Emitter};
+use log::{info, error};
+use crate::favorites::FavoriteKind;
+use crate::load_config;
+use crate::paths::probe_paths;
+
+// Every candidate is probed at once, so startup waits at most this long on a dead mount
+const PROBE_TIMEOUT: Duration = Duration::from_secs(2);
+
+#[derive(Debug, Serialize, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum StartupRule {
+    DefaultLocation,
+    RecentLocation,
+    Favorite,
+    Home,
+}
+
+#[derive(Debug, Serialize, Clone)]
+pub struct StartupLocation {
+    pub path: String,
+    pub rule: StartupRule,
+}
+
+/// Picks the folder to open on launch: the default location, then the most
+/// recent location, then the first favorite folder, then home. Only paths that
+/// answer the existence probe in time are considered.
+pub fn resolve_startup_location() -> Result<StartupLocation, String> {
+    let config = load_config();
+    let mut candidates: Vec<(String, StartupRule)> = Vec::new();
+    if let Some(default) = config.default_location {
+        candidates.push((default, StartupRule::DefaultLocation));
+    }
+    candidates.extend(config.recent_locations.into_iter().map(|path| (path, StartupRule::RecentLocation)));
+    candidates.extend(config.favorite_locations.into_iter()
+        .filter(|entry| entry.kind == FavoriteKind::Folder)
+        .map(|entry| (entry.path, StartupRule::Favorite)));
+
+    let paths: Vec<String> = candidates.iter().map(|(path, _)| path.clone()).collect();
+    let availability = probe_paths(&paths, PROBE_TIMEOUT);
+    if let Some(((path, rule), _)) = candidates.into_iter()
+        .zip(availability)
+        .find(|(_, available)| *available == Some(true))
+    {
+        return Ok(StartupLocation { path, rule });
+    }
+
+    dirs::home_dir()
+        .map(|path| StartupLocation {
+            path: path.to_string_lossy().to_string(),
+            rule: StartupRule::Home,
+        })
+        .ok_or_else(|| "Could not find home directory".to_string())
+}
+
+#[tauri::command]
+pub async fn get_startup_location() -> Result<StartupLocation, String> {
+    resolve_startup_location()
+}
+
+/// Resolves the startup location off the main thread and emits
+/// `startup-location-ready` with the result.
+pub fn announce_startup_location(app: AppHandle) {
+    std::thread::spawn(move || match resolve_startup_location() {
+        Ok(location) => {
+            info!("Startup location {} ({:?})", location.path, location.rule);
+            app.emit("startup-location-ready", location).ok();
+        }
+        Err(e) => error!("Failed to resolve startup location: {}", e),
+    });
+}
@@ -0,0 +1,405 @@
+//! Speed changes that keep the pitch, for speech. `TimeStretch` sits at the
+//! end of the playback chain and, when pitch is preserved, plays the track
+//! faster or slower by overlapping short segments of it (WSOLA) instead of
+//! resampling. It also keeps the track clock, since once audio is stretched
+//! the sink's own position no longer says where in the track playback is.
+
+use std::collections::VecDeque;
+use std::sync::atomic::{AtomicBool, AtomicU32, AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+use once_cell::sync::Lazy;
+use rodio::source::SeekError;
+use rodio::Source;
+use crate::config::load_player_config;
+use crate::pitch;
+
+// Segment lengths in seconds, as used by common WSOLA implementations
+const SEQUENCE_SECS: f64 = 0.040;
+const OVERLAP_SECS: f64 = 0.008;
+const SEEK_WINDOW_SECS: f64 = 0.015;
+// Publish the clock after this many frames rather than every sample
+const CLOCK_INTERVAL_FRAMES: u32 = 256;
+
+struct StretchParams {
+    preserve_pitch: AtomicBool,
+    // f32 bits of the stretch factor; 1.0 while pitch isn't preserved
+    tempo: AtomicU32,
+}
+
+static PARAMS: Lazy<StretchParams> = Lazy::new(|| {
+    let settings = load_player_config().playback_settings;
+    let speed = settings.effective_playback_speed();
+    StretchParams {
+        preserve_pitch: AtomicBool::new(settings.preserve_pitch),
+        tempo: AtomicU32::new(if settings.preserve_pitch { speed } else { 1.0 }.to_bits()),
+    }
+});
+
+pub fn preserves_pitch() -> bool {
+    PARAMS.preserve_pitch.load(Ordering::Relaxed)
+}
+
+/// Applies `speed` to every playing source: by stretching them when pitch is
+/// preserved, otherwise it is left to the sink.
+pub fn set_stretch(speed: f32, preserve_pitch: bool) {
+    PARAMS.preserve_pitch.store(preserve_pitch, Ordering::Relaxed);
+    let tempo = if preserve_pitch { speed } else { 1.0 };
+    PARAMS.tempo.store(tempo.to_bits(), Ordering::Relaxed);
+}
+
+/// The stretch factor, which also makes up for the speed change of a pitch shift.
+fn tempo() -> f64 {
+    f32::from_bits(PARAMS.tempo.load(Ordering::Relaxed)) as f64 / pitch::ratio()
+}
+
+/// How far into its track a source has got, shared with the player state.
+#[derive(Debug, Default)]
+pub struct TrackClock(AtomicU64);
+
+impl TrackClock {
+    pub fn get(&self) -> Duration {
+        Duration::from_secs_f64(f64::from_bits(self.0.load(Ordering::Relaxed)))
+    }
+
+    fn set(&self, seconds: f64) {
+        self.0.store(seconds.max(0.0).to_bits(), Ordering::Relaxed);
+    }
+}
+
+/// Segment sizes in frames for `sample_rate`.
+#[derive(Debug, Clone, Copy)]
+struct Layout {
+    sequence: usize,
+    overlap: usize,
+    seek_window: usize,
+}
+
+impl Layout {
+    fn new(sample_rate: u32) -> Self {
+        let frames = |secs: f64| ((sample_rate as f64 * secs) as usize).max(1);
+        Self { sequence: frames(SEQUENCE_SECS), overlap: frames(OVERLAP_SECS), seek_window: frames(SEEK_WINDOW_SECS) }
+    }
+}
+
+/// Offset within the first `seek_window` frames of `input` where it best lines
+/// up with `mid`, so the crossfade between segments doesn't smear or cancel.
+fn best_offset(mid: &[f32], input: &[f32], channels: usize, seek_window: usize) -> usize {
+    let overlap = mid.len();
+    let mut best = (0, f32::MIN);
+    for offset in 0..seek_window {
+        let window = &input[offset * channels..offset * channels + overlap];
+        let (mut correlation, mut energy) = (0.0f32, 0.0f32);
+        for (a, b) in mid.iter().zip(window) {
+            correlation += a * b;
+            energy += b * b;
+        }
+        let score = correlation / energy.sqrt().max(1e-9);
+        if score > best.1 {
+            best = (offset, score);
+        }
+    }
+    best.0
+}
+
+/// The end of the playback chain: stretches when pitch is preserved and keeps
+/// `clock` at the position in the track of what it hands on.
+pub struct TimeStretch<S> {
+    inner: S,
+    clock: Arc<TrackClock>,
+    channels: usize,
+    sample_rate: u32,
+    layout: Layout,
+    // Seconds of the track read from `inner` so far
+    consumed: f64,
+    // Samples read from `inner` not yet used
+    input: Vec<f32>,
+    // Tail of the last segment, crossfaded into the next one
+    mid: Vec<f32>,
+    output: VecDeque<i16>,
+    // Track time `output` covers, from its first sample to just past its last,
+    // so the clock moves through a segment as it is handed on
+    span: (f64, f64),
+    span_samples: usize,
+    // Fraction of a frame carried between segments, so the tempo is exact on average
+    skip_remainder: f64,
+    // Samples of the current frame passed straight through
+    frame_samples: usize,
+    frames_since_publish: u32,
+    inner_done: bool,
+}
+
+impl<S: Source<Item = i16>> TimeStretch<S> {
+    /// Wraps `inner`, which starts `start` into its track.
+    pub fn new(inner: S, start: Duration) -> Self {
+        let clock = Arc::new(TrackClock::default());
+        clock.set(start.as_secs_f64());
+        let sample_rate = inner.sample_rate();
+        Self {
+            channels: inner.channels().max(1) as usize,
+            sample_rate,
+            layout: Layout::new(sample_rate),
+            inner,
+            clock,
+            consumed: start.as_secs_f64(),
+            input: Vec::new(),
+            mid: Vec::new(),
+            output: VecDeque::new(),
+            span: (start.as_secs_f64(), start.as_secs_f64()),
+            span_samples: 0,
+            skip_remainder: 0.0,
+            frame_samples: 0,
+            frames_since_publish: 0,
+            inner_done: false,
+        }
+    }
+
+    pub fn clock(&self) -> Arc<TrackClock> {
+        Arc::clone(&self.clock)
+    }
+
+    /// Track time of the first sample in `input`, not yet used.
+    fn input_start(&self) -> f64 {
+        let buffered = self.input.len() / self.channels;
+        self.consumed - buffered as f64 / self.sample_rate as f64
+    }
+
+    fn publish(&mut self) {
+        self.clock.set(self.input_start());
+        self.frames_since_publish = 0;
+    }
+
+    /// Notes that `output` now holds what was read from `start` up to the
+    /// start of `input`.
+    fn set_span(&mut self, start: f64) {
+        self.span = (start, self.input_start());
+        self.span_samples = self.output.len();
+    }
+
+    /// Moves the clock through `span` in step with what has been handed on.
+    fn publish_span(&self) {
+        let (start, end) = self.span;
+        let handed_on = 1.0 - self.output.len() as f64 / self.span_samples.max(1) as f64;
+        self.clock.set(start + (end - start) * handed_on);
+    }
+
+    /// Reads from `inner` until `input` holds `frames` frames or it runs out.
+    fn fill(&mut self, frames: usize) {
+        while self.input.len() < frames * self.channels && !self.inner_done {
+            match self.inner.next() {
+                Some(sample) => {
+                    self.input.push(sample as f32);
+                    if self.input.len().is_multiple_of(self.channels) {
+                        self.consumed += 1.0 / self.sample_rate as f64;
+                    }
+                }
+                None => self.inner_done = true,
+            }
+        }
+    }
+
+    fn emit(&mut self, samples: &[f32]) {
+        self.output.extend(samples.iter().map(|sample| sample.clamp(i16::MIN as f32, i16::MAX as f32) as i16));
+    }
+
+    /// Hands on whatever is buffered as it is, for the end of the track or a
+    /// switch back to normal speed.
+    fn flush(&mut self) {
+        let start = self.input_start();
+        let mid = std::mem::take(&mut self.mid);
+        self.emit(&mid);
+        let input = std::mem::take(&mut self.input);
+        self.emit(&input);
+        self.skip_remainder = 0.0;
+        self.set_span(start);
+    }
+
+    /// Produces one stretched segment into `output`.
+    fn stretch_segment(&mut self, tempo: f64) {
+        let Layout { sequence, overlap, seek_window } = self.layout;
+        let channels = self.channels;
+        let start = self.input_start();
+        let advance = tempo * (sequence - overlap) as f64 + self.skip_remainder;
+        let skip = advance.floor() as usize;
+        self.fill((seek_window + sequence).max(skip));
+        if self.input.len() < (seek_window + sequence) * channels {
+            self.flush();
+            return;
+        }
+
+        let offset = if self.mid.is_empty() { 0 } else { best_offset(&self.mid, &self.input, channels, seek_window) };
+        let segment = &self.input[offset * channels..(offset + sequence) * channels];
+        let mut out = Vec::with_capacity((sequence - overlap) * channels);
+        if self.mid.is_empty() {
+            out.extend_from_slice(&segment[..overlap * channels]);
+        } else {
+            for frame in 0..overlap {
+                let fade_in = frame as f32 / overlap as f32;
+                for channel in 0..channels {
+                    let index = frame * channels + channel;
+                    out.push(self.mid[index] * (1.0 - fade_in) + segment[index] * fade_in);
+                }
+            }
+        }
+        out.extend_from_slice(&segment[overlap * channels..(sequence - overlap) * channels]);
+        self.mid = segment[(sequence - overlap) * channels..].to_vec();
+        self.emit(&out);
+
+        self.skip_remainder = advance - skip as f64;
+        let drained = (skip * channels).min(self.input.len());
+        self.input.drain(..drained);
+        self.set_span(start);
+    }
+
+    /// The next sample at `tempo`.
+    fn next_at(&mut self, tempo: f64) -> Option<i16> {
+        loop {
+            if let Some(sample) = self.output.pop_front() {
+                if self.output.len().is_multiple_of(CLOCK_INTERVAL_FRAMES as usize * self.channels) {
+                    self.publish_span();
+                }
+                return Some(sample);
+            }
+            let buffered = !self.input.is_empty() || !self.mid.is_empty();
+            if (tempo - 1.0).abs() < 0.01 {
+                if buffered {
+                    self.flush();
+                    continue;
+                }
+                let sample = self.inner.next();
+                if sample.is_some() {
+                    self.frame_samples += 1;
+                    if self.frame_samples >= self.channels {
+                        self.frame_samples = 0;
+                        self.consumed += 1.0 / self.sample_rate as f64;
+                        self.frames_since_publish += 1;
+                        if self.frames_since_publish >= CLOCK_INTERVAL_FRAMES {
+                            self.publish();
+                        }
+                    }
+                } else {
+                    self.publish();
+                }
+                return sample;
+            }
+            if self.inner_done && !buffered {
+                self.publish();
+                return None;
+            }
+            self.stretch_segment(tempo);
+        }
+    }
+}
+
+impl<S: Source<Item = i16>> Iterator for TimeStretch<S> {
+    type Item = i16;
+
+    fn next(&mut self) -> Option<i16> {
+        self.next_at(tempo())
+    }
+}
+
+impl<S: Source<Item = i16>> Source for TimeStretch<S> {
+    fn current_frame_len(&self) -> Option<usize> {
+        // Stretched output doesn't follow the decoder's frames, but its format doesn't change either
+        if self.output.is_empty() && self.input.is_empty() && self.mid.is_empty() {
+            self.inner.current_frame_len()
+        } else {
+            None
+        }
+    }
+
+    fn channels(&self) -> u16 {
+        self.channels as u16
+    }
+
+    fn sample_rate(&self) -> u32 {
+        self.sample_rate
+    }
+
+    fn total_duration(&self) -> Option<Duration> {
+        self.inner.total_duration()
+    }
+
+    fn try_seek(&mut self, position: Duration) -> Result<(), SeekError> {
+        self.inner.try_seek(position)?;
+        self.input.clear();
+        self.mid.clear();
+        self.output.clear();
+        self.skip_remainder = 0.0;
+        self.frame_samples = 0;
+        self.inner_done = false;
+        self.consumed = position.as_secs_f64();
+        self.publish();
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rodio::buffer::SamplesBuffer;
+    use crate::test_support::sine_samples;
+
+    #[test]
+    fn normal_speed_passes_through_and_keeps_the_clock() {
+        crate::test_support::isolate_config();
+        let samples = sine_samples(8_000, 220.0, 4.0);
+        let source = TimeStretch::new(SamplesBuffer::new(1, 8_000, samples.clone()), Duration::ZERO);
+        let clock = source.clock();
+        let mut played = Vec::new();
+        for sample in source {
+            played.push(sample);
+            if played.len() == 4_000 {
+                assert!((clock.get().as_secs_f32() - 0.5).abs() < 0.05);
+            }
+        }
+        assert_eq!(played, samples);
+        assert!((clock.get().as_secs_f32() - 4.0).abs() < 0.01);
+    }
+
+    #[test]
+    fn a_stretched_segment_covers_more_track_than_it_plays() {
+        let samples = sine_samples(8_000, 220.0, 2.0);
+        let mut source = TimeStretch::new(SamplesBuffer::new(1, 8_000, samples), Duration::ZERO);
+        let mut played = Vec::new();
+        while !source.inner_done || !source.input.is_empty() {
+            source.stretch_segment(2.0);
+            played.extend(source.output.drain(..));
+        }
+        // Twice the speed plays the two seconds in about one, at the same level
+        assert!((played.len() as f32 / 8_000.0 - 1.0).abs() < 0.1, "played {} samples", played.len());
+        assert!((source.span.1 as f32 - 2.0).abs() < 0.01);
+        assert!(played.iter().any(|sample| sample.abs() > 7000));
+    }
+
+    #[test]
+    fn starts_the_clock_where_playback_starts() {
+        let source = TimeStretch::new(SamplesBuffer::new(2, 8_000, vec![0i16; 16]), Duration::from_secs(30));
+        assert_eq!(source.clock().get(), Duration::from_secs(30));
+    }
+
+    #[test]
+    fn clock_follows_seeks_and_speed_changes() {
+        let mut source = TimeStretch::new(SamplesBuffer::new(1, 8_000, sine_samples(8_000, 220.0, 30.0)), Duration::ZERO);
+        let clock = source.clock();
+        let mut expected = 0.0;
+        // Each step plays a second of output at its tempo, covering that many seconds of track
+        for (tempo, seek) in [(1.0, None), (2.0, None), (1.0, Some(10.0)), (0.5, None), (1.5, Some(4.0)), (1.0, None)] {
+            if let Some(position) = seek {
+                source.try_seek(Duration::from_secs_f64(position)).unwrap();
+                expected = position;
+                assert!((clock.get().as_secs_f64() - expected).abs() < 0.001);
+            }
+            for n in 1..=8_000 {
+                source.next_at(tempo).unwrap();
+                if n % 400 == 0 {
+                    let heard = expected + tempo * n as f64 / 8_000.0;
+                    let drift = clock.get().as_secs_f64() - heard;
+                    assert!(drift.abs() < 0.1, "{}s off at {:.2}s into tempo {}", drift, heard, tempo);
+                }
+            }
+            expected += tempo;
+        }
+    }
+}
@@ -0,0 +1,276 @@
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, MAIN_SEPARATOR};
+use rand::Rng;
+use log::{info, error};
+use crate::device::removable_device_root;
+use crate::library::{modified_secs, now_secs, save_index, LIBRARY};
+use crate::transfer::{cached_file_checksum, TransferManifest};
+
+// Lives at the device root; reformatting the device removes it along with the music
+const DEVICE_MARKER: &str = ".musicmanager-device";
+const CHECKSUM_ALGORITHM: &str = "sha256";
+
+/// One verified copy of a local track on a device.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct DeviceSync {
+    pub device_id: String,
+    // Relative to the device root, so it holds wherever the device is mounted
+    pub device_path: String,
+    pub synced_at: u64,
+    pub checksum: String,
+    pub checksum_algorithm: String,
+    // The local file as it was when copied; a change means re-checking the checksum
+    pub local_size: u64,
+    pub local_modified: u64,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct SyncDevice {
+    pub name: String,
+    pub last_synced: u64,
+    // Folders and tracks to copy whenever the device's sync set runs
+    #[serde(default)]
+    pub sync_set: Vec<SyncSetEntry>,
+    #[serde(default)]
+    pub sync_options: DeviceSyncOptions,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct SyncSetEntry {
+    pub path: String,
+    pub is_dir: bool,
+    // Folded tags identifying the album or track, for finding it again after a move
+    pub identity: Option<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+#[serde(default)]
+pub struct DeviceSyncOptions {
+    // Relative to the device root
+    pub music_folder: String,
+    pub verify_transfer: bool,
+}
+
+impl Default for DeviceSyncOptions {
+    fn default() -> Self {
+        Self {
+            music_folder: "Music".to_string(),
+            verify_transfer: true,
+        }
+    }
+}
+
+impl SyncDevice {
+    pub fn new(name: &str) -> Self {
+        Self {
+            name: name.to_string(),
+            last_synced: 0,
+            sync_set: Vec::new(),
+            sync_options: DeviceSyncOptions::default(),
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct DeviceMarker {
+    id: String,
+    created_at: u64,
+}
+
+#[derive(Debug, Serialize, Clone)]
+pub struct DeviceSyncStatus {
+    pub device_id: String,
+    pub device_name: String,
+    // Set for single tracks; albums may be spread over several folders
+    pub device_path: Option<String>,
+    pub synced_at: u64,
+}
+
+#[derive(Debug, Serialize, Clone)]
+pub struct PathSyncStatus {
+    pub path: String,
+    pub devices: Vec<DeviceSyncStatus>,
+}
+
+/// The id in the marker file at `root`, if the device has been synced to before.
+pub fn read_device_id(root: &Path) -> Option<String> {
+    fs::read_to_string(root.join(DEVICE_MARKER)).ok()
+        .and_then(|contents| serde_json::from_str::<DeviceMarker>(&contents).ok())
+        .map(|marker| marker.id)
+}
+
+/// Reads the device's id from its marker file, creating one on first sync.
+pub fn device_id(root: &Path) -> Result<String, String> {
+    if let Some(id) = read_device_id(root) {
+        return Ok(id);
+    }
+
+    let marker_path = root.join(DEVICE_MARKER);
+    let id: String = (0..16).map(|_| format!("{:02x}", rand::thread_rng().gen::<u8>())).collect();
+    let marker = DeviceMarker { id: id.clone(), created_at: now_secs() };
+    let json = serde_json::to_string(&marker).map_err(|e| e.to_string())?;
+    fs::write(&marker_path, json).map_err(|e| format!("Failed to mark device: {}", e))?;
+    Ok(id)
+}
+
+/// Records every file of a verified transfer as present on the target's
+/// device. Transfers to non-removable targets aren't tracked.
+pub fn record_transfer(source: &Path, target: &Path, manifest: &TransferManifest) {
+    let Some((name, root)) = removable_device_root(target) else { return };
+    let id = match device_id(&root) {
+        Ok(id) => id,
+        Err(e) => {
+            error!("Not recording sync to {}: {}", root.display(), e);
+            return;
+        }
+    };
+
+    let synced_at = now_secs();
+    let mut records = Vec::new();
+    for file in &manifest.checksums {
+        let local = source.join(&file.path);
+        let Ok(metadata) = fs::metadata(&local) else { continue };
+        let device_path = target.join(&file.path);
+        let device_path = device_path.strip_prefix(&root).unwrap_or(&device_path);
+        records.push((local.to_string_lossy().to_string(), DeviceSync {
+            device_id: id.clone(),
+            device_path: device_path.to_string_lossy().to_string(),
+            synced_at,
+            checksum: file.checksum.clone(),
+            checksum_algorithm: CHECKSUM_ALGORITHM.to_string(),
+            local_size: metadata.len(),
+            local_modified: modified_secs(&metadata),
+        }));
+    }
+
+    let mut index = LIBRARY.lock();
+    let count = records.len();
+    for (local, record) in records {
+        let synced = index.synced.entry(local).or_default();
+        synced.retain(|existing| existing.device_id != id);
+        synced.push(record);
+    }
+    let device = index.devices.entry(id.clone()).or_insert_with(|| SyncDevice::new(&name));
+    device.name = name.clone();
+    device.last_synced = synced_at;
+    if let Err(e) = save_index(&index) {
+        error!("Failed to save library index: {}", e);
+    }
+    info!("Recorded {} files as synced to {} ({})", count, name, id);
+}
+
+/// Whether the local file still matches what was copied. Size and mtime settle
+/// it cheaply; only a changed file is checksummed again.
+fn still_matches(local: &str, record: &DeviceSync) -> bool {
+    let Ok(metadata) = fs::metadata(local) else { return false };
+    if metadata.len() == record.local_size && modified_secs(&metadata) == record.local_modified {
+        return true;
+    }
+    record.checksum_algorithm == CHECKSUM_ALGORITHM
+        && cached_file_checksum(Path::new(local)).is_ok_and(|checksum| checksum == record.checksum)
+}
+
+/// Sync records for `local` that are still valid. Stale ones are dropped from the index.
+fn valid_records(local: &str) -> Vec<DeviceSync> {
+    let records = LIBRARY.lock().synced.get(local).cloned().unwrap_or_default();
+    let (valid, stale): (Vec<DeviceSync>, Vec<DeviceSync>) = records.into_iter()
+        .partition(|record| still_matches(local, record));
+    if !stale.is_empty() {
+        let mut index = LIBRARY.lock();
+        if let Some(synced) = index.synced.get_mut(local) {
+            synced.retain(|record| !stale.iter().any(|s| s.device_id == record.device_id));
+            if synced.is_empty() {
+                index.synced.remove(local);
+            }
+        }
+        if let Err(e) = save_index(&index) {
+            error!("Failed to save library index: {}", e);
+        }
+    }
+    valid
+}
+
+fn device_name(devices: &HashMap<String, SyncDevice>, id: &str) -> String {
+    devices.get(id).map(|device| device.name.clone()).unwrap_or_else(|| id.to_string())
+}
+
+/// Devices an album folder is fully on: every indexed track under it has a valid copy.
+fn folder_status(folder: &str) -> Vec<DeviceSyncStatus> {
+    let prefix = format!("{}{}", folder.trim_end_matches(MAIN_SEPARATOR), MAIN_SEPARATOR);
+    let tracks: Vec<String> = LIBRARY.lock().tracks.values()
+        .filter(|track| !track.staged && track.path.starts_with(&prefix))
+        .map(|track| track.path.clone())
+        .collect();
+    if tracks.is_empty() {
+        return Vec::new();
+    }
+
+    let mut complete: Option<HashMap<String, u64>> = None;
+    for track in &tracks {
+        let on: HashMap<String, u64> = valid_records(track).into_iter()
+            .map(|record| (record.device_id, record.synced_at))
+            .collect();
+        complete = Some(match complete {
+            None => on,
+            Some(so_far) => so_far.into_iter()
+                .filter_map(|(id, at)| on.get(&id).map(|other| (id, at.min(*other))))
+                .collect(),
+        });
+        if complete.as_ref().is_some_and(|devices| devices.is_empty()) {
+            return Vec::new();
+        }
+    }
+
+    let devices = LIBRARY.lock().devices.clone();
+    complete.unwrap_or_default().into_iter()
+        .map(|(id, synced_at)| DeviceSyncStatus {
+            device_name: device_name(&devices, &id),
+            device_id: id,
+            device_path: None,
+            synced_at,
+        })
+        .collect()
+}
+
+/// Devices each path is known to be on. Files report their own copies; folders
+/// report the devices holding every track under them.
+#[tauri::command]
+pub async fn get_sync_status(paths: Vec<String>) -> Vec<PathSyncStatus> {
+    paths.into_iter().map(|path| {
+        let mut devices = if Path::new(&path).is_dir() {
+            folder_status(&path)
+        } else {
+            let names = LIBRARY.lock().devices.clone();
+            valid_records(&path).into_iter()
+                .map(|record| DeviceSyncStatus {
+                    device_name: device_name(&names, &record.device_id),
+                    device_id: record.device_id,
+                    device_path: Some(record.device_path),
+                    synced_at: record.synced_at,
+                })
+                .collect()
+        };
+        devices.sort_by_key(|device| std::cmp::Reverse(device.synced_at));
+        PathSyncStatus { path, devices }
+    }).collect()
+}
+
+/// Forgets everything synced to `device_id`, e.g. after the device was reformatted.
+/// Returns how many track records were removed.
+#[tauri::command]
+pub fn forget_device_sync_history(device_id: String) -> Result<usize, String> {
+    let mut index = LIBRARY.lock();
+    let mut removed = 0;
+    index.synced.retain(|_, synced| {
+        let before = synced.len();
+        synced.retain(|record| record.device_id != device_id);
+        removed += before - synced.len();
+        !synced.is_empty()
+    });
+    index.devices.remove(&device_id);
+    save_index(&index)?;
+    info!("Forgot {} synced tracks for device {}", removed, device_id);
+    Ok(removed)
+}
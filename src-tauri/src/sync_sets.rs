@@ -0,0 +1,316 @@
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+use std::path::{Path, PathBuf};
+use tauri::AppHandle;
+use log::{error, info};
+use crate::config::load_player_config;
+use crate::device::get_connected_devices;
+use crate::library::{save_index, LibraryTrack, LIBRARY};
+use crate::sync_history::{device_id, read_device_id, DeviceSyncOptions, SyncDevice, SyncSetEntry};
+use crate::text::fold;
+use crate::transfer::{transfer_files, TransferOptions, TransferResult};
+use crate::walker::{walk_audio_files, Exclusions};
+
+#[derive(Debug, Serialize, Clone)]
+pub struct SyncSet {
+    pub device_id: String,
+    pub device_name: String,
+    pub options: DeviceSyncOptions,
+    pub entries: Vec<SyncSetEntry>,
+    // Entries whose path no longer exists; `run_sync_set` tries to find them again
+    pub missing: Vec<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct MovedEntry {
+    pub from: String,
+    pub to: String,
+}
+
+#[derive(Debug, Serialize)]
+pub struct SyncSetRun {
+    pub device_id: String,
+    pub device_path: String,
+    pub transfers: Vec<TransferResult>,
+    // Found again under a new path; the set now points there
+    pub moved: Vec<MovedEntry>,
+    // Neither the path nor a matching album or track could be found
+    pub missing: Vec<String>,
+}
+
+fn album_identity(track: &LibraryTrack) -> Option<String> {
+    let artist = track.album_artist.as_ref().or(track.artist.as_ref())?;
+    Some(format!("album\u{0}{}\u{0}{}", fold(artist), fold(track.album.as_ref()?)))
+}
+
+fn track_identity(track: &LibraryTrack) -> Option<String> {
+    Some(format!(
+        "track\u{0}{}\u{0}{}\u{0}{}",
+        fold(track.artist.as_ref()?),
+        fold(track.album.as_deref().unwrap_or("")),
+        fold(track.title.as_ref()?)
+    ))
+}
+
+/// A folder is identified by its album when every indexed track under it belongs to one.
+fn identity_for(path: &Path, is_dir: bool) -> Option<String> {
+    let index = LIBRARY.lock();
+    if !is_dir {
+        return index.tracks.get(path.to_string_lossy().as_ref()).and_then(track_identity);
+    }
+    let mut identities = index.tracks.values()
+        .filter(|track| !track.staged && Path::new(&track.path).starts_with(path))
+        .map(album_identity);
+    let first = identities.next()??;
+    identities.all(|identity| identity.as_deref() == Some(first.as_str())).then_some(first)
+}
+
+/// Deepest folder containing all of `paths`.
+fn common_folder(paths: &[PathBuf]) -> Option<PathBuf> {
+    let mut common = paths.first()?.parent()?.to_path_buf();
+    for path in &paths[1..] {
+        while !path.starts_with(&common) {
+            common = common.parent()?.to_path_buf();
+        }
+    }
+    Some(common)
+}
+
+/// Where a missing entry went, found through the library index by its tags.
+fn relocate_entry(entry: &SyncSetEntry) -> Option<PathBuf> {
+    let identity = entry.identity.as_deref()?;
+    let index = LIBRARY.lock();
+    let matches: Vec<PathBuf> = index.tracks.values()
+        .filter(|track| !track.staged)
+        .filter(|track| if entry.is_dir { album_identity(track) } else { track_identity(track) }.as_deref() == Some(identity))
+        .map(|track| PathBuf::from(&track.path))
+        .filter(|path| path.exists())
+        .collect();
+    if entry.is_dir {
+        common_folder(&matches)
+    } else {
+        matches.into_iter().next()
+    }
+}
+
+/// Groups files under the library root holding them, or their entry's parent
+/// folder outside any root, so the device mirrors the library's layout.
+fn group_by_base(files: Vec<(PathBuf, PathBuf)>, roots: &[PathBuf]) -> BTreeMap<PathBuf, Vec<String>> {
+    let mut groups: BTreeMap<PathBuf, Vec<String>> = BTreeMap::new();
+    for (file, fallback) in files {
+        let base = roots.iter()
+            .filter(|root| file.starts_with(root))
+            .max_by_key(|root| root.components().count())
+            .cloned()
+            .unwrap_or(fallback);
+        if let Ok(relative) = file.strip_prefix(&base) {
+            let relative = relative.to_string_lossy().to_string();
+            let group = groups.entry(base).or_default();
+            if !group.contains(&relative) {
+                group.push(relative);
+            }
+        }
+    }
+    groups
+}
+
+fn device_mut<'a>(devices: &'a mut HashMap<String, SyncDevice>, device_id: &str) -> Result<&'a mut SyncDevice, String> {
+    devices.get_mut(device_id).ok_or_else(|| format!("Unknown device: {}", device_id))
+}
+
+fn sync_set(device_id: &str, device: &SyncDevice) -> SyncSet {
+    SyncSet {
+        device_id: device_id.to_string(),
+        device_name: device.name.clone(),
+        options: device.sync_options.clone(),
+        entries: device.sync_set.clone(),
+        missing: device.sync_set.iter()
+            .filter(|entry| !Path::new(&entry.path).exists())
+            .map(|entry| entry.path.clone())
+            .collect(),
+    }
+}
+
+/// Mount point of the connected device carrying `device_id`'s marker.
+async fn mounted_root(device_id: &str) -> Result<PathBuf, String> {
+    get_connected_devices().await?.into_iter()
+        .filter(|device| device.removable)
+        .map(|device| PathBuf::from(device.path))
+        .find(|root| read_device_id(root).as_deref() == Some(device_id))
+        .ok_or_else(|| "The device is not connected".to_string())
+}
+
+/// Id of the removable device mounted at `device_path`, marking it on first use
+/// so the same sync set applies wherever it is mounted later.
+#[tauri::command]
+pub async fn get_device_id(device_path: String) -> Result<String, String> {
+    let device = get_connected_devices().await?.into_iter()
+        .filter(|device| device.removable && Path::new(&device_path).starts_with(&device.path))
+        .max_by_key(|device| device.path.len())
+        .ok_or_else(|| format!("{} is not on a removable device", device_path))?;
+    let id = device_id(Path::new(&device.path))?;
+
+    let mut index = LIBRARY.lock();
+    if !index.devices.contains_key(&id) {
+        index.devices.insert(id.clone(), SyncDevice::new(&device.name));
+        save_index(&index)?;
+    }
+    Ok(id)
+}
+
+#[tauri::command]
+pub fn get_sync_set(device_id: String) -> Result<SyncSet, String> {
+    let index = LIBRARY.lock();
+    let device = index.devices.get(&device_id).ok_or_else(|| format!("Unknown device: {}", device_id))?;
+    Ok(sync_set(&device_id, device))
+}
+
+#[tauri::command]
+pub fn add_to_sync_set(device_id: String, paths: Vec<String>) -> Result<SyncSet, String> {
+    let mut added = Vec::new();
+    for path in paths {
+        let path_buf = PathBuf::from(&path);
+        if !path_buf.exists() {
+            return Err(format!("{} does not exist", path));
+        }
+        let is_dir = path_buf.is_dir();
+        added.push(SyncSetEntry { identity: identity_for(&path_buf, is_dir), path, is_dir });
+    }
+
+    let mut index = LIBRARY.lock();
+    let device = device_mut(&mut index.devices, &device_id)?;
+    for entry in added {
+        device.sync_set.retain(|existing| existing.path != entry.path);
+        device.sync_set.push(entry);
+    }
+    let set = sync_set(&device_id, device);
+    save_index(&index)?;
+    Ok(set)
+}
+
+#[tauri::command]
+pub fn remove_from_sync_set(device_id: String, paths: Vec<String>) -> Result<SyncSet, String> {
+    let mut index = LIBRARY.lock();
+    let device = device_mut(&mut index.devices, &device_id)?;
+    device.sync_set.retain(|entry| !paths.contains(&entry.path));
+    let set = sync_set(&device_id, device);
+    save_index(&index)?;
+    Ok(set)
+}
+
+#[tauri::command]
+pub fn set_device_sync_options(device_id: String, options: DeviceSyncOptions) -> Result<SyncSet, String> {
+    let mut index = LIBRARY.lock();
+    let device = device_mut(&mut index.devices, &device_id)?;
+    device.sync_options = options;
+    let set = sync_set(&device_id, device);
+    save_index(&index)?;
+    Ok(set)
+}
+
+/// Copies everything in the device's sync set onto it. Entries that moved are
+/// found again through the library index; ones that can't be found are reported.
+#[tauri::command]
+pub async fn run_sync_set(app: AppHandle, device_id: String) -> Result<SyncSetRun, String> {
+    let device = LIBRARY.lock().devices.get(&device_id).cloned()
+        .ok_or_else(|| format!("Unknown device: {}", device_id))?;
+    if device.sync_set.is_empty() {
+        return Err(format!("The sync set for {} is empty", device.name));
+    }
+    let root = mounted_root(&device_id).await?;
+    let target = root.join(&device.sync_options.music_folder);
+
+    let mut moved = Vec::new();
+    let mut missing = Vec::new();
+    let mut files = Vec::new();
+    let exclusions = Exclusions::load();
+    for entry in &device.sync_set {
+        let mut path = PathBuf::from(&entry.path);
+        if !path.exists() {
+            match relocate_entry(entry) {
+                Some(found) => {
+                    moved.push(MovedEntry { from: entry.path.clone(), to: found.to_string_lossy().to_string() });
+                    path = found;
+                }
+                None => {
+                    missing.push(entry.path.clone());
+                    continue;
+                }
+            }
+        }
+        let fallback = path.parent().map(Path::to_path_buf).unwrap_or_else(|| path.clone());
+        if entry.is_dir {
+            files.extend(walk_audio_files(&path, true, &exclusions).into_iter().map(|file| (file, fallback.clone())));
+        } else {
+            files.push((path, fallback));
+        }
+    }
+
+    if !moved.is_empty() {
+        let mut index = LIBRARY.lock();
+        if let Some(stored) = index.devices.get_mut(&device_id) {
+            for entry in stored.sync_set.iter_mut() {
+                if let Some(found) = moved.iter().find(|m| m.from == entry.path) {
+                    entry.path = found.to.clone();
+                }
+            }
+        }
+        if let Err(e) = save_index(&index) {
+            error!("Failed to save library index: {}", e);
+        }
+    }
+
+    let roots: Vec<PathBuf> = load_player_config().library_roots.into_iter().map(|root| PathBuf::from(root.path)).collect();
+    let mut transfers = Vec::new();
+    for (base, relative) in group_by_base(files, &roots) {
+        let options = TransferOptions {
+            source_path: base.to_string_lossy().to_string(),
+            target_path: target.to_string_lossy().to_string(),
+            create_archive: false,
+            verify_transfer: device.sync_options.verify_transfer,
+            files: Some(relative),
+        };
+        transfers.push(transfer_files(app.clone(), options).await?);
+    }
+
+    info!(
+        "Ran sync set for {}: {} transfers, {} moved, {} missing",
+        device.name, transfers.len(), moved.len(), missing.len()
+    );
+    Ok(SyncSetRun {
+        device_id,
+        device_path: root.to_string_lossy().to_string(),
+        transfers,
+        moved,
+        missing,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn common_folder_is_the_deepest_shared_parent() {
+        let paths = [PathBuf::from("/music/a/album/cd1/01.flac"), PathBuf::from("/music/a/album/cd2/01.flac")];
+        assert_eq!(common_folder(&paths), Some(PathBuf::from("/music/a/album")));
+        assert_eq!(common_folder(&paths[..1]), Some(PathBuf::from("/music/a/album/cd1")));
+        assert_eq!(common_folder(&[]), None);
+    }
+
+    #[test]
+    fn files_keep_their_path_below_the_library_root() {
+        let roots = [PathBuf::from("/music"), PathBuf::from("/music/podcasts")];
+        let files = vec![
+            (PathBuf::from("/music/Artist/Album/01.mp3"), PathBuf::from("/music/Artist")),
+            (PathBuf::from("/music/podcasts/Show/ep1.mp3"), PathBuf::from("/music/podcasts")),
+            (PathBuf::from("/elsewhere/Album/01.mp3"), PathBuf::from("/elsewhere")),
+            // Listed twice when a folder and a track inside it are both in the set
+            (PathBuf::from("/music/Artist/Album/01.mp3"), PathBuf::from("/music/Artist/Album")),
+        ];
+        let groups = group_by_base(files, &roots);
+        assert_eq!(groups[Path::new("/music")], [Path::new("Artist/Album/01.mp3").to_string_lossy()]);
+        assert_eq!(groups[Path::new("/music/podcasts")], [Path::new("Show/ep1.mp3").to_string_lossy()]);
+        assert_eq!(groups[Path::new("/elsewhere")], [Path::new("Album/01.mp3").to_string_lossy()]);
+    }
+}
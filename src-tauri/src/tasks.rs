@@ -0,0 +1,286 @@
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use once_cell::sync::Lazy;
+use parking_lot::Mutex;
+use tauri::{AppHandle, Emitter, Runtime, Wry};
+use log::{info, debug};
+use crate::activity::{self, error_code, ActivityRecord};
+use crate::library::now_secs;
+
+const PROGRESS_INTERVAL: Duration = Duration::from_millis(200);
+// Finished tasks stay listed so the activity panel can show recent results
+const MAX_FINISHED_TASKS: usize = 50;
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum TaskKind {
+    LibraryScan,
+    Transfer,
+    Checksum,
+    Thumbnails,
+    DeviceDiff,
+    Batch,
+    Loudness,
+    Bpm,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum TaskState {
+    Running,
+    Completed,
+    Cancelled,
+    Failed,
+}
+
+#[derive(Debug, Serialize, Clone)]
+pub struct TaskInfo {
+    pub id: u64,
+    pub kind: TaskKind,
+    pub description: String,
+    pub state: TaskState,
+    pub current: u64,
+    pub total: u64,
+    pub bytes_done: u64,
+    pub bytes_total: u64,
+    pub message: Option<String>,
+    pub started_at: u64,
+    pub finished_at: Option<u64>,
+}
+
+struct TaskEntry {
+    info: TaskInfo,
+    cancelled: Arc<AtomicBool>,
+}
+
+static TASKS: Lazy<Mutex<BTreeMap<u64, TaskEntry>>> = Lazy::new(|| Mutex::new(BTreeMap::new()));
+static NEXT_TASK_ID: AtomicU64 = AtomicU64::new(1);
+
+struct TaskInner<R: Runtime> {
+    id: u64,
+    app: AppHandle<R>,
+    cancelled: Arc<AtomicBool>,
+    last_emit: Mutex<Option<Instant>>,
+    finished: AtomicBool,
+    started: Instant,
+    // Reported only in the activity journal, not with progress
+    counts: Mutex<BTreeMap<String, u64>>,
+    error_codes: Mutex<BTreeMap<String, u64>>,
+}
+
+/// Handle a long-running operation uses to report progress and observe
+/// cancellation. Clones share the same task, so worker pools can each hold one.
+/// Dropping the last handle without calling `finish` marks the task failed.
+pub struct TaskHandle<R: Runtime = Wry> {
+    inner: Arc<TaskInner<R>>,
+}
+
+// Derived Clone would require R: Clone
+impl<R: Runtime> Clone for TaskHandle<R> {
+    fn clone(&self) -> Self {
+        Self { inner: Arc::clone(&self.inner) }
+    }
+}
+
+pub fn start_task<R: Runtime>(app: &AppHandle<R>, kind: TaskKind, description: impl Into<String>) -> TaskHandle<R> {
+    let id = NEXT_TASK_ID.fetch_add(1, Ordering::SeqCst);
+    let cancelled = Arc::new(AtomicBool::new(false));
+    let info = TaskInfo {
+        id,
+        kind,
+        description: description.into(),
+        state: TaskState::Running,
+        current: 0,
+        total: 0,
+        bytes_done: 0,
+        bytes_total: 0,
+        message: None,
+        started_at: now_secs(),
+        finished_at: None,
+    };
+    debug!("Started task {} ({:?}): {}", id, kind, info.description);
+
+    TASKS.lock().insert(id, TaskEntry {
+        info: info.clone(),
+        cancelled: Arc::clone(&cancelled),
+    });
+    app.emit("task-progress", info).ok();
+
+    TaskHandle {
+        inner: Arc::new(TaskInner {
+            id,
+            app: app.clone(),
+            cancelled,
+            last_emit: Mutex::new(None),
+            finished: AtomicBool::new(false),
+            started: Instant::now(),
+            counts: Mutex::new(BTreeMap::new()),
+            error_codes: Mutex::new(BTreeMap::new()),
+        }),
+    }
+}
+
+impl<R: Runtime> TaskHandle<R> {
+    pub fn id(&self) -> u64 {
+        self.inner.id
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.inner.cancelled.load(Ordering::Relaxed)
+    }
+
+    /// Applies `update` to the task and emits `task-progress`, rate limited
+    /// unless `force` is set.
+    fn update(&self, force: bool, update: impl FnOnce(&mut TaskInfo)) {
+        let snapshot = {
+            let mut tasks = TASKS.lock();
+            let Some(entry) = tasks.get_mut(&self.inner.id) else { return };
+            update(&mut entry.info);
+            entry.info.clone()
+        };
+
+        {
+            let mut last_emit = self.inner.last_emit.lock();
+            if !force && last_emit.is_some_and(|at| at.elapsed() < PROGRESS_INTERVAL) {
+                return;
+            }
+            *last_emit = Some(Instant::now());
+        }
+        self.inner.app.emit("task-progress", snapshot).ok();
+    }
+
+    pub fn set_progress(&self, current: u64, total: u64) {
+        self.update(false, |info| {
+            info.current = current;
+            info.total = total;
+        });
+    }
+
+    pub fn set_bytes(&self, bytes_done: u64, bytes_total: u64) {
+        self.update(false, |info| {
+            info.bytes_done = bytes_done;
+            info.bytes_total = bytes_total;
+        });
+    }
+
+    pub fn set_message(&self, message: impl Into<String>) {
+        let message = message.into();
+        self.update(false, |info| info.message = Some(message));
+    }
+
+    /// Sets a named total for the activity journal, such as tracks added by a scan.
+    pub fn set_count(&self, name: &str, value: u64) {
+        self.inner.counts.lock().insert(name.to_string(), value);
+    }
+
+    /// Tallies an item that failed with `message` under its error code.
+    pub fn count_error(&self, message: &str) {
+        *self.inner.error_codes.lock().entry(error_code(message).to_string()).or_default() += 1;
+    }
+
+    /// Records the outcome. A cancelled task is reported as cancelled whatever
+    /// the result, since cancellation usually surfaces as an early error.
+    pub fn finish(&self, result: Result<(), String>) {
+        if self.inner.finished.swap(true, Ordering::SeqCst) {
+            return;
+        }
+
+        let cancelled = self.is_cancelled();
+        self.update(true, |info| {
+            info.finished_at = Some(now_secs());
+            match (&result, cancelled) {
+                (_, true) => info.state = TaskState::Cancelled,
+                (Ok(()), false) => info.state = TaskState::Completed,
+                (Err(e), false) => {
+                    info.state = TaskState::Failed;
+                    info.message = Some(e.clone());
+                }
+            }
+        });
+        info!("Task {} finished: {:?}", self.inner.id, result);
+        if let Some(info) = TASKS.lock().get(&self.inner.id).map(|entry| entry.info.clone()) {
+            self.inner.journal(&info);
+        }
+        prune_finished();
+    }
+}
+
+impl<R: Runtime> TaskInner<R> {
+    /// Appends the finished task to the activity journal.
+    fn journal(&self, info: &TaskInfo) {
+        let mut error_codes = self.error_codes.lock().clone();
+        if info.state == TaskState::Failed {
+            let code = info.message.as_deref().map_or("other", error_code);
+            *error_codes.entry(code.to_string()).or_default() += 1;
+        }
+        activity::record(&ActivityRecord {
+            task_id: info.id,
+            kind: info.kind,
+            description: info.description.clone(),
+            outcome: info.state,
+            started_at: info.started_at,
+            finished_at: info.finished_at.unwrap_or_else(now_secs),
+            duration_ms: self.started.elapsed().as_millis() as u64,
+            items_done: info.current,
+            items_total: info.total,
+            bytes_done: info.bytes_done,
+            bytes_total: info.bytes_total,
+            counts: self.counts.lock().clone(),
+            error: (info.state == TaskState::Failed).then(|| info.message.clone()).flatten(),
+            error_codes,
+        });
+    }
+}
+
+impl<R: Runtime> Drop for TaskInner<R> {
+    fn drop(&mut self) {
+        if self.finished.load(Ordering::SeqCst) {
+            return;
+        }
+        let snapshot = {
+            let mut tasks = TASKS.lock();
+            let Some(entry) = tasks.get_mut(&self.id) else { return };
+            entry.info.state = if self.cancelled.load(Ordering::Relaxed) { TaskState::Cancelled } else { TaskState::Failed };
+            entry.info.finished_at = Some(now_secs());
+            entry.info.clone()
+        };
+        self.journal(&snapshot);
+        self.app.emit("task-progress", snapshot).ok();
+    }
+}
+
+fn prune_finished() {
+    let mut tasks = TASKS.lock();
+    let finished: Vec<u64> = tasks.values()
+        .filter(|entry| entry.info.state != TaskState::Running)
+        .map(|entry| entry.info.id)
+        .collect();
+    // Ids increase monotonically, so the first ones are the oldest
+    for id in finished.iter().take(finished.len().saturating_sub(MAX_FINISHED_TASKS)) {
+        tasks.remove(id);
+    }
+}
+
+/// Flags a running task as cancelled. The task itself decides when to stop.
+pub fn cancel_task(id: u64) -> Result<(), String> {
+    let tasks = TASKS.lock();
+    let entry = tasks.get(&id).ok_or_else(|| format!("No task with id {}", id))?;
+    if entry.info.state != TaskState::Running {
+        return Err("Task has already finished".to_string());
+    }
+    entry.cancelled.store(true, Ordering::Relaxed);
+    Ok(())
+}
+
+#[tauri::command]
+pub fn get_background_tasks() -> Vec<TaskInfo> {
+    TASKS.lock().values().map(|entry| entry.info.clone()).collect()
+}
+
+#[tauri::command]
+pub fn cancel_background_task(id: u64) -> Result<(), String> {
+    cancel_task(id)
+}
@@ -0,0 +1,413 @@
+//! Synthetic audio fixtures for tests. Everything is generated on the fly into a
+//! temp dir, so tests never depend on real music files.
+
+use std::collections::HashMap;
+use std::f32::consts::PI;
+use std::fs::{self, File};
+use std::io::Cursor;
+use std::path::{Path, PathBuf};
+use std::sync::Once;
+use std::time::SystemTime;
+use flacenc::component::BitRepr;
+use flacenc::error::Verify;
+use image::{ImageFormat, Rgb, RgbImage};
+use lofty::config::WriteOptions;
+use lofty::prelude::{Accessor, AudioFile, ItemKey, TaggedFileExt};
+use lofty::probe::Probe;
+use lofty::tag::Tag;
+use tempfile::TempDir;
+
+pub const SAMPLE_RATE: u32 = 8_000;
+pub const FIXTURE_SECS: u32 = 2;
+// MPEG-1 Layer III, 128 kbps, 44.1 kHz, mono: 417 bytes per 1152-sample frame
+const MP3_FRAME_HEADER: [u8; 4] = [0xFF, 0xFB, 0x90, 0xC4];
+const MP3_FRAME_LEN: usize = 417;
+const MP3_FRAME_SAMPLES: u32 = 1152;
+const MP3_SAMPLE_RATE: u32 = 44_100;
+
+static ISOLATE_CONFIG: Once = Once::new();
+
+/// Points the config dir at a throwaway location so tests never read or write
+/// the real library index, favorites or settings. Call before touching any of them.
+pub fn isolate_config() {
+    ISOLATE_CONFIG.call_once(|| {
+        let dir = std::env::temp_dir().join(format!("musicmanager-test-config-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        std::env::set_var("XDG_CONFIG_HOME", &dir);
+        std::env::set_var("HOME", &dir);
+    });
+}
+
+pub fn temp_dir() -> TempDir {
+    isolate_config();
+    tempfile::tempdir().unwrap()
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Format {
+    Wav,
+    Flac,
+    Mp3,
+    // No samples, only the boxes lofty needs: for tag tests, not decoding
+    M4a,
+}
+
+impl Format {
+    pub fn extension(self) -> &'static str {
+        match self {
+            Format::Wav => "wav",
+            Format::Flac => "flac",
+            Format::Mp3 => "mp3",
+            Format::M4a => "m4a",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct FixtureTags {
+    pub title: Option<String>,
+    pub artist: Option<String>,
+    pub album: Option<String>,
+    pub track: Option<String>,
+    pub date: Option<String>,
+    pub with_art: bool,
+}
+
+impl FixtureTags {
+    pub fn titled(title: &str) -> Self {
+        Self { title: Some(title.to_string()), ..Self::default() }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.title.is_none() && self.artist.is_none() && self.album.is_none()
+            && self.track.is_none() && self.date.is_none() && !self.with_art
+    }
+}
+
+/// 16-bit mono samples of a `freq` Hz sine wave at `sample_rate`.
+pub fn sine_samples(sample_rate: u32, freq: f32, secs: f32) -> Vec<i16> {
+    (0..(sample_rate as f32 * secs) as usize)
+        .map(|n| ((2.0 * PI * freq * n as f32 / sample_rate as f32).sin() * 8000.0) as i16)
+        .collect()
+}
+
+pub fn wav_bytes(samples: &[i16]) -> Vec<u8> {
+    let data_len = samples.len() as u32 * 2;
+    let mut out = Vec::with_capacity(44 + data_len as usize);
+    out.extend_from_slice(b"RIFF");
+    out.extend_from_slice(&(36 + data_len).to_le_bytes());
+    out.extend_from_slice(b"WAVEfmt ");
+    out.extend_from_slice(&16u32.to_le_bytes());
+    out.extend_from_slice(&1u16.to_le_bytes()); // PCM
+    out.extend_from_slice(&1u16.to_le_bytes()); // mono
+    out.extend_from_slice(&SAMPLE_RATE.to_le_bytes());
+    out.extend_from_slice(&(SAMPLE_RATE * 2).to_le_bytes());
+    out.extend_from_slice(&2u16.to_le_bytes());
+    out.extend_from_slice(&16u16.to_le_bytes());
+    out.extend_from_slice(b"data");
+    out.extend_from_slice(&data_len.to_le_bytes());
+    for sample in samples {
+        out.extend_from_slice(&sample.to_le_bytes());
+    }
+    out
+}
+
+/// A FLAC stream encoded from `samples`, with a padding block after
+/// STREAMINFO as real encoders leave.
+pub fn flac_bytes(samples: &[i16]) -> Vec<u8> {
+    let config = flacenc::config::Encoder::default().into_verified().expect("default encoder config");
+    let samples: Vec<i32> = samples.iter().map(|&sample| sample as i32).collect();
+    let source = flacenc::source::MemSource::from_samples(&samples, 1, 16, SAMPLE_RATE as usize);
+    let mut stream = flacenc::encode_with_fixed_block_size(&config, source, config.block_size).expect("encode FLAC");
+    // The encoder records the short last block as the minimum, which makes
+    // symphonia treat the stream as variable-blocksize and lose frame sync
+    stream.stream_info_mut().set_block_sizes(config.block_size, config.block_size).expect("block sizes");
+    // lofty inserts tags after STREAMINFO without moving the last-block flag,
+    // so STREAMINFO must not carry it
+    stream.add_metadata_block(flacenc::component::MetadataBlockData::new_unknown(1, &[0; 4]).expect("padding block"));
+    let mut sink = flacenc::bitsink::ByteSink::new();
+    stream.write(&mut sink).expect("write FLAC");
+    sink.into_inner()
+}
+
+/// Silent MPEG frames: zeroed side info decodes as silence.
+pub fn mp3_frames(secs: u32) -> Vec<u8> {
+    let frames = (secs * MP3_SAMPLE_RATE).div_ceil(MP3_FRAME_SAMPLES);
+    let mut frame = vec![0u8; MP3_FRAME_LEN];
+    frame[..4].copy_from_slice(&MP3_FRAME_HEADER);
+    frame.repeat(frames as usize)
+}
+
+fn mp4_box(name: &[u8; 4], body: &[u8]) -> Vec<u8> {
+    let mut out = ((body.len() + 8) as u32).to_be_bytes().to_vec();
+    out.extend_from_slice(name);
+    out.extend_from_slice(body);
+    out
+}
+
+/// A box with a version and flags, all zero.
+fn mp4_full_box(name: &[u8; 4], body: &[u8]) -> Vec<u8> {
+    mp4_box(name, &[&[0u8; 4][..], body].concat())
+}
+
+/// An AAC-in-MP4 file with an empty `mdat`, lasting `secs` going by its headers.
+pub fn m4a_bytes(secs: u32) -> Vec<u8> {
+    let be = |n: u32| n.to_be_bytes();
+    let matrix: Vec<u8> = [0x10000u32, 0, 0, 0, 0x10000, 0, 0, 0, 0x4000_0000].iter().flat_map(|n| n.to_be_bytes()).collect();
+    let duration = SAMPLE_RATE * secs;
+
+    // Times, timescale, duration, rate, volume, reserved, matrix, pre-defined, next track
+    let mvhd = [&be(0)[..], &be(0), &be(SAMPLE_RATE), &be(duration), &be(0x10000), &[1, 0], &[0; 10], &matrix, &[0; 24], &be(2)].concat();
+    // Flagged enabled and in the movie; times, track id, reserved, duration, reserved, layer, group, volume, reserved, matrix, size
+    let tkhd = [&[0u8, 0, 0, 7][..], &be(0), &be(0), &be(1), &be(0), &be(duration), &[0; 8], &[0, 0, 0, 0, 1, 0, 0, 0], &matrix, &[0; 8]].concat();
+    // Times, timescale, duration, language "und", pre-defined
+    let mdhd = [&be(0)[..], &be(0), &be(SAMPLE_RATE), &be(duration), &[0x55, 0xC4, 0, 0]].concat();
+    let hdlr = [&be(0)[..], b"soun", &[0; 13]].concat();
+    // ES descriptor holding AAC LC, 8 kHz mono
+    let esds = [&[3u8, 25, 0, 1, 0][..], &[4, 17, 0x40, 0x15, 0, 0, 0], &be(0), &be(0), &[5, 2, 0x15, 0x88], &[6, 1, 2]].concat();
+    // Reserved, data reference, reserved, channels, sample size, reserved, sample rate as 16.16
+    let mp4a = [&[0u8; 6][..], &[0, 1], &[0; 8], &[0, 1, 0, 16, 0, 0, 0, 0], &be(SAMPLE_RATE << 16), &mp4_full_box(b"esds", &esds)].concat();
+    let stbl = [
+        mp4_full_box(b"stsd", &[&be(1)[..], &mp4_box(b"mp4a", &mp4a)].concat()),
+        mp4_full_box(b"stts", &be(0)),
+        mp4_full_box(b"stsc", &be(0)),
+        mp4_full_box(b"stsz", &[be(0), be(0)].concat()),
+        mp4_full_box(b"stco", &be(0)),
+    ].concat();
+    let minf = [mp4_full_box(b"smhd", &[0; 4]), mp4_box(b"stbl", &stbl)].concat();
+    let mdia = [mp4_full_box(b"mdhd", &mdhd), mp4_full_box(b"hdlr", &hdlr), mp4_box(b"minf", &minf)].concat();
+    let trak = [mp4_box(b"tkhd", &tkhd), mp4_box(b"mdia", &mdia)].concat();
+    let moov = [mp4_full_box(b"mvhd", &mvhd), mp4_box(b"trak", &trak)].concat();
+
+    let mut out = mp4_box(b"ftyp", &[&b"M4A "[..], &be(0), b"M4A isom"].concat());
+    out.extend(mp4_box(b"moov", &moov));
+    out.extend(mp4_box(b"mdat", &[]));
+    out
+}
+
+/// A tiny solid-color JPEG for cover art.
+pub fn jpeg_bytes() -> Vec<u8> {
+    let image = RgbImage::from_pixel(8, 8, Rgb([200, 40, 40]));
+    let mut out = Cursor::new(Vec::new());
+    image.write_to(&mut out, ImageFormat::Jpeg).unwrap();
+    out.into_inner()
+}
+
+fn id3_frame(id: &[u8; 4], body: &[u8]) -> Vec<u8> {
+    let mut frame = id.to_vec();
+    frame.extend_from_slice(&(body.len() as u32).to_be_bytes());
+    frame.extend_from_slice(&[0, 0]);
+    frame.extend_from_slice(body);
+    frame
+}
+
+fn id3_text(id: &[u8; 4], text: &str) -> Vec<u8> {
+    let mut body = vec![0u8]; // ISO-8859-1
+    body.extend_from_slice(text.as_bytes());
+    id3_frame(id, &body)
+}
+
+fn syncsafe(size: u32) -> [u8; 4] {
+    [(size >> 21) as u8 & 0x7F, (size >> 14) as u8 & 0x7F, (size >> 7) as u8 & 0x7F, size as u8 & 0x7F]
+}
+
+/// An ID3v2.3 tag assembled frame by frame.
+pub fn id3v2_tag(tags: &FixtureTags) -> Vec<u8> {
+    id3v2_tag_with(tags, &[])
+}
+
+/// An ID3v2.3 tag with `extra` frames, given as ID and body, after the usual ones.
+pub fn id3v2_tag_with(tags: &FixtureTags, extra: &[(&[u8; 4], Vec<u8>)]) -> Vec<u8> {
+    let mut frames = Vec::new();
+    let text_frames = [
+        (b"TIT2", &tags.title),
+        (b"TPE1", &tags.artist),
+        (b"TALB", &tags.album),
+        (b"TRCK", &tags.track),
+        (b"TYER", &tags.date),
+    ];
+    for (id, value) in text_frames {
+        if let Some(value) = value {
+            frames.extend(id3_text(id, value));
+        }
+    }
+    if tags.with_art {
+        let mut body = vec![0u8];
+        body.extend_from_slice(b"image/jpeg\0");
+        body.push(3); // front cover
+        body.push(0); // empty description
+        body.extend(jpeg_bytes());
+        frames.extend(id3_frame(b"APIC", &body));
+    }
+    for (id, body) in extra {
+        frames.extend(id3_frame(id, body));
+    }
+
+    let mut tag = b"ID3\x03\x00\x00".to_vec();
+    tag.extend_from_slice(&syncsafe(frames.len() as u32));
+    tag.extend(frames);
+    tag
+}
+
+/// Applies `tags` with lofty, for formats whose tags aren't built by hand.
+fn apply_tags(path: &Path, tags: &FixtureTags) {
+    let mut tagged_file = Probe::open(path).unwrap().read().unwrap();
+    let tag_type = tagged_file.primary_tag_type();
+    let mut tag = Tag::new(tag_type);
+    if let Some(title) = &tags.title {
+        tag.set_title(title.clone());
+    }
+    if let Some(artist) = &tags.artist {
+        tag.set_artist(artist.clone());
+    }
+    if let Some(album) = &tags.album {
+        tag.set_album(album.clone());
+    }
+    if let Some(track) = &tags.track {
+        tag.insert_text(ItemKey::TrackNumber, track.clone());
+    }
+    if let Some(date) = &tags.date {
+        tag.insert_text(ItemKey::RecordingDate, date.clone());
+    }
+    if tags.with_art {
+        let picture = lofty::picture::Picture::new_unchecked(
+            lofty::picture::PictureType::CoverFront,
+            Some(lofty::picture::MimeType::Jpeg),
+            None,
+            jpeg_bytes(),
+        );
+        tag.push_picture(picture);
+    }
+    tagged_file.insert_tag(tag);
+    tagged_file.save_to_path(path, WriteOptions::default()).unwrap();
+}
+
+/// Writes a two-second fixture of `format` at `path`, tagged with `tags`.
+pub fn write_fixture(path: &Path, format: Format, tags: &FixtureTags) {
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent).unwrap();
+    }
+    let bytes = match format {
+        Format::Wav => wav_bytes(&sine_samples(SAMPLE_RATE, 440.0, FIXTURE_SECS as f32)),
+        Format::Flac => flac_bytes(&sine_samples(SAMPLE_RATE, 440.0, FIXTURE_SECS as f32)),
+        Format::Mp3 => {
+            let mut bytes = if tags.is_empty() { Vec::new() } else { id3v2_tag(tags) };
+            bytes.extend(mp3_frames(FIXTURE_SECS));
+            bytes
+        }
+        Format::M4a => m4a_bytes(FIXTURE_SECS),
+    };
+    fs::write(path, bytes).unwrap();
+    if format != Format::Mp3 && !tags.is_empty() {
+        apply_tags(path, tags);
+    }
+}
+
+pub fn set_modified(path: &Path, modified: SystemTime) {
+    File::options().write(true).open(path).unwrap().set_modified(modified).unwrap();
+}
+
+/// One file in a generated library tree.
+#[derive(Debug, Clone)]
+pub struct TrackSpec {
+    pub relative_path: String,
+    pub format: Format,
+    pub tags: FixtureTags,
+    pub modified: Option<SystemTime>,
+}
+
+impl TrackSpec {
+    pub fn new(relative_path: &str, tags: FixtureTags) -> Self {
+        let format = match Path::new(relative_path).extension().and_then(|ext| ext.to_str()) {
+            Some("flac") => Format::Flac,
+            Some("mp3") => Format::Mp3,
+            Some("m4a") => Format::M4a,
+            _ => Format::Wav,
+        };
+        Self { relative_path: relative_path.to_string(), format, tags, modified: None }
+    }
+
+    pub fn modified(mut self, modified: SystemTime) -> Self {
+        self.modified = Some(modified);
+        self
+    }
+}
+
+/// Builds a nested library under `root`, returning the absolute path of each track.
+pub fn build_library(root: &Path, tracks: &[TrackSpec]) -> Vec<PathBuf> {
+    tracks.iter().map(|track| {
+        let path = root.join(&track.relative_path);
+        write_fixture(&path, track.format, &track.tags);
+        if let Some(modified) = track.modified {
+            set_modified(&path, modified);
+        }
+        path
+    }).collect()
+}
+
+/// Four tracks whose modified and added-at orders differ, returning the
+/// added-at times an index would hold for two of them. Sorted by date added
+/// (falling back to mtime for the other two) they run d, c, a, b; by date
+/// modified, d, b, c, a.
+pub fn build_dated_library(root: &Path) -> HashMap<String, u64> {
+    let epoch = SystemTime::UNIX_EPOCH + std::time::Duration::from_secs(1_600_000_000);
+    let secs = std::time::Duration::from_secs;
+    build_library(root, &[
+        TrackSpec::new("a.mp3", FixtureTags::titled("a")).modified(epoch + secs(30)),
+        TrackSpec::new("b.flac", FixtureTags::titled("b")).modified(epoch + secs(10)),
+        TrackSpec::new("c.wav", FixtureTags::titled("c")).modified(epoch + secs(20)),
+        TrackSpec::new("d.mp3", FixtureTags::titled("d")).modified(epoch + secs(5)),
+    ]);
+    let added_at = |name: &str, offset: u64| (root.join(name).to_string_lossy().to_string(), 1_600_000_000 + offset);
+    HashMap::from([added_at("b.flac", 40), added_at("d.mp3", 1)])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rodio::{Decoder, Source};
+    use std::io::BufReader;
+
+    #[test]
+    fn fixtures_probe_as_two_second_audio() {
+        let dir = temp_dir();
+        for format in [Format::Wav, Format::Flac, Format::Mp3, Format::M4a] {
+            let path = dir.path().join(format!("sine.{}", format.extension()));
+            write_fixture(&path, format, &FixtureTags::default());
+
+            let tagged_file = Probe::open(&path).unwrap().read().unwrap();
+            let secs = tagged_file.properties().duration().as_secs_f32();
+            assert!((secs - FIXTURE_SECS as f32).abs() < 0.1, "{:?} lasted {}s", format, secs);
+        }
+    }
+
+    #[test]
+    fn fixtures_decode() {
+        let dir = temp_dir();
+        for format in [Format::Wav, Format::Flac, Format::Mp3] {
+            let path = dir.path().join(format!("sine.{}", format.extension()));
+            write_fixture(&path, format, &FixtureTags { with_art: true, ..FixtureTags::titled("Sine") });
+
+            let decoder = Decoder::new(BufReader::new(File::open(&path).unwrap()))
+                .unwrap_or_else(|e| panic!("{:?} failed to decode: {}", format, e));
+            assert!(decoder.sample_rate() > 0);
+        }
+    }
+
+    #[test]
+    fn mp3_fixture_carries_hand_built_id3_tag() {
+        let dir = temp_dir();
+        let path = dir.path().join("tagged.mp3");
+        write_fixture(&path, Format::Mp3, &FixtureTags {
+            artist: Some("Artist".into()),
+            with_art: true,
+            ..FixtureTags::titled("Title")
+        });
+
+        let tagged_file = Probe::open(&path).unwrap().read().unwrap();
+        let tag = tagged_file.primary_tag().unwrap();
+        assert_eq!(tag.title().as_deref(), Some("Title"));
+        assert_eq!(tag.artist().as_deref(), Some("Artist"));
+        assert_eq!(tag.pictures().len(), 1);
+    }
+}
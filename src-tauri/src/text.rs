@@ -0,0 +1,133 @@
+//! Text folding shared by every place that matches or sorts names, so "bjork"
+//! finds "Björk" and "BJÖRK" the same way in search, artist lists and rules.
+
+use std::cmp::Ordering;
+use unicode_normalization::char::is_combining_mark;
+use unicode_normalization::UnicodeNormalization;
+
+/// Scripts whose accents are decoration a searcher may leave off. Elsewhere
+/// (kana voicing marks, Indic vowel signs, Hangul jamo) the mark changes the
+/// letter, so stripping would merge unrelated words.
+fn strips_marks(c: char) -> bool {
+    matches!(c as u32,
+        0x0000..=0x024F     // Latin, Latin-1, Latin Extended-A/B
+        | 0x0370..=0x052F   // Greek, Cyrillic
+        | 0x1E00..=0x1FFF   // Latin Extended Additional, Greek Extended
+    )
+}
+
+/// Letters with no canonical decomposition that still read as their base letter.
+fn fold_letter(c: char, out: &mut String) {
+    match c {
+        'ß' | 'ẞ' => out.push_str("ss"),
+        'æ' | 'Æ' => out.push_str("ae"),
+        'œ' | 'Œ' => out.push_str("oe"),
+        'ø' | 'Ø' => out.push('o'),
+        'đ' | 'Đ' => out.push('d'),
+        'ł' | 'Ł' => out.push('l'),
+        'ı' => out.push('i'),
+        'ς' => out.push('σ'),
+        _ => out.extend(c.to_lowercase()),
+    }
+}
+
+/// Case folds and strips diacritics from Latin, Greek and Cyrillic text.
+/// Compatibility forms are unified first, so full-width "Ｂｊｏｒｋ" and
+/// half-width kana match their ordinary spellings.
+pub fn fold(text: &str) -> String {
+    let mut out = String::with_capacity(text.len());
+    for c in text.trim().nfkc() {
+        if !strips_marks(c) {
+            out.extend(c.to_lowercase());
+            continue;
+        }
+        for base in std::iter::once(c).nfd().filter(|c| !is_combining_mark(*c)) {
+            fold_letter(base, &mut out);
+        }
+    }
+    out
+}
+
+/// Case folding only, for callers where accents must keep words apart.
+pub fn fold_case(text: &str) -> String {
+    let mut out = String::with_capacity(text.len());
+    for c in text.trim().nfkc() {
+        match c {
+            'ß' | 'ẞ' => out.push_str("ss"),
+            'ς' => out.push('σ'),
+            _ => out.extend(c.to_lowercase()),
+        }
+    }
+    out
+}
+
+pub fn same_text(a: &str, b: &str) -> bool {
+    fold(a) == fold(b)
+}
+
+pub fn contains_text(haystack: &str, needle: &str) -> bool {
+    fold(haystack).contains(&fold(needle))
+}
+
+/// Orders names the way people look them up: "Édith Piaf" files under E.
+/// Ties fall back to a plain comparison so the order is deterministic.
+pub fn collate(a: &str, b: &str) -> Ordering {
+    fold(a).cmp(&fold(b)).then_with(|| a.cmp(b))
+}
+
+/// `collate` for optional names, with missing ones last.
+pub fn collate_opt(a: Option<&str>, b: Option<&str>) -> Ordering {
+    match (a, b) {
+        (Some(a), Some(b)) => collate(a, b),
+        (Some(_), None) => Ordering::Less,
+        (None, Some(_)) => Ordering::Greater,
+        (None, None) => Ordering::Equal,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn scandinavian_names_match_without_accents() {
+        for spelling in ["Björk", "BJÖRK", "Bjørk", "bjork"] {
+            assert!(same_text(spelling, "bjork"), "{}", spelling);
+        }
+        assert!(contains_text("Sigur Rós - Ágætis byrjun", "agaetis"));
+        assert!(contains_text("Åsa Jinder", "asa"));
+        assert!(same_text("Ærøskøbing", "aeroskobing"));
+    }
+
+    #[test]
+    fn german_names_fold_sharp_s_and_umlauts() {
+        assert!(same_text("Straße", "STRASSE"));
+        assert!(same_text("Motörhead", "motorhead"));
+        assert!(same_text("Die Ärzte", "die arzte"));
+        assert_eq!(fold_case("Größe"), "grösse");
+    }
+
+    #[test]
+    fn japanese_keeps_voicing_marks() {
+        // が and か are different syllables
+        assert!(!same_text("がっこう", "かっこう"));
+        assert!(same_text("がっこう", "がっこう"));
+        // Half-width katakana with a separate voicing mark joins up
+        assert!(same_text("ｶﾞｯｺｳ", "ガッコウ"));
+        assert!(contains_text("坂本龍一 - 戦場のメリークリスマス", "メリー"));
+    }
+
+    #[test]
+    fn compatibility_forms_match_plain_text() {
+        assert!(same_text("Ｂｊｏｒｋ", "bjork"));
+        assert!(same_text("ﬁnal", "final"));
+    }
+
+    #[test]
+    fn collation_ignores_case_and_accents() {
+        let mut artists = vec!["Zz Top", "Édith Piaf", "abba", "Ólafur Arnalds", "Eels"];
+        artists.sort_by(|a, b| collate(a, b));
+        assert_eq!(artists, ["abba", "Édith Piaf", "Eels", "Ólafur Arnalds", "Zz Top"]);
+        assert_eq!(collate_opt(None, Some("a")), Ordering::Greater);
+    }
+}
@@ -0,0 +1,40 @@
+use once_cell::sync::Lazy;
+use parking_lot::{Condvar, Mutex};
+
+// How many background jobs may hit the disk at once. Foreground commands never
+// take a permit, so they are only ever competing with this many workers.
+const BACKGROUND_IO_SLOTS: usize = 2;
+
+struct Semaphore {
+    available: Mutex<usize>,
+    released: Condvar,
+}
+
+static BACKGROUND_IO: Lazy<Semaphore> = Lazy::new(|| Semaphore {
+    available: Mutex::new(BACKGROUND_IO_SLOTS),
+    released: Condvar::new(),
+});
+
+/// Held while a background task does a unit of disk work; released on drop.
+pub struct IoPermit {
+    _private: (),
+}
+
+impl Drop for IoPermit {
+    fn drop(&mut self) {
+        let mut available = BACKGROUND_IO.available.lock();
+        *available += 1;
+        BACKGROUND_IO.released.notify_one();
+    }
+}
+
+/// Blocks until a background IO slot is free. Library scans, thumbnail
+/// generation and other bulk jobs all share the same pool of slots.
+pub fn background_io() -> IoPermit {
+    let mut available = BACKGROUND_IO.available.lock();
+    while *available == 0 {
+        BACKGROUND_IO.released.wait(&mut available);
+    }
+    *available -= 1;
+    IoPermit { _private: () }
+}
@@ -0,0 +1,346 @@
+use serde::Serialize;
+use std::fs;
+use std::io::Cursor;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+use base64::{Engine as _, engine::general_purpose::STANDARD as BASE64};
+use image::codecs::jpeg::JpegEncoder;
+use lofty::{prelude::TaggedFileExt, picture::{Picture, PictureType}, probe::Probe};
+use once_cell::sync::Lazy;
+use parking_lot::Mutex;
+use tauri::{AppHandle, Emitter};
+use log::{info, debug};
+use crate::albums::{collect_audio_files, group_albums};
+use crate::cache::{self, CacheKind, CacheStats};
+use crate::config::load_player_config;
+use crate::library::{modified_secs, LIBRARY};
+use crate::tasks::{cancel_task, start_task, TaskHandle, TaskKind};
+use crate::throttle;
+
+const THUMBNAIL_QUALITY: u8 = 85;
+// Edge of the thumbnails in directory listings, and of get_album_art_thumbnail by default
+pub const LISTING_THUMBNAIL_PX: u32 = 160;
+const FOLDER_IMAGES: [&str; 6] = ["cover.jpg", "cover.png", "folder.jpg", "folder.png", "front.jpg", "front.png"];
+const LOW_PRIORITY_PAUSE: Duration = Duration::from_millis(50);
+const PAUSE_POLL: Duration = Duration::from_millis(200);
+
+pub enum ThumbnailOutcome {
+    Cached(PathBuf),
+    Generated(PathBuf),
+    NoArt,
+}
+
+#[derive(Debug, Serialize, Clone, Default)]
+pub struct PregenProgress {
+    pub completed: usize,
+    pub total: usize,
+    pub generated: usize,
+    pub skipped: usize,
+    pub failed: usize,
+    pub paused: bool,
+    pub cancelled: bool,
+}
+
+struct PregenControl {
+    paused: AtomicBool,
+    task: TaskHandle,
+}
+
+static PREGEN: Lazy<Mutex<Option<Arc<PregenControl>>>> = Lazy::new(|| Mutex::new(None));
+
+/// Embedded front cover, or the first picture if none is marked as the front cover.
+pub fn embedded_cover(path: &Path) -> Option<Picture> {
+    let tagged_file = Probe::open(path).ok()?.read().ok()?;
+    let tag = tagged_file.primary_tag().or_else(|| tagged_file.first_tag())?;
+    let pictures = tag.pictures();
+    pictures.iter()
+        .find(|picture| picture.pic_type() == PictureType::CoverFront)
+        .or_else(|| pictures.first())
+        .cloned()
+}
+
+/// Embedded cover art, falling back to a cover image in the same folder.
+pub fn cover_image_data(path: &Path) -> Option<Vec<u8>> {
+    let embedded = embedded_cover(path).map(|picture| picture.into_data());
+
+    embedded.or_else(|| {
+        let folder = path.parent()?;
+        FOLDER_IMAGES.iter()
+            .map(|name| folder.join(name))
+            .find(|candidate| candidate.is_file())
+            .and_then(|candidate| fs::read(candidate).ok())
+    })
+}
+
+fn thumbnail_file_name(path: &Path, max_px: u32) -> Option<String> {
+    let metadata = fs::metadata(path).ok()?;
+    let key = cache::cache_key(&[
+        &path.to_string_lossy(),
+        &modified_secs(&metadata).to_string(),
+        &metadata.len().to_string(),
+        &max_px.to_string(),
+    ]);
+    Some(format!("{}.jpg", key))
+}
+
+pub fn render_thumbnail(data: &[u8], max_px: u32) -> Result<Vec<u8>, String> {
+    let image = image::load_from_memory(data).map_err(|e| format!("Failed to decode image: {}", e))?;
+    let thumbnail = if image.width() > max_px || image.height() > max_px {
+        image.thumbnail(max_px, max_px)
+    } else {
+        image
+    };
+
+    let mut output = Cursor::new(Vec::new());
+    JpegEncoder::new_with_quality(&mut output, THUMBNAIL_QUALITY)
+        .encode_image(&thumbnail.to_rgb8())
+        .map_err(|e| format!("Failed to encode thumbnail: {}", e))?;
+    Ok(output.into_inner())
+}
+
+/// Returns the cached thumbnail for `path`, generating it if needed.
+pub fn ensure_thumbnail(path: &Path, max_px: u32) -> Result<ThumbnailOutcome, String> {
+    let file_name = thumbnail_file_name(path, max_px)
+        .ok_or_else(|| format!("File does not exist: {}", path.display()))?;
+
+    if let Some(cached) = cache::entry_path(CacheKind::Artwork, &file_name) {
+        if cached.is_file() {
+            cache::mark_used(&cached);
+            return Ok(ThumbnailOutcome::Cached(cached));
+        }
+    }
+
+    let data = match cover_image_data(path) {
+        Some(data) => data,
+        None => return Ok(ThumbnailOutcome::NoArt),
+    };
+    let thumbnail = render_thumbnail(&data, max_px)?;
+    cache::write_entry(CacheKind::Artwork, &file_name, &thumbnail).map(ThumbnailOutcome::Generated)
+}
+
+/// The cached thumbnail for `path`, base64-encoded; None when it has no art.
+pub fn thumbnail_base64(path: &Path, max_px: u32) -> Result<Option<String>, String> {
+    let thumbnail = match ensure_thumbnail(path, max_px)? {
+        ThumbnailOutcome::Cached(thumbnail) | ThumbnailOutcome::Generated(thumbnail) => thumbnail,
+        ThumbnailOutcome::NoArt => return Ok(None),
+    };
+    let data = fs::read(&thumbnail).map_err(|e| format!("Failed to read {}: {}", thumbnail.display(), e))?;
+    Ok(Some(BASE64.encode(data)))
+}
+
+/// A JPEG of the cover for `path` no larger than `size` pixels a side,
+/// base64-encoded and cached on disk by path and modification time.
+#[tauri::command]
+pub fn get_album_art_thumbnail(path: String, size: Option<u32>) -> Result<Option<String>, String> {
+    let size = size.unwrap_or(LISTING_THUMBNAIL_PX);
+    if size == 0 {
+        return Err("Thumbnail size must be at least 1 pixel".to_string());
+    }
+    thumbnail_base64(Path::new(&path), size)
+}
+
+#[tauri::command]
+pub fn clear_art_cache() -> Result<CacheStats, String> {
+    cache::clear(CacheKind::Artwork)
+}
+
+fn emit_progress(app: &AppHandle, event: &str, progress: &Mutex<PregenProgress>, control: &PregenControl) {
+    let mut snapshot = progress.lock().clone();
+    snapshot.paused = control.paused.load(Ordering::Relaxed);
+    snapshot.cancelled = control.task.is_cancelled();
+    app.emit(event, snapshot).ok();
+}
+
+fn pregen_worker(
+    app: AppHandle,
+    albums: Arc<Vec<Vec<PathBuf>>>,
+    next: Arc<AtomicUsize>,
+    control: Arc<PregenControl>,
+    progress: Arc<Mutex<PregenProgress>>,
+    max_px: u32,
+    low_priority: bool,
+) {
+    loop {
+        while control.paused.load(Ordering::Relaxed) && !control.task.is_cancelled() {
+            std::thread::sleep(PAUSE_POLL);
+        }
+        if control.task.is_cancelled() {
+            return;
+        }
+
+        let index = next.fetch_add(1, Ordering::SeqCst);
+        let Some(tracks) = albums.get(index) else { return };
+
+        let outcome = {
+            let _permit = throttle::background_io();
+            // The first track that actually carries art represents the album
+            let mut outcome = Ok(ThumbnailOutcome::NoArt);
+            for track in tracks {
+                outcome = ensure_thumbnail(track, max_px);
+                if !matches!(outcome, Ok(ThumbnailOutcome::NoArt)) {
+                    break;
+                }
+            }
+            outcome
+        };
+
+        {
+            let mut progress = progress.lock();
+            progress.completed += 1;
+            control.task.set_progress(progress.completed as u64, progress.total as u64);
+            match outcome {
+                Ok(ThumbnailOutcome::Generated(_)) => progress.generated += 1,
+                Ok(_) => progress.skipped += 1,
+                Err(e) => {
+                    debug!("Thumbnail generation failed: {}", e);
+                    progress.failed += 1;
+                }
+            }
+        }
+        emit_progress(&app, "thumbnail-pregen-progress", &progress, &control);
+
+        if low_priority {
+            std::thread::sleep(LOW_PRIORITY_PAUSE);
+        }
+    }
+}
+
+fn albums_under(root: &Path) -> Vec<Vec<PathBuf>> {
+    let mut paths: Vec<PathBuf> = LIBRARY.lock().tracks.keys()
+        .map(PathBuf::from)
+        .filter(|path| path.starts_with(root))
+        .collect();
+    if paths.is_empty() {
+        // Not indexed yet; walk the folder instead
+        paths = collect_audio_files(root, true);
+    }
+
+    group_albums(&paths).into_iter()
+        .map(|group| group.tracks.into_iter().map(|track| PathBuf::from(track.path)).collect())
+        .collect()
+}
+
+/// Starts generating thumbnails for every album under `root` and returns the
+/// background task's id; the album count arrives with the progress events.
+#[tauri::command]
+pub fn pregenerate_thumbnails(app: AppHandle, root: String, max_px: u32, priority: String) -> Result<u64, String> {
+    let low_priority = match priority.to_lowercase().as_str() {
+        "low" => true,
+        "normal" => false,
+        other => return Err(format!("Unknown priority: {}", other)),
+    };
+    if max_px == 0 {
+        return Err("max_px must be greater than zero".to_string());
+    }
+
+    let root_path = PathBuf::from(&root);
+    if !root_path.is_dir() {
+        return Err("Root folder does not exist".to_string());
+    }
+
+    let control = {
+        let mut current = PREGEN.lock();
+        if current.is_some() {
+            return Err("Thumbnail generation is already running".to_string());
+        }
+        let control = Arc::new(PregenControl {
+            paused: AtomicBool::new(false),
+            task: start_task(&app, TaskKind::Thumbnails, format!("Generating thumbnails for {}", root)),
+        });
+        *current = Some(Arc::clone(&control));
+        control
+    };
+
+    let task_id = control.task.id();
+    let workers = if low_priority { 1 } else { load_player_config().thumbnail_workers.max(1) };
+
+    // Finding the albums reads every track's tags, so it happens off the command thread
+    std::thread::spawn(move || {
+        control.task.set_message("Finding albums");
+        let albums = {
+            let _permit = throttle::background_io();
+            Arc::new(albums_under(&root_path))
+        };
+        let total = albums.len();
+        control.task.set_message(format!("Generating thumbnails for {} albums", total));
+        info!("Pre-generating thumbnails for {} albums under {} with {} workers", total, root, workers);
+
+        let progress = Arc::new(Mutex::new(PregenProgress { total, ..PregenProgress::default() }));
+        let next = Arc::new(AtomicUsize::new(0));
+        let handles: Vec<_> = (0..workers).map(|_| {
+            let app = app.clone();
+            let albums = Arc::clone(&albums);
+            let next = Arc::clone(&next);
+            let control = Arc::clone(&control);
+            let progress = Arc::clone(&progress);
+            std::thread::spawn(move || pregen_worker(app, albums, next, control, progress, max_px, low_priority))
+        }).collect();
+
+        for handle in handles {
+            let _ = handle.join();
+        }
+
+        *PREGEN.lock() = None;
+        control.task.finish(Ok(()));
+        emit_progress(&app, "thumbnail-pregen-complete", &progress, &control);
+        info!("Thumbnail pre-generation finished: {:?}", progress.lock());
+    });
+
+    Ok(task_id)
+}
+
+fn with_running_pregen(apply: impl FnOnce(&PregenControl)) -> Result<(), String> {
+    match PREGEN.lock().as_ref() {
+        Some(control) => {
+            apply(control);
+            Ok(())
+        }
+        None => Err("Thumbnail generation is not running".to_string()),
+    }
+}
+
+#[tauri::command]
+pub fn pause_thumbnail_pregen() -> Result<(), String> {
+    with_running_pregen(|control| control.paused.store(true, Ordering::Relaxed))
+}
+
+#[tauri::command]
+pub fn resume_thumbnail_pregen() -> Result<(), String> {
+    with_running_pregen(|control| control.paused.store(false, Ordering::Relaxed))
+}
+
+#[tauri::command]
+pub fn cancel_thumbnail_pregen() -> Result<(), String> {
+    let id = PREGEN.lock().as_ref()
+        .map(|control| control.task.id())
+        .ok_or_else(|| "Thumbnail generation is not running".to_string())?;
+    cancel_task(id)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::{temp_dir, write_fixture, FixtureTags, Format};
+
+    #[test]
+    fn thumbnails_are_cached_until_cleared() {
+        let dir = temp_dir();
+        let track = dir.path().join("art.flac");
+        write_fixture(&track, Format::Flac, &FixtureTags { with_art: true, ..FixtureTags::titled("Art") });
+        let bare = dir.path().join("bare.flac");
+        write_fixture(&bare, Format::Flac, &FixtureTags::titled("Bare"));
+        let track = track.to_string_lossy().to_string();
+
+        let thumbnail = get_album_art_thumbnail(track.clone(), Some(4)).unwrap().unwrap();
+        let image = image::load_from_memory(&BASE64.decode(&thumbnail).unwrap()).unwrap();
+        assert_eq!((image.width(), image.height()), (4, 4));
+        assert!(matches!(ensure_thumbnail(Path::new(&track), 4), Ok(ThumbnailOutcome::Cached(_))));
+        assert_eq!(get_album_art_thumbnail(bare.to_string_lossy().to_string(), Some(4)).unwrap(), None);
+        assert!(get_album_art_thumbnail(track.clone(), Some(0)).is_err());
+
+        assert_eq!(clear_art_cache().unwrap().entries, 0);
+        assert!(matches!(ensure_thumbnail(Path::new(&track), 4), Ok(ThumbnailOutcome::Generated(_))));
+    }
+}
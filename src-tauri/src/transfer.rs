@@ -1,6 +1,6 @@
 use serde::{Deserialize, Serialize};
 use std::fs::{self, File};
-use std::path::{Path, PathBuf};
+use std::path::{Component, Path, PathBuf};
 use std::io::{self, Read};
 use sha2::{Sha256, Digest};
 use tar::Builder;
@@ -8,7 +8,14 @@ use log::info;
 use flate2::write::GzEncoder;
 use flate2::read::GzDecoder;
 use flate2::Compression;
-use tauri::{AppHandle, Emitter, Manager};
+use tauri::{AppHandle, Emitter, Manager, Runtime};
+use crate::cache::{self, CacheKind};
+use crate::file_count::{list_counted, CountFilter};
+use crate::library::modified_secs;
+use crate::power;
+use crate::tasks::{start_task, TaskHandle, TaskKind};
+use crate::walker::{emit_skipped, SkippedPath};
+use crate::sync_history::record_transfer;
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct FileChecksum {
@@ -29,6 +36,9 @@ pub struct TransferOptions {
     pub target_path: String,
     pub create_archive: bool,
     pub verify_transfer: bool,
+    // Paths relative to `source_path` to copy instead of the whole folder, e.g. from a device diff
+    #[serde(default)]
+    pub files: Option<Vec<String>>,
 }
 
 #[derive(Debug, Serialize, Clone)]
@@ -65,33 +75,61 @@ fn calculate_file_checksum(path: &Path) -> io::Result<String> {
     Ok(format!("{:x}", hasher.finalize()))
 }
 
-fn visit_dirs(dir: &Path, cb: &mut dyn FnMut(&Path)) -> io::Result<()> {
-    if dir.is_dir() {
-        for entry in fs::read_dir(dir)? {
-            let entry = entry?;
-            let path = entry.path();
-            if path.is_dir() {
-                visit_dirs(&path, cb)?;
-            } else {
-                cb(&path);
-            }
+/// Checksum of `path`, reusing the checksum cache while its size and mtime are unchanged.
+pub fn cached_file_checksum(path: &Path) -> io::Result<String> {
+    let metadata = fs::metadata(path)?;
+    let modified = modified_secs(&metadata);
+    let key = cache::cache_key(&[&path.to_string_lossy(), &modified.to_string(), &metadata.len().to_string()]);
+
+    if let Some(entry) = cache::entry_path(CacheKind::Checksums, &key) {
+        if let Ok(checksum) = fs::read_to_string(&entry) {
+            cache::mark_used(&entry);
+            return Ok(checksum);
         }
     }
-    Ok(())
+
+    let checksum = calculate_file_checksum(path)?;
+    let _ = cache::write_entry(CacheKind::Checksums, &key, checksum.as_bytes());
+    Ok(checksum)
 }
 
-fn create_archive(source_path: &Path, archive_path: &Path) -> io::Result<()> {
+/// The files a transfer covers and their total size: `files` (relative to
+/// `source`) when given, otherwise everything under `source` that the user's
+/// exclusion rules don't hide. Also returns the subfolders that couldn't be read.
+fn list_transfer_files<R: Runtime>(source: &Path, files: Option<&[String]>, task: &TaskHandle<R>) -> Result<(Vec<PathBuf>, u64, Vec<SkippedPath>), String> {
+    if let Some(files) = files {
+        let paths: Vec<PathBuf> = files.iter()
+            // Guard against entries that climb out of the source folder
+            .filter(|relative| !Path::new(relative).components().any(|c| matches!(c, Component::ParentDir)))
+            .map(|relative| source.join(relative))
+            .filter(|path| path.is_file())
+            .collect();
+        let total_size = paths.iter().filter_map(|path| fs::metadata(path).ok()).map(|metadata| metadata.len()).sum();
+        return Ok((paths, total_size, Vec::new()));
+    }
+    if !source.is_dir() {
+        return Ok((Vec::new(), 0, Vec::new()));
+    }
+
+    // Surface an unreadable source up front; unreadable subfolders are skipped
+    fs::read_dir(source).map_err(|e| format!("Failed to walk directory: {}", e))?;
+    let filter = CountFilter { all_files: true, ..Default::default() };
+    let (paths, count, skipped) = list_counted(source, &filter, &|| task.is_cancelled(), &mut |running| {
+        task.set_message(format!("Listing files: {} found", running.file_count));
+    }).ok_or_else(|| "Cancelled".to_string())?;
+    Ok((paths.into_iter().map(|file| file.path).collect(), count.total_bytes, skipped))
+}
+
+fn create_archive(source_path: &Path, files: &[PathBuf], archive_path: &Path) -> io::Result<()> {
     let archive_file = File::create(archive_path)?;
     let encoder = GzEncoder::new(archive_file, Compression::default());
     let mut archive = Builder::new(encoder);
 
-    visit_dirs(source_path, &mut |path| {
-        if path.is_file() {
-            if let Ok(relative_path) = path.strip_prefix(source_path) {
-                let _ = archive.append_path_with_name(path, relative_path);
-            }
+    for path in files {
+        if let Ok(relative_path) = path.strip_prefix(source_path) {
+            let _ = archive.append_path_with_name(path, relative_path);
         }
-    })?;
+    }
 
     archive.finish()?;
     Ok(())
@@ -106,52 +144,51 @@ fn extract_archive(archive_path: &Path, target_path: &Path) -> io::Result<()> {
     Ok(())
 }
 
-#[tauri::command]
-pub async fn calculate_directory_checksum(path: String) -> Result<TransferManifest, String> {
-    let source_path = Path::new(&path);
-    if !source_path.exists() {
-        return Err("Source path does not exist".to_string());
-    }
-
+fn build_manifest<R: Runtime>(source_path: &Path, files: &[PathBuf], bytes_total: u64, task: &TaskHandle<R>) -> Result<TransferManifest, String> {
     let mut manifest = TransferManifest {
         checksums: Vec::new(),
         total_size: 0,
         file_count: 0,
     };
 
-    visit_dirs(source_path, &mut |path| {
-        if path.is_file() {
-            if let Ok(checksum) = calculate_file_checksum(path) {
-                if let Ok(metadata) = fs::metadata(path) {
-                    manifest.total_size += metadata.len();
-                    manifest.file_count += 1;
-
-                    if let Ok(relative_path) = path.strip_prefix(source_path) {
-                        manifest.checksums.push(FileChecksum {
-                            path: relative_path.to_string_lossy().into_owned(),
-                            checksum,
-                        });
-                    }
+    for (index, path) in files.iter().enumerate() {
+        if task.is_cancelled() {
+            return Err("Checksum calculation cancelled".to_string());
+        }
+
+        if let Ok(checksum) = calculate_file_checksum(path) {
+            if let Ok(metadata) = fs::metadata(path) {
+                manifest.total_size += metadata.len();
+                manifest.file_count += 1;
+
+                if let Ok(relative_path) = path.strip_prefix(source_path) {
+                    manifest.checksums.push(FileChecksum {
+                        path: relative_path.to_string_lossy().into_owned(),
+                        checksum,
+                    });
                 }
             }
         }
-    }).map_err(|e| format!("Failed to walk directory: {}", e))?;
+
+        task.set_progress(index as u64 + 1, files.len() as u64);
+        task.set_bytes(manifest.total_size, bytes_total);
+    }
 
     Ok(manifest)
 }
 
-#[tauri::command]
-pub async fn verify_transfer(path: String, original_manifest: TransferManifest) -> Result<TransferResult, String> {
-    let target_path = Path::new(&path);
-    if !target_path.exists() {
-        return Err("Target path does not exist".to_string());
-    }
-
+fn verify_against_manifest<R: Runtime>(target_path: &Path, original_manifest: &TransferManifest, task: &TaskHandle<R>) -> Result<TransferResult, String> {
     let mut mismatches = Vec::new();
     let mut verified_size = 0;
     let mut verified_files = 0;
+    let total = original_manifest.checksums.len() as u64;
+
+    for (index, original_file) in original_manifest.checksums.iter().enumerate() {
+        if task.is_cancelled() {
+            return Err("Verification cancelled".to_string());
+        }
+        task.set_progress(index as u64, total);
 
-    for original_file in &original_manifest.checksums {
         let target_file_path = target_path.join(&original_file.path);
         if !target_file_path.exists() {
             mismatches.push(format!("Missing file: {}", original_file.path));
@@ -167,6 +204,7 @@ pub async fn verify_transfer(path: String, original_manifest: TransferManifest)
             verified_size += metadata.len();
             verified_files += 1;
         }
+        task.set_bytes(verified_size, original_manifest.total_size);
     }
 
     Ok(TransferResult {
@@ -181,12 +219,99 @@ pub async fn verify_transfer(path: String, original_manifest: TransferManifest)
     })
 }
 
+/// Copies `source` to `target`. If the copy fails because the machine slept or
+/// the target volume went away, the transfer pauses until `target_root` is
+/// reachable again and retries, instead of failing every remaining file.
+fn copy_resilient(app: &AppHandle, task: &TaskHandle, source: &Path, target: &Path, target_root: &Path, epoch: &mut u64) -> io::Result<u64> {
+    let error = match fs::copy(source, target) {
+        Ok(bytes) => return Ok(bytes),
+        Err(e) => e,
+    };
+    if power::resume_epoch() == *epoch && target_root.exists() {
+        return Err(error);
+    }
+
+    info!("Transfer paused waiting for {} after: {}", target_root.display(), error);
+    app.emit("transfer-progress", TransferProgress {
+        status: "Paused: waiting for the target to become available".into(),
+        current_file: Some(source.to_string_lossy().to_string()),
+        processed_files: 0,
+        total_files: 0,
+        processed_size: 0,
+        total_size: 0,
+    }).ok();
+    task.set_message(format!("Paused: waiting for {}", target_root.display()));
+    if !power::wait_for_path(target_root, task) {
+        return Err(error);
+    }
+
+    *epoch = power::resume_epoch();
+    task.set_message("Resumed");
+    if let Some(parent) = target.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    fs::copy(source, target)
+}
+
+/// Runs `work` as a registered background task and records its outcome.
+fn run_as_task<T>(task: &TaskHandle, work: impl FnOnce(&TaskHandle) -> Result<T, String>) -> Result<T, String> {
+    let result = work(task);
+    task.finish(result.as_ref().map(|_| ()).map_err(|e| e.clone()));
+    result
+}
+
+#[tauri::command]
+pub async fn calculate_directory_checksum(app: AppHandle, path: String) -> Result<TransferManifest, String> {
+    let source_path = Path::new(&path);
+    if !source_path.exists() {
+        return Err("Source path does not exist".to_string());
+    }
+
+    let task = start_task(&app, TaskKind::Checksum, format!("Checksumming {}", path));
+    run_as_task(&task, |task| {
+        let (files, total_size, skipped) = list_transfer_files(source_path, None, task)?;
+        emit_skipped(&app, "calculate_directory_checksum", source_path, skipped);
+        build_manifest(source_path, &files, total_size, task)
+    })
+}
+
+#[tauri::command]
+pub async fn verify_transfer(app: AppHandle, path: String, original_manifest: TransferManifest) -> Result<TransferResult, String> {
+    let target_path = Path::new(&path);
+    if !target_path.exists() {
+        return Err("Target path does not exist".to_string());
+    }
+
+    let task = start_task(&app, TaskKind::Checksum, format!("Verifying {}", path));
+    run_as_task(&task, |task| verify_against_manifest(target_path, &original_manifest, task))
+}
+
 #[tauri::command]
 pub async fn transfer_files(app: AppHandle, options: TransferOptions) -> Result<TransferResult, String> {
+    let task = start_task(
+        &app,
+        TaskKind::Transfer,
+        format!("Transferring {} to {}", options.source_path, options.target_path),
+    );
+    run_as_task(&task, |task| {
+        let result = run_transfer(&app, &options, task)?;
+        task.set_count("files", result.transferred_files as u64);
+        task.set_count("bytes", result.total_size);
+        task.set_count("verified", (options.verify_transfer && result.success) as u64);
+        Ok(result)
+    })
+}
+
+fn run_transfer(app: &AppHandle, options: &TransferOptions, task: &TaskHandle) -> Result<TransferResult, String> {
     let source_path = PathBuf::from(&options.source_path);
     let target_path = PathBuf::from(&options.target_path);
     let temp_dir = std::env::temp_dir();
     let archive_path = temp_dir.join("transfer.tar.gz");
+    let mut epoch = power::resume_epoch();
+
+    let (files, total_size, skipped) = list_transfer_files(&source_path, options.files.as_deref(), task)?;
+    emit_skipped(app, "transfer_files", &source_path, skipped);
+    let total_files = files.len();
 
     // Step 1: Calculate initial checksums if verification is requested
     let manifest = if options.verify_transfer {
@@ -194,19 +319,17 @@ pub async fn transfer_files(app: AppHandle, options: TransferOptions) -> Result<
             status: "Calculating checksums...".into(),
             current_file: None,
             processed_files: 0,
-            total_files: 0,
+            total_files,
             processed_size: 0,
-            total_size: 0,
+            total_size,
         }).ok();
-        
-        Some(calculate_directory_checksum(options.source_path.clone()).await?)
+        task.set_message("Calculating checksums...");
+
+        Some(build_manifest(&source_path, &files, total_size, task)?)
     } else {
         None
     };
 
-    let total_files = manifest.as_ref().map(|m| m.file_count).unwrap_or(0);
-    let total_size = manifest.as_ref().map(|m| m.total_size).unwrap_or(0);
-
     // Step 2: Create and transfer files
     if options.create_archive {
         // Archive method
@@ -218,9 +341,14 @@ pub async fn transfer_files(app: AppHandle, options: TransferOptions) -> Result<
             processed_size: 0,
             total_size,
         }).ok();
+        task.set_message("Creating archive...");
 
-        create_archive(&source_path, &archive_path)
+        create_archive(&source_path, &files, &archive_path)
             .map_err(|e| format!("Failed to create archive: {}", e))?;
+        if task.is_cancelled() {
+            let _ = fs::remove_file(&archive_path);
+            return Err("Transfer cancelled".to_string());
+        }
 
         info!("Transferring archive... {} {} {} {} {}", total_files, total_size, archive_path.to_string_lossy(), target_path.to_string_lossy(), options.target_path);
         app.emit("transfer-progress", TransferProgress {
@@ -231,8 +359,10 @@ pub async fn transfer_files(app: AppHandle, options: TransferOptions) -> Result<
             processed_size: total_size / 2,
             total_size,
         }).ok();
+        task.set_message("Transferring archive...");
+        task.set_bytes(total_size / 2, total_size);
 
-        fs::copy(&archive_path, target_path.join("transfer.tar.gz"))
+        copy_resilient(app, task, &archive_path, &target_path.join("transfer.tar.gz"), &target_path, &mut epoch)
             .map_err(|e| format!("Failed to transfer archive: {}", e))?;
 
         app.emit("transfer-progress", TransferProgress {
@@ -243,6 +373,8 @@ pub async fn transfer_files(app: AppHandle, options: TransferOptions) -> Result<
             processed_size: total_size * 3 / 4,
             total_size,
         }).ok();
+        task.set_message("Extracting archive...");
+        task.set_bytes(total_size * 3 / 4, total_size);
 
         extract_archive(
             &target_path.join("transfer.tar.gz"),
@@ -256,35 +388,42 @@ pub async fn transfer_files(app: AppHandle, options: TransferOptions) -> Result<
         // Direct copy method
         let mut copied_files = 0;
         let mut total_copied_size = 0;
+        task.set_message("Copying files...");
 
-        visit_dirs(&source_path, &mut |path| {
-            if path.is_file() {
-                if let Ok(relative_path) = path.strip_prefix(&source_path) {
-                    let target_file = target_path.join(relative_path);
-                    
-                    app.emit("transfer-progress", TransferProgress {
-                        status: "Copying files...".into(),
-                        current_file: Some(relative_path.to_string_lossy().to_string()),
-                        processed_files: copied_files,
-                        total_files,
-                        processed_size: total_copied_size,
-                        total_size,
-                    }).ok();
-
-                    if let Some(parent) = target_file.parent() {
-                        let _ = fs::create_dir_all(parent);
-                    }
+        for path in &files {
+            if task.is_cancelled() {
+                break;
+            }
+            if let Ok(relative_path) = path.strip_prefix(&source_path) {
+                let target_file = target_path.join(relative_path);
+                
+                app.emit("transfer-progress", TransferProgress {
+                    status: "Copying files...".into(),
+                    current_file: Some(relative_path.to_string_lossy().to_string()),
+                    processed_files: copied_files,
+                    total_files,
+                    processed_size: total_copied_size,
+                    total_size,
+                }).ok();
+                task.set_progress(copied_files as u64, total_files as u64);
+                task.set_bytes(total_copied_size, total_size);
+
+                if let Some(parent) = target_file.parent() {
+                    let _ = fs::create_dir_all(parent);
+                }
 
-                    if let Ok(metadata) = fs::metadata(path) {
-                        if fs::copy(path, target_file).is_ok() {
-                            copied_files += 1;
-                            total_copied_size += metadata.len();
-                        }
+                if let Ok(metadata) = fs::metadata(path) {
+                    if copy_resilient(app, task, path, &target_file, &target_path, &mut epoch).is_ok() {
+                        copied_files += 1;
+                        total_copied_size += metadata.len();
                     }
                 }
             }
-        }).map_err(|e| format!("Failed to copy files: {}", e))?;
+        }
 
+        if task.is_cancelled() {
+            return Err("Transfer cancelled".to_string());
+        }
         if copied_files == 0 {
             return Err("No files were copied".to_string());
         }
@@ -305,7 +444,13 @@ pub async fn transfer_files(app: AppHandle, options: TransferOptions) -> Result<
         if let Some(manifest) = manifest {
             let file_count = manifest.file_count;
             let total_size = manifest.total_size;
-            return verify_transfer(options.target_path, manifest).await.map(|mut result| {
+            task.set_message("Verifying transfer...");
+            let result = verify_against_manifest(&target_path, &manifest, task);
+            if result.as_ref().is_ok_and(|result| result.success) {
+                // Device lookup blocks on the async runtime, so it can't run on this task's thread
+                std::thread::spawn(move || record_transfer(&source_path, &target_path, &manifest));
+            }
+            return result.map(|mut result| {
                 if result.transferred_files == 0 {
                     result.transferred_files = file_count;
                     result.total_size = total_size;
@@ -321,4 +466,84 @@ pub async fn transfer_files(app: AppHandle, options: TransferOptions) -> Result<
         transferred_files: manifest.clone().map_or(0, |m| m.file_count),
         total_size: manifest.clone().map_or(0, |m| m.total_size),
     })
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tauri::test::{mock_app, MockRuntime};
+    use crate::test_support::{build_library, temp_dir, FixtureTags, TrackSpec};
+
+    fn copy_tree(from: &Path, to: &Path) {
+        for entry in fs::read_dir(from).unwrap() {
+            let path = entry.unwrap().path();
+            let target = to.join(path.file_name().unwrap());
+            if path.is_dir() {
+                fs::create_dir_all(&target).unwrap();
+                copy_tree(&path, &target);
+            } else {
+                fs::copy(&path, &target).unwrap();
+            }
+        }
+    }
+
+    fn checksum_task() -> TaskHandle<MockRuntime> {
+        start_task(mock_app().handle(), TaskKind::Checksum, "test")
+    }
+
+    fn manifest_of(root: &Path, task: &TaskHandle<MockRuntime>) -> TransferManifest {
+        let (files, total_size, _) = list_transfer_files(root, None, task).unwrap();
+        build_manifest(root, &files, total_size, task).unwrap()
+    }
+
+    fn library(root: &Path) {
+        build_library(root, &[
+            TrackSpec::new("Artist/Album/01 - One.flac", FixtureTags::titled("One")),
+            TrackSpec::new("Artist/Album/02 - Two.mp3", FixtureTags::titled("Two")),
+            TrackSpec::new("Loose.wav", FixtureTags::default()),
+        ]);
+    }
+
+    #[test]
+    fn verify_accepts_an_exact_copy() {
+        let source = temp_dir();
+        let target = temp_dir();
+        library(source.path());
+        copy_tree(source.path(), target.path());
+
+        let task = checksum_task();
+        let manifest = manifest_of(source.path(), &task);
+        assert_eq!(manifest.file_count, 3);
+
+        let result = verify_against_manifest(target.path(), &manifest, &task).unwrap();
+        assert!(result.success, "{}", result.message);
+        assert_eq!(result.transferred_files, manifest.file_count);
+        assert_eq!(result.total_size, manifest.total_size);
+
+        let copy_manifest = manifest_of(target.path(), &task);
+        let mut original: Vec<_> = manifest.checksums.iter().map(|f| (&f.path, &f.checksum)).collect();
+        let mut copied: Vec<_> = copy_manifest.checksums.iter().map(|f| (&f.path, &f.checksum)).collect();
+        original.sort();
+        copied.sort();
+        assert_eq!(original, copied);
+    }
+
+    #[test]
+    fn verify_reports_changed_and_missing_files() {
+        let source = temp_dir();
+        let target = temp_dir();
+        library(source.path());
+        copy_tree(source.path(), target.path());
+
+        let task = checksum_task();
+        let manifest = manifest_of(source.path(), &task);
+        fs::write(target.path().join("Loose.wav"), b"truncated").unwrap();
+        fs::remove_file(target.path().join("Artist/Album/02 - Two.mp3")).unwrap();
+
+        let result = verify_against_manifest(target.path(), &manifest, &task).unwrap();
+        assert!(!result.success);
+        assert_eq!(result.transferred_files, 1);
+        assert!(result.message.contains("Checksum mismatch for: Loose.wav"), "{}", result.message);
+        assert!(result.message.contains("Missing file"), "{}", result.message);
+    }
+}
@@ -0,0 +1,279 @@
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+use std::fs;
+use std::path::{Path, PathBuf};
+use once_cell::sync::Lazy;
+use parking_lot::Mutex;
+use tauri::State;
+use log::{info, error};
+use crate::config::get_config_dir;
+use crate::library::{library_root_of, now_secs, remove_tracks, set_staged};
+use crate::playlist::{playlists_referencing, PlaylistReference};
+use crate::PlayerHandle;
+
+pub const TRASH_DIR: &str = ".musicmanager-trash";
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct StagedRemoval {
+    pub original: String,
+    pub staged: String,
+    pub size: u64,
+    pub staged_at: u64,
+}
+
+#[derive(Debug, Serialize, Deserialize, Default)]
+struct TrashManifest {
+    entries: Vec<StagedRemoval>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct FailedPath {
+    pub path: String,
+    pub error: String,
+}
+
+#[derive(Debug, Serialize)]
+pub struct StageResult {
+    pub staged: Vec<StagedRemoval>,
+    pub failed: Vec<FailedPath>,
+    // Stored playlists that now point at staged files
+    pub playlist_warnings: Vec<PlaylistReference>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct StagedRemovals {
+    pub entries: Vec<StagedRemoval>,
+    pub reclaimable_bytes: u64,
+}
+
+#[derive(Debug, Serialize)]
+pub struct RestoreResult {
+    pub restored: Vec<String>,
+    pub failed: Vec<FailedPath>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct PurgeResult {
+    pub purged: usize,
+    pub freed_bytes: u64,
+    pub failed: Vec<FailedPath>,
+}
+
+static TRASH_LOCK: Lazy<Mutex<()>> = Lazy::new(|| Mutex::new(()));
+
+fn manifest_path() -> Option<PathBuf> {
+    get_config_dir().map(|dir| dir.join("staged_removals.json"))
+}
+
+fn load_manifest() -> TrashManifest {
+    manifest_path()
+        .and_then(|path| fs::read_to_string(path).ok())
+        .and_then(|contents| serde_json::from_str(&contents).ok())
+        .unwrap_or_default()
+}
+
+fn save_manifest(manifest: &TrashManifest) -> Result<(), String> {
+    let path = manifest_path().ok_or("Could not determine config directory")?;
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent).map_err(|e| e.to_string())?;
+    }
+    let temp_path = path.with_extension("json.tmp");
+    let json = serde_json::to_string_pretty(manifest).map_err(|e| e.to_string())?;
+    fs::write(&temp_path, json).map_err(|e| e.to_string())?;
+    fs::rename(&temp_path, &path).map_err(|e| e.to_string())
+}
+
+fn failed(path: &str, error: impl Into<String>) -> FailedPath {
+    FailedPath {
+        path: path.to_string(),
+        error: error.into(),
+    }
+}
+
+/// The trash folder for `root`, created with a `.nomedia` marker so scans and listings skip it.
+fn trash_dir(root: &Path) -> Result<PathBuf, String> {
+    let dir = root.join(TRASH_DIR);
+    fs::create_dir_all(&dir).map_err(|e| format!("Failed to create {}: {}", dir.display(), e))?;
+    let marker = dir.join(".nomedia");
+    if !marker.exists() {
+        fs::write(&marker, b"").map_err(|e| e.to_string())?;
+    }
+    Ok(dir)
+}
+
+fn move_file(from: &Path, to: &Path) -> Result<(), String> {
+    if to.exists() {
+        return Err(format!("{} already exists", to.display()));
+    }
+    if let Some(parent) = to.parent() {
+        fs::create_dir_all(parent).map_err(|e| format!("Failed to create {}: {}", parent.display(), e))?;
+    }
+    fs::rename(from, to).map_err(|e| format!("Failed to move {} to {}: {}", from.display(), to.display(), e))
+}
+
+/// Removes folders left empty inside the trash, stopping at the trash folder itself.
+fn prune_empty_dirs(staged: &Path) {
+    for dir in staged.ancestors().skip(1) {
+        if dir.file_name().is_some_and(|name| name == TRASH_DIR) || fs::remove_dir(dir).is_err() {
+            break;
+        }
+    }
+}
+
+fn stage_file(player: &PlayerHandle, path: &str) -> Result<StagedRemoval, String> {
+    let source = Path::new(path);
+    let metadata = fs::metadata(source).map_err(|e| format!("Cannot read {}: {}", path, e))?;
+    if !metadata.is_file() {
+        return Err(format!("{} is not a file", path));
+    }
+    if player.with(|player| player.current_path.clone()).as_deref() == Some(path) {
+        return Err(format!("{} is currently playing", path));
+    }
+
+    let root = library_root_of(source).ok_or_else(|| format!("{} is not inside a library root", path))?;
+    let relative = source.strip_prefix(&root).map_err(|e| e.to_string())?;
+    if relative.starts_with(TRASH_DIR) {
+        return Err(format!("{} is already staged", path));
+    }
+
+    // A second file staged from the same place gets a timestamped name instead of overwriting the first
+    let mut staged = trash_dir(&root)?.join(relative);
+    if staged.exists() {
+        let name = staged.file_name().map(|n| n.to_string_lossy().to_string()).unwrap_or_default();
+        staged.set_file_name(format!("{}.{}", now_secs(), name));
+    }
+    move_file(source, &staged)?;
+
+    Ok(StagedRemoval {
+        original: path.to_string(),
+        staged: staged.to_string_lossy().to_string(),
+        size: metadata.len(),
+        staged_at: now_secs(),
+    })
+}
+
+/// Moves files into `.musicmanager-trash` at their library root, keeping their
+/// relative layout, so a bulk cleanup can be reviewed before anything is deleted.
+#[tauri::command]
+pub fn stage_for_removal(player: State<'_, PlayerHandle>, paths: Vec<String>) -> Result<StageResult, String> {
+    let _guard = TRASH_LOCK.lock();
+    let mut manifest = load_manifest();
+    let mut result = StageResult {
+        staged: Vec::new(),
+        failed: Vec::new(),
+        playlist_warnings: Vec::new(),
+    };
+
+    for path in &paths {
+        match stage_file(&player, path) {
+            Ok(removal) => result.staged.push(removal),
+            Err(e) => result.failed.push(failed(path, e)),
+        }
+    }
+    if result.staged.is_empty() {
+        return Ok(result);
+    }
+
+    manifest.entries.extend(result.staged.iter().cloned());
+    save_manifest(&manifest)?;
+
+    let originals: Vec<String> = result.staged.iter().map(|removal| removal.original.clone()).collect();
+    set_staged(&originals, true);
+    result.playlist_warnings = playlists_referencing(&originals.into_iter().collect());
+    info!("Staged {} files for removal", result.staged.len());
+    Ok(result)
+}
+
+#[tauri::command]
+pub fn list_staged_removals() -> StagedRemovals {
+    let entries = load_manifest().entries;
+    StagedRemovals {
+        reclaimable_bytes: entries.iter().map(|removal| removal.size).sum(),
+        entries,
+    }
+}
+
+/// Moves staged files back to where they came from. `paths` are original paths.
+#[tauri::command]
+pub fn restore_staged(paths: Vec<String>) -> Result<RestoreResult, String> {
+    let _guard = TRASH_LOCK.lock();
+    let mut manifest = load_manifest();
+    let wanted: HashSet<&String> = paths.iter().collect();
+    let mut result = RestoreResult {
+        restored: Vec::new(),
+        failed: Vec::new(),
+    };
+
+    manifest.entries.retain(|removal| {
+        if !wanted.contains(&removal.original) {
+            return true;
+        }
+        let staged = Path::new(&removal.staged);
+        match move_file(staged, Path::new(&removal.original)) {
+            Ok(()) => {
+                prune_empty_dirs(staged);
+                result.restored.push(removal.original.clone());
+                false
+            }
+            Err(e) => {
+                result.failed.push(failed(&removal.original, e));
+                true
+            }
+        }
+    });
+    for path in &paths {
+        if !result.restored.contains(path) && !result.failed.iter().any(|f| &f.path == path) {
+            result.failed.push(failed(path, "Not staged for removal"));
+        }
+    }
+
+    save_manifest(&manifest)?;
+    set_staged(&result.restored, false);
+    Ok(result)
+}
+
+/// Permanently deletes staged files that have been in the trash for at least `older_than_days`.
+#[tauri::command]
+pub fn purge_staged(older_than_days: u64) -> Result<PurgeResult, String> {
+    let _guard = TRASH_LOCK.lock();
+    let mut manifest = load_manifest();
+    let cutoff = now_secs().saturating_sub(older_than_days * 24 * 60 * 60);
+    let mut purged = Vec::new();
+    let mut result = PurgeResult {
+        purged: 0,
+        freed_bytes: 0,
+        failed: Vec::new(),
+    };
+
+    manifest.entries.retain(|removal| {
+        if removal.staged_at > cutoff {
+            return true;
+        }
+        let staged = Path::new(&removal.staged);
+        match fs::remove_file(staged) {
+            Ok(()) => {}
+            // Already gone, e.g. deleted by hand; nothing left to purge
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+            Err(e) => {
+                error!("Failed to purge {}: {}", removal.staged, e);
+                result.failed.push(failed(&removal.original, e.to_string()));
+                return true;
+            }
+        }
+        prune_empty_dirs(staged);
+        result.freed_bytes += removal.size;
+        purged.push(removal.original.clone());
+        false
+    });
+
+    save_manifest(&manifest)?;
+    remove_tracks(&purged);
+    result.purged = purged.len();
+    info!("Purged {} staged files ({} bytes)", result.purged, result.freed_bytes);
+    Ok(result)
+}
+
+/// Original locations of every file waiting in the trash.
+pub fn staged_paths() -> HashSet<String> {
+    load_manifest().entries.into_iter().map(|removal| removal.original).collect()
+}
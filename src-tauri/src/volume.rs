@@ -0,0 +1,111 @@
+use std::time::{Duration, Instant};
+use once_cell::sync::Lazy;
+use parking_lot::Mutex;
+use log::error;
+use crate::commands::apply_gain;
+use tauri::State;
+use crate::config::{load_player_config, update_player_config, PlaybackSettings, VolumeScale};
+use crate::PlayerHandle;
+
+// Slider drags send a stream of changes; the config is written once they settle
+const SAVE_DELAY: Duration = Duration::from_millis(500);
+
+// When volume or speed last changed, while a save is waiting to run
+static LAST_CHANGE: Lazy<Mutex<Option<Instant>>> = Lazy::new(|| Mutex::new(None));
+
+// Quietest level on the logarithmic scale; anything at or below it is silence
+pub const MIN_DB: f32 = -60.0;
+
+pub fn db_to_gain(db: f32) -> f32 {
+    if db <= MIN_DB {
+        0.0
+    } else {
+        10f32.powf(db.min(0.0) / 20.0)
+    }
+}
+
+pub fn gain_to_db(gain: f32) -> f32 {
+    if gain <= 0.0 {
+        MIN_DB
+    } else {
+        (20.0 * gain.min(1.0).log10()).max(MIN_DB)
+    }
+}
+
+/// Converts a 0.0-1.0 slider position to linear gain.
+pub fn slider_to_gain(position: f32, scale: VolumeScale) -> f32 {
+    let position = position.clamp(0.0, 1.0);
+    match scale {
+        VolumeScale::Linear => position,
+        VolumeScale::Logarithmic => db_to_gain(MIN_DB * (1.0 - position)),
+    }
+}
+
+pub fn gain_to_slider(gain: f32, scale: VolumeScale) -> f32 {
+    match scale {
+        VolumeScale::Linear => gain.clamp(0.0, 1.0),
+        VolumeScale::Logarithmic if gain <= 0.0 => 0.0,
+        VolumeScale::Logarithmic => 1.0 - gain_to_db(gain) / MIN_DB,
+    }
+}
+
+/// Gain for the volume stored in the config, whichever scale it was saved in.
+pub fn persisted_gain(settings: &PlaybackSettings) -> f32 {
+    slider_to_gain(settings.volume, settings.volume_saved_scale)
+}
+
+/// Stores `gain` in the config using the current slider scale, tagged with that scale.
+pub fn persist_gain(settings: &mut PlaybackSettings, gain: f32) {
+    settings.volume = gain_to_slider(gain, settings.volume_scale);
+    settings.volume_saved_scale = settings.volume_scale;
+}
+
+/// Saves the current volume and speed once they have stopped changing for a
+/// moment, rather than on every step of a slider drag.
+pub fn save_playback_levels_soon(player: &PlayerHandle) {
+    {
+        let mut last_change = LAST_CHANGE.lock();
+        let scheduled = last_change.is_some();
+        *last_change = Some(Instant::now());
+        if scheduled {
+            return;
+        }
+    }
+
+    let player = player.clone();
+    std::thread::spawn(move || loop {
+        std::thread::sleep(SAVE_DELAY);
+        {
+            let mut last_change = LAST_CHANGE.lock();
+            if last_change.is_some_and(|at| at.elapsed() < SAVE_DELAY) {
+                continue;
+            }
+            *last_change = None;
+        }
+        let (gain, speed) = player.with(|player| (player.volume, player.speed));
+        let result = update_player_config(|config| {
+            persist_gain(&mut config.playback_settings, gain);
+            config.playback_settings.playback_speed = speed;
+            Ok(())
+        });
+        if let Err(e) = result {
+            error!("Failed to save volume and speed: {}", e);
+        }
+        return;
+    });
+}
+
+/// Current volume as a slider position in the configured scale.
+pub fn current_slider_volume(player: &PlayerHandle) -> f32 {
+    gain_to_slider(player.with(|player| player.volume), load_player_config().playback_settings.volume_scale)
+}
+
+#[tauri::command]
+pub fn set_volume_db(player: State<'_, PlayerHandle>, db: f32) -> Result<(), String> {
+    apply_gain(&player, db_to_gain(db))
+}
+
+#[tauri::command]
+pub fn get_volume_db(player: State<'_, PlayerHandle>) -> f32 {
+    gain_to_db(player.with(|player| player.volume))
+}
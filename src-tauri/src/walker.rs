@@ -0,0 +1,253 @@
+use serde::Serialize;
+use std::collections::VecDeque;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use glob::{MatchOptions, Pattern};
+use tauri::{AppHandle, Emitter};
+use log::{error, debug};
+use crate::config::load_player_config;
+use crate::library::is_audio_file;
+
+// Android convention: a folder containing this file is hidden from media scanners
+const NOMEDIA_MARKER: &str = ".nomedia";
+
+const MATCH_OPTIONS: MatchOptions = MatchOptions {
+    case_sensitive: !cfg!(any(target_os = "windows", target_os = "macos")),
+    require_literal_separator: true,
+    require_literal_leading_dot: false,
+};
+
+/// User exclusion globs plus `.nomedia` handling. Patterns without a path
+/// separator (`samples`, `*.tmp`) match any single file or folder name;
+/// patterns with one (`/mnt/music/old/**`) match against the full path.
+#[derive(Default)]
+pub struct Exclusions {
+    name_patterns: Vec<Pattern>,
+    path_patterns: Vec<Pattern>,
+}
+
+impl Exclusions {
+    pub fn from_patterns(patterns: &[String]) -> Self {
+        let mut exclusions = Exclusions::default();
+        for raw in patterns {
+            let raw = raw.trim();
+            if raw.is_empty() {
+                continue;
+            }
+            match Pattern::new(raw) {
+                Ok(pattern) if raw.contains(['/', '\\']) => exclusions.path_patterns.push(pattern),
+                Ok(pattern) => exclusions.name_patterns.push(pattern),
+                Err(e) => error!("Ignoring invalid exclusion pattern {:?}: {}", raw, e),
+            }
+        }
+        exclusions
+    }
+
+    /// The exclusions currently in the player config.
+    pub fn load() -> Self {
+        Self::from_patterns(&load_player_config().exclusions)
+    }
+
+    fn matches(&self, path: &Path) -> bool {
+        let name_matches = path.file_name()
+            .and_then(|name| name.to_str())
+            .is_some_and(|name| self.name_patterns.iter().any(|p| p.matches_with(name, MATCH_OPTIONS)));
+        name_matches || self.path_patterns.iter().any(|p| p.matches_path_with(path, MATCH_OPTIONS))
+    }
+
+    pub fn is_excluded_dir(&self, dir: &Path) -> bool {
+        self.matches(dir) || dir.join(NOMEDIA_MARKER).is_file()
+    }
+
+    pub fn is_excluded_file(&self, file: &Path) -> bool {
+        self.matches(file)
+    }
+
+    /// Checks the file and every folder above it. Walkers prune excluded folders
+    /// as they go; this is for paths that arrive without a walk, such as index entries.
+    pub fn is_path_excluded(&self, path: &Path) -> bool {
+        self.is_excluded_file(path)
+            || path.ancestors().skip(1).any(|dir| !dir.as_os_str().is_empty() && self.is_excluded_dir(dir))
+    }
+}
+
+#[derive(Debug, Serialize, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum AccessErrorKind {
+    PermissionDenied,
+    // macOS privacy protection (TCC): the app needs Full Disk Access for this location
+    FullDiskAccessRequired,
+    Unreadable,
+}
+
+impl AccessErrorKind {
+    pub fn of(error: &io::Error) -> Self {
+        // TCC denials come back as EPERM ("Operation not permitted") rather than EACCES
+        if cfg!(target_os = "macos") && error.raw_os_error() == Some(1) {
+            return AccessErrorKind::FullDiskAccessRequired;
+        }
+        match error.kind() {
+            io::ErrorKind::PermissionDenied => AccessErrorKind::PermissionDenied,
+            _ => AccessErrorKind::Unreadable,
+        }
+    }
+}
+
+/// A folder a walk couldn't read and went past.
+#[derive(Debug, Serialize, Clone)]
+pub struct SkippedPath {
+    pub path: String,
+    pub reason: String,
+    pub kind: AccessErrorKind,
+}
+
+impl SkippedPath {
+    pub fn new(path: &Path, error: &io::Error) -> Self {
+        Self {
+            path: path.to_string_lossy().to_string(),
+            reason: error.to_string(),
+            kind: AccessErrorKind::of(error),
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Clone)]
+pub struct WalkSkipped {
+    pub command: String,
+    pub root: String,
+    pub skipped: Vec<SkippedPath>,
+}
+
+/// Fails with the root's access error when the walk couldn't even start.
+pub fn check_readable(root: &Path) -> Result<(), SkippedPath> {
+    fs::read_dir(root).map(|_| ()).map_err(|e| SkippedPath::new(root, &e))
+}
+
+/// Tells the UI which folders `command` had to skip under `root`, if any.
+pub fn emit_skipped(app: &AppHandle, command: &str, root: &Path, skipped: Vec<SkippedPath>) {
+    if skipped.is_empty() {
+        return;
+    }
+    app.emit("walk-skipped", WalkSkipped {
+        command: command.to_string(),
+        root: root.to_string_lossy().to_string(),
+        skipped,
+    }).ok();
+}
+
+/// Walks `root` breadth-first, skipping excluded folders (and everything
+/// under them) and excluded files. `visit` receives every remaining file.
+pub fn walk_files(root: &Path, recursive: bool, exclusions: &Exclusions, visit: &mut dyn FnMut(&Path)) {
+    walk_files_until(root, recursive, exclusions, &|| false, visit);
+}
+
+/// `walk_files` that checks `stop` before each folder and file and gives up as
+/// soon as it returns true. Returns false if the walk was stopped early.
+pub fn walk_files_until(root: &Path, recursive: bool, exclusions: &Exclusions, stop: &dyn Fn() -> bool, visit: &mut dyn FnMut(&Path)) -> bool {
+    walk_files_reporting(root, recursive, exclusions, stop, visit, &mut Vec::new())
+}
+
+/// `walk_files_until` that also collects the folders it couldn't read into `skipped`.
+pub fn walk_files_reporting(
+    root: &Path,
+    recursive: bool,
+    exclusions: &Exclusions,
+    stop: &dyn Fn() -> bool,
+    visit: &mut dyn FnMut(&Path),
+    skipped: &mut Vec<SkippedPath>,
+) -> bool {
+    let mut to_visit = VecDeque::new();
+    to_visit.push_back(root.to_path_buf());
+
+    while let Some(dir) = to_visit.pop_front() {
+        if stop() {
+            return false;
+        }
+        if dir.join(NOMEDIA_MARKER).is_file() {
+            debug!("Skipping {} ({} present)", dir.display(), NOMEDIA_MARKER);
+            continue;
+        }
+        let entries = match fs::read_dir(&dir) {
+            Ok(entries) => entries,
+            Err(e) => {
+                debug!("Skipping unreadable directory {}: {}", dir.display(), e);
+                skipped.push(SkippedPath::new(&dir, &e));
+                continue;
+            }
+        };
+
+        for entry in entries.flatten() {
+            if stop() {
+                return false;
+            }
+            let path = entry.path();
+            if path.is_dir() {
+                if recursive && !exclusions.matches(&path) {
+                    to_visit.push_back(path);
+                }
+            } else if !exclusions.is_excluded_file(&path) {
+                visit(&path);
+            }
+        }
+    }
+    true
+}
+
+pub fn walk_audio_files(root: &Path, recursive: bool, exclusions: &Exclusions) -> Vec<PathBuf> {
+    let mut files = Vec::new();
+    walk_files(root, recursive, exclusions, &mut |path| {
+        if is_audio_file(path) {
+            files.push(path.to_path_buf());
+        }
+    });
+    files
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::temp_dir;
+
+    #[test]
+    fn classifies_access_errors() {
+        let denied = io::Error::from(io::ErrorKind::PermissionDenied);
+        assert_eq!(AccessErrorKind::of(&denied), AccessErrorKind::PermissionDenied);
+        let missing = io::Error::from(io::ErrorKind::NotFound);
+        assert_eq!(AccessErrorKind::of(&missing), AccessErrorKind::Unreadable);
+        let not_permitted = io::Error::from_raw_os_error(1);
+        let expected = if cfg!(target_os = "macos") {
+            AccessErrorKind::FullDiskAccessRequired
+        } else {
+            AccessErrorKind::PermissionDenied
+        };
+        assert_eq!(AccessErrorKind::of(&not_permitted), expected);
+    }
+
+    #[test]
+    fn unreadable_root_is_reported() {
+        let dir = temp_dir();
+        let missing = dir.path().join("gone");
+        assert!(check_readable(dir.path()).is_ok());
+        let skipped = check_readable(&missing).unwrap_err();
+        assert_eq!(skipped.kind, AccessErrorKind::Unreadable);
+        assert_eq!(skipped.path, missing.to_string_lossy());
+    }
+
+    #[test]
+    fn walk_reports_nothing_skipped_for_readable_tree() {
+        let dir = temp_dir();
+        fs::create_dir_all(dir.path().join("a")).unwrap();
+        fs::write(dir.path().join("a/one.mp3"), b"").unwrap();
+        fs::write(dir.path().join("two.mp3"), b"").unwrap();
+
+        let mut visited = Vec::new();
+        let mut skipped = Vec::new();
+        let completed = walk_files_reporting(dir.path(), true, &Exclusions::default(), &|| false, &mut |path| {
+            visited.push(path.to_path_buf());
+        }, &mut skipped);
+        assert!(completed);
+        assert_eq!(visited.len(), 2);
+        assert!(skipped.is_empty());
+    }
+}
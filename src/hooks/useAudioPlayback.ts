@@ -1,6 +1,7 @@
 import { useCallback } from 'react';
 import { invoke } from '@tauri-apps/api/core';
 import { FileItem } from '../types/music';
+import { requestErrorMessage } from '../types/fileBrowser';
 import path from 'path-browserify';
 
 interface UseAudioPlaybackProps {
@@ -45,7 +46,10 @@ export function useAudioPlayback({ playTrack }: UseAudioPlaybackProps) {
         playTrack(sortedFiles[0], sortedFiles);
       }
     } catch (err) {
-      console.error('Error playing folder:', err);
+      const message = requestErrorMessage(err);
+      if (message !== null) {
+        console.error('Error playing folder:', message);
+      }
     }
   }, [playTrack]);
 
@@ -1,7 +1,7 @@
 import { useState, useCallback, useEffect } from 'react';
 import { invoke } from '@tauri-apps/api/core';
 import { FileItem } from '../types/music';
-import { SortOption } from '../types/fileBrowser';
+import { SortOption, requestErrorMessage } from '../types/fileBrowser';
 
 export function useFileNavigation() {
   const [currentPath, setCurrentPath] = useState<string>('');
@@ -23,7 +23,8 @@ export function useFileNavigation() {
     } else {
       const metadata = await invoke<any[]>('get_metadata_for_directory', {
         path: dirPath,
-        sortBy: sortOption === 'title' ? 'Title' : 'TrackNumber'
+        sortBy: sortOption === 'title' ? 'Title' : 'TrackNumber',
+        includeArt: 'None'
       });
 
       const metadataMap = new Map(metadata.map(m => [m.path, m]));
@@ -58,8 +59,12 @@ export function useFileNavigation() {
         .then(setRecentLocations)
         .catch(console.error);
     } catch (err) {
-      setError(err as string);
-      console.error('Error loading directory:', err);
+      const message = requestErrorMessage(err);
+      // A cancelled listing has been replaced by a newer one
+      if (message !== null) {
+        setError(message);
+        console.error('Error loading directory:', err);
+      }
     } finally {
       setIsLoading(false);
     }
@@ -84,7 +89,7 @@ export function useFileNavigation() {
       await invoke('set_default_location', { path });
       setIsDefault(true);
     } catch (err) {
-      setError(err as string);
+      setError(String(err));
       console.error('Error setting default location:', err);
     }
   }, []);
@@ -102,7 +107,7 @@ export function useFileNavigation() {
           loadDirectory(homePath, 'fileName');
         }
       } catch (err) {
-        setError(err as string);
+        setError(String(err));
       }
     };
 
@@ -13,6 +13,30 @@ export interface SelectedItems {
 
 export type SortOption = 'fileName' | 'title' | 'trackNumber';
 
+// How read_dir, get_metadata_for_directory and get_recursive_audio_files reject.
+// Plain failures arrive as a bare string.
+export type RequestError =
+  | string
+  | { kind: 'Cancelled' }
+  | { kind: 'PermissionDenied' | 'FullDiskAccessRequired'; message: string };
+
+// Text to show for a failed request, or null when it was cancelled or
+// superseded by a newer one and should be ignored.
+export function requestErrorMessage(err: unknown): string | null {
+  if (typeof err === 'string') return err;
+  const error = err as Exclude<RequestError, string> | null;
+  switch (error?.kind) {
+    case 'Cancelled':
+      return null;
+    case 'PermissionDenied':
+      return `Permission denied: ${error.message}`;
+    case 'FullDiskAccessRequired':
+      return `Full Disk Access is required to read ${error.message}`;
+    default:
+      return String(err);
+  }
+}
+
 export interface FileBrowserProps {
   // Add any props if needed in the future
 } 
\ No newline at end of file